@@ -64,15 +64,17 @@ pub async fn route_mkcalendar<C: CalendarStore>(
     let request = MkcalendarRequest::parse_str(&body)?;
     let request = request.set.prop;
 
-    let calendar = Calendar {
+    let mut calendar = Calendar {
         id: cal_id.to_owned(),
         principal: principal.to_owned(),
         order: request.calendar_order.unwrap_or(0),
         displayname: request.displayname,
-        timezone: request.calendar_timezone,
-        timezone_id: request.calendar_timezone_id,
+        timezone: None,
+        timezone_id: None,
         color: request.calendar_color,
         description: request.calendar_description,
+        created_at: None,
+        updated_at: None,
         deleted_at: None,
         synctoken: 0,
         subscription_url: None,
@@ -86,6 +88,12 @@ pub async fn route_mkcalendar<C: CalendarStore>(
                 CalendarObjectType::Journal,
             ]),
     };
+    // calendar-timezone-id takes precedence, the other property is derived (RFC 7809)
+    if request.calendar_timezone_id.is_some() {
+        calendar.set_timezone_id(request.calendar_timezone_id)?;
+    } else {
+        calendar.set_timezone(request.calendar_timezone)?;
+    }
 
     match store.insert_calendar(calendar).await {
         // The spec says we should return a mkcalendar-response but I don't know what goes into it.
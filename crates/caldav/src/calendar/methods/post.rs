@@ -7,18 +7,21 @@ use rustical_dav::privileges::UserPrivilege;
 use rustical_dav::resource::Resource;
 use rustical_dav_push::register::PushRegister;
 use rustical_store::auth::User;
+use rustical_store::quota::Quota;
 use rustical_store::{CalendarStore, Subscription, SubscriptionStore};
 use rustical_xml::XmlDocument;
 use tracing::instrument;
 use tracing_actix_web::RootSpan;
 
-#[instrument(parent = root_span.id(), skip(store, subscription_store, root_span, req))]
+#[allow(clippy::too_many_arguments)]
+#[instrument(parent = root_span.id(), skip(store, subscription_store, quota, root_span, req))]
 pub async fn route_post<C: CalendarStore, S: SubscriptionStore>(
     path: Path<(String, String)>,
     body: String,
     user: User,
     store: Data<C>,
     subscription_store: Data<S>,
+    quota: Data<Quota>,
     root_span: RootSpan,
     req: HttpRequest,
 ) -> Result<HttpResponse, Error> {
@@ -31,6 +34,7 @@ pub async fn route_post<C: CalendarStore, S: SubscriptionStore>(
     let calendar_resource = CalendarResource {
         cal: calendar,
         read_only: true,
+        max_resource_size: quota.max_resource_size(),
     };
 
     if !calendar_resource
@@ -5,15 +5,20 @@ use crate::{
 use actix_web::{
     dev::{Path, ResourceDef},
     http::StatusCode,
+    web::Data,
     HttpRequest,
 };
+use futures_util::stream::{self, StreamExt, TryStreamExt};
 use rustical_dav::{
     resource::Resource,
-    xml::{multistatus::ResponseElement, MultistatusElement, PropElement, PropfindType},
+    xml::{multistatus::ResponseElement, MultistatusStream, PropElement, PropfindType},
 };
-use rustical_store::{auth::User, CalendarObject, CalendarStore};
+use rustical_store::{auth::User, CalendarStore};
 use rustical_xml::XmlDeserialize;
 
+/// Number of objects fetched from the store at once
+const MULTIGET_BATCH_SIZE: usize = 100;
+
 #[derive(XmlDeserialize, Clone, Debug, PartialEq)]
 #[allow(dead_code)]
 // <!ELEMENT calendar-query ((DAV:allprop | DAV:propname | DAV:prop)?, href+)>
@@ -25,32 +30,36 @@ pub(crate) struct CalendarMultigetRequest {
     pub(crate) href: Vec<String>,
 }
 
-pub async fn get_objects_calendar_multiget<C: CalendarStore>(
-    cal_query: &CalendarMultigetRequest,
+/// Splits the requested hrefs into the ids of objects in the calendar
+/// and hrefs that can't refer to an object in it
+fn parse_multiget_hrefs(
+    hrefs: &[String],
     path: &str,
-    principal: &str,
-    cal_id: &str,
-    store: &C,
-) -> Result<(Vec<CalendarObject>, Vec<String>), Error> {
+) -> (Vec<(String, String)>, Vec<String>) {
     let resource_def = ResourceDef::prefix(path).join(&ResourceDef::new("/{object_id}"));
 
-    let mut result = vec![];
+    let mut objects = vec![];
     let mut not_found = vec![];
 
-    for href in &cal_query.href {
+    for href in hrefs {
         let mut path = Path::new(href.as_str());
         if !resource_def.capture_match_info(&mut path) {
             not_found.push(href.to_owned());
+            continue;
         };
-        let object_id = path.get("object_id").unwrap();
-        match store.get_object(principal, cal_id, object_id).await {
-            Ok(object) => result.push(object),
-            Err(rustical_store::Error::NotFound) => not_found.push(href.to_owned()),
-            Err(err) => return Err(err.into()),
-        };
+        let object_id = path.get("object_id").unwrap().to_owned();
+        objects.push((object_id, href.to_owned()));
     }
 
-    Ok((result, not_found))
+    (objects, not_found)
+}
+
+fn not_found_response(href: String) -> ResponseElement<CalendarObjectPropWrapper> {
+    ResponseElement {
+        href,
+        status: Some(StatusCode::NOT_FOUND),
+        ..Default::default()
+    }
 }
 
 pub async fn handle_calendar_multiget<C: CalendarStore>(
@@ -59,11 +68,10 @@ pub async fn handle_calendar_multiget<C: CalendarStore>(
     user: &User,
     principal: &str,
     cal_id: &str,
-    cal_store: &C,
-) -> Result<MultistatusElement<CalendarObjectPropWrapper, String>, Error> {
-    let (objects, not_found) =
-        get_objects_calendar_multiget(&cal_multiget, req.path(), principal, cal_id, cal_store)
-            .await?;
+    cal_store: Data<C>,
+    timezones_by_reference: bool,
+) -> Result<MultistatusStream<String, CalendarObjectPropWrapper, Error>, Error> {
+    let (objects, not_found) = parse_multiget_hrefs(&cal_multiget.href, req.path());
 
     let props = match cal_multiget.prop {
         PropfindType::Allprop => {
@@ -76,32 +84,54 @@ pub async fn handle_calendar_multiget<C: CalendarStore>(
             prop_tags.into_iter().map(|propname| propname.0).collect()
         }
     };
-    let props: Vec<&str> = props.iter().map(String::as_str).collect();
 
-    let mut responses = Vec::new();
-    for object in objects {
-        let path = format!("{}/{}", req.path(), object.get_id());
-        responses.push(
-            CalendarObjectResource {
-                object,
-                principal: principal.to_owned(),
-            }
-            .propfind(&path, &props, user, req.resource_map())?,
-        );
-    }
+    let batches: Vec<Vec<(String, String)>> = objects
+        .chunks(MULTIGET_BATCH_SIZE)
+        .map(<[_]>::to_vec)
+        .collect();
+    let fetch_principal = principal.to_owned();
+    let cal_id = cal_id.to_owned();
+    let batches = stream::iter(batches).then(move |batch| {
+        let cal_store = cal_store.clone();
+        let principal = fetch_principal.clone();
+        let cal_id = cal_id.clone();
+        async move {
+            let object_ids: Vec<String> = batch.iter().map(|(id, _)| id.to_owned()).collect();
+            let objects = cal_store
+                .get_objects_by_ids(&principal, &cal_id, &object_ids)
+                .await?;
+            Ok::<_, Error>((batch, objects))
+        }
+    });
 
-    let not_found_responses = not_found
-        .into_iter()
-        .map(|path| ResponseElement {
-            href: path,
-            status: Some(StatusCode::NOT_FOUND),
-            ..Default::default()
+    let user = user.to_owned();
+    let principal = principal.to_owned();
+    let responses = batches
+        .map_ok(move |(batch, objects)| {
+            let props: Vec<&str> = props.iter().map(String::as_str).collect();
+            let mut responses = vec![];
+            for (object_id, href) in batch {
+                let Some(object) = objects.iter().find(|object| object.get_id() == object_id)
+                else {
+                    responses.push(Ok(not_found_response(href)));
+                    continue;
+                };
+                let path = format!("{}/{}", req.path(), object.get_id());
+                responses.push(
+                    CalendarObjectResource {
+                        object: object.to_owned(),
+                        principal: principal.to_owned(),
+                        timezones_by_reference,
+                    }
+                    .propfind(&path, &props, &user, req.resource_map()),
+                );
+            }
+            stream::iter(responses)
         })
-        .collect();
+        .try_flatten();
 
-    Ok(MultistatusElement {
-        responses,
-        member_responses: not_found_responses,
-        ..Default::default()
-    })
+    let not_found_responses = stream::iter(not_found.into_iter().map(not_found_response).map(Ok));
+    Ok(MultistatusStream::new(
+        not_found_responses.chain(responses).boxed_local(),
+    ))
 }
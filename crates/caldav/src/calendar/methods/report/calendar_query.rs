@@ -1,10 +1,17 @@
 use actix_web::HttpRequest;
+use futures_util::{
+    future::ready,
+    stream::{LocalBoxStream, StreamExt, TryStreamExt},
+};
 use rustical_dav::{
     resource::Resource,
-    xml::{MultistatusElement, PropElement, PropfindType},
+    xml::{MultistatusStream, PropElement, PropfindType},
 };
 use rustical_store::{
-    auth::User, calendar::UtcDateTime, calendar_store::CalendarQuery, CalendarObject, CalendarStore,
+    CalendarObject, CalendarStore,
+    auth::User,
+    calendar::{CalTimezone, UtcDateTime, parse_vcalendar_timezone},
+    calendar_store::CalendarQuery,
 };
 use rustical_xml::XmlDeserialize;
 use std::ops::Deref;
@@ -76,7 +83,11 @@ pub(crate) struct CompFilterElement {
 
 impl CompFilterElement {
     // match the VCALENDAR part
-    pub fn matches_root(&self, cal_object: &CalendarObject) -> bool {
+    pub fn matches_root(
+        &self,
+        cal_object: &CalendarObject,
+        timezone: Option<&CalTimezone>,
+    ) -> bool {
         let comp_vcal = self.name == "VCALENDAR";
         match (self.is_not_defined, comp_vcal) {
             // Client wants VCALENDAR to not exist but we are a VCALENDAR
@@ -97,7 +108,7 @@ impl CompFilterElement {
         if self
             .comp_filter
             .iter()
-            .all(|filter| filter.matches(cal_object))
+            .all(|filter| filter.matches(cal_object, timezone))
         {
             return true;
         }
@@ -106,7 +117,8 @@ impl CompFilterElement {
     }
 
     // match the VEVENT/VTODO/VJOURNAL part
-    pub fn matches(&self, cal_object: &CalendarObject) -> bool {
+    // Floating times are interpreted in the given timezone
+    pub fn matches(&self, cal_object: &CalendarObject, timezone: Option<&CalTimezone>) -> bool {
         let comp_name_matches = self.name == cal_object.get_component_name();
         match (self.is_not_defined, comp_name_matches) {
             // Client wants VCALENDAR to not exist but we are a VCALENDAR
@@ -119,20 +131,18 @@ impl CompFilterElement {
         // TODO: Implement prop-filter (and comp-filter?) at some point
 
         if let Some(time_range) = &self.time_range {
-            if let Some(start) = &time_range.start {
-                if let Some(last_occurence) = cal_object.get_last_occurence().unwrap_or(None) {
-                    if start.deref() > &last_occurence.utc() {
-                        return false;
-                    }
-                };
-            }
-            if let Some(end) = &time_range.end {
-                if let Some(first_occurence) = cal_object.get_first_occurence().unwrap_or(None) {
-                    if end.deref() < &first_occurence.utc() {
-                        return false;
-                    }
-                };
-            }
+            if let Some(start) = &time_range.start
+                && let Some(last_occurence) = cal_object.get_last_occurence().unwrap_or(None)
+                && start.deref() > &last_occurence.utc_in(timezone)
+            {
+                return false;
+            };
+            if let Some(end) = &time_range.end
+                && let Some(first_occurence) = cal_object.get_first_occurence().unwrap_or(None)
+                && end.deref() < &first_occurence.utc_in(timezone)
+            {
+                return false;
+            };
         }
         true
     }
@@ -148,8 +158,8 @@ pub(crate) struct FilterElement {
 }
 
 impl FilterElement {
-    pub fn matches(&self, cal_object: &CalendarObject) -> bool {
-        self.comp_filter.matches_root(cal_object)
+    pub fn matches(&self, cal_object: &CalendarObject, timezone: Option<&CalTimezone>) -> bool {
+        self.comp_filter.matches_root(cal_object, timezone)
     }
 }
 
@@ -159,15 +169,15 @@ impl From<&FilterElement> for CalendarQuery {
         for comp_filter in comp_filter_vcalendar.comp_filter.iter() {
             // A calendar object cannot contain both VEVENT and VTODO, so we only have to handle
             // whatever we get first
-            if matches!(comp_filter.name.as_str(), "VEVENT" | "VTODO") {
-                if let Some(time_range) = &comp_filter.time_range {
-                    let start = time_range.start.as_ref().map(|start| start.date_naive());
-                    let end = time_range.end.as_ref().map(|end| end.date_naive());
-                    return CalendarQuery {
-                        time_start: start,
-                        time_end: end,
-                    };
-                }
+            if matches!(comp_filter.name.as_str(), "VEVENT" | "VTODO")
+                && let Some(time_range) = &comp_filter.time_range
+            {
+                let start = time_range.start.as_ref().map(|start| start.date_naive());
+                let end = time_range.end.as_ref().map(|end| end.date_naive());
+                return CalendarQuery {
+                    time_start: start,
+                    time_end: end,
+                };
             }
         }
         Default::default()
@@ -188,6 +198,24 @@ pub struct CalendarQueryRequest {
     pub(crate) timezone_id: Option<String>,
 }
 
+impl CalendarQueryRequest {
+    /// The timezone specified with the query (RFC 4791 Section 9.8, RFC 7809 Section 5.3)
+    fn get_timezone(&self) -> Result<Option<CalTimezone>, Error> {
+        if let Some(tzid) = &self.timezone_id {
+            let tz = tzid.parse().map_err(|_| {
+                rustical_store::Error::InvalidData(format!("Invalid timezone-id: {tzid}"))
+            })?;
+            return Ok(Some(CalTimezone::Olson(tz)));
+        }
+        if let Some(timezone) = &self.timezone {
+            return Ok(Some(CalTimezone::from_ical(&parse_vcalendar_timezone(
+                timezone,
+            )?)?));
+        }
+        Ok(None)
+    }
+}
+
 impl From<&CalendarQueryRequest> for CalendarQuery {
     fn from(value: &CalendarQueryRequest) -> Self {
         value
@@ -203,14 +231,25 @@ pub async fn get_objects_calendar_query<C: CalendarStore>(
     principal: &str,
     cal_id: &str,
     store: &C,
-) -> Result<Vec<CalendarObject>, Error> {
-    let mut objects = store
-        .calendar_query(principal, cal_id, cal_query.into())
+) -> Result<LocalBoxStream<'static, Result<CalendarObject, rustical_store::Error>>, Error> {
+    let objects = store
+        .calendar_query_stream(principal, cal_id, cal_query.into())
         .await?;
-    if let Some(filter) = &cal_query.filter {
-        objects.retain(|object| filter.matches(object));
-    }
-    Ok(objects)
+    // Without a timezone in the query, floating times are in the timezone of the calendar
+    let timezone = match cal_query.get_timezone()? {
+        Some(timezone) => Some(timezone),
+        None => store.get_calendar(principal, cal_id).await?.get_timezone(),
+    };
+    let filter = cal_query.filter.clone();
+    Ok(objects
+        .try_filter(move |object| {
+            ready(
+                filter
+                    .as_ref()
+                    .is_none_or(|filter| filter.matches(object, timezone.as_ref())),
+            )
+        })
+        .boxed_local())
 }
 
 pub async fn handle_calendar_query<C: CalendarStore>(
@@ -220,7 +259,8 @@ pub async fn handle_calendar_query<C: CalendarStore>(
     principal: &str,
     cal_id: &str,
     cal_store: &C,
-) -> Result<MultistatusElement<CalendarObjectPropWrapper, String>, Error> {
+    timezones_by_reference: bool,
+) -> Result<MultistatusStream<String, CalendarObjectPropWrapper, Error>, Error> {
     let objects = get_objects_calendar_query(&cal_query, principal, cal_id, cal_store).await?;
 
     let props = match cal_query.prop {
@@ -234,22 +274,20 @@ pub async fn handle_calendar_query<C: CalendarStore>(
             prop_tags.into_iter().map(|propname| propname.0).collect()
         }
     };
-    let props: Vec<&str> = props.iter().map(String::as_str).collect();
 
-    let mut responses = Vec::new();
-    for object in objects {
+    let user = user.to_owned();
+    let principal = principal.to_owned();
+    let responses = objects.map(move |object| {
+        let object = object?;
+        let props: Vec<&str> = props.iter().map(String::as_str).collect();
         let path = format!("{}/{}", req.path().trim_end_matches('/'), object.get_id());
-        responses.push(
-            CalendarObjectResource {
-                object,
-                principal: principal.to_owned(),
-            }
-            .propfind(&path, &props, user, req.resource_map())?,
-        );
-    }
+        CalendarObjectResource {
+            object,
+            principal: principal.to_owned(),
+            timezones_by_reference,
+        }
+        .propfind(&path, &props, &user, req.resource_map())
+    });
 
-    Ok(MultistatusElement {
-        responses,
-        ..Default::default()
-    })
+    Ok(MultistatusStream::new(responses.boxed_local()))
 }
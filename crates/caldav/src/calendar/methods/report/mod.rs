@@ -1,7 +1,7 @@
-use crate::Error;
+use crate::{Error, header::CalDAVTimezones};
 use actix_web::{
-    web::{Data, Path},
     HttpRequest, Responder,
+    web::{Data, Path},
 };
 use calendar_multiget::{handle_calendar_multiget, CalendarMultigetRequest};
 use calendar_query::{handle_calendar_query, CalendarQueryRequest};
@@ -32,6 +32,7 @@ pub async fn route_report_calendar<C: CalendarStore>(
     user: User,
     req: HttpRequest,
     cal_store: Data<C>,
+    timezones: CalDAVTimezones,
 ) -> Result<impl Responder, Error> {
     let (principal, cal_id) = path.into_inner();
     if !user.is_principal(&principal) {
@@ -49,6 +50,7 @@ pub async fn route_report_calendar<C: CalendarStore>(
                 &principal,
                 &cal_id,
                 cal_store.as_ref(),
+                timezones.by_reference(),
             )
             .await?
         }
@@ -59,7 +61,8 @@ pub async fn route_report_calendar<C: CalendarStore>(
                 &user,
                 &principal,
                 &cal_id,
-                cal_store.as_ref(),
+                cal_store.clone(),
+                timezones.by_reference(),
             )
             .await?
         }
@@ -71,6 +74,7 @@ pub async fn route_report_calendar<C: CalendarStore>(
                 &principal,
                 &cal_id,
                 cal_store.as_ref(),
+                timezones.by_reference(),
             )
             .await?
         }
@@ -79,9 +83,14 @@ pub async fn route_report_calendar<C: CalendarStore>(
 
 #[cfg(test)]
 mod tests {
-    use calendar_query::{CompFilterElement, FilterElement, TimeRangeElement};
+    use calendar_query::{
+        CompFilterElement, FilterElement, TimeRangeElement, get_objects_calendar_query,
+    };
+    use futures_util::TryStreamExt;
     use rustical_dav::xml::{PropElement, PropfindType, Propname};
     use rustical_store::calendar::UtcDateTime;
+    use rustical_store::memory::MemoryCalendarStore;
+    use rustical_store::{Calendar, CalendarObject};
     use rustical_xml::ValueDeserialize;
 
     use super::*;
@@ -164,4 +173,74 @@ mod tests {
             })
         )
     }
+
+    #[tokio::test]
+    async fn test_calendar_query_floating() {
+        let (send, _recv) = tokio::sync::mpsc::channel(100);
+        let store = MemoryCalendarStore::new(send);
+        let mut calendar = Calendar {
+            principal: "user".to_owned(),
+            id: "calendar".to_owned(),
+            ..Default::default()
+        };
+        calendar
+            .set_timezone_id(Some("Australia/Sydney".to_owned()))
+            .unwrap();
+        store.insert_calendar(calendar).await.unwrap();
+        let ics = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:-//rustical//test//EN\r
+BEGIN:VEVENT\r
+UID:floating\r
+DTSTAMP:20240101T000000Z\r
+DTSTART:20240102T050000\r
+DTEND:20240102T060000\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+        let object = CalendarObject::from_ics("floating".to_owned(), ics.to_owned()).unwrap();
+        store
+            .put_object(
+                "user".to_owned(),
+                "calendar".to_owned(),
+                object,
+                false,
+                None,
+            )
+            .await
+            .unwrap();
+
+        let query = async |end: &str| {
+            let ReportRequest::CalendarQuery(request) = ReportRequest::parse_str(&format!(
+                r#"
+                <?xml version='1.0' encoding='UTF-8' ?>
+                <CAL:calendar-query xmlns="DAV:" xmlns:CAL="urn:ietf:params:xml:ns:caldav">
+                    <prop>
+                        <getetag />
+                    </prop>
+                    <CAL:filter>
+                        <CAL:comp-filter name="VCALENDAR">
+                            <CAL:comp-filter name="VEVENT">
+                                <CAL:time-range start="20231201T000000Z" end="{end}" />
+                            </CAL:comp-filter>
+                        </CAL:comp-filter>
+                    </CAL:filter>
+                </CAL:calendar-query>"#
+            ))
+            .unwrap() else {
+                panic!("not a calendar-query");
+            };
+            get_objects_calendar_query(&request, "user", "calendar", &store)
+                .await
+                .unwrap()
+                .map_ok(|object| object.get_id().to_owned())
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap()
+        };
+
+        // The floating event starts on 2024-01-01T18:00Z in the calendar timezone
+        assert_eq!(query("20240101T200000Z").await, vec!["floating"]);
+        assert!(query("20240101T170000Z").await.is_empty());
+    }
 }
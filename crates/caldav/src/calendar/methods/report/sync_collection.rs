@@ -1,9 +1,10 @@
-use actix_web::{http::StatusCode, HttpRequest};
+use actix_web::{HttpRequest, http::StatusCode};
+use futures_util::stream::{self, StreamExt};
 use rustical_dav::{
     resource::Resource,
     xml::{
-        multistatus::ResponseElement, sync_collection::SyncCollectionRequest, MultistatusElement,
-        PropElement, PropfindType,
+        MultistatusStream, PropElement, PropfindType, multistatus::ResponseElement,
+        sync_collection::SyncCollectionRequest,
     },
 };
 use rustical_store::{
@@ -24,7 +25,8 @@ pub async fn handle_sync_collection<C: CalendarStore>(
     principal: &str,
     cal_id: &str,
     cal_store: &C,
-) -> Result<MultistatusElement<CalendarObjectPropWrapper, String>, Error> {
+    timezones_by_reference: bool,
+) -> Result<MultistatusStream<String, CalendarObjectPropWrapper, Error>, Error> {
     let props = match sync_collection.prop {
         PropfindType::Allprop => {
             vec!["allprop".to_owned()]
@@ -36,37 +38,67 @@ pub async fn handle_sync_collection<C: CalendarStore>(
             prop_tags.into_iter().map(|propname| propname.0).collect()
         }
     };
-    let props: Vec<&str> = props.iter().map(String::as_str).collect();
 
     let old_synctoken = parse_synctoken(&sync_collection.sync_token).unwrap_or(0);
-    let (new_objects, deleted_objects, new_synctoken) = cal_store
-        .sync_changes(principal, cal_id, old_synctoken)
-        .await?;
+    let limit = sync_collection
+        .limit
+        .map(|limit| limit.nresults.try_into().unwrap_or(usize::MAX));
+    let (new_objects, deleted_objects, new_synctoken, truncated) =
+        if old_synctoken == 0 && limit.is_none() {
+            // An initial sync reports every object, so they are streamed instead of loaded at once.
+            // The synctoken is taken first so changes in between are reported again next time.
+            let new_synctoken = cal_store.get_calendar(principal, cal_id).await?.synctoken;
+            let objects = cal_store.get_objects_stream(principal, cal_id).await?;
+            (objects, vec![], new_synctoken, false)
+        } else {
+            let (new_objects, deleted_objects, new_synctoken, truncated) = cal_store
+                .sync_changes(principal, cal_id, old_synctoken, limit)
+                .await?;
+            let new_objects = stream::iter(new_objects.into_iter().map(Ok)).boxed();
+            (new_objects, deleted_objects, new_synctoken, truncated)
+        };
 
-    let mut responses = Vec::new();
-    for object in new_objects {
-        let path = format!("{}/{}", req.path().trim_end_matches('/'), object.get_id());
-        responses.push(
-            CalendarObjectResource {
-                object,
-                principal: principal.to_owned(),
-            }
-            .propfind(&path, &props, user, req.resource_map())?,
-        );
-    }
-
-    for object_id in deleted_objects {
-        let path = format!("{}/{}", req.path().trim_end_matches('/'), object_id);
+    let mut responses = vec![];
+    if truncated {
+        // RFC 6578 Section 3.6: The client continues with the returned sync-token
         responses.push(ResponseElement {
-            href: path,
-            status: Some(StatusCode::NOT_FOUND),
+            href: req.path().to_owned(),
+            status: Some(StatusCode::INSUFFICIENT_STORAGE),
             ..Default::default()
         });
     }
 
-    Ok(MultistatusElement {
+    let collection_path = req.path().trim_end_matches('/').to_owned();
+    let deleted_responses = deleted_objects.into_iter().map({
+        let collection_path = collection_path.clone();
+        move |object_id| {
+            Ok(ResponseElement {
+                href: format!("{collection_path}/{object_id}"),
+                status: Some(StatusCode::NOT_FOUND),
+                ..Default::default()
+            })
+        }
+    });
+
+    let user = user.to_owned();
+    let principal = principal.to_owned();
+    let member_responses = new_objects
+        .map(move |object| {
+            let object = object?;
+            let props: Vec<&str> = props.iter().map(String::as_str).collect();
+            let path = format!("{collection_path}/{}", object.get_id());
+            CalendarObjectResource {
+                object,
+                principal: principal.to_owned(),
+                timezones_by_reference,
+            }
+            .propfind(&path, &props, &user, req.resource_map())
+        })
+        .chain(stream::iter(deleted_responses));
+
+    Ok(MultistatusStream {
         responses,
+        member_responses: member_responses.boxed_local(),
         sync_token: Some(format_synctoken(new_synctoken)),
-        ..Default::default()
     })
 }
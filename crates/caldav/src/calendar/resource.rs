@@ -9,17 +9,20 @@ use actix_web::dev::ResourceMap;
 use actix_web::http::Method;
 use actix_web::web;
 use async_trait::async_trait;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDateTime, Utc};
 use derive_more::derive::{From, Into};
+use futures_util::{StreamExt, stream};
 use rustical_dav::extensions::{
     CommonPropertiesExtension, CommonPropertiesProp, SyncTokenExtension, SyncTokenExtensionProp,
+    TimestampsExtension, TimestampsExtensionProp,
 };
 use rustical_dav::privileges::UserPrivilegeSet;
-use rustical_dav::resource::{Resource, ResourceService};
+use rustical_dav::resource::{MembersStream, Resource, ResourceService};
 use rustical_dav::xml::{HrefElement, Resourcetype, ResourcetypeInner};
 use rustical_dav_push::{DavPushExtension, DavPushExtensionProp};
 use rustical_store::auth::User;
 use rustical_store::calendar::CalDateTime;
+use rustical_store::quota::Quota;
 use rustical_store::{Calendar, CalendarStore, SubscriptionStore};
 use rustical_xml::{EnumUnitVariants, EnumVariants};
 use rustical_xml::{XmlDeserialize, XmlSerialize};
@@ -52,8 +55,8 @@ pub enum CalendarProp {
     SupportedCalendarComponentSet(SupportedCalendarComponentSet),
     #[xml(ns = "rustical_dav::namespace::NS_CALDAV", skip_deserializing)]
     SupportedCalendarData(SupportedCalendarData),
-    #[xml(ns = "rustical_dav::namespace::NS_DAV")]
-    MaxResourceSize(i64),
+    #[xml(ns = "rustical_dav::namespace::NS_CALDAV", skip_deserializing)]
+    MaxResourceSize(u64),
     #[xml(skip_deserializing)]
     #[xml(ns = "rustical_dav::namespace::NS_CALDAV")]
     SupportedReportSet(SupportedReportSet),
@@ -73,6 +76,7 @@ pub enum CalendarPropWrapper {
     Calendar(CalendarProp),
     SyncToken(SyncTokenExtensionProp),
     DavPush(DavPushExtensionProp),
+    Timestamps(TimestampsExtensionProp),
     Common(CommonPropertiesProp),
 }
 
@@ -80,6 +84,7 @@ pub enum CalendarPropWrapper {
 pub struct CalendarResource {
     pub cal: Calendar,
     pub read_only: bool,
+    pub max_resource_size: u64,
 }
 
 impl From<CalendarResource> for Calendar {
@@ -104,6 +109,8 @@ impl CommonPropertiesExtension for CalendarResource {
     type PrincipalResource = PrincipalResource;
 }
 
+impl TimestampsExtension for CalendarResource {}
+
 impl Resource for CalendarResource {
     type Prop = CalendarPropWrapper;
     type Error = Error;
@@ -146,10 +153,10 @@ impl Resource for CalendarResource {
                 CalendarPropName::CalendarTimezone => {
                     CalendarProp::CalendarTimezone(self.cal.timezone.clone())
                 }
-                // chrono_tz uses the IANA database
-                CalendarPropName::TimezoneServiceSet => CalendarProp::TimezoneServiceSet(
-                    "https://www.iana.org/time-zones".to_owned().into(),
-                ),
+                // Served by our own TZDIST service
+                CalendarPropName::TimezoneServiceSet => {
+                    CalendarProp::TimezoneServiceSet("/.well-known/timezone".to_owned().into())
+                }
                 CalendarPropName::CalendarTimezoneId => {
                     CalendarProp::CalendarTimezoneId(self.cal.timezone_id.clone())
                 }
@@ -162,7 +169,9 @@ impl Resource for CalendarResource {
                 CalendarPropName::SupportedCalendarData => {
                     CalendarProp::SupportedCalendarData(SupportedCalendarData::default())
                 }
-                CalendarPropName::MaxResourceSize => CalendarProp::MaxResourceSize(10000000),
+                CalendarPropName::MaxResourceSize => {
+                    CalendarProp::MaxResourceSize(self.max_resource_size)
+                }
                 CalendarPropName::SupportedReportSet => {
                     CalendarProp::SupportedReportSet(SupportedReportSet::default())
                 }
@@ -182,6 +191,9 @@ impl Resource for CalendarResource {
             CalendarPropWrapperName::DavPush(prop) => {
                 CalendarPropWrapper::DavPush(DavPushExtension::get_prop(self, prop)?)
             }
+            CalendarPropWrapperName::Timestamps(prop) => CalendarPropWrapper::Timestamps(
+                <Self as TimestampsExtension>::get_prop(self, prop)?,
+            ),
             CalendarPropWrapperName::Common(prop) => CalendarPropWrapper::Common(
                 CommonPropertiesExtension::get_prop(self, rmap, user, prop)?,
             ),
@@ -206,26 +218,15 @@ impl Resource for CalendarResource {
                     self.cal.description = description;
                     Ok(())
                 }
-                CalendarProp::CalendarTimezone(timezone) => {
-                    // TODO: Ensure that timezone-id is also updated
-                    self.cal.timezone = timezone;
-                    Ok(())
-                }
+                CalendarProp::CalendarTimezone(timezone) => self
+                    .cal
+                    .set_timezone(timezone)
+                    .map_err(|err| rustical_dav::Error::BadRequest(err.to_string())),
                 CalendarProp::TimezoneServiceSet(_) => Err(rustical_dav::Error::PropReadOnly),
-                CalendarProp::CalendarTimezoneId(timezone_id) => {
-                    if let Some(tzid) = &timezone_id {
-                        // Validate timezone id
-                        chrono_tz::Tz::from_str(tzid).map_err(|_| {
-                            rustical_dav::Error::BadRequest(format!(
-                                "Invalid timezone-id: {}",
-                                tzid
-                            ))
-                        })?;
-                        // TODO: Ensure that timezone is also updated (For now hope that clients play nice)
-                    }
-                    self.cal.timezone_id = timezone_id;
-                    Ok(())
-                }
+                CalendarProp::CalendarTimezoneId(timezone_id) => self
+                    .cal
+                    .set_timezone_id(timezone_id)
+                    .map_err(|err| rustical_dav::Error::BadRequest(err.to_string())),
                 CalendarProp::CalendarOrder(order) => {
                     self.cal.order = order.unwrap_or_default();
                     Ok(())
@@ -244,6 +245,7 @@ impl Resource for CalendarResource {
             },
             CalendarPropWrapper::SyncToken(prop) => SyncTokenExtension::set_prop(self, prop),
             CalendarPropWrapper::DavPush(prop) => DavPushExtension::set_prop(self, prop),
+            CalendarPropWrapper::Timestamps(prop) => TimestampsExtension::set_prop(self, prop),
             CalendarPropWrapper::Common(prop) => CommonPropertiesExtension::set_prop(self, prop),
         }
     }
@@ -266,15 +268,13 @@ impl Resource for CalendarResource {
                     self.cal.description = None;
                     Ok(())
                 }
-                CalendarPropName::CalendarTimezone => {
+                // Both properties describe the same timezone
+                CalendarPropName::CalendarTimezone | CalendarPropName::CalendarTimezoneId => {
                     self.cal.timezone = None;
-                    Ok(())
-                }
-                CalendarPropName::TimezoneServiceSet => Err(rustical_dav::Error::PropReadOnly),
-                CalendarPropName::CalendarTimezoneId => {
                     self.cal.timezone_id = None;
                     Ok(())
                 }
+                CalendarPropName::TimezoneServiceSet => Err(rustical_dav::Error::PropReadOnly),
                 CalendarPropName::CalendarOrder => {
                     self.cal.order = 0;
                     Ok(())
@@ -292,6 +292,9 @@ impl Resource for CalendarResource {
             },
             CalendarPropWrapperName::SyncToken(prop) => SyncTokenExtension::remove_prop(self, prop),
             CalendarPropWrapperName::DavPush(prop) => DavPushExtension::remove_prop(self, prop),
+            CalendarPropWrapperName::Timestamps(prop) => {
+                TimestampsExtension::remove_prop(self, prop)
+            }
             CalendarPropWrapperName::Common(prop) => {
                 CommonPropertiesExtension::remove_prop(self, prop)
             }
@@ -302,6 +305,14 @@ impl Resource for CalendarResource {
         Some(&self.cal.principal)
     }
 
+    fn get_creation_date(&self) -> Option<NaiveDateTime> {
+        self.cal.created_at
+    }
+
+    fn get_last_modified(&self) -> Option<NaiveDateTime> {
+        self.cal.updated_at
+    }
+
     fn get_user_privileges(&self, user: &User) -> Result<UserPrivilegeSet, Self::Error> {
         if self.cal.subscription_url.is_some() || self.read_only {
             return Ok(UserPrivilegeSet::owner_read(
@@ -317,13 +328,15 @@ impl Resource for CalendarResource {
 
 pub struct CalendarResourceService<C: CalendarStore, S: SubscriptionStore> {
     cal_store: Arc<C>,
+    quota: Arc<Quota>,
     __phantom_sub: PhantomData<S>,
 }
 
 impl<C: CalendarStore, S: SubscriptionStore> CalendarResourceService<C, S> {
-    pub fn new(cal_store: Arc<C>) -> Self {
+    pub fn new(cal_store: Arc<C>, quota: Arc<Quota>) -> Self {
         Self {
             cal_store,
+            quota,
             __phantom_sub: PhantomData,
         }
     }
@@ -345,6 +358,7 @@ impl<C: CalendarStore, S: SubscriptionStore> ResourceService for CalendarResourc
         Ok(CalendarResource {
             cal: calendar,
             read_only: self.cal_store.is_read_only(),
+            max_resource_size: self.quota.max_resource_size(),
         })
     }
 
@@ -363,12 +377,58 @@ impl<C: CalendarStore, S: SubscriptionStore> ResourceService for CalendarResourc
                     CalendarObjectResource {
                         object,
                         principal: principal.to_owned(),
+                        timezones_by_reference: false,
                     },
                 )
             })
             .collect())
     }
 
+    async fn get_members_stream(
+        &self,
+        (principal, cal_id): &Self::PathComponents,
+    ) -> Result<MembersStream<Self::MemberType, Self::Error>, Self::Error> {
+        let principal = principal.to_owned();
+        Ok(self
+            .cal_store
+            .get_objects_stream(&principal, cal_id)
+            .await?
+            .map(move |object| {
+                let object = object?;
+                Ok((
+                    object.get_id().to_string(),
+                    CalendarObjectResource {
+                        object,
+                        principal: principal.to_owned(),
+                        timezones_by_reference: false,
+                    },
+                ))
+            })
+            .boxed_local())
+    }
+
+    async fn get_members_metadata_stream(
+        &self,
+        (principal, cal_id): &Self::PathComponents,
+    ) -> Result<MembersStream<Self::MemberType, Self::Error>, Self::Error> {
+        let principal = principal.to_owned();
+        let objects = self
+            .cal_store
+            .get_objects_metadata(&principal, cal_id)
+            .await?;
+        Ok(stream::iter(objects.into_iter().map(move |object| {
+            Ok((
+                object.get_id().to_string(),
+                CalendarObjectResource {
+                    object,
+                    principal: principal.to_owned(),
+                    timezones_by_reference: false,
+                },
+            ))
+        }))
+        .boxed_local())
+    }
+
     async fn save_resource(
         &self,
         (principal, cal_id): &Self::PathComponents,
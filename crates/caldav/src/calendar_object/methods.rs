@@ -1,21 +1,28 @@
 use crate::Error;
+use crate::header::CalDAVTimezones;
 use actix_web::http::header;
-use actix_web::http::header::HeaderValue;
-use actix_web::web::{Data, Path};
+use actix_web::http::header::{EntityTag, HeaderValue, IfMatch, IfModifiedSince, IfNoneMatch};
+use actix_web::web::{Data, Header, Path};
 use actix_web::HttpRequest;
 use actix_web::HttpResponse;
+use rustical_dav::resource::Resource;
 use rustical_store::auth::User;
+use rustical_store::quota::Quota;
 use rustical_store::{CalendarObject, CalendarStore};
+use std::time::SystemTime;
 use tracing::instrument;
 use tracing_actix_web::RootSpan;
 
-use super::resource::CalendarObjectPathComponents;
+use super::resource::{CalendarObjectPathComponents, CalendarObjectResource};
 
 #[instrument(parent = root_span.id(), skip(store, root_span))]
 pub async fn get_event<C: CalendarStore>(
     path: Path<CalendarObjectPathComponents>,
     store: Data<C>,
     user: User,
+    if_none_match: Header<IfNoneMatch>,
+    if_modified_since: Option<Header<IfModifiedSince>>,
+    timezones: CalDAVTimezones,
     root_span: RootSpan,
 ) -> Result<HttpResponse, Error> {
     let CalendarObjectPathComponents {
@@ -35,17 +42,40 @@ pub async fn get_event<C: CalendarStore>(
 
     let event = store.get_object(&principal, &cal_id, &object_id).await?;
 
-    Ok(HttpResponse::Ok()
-        .insert_header(("ETag", event.get_etag()))
-        .insert_header(("Content-Type", "text/calendar"))
-        .body(event.get_ics().to_owned()))
+    let last_modified = event.get_updated_at();
+    let resource = CalendarObjectResource {
+        object: event,
+        principal,
+        timezones_by_reference: timezones.by_reference(),
+    };
+    let etag = EntityTag::new_strong(resource.get_calendar_etag());
+    if resource.is_not_modified(&if_none_match, if_modified_since.as_deref()) {
+        return Ok(HttpResponse::NotModified()
+            .insert_header(header::ETag(etag))
+            .finish());
+    }
+
+    let mut response = HttpResponse::Ok();
+    response
+        .insert_header(header::ETag(etag))
+        .insert_header(("Content-Type", "text/calendar"));
+    if let Some(last_modified) = last_modified {
+        response.insert_header(header::LastModified(
+            SystemTime::from(last_modified.and_utc()).into(),
+        ));
+    }
+    Ok(response.body(resource.get_calendar_data()))
 }
 
-#[instrument(parent = root_span.id(), skip(store, req, root_span))]
+#[allow(clippy::too_many_arguments)]
+#[instrument(parent = root_span.id(), skip(store, quota, req, root_span))]
 pub async fn put_event<C: CalendarStore>(
     path: Path<CalendarObjectPathComponents>,
     store: Data<C>,
+    quota: Data<Quota>,
     body: String,
+    if_match: Header<IfMatch>,
+    timezones: CalDAVTimezones,
     user: User,
     req: HttpRequest,
     root_span: RootSpan,
@@ -60,13 +90,66 @@ pub async fn put_event<C: CalendarStore>(
         return Ok(HttpResponse::Unauthorized().body(""));
     }
 
+    if body.len() as u64 > quota.max_resource_size() {
+        return Err(Error::MaxResourceSizeExceeded);
+    }
+
+    let existing = match store.get_object(&principal, &cal_id, &object_id).await {
+        Ok(object) => Some(object),
+        Err(rustical_store::Error::NotFound) => None,
+        Err(err) => return Err(err.into()),
+    };
+
+    // If-Match (RFC 7232 Section 3.1) is only satisfied by an existing object
+    if !matches!(&*if_match, IfMatch::Items(items) if items.is_empty()) {
+        let satisfied = existing.is_some_and(|object| {
+            CalendarObjectResource {
+                object,
+                principal: principal.to_owned(),
+                timezones_by_reference: timezones.by_reference(),
+            }
+            .satisfies_if_match(&if_match)
+        });
+        if !satisfied {
+            return Ok(HttpResponse::PreconditionFailed().finish());
+        }
+    }
+
     let overwrite =
         Some(&HeaderValue::from_static("*")) != req.headers().get(header::IF_NONE_MATCH);
 
+    // Only clients that accept timezones by reference get their VTIMEZONEs of IANA timezones
+    // stored as references (RFC 7809), since they also read them back that way.
+    // Objects of other clients are stored as uploaded and keep their ETag.
     let object = CalendarObject::from_ics(object_id, body)?;
+    let uploaded_etag = object.get_etag();
+    let object = if timezones.by_reference() {
+        object.with_timezones_by_reference()?
+    } else {
+        object
+    };
+    let resource = CalendarObjectResource {
+        object,
+        principal,
+        timezones_by_reference: timezones.by_reference(),
+    };
+    let etag = EntityTag::new_strong(resource.get_calendar_etag());
     store
-        .put_object(principal, cal_id, object, overwrite)
+        .put_object_with_quota(
+            resource.principal,
+            cal_id,
+            resource.object,
+            overwrite,
+            Some(&user.id),
+            &quota,
+        )
         .await?;
 
-    Ok(HttpResponse::Created().body(""))
+    // The ETag must not be returned if the client would read back different calendar data
+    // than it uploaded (RFC 4791 Section 5.3.4), it will then fetch it again
+    let mut response = HttpResponse::Created();
+    if etag.tag() == uploaded_etag {
+        response.insert_header(header::ETag(etag));
+    }
+    Ok(response.body(""))
 }
@@ -1,10 +1,15 @@
 use super::methods::{get_event, put_event};
 use crate::{Error, principal::PrincipalResource};
 use actix_web::dev::ResourceMap;
+use actix_web::http::header::EntityTag;
 use async_trait::async_trait;
+use chrono::NaiveDateTime;
 use derive_more::derive::{From, Into};
 use rustical_dav::{
-    extensions::{CommonPropertiesExtension, CommonPropertiesProp},
+    extensions::{
+        CommonPropertiesExtension, CommonPropertiesProp, TimestampsExtension,
+        TimestampsExtensionProp,
+    },
     privileges::UserPrivilegeSet,
     resource::{Resource, ResourceService},
     xml::Resourcetype,
@@ -42,6 +47,7 @@ pub enum CalendarObjectProp {
 #[xml(unit_variants_ident = "CalendarObjectPropWrapperName", untagged)]
 pub enum CalendarObjectPropWrapper {
     CalendarObject(CalendarObjectProp),
+    Timestamps(TimestampsExtensionProp),
     Common(CommonPropertiesProp),
 }
 
@@ -49,12 +55,37 @@ pub enum CalendarObjectPropWrapper {
 pub struct CalendarObjectResource {
     pub object: CalendarObject,
     pub principal: String,
+    /// Whether VTIMEZONEs of IANA timezones can be left out of the calendar data (RFC 7809)
+    pub timezones_by_reference: bool,
+}
+
+impl CalendarObjectResource {
+    /// The calendar data as returned to the client
+    pub fn get_calendar_data(&self) -> String {
+        if self.timezones_by_reference {
+            self.object.get_ics().to_owned()
+        } else {
+            self.object.get_ics_with_timezones().into_owned()
+        }
+    }
+
+    /// Opaque tag of [`CalendarObjectResource::get_calendar_data`], the calendar data with
+    /// added VTIMEZONEs has its own
+    pub fn get_calendar_etag(&self) -> String {
+        if self.timezones_by_reference {
+            self.object.get_etag()
+        } else {
+            self.object.get_etag_with_timezones()
+        }
+    }
 }
 
 impl CommonPropertiesExtension for CalendarObjectResource {
     type PrincipalResource = PrincipalResource;
 }
 
+impl TimestampsExtension for CalendarObjectResource {}
+
 impl Resource for CalendarObjectResource {
     type Prop = CalendarObjectPropWrapper;
     type Error = Error;
@@ -64,6 +95,10 @@ impl Resource for CalendarObjectResource {
         Resourcetype(&[])
     }
 
+    fn content_props() -> &'static [&'static str] {
+        &["calendar-data"]
+    }
+
     fn get_prop(
         &self,
         rmap: &ResourceMap,
@@ -73,17 +108,22 @@ impl Resource for CalendarObjectResource {
         Ok(match prop {
             CalendarObjectPropWrapperName::CalendarObject(prop) => {
                 CalendarObjectPropWrapper::CalendarObject(match prop {
-                    CalendarObjectPropName::Getetag => {
-                        CalendarObjectProp::Getetag(self.object.get_etag())
-                    }
+                    CalendarObjectPropName::Getetag => CalendarObjectProp::Getetag(
+                        EntityTag::new_strong(self.get_calendar_etag()).to_string(),
+                    ),
                     CalendarObjectPropName::CalendarData => {
-                        CalendarObjectProp::CalendarData(self.object.get_ics().to_owned())
+                        CalendarObjectProp::CalendarData(self.get_calendar_data())
                     }
                     CalendarObjectPropName::Getcontenttype => {
                         CalendarObjectProp::Getcontenttype("text/calendar;charset=utf-8")
                     }
                 })
             }
+            CalendarObjectPropWrapperName::Timestamps(prop) => {
+                CalendarObjectPropWrapper::Timestamps(<Self as TimestampsExtension>::get_prop(
+                    self, prop,
+                )?)
+            }
             CalendarObjectPropWrapperName::Common(prop) => CalendarObjectPropWrapper::Common(
                 CommonPropertiesExtension::get_prop(self, rmap, user, prop)?,
             ),
@@ -94,8 +134,16 @@ impl Resource for CalendarObjectResource {
         Some(&self.principal)
     }
 
+    fn get_creation_date(&self) -> Option<NaiveDateTime> {
+        self.object.get_created_at()
+    }
+
+    fn get_last_modified(&self) -> Option<NaiveDateTime> {
+        self.object.get_updated_at()
+    }
+
     fn get_etag(&self) -> Option<String> {
-        Some(self.object.get_etag())
+        Some(self.get_calendar_etag())
     }
 
     fn get_user_privileges(&self, user: &User) -> Result<UserPrivilegeSet, Self::Error> {
@@ -153,6 +201,7 @@ impl<C: CalendarStore> ResourceService for CalendarObjectResourceService<C> {
         Ok(CalendarObjectResource {
             object,
             principal: principal.to_owned(),
+            timezones_by_reference: false,
         })
     }
 
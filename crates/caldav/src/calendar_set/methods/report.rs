@@ -0,0 +1,367 @@
+use crate::{
+    Error,
+    calendar::resource::{CalendarPropWrapper, CalendarResource},
+    calendar_object::resource::{CalendarObjectPropWrapper, CalendarObjectResource},
+    header::CalDAVTimezones,
+};
+use actix_web::{
+    HttpRequest, Responder,
+    http::StatusCode,
+    web::{Data, Path},
+};
+use rustical_dav::{
+    resource::Resource,
+    xml::{
+        MultistatusElement, PropElement, PropfindType,
+        multistatus::ResponseElement,
+        sync_collection::{SyncCollectionRequest, SyncLevel},
+    },
+};
+use rustical_store::{
+    CalendarStore,
+    auth::User,
+    quota::Quota,
+    synctoken::{format_home_synctoken, parse_home_synctoken},
+};
+use rustical_xml::{XmlDeserialize, XmlDocument};
+use std::collections::HashMap;
+use tracing::instrument;
+
+#[derive(XmlDeserialize, XmlDocument, Clone, Debug, PartialEq)]
+pub(crate) enum ReportRequest {
+    #[xml(ns = "rustical_dav::namespace::NS_DAV")]
+    SyncCollection(SyncCollectionRequest),
+}
+
+#[instrument(skip(req, cal_store, quota))]
+pub async fn route_report_calendar_set<C: CalendarStore>(
+    path: Path<(String,)>,
+    body: String,
+    user: User,
+    req: HttpRequest,
+    cal_store: Data<C>,
+    quota: Data<Quota>,
+    timezones: CalDAVTimezones,
+) -> Result<impl Responder, Error> {
+    let (principal,) = path.into_inner();
+    if !user.is_principal(&principal) {
+        return Err(Error::Unauthorized);
+    }
+
+    Ok(match ReportRequest::parse_str(&body)? {
+        ReportRequest::SyncCollection(sync_collection) => {
+            handle_sync_collection(
+                sync_collection,
+                req,
+                &user,
+                &principal,
+                cal_store.as_ref(),
+                quota.max_resource_size(),
+                timezones.by_reference(),
+            )
+            .await?
+        }
+    })
+}
+
+/// Reports changes across all calendars of a calendar home.
+/// The home sync-token carries the synctoken of every calendar known to the client,
+/// so created and deleted calendars are detected by comparing against it.
+/// With sync-level 1 only the calendars themselves are reported,
+/// with sync-level infinity their changed objects are included.
+pub async fn handle_sync_collection<C: CalendarStore>(
+    sync_collection: SyncCollectionRequest,
+    req: HttpRequest,
+    user: &User,
+    principal: &str,
+    cal_store: &C,
+    max_resource_size: u64,
+    timezones_by_reference: bool,
+) -> Result<MultistatusElement<CalendarPropWrapper, CalendarObjectPropWrapper>, Error> {
+    let props = match sync_collection.prop {
+        PropfindType::Allprop => {
+            vec!["allprop".to_owned()]
+        }
+        PropfindType::Propname => {
+            vec!["propname".to_owned()]
+        }
+        PropfindType::Prop(PropElement(prop_tags)) => {
+            prop_tags.into_iter().map(|propname| propname.0).collect()
+        }
+    };
+    let props: Vec<&str> = props.iter().map(String::as_str).collect();
+
+    // An empty sync-token starts an initial sync, anything else has to be a home sync-token
+    let old_synctokens = match sync_collection.sync_token.as_str() {
+        "" => vec![],
+        sync_token => {
+            parse_home_synctoken(sync_token).ok_or(rustical_store::Error::InvalidSyncToken)?
+        }
+    };
+    let mut old_synctokens: HashMap<String, i64> = old_synctokens.into_iter().collect();
+    // Every response counts against the limit, at least one is returned so that the sync progresses
+    let mut limit = sync_collection
+        .limit
+        .map(|limit| limit.nresults.try_into().unwrap_or(usize::MAX).max(1));
+
+    let mut responses = Vec::new();
+    let mut member_responses = Vec::new();
+    let mut new_synctokens = Vec::new();
+    let mut truncated = false;
+    for cal in cal_store.get_calendars(principal).await? {
+        let old_synctoken = old_synctokens.remove(&cal.id);
+        if truncated || limit == Some(0) {
+            // Left for the next page, calendars unknown to the client will show up as created then
+            truncated = true;
+            if let Some(old_synctoken) = old_synctoken {
+                new_synctokens.push((cal.id, old_synctoken));
+            }
+            continue;
+        }
+
+        let cal_path = format!("{}/{}", req.path().trim_end_matches('/'), cal.id);
+        let mut cal_response = if old_synctoken != Some(cal.synctoken) {
+            Some(
+                CalendarResource {
+                    cal: cal.clone(),
+                    read_only: cal_store.is_read_only(),
+                    max_resource_size,
+                }
+                .propfind(&cal_path, &props, user, req.resource_map())?,
+            )
+        } else {
+            None
+        };
+
+        // The client has to learn about a new calendar before its objects
+        if (sync_collection.sync_level == SyncLevel::One || old_synctoken.is_none())
+            && let Some(cal_response) = cal_response.take()
+        {
+            responses.push(cal_response);
+            if let Some(limit) = limit.as_mut() {
+                *limit -= 1;
+            }
+        }
+        if sync_collection.sync_level == SyncLevel::One {
+            new_synctokens.push((cal.id, cal.synctoken));
+            continue;
+        }
+        if limit == Some(0) {
+            // The objects of the new calendar are left for the next page
+            truncated = true;
+            new_synctokens.push((cal.id, 0));
+            continue;
+        }
+
+        let (new_objects, deleted_objects, new_synctoken, cal_truncated) = cal_store
+            .sync_changes(principal, &cal.id, old_synctoken.unwrap_or(0), limit)
+            .await?;
+        if let Some(limit) = limit.as_mut() {
+            *limit = limit.saturating_sub(new_objects.len() + deleted_objects.len());
+        }
+        truncated = cal_truncated;
+
+        for object in new_objects {
+            let path = format!("{}/{}", cal_path, object.get_id());
+            member_responses.push(
+                CalendarObjectResource {
+                    object,
+                    principal: principal.to_owned(),
+                    timezones_by_reference,
+                }
+                .propfind(&path, &props, user, req.resource_map())?,
+            );
+        }
+
+        for object_id in deleted_objects {
+            member_responses.push(ResponseElement {
+                href: format!("{}/{}", cal_path, object_id),
+                status: Some(StatusCode::NOT_FOUND),
+                ..Default::default()
+            });
+        }
+        new_synctokens.push((cal.id, new_synctoken));
+
+        // A known calendar with changed objects is reported once its objects are complete,
+        // it is left out if there is no room for it since its objects were reported already
+        if let Some(cal_response) = cal_response
+            && !truncated
+            && limit != Some(0)
+        {
+            responses.push(cal_response);
+            if let Some(limit) = limit.as_mut() {
+                *limit -= 1;
+            }
+        }
+    }
+
+    // Calendars the client knows about but which are gone now
+    for (cal_id, old_synctoken) in old_synctokens {
+        if truncated || limit == Some(0) {
+            truncated = true;
+            new_synctokens.push((cal_id, old_synctoken));
+            continue;
+        }
+        responses.push(ResponseElement {
+            href: format!("{}/{}", req.path().trim_end_matches('/'), cal_id),
+            status: Some(StatusCode::NOT_FOUND),
+            ..Default::default()
+        });
+        if let Some(limit) = limit.as_mut() {
+            *limit -= 1;
+        }
+    }
+
+    if truncated {
+        // RFC 6578 Section 3.6: The client continues with the returned sync-token
+        responses.push(ResponseElement {
+            href: req.path().to_owned(),
+            status: Some(StatusCode::INSUFFICIENT_STORAGE),
+            ..Default::default()
+        });
+    }
+
+    Ok(MultistatusElement {
+        responses,
+        member_responses,
+        sync_token: Some(format_home_synctoken(&new_synctokens)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+    use rustical_dav::xml::{Propname, sync_collection::LimitElement};
+    use rustical_store::{
+        Calendar, CalendarObject, memory::MemoryCalendarStore, synctoken::format_synctoken,
+    };
+
+    #[test]
+    fn test_xml_sync_collection_infinity() {
+        let report_request = ReportRequest::parse_str(
+            r#"
+        <?xml version='1.0' encoding='UTF-8' ?>
+        <sync-collection xmlns="DAV:">
+            <sync-token />
+            <sync-level>infinite</sync-level>
+            <prop>
+                <getetag />
+            </prop>
+        </sync-collection>"#,
+        )
+        .unwrap();
+        assert_eq!(
+            report_request,
+            ReportRequest::SyncCollection(SyncCollectionRequest {
+                sync_token: "".to_owned(),
+                sync_level: SyncLevel::Infinity,
+                prop: PropfindType::Prop(PropElement(vec![Propname("getetag".to_owned())])),
+                limit: None,
+            })
+        )
+    }
+
+    #[tokio::test]
+    async fn test_sync_collection_limit() {
+        let (send, _recv) = tokio::sync::mpsc::channel(100);
+        let store = MemoryCalendarStore::new(send);
+        for cal_id in ["a", "b"] {
+            store
+                .insert_calendar(Calendar {
+                    principal: "user".to_owned(),
+                    id: cal_id.to_owned(),
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+            for uid in ["1", "2"] {
+                let ics = format!(
+                    "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:-//rustical//test//EN\r
+BEGIN:VTODO\r
+UID:{uid}\r
+DTSTAMP:20240101T000000Z\r
+END:VTODO\r
+END:VCALENDAR\r
+"
+                );
+                let object = CalendarObject::from_ics(uid.to_owned(), ics).unwrap();
+                store
+                    .put_object("user".to_owned(), cal_id.to_owned(), object, false, None)
+                    .await
+                    .unwrap();
+            }
+        }
+        let user = User {
+            id: "user".to_owned(),
+            displayname: None,
+            principal_type: Default::default(),
+            password: None,
+            app_tokens: vec![],
+            memberships: vec![],
+            emails: vec![],
+        };
+        let sync = async |sync_token: &str, sync_level: SyncLevel, limit: u64| {
+            handle_sync_collection(
+                SyncCollectionRequest {
+                    sync_token: sync_token.to_owned(),
+                    sync_level,
+                    prop: PropfindType::Prop(PropElement(vec![Propname("getetag".to_owned())])),
+                    limit: Some(LimitElement { nresults: limit }),
+                },
+                TestRequest::default()
+                    .uri("/caldav/principal/user/")
+                    .to_http_request(),
+                &user,
+                "user",
+                &store,
+                0,
+                false,
+            )
+            .await
+        };
+
+        // Every response counts against the limit and every page makes progress
+        for (sync_level, total) in [(SyncLevel::One, 2), (SyncLevel::Infinity, 6)] {
+            let mut sync_token = String::new();
+            let mut hrefs = vec![];
+            loop {
+                let multistatus = sync(&sync_token, sync_level.clone(), 1).await.unwrap();
+                sync_token = multistatus.sync_token.unwrap();
+                let (page, truncated): (Vec<_>, Vec<_>) = multistatus
+                    .responses
+                    .iter()
+                    .map(|response| (response.href.to_owned(), response.status))
+                    .chain(
+                        multistatus
+                            .member_responses
+                            .iter()
+                            .map(|response| (response.href.to_owned(), response.status)),
+                    )
+                    .partition(|(_, status)| *status != Some(StatusCode::INSUFFICIENT_STORAGE));
+                assert!(page.len() <= 1);
+                hrefs.extend(page.into_iter().map(|(href, _)| href));
+                if truncated.is_empty() {
+                    break;
+                }
+            }
+            hrefs.sort();
+            hrefs.dedup();
+            assert_eq!(hrefs.len(), total);
+            // Nothing changed since
+            let multistatus = sync(&sync_token, sync_level, 10).await.unwrap();
+            assert!(multistatus.responses.is_empty());
+            assert!(multistatus.member_responses.is_empty());
+        }
+
+        // A synctoken of a single calendar is no home sync-token
+        for sync_token in [format_synctoken(4), "invalid".to_owned()] {
+            assert!(matches!(
+                sync(&sync_token, SyncLevel::Infinity, 10).await,
+                Err(Error::StoreError(rustical_store::Error::InvalidSyncToken))
+            ));
+        }
+    }
+}
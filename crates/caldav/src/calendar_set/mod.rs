@@ -2,25 +2,36 @@ use crate::Error;
 use crate::calendar::resource::CalendarResource;
 use crate::principal::PrincipalResource;
 use actix_web::dev::ResourceMap;
+use actix_web::http::Method;
+use actix_web::web;
 use async_trait::async_trait;
-use rustical_dav::extensions::{CommonPropertiesExtension, CommonPropertiesProp};
+use methods::report::route_report_calendar_set;
+use rustical_dav::extensions::{
+    CommonPropertiesExtension, CommonPropertiesProp, QuotaExtension, QuotaExtensionProp,
+};
 use rustical_dav::privileges::UserPrivilegeSet;
 use rustical_dav::resource::{Resource, ResourceService};
 use rustical_dav::xml::{Resourcetype, ResourcetypeInner};
 use rustical_store::CalendarStore;
 use rustical_store::auth::User;
+use rustical_store::quota::{Quota, QuotaUsage};
 use rustical_xml::{EnumUnitVariants, EnumVariants, XmlDeserialize, XmlSerialize};
+use std::str::FromStr;
 use std::sync::Arc;
 
+pub mod methods;
+
 #[derive(Clone)]
 pub struct CalendarSetResource {
     pub(crate) principal: String,
     pub(crate) read_only: bool,
+    pub(crate) quota: QuotaUsage,
 }
 
 #[derive(XmlDeserialize, XmlSerialize, PartialEq, Clone, EnumVariants, EnumUnitVariants)]
 #[xml(unit_variants_ident = "PrincipalPropWrapperName", untagged)]
 pub enum PrincipalPropWrapper {
+    Quota(QuotaExtensionProp),
     Common(CommonPropertiesProp),
 }
 
@@ -28,6 +39,16 @@ impl CommonPropertiesExtension for CalendarSetResource {
     type PrincipalResource = PrincipalResource;
 }
 
+impl QuotaExtension for CalendarSetResource {
+    fn get_quota_used_bytes(&self) -> Option<u64> {
+        Some(self.quota.used_bytes)
+    }
+
+    fn get_quota_available_bytes(&self) -> Option<u64> {
+        self.quota.available_bytes()
+    }
+}
+
 impl Resource for CalendarSetResource {
     type Prop = PrincipalPropWrapper;
     type Error = Error;
@@ -47,6 +68,9 @@ impl Resource for CalendarSetResource {
         prop: &PrincipalPropWrapperName,
     ) -> Result<Self::Prop, Self::Error> {
         Ok(match prop {
+            PrincipalPropWrapperName::Quota(prop) => {
+                PrincipalPropWrapper::Quota(<Self as QuotaExtension>::get_prop(self, prop)?)
+            }
             PrincipalPropWrapperName::Common(prop) => PrincipalPropWrapper::Common(
                 <Self as CommonPropertiesExtension>::get_prop(self, rmap, user, prop)?,
             ),
@@ -68,11 +92,12 @@ impl Resource for CalendarSetResource {
 
 pub struct CalendarSetResourceService<C: CalendarStore> {
     cal_store: Arc<C>,
+    quota: Arc<Quota>,
 }
 
 impl<C: CalendarStore> CalendarSetResourceService<C> {
-    pub fn new(cal_store: Arc<C>) -> Self {
-        Self { cal_store }
+    pub fn new(cal_store: Arc<C>, quota: Arc<Quota>) -> Self {
+        Self { cal_store, quota }
     }
 }
 
@@ -91,6 +116,7 @@ impl<C: CalendarStore> ResourceService for CalendarSetResourceService<C> {
         Ok(CalendarSetResource {
             principal: principal.to_owned(),
             read_only: self.cal_store.is_read_only(),
+            quota: self.quota.get_usage(principal).await?,
         })
     }
 
@@ -107,9 +133,15 @@ impl<C: CalendarStore> ResourceService for CalendarSetResourceService<C> {
                     CalendarResource {
                         cal,
                         read_only: self.cal_store.is_read_only(),
+                        max_resource_size: self.quota.max_resource_size(),
                     },
                 )
             })
             .collect())
     }
+    #[inline]
+    fn actix_additional_routes(res: actix_web::Resource) -> actix_web::Resource {
+        let report_method = web::method(Method::from_str("REPORT").unwrap());
+        res.route(report_method.to(route_report_calendar_set::<C>))
+    }
 }
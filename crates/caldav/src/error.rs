@@ -12,6 +12,10 @@ pub enum Error {
     #[error("Not implemented")]
     NotImplemented,
 
+    /// Precondition of CalDAV (RFC 4791), the object is larger than max-resource-size
+    #[error("Resource exceeds max-resource-size")]
+    MaxResourceSizeExceeded,
+
     #[error(transparent)]
     StoreError(#[from] rustical_store::Error),
 
@@ -31,6 +35,9 @@ impl actix_web::ResponseError for Error {
             Error::StoreError(err) => match err {
                 rustical_store::Error::NotFound => StatusCode::NOT_FOUND,
                 rustical_store::Error::InvalidData(_) => StatusCode::BAD_REQUEST,
+                rustical_store::Error::InvalidSyncToken => StatusCode::FORBIDDEN,
+                rustical_store::Error::QuotaExceeded => StatusCode::INSUFFICIENT_STORAGE,
+                rustical_store::Error::MaxResourceSizeExceeded => StatusCode::FORBIDDEN,
                 _ => StatusCode::INTERNAL_SERVER_ERROR,
             },
             Error::ChronoParseError(_) => StatusCode::INTERNAL_SERVER_ERROR,
@@ -39,12 +46,23 @@ impl actix_web::ResponseError for Error {
             Error::XmlDecodeError(_) => StatusCode::BAD_REQUEST,
             Error::NotImplemented => StatusCode::INTERNAL_SERVER_ERROR,
             Error::NotFound => StatusCode::NOT_FOUND,
+            Error::MaxResourceSizeExceeded => StatusCode::FORBIDDEN,
         }
     }
     fn error_response(&self) -> actix_web::HttpResponse<actix_web::body::BoxBody> {
         error!("Error: {self}");
         match self {
             Error::DavError(err) => err.error_response(),
+            Error::StoreError(rustical_store::Error::InvalidSyncToken) => {
+                rustical_dav::Error::InvalidSyncToken.error_response()
+            }
+            Error::StoreError(rustical_store::Error::QuotaExceeded) => {
+                rustical_dav::Error::QuotaExceeded.error_response()
+            }
+            Error::MaxResourceSizeExceeded
+            | Error::StoreError(rustical_store::Error::MaxResourceSizeExceeded) => HttpResponse::build(self.status_code())
+                .content_type("application/xml; charset=utf-8")
+                .body(r#"<?xml version="1.0" encoding="utf-8"?><error xmlns="DAV:" xmlns:C="urn:ietf:params:xml:ns:caldav"><C:max-resource-size/></error>"#),
             _ => HttpResponse::build(self.status_code()).body(self.to_string()),
         }
     }
@@ -0,0 +1,64 @@
+use actix_web::{FromRequest, HttpRequest, ResponseError, http::StatusCode};
+use futures_util::future::{Ready, err, ok};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+#[error("Invalid CalDAV-Timezones header")]
+pub struct InvalidCalDAVTimezonesHeader;
+
+impl ResponseError for InvalidCalDAVTimezonesHeader {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        StatusCode::BAD_REQUEST
+    }
+}
+
+/// CalDAV-Timezones header (RFC 7809 Section 3.1.1).
+/// With `F` the client accepts timezones by reference, so VTIMEZONEs of timezones from the
+/// IANA database are left out of returned calendar data and are replaced by references in
+/// calendar data it stores. Otherwise calendar data is stored as uploaded and VTIMEZONEs are
+/// added for timezones that are only referenced, which gives that data its own ETag.
+#[derive(Debug, PartialEq, Default)]
+pub enum CalDAVTimezones {
+    #[default]
+    T,
+    F,
+}
+
+impl CalDAVTimezones {
+    pub fn by_reference(&self) -> bool {
+        matches!(self, Self::F)
+    }
+}
+
+impl TryFrom<&[u8]> for CalDAVTimezones {
+    type Error = InvalidCalDAVTimezonesHeader;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        match value {
+            b"T" => Ok(CalDAVTimezones::T),
+            b"F" => Ok(CalDAVTimezones::F),
+            _ => Err(InvalidCalDAVTimezonesHeader),
+        }
+    }
+}
+
+impl FromRequest for CalDAVTimezones {
+    type Error = InvalidCalDAVTimezonesHeader;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn extract(req: &HttpRequest) -> Self::Future {
+        if let Some(timezones_header) = req.headers().get("CalDAV-Timezones") {
+            match timezones_header.as_bytes().try_into() {
+                Ok(timezones) => ok(timezones),
+                Err(e) => err(e),
+            }
+        } else {
+            // Clients that don't know about timezones by reference expect VTIMEZONEs
+            ok(CalDAVTimezones::T)
+        }
+    }
+
+    fn from_request(req: &HttpRequest, _payload: &mut actix_web::dev::Payload) -> Self::Future {
+        Self::extract(req)
+    }
+}
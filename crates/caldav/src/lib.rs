@@ -6,11 +6,13 @@ use actix_web::middleware::{ErrorHandlerResponse, ErrorHandlers};
 use actix_web::web::{self, Data};
 use calendar::resource::CalendarResourceService;
 use calendar_object::resource::CalendarObjectResourceService;
+use calendar_object::revisions::revisions_service;
 use calendar_set::CalendarSetResourceService;
 use principal::{PrincipalResource, PrincipalResourceService};
 use rustical_dav::resource::{NamedRoute, ResourceService, ResourceServiceRoute};
 use rustical_dav::resources::RootResourceService;
 use rustical_store::auth::{AuthenticationMiddleware, AuthenticationProvider, User};
+use rustical_store::quota::Quota;
 use rustical_store::{AddressbookStore, CalendarStore, ContactBirthdayStore, SubscriptionStore};
 use std::sync::Arc;
 use subscription::subscription_resource;
@@ -19,8 +21,10 @@ pub mod calendar;
 pub mod calendar_object;
 pub mod calendar_set;
 pub mod error;
+pub mod header;
 pub mod principal;
 mod subscription;
+pub mod tzdist;
 
 pub use error::Error;
 
@@ -34,6 +38,7 @@ pub fn caldav_service<
     store: Arc<C>,
     addr_store: Arc<AS>,
     subscription_store: Arc<S>,
+    quota: Arc<Quota>,
 ) -> impl HttpServiceFactory {
     let birthday_store = Arc::new(ContactBirthdayStore::new(addr_store));
 
@@ -62,30 +67,31 @@ pub fn caldav_service<
             .app_data(Data::from(store.clone()))
             .app_data(Data::from(birthday_store.clone()))
             .app_data(Data::from(subscription_store))
+            .app_data(Data::from(quota.clone()))
             .service(RootResourceService::<PrincipalResource, User>::default().actix_resource())
             .service(
                 web::scope("/principal").service(
                     web::scope("/{principal}")
-                        .service(PrincipalResourceService{auth_provider, home_set: &[
+                        .service(PrincipalResourceService{auth_provider, quota: quota.clone(), home_set: &[
                             ("calendar", false), ("birthdays", true)
                         ]}.actix_resource().name(PrincipalResource::route_name()))
                         .service(web::scope("/calendar")
-                            .service(CalendarSetResourceService::new(store.clone()).actix_resource())
+                            .service(CalendarSetResourceService::new(store.clone(), quota.clone()).actix_resource())
                             .service(
                                 web::scope("/{calendar}")
                                     .service(
-                                        ResourceServiceRoute(CalendarResourceService::<_, S>::new(store.clone()))
+                                        ResourceServiceRoute(CalendarResourceService::<_, S>::new(store.clone(), quota.clone()))
                                     )
                                         .service(web::scope("/{object}").service(CalendarObjectResourceService::new(store.clone()).actix_resource()
-                                    ))
+                                    ).service(revisions_service::<C>()))
                             )
                         )
                         .service(web::scope("/birthdays")
-                            .service(CalendarSetResourceService::new(birthday_store.clone()).actix_resource())
+                            .service(CalendarSetResourceService::new(birthday_store.clone(), quota.clone()).actix_resource())
                             .service(
                                 web::scope("/{calendar}")
                                     .service(
-                                        ResourceServiceRoute(CalendarResourceService::<_, S>::new(birthday_store.clone()))
+                                        ResourceServiceRoute(CalendarResourceService::<_, S>::new(birthday_store.clone(), quota))
                                     )
                                         .service(web::scope("/{object}").service(CalendarObjectResourceService::new(birthday_store.clone()).actix_resource()
                                     ))
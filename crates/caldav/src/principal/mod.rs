@@ -10,6 +10,7 @@ use rustical_dav::resource::{NamedRoute, Resource, ResourceService};
 use rustical_dav::xml::{HrefElement, Resourcetype, ResourcetypeInner};
 use rustical_store::auth::user::PrincipalType;
 use rustical_store::auth::{AuthenticationProvider, User};
+use rustical_store::quota::Quota;
 use rustical_xml::{EnumUnitVariants, EnumVariants, XmlDeserialize, XmlSerialize};
 
 #[derive(Clone)]
@@ -21,6 +22,9 @@ pub struct PrincipalResource {
 #[derive(XmlDeserialize, XmlSerialize, PartialEq, Clone)]
 pub struct CalendarHomeSet(#[xml(ty = "untagged", flatten)] Vec<HrefElement>);
 
+#[derive(XmlDeserialize, XmlSerialize, PartialEq, Clone)]
+pub struct CalendarUserAddressSet(#[xml(ty = "untagged", flatten)] Vec<HrefElement>);
+
 #[derive(XmlDeserialize, XmlSerialize, PartialEq, Clone, EnumVariants, EnumUnitVariants)]
 #[xml(unit_variants_ident = "PrincipalPropName")]
 pub enum PrincipalProp {
@@ -31,7 +35,7 @@ pub enum PrincipalProp {
     #[xml(ns = "rustical_dav::namespace::NS_CALDAV", skip_deserializing)]
     CalendarUserType(PrincipalType),
     #[xml(ns = "rustical_dav::namespace::NS_CALDAV")]
-    CalendarUserAddressSet(HrefElement),
+    CalendarUserAddressSet(CalendarUserAddressSet),
 
     // WebDAV Access Control (RFC 3744)
     #[xml(ns = "rustical_dav::namespace::NS_DAV", rename = b"principal-URL")]
@@ -114,7 +118,15 @@ impl Resource for PrincipalResource {
                     }
                     PrincipalPropName::CalendarHomeSet => PrincipalProp::CalendarHomeSet(home_set),
                     PrincipalPropName::CalendarUserAddressSet => {
-                        PrincipalProp::CalendarUserAddressSet(principal_url.into())
+                        // Email addresses first since clients tend to pick the first entry
+                        PrincipalProp::CalendarUserAddressSet(CalendarUserAddressSet(
+                            self.principal
+                                .calendar_user_addresses()
+                                .into_iter()
+                                .chain([principal_url])
+                                .map(HrefElement::new)
+                                .collect(),
+                        ))
                     }
                 })
             }
@@ -137,6 +149,7 @@ impl Resource for PrincipalResource {
 
 pub struct PrincipalResourceService<AP: AuthenticationProvider> {
     pub auth_provider: Arc<AP>,
+    pub quota: Arc<Quota>,
     pub home_set: &'static [(&'static str, bool)],
 }
 
@@ -167,6 +180,7 @@ impl<AP: AuthenticationProvider> ResourceService for PrincipalResourceService<AP
         &self,
         (principal,): &Self::PathComponents,
     ) -> Result<Vec<(String, Self::MemberType)>, Self::Error> {
+        let quota = self.quota.get_usage(principal).await?;
         Ok(self
             .home_set
             .iter()
@@ -176,6 +190,7 @@ impl<AP: AuthenticationProvider> ResourceService for PrincipalResourceService<AP
                     CalendarSetResource {
                         principal: principal.to_owned(),
                         read_only,
+                        quota,
                     },
                 )
             })
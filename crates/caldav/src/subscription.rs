@@ -2,11 +2,7 @@ use actix_web::{
     web::{self, Data, Path},
     HttpResponse,
 };
-use rustical_dav::xml::multistatus::PropstatElement;
 use rustical_store::SubscriptionStore;
-use rustical_xml::{XmlRootTag, XmlSerialize};
-
-use crate::calendar::resource::CalendarProp;
 
 async fn handle_delete<S: SubscriptionStore>(
     store: Data<S>,
@@ -22,9 +18,3 @@ pub fn subscription_resource<S: SubscriptionStore>() -> actix_web::Resource {
         .name("subscription")
         .delete(handle_delete::<S>)
 }
-
-#[derive(XmlSerialize, XmlRootTag)]
-#[xml(root = b"push-message", ns = "rustical_dav::namespace::NS_DAVPUSH")]
-pub struct PushMessage {
-    propstat: PropstatElement<CalendarProp>,
-}
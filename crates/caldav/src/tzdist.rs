@@ -0,0 +1,148 @@
+//! Timezone distribution service (RFC 7808) for the IANA database bundled with chrono-tz,
+//! mounted at `/.well-known/timezone`: `GET capabilities` describes the service,
+//! `GET zones` lists the timezones and `GET zones/{tzid}` returns one as VTIMEZONE.
+use actix_web::HttpResponse;
+use actix_web::dev::HttpServiceFactory;
+use actix_web::http::header::{self, EntityTag};
+use actix_web::web::{self, Path, Query};
+use chrono_tz::{IANA_TZDB_VERSION, TZ_VARIANTS, Tz};
+use rustical_store::calendar::VTimezone;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct Capabilities {
+    version: u32,
+    info: CapabilitiesInfo,
+    actions: Vec<Action>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct CapabilitiesInfo {
+    primary_source: String,
+    formats: Vec<&'static str>,
+    contacts: Vec<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct Action {
+    name: &'static str,
+    uri_template: &'static str,
+    parameters: Vec<ActionParameter>,
+}
+
+#[derive(Serialize)]
+struct ActionParameter {
+    name: &'static str,
+    required: bool,
+    multi: bool,
+}
+
+#[derive(Serialize)]
+struct TimezoneList {
+    synctoken: &'static str,
+    timezones: Vec<TimezoneInfo>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct TimezoneInfo {
+    tzid: &'static str,
+    last_modified: String,
+}
+
+/// Problem details (RFC 7807)
+#[derive(Serialize)]
+struct Problem {
+    r#type: &'static str,
+    title: String,
+    status: u16,
+}
+
+#[derive(Deserialize)]
+struct ListQuery {
+    changedsince: Option<String>,
+}
+
+pub fn tzdist_service() -> impl HttpServiceFactory {
+    web::scope("/timezone")
+        .service(web::resource("/capabilities").get(get_capabilities))
+        .service(web::resource("/zones").get(get_zones))
+        // The TZID is a single path segment with its slashes encoded,
+        // but clients might not encode them
+        .service(web::resource("/zones/{tzid:.+}").get(get_zone))
+}
+
+async fn get_capabilities() -> HttpResponse {
+    HttpResponse::Ok().json(Capabilities {
+        version: 1,
+        info: CapabilitiesInfo {
+            primary_source: format!("IANA:{IANA_TZDB_VERSION}"),
+            formats: vec!["text/calendar"],
+            contacts: vec![],
+        },
+        actions: vec![
+            Action {
+                name: "capabilities",
+                uri_template: "/capabilities",
+                parameters: vec![],
+            },
+            Action {
+                name: "list",
+                uri_template: "/zones{?changedsince}",
+                parameters: vec![ActionParameter {
+                    name: "changedsince",
+                    required: false,
+                    multi: false,
+                }],
+            },
+            Action {
+                name: "get",
+                uri_template: "/zones{/tzid}",
+                parameters: vec![],
+            },
+        ],
+    })
+}
+
+async fn get_zones(query: Query<ListQuery>) -> HttpResponse {
+    // The data only changes with the bundled database, so its version is the synctoken
+    let timezones = if query.changedsince.as_deref() == Some(IANA_TZDB_VERSION) {
+        vec![]
+    } else {
+        // The release date isn't bundled, the start of its year has to do
+        let last_modified = format!("{}-01-01T00:00:00Z", &IANA_TZDB_VERSION[..4]);
+        TZ_VARIANTS
+            .iter()
+            .map(|tz| TimezoneInfo {
+                tzid: tz.name(),
+                last_modified: last_modified.to_owned(),
+            })
+            .collect()
+    };
+    HttpResponse::Ok().json(TimezoneList {
+        synctoken: IANA_TZDB_VERSION,
+        timezones,
+    })
+}
+
+async fn get_zone(path: Path<String>) -> HttpResponse {
+    let tzid = path.into_inner().replace("%2F", "/").replace("%2f", "/");
+    let Ok(tz) = tzid.parse::<Tz>() else {
+        return HttpResponse::NotFound()
+            .content_type("application/problem+json")
+            .json(Problem {
+                r#type: "urn:ietf:params:tzdist:error:tzid-not-found",
+                title: format!("Timezone {tzid} not found"),
+                status: 404,
+            });
+    };
+    HttpResponse::Ok()
+        .insert_header(header::ETag(EntityTag::new_strong(
+            IANA_TZDB_VERSION.to_owned(),
+        )))
+        .content_type("text/calendar; charset=utf-8")
+        .body(VTimezone::from_tz(tz).to_vcalendar())
+}
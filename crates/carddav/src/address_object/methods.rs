@@ -1,23 +1,28 @@
-use super::resource::AddressObjectPathComponents;
+use super::resource::{AddressObjectPathComponents, AddressObjectResource};
 use crate::addressbook::resource::AddressbookResource;
 use crate::Error;
 use actix_web::http::header;
-use actix_web::http::header::HeaderValue;
-use actix_web::web::{Data, Path};
+use actix_web::http::header::{EntityTag, HeaderValue, IfMatch, IfModifiedSince, IfNoneMatch};
+use actix_web::web::{Data, Header, Path};
 use actix_web::HttpRequest;
 use actix_web::HttpResponse;
 use rustical_dav::privileges::UserPrivilege;
 use rustical_dav::resource::Resource;
 use rustical_store::auth::User;
+use rustical_store::quota::Quota;
 use rustical_store::{AddressObject, AddressbookStore};
+use std::time::SystemTime;
 use tracing::instrument;
 use tracing_actix_web::RootSpan;
 
-#[instrument(parent = root_span.id(), skip(store, root_span))]
+#[instrument(parent = root_span.id(), skip(store, quota, root_span))]
 pub async fn get_object<AS: AddressbookStore>(
     path: Path<AddressObjectPathComponents>,
     store: Data<AS>,
+    quota: Data<Quota>,
     user: User,
+    if_none_match: Header<IfNoneMatch>,
+    if_modified_since: Option<Header<IfModifiedSince>>,
     root_span: RootSpan,
 ) -> Result<HttpResponse, Error> {
     let AddressObjectPathComponents {
@@ -31,7 +36,11 @@ pub async fn get_object<AS: AddressbookStore>(
     }
 
     let addressbook = store.get_addressbook(&principal, &addressbook_id).await?;
-    let addressbook_resource = AddressbookResource(addressbook);
+    let addressbook_resource = AddressbookResource {
+        addressbook,
+        read_only: store.is_read_only(),
+        max_resource_size: quota.max_resource_size(),
+    };
     if !addressbook_resource
         .get_user_privileges(&user)?
         .has(&UserPrivilege::Read)
@@ -43,17 +52,38 @@ pub async fn get_object<AS: AddressbookStore>(
         .get_object(&principal, &addressbook_id, &object_id)
         .await?;
 
-    Ok(HttpResponse::Ok()
-        .insert_header(("ETag", object.get_etag()))
-        .insert_header(("Content-Type", "text/vcard"))
-        .body(object.get_vcf().to_owned()))
+    let etag = EntityTag::new_strong(object.get_etag());
+    let last_modified = object.get_updated_at();
+    let resource = AddressObjectResource {
+        object,
+        principal,
+    };
+    if resource.is_not_modified(&if_none_match, if_modified_since.as_deref()) {
+        return Ok(HttpResponse::NotModified()
+            .insert_header(header::ETag(etag))
+            .finish());
+    }
+
+    let mut response = HttpResponse::Ok();
+    response
+        .insert_header(header::ETag(etag))
+        .insert_header(("Content-Type", "text/vcard"));
+    if let Some(last_modified) = last_modified {
+        response.insert_header(header::LastModified(
+            SystemTime::from(last_modified.and_utc()).into(),
+        ));
+    }
+    Ok(response.body(resource.object.get_vcf().to_owned()))
 }
 
-#[instrument(parent = root_span.id(), skip(store, req, root_span))]
+#[allow(clippy::too_many_arguments)]
+#[instrument(parent = root_span.id(), skip(store, quota, req, root_span))]
 pub async fn put_object<AS: AddressbookStore>(
     path: Path<AddressObjectPathComponents>,
     store: Data<AS>,
+    quota: Data<Quota>,
     body: String,
+    if_match: Header<IfMatch>,
     user: User,
     req: HttpRequest,
     root_span: RootSpan,
@@ -68,13 +98,50 @@ pub async fn put_object<AS: AddressbookStore>(
         return Err(Error::Unauthorized);
     }
 
+    if body.len() as u64 > quota.max_resource_size() {
+        return Err(Error::MaxResourceSizeExceeded);
+    }
+
+    let existing = match store
+        .get_object(&principal, &addressbook_id, &object_id)
+        .await
+    {
+        Ok(object) => Some(object),
+        Err(rustical_store::Error::NotFound) => None,
+        Err(err) => return Err(err.into()),
+    };
+
+    // If-Match (RFC 7232 Section 3.1) is only satisfied by an existing object
+    if !matches!(&*if_match, IfMatch::Items(items) if items.is_empty()) {
+        let satisfied = existing.is_some_and(|object| {
+            AddressObjectResource {
+                object,
+                principal: principal.to_owned(),
+            }
+            .satisfies_if_match(&if_match)
+        });
+        if !satisfied {
+            return Ok(HttpResponse::PreconditionFailed().finish());
+        }
+    }
+
     let overwrite =
         Some(&HeaderValue::from_static("*")) != req.headers().get(header::IF_NONE_MATCH);
 
     let object = AddressObject::from_vcf(object_id, body)?;
+    let etag = EntityTag::new_strong(object.get_etag());
     store
-        .put_object(principal, addressbook_id, object, overwrite)
+        .put_object_with_quota(
+            principal,
+            addressbook_id,
+            object,
+            overwrite,
+            Some(&user.id),
+            &quota,
+        )
         .await?;
 
-    Ok(HttpResponse::Created().finish())
+    Ok(HttpResponse::Created()
+        .insert_header(header::ETag(etag))
+        .finish())
 }
@@ -1,2 +1,3 @@
 pub mod methods;
 pub mod resource;
+pub mod revisions;
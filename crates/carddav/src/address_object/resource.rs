@@ -1,9 +1,14 @@
 use crate::{Error, principal::PrincipalResource};
 use actix_web::dev::ResourceMap;
+use actix_web::http::header::EntityTag;
 use async_trait::async_trait;
+use chrono::NaiveDateTime;
 use derive_more::derive::{Constructor, From, Into};
 use rustical_dav::{
-    extensions::{CommonPropertiesExtension, CommonPropertiesProp},
+    extensions::{
+        CommonPropertiesExtension, CommonPropertiesProp, TimestampsExtension,
+        TimestampsExtensionProp,
+    },
     privileges::UserPrivilegeSet,
     resource::{Resource, ResourceService},
     xml::Resourcetype,
@@ -38,6 +43,7 @@ pub enum AddressObjectProp {
 #[xml(unit_variants_ident = "AddressObjectPropWrapperName", untagged)]
 pub enum AddressObjectPropWrapper {
     AddressObject(AddressObjectProp),
+    Timestamps(TimestampsExtensionProp),
     Common(CommonPropertiesProp),
 }
 
@@ -51,6 +57,8 @@ impl CommonPropertiesExtension for AddressObjectResource {
     type PrincipalResource = PrincipalResource;
 }
 
+impl TimestampsExtension for AddressObjectResource {}
+
 impl Resource for AddressObjectResource {
     type Prop = AddressObjectPropWrapper;
     type Error = Error;
@@ -60,6 +68,10 @@ impl Resource for AddressObjectResource {
         Resourcetype(&[])
     }
 
+    fn content_props() -> &'static [&'static str] {
+        &["address-data"]
+    }
+
     fn get_prop(
         &self,
         rmap: &ResourceMap,
@@ -69,9 +81,9 @@ impl Resource for AddressObjectResource {
         Ok(match prop {
             AddressObjectPropWrapperName::AddressObject(prop) => {
                 AddressObjectPropWrapper::AddressObject(match prop {
-                    AddressObjectPropName::Getetag => {
-                        AddressObjectProp::Getetag(self.object.get_etag())
-                    }
+                    AddressObjectPropName::Getetag => AddressObjectProp::Getetag(
+                        EntityTag::new_strong(self.object.get_etag()).to_string(),
+                    ),
                     AddressObjectPropName::AddressData => {
                         AddressObjectProp::AddressData(self.object.get_vcf().to_owned())
                     }
@@ -80,6 +92,9 @@ impl Resource for AddressObjectResource {
                     }
                 })
             }
+            AddressObjectPropWrapperName::Timestamps(prop) => AddressObjectPropWrapper::Timestamps(
+                <Self as TimestampsExtension>::get_prop(self, prop)?,
+            ),
             AddressObjectPropWrapperName::Common(prop) => AddressObjectPropWrapper::Common(
                 CommonPropertiesExtension::get_prop(self, rmap, user, prop)?,
             ),
@@ -90,6 +105,14 @@ impl Resource for AddressObjectResource {
         Some(&self.principal)
     }
 
+    fn get_creation_date(&self) -> Option<NaiveDateTime> {
+        self.object.get_created_at()
+    }
+
+    fn get_last_modified(&self) -> Option<NaiveDateTime> {
+        self.object.get_updated_at()
+    }
+
     fn get_etag(&self) -> Option<String> {
         Some(self.object.get_etag())
     }
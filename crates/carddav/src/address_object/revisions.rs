@@ -0,0 +1,181 @@
+//! Read-only access to the revisions of an object and rollback:
+//! `GET {object}/revisions` lists them as JSON, `GET {object}/revisions/{revision}` returns
+//! the object at that revision, `GET {object}/revisions/{revision}/diff` the changes from that
+//! revision to the current version and `POST {object}/revisions/{revision}` rolls back to it.
+use super::resource::AddressObjectPathComponents;
+use crate::Error;
+use actix_web::HttpResponse;
+use actix_web::dev::HttpServiceFactory;
+use actix_web::http::header::{self, EntityTag};
+use actix_web::web::{self, Data, Path};
+use chrono::NaiveDateTime;
+use rustical_store::auth::User;
+use rustical_store::quota::Quota;
+use rustical_store::revision::diff_lines;
+use rustical_store::{AddressObject, AddressbookStore, ObjectRevision};
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+#[derive(Debug, Clone)]
+pub struct RevisionPathComponents {
+    pub object: AddressObjectPathComponents,
+    pub revision: i64,
+}
+
+impl<'de> Deserialize<'de> for RevisionPathComponents {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        type Inner = (String, String, String, i64);
+        let (principal, addressbook_id, mut object_id, revision) =
+            Inner::deserialize(deserializer)?;
+        if object_id.ends_with(".vcf") {
+            object_id.truncate(object_id.len() - 4);
+        }
+        Ok(Self {
+            object: AddressObjectPathComponents {
+                principal,
+                addressbook_id,
+                object_id,
+            },
+            revision,
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct RevisionInfo {
+    revision: i64,
+    created_at: NaiveDateTime,
+    changed_by: Option<String>,
+    etag: String,
+}
+
+pub fn revisions_service<AS: AddressbookStore>() -> impl HttpServiceFactory {
+    web::scope("/revisions")
+        .service(web::resource("").get(get_revisions::<AS>))
+        .service(
+            web::resource("/{revision}")
+                .get(get_revision::<AS>)
+                .post(post_rollback::<AS>),
+        )
+        .service(web::resource("/{revision}/diff").get(get_revision_diff::<AS>))
+}
+
+async fn find_revision<AS: AddressbookStore>(
+    store: &AS,
+    RevisionPathComponents { object, revision }: &RevisionPathComponents,
+) -> Result<ObjectRevision<AddressObject>, Error> {
+    Ok(store
+        .get_object_revisions(&object.principal, &object.addressbook_id, &object.object_id)
+        .await?
+        .into_iter()
+        .find(|rev| rev.revision == *revision)
+        .ok_or(rustical_store::Error::NotFound)?)
+}
+
+#[instrument(skip(store))]
+async fn get_revisions<AS: AddressbookStore>(
+    path: Path<AddressObjectPathComponents>,
+    store: Data<AS>,
+    user: User,
+) -> Result<HttpResponse, Error> {
+    let AddressObjectPathComponents {
+        principal,
+        addressbook_id,
+        object_id,
+    } = path.into_inner();
+    if !user.is_principal(&principal) {
+        return Err(Error::Unauthorized);
+    }
+
+    let revisions: Vec<_> = store
+        .get_object_revisions(&principal, &addressbook_id, &object_id)
+        .await?
+        .into_iter()
+        .map(|rev| RevisionInfo {
+            revision: rev.revision,
+            created_at: rev.created_at,
+            changed_by: rev.changed_by,
+            etag: rev.object.get_etag(),
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(revisions))
+}
+
+#[instrument(skip(store))]
+async fn get_revision<AS: AddressbookStore>(
+    path: Path<RevisionPathComponents>,
+    store: Data<AS>,
+    user: User,
+) -> Result<HttpResponse, Error> {
+    let path = path.into_inner();
+    if !user.is_principal(&path.object.principal) {
+        return Err(Error::Unauthorized);
+    }
+
+    let revision = find_revision(store.as_ref(), &path).await?;
+    Ok(HttpResponse::Ok()
+        .insert_header(header::ETag(EntityTag::new_strong(
+            revision.object.get_etag(),
+        )))
+        .insert_header(("Content-Type", "text/vcard"))
+        .body(revision.object.get_vcf().to_owned()))
+}
+
+#[instrument(skip(store))]
+async fn get_revision_diff<AS: AddressbookStore>(
+    path: Path<RevisionPathComponents>,
+    store: Data<AS>,
+    user: User,
+) -> Result<HttpResponse, Error> {
+    let path = path.into_inner();
+    let object = &path.object;
+    if !user.is_principal(&object.principal) {
+        return Err(Error::Unauthorized);
+    }
+
+    let revision = find_revision(store.as_ref(), &path).await?;
+    // The object might have been deleted since
+    let current = match store
+        .get_object(&object.principal, &object.addressbook_id, &object.object_id)
+        .await
+    {
+        Ok(current) => current.get_vcf().to_owned(),
+        Err(rustical_store::Error::NotFound) => String::new(),
+        Err(err) => return Err(err.into()),
+    };
+    let diff: String = diff_lines(revision.object.get_vcf(), &current)
+        .iter()
+        .map(|line| line.format() + "\n")
+        .collect();
+    Ok(HttpResponse::Ok()
+        .insert_header(("Content-Type", "text/x-diff"))
+        .body(diff))
+}
+
+#[instrument(skip(store, quota))]
+async fn post_rollback<AS: AddressbookStore>(
+    path: Path<RevisionPathComponents>,
+    store: Data<AS>,
+    quota: Data<Quota>,
+    user: User,
+) -> Result<HttpResponse, Error> {
+    let RevisionPathComponents { object, revision } = path.into_inner();
+    if !user.is_principal(&object.principal) {
+        return Err(Error::Unauthorized);
+    }
+
+    store
+        .rollback_object(
+            &object.principal,
+            &object.addressbook_id,
+            &object.object_id,
+            revision,
+            Some(&user.id),
+            &quota,
+        )
+        .await?;
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -60,6 +60,8 @@ pub async fn route_mkcol<AS: AddressbookStore>(
         principal: principal.to_owned(),
         displayname: request.displayname,
         description: request.description,
+        created_at: None,
+        updated_at: None,
         deleted_at: None,
         synctoken: 0,
         push_topic: uuid::Uuid::new_v4().to_string(),
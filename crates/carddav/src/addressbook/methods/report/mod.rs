@@ -64,7 +64,10 @@ pub async fn route_report_addressbook<AS: AddressbookStore>(
 
 #[cfg(test)]
 mod tests {
-    use rustical_dav::xml::{sync_collection::SyncLevel, PropElement, Propname};
+    use rustical_dav::xml::{
+        PropElement, Propname,
+        sync_collection::{LimitElement, SyncLevel},
+    };
 
     use super::*;
 
@@ -95,6 +98,27 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_xml_sync_collection_limit() {
+        let report_request = ReportRequest::parse_str(
+            r#"
+        <?xml version='1.0' encoding='UTF-8' ?>
+        <sync-collection xmlns="DAV:">
+            <sync-token>github.com/lennart-k/rustical/ns/12</sync-token>
+            <sync-level>1</sync-level>
+            <limit><nresults>100</nresults></limit>
+            <prop>
+                <getetag />
+            </prop>
+        </sync-collection>"#,
+        )
+        .unwrap();
+        let ReportRequest::SyncCollection(sync_collection) = report_request else {
+            panic!("expected sync-collection");
+        };
+        assert_eq!(sync_collection.limit, Some(LimitElement { nresults: 100 }));
+    }
+
     #[test]
     fn test_xml_addressbook_multiget() {
         let report_request = ReportRequest::parse_str(r#"
@@ -38,8 +38,11 @@ pub async fn handle_sync_collection<AS: AddressbookStore>(
     let props: Vec<&str> = props.iter().map(String::as_str).collect();
 
     let old_synctoken = parse_synctoken(&sync_collection.sync_token).unwrap_or(0);
-    let (new_objects, deleted_objects, new_synctoken) = addr_store
-        .sync_changes(principal, addressbook_id, old_synctoken)
+    let limit = sync_collection
+        .limit
+        .map(|limit| limit.nresults.try_into().unwrap_or(usize::MAX));
+    let (new_objects, deleted_objects, new_synctoken, truncated) = addr_store
+        .sync_changes(principal, addressbook_id, old_synctoken, limit)
         .await?;
 
     let mut responses = Vec::new();
@@ -63,6 +66,15 @@ pub async fn handle_sync_collection<AS: AddressbookStore>(
         });
     }
 
+    if truncated {
+        // RFC 6578 Section 3.6: The client continues with the returned sync-token
+        responses.push(ResponseElement {
+            href: req.path().to_owned(),
+            status: Some(StatusCode::INSUFFICIENT_STORAGE),
+            ..Default::default()
+        });
+    }
+
     Ok(MultistatusElement {
         responses,
         sync_token: Some(format_synctoken(new_synctoken)),
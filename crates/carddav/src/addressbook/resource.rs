@@ -9,15 +9,19 @@ use actix_web::dev::ResourceMap;
 use actix_web::http::Method;
 use actix_web::web;
 use async_trait::async_trait;
+use chrono::NaiveDateTime;
 use derive_more::derive::{From, Into};
+use futures_util::{StreamExt, stream};
 use rustical_dav::extensions::{
     CommonPropertiesExtension, CommonPropertiesProp, SyncTokenExtension, SyncTokenExtensionProp,
+    TimestampsExtension, TimestampsExtensionProp,
 };
 use rustical_dav::privileges::UserPrivilegeSet;
-use rustical_dav::resource::{Resource, ResourceService};
+use rustical_dav::resource::{MembersStream, Resource, ResourceService};
 use rustical_dav::xml::{Resourcetype, ResourcetypeInner};
 use rustical_dav_push::{DavPushExtension, DavPushExtensionProp};
 use rustical_store::auth::User;
+use rustical_store::quota::Quota;
 use rustical_store::{Addressbook, AddressbookStore, SubscriptionStore};
 use rustical_xml::{EnumUnitVariants, EnumVariants, XmlDeserialize, XmlSerialize};
 use std::marker::PhantomData;
@@ -26,13 +30,15 @@ use std::sync::Arc;
 
 pub struct AddressbookResourceService<AS: AddressbookStore, S: SubscriptionStore> {
     addr_store: Arc<AS>,
+    quota: Arc<Quota>,
     __phantom_sub: PhantomData<S>,
 }
 
 impl<A: AddressbookStore, S: SubscriptionStore> AddressbookResourceService<A, S> {
-    pub fn new(addr_store: Arc<A>) -> Self {
+    pub fn new(addr_store: Arc<A>, quota: Arc<Quota>) -> Self {
         Self {
             addr_store,
+            quota,
             __phantom_sub: PhantomData,
         }
     }
@@ -52,8 +58,8 @@ pub enum AddressbookProp {
     SupportedAddressData(SupportedAddressData),
     #[xml(ns = "rustical_dav::namespace::NS_CARDDAV", skip_deserializing)]
     SupportedReportSet(SupportedReportSet),
-    #[xml(ns = "rustical_dav::namespace::NS_DAV")]
-    MaxResourceSize(i64),
+    #[xml(ns = "rustical_dav::namespace::NS_CARDDAV", skip_deserializing)]
+    MaxResourceSize(u64),
 }
 
 #[derive(XmlDeserialize, XmlSerialize, PartialEq, Clone, EnumVariants, EnumUnitVariants)]
@@ -62,21 +68,32 @@ pub enum AddressbookPropWrapper {
     Addressbook(AddressbookProp),
     SyncToken(SyncTokenExtensionProp),
     DavPush(DavPushExtensionProp),
+    Timestamps(TimestampsExtensionProp),
     Common(CommonPropertiesProp),
 }
 
 #[derive(Clone, Debug, From, Into)]
-pub struct AddressbookResource(pub(crate) Addressbook);
+pub struct AddressbookResource {
+    pub addressbook: Addressbook,
+    pub read_only: bool,
+    pub max_resource_size: u64,
+}
+
+impl From<AddressbookResource> for Addressbook {
+    fn from(value: AddressbookResource) -> Self {
+        value.addressbook
+    }
+}
 
 impl SyncTokenExtension for AddressbookResource {
     fn get_synctoken(&self) -> String {
-        self.0.format_synctoken()
+        self.addressbook.format_synctoken()
     }
 }
 
 impl DavPushExtension for AddressbookResource {
     fn get_topic(&self) -> String {
-        self.0.push_topic.to_owned()
+        self.addressbook.push_topic.to_owned()
     }
 }
 
@@ -84,6 +101,8 @@ impl CommonPropertiesExtension for AddressbookResource {
     type PrincipalResource = PrincipalResource;
 }
 
+impl TimestampsExtension for AddressbookResource {}
+
 impl Resource for AddressbookResource {
     type Prop = AddressbookPropWrapper;
     type Error = Error;
@@ -106,16 +125,18 @@ impl Resource for AddressbookResource {
             AddressbookPropWrapperName::Addressbook(prop) => {
                 AddressbookPropWrapper::Addressbook(match prop {
                     AddressbookPropName::Displayname => {
-                        AddressbookProp::Displayname(self.0.displayname.clone())
+                        AddressbookProp::Displayname(self.addressbook.displayname.clone())
                     }
                     AddressbookPropName::MaxResourceSize => {
-                        AddressbookProp::MaxResourceSize(10000000)
+                        AddressbookProp::MaxResourceSize(self.max_resource_size)
                     }
                     AddressbookPropName::SupportedReportSet => {
                         AddressbookProp::SupportedReportSet(SupportedReportSet::default())
                     }
                     AddressbookPropName::AddressbookDescription => {
-                        AddressbookProp::AddressbookDescription(self.0.description.to_owned())
+                        AddressbookProp::AddressbookDescription(
+                            self.addressbook.description.to_owned(),
+                        )
                     }
                     AddressbookPropName::SupportedAddressData => {
                         AddressbookProp::SupportedAddressData(SupportedAddressData::default())
@@ -129,6 +150,9 @@ impl Resource for AddressbookResource {
             AddressbookPropWrapperName::DavPush(prop) => {
                 AddressbookPropWrapper::DavPush(<Self as DavPushExtension>::get_prop(self, prop)?)
             }
+            AddressbookPropWrapperName::Timestamps(prop) => AddressbookPropWrapper::Timestamps(
+                <Self as TimestampsExtension>::get_prop(self, prop)?,
+            ),
             AddressbookPropWrapperName::Common(prop) => AddressbookPropWrapper::Common(
                 CommonPropertiesExtension::get_prop(self, rmap, user, prop)?,
             ),
@@ -136,14 +160,17 @@ impl Resource for AddressbookResource {
     }
 
     fn set_prop(&mut self, prop: Self::Prop) -> Result<(), rustical_dav::Error> {
+        if self.read_only {
+            return Err(rustical_dav::Error::PropReadOnly);
+        }
         match prop {
             AddressbookPropWrapper::Addressbook(prop) => match prop {
                 AddressbookProp::Displayname(displayname) => {
-                    self.0.displayname = displayname;
+                    self.addressbook.displayname = displayname;
                     Ok(())
                 }
                 AddressbookProp::AddressbookDescription(description) => {
-                    self.0.description = description;
+                    self.addressbook.description = description;
                     Ok(())
                 }
                 AddressbookProp::MaxResourceSize(_) => Err(rustical_dav::Error::PropReadOnly),
@@ -152,6 +179,7 @@ impl Resource for AddressbookResource {
             },
             AddressbookPropWrapper::SyncToken(prop) => SyncTokenExtension::set_prop(self, prop),
             AddressbookPropWrapper::DavPush(prop) => DavPushExtension::set_prop(self, prop),
+            AddressbookPropWrapper::Timestamps(prop) => TimestampsExtension::set_prop(self, prop),
             AddressbookPropWrapper::Common(prop) => CommonPropertiesExtension::set_prop(self, prop),
         }
     }
@@ -160,14 +188,17 @@ impl Resource for AddressbookResource {
         &mut self,
         prop: &AddressbookPropWrapperName,
     ) -> Result<(), rustical_dav::Error> {
+        if self.read_only {
+            return Err(rustical_dav::Error::PropReadOnly);
+        }
         match prop {
             AddressbookPropWrapperName::Addressbook(prop) => match prop {
                 AddressbookPropName::Displayname => {
-                    self.0.displayname = None;
+                    self.addressbook.displayname = None;
                     Ok(())
                 }
                 AddressbookPropName::AddressbookDescription => {
-                    self.0.description = None;
+                    self.addressbook.description = None;
                     Ok(())
                 }
                 AddressbookPropName::MaxResourceSize => Err(rustical_dav::Error::PropReadOnly),
@@ -178,6 +209,9 @@ impl Resource for AddressbookResource {
                 SyncTokenExtension::remove_prop(self, prop)
             }
             AddressbookPropWrapperName::DavPush(prop) => DavPushExtension::remove_prop(self, prop),
+            AddressbookPropWrapperName::Timestamps(prop) => {
+                TimestampsExtension::remove_prop(self, prop)
+            }
             AddressbookPropWrapperName::Common(prop) => {
                 CommonPropertiesExtension::remove_prop(self, prop)
             }
@@ -185,12 +219,26 @@ impl Resource for AddressbookResource {
     }
 
     fn get_owner(&self) -> Option<&str> {
-        Some(&self.0.principal)
+        Some(&self.addressbook.principal)
+    }
+
+    fn get_creation_date(&self) -> Option<NaiveDateTime> {
+        self.addressbook.created_at
+    }
+
+    fn get_last_modified(&self) -> Option<NaiveDateTime> {
+        self.addressbook.updated_at
     }
 
     fn get_user_privileges(&self, user: &User) -> Result<UserPrivilegeSet, Self::Error> {
+        if self.read_only {
+            return Ok(UserPrivilegeSet::owner_read(
+                user.is_principal(&self.addressbook.principal),
+            ));
+        }
+
         Ok(UserPrivilegeSet::owner_only(
-            user.is_principal(&self.0.principal),
+            user.is_principal(&self.addressbook.principal),
         ))
     }
 }
@@ -214,7 +262,11 @@ impl<AS: AddressbookStore, S: SubscriptionStore> ResourceService
             .get_addressbook(principal, addressbook_id)
             .await
             .map_err(|_e| Error::NotFound)?;
-        Ok(addressbook.into())
+        Ok(AddressbookResource {
+            addressbook,
+            read_only: self.addr_store.is_read_only(),
+            max_resource_size: self.quota.max_resource_size(),
+        })
     }
 
     async fn get_members(
@@ -238,6 +290,27 @@ impl<AS: AddressbookStore, S: SubscriptionStore> ResourceService
             .collect())
     }
 
+    async fn get_members_metadata_stream(
+        &self,
+        (principal, addressbook_id): &Self::PathComponents,
+    ) -> Result<MembersStream<Self::MemberType, Self::Error>, Self::Error> {
+        let principal = principal.to_owned();
+        let objects = self
+            .addr_store
+            .get_objects_metadata(&principal, addressbook_id)
+            .await?;
+        Ok(stream::iter(objects.into_iter().map(move |object| {
+            Ok((
+                object.get_id().to_string(),
+                AddressObjectResource {
+                    object,
+                    principal: principal.to_owned(),
+                },
+            ))
+        }))
+        .boxed_local())
+    }
+
     async fn save_resource(
         &self,
         (principal, addressbook_id): &Self::PathComponents,
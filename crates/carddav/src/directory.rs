@@ -0,0 +1,11 @@
+use rustical_dav::resource::NamedRoute;
+
+/// Route of the addressbook listing all principals,
+/// advertised as directory gateway (https://datatracker.ietf.org/doc/html/rfc6352#section-6.2.3)
+pub struct DirectoryRoute;
+
+impl NamedRoute for DirectoryRoute {
+    fn route_name() -> &'static str {
+        "carddav_directory"
+    }
+}
@@ -9,19 +9,23 @@ use actix_web::{
     web::{self, Data},
 };
 use address_object::resource::AddressObjectResourceService;
+use address_object::revisions::revisions_service;
 use addressbook::resource::AddressbookResourceService;
+use directory::DirectoryRoute;
 pub use error::Error;
 use principal::{PrincipalResource, PrincipalResourceService};
 use rustical_dav::resource::{NamedRoute, ResourceService};
 use rustical_dav::resources::RootResourceService;
 use rustical_store::{
-    AddressbookStore, SubscriptionStore,
+    AddressbookStore, DirectoryStore, SubscriptionStore,
     auth::{AuthenticationMiddleware, AuthenticationProvider, User},
+    quota::Quota,
 };
 use std::sync::Arc;
 
 pub mod address_object;
 pub mod addressbook;
+pub mod directory;
 pub mod error;
 pub mod principal;
 
@@ -29,7 +33,10 @@ pub fn carddav_service<AP: AuthenticationProvider, A: AddressbookStore, S: Subsc
     auth_provider: Arc<AP>,
     store: Arc<A>,
     subscription_store: Arc<S>,
+    quota: Arc<Quota>,
 ) -> impl HttpServiceFactory {
+    let directory_store = Arc::new(DirectoryStore::new(auth_provider.clone()));
+
     web::scope("")
         .wrap(AuthenticationMiddleware::new(auth_provider.clone()))
         .wrap(
@@ -53,29 +60,49 @@ pub fn carddav_service<AP: AuthenticationProvider, A: AddressbookStore, S: Subsc
             }),
         )
         .app_data(Data::from(store.clone()))
+        .app_data(Data::from(directory_store.clone()))
         .app_data(Data::from(subscription_store))
+        .app_data(Data::from(quota.clone()))
         .service(RootResourceService::<PrincipalResource, User>::default().actix_resource())
         .service(
             web::scope("/principal").service(
                 web::scope("/{principal}")
                     .service(
-                        PrincipalResourceService::new(store.clone(), auth_provider)
+                        PrincipalResourceService::new(store.clone(), auth_provider, quota.clone())
                             .actix_resource()
                             .name(PrincipalResource::route_name()),
                     )
                     .service(
                         web::scope("/{addressbook}")
                             .service(
-                                AddressbookResourceService::<A, S>::new(store.clone())
-                                    .actix_resource(),
+                                AddressbookResourceService::<A, S>::new(
+                                    store.clone(),
+                                    quota.clone(),
+                                )
+                                .actix_resource(),
                             )
                             .service(
-                                web::scope("/{object}").service(
-                                    AddressObjectResourceService::<A>::new(store.clone())
-                                        .actix_resource(),
-                                ),
+                                web::scope("/{object}")
+                                    .service(
+                                        AddressObjectResourceService::<A>::new(store.clone())
+                                            .actix_resource(),
+                                    )
+                                    .service(revisions_service::<A>()),
                             ),
                     ),
             ),
         )
+        .service(
+            web::scope("/directory/{principal}/{addressbook}")
+                .service(
+                    AddressbookResourceService::<_, S>::new(directory_store.clone(), quota)
+                        .actix_resource()
+                        .name(DirectoryRoute::route_name()),
+                )
+                .service(
+                    web::scope("/{object}").service(
+                        AddressObjectResourceService::new(directory_store).actix_resource(),
+                    ),
+                ),
+        )
 }
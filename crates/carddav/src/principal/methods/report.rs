@@ -0,0 +1,229 @@
+use crate::{
+    Error,
+    address_object::resource::{AddressObjectPropWrapper, AddressObjectResource},
+    addressbook::resource::{AddressbookPropWrapper, AddressbookResource},
+};
+use actix_web::{
+    HttpRequest, Responder,
+    http::StatusCode,
+    web::{Data, Path},
+};
+use rustical_dav::{
+    resource::Resource,
+    xml::{
+        MultistatusElement, PropElement, PropfindType,
+        multistatus::ResponseElement,
+        sync_collection::{SyncCollectionRequest, SyncLevel},
+    },
+};
+use rustical_store::{
+    AddressbookStore,
+    auth::User,
+    quota::Quota,
+    synctoken::{format_home_synctoken, parse_home_synctoken},
+};
+use rustical_xml::{XmlDeserialize, XmlDocument};
+use std::collections::HashMap;
+use tracing::instrument;
+
+#[derive(XmlDeserialize, XmlDocument, Clone, Debug, PartialEq)]
+pub(crate) enum ReportRequest {
+    #[xml(ns = "rustical_dav::namespace::NS_DAV")]
+    SyncCollection(SyncCollectionRequest),
+}
+
+#[instrument(skip(req, addr_store, quota))]
+pub async fn route_report_addressbook_home<AS: AddressbookStore>(
+    path: Path<(String,)>,
+    body: String,
+    user: User,
+    req: HttpRequest,
+    addr_store: Data<AS>,
+    quota: Data<Quota>,
+) -> Result<impl Responder, Error> {
+    let (principal,) = path.into_inner();
+    if !user.is_principal(&principal) {
+        return Err(Error::Unauthorized);
+    }
+
+    Ok(match ReportRequest::parse_str(&body)? {
+        ReportRequest::SyncCollection(sync_collection) => {
+            handle_sync_collection(
+                sync_collection,
+                req,
+                &user,
+                &principal,
+                addr_store.as_ref(),
+                quota.max_resource_size(),
+            )
+            .await?
+        }
+    })
+}
+
+/// Reports changes across all addressbooks of an addressbook home.
+/// The home sync-token carries the synctoken of every addressbook known to the client,
+/// so created and deleted addressbooks are detected by comparing against it.
+/// With sync-level 1 only the addressbooks themselves are reported,
+/// with sync-level infinity their changed objects are included.
+pub async fn handle_sync_collection<AS: AddressbookStore>(
+    sync_collection: SyncCollectionRequest,
+    req: HttpRequest,
+    user: &User,
+    principal: &str,
+    addr_store: &AS,
+    max_resource_size: u64,
+) -> Result<MultistatusElement<AddressbookPropWrapper, AddressObjectPropWrapper>, Error> {
+    let props = match sync_collection.prop {
+        PropfindType::Allprop => {
+            vec!["allprop".to_owned()]
+        }
+        PropfindType::Propname => {
+            vec!["propname".to_owned()]
+        }
+        PropfindType::Prop(PropElement(prop_tags)) => {
+            prop_tags.into_iter().map(|propname| propname.0).collect()
+        }
+    };
+    let props: Vec<&str> = props.iter().map(String::as_str).collect();
+
+    // An empty sync-token starts an initial sync, anything else has to be a home sync-token
+    let old_synctokens = match sync_collection.sync_token.as_str() {
+        "" => vec![],
+        sync_token => {
+            parse_home_synctoken(sync_token).ok_or(rustical_store::Error::InvalidSyncToken)?
+        }
+    };
+    let mut old_synctokens: HashMap<String, i64> = old_synctokens.into_iter().collect();
+    // Every response counts against the limit, at least one is returned so that the sync progresses
+    let mut limit = sync_collection
+        .limit
+        .map(|limit| limit.nresults.try_into().unwrap_or(usize::MAX).max(1));
+
+    let mut responses = Vec::new();
+    let mut member_responses = Vec::new();
+    let mut new_synctokens = Vec::new();
+    let mut truncated = false;
+    for addressbook in addr_store.get_addressbooks(principal).await? {
+        let old_synctoken = old_synctokens.remove(&addressbook.id);
+        if truncated || limit == Some(0) {
+            // Left for the next page, addressbooks unknown to the client will show up as created then
+            truncated = true;
+            if let Some(old_synctoken) = old_synctoken {
+                new_synctokens.push((addressbook.id, old_synctoken));
+            }
+            continue;
+        }
+
+        let addressbook_path = format!("{}/{}", req.path().trim_end_matches('/'), addressbook.id);
+        let mut addressbook_response = if old_synctoken != Some(addressbook.synctoken) {
+            Some(
+                AddressbookResource {
+                    addressbook: addressbook.clone(),
+                    read_only: addr_store.is_read_only(),
+                    max_resource_size,
+                }
+                .propfind(&addressbook_path, &props, user, req.resource_map())?,
+            )
+        } else {
+            None
+        };
+
+        // The client has to learn about a new addressbook before its objects
+        if (sync_collection.sync_level == SyncLevel::One || old_synctoken.is_none())
+            && let Some(addressbook_response) = addressbook_response.take()
+        {
+            responses.push(addressbook_response);
+            if let Some(limit) = limit.as_mut() {
+                *limit -= 1;
+            }
+        }
+        if sync_collection.sync_level == SyncLevel::One {
+            new_synctokens.push((addressbook.id, addressbook.synctoken));
+            continue;
+        }
+        if limit == Some(0) {
+            // The objects of the new addressbook are left for the next page
+            truncated = true;
+            new_synctokens.push((addressbook.id, 0));
+            continue;
+        }
+
+        let (new_objects, deleted_objects, new_synctoken, addressbook_truncated) = addr_store
+            .sync_changes(
+                principal,
+                &addressbook.id,
+                old_synctoken.unwrap_or(0),
+                limit,
+            )
+            .await?;
+        if let Some(limit) = limit.as_mut() {
+            *limit = limit.saturating_sub(new_objects.len() + deleted_objects.len());
+        }
+        truncated = addressbook_truncated;
+
+        for object in new_objects {
+            let path = format!("{}/{}", addressbook_path, object.get_id());
+            member_responses.push(
+                AddressObjectResource {
+                    object,
+                    principal: principal.to_owned(),
+                }
+                .propfind(&path, &props, user, req.resource_map())?,
+            );
+        }
+
+        for object_id in deleted_objects {
+            member_responses.push(ResponseElement {
+                href: format!("{}/{}", addressbook_path, object_id),
+                status: Some(StatusCode::NOT_FOUND),
+                ..Default::default()
+            });
+        }
+        new_synctokens.push((addressbook.id, new_synctoken));
+
+        // A known addressbook with changed objects is reported once its objects are complete,
+        // it is left out if there is no room for it since its objects were reported already
+        if let Some(addressbook_response) = addressbook_response
+            && !truncated
+            && limit != Some(0)
+        {
+            responses.push(addressbook_response);
+            if let Some(limit) = limit.as_mut() {
+                *limit -= 1;
+            }
+        }
+    }
+
+    // Addressbooks the client knows about but which are gone now
+    for (addressbook_id, old_synctoken) in old_synctokens {
+        if truncated || limit == Some(0) {
+            truncated = true;
+            new_synctokens.push((addressbook_id, old_synctoken));
+            continue;
+        }
+        responses.push(ResponseElement {
+            href: format!("{}/{}", req.path().trim_end_matches('/'), addressbook_id),
+            status: Some(StatusCode::NOT_FOUND),
+            ..Default::default()
+        });
+        if let Some(limit) = limit.as_mut() {
+            *limit -= 1;
+        }
+    }
+
+    if truncated {
+        // RFC 6578 Section 3.6: The client continues with the returned sync-token
+        responses.push(ResponseElement {
+            href: req.path().to_owned(),
+            status: Some(StatusCode::INSUFFICIENT_STORAGE),
+            ..Default::default()
+        });
+    }
+
+    Ok(MultistatusElement {
+        responses,
+        member_responses,
+        sync_token: Some(format_home_synctoken(&new_synctokens)),
+    })
+}
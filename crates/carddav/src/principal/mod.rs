@@ -1,26 +1,38 @@
 use crate::Error;
 use crate::addressbook::resource::AddressbookResource;
+use crate::directory::DirectoryRoute;
 use actix_web::dev::ResourceMap;
+use actix_web::http::Method;
+use actix_web::web;
 use async_trait::async_trait;
-use rustical_dav::extensions::{CommonPropertiesExtension, CommonPropertiesProp};
+use methods::report::route_report_addressbook_home;
+use rustical_dav::extensions::{
+    CommonPropertiesExtension, CommonPropertiesProp, QuotaExtension, QuotaExtensionProp,
+};
 use rustical_dav::privileges::UserPrivilegeSet;
 use rustical_dav::resource::{NamedRoute, Resource, ResourceService};
 use rustical_dav::xml::{HrefElement, Resourcetype, ResourcetypeInner};
-use rustical_store::AddressbookStore;
 use rustical_store::auth::{AuthenticationProvider, User};
+use rustical_store::quota::{Quota, QuotaUsage};
+use rustical_store::{AddressbookStore, DIRECTORY_ID};
 use rustical_xml::{EnumUnitVariants, EnumVariants, XmlDeserialize, XmlSerialize};
+use std::str::FromStr;
 use std::sync::Arc;
 
+pub mod methods;
+
 pub struct PrincipalResourceService<A: AddressbookStore, AP: AuthenticationProvider> {
     addr_store: Arc<A>,
     auth_provider: Arc<AP>,
+    quota: Arc<Quota>,
 }
 
 impl<A: AddressbookStore, AP: AuthenticationProvider> PrincipalResourceService<A, AP> {
-    pub fn new(addr_store: Arc<A>, auth_provider: Arc<AP>) -> Self {
+    pub fn new(addr_store: Arc<A>, auth_provider: Arc<AP>, quota: Arc<Quota>) -> Self {
         Self {
             addr_store,
             auth_provider,
+            quota,
         }
     }
 }
@@ -28,6 +40,7 @@ impl<A: AddressbookStore, AP: AuthenticationProvider> PrincipalResourceService<A
 #[derive(Debug, Clone)]
 pub struct PrincipalResource {
     principal: User,
+    quota: QuotaUsage,
 }
 
 #[derive(XmlDeserialize, XmlSerialize, PartialEq, Clone)]
@@ -49,12 +62,15 @@ pub enum PrincipalProp {
     AddressbookHomeSet(AddressbookHomeSet),
     #[xml(ns = "rustical_dav::namespace::NS_CARDDAV")]
     PrincipalAddress(Option<HrefElement>),
+    #[xml(ns = "rustical_dav::namespace::NS_CARDDAV")]
+    DirectoryGateway(HrefElement),
 }
 
 #[derive(XmlDeserialize, XmlSerialize, PartialEq, Clone, EnumVariants, EnumUnitVariants)]
 #[xml(unit_variants_ident = "PrincipalPropWrapperName", untagged)]
 pub enum PrincipalPropWrapper {
     Principal(PrincipalProp),
+    Quota(QuotaExtensionProp),
     Common(CommonPropertiesProp),
 }
 
@@ -74,6 +90,17 @@ impl CommonPropertiesExtension for PrincipalResource {
     type PrincipalResource = Self;
 }
 
+// The principal is also the addressbook home
+impl QuotaExtension for PrincipalResource {
+    fn get_quota_used_bytes(&self) -> Option<u64> {
+        Some(self.quota.used_bytes)
+    }
+
+    fn get_quota_available_bytes(&self) -> Option<u64> {
+        self.quota.available_bytes()
+    }
+}
+
 impl Resource for PrincipalResource {
     type Prop = PrincipalPropWrapper;
     type Error = Error;
@@ -116,9 +143,21 @@ impl Resource for PrincipalResource {
                         PrincipalProp::AddressbookHomeSet(home_set)
                     }
                     PrincipalPropName::PrincipalAddress => PrincipalProp::PrincipalAddress(None),
+                    PrincipalPropName::DirectoryGateway => {
+                        PrincipalProp::DirectoryGateway(HrefElement::new(
+                            DirectoryRoute::get_url(
+                                rmap,
+                                vec![self.principal.id.as_str(), DIRECTORY_ID],
+                            )
+                            .unwrap(),
+                        ))
+                    }
                 })
             }
 
+            PrincipalPropWrapperName::Quota(prop) => {
+                PrincipalPropWrapper::Quota(<Self as QuotaExtension>::get_prop(self, prop)?)
+            }
             PrincipalPropWrapperName::Common(prop) => PrincipalPropWrapper::Common(
                 CommonPropertiesExtension::get_prop(self, rmap, user, prop)?,
             ),
@@ -155,7 +194,10 @@ impl<A: AddressbookStore, AP: AuthenticationProvider> ResourceService
             .get_principal(principal)
             .await?
             .ok_or(crate::Error::NotFound)?;
-        Ok(PrincipalResource { principal: user })
+        Ok(PrincipalResource {
+            principal: user,
+            quota: self.quota.get_usage(principal).await?,
+        })
     }
 
     async fn get_members(
@@ -165,7 +207,21 @@ impl<A: AddressbookStore, AP: AuthenticationProvider> ResourceService
         let addressbooks = self.addr_store.get_addressbooks(principal).await?;
         Ok(addressbooks
             .into_iter()
-            .map(|addressbook| (addressbook.id.to_owned(), addressbook.into()))
+            .map(|addressbook| {
+                (
+                    addressbook.id.to_owned(),
+                    AddressbookResource {
+                        addressbook,
+                        read_only: self.addr_store.is_read_only(),
+                        max_resource_size: self.quota.max_resource_size(),
+                    },
+                )
+            })
             .collect())
     }
+    #[inline]
+    fn actix_additional_routes(res: actix_web::Resource) -> actix_web::Resource {
+        let report_method = web::method(Method::from_str("REPORT").unwrap());
+        res.route(report_method.to(route_report_addressbook_home::<A>))
+    }
 }
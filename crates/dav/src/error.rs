@@ -20,6 +20,14 @@ pub enum Error {
     #[error("prop is read-only")]
     PropReadOnly,
 
+    /// Precondition of sync-collection (RFC 6578), the client has to start a new initial sync
+    #[error("Invalid sync token")]
+    InvalidSyncToken,
+
+    /// Precondition of RFC 4331, the request would exceed the principal's quota
+    #[error("Quota exceeded")]
+    QuotaExceeded,
+
     #[error(transparent)]
     XmlError(#[from] rustical_xml::XmlError),
 
@@ -44,6 +52,8 @@ impl actix_web::error::ResponseError for Error {
                 _ => StatusCode::BAD_REQUEST,
             },
             Error::PropReadOnly => StatusCode::CONFLICT,
+            Error::InvalidSyncToken => StatusCode::FORBIDDEN,
+            Error::QuotaExceeded => StatusCode::INSUFFICIENT_STORAGE,
             Self::IOError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -54,6 +64,12 @@ impl actix_web::error::ResponseError for Error {
             Error::Unauthorized => HttpResponse::build(self.status_code())
                 .append_header(("WWW-Authenticate", "Basic"))
                 .body(self.to_string()),
+            Error::InvalidSyncToken => HttpResponse::build(self.status_code())
+                .content_type("application/xml; charset=utf-8")
+                .body(r#"<?xml version="1.0" encoding="utf-8"?><error xmlns="DAV:"><valid-sync-token/></error>"#),
+            Error::QuotaExceeded => HttpResponse::build(self.status_code())
+                .content_type("application/xml; charset=utf-8")
+                .body(r#"<?xml version="1.0" encoding="utf-8"?><error xmlns="DAV:"><quota-not-exceeded/></error>"#),
             _ => HttpResponse::build(self.status_code()).body(self.to_string()),
         }
     }
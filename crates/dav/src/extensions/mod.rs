@@ -1,5 +1,9 @@
 mod common;
+mod quota;
 mod synctoken;
+mod timestamps;
 
 pub use common::*;
+pub use quota::*;
 pub use synctoken::*;
+pub use timestamps::*;
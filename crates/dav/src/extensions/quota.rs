@@ -0,0 +1,40 @@
+use crate::resource::Resource;
+use rustical_xml::{EnumUnitVariants, EnumVariants, XmlDeserialize, XmlSerialize};
+
+#[derive(XmlDeserialize, XmlSerialize, PartialEq, Clone, EnumUnitVariants, EnumVariants)]
+#[xml(unit_variants_ident = "QuotaExtensionPropName")]
+pub enum QuotaExtensionProp {
+    // Quota and Size Properties for DAV Collections (RFC 4331)
+    #[xml(skip_deserializing)]
+    #[xml(ns = "crate::namespace::NS_DAV")]
+    QuotaAvailableBytes(Option<u64>),
+    #[xml(skip_deserializing)]
+    #[xml(ns = "crate::namespace::NS_DAV")]
+    QuotaUsedBytes(Option<u64>),
+}
+
+pub trait QuotaExtension: Resource {
+    fn get_quota_used_bytes(&self) -> Option<u64>;
+
+    /// None if no quota applies
+    fn get_quota_available_bytes(&self) -> Option<u64>;
+
+    fn get_prop(&self, prop: &QuotaExtensionPropName) -> Result<QuotaExtensionProp, crate::Error> {
+        Ok(match prop {
+            QuotaExtensionPropName::QuotaAvailableBytes => {
+                QuotaExtensionProp::QuotaAvailableBytes(self.get_quota_available_bytes())
+            }
+            QuotaExtensionPropName::QuotaUsedBytes => {
+                QuotaExtensionProp::QuotaUsedBytes(self.get_quota_used_bytes())
+            }
+        })
+    }
+
+    fn set_prop(&self, _prop: QuotaExtensionProp) -> Result<(), crate::Error> {
+        Err(crate::Error::PropReadOnly)
+    }
+
+    fn remove_prop(&self, _prop: &QuotaExtensionPropName) -> Result<(), crate::Error> {
+        Err(crate::Error::PropReadOnly)
+    }
+}
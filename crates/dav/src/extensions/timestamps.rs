@@ -0,0 +1,49 @@
+use crate::resource::Resource;
+use actix_web::http::header::HttpDate;
+use chrono::SecondsFormat;
+use rustical_xml::{EnumUnitVariants, EnumVariants, XmlDeserialize, XmlSerialize};
+use std::time::SystemTime;
+
+#[derive(XmlDeserialize, XmlSerialize, PartialEq, Clone, EnumUnitVariants, EnumVariants)]
+#[xml(unit_variants_ident = "TimestampsExtensionPropName")]
+pub enum TimestampsExtensionProp {
+    // WebDAV (RFC 4918)
+    #[xml(skip_deserializing)]
+    #[xml(ns = "crate::namespace::NS_DAV")]
+    Creationdate(Option<String>),
+    #[xml(skip_deserializing)]
+    #[xml(ns = "crate::namespace::NS_DAV")]
+    Getlastmodified(Option<String>),
+}
+
+pub trait TimestampsExtension: Resource {
+    fn get_prop(
+        &self,
+        prop: &TimestampsExtensionPropName,
+    ) -> Result<TimestampsExtensionProp, crate::Error> {
+        Ok(match prop {
+            // RFC 3339 date-time
+            TimestampsExtensionPropName::Creationdate => {
+                TimestampsExtensionProp::Creationdate(self.get_creation_date().map(|created_at| {
+                    created_at
+                        .and_utc()
+                        .to_rfc3339_opts(SecondsFormat::Secs, true)
+                }))
+            }
+            // The same HTTP-date as in the Last-Modified header
+            TimestampsExtensionPropName::Getlastmodified => {
+                TimestampsExtensionProp::Getlastmodified(self.get_last_modified().map(
+                    |updated_at| HttpDate::from(SystemTime::from(updated_at.and_utc())).to_string(),
+                ))
+            }
+        })
+    }
+
+    fn set_prop(&self, _prop: TimestampsExtensionProp) -> Result<(), crate::Error> {
+        Err(crate::Error::PropReadOnly)
+    }
+
+    fn remove_prop(&self, _prop: &TimestampsExtensionPropName) -> Result<(), crate::Error> {
+        Err(crate::Error::PropReadOnly)
+    }
+}
@@ -40,7 +40,7 @@ pub async fn route_delete<R: ResourceService>(
         // Precondition failed
         return Ok(HttpResponse::PreconditionFailed().finish());
     }
-    if resource.satisfies_if_none_match(&if_none_match) {
+    if !resource.satisfies_if_none_match(&if_none_match) {
         // Precondition failed
         return Ok(HttpResponse::PreconditionFailed().finish());
     }
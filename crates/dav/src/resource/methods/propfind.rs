@@ -3,13 +3,15 @@ use crate::header::Depth;
 use crate::privileges::UserPrivilege;
 use crate::resource::Resource;
 use crate::resource::ResourceService;
-use crate::xml::MultistatusElement;
+use crate::xml::MultistatusStream;
 use crate::xml::PropElement;
 use crate::xml::PropfindElement;
 use crate::xml::PropfindType;
 use actix_web::HttpRequest;
 use actix_web::web::Data;
 use actix_web::web::Path;
+use futures_util::StreamExt;
+use futures_util::stream;
 use rustical_xml::XmlDocument;
 use tracing::instrument;
 use tracing_actix_web::RootSpan;
@@ -25,7 +27,7 @@ pub(crate) async fn route_propfind<R: ResourceService>(
     root_span: RootSpan,
     resource_service: Data<R>,
 ) -> Result<
-    MultistatusElement<<R::Resource as Resource>::Prop, <R::MemberType as Resource>::Prop>,
+    MultistatusStream<<R::Resource as Resource>::Prop, <R::MemberType as Resource>::Prop, R::Error>,
     R::Error,
 > {
     let resource = resource_service.get_resource(&path).await?;
@@ -53,23 +55,38 @@ pub(crate) async fn route_propfind<R: ResourceService>(
             .collect(),
     };
 
-    let mut member_responses = Vec::new();
-    if depth != Depth::Zero {
-        for (subpath, member) in resource_service.get_members(&path).await? {
-            member_responses.push(member.propfind(
-                &format!("{}/{}", req.path().trim_end_matches('/'), subpath),
-                &props,
-                &user,
-                req.resource_map(),
-            )?);
-        }
-    }
-
     let response = resource.propfind(req.path(), &props, &user, req.resource_map())?;
 
-    Ok(MultistatusElement {
+    let member_responses = if depth != Depth::Zero {
+        let content_props = <R::MemberType as Resource>::content_props();
+        let members = if props
+            .iter()
+            .any(|prop| *prop == "allprop" || content_props.contains(prop))
+        {
+            resource_service.get_members_stream(&path).await?
+        } else {
+            resource_service.get_members_metadata_stream(&path).await?
+        };
+        let props: Vec<String> = props.into_iter().map(str::to_owned).collect();
+        members
+            .map(move |member| {
+                let (subpath, member) = member?;
+                let props: Vec<&str> = props.iter().map(String::as_str).collect();
+                member.propfind(
+                    &format!("{}/{}", req.path().trim_end_matches('/'), subpath),
+                    &props,
+                    &user,
+                    req.resource_map(),
+                )
+            })
+            .boxed_local()
+    } else {
+        stream::empty().boxed_local()
+    };
+
+    Ok(MultistatusStream {
         responses: vec![response],
         member_responses,
-        ..Default::default()
+        sync_token: None,
     })
 }
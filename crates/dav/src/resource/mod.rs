@@ -4,13 +4,15 @@ use crate::xml::multistatus::{PropTagWrapper, PropstatElement, PropstatWrapper};
 use crate::xml::{TagList, multistatus::ResponseElement};
 use crate::{Error, Principal};
 use actix_web::dev::ResourceMap;
-use actix_web::http::header::{EntityTag, IfMatch, IfNoneMatch};
+use actix_web::http::header::{EntityTag, IfMatch, IfModifiedSince, IfNoneMatch};
 use actix_web::{ResponseError, http::StatusCode};
+use chrono::{DateTime, NaiveDateTime, Utc};
 use itertools::Itertools;
 use quick_xml::name::Namespace;
 pub use resource_service::ResourceService;
 use rustical_xml::{EnumUnitVariants, EnumVariants, XmlDeserialize, XmlSerialize};
 use std::str::FromStr;
+use std::time::SystemTime;
 
 mod methods;
 mod resource_service;
@@ -34,6 +36,12 @@ pub trait Resource: Clone + 'static {
         Self::Prop::variant_names()
     }
 
+    /// Properties computed from the content of the resource, they are not available on
+    /// members listed by [`ResourceService::get_members_metadata_stream`]
+    fn content_props() -> &'static [&'static str] {
+        &[]
+    }
+
     fn get_prop(
         &self,
         rmap: &ResourceMap,
@@ -56,10 +64,45 @@ pub trait Resource: Clone + 'static {
         None
     }
 
+    /// Opaque tag without quotes, resources with an etag are compared strongly
     fn get_etag(&self) -> Option<String> {
         None
     }
 
+    /// The etag as sent in the ETag header and the getetag property
+    fn get_entity_tag(&self) -> Option<EntityTag> {
+        self.get_etag().map(EntityTag::new_strong)
+    }
+
+    fn get_creation_date(&self) -> Option<NaiveDateTime> {
+        None
+    }
+
+    fn get_last_modified(&self) -> Option<NaiveDateTime> {
+        None
+    }
+
+    /// Whether a GET can be answered with 304 Not Modified (RFC 7232 Section 6),
+    /// If-Modified-Since is only evaluated without If-None-Match
+    fn is_not_modified(
+        &self,
+        if_none_match: &IfNoneMatch,
+        if_modified_since: Option<&IfModifiedSince>,
+    ) -> bool {
+        if !matches!(if_none_match, IfNoneMatch::Items(items) if items.is_empty()) {
+            return !self.satisfies_if_none_match(if_none_match);
+        }
+        match (if_modified_since, self.get_last_modified()) {
+            // HTTP dates only have a precision of seconds
+            (Some(IfModifiedSince(since)), Some(last_modified)) => {
+                last_modified.and_utc().timestamp()
+                    <= DateTime::<Utc>::from(SystemTime::from(*since)).timestamp()
+            }
+            _ => false,
+        }
+    }
+
+    /// If-Match uses the strong comparison (RFC 7232 Section 3.1)
     fn satisfies_if_match(&self, if_match: &IfMatch) -> bool {
         match if_match {
             IfMatch::Any => true,
@@ -67,8 +110,7 @@ pub trait Resource: Clone + 'static {
             // IfMatch::Items header
             IfMatch::Items(items) if items.is_empty() => true,
             IfMatch::Items(items) => {
-                if let Some(etag) = self.get_etag() {
-                    let etag = EntityTag::new_strong(etag.to_owned());
+                if let Some(etag) = self.get_entity_tag() {
                     return items.iter().any(|item| item.strong_eq(&etag));
                 }
                 false
@@ -76,16 +118,17 @@ pub trait Resource: Clone + 'static {
         }
     }
 
+    /// If-None-Match uses the weak comparison (RFC 7232 Section 3.2)
     fn satisfies_if_none_match(&self, if_none_match: &IfNoneMatch) -> bool {
         match if_none_match {
+            // The resource exists
             IfNoneMatch::Any => false,
             // This is not nice but if the header doesn't exist, actix just gives us an empty
             // IfNoneMatch::Items header
-            IfNoneMatch::Items(items) if items.is_empty() => false,
+            IfNoneMatch::Items(items) if items.is_empty() => true,
             IfNoneMatch::Items(items) => {
-                if let Some(etag) = self.get_etag() {
-                    let etag = EntityTag::new_strong(etag.to_owned());
-                    return items.iter().all(|item| item.strong_ne(&etag));
+                if let Some(etag) = self.get_entity_tag() {
+                    return items.iter().all(|item| item.weak_ne(&etag));
                 }
                 true
             }
@@ -4,6 +4,8 @@ use actix_web::test::TestRequest;
 use actix_web::web::Data;
 use actix_web::{ResponseError, dev::ResourceMap, http::Method, web};
 use async_trait::async_trait;
+use futures_util::StreamExt;
+use futures_util::stream::{self, LocalBoxStream};
 use serde::Deserialize;
 use std::str::FromStr;
 
@@ -12,6 +14,8 @@ use crate::Principal;
 use super::Resource;
 use super::methods::{route_delete, route_propfind, route_proppatch};
 
+pub type MembersStream<M, E> = LocalBoxStream<'static, Result<(String, M), E>>;
+
 #[async_trait(?Send)]
 pub trait ResourceService: Sized + 'static {
     type MemberType: Resource<Error = Self::Error, Principal = Self::Principal>;
@@ -27,6 +31,26 @@ pub trait ResourceService: Sized + 'static {
         Ok(vec![])
     }
 
+    /// Used by PROPFIND to send members as they are loaded,
+    /// override this if the members can be streamed from the store
+    async fn get_members_stream(
+        &self,
+        path_components: &Self::PathComponents,
+    ) -> Result<MembersStream<Self::MemberType, Self::Error>, Self::Error> {
+        let members = self.get_members(path_components).await?;
+        Ok(stream::iter(members.into_iter().map(Ok)).boxed_local())
+    }
+
+    /// Used by PROPFIND instead of [`ResourceService::get_members_stream`] if none of the
+    /// requested properties is one of the [`Resource::content_props`] of the members,
+    /// override this if the members can be listed without their content
+    async fn get_members_metadata_stream(
+        &self,
+        path_components: &Self::PathComponents,
+    ) -> Result<MembersStream<Self::MemberType, Self::Error>, Self::Error> {
+        self.get_members_stream(path_components).await
+    }
+
     async fn get_resource(
         &self,
         _path: &Self::PathComponents,
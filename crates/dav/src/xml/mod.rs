@@ -3,7 +3,7 @@ mod propfind;
 mod resourcetype;
 pub mod tag_list;
 use derive_more::derive::From;
-pub use multistatus::MultistatusElement;
+pub use multistatus::{MultistatusElement, MultistatusStream};
 pub use propfind::{PropElement, PropfindElement, PropfindType, Propname};
 pub use resourcetype::{Resourcetype, ResourcetypeInner};
 use rustical_xml::{XmlDeserialize, XmlSerialize};
@@ -4,9 +4,18 @@ use crate::xml::TagList;
 use actix_web::{
     body::BoxBody,
     http::{header::ContentType, StatusCode},
+    web::Bytes,
     HttpRequest, HttpResponse, Responder, ResponseError,
 };
-use quick_xml::name::Namespace;
+use futures_util::{
+    future::ready,
+    stream::{self, LocalBoxStream},
+    StreamExt,
+};
+use quick_xml::{
+    events::{BytesEnd, BytesStart, Event},
+    name::Namespace,
+};
 use rustical_xml::{XmlRootTag, XmlSerialize, XmlSerializeRoot};
 
 #[derive(XmlSerialize)]
@@ -124,3 +133,153 @@ impl<T1: XmlSerialize, T2: XmlSerialize> Responder for MultistatusElement<T1, T2
             .body(String::from_utf8(output).unwrap())
     }
 }
+
+/// A multistatus response whose member responses are serialized and sent one by one
+/// as the stream yields them instead of being buffered in memory as a whole.
+/// Errors occuring after the first chunk was sent can only abort the response.
+pub struct MultistatusStream<PropType: XmlSerialize, MemberPropType: XmlSerialize, E> {
+    pub responses: Vec<ResponseElement<PropType>>,
+    pub member_responses: LocalBoxStream<'static, Result<ResponseElement<MemberPropType>, E>>,
+    pub sync_token: Option<String>,
+}
+
+impl<T1: XmlSerialize, T2: XmlSerialize, E> MultistatusStream<T1, T2, E> {
+    pub fn new(member_responses: LocalBoxStream<'static, Result<ResponseElement<T2>, E>>) -> Self {
+        Self {
+            responses: vec![],
+            member_responses,
+            sync_token: None,
+        }
+    }
+}
+
+fn serialize_chunk(
+    write: impl FnOnce(&mut quick_xml::Writer<&mut Vec<u8>>) -> std::io::Result<()>,
+) -> std::io::Result<Bytes> {
+    let mut output = Vec::new();
+    write(&mut quick_xml::Writer::new(&mut output))?;
+    Ok(Bytes::from(output))
+}
+
+impl<T1, T2, E> Responder for MultistatusStream<T1, T2, E>
+where
+    T1: XmlSerialize,
+    T2: XmlSerialize + 'static,
+    E: ResponseError + 'static,
+{
+    type Body = BoxBody;
+
+    fn respond_to(self, _req: &HttpRequest) -> HttpResponse<Self::Body> {
+        let namespaces = MultistatusElement::<(), ()>::root_ns_prefixes();
+        let mut prefixes: Vec<_> = namespaces.iter().collect();
+        prefixes.sort_by_key(|(_, prefix)| **prefix);
+
+        let head = serialize_chunk(|writer| {
+            writer
+                .get_mut()
+                .extend(b"<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+            let mut start = BytesStart::new("multistatus");
+            for (ns, prefix) in prefixes {
+                let attr = if prefix.is_empty() {
+                    b"xmlns".to_vec()
+                } else {
+                    [b"xmlns:".as_ref(), prefix].concat()
+                };
+                start.push_attribute((attr.as_slice(), ns.as_ref()));
+            }
+            writer.write_event(Event::Start(start))?;
+            for response in &self.responses {
+                response.serialize(
+                    Some(crate::namespace::NS_DAV),
+                    Some(b"response"),
+                    &namespaces,
+                    writer,
+                )?;
+            }
+            Ok(())
+        });
+        let head = match head {
+            Ok(head) => head,
+            Err(err) => return crate::Error::from(err).error_response(),
+        };
+
+        let member_namespaces = namespaces.clone();
+        let members = self.member_responses.map(move |response| {
+            let response = response.map_err(|err| {
+                tracing::error!("Aborting multistatus response: {err}");
+                actix_web::Error::from(err)
+            })?;
+            Ok(serialize_chunk(|writer| {
+                response.serialize(
+                    Some(crate::namespace::NS_DAV),
+                    Some(b"response"),
+                    &member_namespaces,
+                    writer,
+                )
+            })
+            .map_err(crate::Error::from)?)
+        });
+
+        let sync_token = self.sync_token;
+        let tail = stream::once(async move {
+            Ok(serialize_chunk(|writer| {
+                sync_token.serialize(
+                    Some(crate::namespace::NS_DAV),
+                    Some(b"sync-token"),
+                    &namespaces,
+                    writer,
+                )?;
+                writer.write_event(Event::End(BytesEnd::new("multistatus")))
+            })
+            .map_err(crate::Error::from)?)
+        });
+
+        HttpResponse::MultiStatus()
+            .content_type(ContentType::xml())
+            .streaming(
+                stream::once(ready(Ok::<_, actix_web::Error>(head)))
+                    .chain(members)
+                    .chain(tail),
+            )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{body::to_bytes, test::TestRequest};
+
+    #[tokio::test]
+    async fn test_multistatus_stream() {
+        let multistatus = MultistatusStream::<String, String, crate::Error> {
+            responses: vec![ResponseElement {
+                href: "/calendar/".to_owned(),
+                status: Some(StatusCode::OK),
+                ..Default::default()
+            }],
+            member_responses: stream::iter(["a", "b"].map(|id| {
+                Ok(ResponseElement {
+                    href: format!("/calendar/{id}"),
+                    status: Some(StatusCode::NOT_FOUND),
+                    ..Default::default()
+                })
+            }))
+            .boxed_local(),
+            sync_token: Some("token".to_owned()),
+        };
+        let response = multistatus.respond_to(&TestRequest::default().to_http_request());
+        assert_eq!(response.status(), StatusCode::MULTI_STATUS);
+        let body = to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(
+            String::from_utf8(body.to_vec()).unwrap(),
+            concat!(
+                "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n",
+                "<multistatus xmlns=\"DAV:\" xmlns:CAL=\"urn:ietf:params:xml:ns:caldav\" xmlns:CARD=\"urn:ietf:params:xml:ns:carddav\" xmlns:CS=\"http://calendarserver.org/ns/\" xmlns:PUSH=\"https://bitfire.at/webdav-push\">",
+                "<response><href>/calendar/</href><status>HTTP/1.1 200 OK</status></response>",
+                "<response><href>/calendar/a</href><status>HTTP/1.1 404 Not Found</status></response>",
+                "<response><href>/calendar/b</href><status>HTTP/1.1 404 Not Found</status></response>",
+                "<sync-token>token</sync-token></multistatus>"
+            )
+        );
+    }
+}
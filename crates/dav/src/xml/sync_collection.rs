@@ -12,7 +12,8 @@ impl ValueDeserialize for SyncLevel {
     fn deserialize(val: &str) -> Result<Self, rustical_xml::XmlError> {
         Ok(match val {
             "1" => Self::One,
-            "Infinity" => Self::Infinity,
+            // RFC 6578 specifies "infinite", "Infinity" is kept for compatibility
+            "infinite" | "Infinity" => Self::Infinity,
             _ => {
                 return Err(rustical_xml::XmlError::InvalidValue(
                     rustical_xml::ParseValueError::Other("Invalid sync-level".to_owned()),
@@ -26,7 +27,7 @@ impl ValueSerialize for SyncLevel {
     fn serialize(&self) -> String {
         match self {
             SyncLevel::One => "1",
-            SyncLevel::Infinity => "Infinity",
+            SyncLevel::Infinity => "infinite",
         }
         .to_owned()
     }
@@ -45,5 +46,13 @@ pub struct SyncCollectionRequest {
     #[xml(ns = "crate::namespace::NS_DAV", ty = "untagged")]
     pub prop: PropfindType,
     #[xml(ns = "crate::namespace::NS_DAV")]
-    pub limit: Option<u64>,
+    pub limit: Option<LimitElement>,
+}
+
+#[derive(XmlDeserialize, Clone, Debug, PartialEq)]
+// RFC 5323 Section 5.17
+// <!ELEMENT limit (nresults) >
+pub struct LimitElement {
+    #[xml(ns = "crate::namespace::NS_DAV")]
+    pub nresults: u64,
 }
@@ -64,7 +64,7 @@ fn propfind_prop() {
 /// Example taken from DAVx5
 #[test]
 fn propfind_decl() {
-    let propfind = PropfindElement::parse_str(
+    let _propfind = PropfindElement::parse_str(
         r#"
         <?xml version='1.0' encoding='UTF-8' ?>
         <propfind xmlns="DAV:" xmlns:CAL="urn:ietf:params:xml:ns:caldav" xmlns:CARD="urn:ietf:params:xml:ns:carddav">
@@ -17,14 +17,25 @@ use assets::{Assets, EmbedService};
 use async_trait::async_trait;
 use rand::{Rng, distributions::Alphanumeric};
 use routes::{
-    addressbook::{route_addressbook, route_addressbook_restore},
-    calendar::{route_calendar, route_calendar_restore},
+    addressbook::{
+        route_addressbook, route_addressbook_object_history, route_addressbook_object_purge,
+        route_addressbook_object_restore, route_addressbook_object_rollback,
+        route_addressbook_purge, route_addressbook_restore,
+    },
+    birthday_settings::route_post_birthday_settings,
+    calendar::{
+        route_calendar, route_calendar_object_history, route_calendar_object_purge,
+        route_calendar_object_restore, route_calendar_object_rollback, route_calendar_purge,
+        route_calendar_restore,
+    },
     login::{route_get_login, route_post_login, route_post_logout},
+    profile::route_post_profile,
 };
 use rustical_oidc::{OidcConfig, OidcServiceConfig, UserStore, configure_oidc};
 use rustical_store::{
-    Addressbook, AddressbookStore, Calendar, CalendarStore,
+    Addressbook, AddressbookStore, BirthdayCalendarSettings, Calendar, CalendarStore,
     auth::{AuthenticationMiddleware, AuthenticationProvider, User},
+    quota::Quota,
 };
 use serde::Deserialize;
 use std::sync::Arc;
@@ -55,6 +66,7 @@ struct UserPage {
     pub deleted_calendars: Vec<Calendar>,
     pub addressbooks: Vec<Addressbook>,
     pub deleted_addressbooks: Vec<Addressbook>,
+    pub birthday_settings: BirthdayCalendarSettings,
 }
 
 async fn route_user_named<CS: CalendarStore, AS: AddressbookStore>(
@@ -90,11 +102,14 @@ async fn route_user_named<CS: CalendarStore, AS: AddressbookStore>(
         deleted_addressbooks.extend(addr_store.get_deleted_addressbooks(group).await.unwrap());
     }
 
+    let birthday_settings = addr_store.get_birthday_settings(&user.id).await.unwrap();
+
     UserPage {
         calendars,
         deleted_calendars,
         addressbooks,
         deleted_addressbooks,
+        birthday_settings,
         user,
     }
     .respond_to(&req)
@@ -195,6 +210,7 @@ pub fn configure_frontend<AP: AuthenticationProvider, CS: CalendarStore, AS: Add
     auth_provider: Arc<AP>,
     cal_store: Arc<CS>,
     addr_store: Arc<AS>,
+    quota: Arc<Quota>,
     frontend_config: FrontendConfig,
     oidc_config: Option<OidcConfig>,
 ) {
@@ -205,6 +221,7 @@ pub fn configure_frontend<AP: AuthenticationProvider, CS: CalendarStore, AS: Add
         .app_data(Data::from(auth_provider.clone()))
         .app_data(Data::from(cal_store.clone()))
         .app_data(Data::from(addr_store.clone()))
+        .app_data(Data::from(quota))
         .app_data(Data::new(frontend_config.clone()))
         .app_data(Data::new(oidc_config.clone()))
         .service(EmbedService::<Assets>::new("/assets".to_owned()))
@@ -219,6 +236,7 @@ pub fn configure_frontend<AP: AuthenticationProvider, CS: CalendarStore, AS: Add
                 .get(route_user_named::<CS, AS>)
                 .name(ROUTE_USER_NAMED),
         )
+        .service(web::resource("/user/{user}/profile").post(route_post_profile::<AP>))
         // App token management
         .service(web::resource("/user/{user}/app_token").post(route_post_app_token::<AP>))
         .service(
@@ -231,6 +249,26 @@ pub fn configure_frontend<AP: AuthenticationProvider, CS: CalendarStore, AS: Add
             web::resource("/user/{user}/calendar/{calendar}/restore")
                 .post(route_calendar_restore::<CS>),
         )
+        .service(
+            web::resource("/user/{user}/calendar/{calendar}/purge")
+                .post(route_calendar_purge::<CS>),
+        )
+        .service(
+            web::resource("/user/{user}/calendar/{calendar}/object/{object}/restore")
+                .post(route_calendar_object_restore::<CS>),
+        )
+        .service(
+            web::resource("/user/{user}/calendar/{calendar}/object/{object}/purge")
+                .post(route_calendar_object_purge::<CS>),
+        )
+        .service(
+            web::resource("/user/{user}/calendar/{calendar}/object/{object}")
+                .get(route_calendar_object_history::<CS>),
+        )
+        .service(
+            web::resource("/user/{user}/calendar/{calendar}/object/{object}/rollback/{revision}")
+                .post(route_calendar_object_rollback::<CS>),
+        )
         // Addressbook
         .service(
             web::resource("/user/{user}/addressbook/{addressbook}").get(route_addressbook::<AS>),
@@ -239,6 +277,33 @@ pub fn configure_frontend<AP: AuthenticationProvider, CS: CalendarStore, AS: Add
             web::resource("/user/{user}/addressbook/{addressbook}/restore")
                 .post(route_addressbook_restore::<AS>),
         )
+        .service(
+            web::resource("/user/{user}/addressbook/{addressbook}/purge")
+                .post(route_addressbook_purge::<AS>),
+        )
+        .service(
+            web::resource("/user/{user}/addressbook/{addressbook}/object/{object}/restore")
+                .post(route_addressbook_object_restore::<AS>),
+        )
+        .service(
+            web::resource("/user/{user}/addressbook/{addressbook}/object/{object}/purge")
+                .post(route_addressbook_object_purge::<AS>),
+        )
+        .service(
+            web::resource("/user/{user}/addressbook/{addressbook}/object/{object}")
+                .get(route_addressbook_object_history::<AS>),
+        )
+        .service(
+            web::resource(
+                "/user/{user}/addressbook/{addressbook}/object/{object}/rollback/{revision}",
+            )
+            .post(route_addressbook_object_rollback::<AS>),
+        )
+        // Birthday calendars
+        .service(
+            web::resource("/user/{user}/birthday_settings")
+                .post(route_post_birthday_settings::<AS>),
+        )
         // Login
         .service(
             web::resource("/login")
@@ -281,14 +346,18 @@ impl<AP: AuthenticationProvider> UserStore for OidcUserStore<AP> {
 
     async fn insert_user(&self, id: &str) -> Result<(), Self::Error> {
         self.0
-            .insert_principal(User {
-                id: id.to_owned(),
-                displayname: None,
-                principal_type: Default::default(),
-                password: None,
-                app_tokens: vec![],
-                memberships: vec![],
-            })
+            .insert_principal(
+                User {
+                    id: id.to_owned(),
+                    displayname: None,
+                    principal_type: Default::default(),
+                    password: None,
+                    app_tokens: vec![],
+                    memberships: vec![],
+                    emails: vec![],
+                },
+                false,
+            )
             .await
     }
 }
@@ -1,16 +1,20 @@
+use super::{object_history::ObjectHistoryPage, redirect_to_referer};
 use actix_web::{
     HttpRequest, HttpResponse, Responder,
-    http::{StatusCode, header},
-    web::{self, Data, Path},
+    web::{Data, Path},
 };
 use askama::Template;
 use askama_web::WebTemplate;
-use rustical_store::{Addressbook, AddressbookStore, auth::User};
+use rustical_store::{
+    AddressObject, Addressbook, AddressbookStore, Error, auth::User, quota::Quota,
+};
 
 #[derive(Template, WebTemplate)]
 #[template(path = "pages/addressbook.html")]
 struct AddressbookPage {
     addressbook: Addressbook,
+    objects: Vec<AddressObject>,
+    deleted_objects: Vec<AddressObject>,
 }
 
 pub async fn route_addressbook<AS: AddressbookStore>(
@@ -25,6 +29,8 @@ pub async fn route_addressbook<AS: AddressbookStore>(
     }
     Ok(AddressbookPage {
         addressbook: store.get_addressbook(&owner, &addrbook_id).await?,
+        objects: store.get_objects(&owner, &addrbook_id).await?,
+        deleted_objects: store.get_deleted_objects(&owner, &addrbook_id).await?,
     }
     .respond_to(&req))
 }
@@ -40,11 +46,127 @@ pub async fn route_addressbook_restore<AS: AddressbookStore>(
         return Ok(HttpResponse::Unauthorized().body("Unauthorized"));
     }
     store.restore_addressbook(&owner, &addressbook_id).await?;
-    Ok(match req.headers().get(header::REFERER) {
-        Some(referer) => web::Redirect::to(referer.to_str().unwrap().to_owned())
-            .using_status_code(StatusCode::FOUND)
-            .respond_to(&req)
-            .map_into_boxed_body(),
-        None => HttpResponse::Ok().body("Restored"),
-    })
+    Ok(redirect_to_referer(
+        &req,
+        HttpResponse::Ok().body("Restored"),
+    ))
+}
+
+/// Permanently deletes an addressbook from the trashbin
+pub async fn route_addressbook_purge<AS: AddressbookStore>(
+    path: Path<(String, String)>,
+    req: HttpRequest,
+    store: Data<AS>,
+    user: User,
+) -> Result<impl Responder, rustical_store::Error> {
+    let (owner, addressbook_id) = path.into_inner();
+    if !user.is_principal(&owner) {
+        return Ok(HttpResponse::Unauthorized().body("Unauthorized"));
+    }
+    if store
+        .get_addressbook(&owner, &addressbook_id)
+        .await?
+        .deleted_at
+        .is_none()
+    {
+        return Err(Error::NotFound);
+    }
+    store
+        .delete_addressbook(&owner, &addressbook_id, false)
+        .await?;
+    Ok(redirect_to_referer(&req, HttpResponse::Ok().body("Purged")))
+}
+
+pub async fn route_addressbook_object_restore<AS: AddressbookStore>(
+    path: Path<(String, String, String)>,
+    req: HttpRequest,
+    store: Data<AS>,
+    user: User,
+) -> Result<impl Responder, rustical_store::Error> {
+    let (owner, addressbook_id, object_id) = path.into_inner();
+    if !user.is_principal(&owner) {
+        return Ok(HttpResponse::Unauthorized().body("Unauthorized"));
+    }
+    store
+        .restore_object(&owner, &addressbook_id, &object_id)
+        .await?;
+    Ok(redirect_to_referer(
+        &req,
+        HttpResponse::Ok().body("Restored"),
+    ))
+}
+
+/// Permanently deletes an object from the trashbin
+pub async fn route_addressbook_object_purge<AS: AddressbookStore>(
+    path: Path<(String, String, String)>,
+    req: HttpRequest,
+    store: Data<AS>,
+    user: User,
+) -> Result<impl Responder, rustical_store::Error> {
+    let (owner, addressbook_id, object_id) = path.into_inner();
+    if !user.is_principal(&owner) {
+        return Ok(HttpResponse::Unauthorized().body("Unauthorized"));
+    }
+    // Objects that are not in the trashbin must be deleted through CardDAV
+    if !store
+        .get_deleted_objects(&owner, &addressbook_id)
+        .await?
+        .iter()
+        .any(|object| object.get_id() == object_id)
+    {
+        return Err(Error::NotFound);
+    }
+    store
+        .delete_object(&owner, &addressbook_id, &object_id, false)
+        .await?;
+    Ok(redirect_to_referer(&req, HttpResponse::Ok().body("Purged")))
+}
+
+pub async fn route_addressbook_object_history<AS: AddressbookStore>(
+    path: Path<(String, String, String)>,
+    req: HttpRequest,
+    store: Data<AS>,
+    user: User,
+) -> Result<impl Responder, rustical_store::Error> {
+    let (owner, addrbook_id, object_id) = path.into_inner();
+    if !user.is_principal(&owner) {
+        return Ok(HttpResponse::Unauthorized().body("Unauthorized"));
+    }
+    let revisions = store
+        .get_object_revisions(&owner, &addrbook_id, &object_id)
+        .await?;
+    Ok(ObjectHistoryPage::new(
+        format!("/frontend/user/{owner}/addressbook/{addrbook_id}"),
+        object_id,
+        revisions,
+        AddressObject::get_vcf,
+    )
+    .respond_to(&req))
+}
+
+pub async fn route_addressbook_object_rollback<AS: AddressbookStore>(
+    path: Path<(String, String, String, i64)>,
+    req: HttpRequest,
+    store: Data<AS>,
+    quota: Data<Quota>,
+    user: User,
+) -> Result<impl Responder, rustical_store::Error> {
+    let (owner, addrbook_id, object_id, revision) = path.into_inner();
+    if !user.is_principal(&owner) {
+        return Ok(HttpResponse::Unauthorized().body("Unauthorized"));
+    }
+    store
+        .rollback_object(
+            &owner,
+            &addrbook_id,
+            &object_id,
+            revision,
+            Some(&user.id),
+            &quota,
+        )
+        .await?;
+    Ok(redirect_to_referer(
+        &req,
+        HttpResponse::Ok().body("Rolled back"),
+    ))
 }
@@ -0,0 +1,63 @@
+use crate::ROUTE_USER_NAMED;
+use actix_web::{
+    HttpRequest, HttpResponse, Responder,
+    web::{Data, Form, Path, Redirect},
+};
+use rustical_store::{AddressbookStore, BirthdayCalendarSettings, auth::User};
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct PostBirthdaySettingsForm {
+    birthday_summary_template: String,
+    anniversary_summary_template: String,
+    abdate_summary_template: String,
+    // Empty to disable alarms
+    alarm_trigger: String,
+    color: String,
+    displayname_template: String,
+    // Unchecked checkboxes are not submitted
+    #[serde(default)]
+    include_anniversaries: bool,
+    #[serde(default)]
+    include_abdates: bool,
+}
+
+impl From<PostBirthdaySettingsForm> for BirthdayCalendarSettings {
+    fn from(value: PostBirthdaySettingsForm) -> Self {
+        let non_empty = |value: String| (!value.trim().is_empty()).then_some(value);
+        Self {
+            birthday_summary_template: value.birthday_summary_template,
+            anniversary_summary_template: value.anniversary_summary_template,
+            abdate_summary_template: value.abdate_summary_template,
+            alarm_trigger: non_empty(value.alarm_trigger),
+            color: non_empty(value.color),
+            displayname_template: value.displayname_template,
+            include_anniversaries: value.include_anniversaries,
+            include_abdates: value.include_abdates,
+        }
+    }
+}
+
+pub async fn route_post_birthday_settings<AS: AddressbookStore>(
+    path: Path<String>,
+    req: HttpRequest,
+    store: Data<AS>,
+    user: User,
+    Form(form): Form<PostBirthdaySettingsForm>,
+) -> Result<HttpResponse, rustical_store::Error> {
+    let principal = path.into_inner();
+    if !user.is_principal(&principal) {
+        return Ok(HttpResponse::Unauthorized().body("Unauthorized"));
+    }
+    store
+        .update_birthday_settings(&principal, form.into())
+        .await?;
+    Ok(Redirect::to(
+        req.url_for(ROUTE_USER_NAMED, &[principal])
+            .unwrap()
+            .to_string(),
+    )
+    .see_other()
+    .respond_to(&req)
+    .map_into_boxed_body())
+}
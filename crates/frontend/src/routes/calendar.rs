@@ -1,16 +1,18 @@
+use super::{object_history::ObjectHistoryPage, redirect_to_referer};
 use actix_web::{
     HttpRequest, HttpResponse, Responder,
-    http::{StatusCode, header},
-    web::{self, Data, Path},
+    web::{Data, Path},
 };
 use askama::Template;
 use askama_web::WebTemplate;
-use rustical_store::{Calendar, CalendarStore, auth::User};
+use rustical_store::{Calendar, CalendarObject, CalendarStore, Error, auth::User, quota::Quota};
 
 #[derive(Template, WebTemplate)]
 #[template(path = "pages/calendar.html")]
 struct CalendarPage {
     calendar: Calendar,
+    objects: Vec<CalendarObject>,
+    deleted_objects: Vec<CalendarObject>,
 }
 
 pub async fn route_calendar<C: CalendarStore>(
@@ -25,6 +27,8 @@ pub async fn route_calendar<C: CalendarStore>(
     }
     Ok(CalendarPage {
         calendar: store.get_calendar(&owner, &cal_id).await?,
+        objects: store.get_objects(&owner, &cal_id).await?,
+        deleted_objects: store.get_deleted_objects(&owner, &cal_id).await?,
     }
     .respond_to(&req))
 }
@@ -40,11 +44,123 @@ pub async fn route_calendar_restore<CS: CalendarStore>(
         return Ok(HttpResponse::Unauthorized().body("Unauthorized"));
     }
     store.restore_calendar(&owner, &cal_id).await?;
-    Ok(match req.headers().get(header::REFERER) {
-        Some(referer) => web::Redirect::to(referer.to_str().unwrap().to_owned())
-            .using_status_code(StatusCode::FOUND)
-            .respond_to(&req)
-            .map_into_boxed_body(),
-        None => HttpResponse::Created().body("Restored"),
-    })
+    Ok(redirect_to_referer(
+        &req,
+        HttpResponse::Created().body("Restored"),
+    ))
+}
+
+/// Permanently deletes a calendar from the trashbin
+pub async fn route_calendar_purge<CS: CalendarStore>(
+    path: Path<(String, String)>,
+    req: HttpRequest,
+    store: Data<CS>,
+    user: User,
+) -> Result<HttpResponse, rustical_store::Error> {
+    let (owner, cal_id) = path.into_inner();
+    if !user.is_principal(&owner) {
+        return Ok(HttpResponse::Unauthorized().body("Unauthorized"));
+    }
+    if store
+        .get_calendar(&owner, &cal_id)
+        .await?
+        .deleted_at
+        .is_none()
+    {
+        return Err(Error::NotFound);
+    }
+    store.delete_calendar(&owner, &cal_id, false).await?;
+    Ok(redirect_to_referer(&req, HttpResponse::Ok().body("Purged")))
+}
+
+pub async fn route_calendar_object_restore<CS: CalendarStore>(
+    path: Path<(String, String, String)>,
+    req: HttpRequest,
+    store: Data<CS>,
+    user: User,
+) -> Result<HttpResponse, rustical_store::Error> {
+    let (owner, cal_id, object_id) = path.into_inner();
+    if !user.is_principal(&owner) {
+        return Ok(HttpResponse::Unauthorized().body("Unauthorized"));
+    }
+    store.restore_object(&owner, &cal_id, &object_id).await?;
+    Ok(redirect_to_referer(
+        &req,
+        HttpResponse::Created().body("Restored"),
+    ))
+}
+
+/// Permanently deletes an object from the trashbin
+pub async fn route_calendar_object_purge<CS: CalendarStore>(
+    path: Path<(String, String, String)>,
+    req: HttpRequest,
+    store: Data<CS>,
+    user: User,
+) -> Result<HttpResponse, rustical_store::Error> {
+    let (owner, cal_id, object_id) = path.into_inner();
+    if !user.is_principal(&owner) {
+        return Ok(HttpResponse::Unauthorized().body("Unauthorized"));
+    }
+    // Objects that are not in the trashbin must be deleted through CalDAV
+    if !store
+        .get_deleted_objects(&owner, &cal_id)
+        .await?
+        .iter()
+        .any(|object| object.get_id() == object_id)
+    {
+        return Err(Error::NotFound);
+    }
+    store
+        .delete_object(&owner, &cal_id, &object_id, false)
+        .await?;
+    Ok(redirect_to_referer(&req, HttpResponse::Ok().body("Purged")))
+}
+
+pub async fn route_calendar_object_history<CS: CalendarStore>(
+    path: Path<(String, String, String)>,
+    req: HttpRequest,
+    store: Data<CS>,
+    user: User,
+) -> Result<HttpResponse, rustical_store::Error> {
+    let (owner, cal_id, object_id) = path.into_inner();
+    if !user.is_principal(&owner) {
+        return Ok(HttpResponse::Unauthorized().body("Unauthorized"));
+    }
+    let revisions = store
+        .get_object_revisions(&owner, &cal_id, &object_id)
+        .await?;
+    Ok(ObjectHistoryPage::new(
+        format!("/frontend/user/{owner}/calendar/{cal_id}"),
+        object_id,
+        revisions,
+        CalendarObject::get_ics,
+    )
+    .respond_to(&req))
+}
+
+pub async fn route_calendar_object_rollback<CS: CalendarStore>(
+    path: Path<(String, String, String, i64)>,
+    req: HttpRequest,
+    store: Data<CS>,
+    quota: Data<Quota>,
+    user: User,
+) -> Result<HttpResponse, rustical_store::Error> {
+    let (owner, cal_id, object_id, revision) = path.into_inner();
+    if !user.is_principal(&owner) {
+        return Ok(HttpResponse::Unauthorized().body("Unauthorized"));
+    }
+    store
+        .rollback_object(
+            &owner,
+            &cal_id,
+            &object_id,
+            revision,
+            Some(&user.id),
+            &quota,
+        )
+        .await?;
+    Ok(redirect_to_referer(
+        &req,
+        HttpResponse::Ok().body("Rolled back"),
+    ))
 }
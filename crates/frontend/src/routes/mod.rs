@@ -1,4 +1,23 @@
+use actix_web::{
+    HttpRequest, HttpResponse, Responder,
+    http::{StatusCode, header},
+    web,
+};
+
 pub mod addressbook;
+pub mod birthday_settings;
 pub mod calendar;
 pub mod login;
+mod object_history;
+pub mod profile;
 
+/// Sends the browser back to the page the form was submitted from
+pub(crate) fn redirect_to_referer(req: &HttpRequest, fallback: HttpResponse) -> HttpResponse {
+    match req.headers().get(header::REFERER) {
+        Some(referer) => web::Redirect::to(referer.to_str().unwrap().to_owned())
+            .using_status_code(StatusCode::FOUND)
+            .respond_to(req)
+            .map_into_boxed_body(),
+        None => fallback,
+    }
+}
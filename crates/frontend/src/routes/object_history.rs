@@ -0,0 +1,61 @@
+use askama::Template;
+use askama_web::WebTemplate;
+use chrono::NaiveDateTime;
+use rustical_store::revision::{DiffLine, ObjectRevision, diff_lines};
+
+pub(crate) struct RevisionView {
+    pub revision: i64,
+    pub created_at: NaiveDateTime,
+    pub changed_by: Option<String>,
+    /// Changes compared to the previous revision as (css class, line)
+    pub diff: Vec<(&'static str, String)>,
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "pages/object_history.html")]
+pub(crate) struct ObjectHistoryPage {
+    /// Frontend URL of the calendar or addressbook
+    pub collection_url: String,
+    pub object_id: String,
+    pub revisions: Vec<RevisionView>,
+}
+
+impl ObjectHistoryPage {
+    /// Revisions are expected newest first like the store returns them
+    pub fn new<O>(
+        collection_url: String,
+        object_id: String,
+        revisions: Vec<ObjectRevision<O>>,
+        content: impl Fn(&O) -> &str,
+    ) -> Self {
+        let revisions = revisions
+            .iter()
+            .enumerate()
+            .map(|(i, rev)| {
+                // The oldest revision we still have is compared to an empty object
+                let previous = revisions.get(i + 1).map(|prev| content(&prev.object));
+                RevisionView {
+                    revision: rev.revision,
+                    created_at: rev.created_at,
+                    changed_by: rev.changed_by.to_owned(),
+                    diff: diff_lines(previous.unwrap_or_default(), content(&rev.object))
+                        .into_iter()
+                        .map(|line| {
+                            let class = match line {
+                                DiffLine::Unchanged(_) => "unchanged",
+                                DiffLine::Removed(_) => "removed",
+                                DiffLine::Added(_) => "added",
+                            };
+                            (class, line.format())
+                        })
+                        .collect(),
+                }
+            })
+            .collect();
+        Self {
+            collection_url,
+            object_id,
+            revisions,
+        }
+    }
+}
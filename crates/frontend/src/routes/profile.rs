@@ -0,0 +1,49 @@
+use crate::ROUTE_USER_NAMED;
+use actix_web::{
+    HttpRequest, HttpResponse, Responder,
+    web::{Data, Form, Path, Redirect},
+};
+use rustical_store::{
+    Error,
+    auth::{AuthenticationProvider, User, user::normalize_emails},
+};
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct PostProfileForm {
+    displayname: String,
+    // One address per line, the first one is the primary address
+    emails: String,
+}
+
+pub async fn route_post_profile<AP: AuthenticationProvider>(
+    path: Path<String>,
+    req: HttpRequest,
+    auth_provider: Data<AP>,
+    user: User,
+    Form(form): Form<PostProfileForm>,
+) -> Result<HttpResponse, Error> {
+    let principal_id = path.into_inner();
+    // Group members may not edit the group's profile
+    if principal_id != user.id {
+        return Ok(HttpResponse::Unauthorized().body("Unauthorized"));
+    }
+    let mut principal = auth_provider
+        .get_principal(&principal_id)
+        .await?
+        .ok_or(Error::NotFound)?;
+
+    let displayname = form.displayname.trim();
+    principal.displayname = (!displayname.is_empty()).then(|| displayname.to_owned());
+    principal.emails = normalize_emails(form.emails.lines())?;
+    auth_provider.insert_principal(principal, true).await?;
+
+    Ok(Redirect::to(
+        req.url_for(ROUTE_USER_NAMED, &[principal_id])
+            .unwrap()
+            .to_string(),
+    )
+    .see_other()
+    .respond_to(&req)
+    .map_into_boxed_body())
+}
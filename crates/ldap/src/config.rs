@@ -0,0 +1,78 @@
+use serde::{Deserialize, Serialize};
+
+fn default_user_filter() -> String {
+    "(objectClass=person)".to_owned()
+}
+
+fn default_user_id_attr() -> String {
+    "uid".to_owned()
+}
+
+fn default_displayname_attr() -> String {
+    "cn".to_owned()
+}
+
+fn default_email_attr() -> String {
+    "mail".to_owned()
+}
+
+fn default_group_filter() -> String {
+    "(objectClass=groupOfNames)".to_owned()
+}
+
+fn default_group_id_attr() -> String {
+    "cn".to_owned()
+}
+
+fn default_group_member_attr() -> String {
+    "member".to_owned()
+}
+
+fn default_cache_ttl() -> u64 {
+    60
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct LdapConfig {
+    /// e.g. ldaps://ldap.example.com
+    pub url: String,
+    #[serde(default)]
+    pub starttls: bool,
+    /// Account used to look up principals, binds anonymously if not set
+    pub bind_dn: Option<String>,
+    pub bind_password: Option<String>,
+
+    pub user_base_dn: String,
+    #[serde(default = "default_user_filter")]
+    pub user_filter: String,
+    /// Attribute containing the principal id
+    #[serde(default = "default_user_id_attr")]
+    pub user_id_attr: String,
+    /// Attribute containing the displayname of users and groups
+    #[serde(default = "default_displayname_attr")]
+    pub displayname_attr: String,
+    /// Attribute containing email addresses, the first one is the primary address
+    #[serde(default = "default_email_attr")]
+    pub email_attr: String,
+
+    /// Groups are only looked up if set
+    pub group_base_dn: Option<String>,
+    #[serde(default = "default_group_filter")]
+    pub group_filter: String,
+    /// Attribute containing the principal id of a group
+    #[serde(default = "default_group_id_attr")]
+    pub group_id_attr: String,
+    /// Attribute containing the DNs of the group members
+    #[serde(default = "default_group_member_attr")]
+    pub group_member_attr: String,
+
+    /// Principals file to keep the app tokens in,
+    /// the database of the SQL data store is used if not set
+    pub app_token_path: Option<String>,
+
+    /// Seconds for which principals looked up in the directory are cached.
+    /// App tokens are only revoked for removed principals once their entry expires.
+    #[serde(default = "default_cache_ttl")]
+    pub cache_ttl: u64,
+}
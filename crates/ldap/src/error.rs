@@ -0,0 +1,17 @@
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    LdapError(#[from] ldap3::LdapError),
+
+    #[error(transparent)]
+    StoreError(#[from] rustical_store::Error),
+}
+
+impl From<Error> for rustical_store::Error {
+    fn from(value: Error) -> Self {
+        match value {
+            Error::LdapError(err) => Self::Other(err.into()),
+            Error::StoreError(err) => err,
+        }
+    }
+}
@@ -0,0 +1,424 @@
+//! Authentication against an LDAP directory
+//!
+//! Passwords are validated with an LDAP bind and LDAP groups become group principals.
+//! Since app tokens cannot be stored in the directory they are kept in a local [`AuthenticationProvider`]
+//! which also receives a copy of the principal data from LDAP.
+use async_trait::async_trait;
+use ldap3::{Ldap, LdapConnAsync, LdapConnSettings, Scope, SearchEntry, ldap_escape};
+use lru::LruCache;
+use rustical_store::auth::user::PrincipalType;
+use rustical_store::auth::{AuthenticationProvider, User};
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{instrument, warn};
+
+mod config;
+pub mod error;
+
+pub use config::LdapConfig;
+pub use error::Error;
+
+/// LDAP result code for a failed bind
+const INVALID_CREDENTIALS: u32 = 49;
+
+/// Number of principals whose directory lookups are cached
+const LOOKUP_CACHE_SIZE: usize = 1000;
+
+/// Result of a directory lookup, the principal doesn't exist if it's `None`
+type Lookup = (Instant, Option<User>);
+
+#[derive(Debug)]
+pub struct LdapAuthenticationProvider<L: AuthenticationProvider> {
+    config: LdapConfig,
+    /// Keeps the app tokens
+    local: Arc<L>,
+    /// Principals by id as found in the directory, without app tokens
+    lookups: Mutex<LruCache<String, Lookup>>,
+}
+
+impl<L: AuthenticationProvider> LdapAuthenticationProvider<L> {
+    pub fn new(config: LdapConfig, local: Arc<L>) -> Self {
+        Self {
+            config,
+            local,
+            lookups: Mutex::new(LruCache::new(NonZeroUsize::new(LOOKUP_CACHE_SIZE).unwrap())),
+        }
+    }
+
+    async fn connect(&self) -> Result<Ldap, Error> {
+        let settings = LdapConnSettings::new().set_starttls(self.config.starttls);
+        let (conn, ldap) = LdapConnAsync::with_settings(settings, &self.config.url).await?;
+        ldap3::drive!(conn);
+        Ok(ldap)
+    }
+
+    /// Connects with the account used to look up principals
+    async fn connect_lookup(&self) -> Result<Ldap, Error> {
+        let mut ldap = self.connect().await?;
+        if let Some(bind_dn) = &self.config.bind_dn {
+            ldap.simple_bind(
+                bind_dn,
+                self.config.bind_password.as_deref().unwrap_or_default(),
+            )
+            .await?
+            .success()?;
+        }
+        Ok(ldap)
+    }
+
+    async fn search(
+        ldap: &mut Ldap,
+        base: &str,
+        filter: &str,
+        attrs: &[&str],
+    ) -> Result<Vec<SearchEntry>, Error> {
+        let (entries, _) = ldap
+            .search(base, Scope::Subtree, filter, attrs)
+            .await?
+            .success()?;
+        Ok(entries.into_iter().map(SearchEntry::construct).collect())
+    }
+
+    fn first_attr<'a>(entry: &'a SearchEntry, attr: &str) -> Option<&'a String> {
+        entry.attrs.get(attr).and_then(|values| values.first())
+    }
+
+    async fn search_users(
+        &self,
+        ldap: &mut Ldap,
+        id: Option<&str>,
+    ) -> Result<Vec<SearchEntry>, Error> {
+        let filter = match id {
+            Some(id) => format!(
+                "(&{}({}={}))",
+                self.config.user_filter,
+                self.config.user_id_attr,
+                ldap_escape(id)
+            ),
+            None => self.config.user_filter.to_owned(),
+        };
+        Self::search(
+            ldap,
+            &self.config.user_base_dn,
+            &filter,
+            &[
+                self.config.user_id_attr.as_str(),
+                self.config.displayname_attr.as_str(),
+                self.config.email_attr.as_str(),
+            ],
+        )
+        .await
+    }
+
+    /// `filter` is combined with the group filter
+    async fn search_groups(
+        &self,
+        ldap: &mut Ldap,
+        filter: &str,
+    ) -> Result<Vec<SearchEntry>, Error> {
+        let Some(group_base_dn) = &self.config.group_base_dn else {
+            return Ok(vec![]);
+        };
+        Self::search(
+            ldap,
+            group_base_dn,
+            &format!("(&{}{filter})", self.config.group_filter),
+            &[
+                self.config.group_id_attr.as_str(),
+                self.config.displayname_attr.as_str(),
+                self.config.group_member_attr.as_str(),
+            ],
+        )
+        .await
+    }
+
+    fn user_from_entry(&self, entry: &SearchEntry, memberships: Vec<String>) -> Option<User> {
+        Some(User {
+            id: Self::first_attr(entry, &self.config.user_id_attr)?.to_owned(),
+            displayname: Self::first_attr(entry, &self.config.displayname_attr).cloned(),
+            principal_type: PrincipalType::Individual,
+            password: None,
+            app_tokens: vec![],
+            memberships,
+            emails: entry
+                .attrs
+                .get(&self.config.email_attr)
+                .cloned()
+                .unwrap_or_default(),
+        })
+    }
+
+    fn group_from_entry(&self, entry: &SearchEntry) -> Option<User> {
+        Some(User {
+            id: Self::first_attr(entry, &self.config.group_id_attr)?.to_owned(),
+            displayname: Self::first_attr(entry, &self.config.displayname_attr).cloned(),
+            principal_type: PrincipalType::Group,
+            password: None,
+            app_tokens: vec![],
+            memberships: vec![],
+            emails: vec![],
+        })
+    }
+
+    /// Looks up a principal and the DN of the user if the principal is a user
+    async fn lookup_principal(
+        &self,
+        ldap: &mut Ldap,
+        id: &str,
+    ) -> Result<Option<(User, Option<String>)>, Error> {
+        if let Some(entry) = self.search_users(ldap, Some(id)).await?.first() {
+            let groups = self
+                .search_groups(
+                    ldap,
+                    &format!(
+                        "({}={})",
+                        self.config.group_member_attr,
+                        ldap_escape(&entry.dn)
+                    ),
+                )
+                .await?;
+            let memberships = groups
+                .iter()
+                .filter_map(|group| Self::first_attr(group, &self.config.group_id_attr).cloned())
+                .collect();
+            return Ok(self
+                .user_from_entry(entry, memberships)
+                .map(|user| (user, Some(entry.dn.to_owned()))));
+        }
+
+        let groups = self
+            .search_groups(
+                ldap,
+                &format!("({}={})", self.config.group_id_attr, ldap_escape(id)),
+            )
+            .await?;
+        Ok(groups
+            .first()
+            .and_then(|group| self.group_from_entry(group))
+            .map(|group| (group, None)))
+    }
+
+    /// Adds the app tokens from the local store and updates its copy of the principal
+    async fn sync_local(&self, mut user: User) -> Result<User, Error> {
+        let Some(local_user) = self.local.get_principal(&user.id).await? else {
+            return Ok(user);
+        };
+        user.app_tokens = local_user.app_tokens.to_owned();
+        if local_user.displayname != user.displayname
+            || local_user.principal_type != user.principal_type
+            || local_user.emails != user.emails
+            || local_user.memberships != user.memberships
+        {
+            self.local.insert_principal(user.clone(), true).await?;
+        }
+        Ok(user)
+    }
+
+    fn cache_lookup(&self, id: &str, user: Option<&User>) {
+        let user = user.map(|user| User {
+            app_tokens: vec![],
+            ..user.clone()
+        });
+        self.lookups
+            .lock()
+            .unwrap()
+            .put(id.to_owned(), (Instant::now(), user));
+    }
+
+    /// Looks up a principal in the directory unless it was looked up recently,
+    /// the local store is only synced when the directory is queried
+    async fn _get_principal(&self, id: &str) -> Result<Option<User>, Error> {
+        let cached = self
+            .lookups
+            .lock()
+            .unwrap()
+            .get(id)
+            .filter(|(looked_up_at, _)| {
+                looked_up_at.elapsed() < Duration::from_secs(self.config.cache_ttl)
+            })
+            .map(|(_, user)| user.clone());
+        if let Some(user) = cached {
+            let Some(mut user) = user else {
+                return Ok(None);
+            };
+            if let Some(local_user) = self.local.get_principal(id).await? {
+                user.app_tokens = local_user.app_tokens;
+            }
+            return Ok(Some(user));
+        }
+
+        let mut ldap = self.connect_lookup().await?;
+        let principal = self.lookup_principal(&mut ldap, id).await?;
+        ldap.unbind().await?;
+        let user = match principal {
+            Some((user, _)) => Some(self.sync_local(user).await?),
+            None => None,
+        };
+        self.cache_lookup(id, user.as_ref());
+        Ok(user)
+    }
+
+    async fn _get_principals(&self) -> Result<Vec<User>, Error> {
+        let mut ldap = self.connect_lookup().await?;
+        let users = self.search_users(&mut ldap, None).await?;
+        let groups = self.search_groups(&mut ldap, "").await?;
+        ldap.unbind().await?;
+
+        // Group ids by member DN
+        let mut memberships: HashMap<&str, Vec<String>> = HashMap::new();
+        for group in &groups {
+            let Some(group_id) = Self::first_attr(group, &self.config.group_id_attr) else {
+                continue;
+            };
+            for member in group
+                .attrs
+                .get(&self.config.group_member_attr)
+                .into_iter()
+                .flatten()
+            {
+                memberships
+                    .entry(member)
+                    .or_default()
+                    .push(group_id.to_owned());
+            }
+        }
+
+        let mut app_tokens: HashMap<String, _> = self
+            .local
+            .get_principals()
+            .await?
+            .into_iter()
+            .map(|user| (user.id, user.app_tokens))
+            .collect();
+
+        let mut principals: Vec<User> = users
+            .iter()
+            .filter_map(|entry| {
+                let member_of = memberships.remove(entry.dn.as_str()).unwrap_or_default();
+                self.user_from_entry(entry, member_of)
+            })
+            .chain(
+                groups
+                    .iter()
+                    .filter_map(|group| self.group_from_entry(group)),
+            )
+            .collect();
+        for principal in &mut principals {
+            principal.app_tokens = app_tokens.remove(&principal.id).unwrap_or_default();
+        }
+        Ok(principals)
+    }
+
+    async fn _validate_password(
+        &self,
+        user_id: &str,
+        password: &str,
+    ) -> Result<Option<User>, Error> {
+        // An empty password would result in an unauthenticated bind which always succeeds
+        if password.is_empty() {
+            return Ok(None);
+        }
+
+        let mut ldap = self.connect_lookup().await?;
+        let principal = self.lookup_principal(&mut ldap, user_id).await?;
+        ldap.unbind().await?;
+        self.cache_lookup(user_id, principal.as_ref().map(|(user, _)| user));
+        let Some((user, Some(dn))) = principal else {
+            return Ok(None);
+        };
+
+        let mut ldap = self.connect().await?;
+        let result = ldap.simple_bind(&dn, password).await?;
+        ldap.unbind().await?;
+        match result.rc {
+            0 => Ok(Some(self.sync_local(user).await?)),
+            INVALID_CREDENTIALS => Ok(None),
+            _ => {
+                warn!("LDAP bind for {dn} failed: {result}");
+                Ok(None)
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<L: AuthenticationProvider> AuthenticationProvider for LdapAuthenticationProvider<L> {
+    #[instrument(skip(self))]
+    async fn get_principals(&self) -> Result<Vec<User>, rustical_store::Error> {
+        Ok(self._get_principals().await?)
+    }
+
+    #[instrument(skip(self))]
+    async fn get_principal(&self, id: &str) -> Result<Option<User>, rustical_store::Error> {
+        Ok(self._get_principal(id).await?)
+    }
+
+    // Principals are managed in the LDAP directory
+    async fn remove_principal(&self, _id: &str) -> Result<(), rustical_store::Error> {
+        Err(rustical_store::Error::ReadOnly)
+    }
+
+    async fn insert_principal(
+        &self,
+        _user: User,
+        _overwrite: bool,
+    ) -> Result<(), rustical_store::Error> {
+        Err(rustical_store::Error::ReadOnly)
+    }
+
+    #[instrument(skip(self, password))]
+    async fn validate_password(
+        &self,
+        user_id: &str,
+        password: &str,
+    ) -> Result<Option<User>, rustical_store::Error> {
+        Ok(self._validate_password(user_id, password).await?)
+    }
+
+    #[instrument(skip(self, token))]
+    async fn validate_app_token(
+        &self,
+        user_id: &str,
+        token: &str,
+    ) -> Result<Option<User>, rustical_store::Error> {
+        // Invalid tokens are rejected without querying the directory
+        if self
+            .local
+            .validate_app_token(user_id, token)
+            .await?
+            .is_none()
+        {
+            return Ok(None);
+        }
+        // Tokens of principals that were removed from the directory are invalid
+        self.get_principal(user_id).await
+    }
+
+    #[instrument(skip(self, token))]
+    async fn add_app_token(
+        &self,
+        user_id: &str,
+        name: String,
+        token: String,
+    ) -> Result<String, rustical_store::Error> {
+        let user = self
+            .get_principal(user_id)
+            .await?
+            .ok_or(rustical_store::Error::NotFound)?;
+        if self.local.get_principal(user_id).await?.is_none() {
+            self.local.insert_principal(user, false).await?;
+        }
+        self.local.add_app_token(user_id, name, token).await
+    }
+
+    #[instrument(skip(self))]
+    async fn remove_app_token(
+        &self,
+        user_id: &str,
+        token_id: &str,
+    ) -> Result<(), rustical_store::Error> {
+        self.local.remove_app_token(user_id, token_id).await
+    }
+}
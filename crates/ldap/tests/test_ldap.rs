@@ -0,0 +1,494 @@
+use rustical_ldap::{LdapAuthenticationProvider, LdapConfig};
+use rustical_store::Error;
+use rustical_store::auth::user::PrincipalType;
+use rustical_store::auth::{AuthenticationProvider, User};
+use rustical_store::memory::MemoryPrincipalStore;
+use stand_in::Entry;
+use std::sync::{Arc, Mutex};
+
+/// Minimal LDAP server supporting simple binds and searches with and/or/not/equality/presence filters
+mod stand_in {
+    use bytes::BytesMut;
+    use lber::common::TagClass;
+    use lber::parse::parse_tag;
+    use lber::structure::{PL, StructureTag};
+    use lber::write::encode_into;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    #[derive(Debug, Clone)]
+    pub struct Entry {
+        pub dn: String,
+        pub attrs: Vec<(String, Vec<String>)>,
+    }
+
+    impl Entry {
+        pub fn new(dn: &str, attrs: &[(&str, &[&str])]) -> Self {
+            Self {
+                dn: dn.to_owned(),
+                attrs: attrs
+                    .iter()
+                    .map(|(attr, values)| {
+                        (
+                            (*attr).to_owned(),
+                            values.iter().map(|value| (*value).to_owned()).collect(),
+                        )
+                    })
+                    .collect(),
+            }
+        }
+
+        fn values(&self, attr: &str) -> &[String] {
+            self.attrs
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(attr))
+                .map(|(_, values)| values.as_slice())
+                .unwrap_or_default()
+        }
+    }
+
+    pub type Directory = Arc<Mutex<Vec<Entry>>>;
+
+    /// Returns the URL of the server
+    pub async fn start(directory: Directory) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ldap://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(handle_connection(stream, directory.clone()));
+            }
+        });
+        url
+    }
+
+    fn tag(class: TagClass, id: u64, payload: PL) -> StructureTag {
+        StructureTag { class, id, payload }
+    }
+
+    fn octet_string(value: &str) -> StructureTag {
+        tag(TagClass::Universal, 4, PL::P(value.as_bytes().to_vec()))
+    }
+
+    fn integer(id: u64, value: u64) -> StructureTag {
+        let mut bytes: Vec<u8> = value
+            .to_be_bytes()
+            .into_iter()
+            .skip_while(|b| *b == 0)
+            .collect();
+        if bytes.first().is_none_or(|b| b & 0x80 != 0) {
+            bytes.insert(0, 0);
+        }
+        tag(TagClass::Universal, id, PL::P(bytes))
+    }
+
+    fn ldap_result(id: u64, rc: u64) -> StructureTag {
+        tag(
+            TagClass::Application,
+            id,
+            PL::C(vec![integer(10, rc), octet_string(""), octet_string("")]),
+        )
+    }
+
+    fn primitive(tag: &StructureTag) -> String {
+        match &tag.payload {
+            PL::P(bytes) => String::from_utf8_lossy(bytes).into_owned(),
+            PL::C(_) => panic!("expected primitive"),
+        }
+    }
+
+    fn constructed(tag: &StructureTag) -> &[StructureTag] {
+        match &tag.payload {
+            PL::C(tags) => tags,
+            PL::P(_) => panic!("expected constructed"),
+        }
+    }
+
+    fn matches(entry: &Entry, filter: &StructureTag) -> bool {
+        match filter.id {
+            0 => constructed(filter).iter().all(|sub| matches(entry, sub)),
+            1 => constructed(filter).iter().any(|sub| matches(entry, sub)),
+            2 => !matches(entry, &constructed(filter)[0]),
+            3 => {
+                let [attr, value] = constructed(filter) else {
+                    panic!("invalid equality filter")
+                };
+                entry
+                    .values(&primitive(attr))
+                    .iter()
+                    .any(|own| own.eq_ignore_ascii_case(&primitive(value)))
+            }
+            7 => !entry.values(&primitive(filter)).is_empty(),
+            id => panic!("unsupported filter {id}"),
+        }
+    }
+
+    async fn handle_connection(mut stream: TcpStream, directory: Directory) {
+        let mut buf = vec![];
+        let mut read_buf = [0u8; 4096];
+        loop {
+            let Ok((rest, message)) = parse_tag(&buf) else {
+                match stream.read(&mut read_buf).await {
+                    Ok(0) | Err(_) => return,
+                    Ok(n) => buf.extend_from_slice(&read_buf[..n]),
+                }
+                continue;
+            };
+            buf = rest.to_vec();
+
+            let message = constructed(&message).to_vec();
+            let message_id = match &message[0].payload {
+                PL::P(bytes) => bytes.iter().fold(0, |id, b| (id << 8) | *b as u64),
+                PL::C(_) => panic!("invalid message id"),
+            };
+            let op = &message[1];
+            let responses = match op.id {
+                // Bind
+                0 => {
+                    let request = constructed(op);
+                    let (dn, password) = (primitive(&request[1]), primitive(&request[2]));
+                    let success = dn.is_empty()
+                        || directory.lock().unwrap().iter().any(|entry| {
+                            entry.dn.eq_ignore_ascii_case(&dn)
+                                && entry.values("userPassword").contains(&password)
+                        });
+                    vec![ldap_result(1, if success { 0 } else { 49 })]
+                }
+                // Unbind
+                2 => return,
+                // Search
+                3 => {
+                    let request = constructed(op);
+                    let base = primitive(&request[0]).to_lowercase();
+                    let filter = &request[6];
+                    let attrs: Vec<_> = constructed(&request[7]).iter().map(primitive).collect();
+                    let mut responses: Vec<_> = directory
+                        .lock()
+                        .unwrap()
+                        .iter()
+                        .filter(|entry| entry.dn.to_lowercase().ends_with(&base))
+                        .filter(|entry| matches(entry, filter))
+                        .map(|entry| {
+                            let attributes = entry
+                                .attrs
+                                .iter()
+                                .filter(|(name, _)| {
+                                    attrs.is_empty()
+                                        || attrs.iter().any(|attr| attr.eq_ignore_ascii_case(name))
+                                })
+                                .map(|(name, values)| {
+                                    tag(
+                                        TagClass::Universal,
+                                        16,
+                                        PL::C(vec![
+                                            octet_string(name),
+                                            tag(
+                                                TagClass::Universal,
+                                                17,
+                                                PL::C(
+                                                    values
+                                                        .iter()
+                                                        .map(|v| octet_string(v))
+                                                        .collect(),
+                                                ),
+                                            ),
+                                        ]),
+                                    )
+                                })
+                                .collect();
+                            tag(
+                                TagClass::Application,
+                                4,
+                                PL::C(vec![
+                                    octet_string(&entry.dn),
+                                    tag(TagClass::Universal, 16, PL::C(attributes)),
+                                ]),
+                            )
+                        })
+                        .collect();
+                    responses.push(ldap_result(5, 0));
+                    responses
+                }
+                id => panic!("unsupported operation {id}"),
+            };
+
+            for response in responses {
+                let mut out = BytesMut::new();
+                encode_into(
+                    &mut out,
+                    tag(
+                        TagClass::Universal,
+                        16,
+                        PL::C(vec![integer(2, message_id), response]),
+                    ),
+                )
+                .unwrap();
+                stream.write_all(&out).await.unwrap();
+            }
+        }
+    }
+}
+
+fn directory() -> stand_in::Directory {
+    Arc::new(Mutex::new(vec![
+        Entry::new(
+            "cn=admin,dc=example,dc=com",
+            &[("cn", &["admin"]), ("userPassword", &["admin"])],
+        ),
+        Entry::new(
+            "uid=alice,ou=people,dc=example,dc=com",
+            &[
+                ("objectClass", &["person"]),
+                ("uid", &["alice"]),
+                ("cn", &["Alice"]),
+                ("mail", &["alice@example.com", "a@example.org"]),
+                ("userPassword", &["secret"]),
+            ],
+        ),
+        Entry::new(
+            "uid=bob,ou=people,dc=example,dc=com",
+            &[
+                ("objectClass", &["person"]),
+                ("uid", &["bob"]),
+                ("cn", &["Bob"]),
+                ("userPassword", &["hunter2"]),
+            ],
+        ),
+        Entry::new(
+            "cn=developers,ou=groups,dc=example,dc=com",
+            &[
+                ("objectClass", &["groupOfNames"]),
+                ("cn", &["developers"]),
+                ("member", &["uid=alice,ou=people,dc=example,dc=com"]),
+            ],
+        ),
+    ]))
+}
+
+async fn provider(
+    directory: stand_in::Directory,
+) -> (
+    LdapAuthenticationProvider<MemoryPrincipalStore>,
+    Arc<MemoryPrincipalStore>,
+) {
+    cached_provider(directory, 0).await
+}
+
+async fn cached_provider(
+    directory: stand_in::Directory,
+    cache_ttl: u64,
+) -> (
+    LdapAuthenticationProvider<MemoryPrincipalStore>,
+    Arc<MemoryPrincipalStore>,
+) {
+    let url = stand_in::start(directory).await;
+    let local = Arc::new(MemoryPrincipalStore::default());
+    let config: LdapConfig = toml::from_str(&format!(
+        r#"
+        url = "{url}"
+        bind_dn = "cn=admin,dc=example,dc=com"
+        bind_password = "admin"
+        user_base_dn = "ou=people,dc=example,dc=com"
+        group_base_dn = "ou=groups,dc=example,dc=com"
+        cache_ttl = {cache_ttl}
+        "#
+    ))
+    .unwrap();
+    (
+        LdapAuthenticationProvider::new(config, local.clone()),
+        local,
+    )
+}
+
+#[tokio::test]
+async fn test_validate_password() {
+    let (provider, _) = provider(directory()).await;
+    let alice = provider
+        .validate_password("alice", "secret")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(alice.displayname.as_deref(), Some("Alice"));
+    assert_eq!(alice.principal_type, PrincipalType::Individual);
+    assert_eq!(alice.emails, vec!["alice@example.com", "a@example.org"]);
+    assert_eq!(alice.memberships, vec!["developers"]);
+
+    assert!(
+        provider
+            .validate_password("alice", "wrong")
+            .await
+            .unwrap()
+            .is_none()
+    );
+    // Must not result in an unauthenticated bind
+    assert!(
+        provider
+            .validate_password("alice", "")
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert!(
+        provider
+            .validate_password("mallory", "secret")
+            .await
+            .unwrap()
+            .is_none()
+    );
+    // Groups cannot log in
+    assert!(
+        provider
+            .validate_password("developers", "secret")
+            .await
+            .unwrap()
+            .is_none()
+    );
+}
+
+#[tokio::test]
+async fn test_principals() {
+    let (provider, _) = provider(directory()).await;
+    let group = provider.get_principal("developers").await.unwrap().unwrap();
+    assert_eq!(group.principal_type, PrincipalType::Group);
+    assert!(provider.get_principal("mallory").await.unwrap().is_none());
+
+    let mut principals: Vec<_> = provider
+        .get_principals()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|principal| (principal.id, principal.memberships))
+        .collect();
+    principals.sort();
+    assert_eq!(
+        principals,
+        vec![
+            ("alice".to_owned(), vec!["developers".to_owned()]),
+            ("bob".to_owned(), vec![]),
+            ("developers".to_owned(), vec![]),
+        ]
+    );
+
+    assert!(matches!(
+        provider
+            .insert_principal(
+                User {
+                    id: "mallory".to_owned(),
+                    displayname: None,
+                    principal_type: PrincipalType::Individual,
+                    password: None,
+                    app_tokens: vec![],
+                    memberships: vec![],
+                    emails: vec![],
+                },
+                false
+            )
+            .await,
+        Err(Error::ReadOnly)
+    ));
+}
+
+#[tokio::test]
+async fn test_app_tokens() {
+    let directory = directory();
+    let (provider, local) = provider(directory.clone()).await;
+    let token_id = provider
+        .add_app_token("bob", "Phone".to_owned(), "token".to_owned())
+        .await
+        .unwrap();
+    assert!(
+        provider
+            .validate_app_token("bob", "token")
+            .await
+            .unwrap()
+            .is_some()
+    );
+    assert!(
+        provider
+            .validate_app_token("bob", "wrong")
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert!(matches!(
+        provider
+            .add_app_token("mallory", "Phone".to_owned(), "token".to_owned())
+            .await,
+        Err(Error::NotFound)
+    ));
+
+    // Changes in the directory are synced to the local store
+    directory.lock().unwrap()[2].attrs[2].1 = vec!["Robert".to_owned()];
+    let bob = provider.get_principal("bob").await.unwrap().unwrap();
+    assert_eq!(bob.displayname.as_deref(), Some("Robert"));
+    assert_eq!(bob.app_tokens.len(), 1);
+    let local_bob = local.get_principal("bob").await.unwrap().unwrap();
+    assert_eq!(local_bob.displayname.as_deref(), Some("Robert"));
+    assert!(local_bob.password.is_none());
+
+    provider.remove_app_token("bob", &token_id).await.unwrap();
+    assert!(
+        provider
+            .validate_app_token("bob", "token")
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    // Tokens of principals removed from the directory are invalid
+    provider
+        .add_app_token("bob", "Laptop".to_owned(), "token".to_owned())
+        .await
+        .unwrap();
+    directory.lock().unwrap().remove(2);
+    assert!(
+        provider
+            .validate_app_token("bob", "token")
+            .await
+            .unwrap()
+            .is_none()
+    );
+}
+
+#[tokio::test]
+async fn test_cached_lookups() {
+    let directory = directory();
+    let (provider, _) = cached_provider(directory.clone(), 60).await;
+    provider
+        .add_app_token("bob", "Phone".to_owned(), "token".to_owned())
+        .await
+        .unwrap();
+
+    // The directory isn't queried again until the cached principal expires
+    directory.lock().unwrap().remove(2);
+    let bob = provider
+        .validate_app_token("bob", "token")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(bob.app_tokens.len(), 1);
+    assert!(
+        provider
+            .validate_app_token("bob", "wrong")
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    // A password login always queries the directory and refreshes the cache
+    assert!(
+        provider
+            .validate_password("bob", "hunter2")
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert!(
+        provider
+            .validate_app_token("bob", "token")
+            .await
+            .unwrap()
+            .is_none()
+    );
+}
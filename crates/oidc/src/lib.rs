@@ -187,15 +187,14 @@ pub async fn route_get_oidc_callback<US: UserStore>(
         .await
         .map_err(|_| OidcError::Other("Error fetching user info"))?;
 
-    if let Some(require_group) = &oidc_config.require_group {
-        if !user_info_claims
+    if let Some(require_group) = &oidc_config.require_group
+        && !user_info_claims
             .additional_claims()
             .groups
             .contains(require_group)
-        {
-            return Ok(HttpResponse::build(StatusCode::UNAUTHORIZED)
-                .body("User is not in an authorized group to use RustiCal"));
-        }
+    {
+        return Ok(HttpResponse::build(StatusCode::UNAUTHORIZED)
+            .body("User is not in an authorized group to use RustiCal"));
     }
 
     let user_id = match oidc_config.claim_userid {
@@ -1,25 +1,68 @@
-use std::{collections::HashMap, io::BufReader};
+use std::{collections::HashMap, io::BufReader, sync::OnceLock};
 
-use crate::{
-    calendar::{CalDateTime, LOCAL_DATE},
-    CalendarObject, Error,
-};
-use chrono::Datelike;
-use ical::parser::{
-    vcard::{self, component::VcardContact},
-    Component,
+use super::{BirthdayCalendarSettings, VcardDate};
+use crate::{CalendarObject, Error, calendar::LOCAL_DATE};
+use chrono::{NaiveDate, NaiveDateTime};
+use ical::{
+    parser::{
+        Component,
+        vcard::{self, component::VcardContact},
+    },
+    property::Property,
 };
 use sha2::{Digest, Sha256};
+use tracing::warn;
+
+/// Upper bound for the number of X-ABDATE dates per contact
+///
+/// Since the dates are only identified by their index this is required to know which objects
+/// might have been deleted.
+pub const MAX_ABDATES: usize = 10;
 
+/// A vCard object, the vCard is only parsed once it's needed
 #[derive(Debug, Clone)]
 pub struct AddressObject {
     id: String,
     vcf: String,
-    vcard: VcardContact,
+    etag: String,
+    vcard: OnceLock<Option<VcardContact>>,
+    created_at: Option<NaiveDateTime>,
+    updated_at: Option<NaiveDateTime>,
 }
 
 impl AddressObject {
+    /// Parses and validates an object
     pub fn from_vcf(object_id: String, vcf: String) -> Result<Self, Error> {
+        let vcard = Self::parse(&vcf)?;
+        let object = Self::from_stored(object_id, vcf, None);
+        object.vcard.get_or_init(|| Some(vcard));
+        Ok(object)
+    }
+
+    /// Constructs an object from stored data without parsing it.
+    /// Uses the etag persisted alongside the vcf if there is one.
+    /// The vcf must have been validated by [`AddressObject::from_vcf`] before.
+    pub fn from_stored(object_id: String, vcf: String, etag: Option<String>) -> Self {
+        let etag = etag.unwrap_or_else(|| Self::compute_etag(&object_id, &vcf));
+        Self {
+            id: object_id,
+            vcf,
+            etag,
+            vcard: OnceLock::new(),
+            created_at: None,
+            updated_at: None,
+        }
+    }
+
+    /// Constructs an object that only carries its metadata, e.g. to answer a PROPFIND for
+    /// getetag without reading the content. Its vcf is empty and it has no properties.
+    pub fn from_metadata(object_id: String, etag: String) -> Self {
+        let object = Self::from_stored(object_id, String::new(), Some(etag));
+        object.vcard.get_or_init(|| None);
+        object
+    }
+
+    fn parse(vcf: &str) -> Result<VcardContact, Error> {
         let mut parser = vcard::VcardParser::new(BufReader::new(vcf.as_bytes()));
         let vcard = parser.next().ok_or(Error::NotFound)??;
         if parser.next().is_some() {
@@ -27,137 +70,411 @@ impl AddressObject {
                 "multiple vcards, only one allowed".to_owned(),
             ));
         }
-        Ok(Self {
-            id: object_id,
-            vcf,
-            vcard,
-        })
+        Ok(vcard)
+    }
+
+    /// None if a stored object turns out to be invalid
+    fn get_vcard(&self) -> Option<&VcardContact> {
+        self.vcard
+            .get_or_init(|| {
+                Self::parse(&self.vcf)
+                    .inspect_err(|err| warn!("Stored contact {} is invalid: {err}", self.id))
+                    .ok()
+            })
+            .as_ref()
+    }
+
+    /// Attaches the timestamps tracked by the store
+    pub fn with_timestamps(
+        mut self,
+        created_at: Option<NaiveDateTime>,
+        updated_at: Option<NaiveDateTime>,
+    ) -> Self {
+        self.created_at = created_at;
+        self.updated_at = updated_at;
+        self
+    }
+
+    fn compute_etag(object_id: &str, vcf: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(object_id);
+        hasher.update(vcf);
+        format!("{:x}", hasher.finalize())
     }
 
     pub fn get_id(&self) -> &str {
         &self.id
     }
 
+    /// Opaque tag without quotes, it changes whenever the id or content changes
     pub fn get_etag(&self) -> String {
-        let mut hasher = Sha256::new();
-        hasher.update(&self.id);
-        hasher.update(self.get_vcf());
-        format!("{:x}", hasher.finalize())
+        self.etag.to_owned()
     }
 
     pub fn get_vcf(&self) -> &str {
         &self.vcf
     }
 
-    pub fn get_anniversary(&self) -> Option<CalDateTime> {
-        let prop = self.vcard.get_property("ANNIVERSARY")?;
-        CalDateTime::parse_prop(prop, &HashMap::default()).unwrap_or(None)
+    /// Only known for objects read from a store
+    pub fn get_created_at(&self) -> Option<NaiveDateTime> {
+        self.created_at
     }
 
-    pub fn get_birthday(&self) -> Option<CalDateTime> {
-        let prop = self.vcard.get_property("BDAY")?;
-        CalDateTime::parse_prop(prop, &HashMap::default()).unwrap_or(None)
+    /// Only known for objects read from a store
+    pub fn get_updated_at(&self) -> Option<NaiveDateTime> {
+        self.updated_at
+    }
+
+    /// Dates that can't be placed in a calendar are skipped with a warning
+    fn parse_date_prop(&self, prop: &Property) -> Option<VcardDate> {
+        VcardDate::parse_prop(prop)
+            .inspect_err(|err| warn!("Skipping {} of contact {}: {err}", prop.name, self.id))
+            .ok()
+    }
+
+    pub fn get_anniversary(&self) -> Option<VcardDate> {
+        self.parse_date_prop(self.get_vcard()?.get_property("ANNIVERSARY")?)
+    }
+
+    pub fn get_birthday(&self) -> Option<VcardDate> {
+        self.parse_date_prop(self.get_vcard()?.get_property("BDAY")?)
     }
 
     pub fn get_full_name(&self) -> Option<&String> {
-        let prop = self.vcard.get_property("FN")?;
+        let prop = self.get_vcard()?.get_property("FN")?;
         prop.value.as_ref()
     }
 
-    pub fn get_anniversary_object(&self) -> Result<Option<CalendarObject>, Error> {
-        Ok(if let Some(anniversary) = self.get_anniversary() {
-            let fullname = if let Some(name) = self.get_full_name() {
-                name
-            } else {
-                return Ok(None);
-            };
-            let anniversary = anniversary.date();
-            let year = anniversary.year();
-            let anniversary_start = anniversary.format(LOCAL_DATE);
-            let anniversary_end = anniversary
-                .succ_opt()
-                .unwrap_or(anniversary)
-                .format(LOCAL_DATE);
-            let uid = format!("{}-anniversary", self.get_id());
-
-            Some(CalendarObject::from_ics(
-                uid.clone(),
-                format!(
-                    r#"BEGIN:VCALENDAR
-VERSION:2.0
-CALSCALE:GREGORIAN
-PRODID:-//github.com/lennart-k/rustical birthday calendar//EN
-BEGIN:VEVENT
-DTSTART;VALUE=DATE:{anniversary_start}
-DTEND;VALUE=DATE:{anniversary_end}
-UID:{uid}
-RRULE:FREQ=YEARLY
-SUMMARY:💍 {fullname} ({year})
-TRANSP:TRANSPARENT
+    /// X-ABDATE properties (Apple's custom dates) together with their X-ABLABEL
+    pub fn get_abdates(&self) -> Vec<(Option<String>, VcardDate)> {
+        let Some(vcard) = self.get_vcard() else {
+            return vec![];
+        };
+        vcard
+            .properties
+            .iter()
+            .filter_map(|prop| {
+                let (group, name) = match prop.name.rsplit_once('.') {
+                    Some((group, name)) => (Some(group), name),
+                    None => (None, prop.name.as_str()),
+                };
+                if !name.eq_ignore_ascii_case("X-ABDATE") {
+                    return None;
+                }
+                let date = self.parse_date_prop(prop)?;
+                let label = group.and_then(|group| {
+                    vcard
+                        .properties
+                        .iter()
+                        .find(|label| {
+                            label
+                                .name
+                                .eq_ignore_ascii_case(&format!("{group}.X-ABLABEL"))
+                        })
+                        .and_then(|label| label.value.as_ref())
+                        // Apple uses labels like _$!<Anniversary>!$_ for predefined labels
+                        .map(|label| {
+                            label
+                                .trim_start_matches("_$!<")
+                                .trim_end_matches(">!$_")
+                                .to_owned()
+                        })
+                });
+                Some((label, date))
+            })
+            .take(MAX_ABDATES)
+            .collect()
+    }
+
+    /// All dates of the contact that can be placed in a calendar, regardless of the birthday
+    /// calendar settings. Stores index them to prefilter birthday calendars.
+    pub fn get_dates(&self) -> Vec<VcardDate> {
+        let mut dates: Vec<_> = self.get_birthday().into_iter().collect();
+        dates.extend(self.get_anniversary());
+        dates.extend(self.get_abdates().into_iter().map(|(_label, date)| date));
+        dates
+    }
+
+    fn get_significant_date_object(
+        &self,
+        date_type: &str,
+        date: &VcardDate,
+        summary_template: &str,
+        label: Option<&str>,
+        settings: &BirthdayCalendarSettings,
+    ) -> Result<Option<CalendarObject>, Error> {
+        let fullname = if let Some(name) = self.get_full_name() {
+            name
+        } else {
+            return Ok(None);
+        };
+        let summary = escape_text(&BirthdayCalendarSettings::format_summary(
+            summary_template,
+            fullname,
+            date.year,
+            label,
+        ));
+        let rrule = date.rrule();
+        let date = if let Some(date) = date.first_date() {
+            date
+        } else {
+            return Ok(None);
+        };
+        let start = date.format(LOCAL_DATE);
+        let end = date.succ_opt().unwrap_or(date).format(LOCAL_DATE);
+        let uid = format!("{}-{date_type}", self.get_id());
+        let alarm = if let Some(trigger) = &settings.alarm_trigger {
+            format!(
+                r#"
 BEGIN:VALARM
-TRIGGER;VALUE=DURATION:-PT0M
+TRIGGER;VALUE=DURATION:{trigger}
 ACTION:DISPLAY
-DESCRIPTION:💍 {fullname} ({year})
-END:VALARM
-END:VEVENT
-END:VCALENDAR"#,
-                ),
-            )?)
+DESCRIPTION:{summary}
+END:VALARM"#
+            )
         } else {
-            None
-        })
-    }
+            String::new()
+        };
 
-    pub fn get_birthday_object(&self) -> Result<Option<CalendarObject>, Error> {
-        Ok(if let Some(birthday) = self.get_birthday() {
-            let fullname = if let Some(name) = self.get_full_name() {
-                name
-            } else {
-                return Ok(None);
-            };
-            let birthday = birthday.date();
-            let year = birthday.year();
-            let birthday_start = birthday.format(LOCAL_DATE);
-            let birthday_end = birthday.succ_opt().unwrap_or(birthday).format(LOCAL_DATE);
-            let uid = format!("{}-birthday", self.get_id());
-
-            Some(CalendarObject::from_ics(
-                uid.clone(),
-                format!(
-                    r#"BEGIN:VCALENDAR
+        Ok(Some(CalendarObject::from_ics(
+            uid.clone(),
+            format!(
+                r#"BEGIN:VCALENDAR
 VERSION:2.0
 CALSCALE:GREGORIAN
 PRODID:-//github.com/lennart-k/rustical birthday calendar//EN
 BEGIN:VEVENT
-DTSTART;VALUE=DATE:{birthday_start}
-DTEND;VALUE=DATE:{birthday_end}
+DTSTART;VALUE=DATE:{start}
+DTEND;VALUE=DATE:{end}
 UID:{uid}
-RRULE:FREQ=YEARLY
-SUMMARY:🎂 {fullname} ({year})
-TRANSP:TRANSPARENT
-BEGIN:VALARM
-TRIGGER;VALUE=DURATION:-PT0M
-ACTION:DISPLAY
-DESCRIPTION:🎂 {fullname} ({year})
-END:VALARM
+RRULE:{rrule}
+SUMMARY:{summary}
+TRANSP:TRANSPARENT{alarm}
 END:VEVENT
 END:VCALENDAR"#,
-                ),
-            )?)
+            ),
+        )?))
+    }
+
+    pub fn get_anniversary_object(
+        &self,
+        settings: &BirthdayCalendarSettings,
+    ) -> Result<Option<CalendarObject>, Error> {
+        if let Some(anniversary) = self.get_anniversary() {
+            self.get_significant_date_object(
+                "anniversary",
+                &anniversary,
+                &settings.anniversary_summary_template,
+                None,
+                settings,
+            )
         } else {
-            None
-        })
+            Ok(None)
+        }
+    }
+
+    pub fn get_birthday_object(
+        &self,
+        settings: &BirthdayCalendarSettings,
+    ) -> Result<Option<CalendarObject>, Error> {
+        if let Some(birthday) = self.get_birthday() {
+            self.get_significant_date_object(
+                "birthday",
+                &birthday,
+                &settings.birthday_summary_template,
+                None,
+                settings,
+            )
+        } else {
+            Ok(None)
+        }
     }
 
     /// Get significant dates associated with this address object
-    pub fn get_significant_dates(&self) -> Result<HashMap<&'static str, CalendarObject>, Error> {
-        let mut out = HashMap::new();
-        if let Some(birthday) = self.get_birthday_object()? {
-            out.insert("birthday", birthday);
+    pub fn get_significant_dates(
+        &self,
+        settings: &BirthdayCalendarSettings,
+    ) -> Result<HashMap<String, CalendarObject>, Error> {
+        self.get_significant_dates_between(settings, None, None)
+    }
+
+    /// Get significant dates that have an occurence in the given (inclusive) range
+    ///
+    /// Only the matching dates get rendered which makes this cheaper than filtering the result of
+    /// get_significant_dates
+    pub fn get_significant_dates_between(
+        &self,
+        settings: &BirthdayCalendarSettings,
+        start: Option<NaiveDate>,
+        end: Option<NaiveDate>,
+    ) -> Result<HashMap<String, CalendarObject>, Error> {
+        let mut dates = vec![];
+        if let Some(birthday) = self.get_birthday() {
+            dates.push((
+                "birthday".to_owned(),
+                birthday,
+                &settings.birthday_summary_template,
+                None,
+            ));
+        }
+        if settings.include_anniversaries
+            && let Some(anniversary) = self.get_anniversary()
+        {
+            dates.push((
+                "anniversary".to_owned(),
+                anniversary,
+                &settings.anniversary_summary_template,
+                None,
+            ));
         }
-        if let Some(anniversary) = self.get_anniversary_object()? {
-            out.insert("anniversary", anniversary);
+        if settings.include_abdates {
+            for (i, (label, date)) in self.get_abdates().into_iter().enumerate() {
+                // The date type must not contain a dash since it's used to split the object id
+                dates.push((
+                    format!("abdate{i}"),
+                    date,
+                    &settings.abdate_summary_template,
+                    label,
+                ));
+            }
+        }
+
+        let mut out = HashMap::new();
+        for (date_type, date, summary_template, label) in dates {
+            if !date.occurs_between(start, end) {
+                continue;
+            }
+            if let Some(object) = self.get_significant_date_object(
+                &date_type,
+                &date,
+                summary_template,
+                label.as_deref(),
+                settings,
+            )? {
+                out.insert(date_type, object);
+            }
         }
         Ok(out)
     }
+
+    /// All date types get_significant_dates might return with the given settings
+    pub fn significant_date_types(settings: &BirthdayCalendarSettings) -> Vec<String> {
+        let mut date_types = vec!["birthday".to_owned(), "anniversary".to_owned()];
+        if settings.include_abdates {
+            date_types.extend((0..MAX_ABDATES).map(|i| format!("abdate{i}")));
+        }
+        date_types
+    }
+}
+
+/// Escape TEXT values as specified in https://datatracker.ietf.org/doc/html/rfc5545#section-3.3.11
+pub(crate) fn escape_text(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+#[test]
+fn test_significant_dates() {
+    let object = AddressObject::from_vcf(
+        "contact".to_owned(),
+        r#"BEGIN:VCARD
+VERSION:4.0
+FN:Jane Doe
+BDAY:19850412
+ANNIVERSARY:20100612
+item1.X-ABDATE:2015-03-01
+item1.X-ABLabel:_$!<Anniversary>!$_
+END:VCARD"#
+            .to_owned(),
+    )
+    .unwrap();
+
+    let dates = object
+        .get_significant_dates(&BirthdayCalendarSettings::default())
+        .unwrap();
+    assert_eq!(dates.len(), 2);
+    assert!(
+        dates["birthday"]
+            .get_ics()
+            .contains("SUMMARY:🎂 Jane Doe (1985)")
+    );
+    assert!(dates["birthday"].get_ics().contains("BEGIN:VALARM"));
+
+    let dates = object
+        .get_significant_dates(&BirthdayCalendarSettings {
+            birthday_summary_template: "{name} born {year}".to_owned(),
+            alarm_trigger: None,
+            include_anniversaries: false,
+            include_abdates: true,
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(dates.len(), 2);
+    assert!(
+        dates["birthday"]
+            .get_ics()
+            .contains("SUMMARY:Jane Doe born 1985")
+    );
+    assert!(!dates["birthday"].get_ics().contains("BEGIN:VALARM"));
+    assert!(
+        dates["abdate0"]
+            .get_ics()
+            .contains("SUMMARY:📅 Jane Doe: Anniversary (2015)")
+    );
+}
+
+#[test]
+fn test_yearless_birthday() {
+    let settings = BirthdayCalendarSettings {
+        birthday_summary_template: "{name}{? ({year})}".to_owned(),
+        ..Default::default()
+    };
+    let object = AddressObject::from_vcf(
+        "contact".to_owned(),
+        "BEGIN:VCARD\nVERSION:4.0\nFN:Jane Doe\nBDAY:--0229\nEND:VCARD".to_owned(),
+    )
+    .unwrap();
+    let ics = object.get_birthday_object(&settings).unwrap().unwrap();
+    let ics = ics.get_ics();
+    assert!(ics.contains("SUMMARY:Jane Doe\n"));
+    assert!(ics.contains("DTSTART;VALUE=DATE:19720229"));
+    assert!(ics.contains("RRULE:FREQ=YEARLY;BYMONTH=2;BYMONTHDAY=-1"));
+
+    // Text dates are used if they contain a date
+    let object = AddressObject::from_vcf(
+        "contact".to_owned(),
+        "BEGIN:VCARD\nVERSION:4.0\nFN:Jane Doe\nBDAY;VALUE=text:1985-04-12\nEND:VCARD".to_owned(),
+    )
+    .unwrap();
+    let ics = object.get_birthday_object(&settings).unwrap().unwrap();
+    assert!(ics.get_ics().contains("DTSTART;VALUE=DATE:19850412"));
+
+    for bday in ["BDAY:--04", "BDAY:1985", "BDAY;VALUE=text:circa 1800"] {
+        let object = AddressObject::from_vcf(
+            "contact".to_owned(),
+            format!("BEGIN:VCARD\nVERSION:4.0\nFN:Jane Doe\n{bday}\nEND:VCARD"),
+        )
+        .unwrap();
+        assert!(object.get_birthday_object(&settings).unwrap().is_none());
+    }
+}
+
+#[test]
+fn test_lazy_address_object() {
+    let vcf = "BEGIN:VCARD\r\nVERSION:4.0\r\nUID:lazy\r\nFN:Lazy\r\nEND:VCARD\r\n";
+    let parsed = AddressObject::from_vcf("lazy".to_owned(), vcf.to_owned()).unwrap();
+    let stored = AddressObject::from_stored("lazy".to_owned(), vcf.to_owned(), None);
+    assert_eq!(stored.get_etag(), parsed.get_etag());
+    assert_eq!(stored.get_full_name(), parsed.get_full_name());
+
+    // Invalid stored objects only fail once their content is needed
+    let invalid = AddressObject::from_stored("invalid".to_owned(), "invalid".to_owned(), None);
+    assert_eq!(invalid.get_full_name(), None);
+
+    let metadata = AddressObject::from_metadata("lazy".to_owned(), parsed.get_etag());
+    assert_eq!(metadata.get_etag(), parsed.get_etag());
+    assert_eq!(metadata.get_vcf(), "");
+    assert!(metadata.get_dates().is_empty());
 }
@@ -8,6 +8,8 @@ pub struct Addressbook {
     pub principal: String,
     pub displayname: Option<String>,
     pub description: Option<String>,
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
     pub deleted_at: Option<NaiveDateTime>,
     pub synctoken: i64,
     pub push_topic: String,
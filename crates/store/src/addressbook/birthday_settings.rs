@@ -0,0 +1,141 @@
+use crate::{Error, calendar::parse_duration};
+use serde::{Deserialize, Serialize};
+
+/// Per-principal settings for the calendars generated by `ContactBirthdayStore`
+///
+/// Summary templates support the following placeholders:
+/// - `{name}`: The full name of the contact
+/// - `{year}`: The year of the original date
+/// - `{label}`: The label of a X-ABDATE date (only for `abdate_summary_template`)
+///
+/// Text inside `{?...}` is only rendered for dates with a year, e.g. `{name}{? ({year})}`.
+/// Outside of such sections `{year}` is empty for dates without a year.
+///
+/// There is no placeholder for the age since every date is a single yearly recurring event
+/// whose content must not change from one year to the next.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BirthdayCalendarSettings {
+    pub birthday_summary_template: String,
+    pub anniversary_summary_template: String,
+    pub abdate_summary_template: String,
+    /// Duration relative to the start of the event (RFC 5545 3.3.6), e.g. -PT9H
+    /// No alarm is generated if None
+    pub alarm_trigger: Option<String>,
+    pub color: Option<String>,
+    /// `{name}` is replaced with the addressbook's displayname
+    pub displayname_template: String,
+    pub include_anniversaries: bool,
+    pub include_abdates: bool,
+}
+
+impl Default for BirthdayCalendarSettings {
+    fn default() -> Self {
+        Self {
+            birthday_summary_template: "🎂 {name}{? ({year})}".to_owned(),
+            anniversary_summary_template: "💍 {name}{? ({year})}".to_owned(),
+            abdate_summary_template: "📅 {name}: {label}{? ({year})}".to_owned(),
+            alarm_trigger: Some("-PT0M".to_owned()),
+            color: None,
+            displayname_template: "{name} birthdays".to_owned(),
+            include_anniversaries: true,
+            include_abdates: false,
+        }
+    }
+}
+
+impl BirthdayCalendarSettings {
+    pub fn validate(&self) -> Result<(), Error> {
+        for template in [
+            &self.birthday_summary_template,
+            &self.anniversary_summary_template,
+            &self.abdate_summary_template,
+        ] {
+            if template.contains("{age}") {
+                return Err(Error::InvalidData(
+                    "Summary templates don't support {age}".to_owned(),
+                ));
+            }
+        }
+        if let Some(alarm_trigger) = &self.alarm_trigger {
+            parse_duration(alarm_trigger)?;
+        }
+        Ok(())
+    }
+
+    pub fn format_displayname(&self, name: &str) -> String {
+        self.displayname_template.replace("{name}", name)
+    }
+
+    pub fn format_summary(
+        template: &str,
+        name: &str,
+        year: Option<i32>,
+        label: Option<&str>,
+    ) -> String {
+        let mut summary = String::new();
+        let mut rest = template;
+        while let Some(start) = rest.find("{?") {
+            summary.push_str(&rest[..start]);
+            let section = &rest[start + 2..];
+            // Placeholders are nested inside the section
+            let mut depth = 0;
+            let end = section
+                .char_indices()
+                .find(|(_, c)| match c {
+                    '{' => {
+                        depth += 1;
+                        false
+                    }
+                    '}' if depth == 0 => true,
+                    '}' => {
+                        depth -= 1;
+                        false
+                    }
+                    _ => false,
+                })
+                .map_or(section.len(), |(i, _)| i);
+            if year.is_some() {
+                summary.push_str(&section[..end]);
+            }
+            rest = section.get(end + 1..).unwrap_or_default();
+        }
+        summary.push_str(rest);
+
+        let year = year.map(|year| year.to_string()).unwrap_or_default();
+        summary
+            .replace("{year}", &year)
+            // Might still be contained in templates saved before it was removed
+            .replace("{age}", "")
+            .replace("{label}", label.unwrap_or_default())
+            .replace("{name}", name)
+    }
+}
+
+#[test]
+fn test_format_summary() {
+    let format = |template, year| {
+        BirthdayCalendarSettings::format_summary(template, "Jane Doe", year, Some("Wedding"))
+    };
+    assert_eq!(
+        format("🎂 {name}{? ({year})}", Some(1985)),
+        "🎂 Jane Doe (1985)"
+    );
+    assert_eq!(format("🎂 {name}{? ({year})}", None), "🎂 Jane Doe");
+    assert_eq!(format("{name}{? (born {year})}", None), "Jane Doe");
+    // Text outside of sections is kept as is
+    assert_eq!(
+        format("{label}:  {name} () {year}", None),
+        "Wedding:  Jane Doe () "
+    );
+    assert_eq!(format("{name} {?unterminated", None), "Jane Doe ");
+}
+
+#[test]
+fn test_validate() {
+    assert!(BirthdayCalendarSettings::default().validate().is_ok());
+    let settings = BirthdayCalendarSettings {
+        birthday_summary_template: "{name} turns {age}".to_owned(),
+        ..Default::default()
+    };
+    assert!(matches!(settings.validate(), Err(Error::InvalidData(_))));
+}
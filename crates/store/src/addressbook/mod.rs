@@ -1,5 +1,10 @@
 pub mod address_object;
+#[allow(clippy::module_inception)]
 pub mod addressbook;
+pub mod birthday_settings;
+pub mod vcard_date;
 
 pub use address_object::*;
 pub use addressbook::*;
+pub use birthday_settings::*;
+pub use vcard_date::*;
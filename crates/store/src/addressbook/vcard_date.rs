@@ -0,0 +1,276 @@
+use crate::{Error, calendar::RE_VCARD_DATE_MM_DD};
+use chrono::{Datelike, NaiveDate, TimeDelta};
+use ical::property::Property;
+
+// Because 1972 is a leap year
+const YEARLESS_YEAR: i32 = 1972;
+
+/// A date from a vCard (https://datatracker.ietf.org/doc/html/rfc6350#section-4.3.1)
+///
+/// Only dates with at least a month and a day can be placed in a calendar,
+/// reduced accuracy dates like --04 or 1985 and text values that aren't dates are rejected.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VcardDate {
+    pub year: Option<i32>,
+    pub month: u32,
+    pub day: u32,
+}
+
+impl VcardDate {
+    pub fn parse_prop(prop: &Property) -> Result<Self, Error> {
+        let params = prop.params.as_deref().unwrap_or_default();
+        let param = |name: &str| {
+            params
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .and_then(|(_, values)| values.first())
+        };
+        let value = prop
+            .value
+            .as_deref()
+            .ok_or(Error::InvalidData(format!("{} without value", prop.name)))?;
+        if param("VALUE").is_some_and(|value| value.eq_ignore_ascii_case("text")) {
+            // Some clients store regular dates as text
+            return Self::parse(value.trim()).map_err(|_| {
+                Error::InvalidData(format!("Text {} is not a date: {value}", prop.name))
+            });
+        }
+        let mut date = Self::parse(value)?;
+        // Apple clients store yearless dates with a placeholder year
+        if let Some(omit_year) = param("X-APPLE-OMIT-YEAR")
+            && omit_year.parse().ok() == date.year
+        {
+            date.year = None;
+        }
+        Ok(date)
+    }
+
+    pub fn parse(value: &str) -> Result<Self, Error> {
+        if let Some(captures) = RE_VCARD_DATE_MM_DD.captures(value) {
+            // Cannot fail because of the regex
+            let month = captures.name("m").unwrap().as_str().parse().unwrap();
+            let day = captures.name("d").unwrap().as_str().parse().unwrap();
+            let date = Self {
+                year: None,
+                month,
+                day,
+            };
+            // Validate month and day
+            return date
+                .first_date()
+                .map(|_| date)
+                .ok_or(Error::InvalidData(format!("Invalid date {value}")));
+        }
+
+        // Dates might also be specified as date-time
+        let date = value.split_once('T').map_or(value, |(date, _time)| date);
+        if let Ok(date) = NaiveDate::parse_from_str(date, "%Y%m%d")
+            .or_else(|_| NaiveDate::parse_from_str(date, "%Y-%m-%d"))
+        {
+            return Ok(Self {
+                year: Some(date.year()),
+                month: date.month(),
+                day: date.day(),
+            });
+        }
+        // Reduced accuracy dates (--MM, YYYY, YYYY-MM, ---DD) lack a day or month
+        if date.starts_with("--")
+            || (date.len() <= 7 && date.chars().all(|c| c.is_ascii_digit() || c == '-'))
+        {
+            return Err(Error::InvalidData(format!(
+                "Date {value} has reduced accuracy and can't be placed in a calendar"
+            )));
+        }
+        Err(Error::InvalidData(format!("Invalid date {value}")))
+    }
+
+    /// The first date the event takes place on
+    /// Yearless dates are placed in a leap year so that February 29 is valid
+    pub fn first_date(&self) -> Option<NaiveDate> {
+        NaiveDate::from_ymd_opt(self.year.unwrap_or(YEARLESS_YEAR), self.month, self.day)
+    }
+
+    pub fn is_leap_day(&self) -> bool {
+        self.month == 2 && self.day == 29
+    }
+
+    /// The date of the occurence in a given year
+    pub fn occurence_in_year(&self, year: i32) -> Option<NaiveDate> {
+        if self.year.is_some_and(|first_year| year < first_year) {
+            return None;
+        }
+        NaiveDate::from_ymd_opt(year, self.month, self.day).or_else(|| {
+            self.is_leap_day()
+                .then(|| NaiveDate::from_ymd_opt(year, 2, 28))
+                .flatten()
+        })
+    }
+
+    /// Whether the yearly event has an occurence in the given (inclusive) range
+    pub fn occurs_between(&self, start: Option<NaiveDate>, end: Option<NaiveDate>) -> bool {
+        let first_date = if let Some(first_date) = self.first_date() {
+            first_date
+        } else {
+            return false;
+        };
+        let end = end.unwrap_or(NaiveDate::MAX);
+        let start = start.unwrap_or(first_date).max(first_date);
+        if start > end {
+            return false;
+        }
+        // The range contains at least one full year
+        if end.year() - start.year() >= 2 {
+            return true;
+        }
+        (start.year()..=end.year())
+            .filter_map(|year| self.occurence_in_year(year))
+            .any(|date| start <= date && date <= end)
+    }
+
+    /// The month and day as MMDD, used by stores to index dates
+    pub fn month_day(&self) -> u32 {
+        self.month * 100 + self.day
+    }
+
+    /// The recurrence rule for the yearly event
+    pub fn rrule(&self) -> &'static str {
+        if self.is_leap_day() {
+            // Fire on February 28 in non-leap years
+            "FREQ=YEARLY;BYMONTH=2;BYMONTHDAY=-1"
+        } else {
+            "FREQ=YEARLY"
+        }
+    }
+}
+
+/// Prefilter for yearly dates occurring in an (inclusive) range that stores can evaluate on
+/// indexed dates, see [`VcardDate::month_day`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct VcardDateRange {
+    /// Dates first occurring after this year are excluded
+    pub max_year: i32,
+    /// Two inclusive ranges of MMDD values, the second one is needed if the range crosses the
+    /// end of a year and equals the first one otherwise
+    pub month_days: [(u32, u32); 2],
+}
+
+impl VcardDateRange {
+    pub fn new(start: Option<NaiveDate>, end: Option<NaiveDate>) -> Self {
+        const ALL: (u32, u32) = (0, 1231);
+        let max_year = end.map_or(i32::MAX, |end| end.year());
+        let month_day = |date: NaiveDate| date.month() * 100 + date.day();
+        // February 29 falls on February 28 in non-leap years
+        let month_day_end = |date: NaiveDate| match month_day(date) {
+            228 => 229,
+            month_day => month_day,
+        };
+        let month_days = match (start, end) {
+            (Some(start), Some(end)) if start > end => [(1, 0); 2],
+            // The range covers every day of the year
+            (Some(start), Some(end)) if end - start >= TimeDelta::days(365) => [ALL; 2],
+            (Some(start), Some(end)) if start.year() == end.year() => {
+                [(month_day(start), month_day_end(end)); 2]
+            }
+            (Some(start), Some(end)) => [(month_day(start), 1231), (0, month_day_end(end))],
+            _ => [ALL; 2],
+        };
+        Self {
+            max_year,
+            month_days,
+        }
+    }
+
+    pub fn matches(&self, date: &VcardDate) -> bool {
+        let month_day = date.month_day();
+        date.year.is_none_or(|year| year <= self.max_year)
+            && self
+                .month_days
+                .iter()
+                .any(|(start, end)| (*start..=*end).contains(&month_day))
+    }
+}
+
+#[test]
+fn test_parse_vcard_date() {
+    assert_eq!(
+        VcardDate::parse("19850412").unwrap(),
+        VcardDate {
+            year: Some(1985),
+            month: 4,
+            day: 12
+        }
+    );
+    assert_eq!(
+        VcardDate::parse("1985-04-12").unwrap(),
+        VcardDate::parse("19850412T230000Z").unwrap()
+    );
+    assert_eq!(
+        VcardDate::parse("--0412").unwrap(),
+        VcardDate {
+            year: None,
+            month: 4,
+            day: 12
+        }
+    );
+    assert_eq!(
+        VcardDate::parse("--04-12").unwrap(),
+        VcardDate::parse("--0412").unwrap()
+    );
+    assert!(VcardDate::parse("--0229").unwrap().is_leap_day());
+    assert!(VcardDate::parse("--0230").is_err());
+    for reduced in ["--04", "1985", "1985-04", "---12"] {
+        assert!(
+            VcardDate::parse(reduced)
+                .unwrap_err()
+                .to_string()
+                .contains("reduced accuracy")
+        );
+    }
+    assert!(VcardDate::parse("circa 1800").is_err());
+}
+
+#[test]
+fn test_vcard_date_occurs_between() {
+    let date = |value: &str| NaiveDate::parse_from_str(value, "%Y-%m-%d").ok();
+    let birthday = VcardDate::parse("1985-04-12").unwrap();
+    assert!(birthday.occurs_between(None, None));
+    assert!(birthday.occurs_between(date("2024-04-01"), date("2024-04-30")));
+    assert!(birthday.occurs_between(date("2024-12-01"), date("2025-04-12")));
+    assert!(!birthday.occurs_between(date("2024-04-13"), date("2025-04-11")));
+    // No occurences before the original date
+    assert!(!birthday.occurs_between(date("1980-01-01"), date("1985-04-11")));
+    assert!(birthday.occurs_between(None, date("1985-04-12")));
+
+    let leap_day = VcardDate::parse("--0229").unwrap();
+    assert!(leap_day.occurs_between(date("2023-02-28"), date("2023-02-28")));
+    assert!(!leap_day.occurs_between(date("2024-02-28"), date("2024-02-28")));
+    assert!(leap_day.occurs_between(date("2024-02-29"), date("2024-02-29")));
+}
+
+#[test]
+fn test_vcard_date_range() {
+    let date = |value: &str| NaiveDate::parse_from_str(value, "%Y-%m-%d").ok();
+    let birthday = VcardDate::parse("1985-04-12").unwrap();
+    let leap_day = VcardDate::parse("--0229").unwrap();
+    for (start, end) in [
+        (None, None),
+        (date("2024-04-01"), date("2024-04-30")),
+        (date("2024-12-01"), date("2025-04-12")),
+        (date("2020-01-01"), date("2022-01-01")),
+        (date("2023-02-28"), date("2023-02-28")),
+        (date("2024-04-13"), date("2025-04-11")),
+        (date("1980-01-01"), date("1984-12-31")),
+        (date("2024-05-01"), date("2024-04-01")),
+    ] {
+        let range = VcardDateRange::new(start, end);
+        // The prefilter never excludes a date that occurs in the range
+        for date in [&birthday, &leap_day] {
+            if date.occurs_between(start, end) {
+                assert!(range.matches(date), "{date:?} in {start:?}..{end:?}");
+            }
+        }
+    }
+    assert!(!VcardDateRange::new(date("2024-04-13"), date("2025-04-11")).matches(&birthday));
+    assert!(!VcardDateRange::new(date("1980-01-01"), date("1984-12-31")).matches(&birthday));
+    assert!(VcardDateRange::new(date("2023-02-28"), date("2023-02-28")).matches(&leap_day));
+}
@@ -1,8 +1,12 @@
 use crate::{
-    addressbook::{AddressObject, Addressbook},
     Error,
+    addressbook::{AddressObject, Addressbook, BirthdayCalendarSettings, VcardDateRange},
+    quota::Quota,
+    revision::ObjectRevision,
 };
 use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use std::collections::HashMap;
 
 #[async_trait]
 pub trait AddressbookStore: Send + Sync + 'static {
@@ -25,31 +29,96 @@ pub trait AddressbookStore: Send + Sync + 'static {
     ) -> Result<(), Error>;
     async fn restore_addressbook(&self, principal: &str, name: &str) -> Result<(), Error>;
 
+    /// Returns the objects changed and deleted since `synctoken` and the new synctoken.
+    /// With a `limit` the changes might be truncated, which is indicated by the last value.
+    /// The sync then continues from the returned synctoken.
+    /// A synctoken from before the changelog was compacted returns [`Error::InvalidSyncToken`],
+    /// `0` requests an initial sync and is always valid, as are the negative synctokens continuing it.
     async fn sync_changes(
         &self,
         principal: &str,
         addressbook_id: &str,
         synctoken: i64,
-    ) -> Result<(Vec<AddressObject>, Vec<String>, i64), Error>;
+        limit: Option<usize>,
+    ) -> Result<(Vec<AddressObject>, Vec<String>, i64, bool), Error>;
 
     async fn get_objects(
         &self,
         principal: &str,
         addressbook_id: &str,
     ) -> Result<Vec<AddressObject>, Error>;
+    /// Like [`AddressbookStore::get_objects`] but the objects may only carry their metadata
+    /// (see [`AddressObject::from_metadata`]), for listings that don't need the address data
+    async fn get_objects_metadata(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+    ) -> Result<Vec<AddressObject>, Error> {
+        self.get_objects(principal, addressbook_id).await
+    }
+    /// Returns the objects with a date (birthday, anniversary or X-ABDATE) that might occur in
+    /// the range when recurring yearly, used to prefilter birthday calendars.
+    /// Stores can evaluate the range on an index of [`VcardDate::month_day`](crate::addressbook::VcardDate::month_day),
+    /// the default implementation filters all objects.
+    async fn get_objects_with_dates(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+        range: &VcardDateRange,
+    ) -> Result<Vec<AddressObject>, Error> {
+        Ok(self
+            .get_objects(principal, addressbook_id)
+            .await?
+            .into_iter()
+            .filter(|object| object.get_dates().iter().any(|date| range.matches(date)))
+            .collect())
+    }
     async fn get_object(
         &self,
         principal: &str,
         addressbook_id: &str,
         object_id: &str,
     ) -> Result<AddressObject, Error>;
+    /// Also records the object as a new revision, `changed_by` is the principal making the change
     async fn put_object(
         &self,
         principal: String,
         addressbook_id: String,
         object: AddressObject,
         overwrite: bool,
+        changed_by: Option<&str>,
     ) -> Result<(), Error>;
+    /// Like [`AddressbookStore::put_object`] but fails with [`Error::QuotaExceeded`] if the object
+    /// grows beyond the principal's quota.
+    /// Stores with transactions check the quota atomically with the write,
+    /// the default implementation checks it before writing.
+    async fn put_object_with_quota(
+        &self,
+        principal: String,
+        addressbook_id: String,
+        object: AddressObject,
+        overwrite: bool,
+        changed_by: Option<&str>,
+        quota: &Quota,
+    ) -> Result<(), Error> {
+        let old_size = match self
+            .get_object(&principal, &addressbook_id, object.get_id())
+            .await
+        {
+            Ok(existing) => existing.get_vcf().len() as u64,
+            Err(Error::NotFound) => 0,
+            Err(err) => return Err(err),
+        };
+        if !quota
+            .allows_put(&principal, old_size, object.get_vcf().len() as u64)
+            .await?
+        {
+            return Err(Error::QuotaExceeded);
+        }
+        self.put_object(principal, addressbook_id, object, overwrite, changed_by)
+            .await
+    }
+    /// Without the trashbin the object is deleted permanently, also if it's already in the trashbin
     async fn delete_object(
         &self,
         principal: &str,
@@ -63,4 +132,96 @@ pub trait AddressbookStore: Send + Sync + 'static {
         addressbook_id: &str,
         object_id: &str,
     ) -> Result<(), Error>;
+    /// Returns the objects of an addressbook that are in the trashbin
+    async fn get_deleted_objects(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+    ) -> Result<Vec<AddressObject>, Error>;
+
+    /// Returns the total size in bytes of a principal's objects, counted against its quota.
+    /// Objects in the trashbin don't count.
+    async fn get_used_bytes(&self, principal: &str) -> Result<u64, Error>;
+
+    /// Permanently deletes addressbooks and objects moved to the trashbin before `deleted_before`
+    /// and returns how many were deleted.
+    /// The changelog of deleted objects is pruned to the deletion itself.
+    async fn purge_trashbin(&self, deleted_before: NaiveDateTime) -> Result<usize, Error>;
+
+    /// Collapses the changelog of every object to its latest change and drops changes made
+    /// before `horizon`, except for the latest change of objects that still exist.
+    /// Synctokens from before `horizon` become invalid. Returns how many changes were dropped.
+    async fn compact_changelog(&self, horizon: NaiveDateTime) -> Result<usize, Error>;
+
+    /// Returns the most recent revisions of an object, newest first.
+    /// Permanently deleting an object also deletes its revisions.
+    async fn get_object_revisions(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+        object_id: &str,
+    ) -> Result<Vec<ObjectRevision<AddressObject>>, Error>;
+    /// Like [`AddressbookStore::get_object_revisions`] for multiple objects at once,
+    /// objects without revisions are left out
+    async fn get_objects_revisions(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+        object_ids: &[String],
+    ) -> Result<HashMap<String, Vec<ObjectRevision<AddressObject>>>, Error> {
+        let mut revisions = HashMap::new();
+        for object_id in object_ids {
+            let object_revisions = self
+                .get_object_revisions(principal, addressbook_id, object_id)
+                .await?;
+            if !object_revisions.is_empty() {
+                revisions.insert(object_id.to_owned(), object_revisions);
+            }
+        }
+        Ok(revisions)
+    }
+
+    /// Writes an older revision of an object as its new version,
+    /// like any other write it has to fit into the principal's quota
+    async fn rollback_object(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+        object_id: &str,
+        revision: i64,
+        changed_by: Option<&str>,
+        quota: &Quota,
+    ) -> Result<(), Error> {
+        let revision = self
+            .get_object_revisions(principal, addressbook_id, object_id)
+            .await?
+            .into_iter()
+            .find(|rev| rev.revision == revision)
+            .ok_or(Error::NotFound)?;
+        if revision.object.get_vcf().len() as u64 > quota.max_resource_size() {
+            return Err(Error::MaxResourceSizeExceeded);
+        }
+        self.put_object_with_quota(
+            principal.to_owned(),
+            addressbook_id.to_owned(),
+            revision.object,
+            true,
+            changed_by,
+            quota,
+        )
+        .await
+    }
+
+    /// Settings for the birthday calendars derived from the principal's addressbooks
+    async fn get_birthday_settings(
+        &self,
+        principal: &str,
+    ) -> Result<BirthdayCalendarSettings, Error>;
+    async fn update_birthday_settings(
+        &self,
+        principal: &str,
+        settings: BirthdayCalendarSettings,
+    ) -> Result<(), Error>;
+
+    fn is_read_only(&self) -> bool;
 }
@@ -68,14 +68,13 @@ where
         Box::pin(async move {
             if let Ok(auth) = Authorization::<Basic>::parse(req.request()) {
                 let user_id = auth.as_ref().user_id();
-                if let Some(password) = auth.as_ref().password() {
-                    if let Ok(Some(user)) = auth_provider
+                if let Some(password) = auth.as_ref().password()
+                    && let Ok(Some(user)) = auth_provider
                         .validate_app_token(user_id, password)
                         .instrument(info_span!("validate_user_token"))
                         .await
-                    {
-                        req.extensions_mut().insert(user);
-                    }
+                {
+                    req.extensions_mut().insert(user);
                 }
             }
 
@@ -2,17 +2,13 @@ use super::{AuthenticationProvider, user::AppToken};
 use crate::{auth::User, error::Error};
 use anyhow::anyhow;
 use async_trait::async_trait;
-use password_hash::PasswordHasher;
-use pbkdf2::{
-    Params,
-    password_hash::{self, SaltString, rand_core::OsRng},
-};
 use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, fs, io, ops::Deref};
 use tokio::sync::RwLock;
 
 #[derive(Debug, Clone, Deserialize, Serialize, Default)]
 struct TomlDataModel {
+    #[serde(default)]
     principals: Vec<User>,
 }
 
@@ -48,10 +44,7 @@ impl TomlPrincipalStore {
 
     fn save(&self, principals: &HashMap<String, User>) -> Result<(), Error> {
         let out = toml::to_string_pretty(&TomlDataModel {
-            principals: principals
-                .iter()
-                .map(|(_, value)| value.to_owned())
-                .collect(),
+            principals: principals.values().map(|value| value.to_owned()).collect(),
         })
         .map_err(|_| anyhow!("Error saving principal database"))?;
         fs::write(&self.config.path, out)?;
@@ -69,9 +62,9 @@ impl AuthenticationProvider for TomlPrincipalStore {
         Ok(self.principals.read().await.get(id).cloned())
     }
 
-    async fn insert_principal(&self, user: User) -> Result<(), crate::Error> {
+    async fn insert_principal(&self, user: User, overwrite: bool) -> Result<(), crate::Error> {
         let mut principals = self.principals.write().await;
-        if principals.contains_key(&user.id) {
+        if !overwrite && principals.contains_key(&user.id) {
             return Err(Error::AlreadyExists);
         }
         principals.insert(user.id.clone(), user);
@@ -91,33 +84,17 @@ impl AuthenticationProvider for TomlPrincipalStore {
         user_id: &str,
         password_input: &str,
     ) -> Result<Option<User>, Error> {
-        let user: User = match self.get_principal(user_id).await? {
-            Some(user) => user,
-            None => return Ok(None),
-        };
-        let password = match &user.password {
-            Some(password) => password,
-            None => return Ok(None),
-        };
-
-        if password_auth::verify_password(password_input, password.as_ref()).is_ok() {
-            return Ok(Some(user));
-        }
-        Ok(None)
+        Ok(self
+            .get_principal(user_id)
+            .await?
+            .filter(|user| user.verify_password(password_input)))
     }
 
     async fn validate_app_token(&self, user_id: &str, token: &str) -> Result<Option<User>, Error> {
-        let user: User = match self.get_principal(user_id).await? {
-            Some(user) => user,
-            None => return Ok(None),
-        };
-
-        for app_token in &user.app_tokens {
-            if password_auth::verify_password(token, app_token.token.as_ref()).is_ok() {
-                return Ok(Some(user));
-            }
-        }
-        Ok(None)
+        Ok(self
+            .get_principal(user_id)
+            .await?
+            .filter(|user| user.verify_app_token(token)))
     }
 
     /// Returns an identifier for the new app token
@@ -129,27 +106,9 @@ impl AuthenticationProvider for TomlPrincipalStore {
     ) -> Result<String, Error> {
         let mut principals = self.principals.write().await;
         if let Some(principal) = principals.get_mut(user_id) {
-            let id = uuid::Uuid::new_v4().to_string();
-            let salt = SaltString::generate(OsRng);
-            let token_hash = pbkdf2::Pbkdf2
-                .hash_password_customized(
-                    token.as_bytes(),
-                    None,
-                    None,
-                    Params {
-                        rounds: 1000,
-                        ..Default::default()
-                    },
-                    &salt,
-                )
-                .map_err(|_| Error::PasswordHash)?
-                .to_string();
-            principal.app_tokens.push(AppToken {
-                name,
-                token: token_hash.into(),
-                created_at: Some(chrono::Utc::now()),
-                id: id.clone(),
-            });
+            let app_token = AppToken::new(name, &token)?;
+            let id = app_token.id.clone();
+            principal.app_tokens.push(app_token);
             self.save(principals.deref())?;
             Ok(id)
         } else {
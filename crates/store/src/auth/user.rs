@@ -5,6 +5,11 @@ use actix_web::{
 };
 use chrono::{DateTime, Utc};
 use derive_more::Display;
+use password_hash::PasswordHasher;
+use pbkdf2::{
+    Params,
+    password_hash::{self, SaltString, rand_core::OsRng},
+};
 use rustical_xml::ValueSerialize;
 use serde::{Deserialize, Serialize};
 use std::future::{Ready, ready};
@@ -24,6 +29,31 @@ pub enum PrincipalType {
     // TODO: X-Name, IANA-token
 }
 
+impl PrincipalType {
+    /// The identifier used in the principals TOML and database tables
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PrincipalType::Individual => "individual",
+            PrincipalType::Group => "group",
+            PrincipalType::Resource => "resource",
+            PrincipalType::Room => "room",
+            PrincipalType::Unknown => "unknown",
+        }
+    }
+}
+
+impl From<&str> for PrincipalType {
+    fn from(value: &str) -> Self {
+        match value {
+            "individual" => PrincipalType::Individual,
+            "group" => PrincipalType::Group,
+            "resource" => PrincipalType::Resource,
+            "room" => PrincipalType::Room,
+            _ => PrincipalType::Unknown,
+        }
+    }
+}
+
 impl ValueSerialize for PrincipalType {
     fn serialize(&self) -> String {
         match self {
@@ -45,6 +75,32 @@ pub struct AppToken {
     pub created_at: Option<DateTime<Utc>>,
 }
 
+impl AppToken {
+    /// Creates an app token with a new id, only the hash of the token is stored
+    pub fn new(name: String, token: &str) -> Result<Self, crate::Error> {
+        let salt = SaltString::generate(OsRng);
+        let token_hash = pbkdf2::Pbkdf2
+            .hash_password_customized(
+                token.as_bytes(),
+                None,
+                None,
+                Params {
+                    rounds: 1000,
+                    ..Default::default()
+                },
+                &salt,
+            )
+            .map_err(|_| crate::Error::PasswordHash)?
+            .to_string();
+        Ok(Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            name,
+            token: token_hash.into(),
+            created_at: Some(Utc::now()),
+        })
+    }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
 // TODO: Rename this to Principal
@@ -58,6 +114,9 @@ pub struct User {
     pub app_tokens: Vec<AppToken>,
     #[serde(default)]
     pub memberships: Vec<String>,
+    /// Email addresses of the principal, the first one is the primary address
+    #[serde(default)]
+    pub emails: Vec<String>,
 }
 
 impl User {
@@ -73,12 +132,81 @@ impl User {
             .any(|membership| membership == principal)
     }
 
+    /// The mailto: calendar user addresses (RFC 6638 2.4.1), primary address first
+    pub fn calendar_user_addresses(&self) -> Vec<String> {
+        self.emails
+            .iter()
+            .map(|email| format!("mailto:{email}"))
+            .collect()
+    }
+
+    /// Whether a calendar user address (e.g. from ORGANIZER or ATTENDEE) belongs to the principal
+    pub fn has_calendar_user_address(&self, address: &str) -> bool {
+        let email = match address.trim().split_once(':') {
+            Some((scheme, email)) if scheme.eq_ignore_ascii_case("mailto") => email,
+            _ => return false,
+        };
+        self.emails
+            .iter()
+            .any(|own_email| own_email.eq_ignore_ascii_case(email))
+    }
+
     /// Returns all principals the user implements
     pub fn memberships(&self) -> Vec<&str> {
         let mut memberships: Vec<_> = self.memberships.iter().map(String::as_ref).collect();
         memberships.push(self.id.as_str());
         memberships
     }
+
+    pub fn verify_password(&self, password: &str) -> bool {
+        self.password.as_ref().is_some_and(|password_hash| {
+            password_auth::verify_password(password, password_hash.as_ref()).is_ok()
+        })
+    }
+
+    pub fn verify_app_token(&self, token: &str) -> bool {
+        self.app_tokens.iter().any(|app_token| {
+            password_auth::verify_password(token, app_token.token.as_ref()).is_ok()
+        })
+    }
+}
+
+/// Validates email addresses and brings them into the form they are stored in:
+/// without a mailto: prefix, with a lowercase domain and without duplicates.
+/// Blank entries are skipped.
+pub fn normalize_emails<'a>(
+    emails: impl IntoIterator<Item = &'a str>,
+) -> Result<Vec<String>, crate::Error> {
+    let mut normalized: Vec<String> = vec![];
+    for email in emails {
+        let email = email.trim();
+        if email.is_empty() {
+            continue;
+        }
+        let address = match email.split_once(':') {
+            Some((scheme, address)) if scheme.eq_ignore_ascii_case("mailto") => address,
+            _ => email,
+        };
+        // The local part is case-sensitive (RFC 5321 2.4), the domain isn't
+        let (local, domain) = address
+            .rsplit_once('@')
+            .filter(|(local, domain)| {
+                !local.is_empty()
+                    && !local.contains('@')
+                    && !domain.is_empty()
+                    && !domain.starts_with('.')
+                    && !domain.ends_with('.')
+                    && !address
+                        .chars()
+                        .any(|c| c.is_whitespace() || c.is_control() || "<>,;:\"".contains(c))
+            })
+            .ok_or_else(|| crate::Error::InvalidData(format!("Invalid email address: {email}")))?;
+        let address = format!("{local}@{}", domain.to_lowercase());
+        if !normalized.contains(&address) {
+            normalized.push(address);
+        }
+    }
+    Ok(normalized)
 }
 
 impl rustical_dav::Principal for User {
@@ -120,3 +248,57 @@ impl FromRequest for User {
         )
     }
 }
+
+#[test]
+fn test_calendar_user_addresses() {
+    let user = User {
+        id: "alice".to_owned(),
+        displayname: None,
+        principal_type: PrincipalType::Individual,
+        password: None,
+        app_tokens: vec![],
+        memberships: vec![],
+        emails: vec!["alice@example.com".to_owned(), "a@example.org".to_owned()],
+    };
+    assert_eq!(
+        user.calendar_user_addresses(),
+        vec!["mailto:alice@example.com", "mailto:a@example.org"]
+    );
+    assert!(user.has_calendar_user_address("MAILTO:Alice@Example.com"));
+    assert!(user.has_calendar_user_address("mailto:a@example.org"));
+    assert!(!user.has_calendar_user_address("mailto:bob@example.com"));
+    assert!(!user.has_calendar_user_address("alice@example.com"));
+}
+
+#[test]
+fn test_normalize_emails() {
+    assert_eq!(
+        normalize_emails([
+            " Alice@Example.COM ",
+            "",
+            "MAILTO:a@example.org",
+            "Alice@example.com",
+        ])
+        .unwrap(),
+        vec!["Alice@example.com", "a@example.org"]
+    );
+    for invalid in [
+        "alice",
+        "@example.com",
+        "alice@",
+        "alice@bob@example.com",
+        "alice@example.com.",
+        "alice smith@example.com",
+        "Alice <alice@example.com>",
+        "alice@example.com,bob@example.com",
+        "tel:+1234",
+    ] {
+        assert!(
+            matches!(
+                normalize_emails([invalid]),
+                Err(crate::Error::InvalidData(_))
+            ),
+            "{invalid}"
+        );
+    }
+}
@@ -1,6 +1,7 @@
-use super::CalendarObjectType;
-use crate::synctoken::format_synctoken;
+use super::{CalTimezone, CalendarObjectType, VTimezone, iana_timezone, parse_vcalendar_timezone};
+use crate::{Error, synctoken::format_synctoken};
 use chrono::NaiveDateTime;
+use chrono_tz::Tz;
 use serde::Serialize;
 
 #[derive(Debug, Default, Clone, Serialize)]
@@ -13,6 +14,8 @@ pub struct Calendar {
     pub color: Option<String>,
     pub timezone: Option<String>,
     pub timezone_id: Option<String>,
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
     pub deleted_at: Option<NaiveDateTime>,
     pub synctoken: i64,
     pub subscription_url: Option<String>,
@@ -24,4 +27,175 @@ impl Calendar {
     pub fn format_synctoken(&self) -> String {
         format_synctoken(self.synctoken)
     }
+
+    /// Sets calendar-timezone-id together with a calendar-timezone generated from the IANA
+    /// database (RFC 7809 Section 5.2)
+    pub fn set_timezone_id(&mut self, timezone_id: Option<String>) -> Result<(), Error> {
+        self.timezone = match &timezone_id {
+            Some(tzid) => {
+                let tz: Tz = tzid
+                    .parse()
+                    .map_err(|_| Error::InvalidData(format!("Invalid timezone-id: {tzid}")))?;
+                Some(VTimezone::from_tz(tz).to_vcalendar())
+            }
+            None => None,
+        };
+        self.timezone_id = timezone_id;
+        Ok(())
+    }
+
+    /// Sets calendar-timezone, calendar-timezone-id is derived from the TZID or X-LIC-LOCATION
+    /// of its VTIMEZONE and unset for custom timezones
+    pub fn set_timezone(&mut self, timezone: Option<String>) -> Result<(), Error> {
+        self.timezone_id = match &timezone {
+            Some(timezone) => {
+                iana_timezone(&parse_vcalendar_timezone(timezone)?).map(|tz| tz.name().to_owned())
+            }
+            None => None,
+        };
+        self.timezone = timezone;
+        Ok(())
+    }
+
+    /// The timezone floating times in this calendar are interpreted in
+    pub fn get_timezone(&self) -> Option<CalTimezone> {
+        if let Some(tz) = self
+            .timezone_id
+            .as_ref()
+            .and_then(|tzid| tzid.parse::<Tz>().ok())
+        {
+            return Some(CalTimezone::Olson(tz));
+        }
+        CalTimezone::from_ical(&parse_vcalendar_timezone(self.timezone.as_ref()?).ok()?).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Calendar;
+    use crate::calendar::{CalDateTime, CalTimezone};
+    use chrono::NaiveDateTime;
+
+    const BERLIN_OUTLOOK: &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:-//rustical//test//EN\r
+BEGIN:VTIMEZONE\r
+TZID:W. Europe Standard Time\r
+BEGIN:STANDARD\r
+DTSTART:16011028T030000\r
+RRULE:FREQ=YEARLY;BYDAY=-1SU;BYMONTH=10\r
+TZOFFSETFROM:+0200\r
+TZOFFSETTO:+0100\r
+END:STANDARD\r
+BEGIN:DAYLIGHT\r
+DTSTART:16010325T020000\r
+RRULE:FREQ=YEARLY;BYDAY=-1SU;BYMONTH=3\r
+TZOFFSETFROM:+0100\r
+TZOFFSETTO:+0200\r
+END:DAYLIGHT\r
+END:VTIMEZONE\r
+END:VCALENDAR\r
+";
+
+    const BERLIN_THUNDERBIRD: &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:-//rustical//test//EN\r
+BEGIN:VTIMEZONE\r
+TZID:/mozilla.org/20050126_1/Europe/Berlin\r
+X-LIC-LOCATION:Europe/Berlin\r
+BEGIN:DAYLIGHT\r
+TZOFFSETFROM:+0100\r
+TZOFFSETTO:+0200\r
+DTSTART:19700329T020000\r
+RRULE:FREQ=YEARLY;BYDAY=-1SU;BYMONTH=3\r
+END:DAYLIGHT\r
+BEGIN:STANDARD\r
+TZOFFSETFROM:+0200\r
+TZOFFSETTO:+0100\r
+DTSTART:19701025T030000\r
+RRULE:FREQ=YEARLY;BYDAY=-1SU;BYMONTH=10\r
+END:STANDARD\r
+END:VTIMEZONE\r
+END:VCALENDAR\r
+";
+
+    fn datetime(value: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").unwrap()
+    }
+
+    #[test]
+    fn test_calendar_timezone() {
+        let mut calendar = Calendar::default();
+        calendar
+            .set_timezone_id(Some("Europe/Berlin".to_owned()))
+            .unwrap();
+        assert!(
+            calendar
+                .timezone
+                .as_ref()
+                .unwrap()
+                .contains("TZID:Europe/Berlin\r\n")
+        );
+        assert!(
+            calendar
+                .set_timezone_id(Some("Mars/Olympus".to_owned()))
+                .is_err()
+        );
+
+        // The id is derived from X-LIC-LOCATION or TZID
+        calendar
+            .set_timezone(Some(BERLIN_THUNDERBIRD.to_owned()))
+            .unwrap();
+        assert_eq!(calendar.timezone_id.as_deref(), Some("Europe/Berlin"));
+        calendar
+            .set_timezone(Some(BERLIN_OUTLOOK.to_owned()))
+            .unwrap();
+        assert_eq!(calendar.timezone_id, None);
+        assert!(matches!(
+            calendar.get_timezone(),
+            Some(CalTimezone::Custom(_))
+        ));
+        assert!(
+            calendar
+                .set_timezone(Some("BEGIN:VCALENDAR".to_owned()))
+                .is_err()
+        );
+
+        calendar.set_timezone(None).unwrap();
+        assert_eq!(calendar.timezone, None);
+        assert!(calendar.get_timezone().is_none());
+    }
+
+    #[test]
+    fn test_calendar_timezone_floating() {
+        // Floating times are interpreted in the calendar timezone
+        let mut calendar = Calendar::default();
+        let floating = CalDateTime::Local(datetime("20240701T100000"));
+        assert_eq!(
+            floating
+                .utc_in(calendar.get_timezone().as_ref())
+                .naive_utc(),
+            datetime("20240701T100000")
+        );
+        calendar
+            .set_timezone_id(Some("America/New_York".to_owned()))
+            .unwrap();
+        assert_eq!(
+            floating
+                .utc_in(calendar.get_timezone().as_ref())
+                .naive_utc(),
+            datetime("20240701T140000")
+        );
+
+        calendar
+            .set_timezone(Some(BERLIN_OUTLOOK.to_owned()))
+            .unwrap();
+        let custom = calendar.get_timezone();
+        assert_eq!(
+            floating.utc_in(custom.as_ref()).naive_utc(),
+            datetime("20240701T080000")
+        );
+        let utc = CalDateTime::Utc(datetime("20240701T100000").and_utc());
+        assert_eq!(utc.utc_in(custom.as_ref()), utc.utc());
+    }
 }
@@ -1,17 +1,13 @@
-use super::{parse_duration, CalDateTime};
+use super::{CalDateTime, CalTimezone, parse_duration};
 use crate::Error;
 use chrono::Duration;
-use ical::{
-    generator::IcalEvent,
-    parser::{ical::component::IcalTimeZone, Component},
-    property::Property,
-};
+use ical::{generator::IcalEvent, parser::Component, property::Property};
 use std::collections::HashMap;
 
 #[derive(Debug, Clone)]
 pub struct EventObject {
     pub(crate) event: IcalEvent,
-    pub(crate) timezones: HashMap<String, IcalTimeZone>,
+    pub(crate) timezones: HashMap<String, CalTimezone>,
 }
 
 impl EventObject {
@@ -1,8 +1,10 @@
+#[allow(clippy::module_inception)]
 mod calendar;
 mod event;
 mod journal;
 mod object;
 mod timestamp;
+mod timezone;
 mod todo;
 
 pub use calendar::*;
@@ -10,4 +12,5 @@ pub use event::*;
 pub use journal::*;
 pub use object::*;
 pub use timestamp::*;
+pub use timezone::*;
 pub use todo::*;
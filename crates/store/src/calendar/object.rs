@@ -1,11 +1,34 @@
-use super::{CalDateTime, EventObject, JournalObject, TodoObject};
+use super::{
+    CalDateTime, CalTimezone, EventObject, JournalObject, TodoObject, add_iana_timezones,
+    references_iana_timezones, remove_iana_timezones,
+};
 use crate::Error;
-use ical::parser::{Component, ical::component::IcalTimeZone};
-use serde::Serialize;
+use chrono::NaiveDateTime;
+use ical::parser::Component;
+use lazy_static::lazy_static;
+use lru::LruCache;
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-use std::{collections::HashMap, io::BufReader};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    io::BufReader,
+    num::NonZeroUsize,
+    sync::{Arc, Mutex, OnceLock},
+};
 
-#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+/// Number of parsed objects kept in memory across requests
+const PARSED_OBJECT_CACHE_SIZE: usize = 1024;
+
+lazy_static! {
+    /// Parsed components keyed by the etag of their object.
+    /// Since the etag is a hash of the object id and content an entry can never become stale.
+    static ref PARSED_OBJECTS: Mutex<LruCache<String, Arc<CalendarObjectComponent>>> = Mutex::new(
+        LruCache::new(NonZeroUsize::new(PARSED_OBJECT_CACHE_SIZE).unwrap())
+    );
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 // specified in https://datatracker.ietf.org/doc/html/rfc5545#section-3.6
 pub enum CalendarObjectType {
     Event = 0,
@@ -23,6 +46,19 @@ impl CalendarObjectType {
     }
 }
 
+impl TryFrom<i64> for CalendarObjectType {
+    type Error = Error;
+
+    fn try_from(value: i64) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Event),
+            1 => Ok(Self::Todo),
+            2 => Ok(Self::Journal),
+            _ => Err(Error::InvalidData(format!("Invalid object type {value}"))),
+        }
+    }
+}
+
 impl rustical_xml::ValueSerialize for CalendarObjectType {
     fn serialize(&self) -> String {
         self.as_str().to_owned()
@@ -52,17 +88,10 @@ pub enum CalendarObjectComponent {
     Journal(JournalObject),
 }
 
-#[derive(Debug, Clone)]
-pub struct CalendarObject {
-    id: String,
-    ics: String,
-    data: CalendarObjectComponent,
-}
-
-impl CalendarObject {
-    pub fn from_ics(object_id: String, ics: String) -> Result<Self, Error> {
+impl CalendarObjectComponent {
+    fn parse(ics: &str) -> Result<Self, Error> {
         let mut parser = ical::IcalParser::new(BufReader::new(ics.as_bytes()));
-        let cal = parser.next().ok_or(Error::NotFound)??;
+        let mut cal = parser.next().ok_or(Error::NotFound)??;
         if parser.next().is_some() {
             return Err(Error::InvalidData(
                 "multiple calendars, only one allowed".to_owned(),
@@ -81,42 +110,23 @@ impl CalendarObject {
             ));
         }
 
-        let timezones: HashMap<String, IcalTimeZone> = cal
-            .timezones
-            .clone()
-            .into_iter()
-            .filter_map(|timezone| {
-                let timezone_prop = timezone.get_property("TZID")?.to_owned();
-                let tzid = timezone_prop.value?;
-                Some((tzid, timezone))
-            })
-            .collect();
-
-        if let Some(event) = cal.events.first() {
-            return Ok(CalendarObject {
-                id: object_id,
-                ics,
-                data: CalendarObjectComponent::Event(EventObject {
-                    event: event.clone(),
-                    timezones,
-                }),
-            });
-        }
-        if let Some(todo) = cal.todos.first() {
-            return Ok(CalendarObject {
-                id: object_id,
-                ics,
-                data: CalendarObjectComponent::Todo(TodoObject { todo: todo.clone() }),
-            });
-        }
-        if let Some(journal) = cal.journals.first() {
-            return Ok(CalendarObject {
-                id: object_id,
-                ics,
-                data: CalendarObjectComponent::Journal(JournalObject {
-                    journal: journal.clone(),
-                }),
-            });
+        if let Some(event) = cal.events.pop() {
+            let timezones: HashMap<String, CalTimezone> = cal
+                .timezones
+                .into_iter()
+                .filter_map(|timezone| {
+                    let timezone_prop = timezone.get_property("TZID")?.to_owned();
+                    let tzid = timezone_prop.value?;
+                    Some((tzid, CalTimezone::from_ical(&timezone).ok()?))
+                })
+                .collect();
+            return Ok(Self::Event(EventObject { event, timezones }));
+        }
+        if let Some(todo) = cal.todos.pop() {
+            return Ok(Self::Todo(TodoObject { todo }));
+        }
+        if let Some(journal) = cal.journals.pop() {
+            return Ok(Self::Journal(JournalObject { journal }));
         }
 
         Err(Error::InvalidData(
@@ -124,47 +134,390 @@ impl CalendarObject {
         ))
     }
 
+    fn get_object_type(&self) -> CalendarObjectType {
+        match self {
+            Self::Todo(_) => CalendarObjectType::Todo,
+            Self::Event(_) => CalendarObjectType::Event,
+            Self::Journal(_) => CalendarObjectType::Journal,
+        }
+    }
+}
+
+/// An iCalendar object, the component is only parsed once it's needed
+#[derive(Debug, Clone)]
+pub struct CalendarObject {
+    id: String,
+    ics: String,
+    etag: String,
+    object_type: CalendarObjectType,
+    data: OnceLock<Arc<CalendarObjectComponent>>,
+    /// First and last occurence as stored, avoids parsing for time-range queries
+    occurences: Option<(Option<CalDateTime>, Option<CalDateTime>)>,
+    /// Whether the ics references IANA timezones without their VTIMEZONE, as stored or
+    /// computed once needed
+    references_timezones: OnceLock<bool>,
+    created_at: Option<NaiveDateTime>,
+    updated_at: Option<NaiveDateTime>,
+}
+
+impl CalendarObject {
+    /// Parses and validates an object
+    pub fn from_ics(object_id: String, ics: String) -> Result<Self, Error> {
+        let etag = Self::compute_etag(&object_id, &ics);
+        let data = Self::parse_cached(&etag, &ics)?;
+        Ok(Self {
+            id: object_id,
+            ics,
+            etag,
+            object_type: data.get_object_type(),
+            data: OnceLock::from(data),
+            occurences: None,
+            references_timezones: OnceLock::new(),
+            created_at: None,
+            updated_at: None,
+        })
+    }
+
+    /// Constructs an object from stored metadata without parsing it.
+    /// The ics must have been validated by [`CalendarObject::from_ics`] before.
+    pub fn from_stored(
+        object_id: String,
+        ics: String,
+        etag: Option<String>,
+        object_type: CalendarObjectType,
+    ) -> Self {
+        let etag = etag.unwrap_or_else(|| Self::compute_etag(&object_id, &ics));
+        Self {
+            id: object_id,
+            ics,
+            etag,
+            object_type,
+            data: OnceLock::new(),
+            occurences: None,
+            references_timezones: OnceLock::new(),
+            created_at: None,
+            updated_at: None,
+        }
+    }
+
+    /// Constructs an object that only carries its metadata, e.g. to answer a PROPFIND for
+    /// getetag without reading the content. Its ics is empty and it has no occurences.
+    /// Without [`CalendarObject::with_references_timezones`] it references no timezones.
+    pub fn from_metadata(object_id: String, etag: String, object_type: CalendarObjectType) -> Self {
+        Self {
+            occurences: Some((None, None)),
+            ..Self::from_stored(object_id, String::new(), Some(etag), object_type)
+        }
+    }
+
+    /// Attaches the timestamps tracked by the store
+    pub fn with_timestamps(
+        mut self,
+        created_at: Option<NaiveDateTime>,
+        updated_at: Option<NaiveDateTime>,
+    ) -> Self {
+        self.created_at = created_at;
+        self.updated_at = updated_at;
+        self
+    }
+
+    /// Attaches the occurence bounds stored by [`CalendarObject::format_occurences`],
+    /// invalid or missing bounds are computed from the ics once needed
+    pub fn with_occurences(mut self, occurences: Option<&str>) -> Self {
+        let parse_bound = |bound: &str| match bound {
+            "" => Ok(None),
+            bound => CalDateTime::parse(bound, None).map(Some),
+        };
+        self.occurences = occurences
+            .and_then(|occurences| occurences.split_once('/'))
+            .and_then(|(first, last)| Some((parse_bound(first).ok()?, parse_bound(last).ok()?)));
+        self
+    }
+
+    /// Attaches whether the ics references timezones as stored from
+    /// [`CalendarObject::references_timezones`], it's computed from the ics if unknown
+    pub fn with_references_timezones(self, references_timezones: Option<bool>) -> Self {
+        if let Some(references_timezones) = references_timezones {
+            let _ = self.references_timezones.set(references_timezones);
+        }
+        self
+    }
+
+    /// The first and last occurence as `first/last` for the store, either may be empty.
+    /// Zoned times are converted to UTC so that they can be read without their VTIMEZONE,
+    /// floating times and dates stay floating.
+    pub fn format_occurences(&self) -> Result<String, Error> {
+        let format_bound = |bound: Option<CalDateTime>| match bound {
+            Some(datetime @ (CalDateTime::OlsonTZ(_) | CalDateTime::Offset(_))) => {
+                CalDateTime::Utc(datetime.utc()).format()
+            }
+            Some(datetime) => datetime.format(),
+            None => String::new(),
+        };
+        Ok(format!(
+            "{}/{}",
+            format_bound(self.get_first_occurence()?),
+            format_bound(self.get_last_occurence()?)
+        ))
+    }
+
+    fn compute_etag(object_id: &str, ics: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(object_id);
+        hasher.update(ics);
+        format!("{:x}", hasher.finalize())
+    }
+
+    /// Only successfully parsed objects end up in the cache
+    fn parse_cached(etag: &str, ics: &str) -> Result<Arc<CalendarObjectComponent>, Error> {
+        if let Some(data) = PARSED_OBJECTS.lock().unwrap().get(etag) {
+            return Ok(data.clone());
+        }
+        let data = Arc::new(CalendarObjectComponent::parse(ics)?);
+        PARSED_OBJECTS
+            .lock()
+            .unwrap()
+            .put(etag.to_owned(), data.clone());
+        Ok(data)
+    }
+
+    fn get_data(&self) -> Result<&CalendarObjectComponent, Error> {
+        if let Some(data) = self.data.get() {
+            return Ok(data);
+        }
+        let data = Self::parse_cached(&self.etag, &self.ics)?;
+        Ok(self.data.get_or_init(|| data))
+    }
+
     pub fn get_id(&self) -> &str {
         &self.id
     }
+    /// Opaque tag without quotes, it changes whenever the id or content changes
     pub fn get_etag(&self) -> String {
-        let mut hasher = Sha256::new();
-        hasher.update(&self.id);
-        hasher.update(self.get_ics());
-        format!("{:x}", hasher.finalize())
+        self.etag.to_owned()
     }
 
     pub fn get_ics(&self) -> &str {
         &self.ics
     }
 
-    pub fn get_component_name(&self) -> &str {
-        match self.data {
-            CalendarObjectComponent::Todo(_) => "VTODO",
-            CalendarObjectComponent::Event(_) => "VEVENT",
-            CalendarObjectComponent::Journal(_) => "VJOURNAL",
+    /// The ics including VTIMEZONEs for timezones that are only referenced by their IANA name,
+    /// for clients that don't support timezones by reference (RFC 7809)
+    pub fn get_ics_with_timezones(&self) -> Cow<'_, str> {
+        if !self.references_timezones() {
+            return Cow::Borrowed(&self.ics);
+        }
+        match add_iana_timezones(&self.ics) {
+            Some(ics) => Cow::Owned(ics),
+            None => Cow::Borrowed(&self.ics),
         }
     }
 
-    pub fn get_object_type(&self) -> CalendarObjectType {
-        match self.data {
-            CalendarObjectComponent::Todo(_) => CalendarObjectType::Todo,
-            CalendarObjectComponent::Event(_) => CalendarObjectType::Event,
-            CalendarObjectComponent::Journal(_) => CalendarObjectType::Journal,
+    /// Whether the ics references IANA timezones without their VTIMEZONE,
+    /// so that [`CalendarObject::get_ics_with_timezones`] differs from it
+    pub fn references_timezones(&self) -> bool {
+        *self
+            .references_timezones
+            .get_or_init(|| references_iana_timezones(&self.ics))
+    }
+
+    /// Opaque tag of [`CalendarObject::get_ics_with_timezones`], it only differs from
+    /// [`CalendarObject::get_etag`] if the ics references timezones
+    pub fn get_etag_with_timezones(&self) -> String {
+        if self.references_timezones() {
+            format!("{}-tz", self.etag)
+        } else {
+            self.get_etag()
+        }
+    }
+
+    /// Replaces VTIMEZONEs that are equivalent to an IANA timezone by a reference to it
+    /// (RFC 7809), the content and etag only change if there are any
+    pub fn with_timezones_by_reference(self) -> Result<Self, Error> {
+        match remove_iana_timezones(&self.ics) {
+            Some(ics) => Self::from_ics(self.id, ics),
+            None => Ok(self),
         }
     }
 
+    /// Only known for objects read from a store
+    pub fn get_created_at(&self) -> Option<NaiveDateTime> {
+        self.created_at
+    }
+
+    /// Only known for objects read from a store
+    pub fn get_updated_at(&self) -> Option<NaiveDateTime> {
+        self.updated_at
+    }
+
+    pub fn get_component_name(&self) -> &str {
+        self.object_type.as_str()
+    }
+
+    pub fn get_object_type(&self) -> CalendarObjectType {
+        self.object_type.clone()
+    }
+
     pub fn get_first_occurence(&self) -> Result<Option<CalDateTime>, Error> {
-        match &self.data {
+        if self.object_type != CalendarObjectType::Event {
+            return Ok(None);
+        }
+        if let Some((first_occurence, _)) = &self.occurences {
+            return Ok(first_occurence.clone());
+        }
+        match self.get_data()? {
             CalendarObjectComponent::Event(event) => event.get_first_occurence(),
             _ => Ok(None),
         }
     }
 
     pub fn get_last_occurence(&self) -> Result<Option<CalDateTime>, Error> {
-        match &self.data {
+        if self.object_type != CalendarObjectType::Event {
+            return Ok(None);
+        }
+        if let Some((_, last_occurence)) = &self.occurences {
+            return Ok(last_occurence.clone());
+        }
+        match self.get_data()? {
             CalendarObjectComponent::Event(event) => event.get_last_occurence(),
             _ => Ok(None),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{CalendarObject, CalendarObjectType};
+
+    const EVENT: &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:-//rustical//test//EN\r
+BEGIN:VEVENT\r
+UID:lazy\r
+DTSTAMP:20240101T000000Z\r
+DTSTART:20240101T100000Z\r
+DTEND:20240101T110000Z\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+
+    #[test]
+    fn test_from_stored() {
+        let parsed = CalendarObject::from_ics("lazy".to_owned(), EVENT.to_owned()).unwrap();
+        let stored = CalendarObject::from_stored(
+            "lazy".to_owned(),
+            EVENT.to_owned(),
+            None,
+            CalendarObjectType::Event,
+        );
+        assert_eq!(stored.get_etag(), parsed.get_etag());
+        assert_eq!(stored.get_component_name(), "VEVENT");
+        assert_eq!(
+            stored.get_first_occurence().unwrap(),
+            parsed.get_first_occurence().unwrap()
+        );
+        assert_eq!(
+            stored.get_last_occurence().unwrap(),
+            parsed.get_last_occurence().unwrap()
+        );
+
+        // Nothing is parsed as long as the metadata is sufficient
+        let todo = CalendarObject::from_stored(
+            "todo".to_owned(),
+            "not an iCalendar object".to_owned(),
+            Some("etag".to_owned()),
+            CalendarObjectType::Todo,
+        );
+        assert_eq!(todo.get_etag(), "etag");
+        assert_eq!(todo.get_first_occurence().unwrap(), None);
+
+        let invalid = CalendarObject::from_stored(
+            "invalid".to_owned(),
+            "not an iCalendar object".to_owned(),
+            None,
+            CalendarObjectType::Event,
+        );
+        assert!(invalid.get_first_occurence().is_err());
+    }
+
+    #[test]
+    fn test_stored_occurences() {
+        let parsed = CalendarObject::from_ics("lazy".to_owned(), EVENT.to_owned()).unwrap();
+        let occurences = parsed.format_occurences().unwrap();
+        assert_eq!(occurences, "20240101T100000Z/20240101T110000Z");
+
+        // The stored bounds are used without parsing
+        let stored = CalendarObject::from_stored(
+            "lazy".to_owned(),
+            "not an iCalendar object".to_owned(),
+            None,
+            CalendarObjectType::Event,
+        )
+        .with_occurences(Some(&occurences));
+        assert_eq!(
+            stored.get_first_occurence().unwrap(),
+            parsed.get_first_occurence().unwrap()
+        );
+        assert_eq!(
+            stored.get_last_occurence().unwrap(),
+            parsed.get_last_occurence().unwrap()
+        );
+
+        let floating = CalendarObject::from_stored(
+            "lazy".to_owned(),
+            "not an iCalendar object".to_owned(),
+            None,
+            CalendarObjectType::Event,
+        )
+        .with_occurences(Some("20240102T050000/"));
+        assert_eq!(
+            floating.get_first_occurence().unwrap().unwrap().format(),
+            "20240102T050000"
+        );
+        assert_eq!(floating.get_last_occurence().unwrap(), None);
+
+        // Without stored bounds the ics is parsed
+        let unknown = CalendarObject::from_stored(
+            "lazy".to_owned(),
+            "not an iCalendar object".to_owned(),
+            None,
+            CalendarObjectType::Event,
+        )
+        .with_occurences(None);
+        assert!(unknown.get_first_occurence().is_err());
+    }
+
+    #[test]
+    fn test_references_timezones() {
+        let zoned = EVENT.replace(
+            "DTSTART:20240101T100000Z",
+            "DTSTART;TZID=Europe/Berlin:20240101T100000",
+        );
+        let object = CalendarObject::from_ics("lazy".to_owned(), zoned.clone()).unwrap();
+        assert!(object.references_timezones());
+        assert!(object.get_ics_with_timezones().contains("BEGIN:VTIMEZONE"));
+        // The calendar data with VTIMEZONEs is a different representation with its own etag
+        assert_ne!(object.get_etag_with_timezones(), object.get_etag());
+
+        let utc = CalendarObject::from_ics("lazy".to_owned(), EVENT.to_owned()).unwrap();
+        assert!(!utc.references_timezones());
+        assert_eq!(utc.get_ics_with_timezones(), EVENT);
+        assert_eq!(utc.get_etag_with_timezones(), utc.get_etag());
+
+        // Objects without content need the stored flag
+        let metadata = CalendarObject::from_metadata(
+            "lazy".to_owned(),
+            object.get_etag(),
+            CalendarObjectType::Event,
+        )
+        .with_references_timezones(Some(true));
+        assert_eq!(
+            metadata.get_etag_with_timezones(),
+            object.get_etag_with_timezones()
+        );
+        let stored =
+            CalendarObject::from_stored("lazy".to_owned(), zoned, None, CalendarObjectType::Event)
+                .with_references_timezones(None);
+        assert!(stored.references_timezones());
+    }
+}
@@ -1,11 +1,9 @@
+use super::CalTimezone;
 use crate::Error;
-use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use chrono::{DateTime, Duration, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
 use chrono_tz::Tz;
 use derive_more::derive::Deref;
-use ical::{
-    parser::{ical::component::IcalTimeZone, Component},
-    property::Property,
-};
+use ical::property::Property;
 use lazy_static::lazy_static;
 use rustical_xml::{ValueDeserialize, ValueSerialize};
 use std::{collections::HashMap, ops::Add};
@@ -13,8 +11,9 @@ use std::{collections::HashMap, ops::Add};
 lazy_static! {
     static ref RE_DURATION: regex::Regex = regex::Regex::new(r"^(?<sign>[+-])?P((?P<W>\d+)W)?((?P<D>\d+)D)?(T((?P<H>\d+)H)?((?P<M>\d+)M)?((?P<S>\d+)S)?)?$").unwrap();
 
-    static ref RE_VCARD_DATE_MM_DD: regex::Regex =
-        regex::Regex::new(r"^--(?<m>\d{2})(?<d>\d{2})$").unwrap();
+    // vCard 4 allows to omit the year (--MMDD), vCard 3 clients sometimes send --MM-DD
+    pub(crate) static ref RE_VCARD_DATE_MM_DD: regex::Regex =
+        regex::Regex::new(r"^--(?<m>\d{2})-?(?<d>\d{2})$").unwrap();
 }
 
 const LOCAL_DATE_TIME: &str = "%Y%m%dT%H%M%S";
@@ -53,6 +52,8 @@ pub enum CalDateTime {
     // Form 3, example: TZID=America/New_York:19980119T020000
     // https://en.wikipedia.org/wiki/Tz_database
     OlsonTZ(DateTime<Tz>),
+    // Form 3 with a custom VTIMEZONE, the offset is resolved from its observances
+    Offset(DateTime<FixedOffset>),
     Date(NaiveDate),
 }
 
@@ -64,6 +65,7 @@ impl Add<Duration> for CalDateTime {
             Self::Local(datetime) => Self::Local(datetime + duration),
             Self::Utc(datetime) => Self::Utc(datetime + duration),
             Self::OlsonTZ(datetime) => Self::OlsonTZ(datetime + duration),
+            Self::Offset(datetime) => Self::Offset(datetime + duration),
             Self::Date(date) => Self::Local(date.and_time(NaiveTime::default()) + duration),
         }
     }
@@ -72,7 +74,7 @@ impl Add<Duration> for CalDateTime {
 impl CalDateTime {
     pub fn parse_prop(
         prop: &Property,
-        timezones: &HashMap<String, IcalTimeZone>,
+        timezones: &HashMap<String, CalTimezone>,
     ) -> Result<Option<Self>, Error> {
         let prop_value = if let Some(value) = &prop.value {
             value.to_owned()
@@ -92,31 +94,11 @@ impl CalDateTime {
             .unwrap_or_default()
         {
             if let Some(timezone) = timezones.get(tzid.to_owned()) {
-                // X-LIC-LOCATION is often used to refer to a standardised timezone from the Olson
-                // database
-                if let Some(olson_name) = timezone
-                    .get_property("X-LIC-LOCATION")
-                    .map(|prop| prop.value.to_owned())
-                    .unwrap_or_default()
-                {
-                    if let Ok(tz) = olson_name.parse::<Tz>() {
-                        Some(tz)
-                    } else {
-                        return Err(Error::InvalidData(format!(
-                            "Timezone has X-LIC-LOCATION property to specify a timezone from the Olson database, however it's value {olson_name} is invalid"
-                        )));
-                    }
-                } else {
-                    // If the TZID matches a name from the Olson database (e.g. Europe/Berlin) we
-                    // guess that we can just use it
-                    if let Ok(tz) = tzid.parse::<Tz>() {
-                        Some(tz)
-                    } else {
-                        // TODO: Too bad, we need to manually parse it
-                        // For now it's just treated as localtime
-                        None
-                    }
-                }
+                Some(timezone.to_owned())
+            } else if let Ok(tz) = tzid.parse::<Tz>() {
+                // Timezone by reference (RFC 7809), the VTIMEZONE may be omitted for timezones
+                // from the Olson database
+                Some(CalTimezone::Olson(tz))
             } else {
                 // TZID refers to timezone that does not exist
                 return Err(Error::InvalidData(format!(
@@ -129,7 +111,16 @@ impl CalDateTime {
             None
         };
 
-        Self::parse(&prop_value, timezone).map(Some)
+        match timezone {
+            Some(CalTimezone::Olson(tz)) => Self::parse(&prop_value, Some(tz)).map(Some),
+            Some(CalTimezone::Custom(timezone)) => {
+                Ok(Some(match Self::parse(&prop_value, None)? {
+                    Self::Local(datetime) => Self::Offset(timezone.localize(datetime)),
+                    other => other,
+                }))
+            }
+            None => Self::parse(&prop_value, None).map(Some),
+        }
     }
 
     pub fn format(&self) -> String {
@@ -138,6 +129,7 @@ impl CalDateTime {
             Self::Date(date) => date.format(LOCAL_DATE).to_string(),
             Self::Local(datetime) => datetime.format(LOCAL_DATE_TIME).to_string(),
             Self::OlsonTZ(datetime) => datetime.format(LOCAL_DATE_TIME).to_string(),
+            Self::Offset(datetime) => datetime.format(LOCAL_DATE_TIME).to_string(),
         }
     }
 
@@ -147,6 +139,7 @@ impl CalDateTime {
             Self::Date(date) => date.to_owned(),
             Self::Local(datetime) => datetime.date(),
             Self::OlsonTZ(datetime) => datetime.date_naive(),
+            Self::Offset(datetime) => datetime.date_naive(),
         }
     }
 
@@ -195,11 +188,31 @@ impl CalDateTime {
         Err(Error::InvalidData("Invalid datetime format".to_owned()))
     }
 
+    /// Like [`CalDateTime::utc`] but floating times and dates are interpreted in a timezone
+    /// (RFC 4791 Section 9.9)
+    pub fn utc_in(&self, timezone: Option<&CalTimezone>) -> DateTime<Utc> {
+        let local = match self {
+            Self::Local(datetime) => *datetime,
+            Self::Date(date) => date.and_time(NaiveTime::default()),
+            _ => return self.utc(),
+        };
+        match timezone {
+            Some(CalTimezone::Olson(tz)) => tz
+                .from_local_datetime(&local)
+                .earliest()
+                .map(|datetime| datetime.to_utc())
+                .unwrap_or_else(|| self.utc()),
+            Some(CalTimezone::Custom(timezone)) => timezone.localize(local).to_utc(),
+            None => self.utc(),
+        }
+    }
+
     pub fn utc(&self) -> DateTime<Utc> {
         match &self {
             CalDateTime::Local(local_datetime) => local_datetime.and_utc(),
             CalDateTime::Utc(utc_datetime) => utc_datetime.to_owned(),
             CalDateTime::OlsonTZ(datetime) => datetime.to_utc(),
+            CalDateTime::Offset(datetime) => datetime.to_utc(),
             CalDateTime::Date(date) => date.and_time(NaiveTime::default()).and_utc(),
         }
     }
@@ -232,10 +245,10 @@ pub fn parse_duration(string: &str) -> Result<Duration, Error> {
     if let Some(seconds) = captures.name("S") {
         duration += Duration::seconds(seconds.as_str().parse().unwrap());
     }
-    if let Some(sign) = captures.name("sign") {
-        if sign.as_str() == "-" {
-            duration = -duration;
-        }
+    if let Some(sign) = captures.name("sign")
+        && sign.as_str() == "-"
+    {
+        duration = -duration;
     }
 
     Ok(duration)
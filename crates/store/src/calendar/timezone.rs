@@ -0,0 +1,1193 @@
+use crate::Error;
+use chrono::{
+    DateTime, Datelike, Duration, FixedOffset, NaiveDate, NaiveDateTime, Offset, TimeZone,
+    Timelike, Weekday,
+};
+use chrono_tz::{OffsetComponents, OffsetName, Tz};
+use ical::parser::{
+    Component,
+    ical::component::{IcalCalendar, IcalTimeZone, IcalTimeZoneTransitionType},
+};
+use lazy_static::lazy_static;
+use std::{
+    collections::HashMap,
+    io::BufReader,
+    ops::Range,
+    sync::{Arc, Mutex},
+};
+
+/// VTIMEZONEs generated from the IANA database list transitions up to this year,
+/// later ones follow the yearly rules in effect at that time
+const LAST_YEAR: i32 = 2037;
+const FIRST_YEAR: i32 = 1970;
+
+const LOCAL_DATE_TIME: &str = "%Y%m%dT%H%M%S";
+const UTC_DATE_TIME: &str = "%Y%m%dT%H%M%SZ";
+
+lazy_static! {
+    static ref GENERATED_TIMEZONES: Mutex<HashMap<Tz, Arc<VTimezone>>> = Mutex::new(HashMap::new());
+}
+
+/// A timezone referenced by TZID
+#[derive(Debug, Clone, PartialEq)]
+pub enum CalTimezone {
+    /// A timezone from the IANA database, referenced by name or through X-LIC-LOCATION
+    Olson(Tz),
+    /// A timezone defined by the observances of its VTIMEZONE
+    Custom(VTimezone),
+}
+
+impl CalTimezone {
+    /// The observances of a VTIMEZONE take precedence, only if we can't evaluate them the
+    /// timezone is looked up in the IANA database.
+    pub fn from_ical(timezone: &IcalTimeZone) -> Result<Self, Error> {
+        match VTimezone::parse(timezone) {
+            Ok(vtimezone) => Ok(Self::Custom(vtimezone)),
+            Err(err) => iana_timezone(timezone).map(Self::Olson).ok_or(err),
+        }
+    }
+}
+
+/// Day of the month a yearly rule recurs on
+#[derive(Debug, Clone, PartialEq)]
+enum RuleDay {
+    /// BYMONTHDAY=15
+    MonthDay(u32),
+    /// BYDAY=2SU, or BYDAY=-1SU for the last Sunday of the month
+    Nth(Weekday, i32),
+    /// BYDAY=SU;BYMONTHDAY=8,9,10,11,12,13,14 for the first Sunday on or after the 8th
+    OnOrAfter(Weekday, u32),
+}
+
+impl RuleDay {
+    fn date(&self, year: i32, month: u32) -> Option<NaiveDate> {
+        match *self {
+            Self::MonthDay(day) => NaiveDate::from_ymd_opt(year, month, day),
+            Self::Nth(weekday, nth) if nth > 0 => {
+                NaiveDate::from_weekday_of_month_opt(year, month, weekday, nth.try_into().ok()?)
+            }
+            Self::Nth(weekday, nth) => {
+                let last = last_day_of_month(year, month)?;
+                let days_back = (last.weekday().days_since(weekday) as i32) + 7 * (-nth - 1);
+                Some(last - Duration::days(days_back.into())).filter(|date| date.month() == month)
+            }
+            Self::OnOrAfter(weekday, day) => {
+                let first = NaiveDate::from_ymd_opt(year, month, day)?;
+                Some(first + Duration::days(weekday.days_since(first.weekday()).into()))
+            }
+        }
+    }
+
+    fn rrule_parts(&self) -> String {
+        match self {
+            Self::MonthDay(day) => format!("BYMONTHDAY={day}"),
+            Self::Nth(weekday, nth) => format!("BYDAY={nth}{}", weekday_name(*weekday)),
+            Self::OnOrAfter(weekday, day) => format!(
+                "BYDAY={};BYMONTHDAY={}",
+                weekday_name(*weekday),
+                (*day..day + 7)
+                    .map(|day| day.to_string())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+        }
+    }
+}
+
+/// The subset of RRULEs used by VTIMEZONEs in practice: one onset per year
+#[derive(Debug, Clone, PartialEq)]
+struct YearlyRule {
+    month: u32,
+    day: RuleDay,
+    until: Option<NaiveDateTime>,
+    count: Option<i32>,
+}
+
+impl YearlyRule {
+    fn parse(value: &str, dtstart: NaiveDateTime) -> Result<Self, Error> {
+        let unsupported = || Error::InvalidData(format!("Unsupported timezone RRULE {value}"));
+        let mut month = dtstart.month();
+        let mut weekday = None;
+        let mut monthdays = vec![];
+        let mut until = None;
+        let mut count = None;
+        for part in value.split(';') {
+            let (name, value) = part.split_once('=').ok_or_else(unsupported)?;
+            match name.to_ascii_uppercase().as_str() {
+                "FREQ" if value.eq_ignore_ascii_case("YEARLY") => {}
+                "INTERVAL" if value == "1" => {}
+                "WKST" => {}
+                "BYMONTH" => month = value.parse().map_err(|_| unsupported())?,
+                "BYDAY" => {
+                    let split = value.len().checked_sub(2).ok_or_else(unsupported)?;
+                    let (nth, day) = value.split_at(split);
+                    let nth = match nth.trim_start_matches('+') {
+                        "" => None,
+                        nth => Some(nth.parse::<i32>().map_err(|_| unsupported())?),
+                    };
+                    weekday = Some((parse_weekday(day).ok_or_else(unsupported)?, nth));
+                }
+                "BYMONTHDAY" => {
+                    monthdays = value
+                        .split(',')
+                        .map(str::parse::<u32>)
+                        .collect::<Result<_, _>>()
+                        .map_err(|_| unsupported())?;
+                }
+                "UNTIL" => until = Some(parse_until(value).ok_or_else(unsupported)?),
+                "COUNT" => count = Some(value.parse().map_err(|_| unsupported())?),
+                _ => return Err(unsupported()),
+            }
+        }
+
+        let day = match (weekday, monthdays.iter().min()) {
+            (Some((weekday, Some(nth))), None) if nth != 0 => RuleDay::Nth(weekday, nth),
+            (Some((weekday, None)), Some(&first)) => RuleDay::OnOrAfter(weekday, first),
+            (None, Some(&day)) if monthdays.len() == 1 => RuleDay::MonthDay(day),
+            (None, None) => RuleDay::MonthDay(dtstart.day()),
+            _ => return Err(unsupported()),
+        };
+        Ok(Self {
+            month,
+            day,
+            until,
+            count,
+        })
+    }
+}
+
+/// A STANDARD or DAYLIGHT component
+#[derive(Debug, Clone, PartialEq)]
+struct Observance {
+    daylight: bool,
+    name: Option<String>,
+    // Onsets are in local time before the transition
+    dtstart: NaiveDateTime,
+    offset_from: FixedOffset,
+    offset_to: FixedOffset,
+    rule: Option<YearlyRule>,
+    rdates: Vec<NaiveDateTime>,
+}
+
+impl Observance {
+    fn to_utc(&self, local: NaiveDateTime) -> NaiveDateTime {
+        local - Duration::seconds(self.offset_from.local_minus_utc().into())
+    }
+
+    /// Onset of the rule in a year as UTC
+    fn rule_onset(&self, year: i32) -> Option<NaiveDateTime> {
+        let rule = self.rule.as_ref()?;
+        if year < self.dtstart.year()
+            || rule
+                .count
+                .is_some_and(|count| year >= self.dtstart.year() + count)
+        {
+            return None;
+        }
+        let local = rule
+            .day
+            .date(year, rule.month)?
+            .and_time(self.dtstart.time());
+        let onset = self.to_utc(local);
+        if local < self.dtstart || rule.until.is_some_and(|until| onset > until) {
+            return None;
+        }
+        Some(onset)
+    }
+
+    /// The latest onset up to an instant as UTC
+    fn last_onset(&self, utc: NaiveDateTime) -> Option<NaiveDateTime> {
+        let explicit = std::iter::once(self.dtstart)
+            .chain(self.rdates.iter().copied())
+            .map(|local| self.to_utc(local))
+            .filter(|onset| *onset <= utc)
+            .max();
+
+        let mut last_year = utc.year();
+        if let Some(rule) = &self.rule {
+            if let Some(until) = rule.until {
+                last_year = last_year.min(until.year());
+            }
+            if let Some(count) = rule.count {
+                last_year = last_year.min(self.dtstart.year() + count - 1);
+            }
+        }
+        let recurring = (last_year - 1..=last_year)
+            .rev()
+            .filter_map(|year| self.rule_onset(year))
+            .find(|onset| *onset <= utc);
+
+        explicit.max(recurring)
+    }
+
+    /// All onsets in a range as UTC
+    fn onsets_between(&self, from: NaiveDateTime, to: NaiveDateTime) -> Vec<NaiveDateTime> {
+        std::iter::once(self.dtstart)
+            .chain(self.rdates.iter().copied())
+            .map(|local| self.to_utc(local))
+            .chain((from.year()..=to.year()).filter_map(|year| self.rule_onset(year)))
+            .filter(|onset| (from..=to).contains(onset))
+            .collect()
+    }
+
+    fn to_ics(&self) -> String {
+        let key = if self.daylight {
+            "DAYLIGHT"
+        } else {
+            "STANDARD"
+        };
+        let mut ics = format!("BEGIN:{key}\r\n");
+        if let Some(name) = &self.name {
+            ics += &format!("TZNAME:{name}\r\n");
+        }
+        ics += &format!(
+            "TZOFFSETFROM:{}\r\nTZOFFSETTO:{}\r\nDTSTART:{}\r\n",
+            format_offset(self.offset_from),
+            format_offset(self.offset_to),
+            self.dtstart.format(LOCAL_DATE_TIME)
+        );
+        if let Some(rule) = &self.rule {
+            ics += &format!(
+                "RRULE:FREQ=YEARLY;BYMONTH={};{}\r\n",
+                rule.month,
+                rule.day.rrule_parts()
+            );
+        }
+        for rdate in &self.rdates {
+            ics += &format!("RDATE:{}\r\n", rdate.format(LOCAL_DATE_TIME));
+        }
+        ics + &format!("END:{key}\r\n")
+    }
+}
+
+/// A VTIMEZONE whose offsets are evaluated from its observances
+#[derive(Debug, Clone, PartialEq)]
+pub struct VTimezone {
+    tzid: String,
+    observances: Vec<Observance>,
+}
+
+impl VTimezone {
+    pub fn parse(timezone: &IcalTimeZone) -> Result<Self, Error> {
+        let tzid = timezone
+            .get_property("TZID")
+            .and_then(|prop| prop.value.to_owned())
+            .ok_or(Error::InvalidData("VTIMEZONE without TZID".to_owned()))?;
+        let invalid = |reason: &str| Error::InvalidData(format!("VTIMEZONE {tzid}: {reason}"));
+
+        let mut observances = vec![];
+        for transition in &timezone.transitions {
+            let value = |name: &str| {
+                transition
+                    .get_property(name)
+                    .and_then(|prop| prop.value.as_deref())
+            };
+            let offset_from = value("TZOFFSETFROM")
+                .and_then(parse_offset)
+                .ok_or_else(|| invalid("invalid TZOFFSETFROM"))?;
+            let offset_to = value("TZOFFSETTO")
+                .and_then(parse_offset)
+                .ok_or_else(|| invalid("invalid TZOFFSETTO"))?;
+            let dtstart = value("DTSTART")
+                .and_then(|value| NaiveDateTime::parse_from_str(value, LOCAL_DATE_TIME).ok())
+                .ok_or_else(|| invalid("invalid DTSTART"))?;
+            let rule = value("RRULE")
+                .map(|rrule| YearlyRule::parse(rrule, dtstart))
+                .transpose()?;
+
+            let mut rdates = vec![];
+            for prop in &transition.properties {
+                if prop.name != "RDATE" {
+                    continue;
+                }
+                for rdate in prop.value.as_deref().unwrap_or_default().split(',') {
+                    rdates.push(
+                        if let Ok(utc) = NaiveDateTime::parse_from_str(rdate, UTC_DATE_TIME) {
+                            utc + Duration::seconds(offset_from.local_minus_utc().into())
+                        } else {
+                            NaiveDateTime::parse_from_str(rdate, LOCAL_DATE_TIME)
+                                .map_err(|_| invalid("invalid RDATE"))?
+                        },
+                    );
+                }
+            }
+
+            observances.push(Observance {
+                daylight: matches!(transition.transition, IcalTimeZoneTransitionType::DAYLIGHT),
+                name: value("TZNAME").map(str::to_owned),
+                dtstart,
+                offset_from,
+                offset_to,
+                rule,
+                rdates,
+            });
+        }
+        if observances.is_empty() {
+            return Err(invalid("no STANDARD or DAYLIGHT component"));
+        }
+
+        Ok(Self { tzid, observances })
+    }
+
+    /// Generates a VTIMEZONE from the IANA database, with the TZID being its name
+    pub fn from_tz(tz: Tz) -> Arc<Self> {
+        if let Some(vtimezone) = GENERATED_TIMEZONES.lock().unwrap().get(&tz) {
+            return vtimezone.clone();
+        }
+        let vtimezone = Arc::new(Self::generate(tz));
+        GENERATED_TIMEZONES
+            .lock()
+            .unwrap()
+            .insert(tz, vtimezone.clone());
+        vtimezone
+    }
+
+    pub fn get_tzid(&self) -> &str {
+        &self.tzid
+    }
+
+    pub fn offset_at_utc(&self, utc: NaiveDateTime) -> FixedOffset {
+        self.observances
+            .iter()
+            .filter_map(|observance| Some((observance.last_onset(utc)?, observance)))
+            .max_by_key(|(onset, _)| *onset)
+            .map(|(_, observance)| observance.offset_to)
+            .unwrap_or_else(|| {
+                // Before the first onset the offset it transitions from applies
+                self.observances
+                    .iter()
+                    .min_by_key(|observance| observance.dtstart)
+                    .map(|observance| observance.offset_from)
+                    .unwrap_or(FixedOffset::east_opt(0).unwrap())
+            })
+    }
+
+    /// Resolves a local time like RFC 5545 Section 3.3.5 specifies: an ambiguous time refers to
+    /// its first occurrence and a time in a gap uses the offset from before the gap.
+    pub fn localize(&self, local: NaiveDateTime) -> DateTime<FixedOffset> {
+        let mut offsets: Vec<FixedOffset> = self
+            .observances
+            .iter()
+            .flat_map(|observance| [observance.offset_from, observance.offset_to])
+            .collect();
+        offsets.sort_by_key(|offset| -offset.local_minus_utc());
+        offsets.dedup();
+
+        let to_utc =
+            |offset: &FixedOffset| local - Duration::seconds(offset.local_minus_utc().into());
+        let offset = offsets
+            .iter()
+            .find(|offset| self.offset_at_utc(to_utc(offset)) == **offset)
+            .copied()
+            .unwrap_or_else(|| self.offset_at_utc(to_utc(&offsets[0])));
+        offset.from_local_datetime(&local).unwrap()
+    }
+
+    /// Whether the offsets match those of an IANA timezone from an instant on
+    pub fn is_equivalent(&self, tz: Tz, since: NaiveDateTime) -> bool {
+        let until = NaiveDate::from_ymd_opt(LAST_YEAR, 12, 31)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        // Both offsets only change at onsets of either timezone
+        let generated = Self::from_tz(tz);
+        std::iter::once(since)
+            .chain(self.onsets_between(since, until))
+            .chain(generated.onsets_between(since, until))
+            .all(|utc| self.offset_at_utc(utc) == tz.offset_from_utc_datetime(&utc).fix())
+    }
+
+    fn onsets_between(&self, from: NaiveDateTime, to: NaiveDateTime) -> Vec<NaiveDateTime> {
+        self.observances
+            .iter()
+            .flat_map(|observance| observance.onsets_between(from, to))
+            .collect()
+    }
+
+    /// A VCALENDAR object containing only this VTIMEZONE
+    pub fn to_vcalendar(&self) -> String {
+        "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//github.com/lennart-k/rustical timezones//EN\r\n"
+            .to_owned()
+            + &self.to_ics()
+            + "END:VCALENDAR\r\n"
+    }
+
+    pub fn to_ics(&self) -> String {
+        format!("BEGIN:VTIMEZONE\r\nTZID:{}\r\n", self.tzid)
+            + &self
+                .observances
+                .iter()
+                .map(Observance::to_ics)
+                .collect::<String>()
+            + "END:VTIMEZONE\r\n"
+    }
+
+    fn generate(tz: Tz) -> Self {
+        let start = NaiveDate::from_ymd_opt(FIRST_YEAR, 1, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        let end = NaiveDate::from_ymd_opt(LAST_YEAR + 1, 1, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+
+        let initial = TzState::at(tz, start);
+        let mut observances = vec![Observance {
+            daylight: initial.daylight,
+            name: initial.name.to_owned(),
+            dtstart: start,
+            offset_from: initial.offset,
+            offset_to: initial.offset,
+            rule: None,
+            rdates: vec![],
+        }];
+
+        // Scan day by day and narrow every change down to the second
+        let mut transitions = vec![];
+        let mut state = initial;
+        let mut time = start;
+        while time < end {
+            let next = TzState::at(tz, time + Duration::days(1));
+            if next != state {
+                let (mut before, mut after) = (time, time + Duration::days(1));
+                while after - before > Duration::seconds(1) {
+                    let middle = before + Duration::seconds((after - before).num_seconds() / 2);
+                    if TzState::at(tz, middle) == state {
+                        before = middle;
+                    } else {
+                        after = middle;
+                    }
+                }
+                transitions.push(Transition {
+                    onset: after + Duration::seconds(state.offset.local_minus_utc().into()),
+                    offset_from: state.offset,
+                    state: next.clone(),
+                });
+            }
+            state = next;
+            time += Duration::days(1);
+        }
+
+        let (explicit, rules) = split_yearly_rules(&transitions);
+
+        // Explicit transitions are grouped into one observance with RDATEs per kind
+        for transition in explicit {
+            let kind = |observance: &&mut Observance| {
+                observance.rule.is_none()
+                    && observance.daylight == transition.state.daylight
+                    && observance.name == transition.state.name
+                    && observance.offset_from == transition.offset_from
+                    && observance.offset_to == transition.state.offset
+                    && observance.dtstart != start
+            };
+            if let Some(observance) = observances.iter_mut().find(kind) {
+                observance.rdates.push(transition.onset);
+            } else {
+                observances.push(Observance {
+                    daylight: transition.state.daylight,
+                    name: transition.state.name.to_owned(),
+                    dtstart: transition.onset,
+                    offset_from: transition.offset_from,
+                    offset_to: transition.state.offset,
+                    rule: None,
+                    rdates: vec![],
+                });
+            }
+        }
+        observances.extend(rules);
+
+        Self {
+            tzid: tz.name().to_owned(),
+            observances,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct TzState {
+    offset: FixedOffset,
+    daylight: bool,
+    name: Option<String>,
+}
+
+impl TzState {
+    fn at(tz: Tz, utc: NaiveDateTime) -> Self {
+        let offset = tz.offset_from_utc_datetime(&utc);
+        Self {
+            offset: offset.fix(),
+            daylight: !offset.dst_offset().is_zero(),
+            name: offset.abbreviation().map(str::to_owned),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Transition {
+    // Local time before the transition
+    onset: NaiveDateTime,
+    offset_from: FixedOffset,
+    state: TzState,
+}
+
+/// Transitions recurring every year until the end of the generated range
+struct YearlySeries<'a> {
+    first: &'a Transition,
+    first_year: i32,
+    count: i32,
+    last: bool,
+    nth: Option<i32>,
+    min_day: u32,
+    max_day: u32,
+}
+
+impl<'a> YearlySeries<'a> {
+    fn new(transition: &'a Transition) -> Self {
+        let date = transition.onset.date();
+        Self {
+            first: transition,
+            first_year: date.year(),
+            count: 1,
+            last: is_last_weekday(date),
+            nth: Some(nth_weekday(date)),
+            min_day: date.day(),
+            max_day: date.day(),
+        }
+    }
+
+    fn is_same_kind(&self, transition: &Transition) -> bool {
+        let (first, onset) = (self.first.onset, transition.onset);
+        self.first.state == transition.state
+            && self.first.offset_from == transition.offset_from
+            && first.month() == onset.month()
+            && first.weekday() == onset.weekday()
+            && first.time() == onset.time()
+    }
+
+    /// Adds a transition of the previous year if there's still a rule describing all of them
+    fn push(&mut self, transition: &'a Transition) -> bool {
+        let date = transition.onset.date();
+        let last = self.last && is_last_weekday(date);
+        let nth = self.nth.filter(|nth| *nth == nth_weekday(date));
+        let (min_day, max_day) = (self.min_day.min(date.day()), self.max_day.max(date.day()));
+        if date.year() != self.first_year - 1 || !(last || nth.is_some() || max_day - min_day < 7) {
+            return false;
+        }
+        *self = Self {
+            first: transition,
+            first_year: date.year(),
+            count: self.count + 1,
+            last,
+            nth,
+            min_day,
+            max_day,
+        };
+        true
+    }
+
+    fn into_observance(self) -> Option<Observance> {
+        let day = if self.last {
+            RuleDay::Nth(self.first.onset.weekday(), -1)
+        } else if let Some(nth) = self.nth {
+            RuleDay::Nth(self.first.onset.weekday(), nth)
+        } else if self.count >= 7 {
+            // Only after seeing every weekday we can be sure about the first possible day
+            RuleDay::OnOrAfter(self.first.onset.weekday(), self.min_day)
+        } else {
+            return None;
+        };
+        Some(Observance {
+            daylight: self.first.state.daylight,
+            name: self.first.state.name.to_owned(),
+            dtstart: self.first.onset,
+            offset_from: self.first.offset_from,
+            offset_to: self.first.state.offset,
+            rule: Some(YearlyRule {
+                month: self.first.onset.month(),
+                day,
+                until: None,
+                count: None,
+            }),
+            rdates: vec![],
+        })
+    }
+}
+
+/// Splits off the transitions at the end that follow yearly rules (like daylight saving time),
+/// so that they continue after the generated range.
+fn split_yearly_rules(transitions: &[Transition]) -> (&[Transition], Vec<Observance>) {
+    let mut series: Vec<YearlySeries> = vec![];
+    let mut split = transitions.len();
+    for (index, transition) in transitions.iter().enumerate().rev() {
+        let accepted = match series
+            .iter()
+            .position(|series| series.is_same_kind(transition))
+        {
+            Some(position) => series[position].push(transition),
+            None if series.len() < 2 && transition.onset.year() >= LAST_YEAR - 1 => {
+                series.push(YearlySeries::new(transition));
+                true
+            }
+            None => false,
+        };
+        if !accepted {
+            break;
+        }
+        split = index;
+    }
+
+    if series.len() < 2 || series.iter().any(|series| series.count < 2) {
+        return (transitions, vec![]);
+    }
+    let mut rules = vec![];
+    for series in series {
+        match series.into_observance() {
+            Some(observance) => rules.push(observance),
+            None => return (transitions, vec![]),
+        }
+    }
+    rules.sort_by_key(|observance| observance.dtstart);
+    (&transitions[..split], rules)
+}
+
+/// Parses a VCALENDAR object containing a single VTIMEZONE, like the calendar-timezone property
+pub fn parse_vcalendar_timezone(ics: &str) -> Result<IcalTimeZone, Error> {
+    let mut cal = ical::IcalParser::new(BufReader::new(ics.as_bytes()))
+        .next()
+        .ok_or(Error::InvalidData("Empty calendar-timezone".to_owned()))??;
+    match (cal.timezones.pop(), cal.timezones.is_empty()) {
+        (Some(timezone), true) => Ok(timezone),
+        _ => Err(Error::InvalidData(
+            "calendar-timezone must contain exactly one VTIMEZONE".to_owned(),
+        )),
+    }
+}
+
+/// The IANA timezone a VTIMEZONE represents according to its TZID or X-LIC-LOCATION.
+/// Some clients prefix the name, like /mozilla.org/20050126_1/Europe/Berlin
+pub fn iana_timezone(timezone: &IcalTimeZone) -> Option<Tz> {
+    let names = ["X-LIC-LOCATION", "TZID"]
+        .into_iter()
+        .filter_map(|name| timezone.get_property(name)?.value.as_deref());
+    for name in names {
+        let mut name = name.trim_start_matches('/');
+        loop {
+            if let Ok(tz) = name.parse() {
+                return Some(tz);
+            }
+            match name.split_once('/') {
+                Some((_, rest)) => name = rest,
+                None => break,
+            }
+        }
+    }
+    None
+}
+
+/// Replaces VTIMEZONEs that are equivalent to an IANA timezone by references to it
+/// (RFC 7809), returns `None` if there are none.
+/// The timezones only have to match from the earliest time referencing them on.
+pub(crate) fn remove_iana_timezones(ics: &str) -> Option<String> {
+    let cal = ical::IcalParser::new(BufReader::new(ics.as_bytes()))
+        .next()?
+        .ok()?;
+    let first_years = first_years_by_tzid(&cal);
+
+    let mut replacements = HashMap::new();
+    for timezone in &cal.timezones {
+        let Ok(vtimezone) = VTimezone::parse(timezone) else {
+            continue;
+        };
+        let (Some(tz), Some(first_year)) = (
+            iana_timezone(timezone),
+            first_years.get(vtimezone.get_tzid()),
+        ) else {
+            continue;
+        };
+        let since = NaiveDate::from_ymd_opt((*first_year).clamp(FIRST_YEAR, LAST_YEAR), 1, 1)?
+            .and_hms_opt(0, 0, 0)?;
+        if vtimezone.is_equivalent(tz, since) {
+            replacements.insert(vtimezone.tzid, tz.name());
+        }
+    }
+    if replacements.is_empty() {
+        return None;
+    }
+
+    let mut result = String::with_capacity(ics.len());
+    let mut vtimezone: Option<(usize, Option<String>)> = None;
+    for (line, range) in content_lines(ics) {
+        if line.eq_ignore_ascii_case("BEGIN:VTIMEZONE") {
+            vtimezone = Some((range.start, None));
+        } else if let Some((start, tzid)) = &mut vtimezone {
+            if let Some(value) = line.strip_prefix("TZID:") {
+                *tzid = Some(value.to_owned());
+            }
+            if line.eq_ignore_ascii_case("END:VTIMEZONE") {
+                if !tzid
+                    .as_ref()
+                    .is_some_and(|tzid| replacements.contains_key(tzid))
+                {
+                    result.push_str(&ics[*start..range.end]);
+                }
+                vtimezone = None;
+            }
+        } else if let Some(param) = tzid_param(&line)
+            && let Some(name) = replacements.get(&line[param.clone()])
+            && line[param.clone()] != **name
+        {
+            result.push_str(&fold_line(&format!(
+                "{}{name}{}",
+                &line[..param.start],
+                &line[param.end..]
+            )));
+        } else {
+            result.push_str(&ics[range]);
+        }
+    }
+    Some(result)
+}
+
+/// Adds VTIMEZONEs for IANA timezones that are only referenced by their name,
+/// returns `None` if there are none
+pub(crate) fn add_iana_timezones(ics: &str) -> Option<String> {
+    let (position, tzs) = undefined_iana_timezones(ics)?;
+    let timezones: String = tzs
+        .into_iter()
+        .map(|tz| VTimezone::from_tz(tz).to_ics())
+        .collect();
+    Some(ics[..position].to_owned() + &timezones + &ics[position..])
+}
+
+/// Whether [`add_iana_timezones`] would add any VTIMEZONEs
+pub fn references_iana_timezones(ics: &str) -> bool {
+    undefined_iana_timezones(ics).is_some()
+}
+
+/// The IANA timezones that are referenced without a VTIMEZONE and the position in front of the
+/// first component where their VTIMEZONEs go, `None` if there are none
+fn undefined_iana_timezones(ics: &str) -> Option<(usize, Vec<Tz>)> {
+    if !ics.contains("TZID") {
+        return None;
+    }
+    let mut defined = vec![];
+    let mut referenced = vec![];
+    let mut in_vtimezone = false;
+    let mut position = None;
+    for (line, range) in content_lines(ics) {
+        if line.eq_ignore_ascii_case("BEGIN:VTIMEZONE") {
+            in_vtimezone = true;
+        } else if line.eq_ignore_ascii_case("END:VTIMEZONE") {
+            in_vtimezone = false;
+        } else if in_vtimezone {
+            if let Some(tzid) = line.strip_prefix("TZID:") {
+                defined.push(tzid.to_owned());
+            }
+        } else if let Some(param) = tzid_param(&line) {
+            referenced.push(line[param].to_owned());
+        }
+        // The VTIMEZONEs go in front of the first component
+        if position.is_none()
+            && line.starts_with("BEGIN:")
+            && !line.eq_ignore_ascii_case("BEGIN:VCALENDAR")
+        {
+            position = Some(range.start);
+        }
+    }
+
+    let mut undefined = vec![];
+    for tzid in referenced {
+        if defined.contains(&tzid) {
+            continue;
+        }
+        if let Ok(tz) = tzid.parse::<Tz>()
+            && !undefined.contains(&tz)
+        {
+            undefined.push(tz);
+        }
+    }
+    let position = position?;
+    if undefined.is_empty() {
+        return None;
+    }
+    Some((position, undefined))
+}
+
+/// The earliest year a TZID is used in
+fn first_years_by_tzid(cal: &IcalCalendar) -> HashMap<String, i32> {
+    let properties = cal
+        .events
+        .iter()
+        .flat_map(|event| &event.properties)
+        .chain(cal.todos.iter().flat_map(|todo| &todo.properties))
+        .chain(cal.journals.iter().flat_map(|journal| &journal.properties));
+
+    let mut first_years = HashMap::new();
+    for prop in properties {
+        let Some(tzid) = prop
+            .params
+            .iter()
+            .flatten()
+            .find(|(name, _)| name.eq_ignore_ascii_case("TZID"))
+            .and_then(|(_, values)| values.first())
+        else {
+            continue;
+        };
+        let years = prop
+            .value
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|value| value.get(..4)?.parse::<i32>().ok());
+        for year in years {
+            first_years
+                .entry(tzid.to_owned())
+                .and_modify(|first: &mut i32| *first = (*first).min(year))
+                .or_insert(year);
+        }
+    }
+    first_years
+}
+
+/// Unfolded content lines together with the range of their folded form
+fn content_lines(ics: &str) -> Vec<(String, Range<usize>)> {
+    let mut lines: Vec<(String, Range<usize>)> = vec![];
+    let mut position = 0;
+    for physical in ics.split_inclusive('\n') {
+        let end = position + physical.len();
+        let content = physical.trim_end_matches(['\r', '\n']);
+        match lines.last_mut() {
+            Some((line, range)) if content.starts_with([' ', '\t']) => {
+                line.push_str(&content[1..]);
+                range.end = end;
+            }
+            _ => lines.push((content.to_owned(), position..end)),
+        }
+        position = end;
+    }
+    lines
+}
+
+/// Range of the TZID parameter value in a content line, without quotes
+fn tzid_param(line: &str) -> Option<Range<usize>> {
+    let mut in_quotes = false;
+    let mut param_start = None;
+    for (index, char) in line.char_indices() {
+        match char {
+            '"' => in_quotes = !in_quotes,
+            ';' | ':' if !in_quotes => {
+                if let Some(start) = param_start {
+                    let (name, value) = line[start..index].split_once('=')?;
+                    if name.eq_ignore_ascii_case("TZID") {
+                        let value_start = start + name.len() + 1;
+                        return Some(
+                            if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+                                value_start + 1..index - 1
+                            } else {
+                                value_start..index
+                            },
+                        );
+                    }
+                }
+                if char == ':' {
+                    return None;
+                }
+                param_start = Some(index + 1);
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Folds a content line into lines of at most 75 octets (RFC 5545 Section 3.1)
+fn fold_line(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len() + line.len() / 25);
+    let mut length = 0;
+    for char in line.chars() {
+        if length + char.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            length = 1;
+        }
+        folded.push(char);
+        length += char.len_utf8();
+    }
+    folded + "\r\n"
+}
+
+fn parse_offset(value: &str) -> Option<FixedOffset> {
+    let sign = match value.get(..1)? {
+        "+" => 1,
+        "-" => -1,
+        _ => return None,
+    };
+    let digits = &value[1..];
+    if !matches!(digits.len(), 4 | 6) || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    let hours: i32 = digits[..2].parse().ok()?;
+    let minutes: i32 = digits[2..4].parse().ok()?;
+    let seconds: i32 = match &digits[4..] {
+        "" => 0,
+        seconds => seconds.parse().ok()?,
+    };
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60 + seconds))
+}
+
+fn format_offset(offset: FixedOffset) -> String {
+    let seconds = offset.local_minus_utc();
+    let sign = if seconds < 0 { '-' } else { '+' };
+    let time = chrono::NaiveTime::from_num_seconds_from_midnight_opt(seconds.unsigned_abs(), 0)
+        .unwrap_or_default();
+    if time.second() == 0 {
+        format!("{sign}{}", time.format("%H%M"))
+    } else {
+        format!("{sign}{}", time.format("%H%M%S"))
+    }
+}
+
+fn parse_until(value: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(value, UTC_DATE_TIME)
+        .or_else(|_| NaiveDateTime::parse_from_str(value, LOCAL_DATE_TIME))
+        .ok()
+        .or_else(|| {
+            NaiveDate::parse_from_str(value, "%Y%m%d")
+                .ok()?
+                .and_hms_opt(23, 59, 59)
+        })
+}
+
+fn parse_weekday(value: &str) -> Option<Weekday> {
+    Some(match value.to_ascii_uppercase().as_str() {
+        "MO" => Weekday::Mon,
+        "TU" => Weekday::Tue,
+        "WE" => Weekday::Wed,
+        "TH" => Weekday::Thu,
+        "FR" => Weekday::Fri,
+        "SA" => Weekday::Sat,
+        "SU" => Weekday::Sun,
+        _ => return None,
+    })
+}
+
+fn weekday_name(weekday: Weekday) -> &'static str {
+    match weekday {
+        Weekday::Mon => "MO",
+        Weekday::Tue => "TU",
+        Weekday::Wed => "WE",
+        Weekday::Thu => "TH",
+        Weekday::Fri => "FR",
+        Weekday::Sat => "SA",
+        Weekday::Sun => "SU",
+    }
+}
+
+fn last_day_of_month(year: i32, month: u32) -> Option<NaiveDate> {
+    let (year, month) = if month == 12 {
+        (year + 1, 1)
+    } else {
+        (year, month + 1)
+    };
+    NaiveDate::from_ymd_opt(year, month, 1)?.pred_opt()
+}
+
+fn nth_weekday(date: NaiveDate) -> i32 {
+    (date.day0() / 7 + 1) as i32
+}
+
+fn is_last_weekday(date: NaiveDate) -> bool {
+    (date + Duration::days(7)).month() != date.month()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{VTimezone, add_iana_timezones, remove_iana_timezones};
+    use crate::calendar::{CalDateTime, CalendarObject};
+    use chrono::{Duration, NaiveDate, NaiveDateTime, Offset, TimeZone};
+    use chrono_tz::Tz;
+    use std::io::BufReader;
+
+    const BERLIN_OUTLOOK: &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:-//rustical//test//EN\r
+BEGIN:VTIMEZONE\r
+TZID:W. Europe Standard Time\r
+BEGIN:STANDARD\r
+DTSTART:16011028T030000\r
+RRULE:FREQ=YEARLY;BYDAY=-1SU;BYMONTH=10\r
+TZOFFSETFROM:+0200\r
+TZOFFSETTO:+0100\r
+END:STANDARD\r
+BEGIN:DAYLIGHT\r
+DTSTART:16010325T020000\r
+RRULE:FREQ=YEARLY;BYDAY=-1SU;BYMONTH=3\r
+TZOFFSETFROM:+0100\r
+TZOFFSETTO:+0200\r
+END:DAYLIGHT\r
+END:VTIMEZONE\r
+BEGIN:VEVENT\r
+UID:outlook\r
+DTSTAMP:20240101T000000Z\r
+DTSTART;TZID=W. Europe Standard Time:20240701T100000\r
+DTEND;TZID=W. Europe Standard Time:20240701T110000\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+
+    const BERLIN_THUNDERBIRD: &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:-//rustical//test//EN\r
+BEGIN:VTIMEZONE\r
+TZID:/mozilla.org/20050126_1/Europe/Berlin\r
+X-LIC-LOCATION:Europe/Berlin\r
+BEGIN:DAYLIGHT\r
+TZOFFSETFROM:+0100\r
+TZOFFSETTO:+0200\r
+TZNAME:CEST\r
+DTSTART:19700329T020000\r
+RRULE:FREQ=YEARLY;BYDAY=-1SU;BYMONTH=3\r
+END:DAYLIGHT\r
+BEGIN:STANDARD\r
+TZOFFSETFROM:+0200\r
+TZOFFSETTO:+0100\r
+TZNAME:CET\r
+DTSTART:19701025T030000\r
+RRULE:FREQ=YEARLY;BYDAY=-1SU;BYMONTH=10\r
+END:STANDARD\r
+END:VTIMEZONE\r
+BEGIN:VEVENT\r
+UID:thunderbird\r
+DTSTAMP:20240101T000000Z\r
+DTSTART;TZID=/mozilla.org/20050126_1/Europe/Berlin:20240101T100000\r
+DTEND;TZID=\"/mozilla.org/20050126_1/Europe/Berlin\":20240101T110000\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+
+    fn datetime(value: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").unwrap()
+    }
+
+    fn parse_vtimezone(ics: &str) -> VTimezone {
+        let cal = ical::IcalParser::new(BufReader::new(ics.as_bytes()))
+            .next()
+            .unwrap()
+            .unwrap();
+        VTimezone::parse(&cal.timezones[0]).unwrap()
+    }
+
+    #[test]
+    fn test_custom_timezone() {
+        let vtimezone = parse_vtimezone(BERLIN_OUTLOOK);
+        let offset = |utc| vtimezone.offset_at_utc(datetime(utc)).local_minus_utc() / 3600;
+        assert_eq!(offset("20240330T120000"), 1);
+        assert_eq!(offset("20240331T005959"), 1);
+        assert_eq!(offset("20240331T010000"), 2);
+        assert_eq!(offset("20241027T005959"), 2);
+        assert_eq!(offset("20241027T010000"), 1);
+
+        // Ambiguous times refer to their first occurrence, times in a gap use the offset before
+        let localize = |local| vtimezone.localize(datetime(local)).to_utc().naive_utc();
+        assert_eq!(localize("20241027T023000"), datetime("20241027T003000"));
+        assert_eq!(localize("20240331T023000"), datetime("20240331T013000"));
+        assert_eq!(localize("20240701T100000"), datetime("20240701T080000"));
+
+        let cal_object =
+            CalendarObject::from_ics("outlook".to_owned(), BERLIN_OUTLOOK.to_owned()).unwrap();
+        let first_occurence = cal_object.get_first_occurence().unwrap().unwrap();
+        assert!(matches!(first_occurence, CalDateTime::Offset(_)));
+        assert_eq!(
+            first_occurence.utc().naive_utc(),
+            datetime("20240701T080000")
+        );
+        assert_eq!(first_occurence.format(), "20240701T100000");
+    }
+
+    #[test]
+    fn test_generated_timezones() {
+        for tz in [
+            Tz::Europe__Berlin,
+            Tz::America__New_York,
+            Tz::Australia__Sydney,
+            Tz::America__Sao_Paulo,
+            Tz::Asia__Tokyo,
+            Tz::Asia__Jerusalem,
+            Tz::UTC,
+        ] {
+            let vtimezone = VTimezone::from_tz(tz);
+            assert_eq!(vtimezone.get_tzid(), tz.name());
+            // The generated VTIMEZONE is understood by our own parser
+            let ics = vtimezone.to_ics();
+            assert_eq!(
+                &parse_vtimezone(&format!("BEGIN:VCALENDAR\r\n{ics}END:VCALENDAR\r\n")),
+                &*vtimezone
+            );
+
+            assert!(vtimezone.is_equivalent(tz, datetime("19700101T000000")));
+            // The rules continue after the generated range
+            let mut utc = datetime("20370101T000000");
+            while utc < datetime("20500101T000000") {
+                assert_eq!(
+                    vtimezone.offset_at_utc(utc),
+                    tz.offset_from_utc_datetime(&utc).fix(),
+                    "{tz} at {utc}"
+                );
+                utc += Duration::hours(31);
+            }
+        }
+
+        let ics = VTimezone::from_tz(Tz::Europe__Berlin).to_ics();
+        assert!(ics.contains("RRULE:FREQ=YEARLY;BYMONTH=3;BYDAY=-1SU\r\n"));
+        assert!(ics.contains("RRULE:FREQ=YEARLY;BYMONTH=10;BYDAY=-1SU\r\n"));
+        assert!(ics.contains("TZNAME:CEST\r\n"));
+    }
+
+    #[test]
+    fn test_equivalence() {
+        let outlook = parse_vtimezone(BERLIN_OUTLOOK);
+        let since = NaiveDate::from_ymd_opt(2000, 1, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        assert!(outlook.is_equivalent(Tz::Europe__Berlin, since));
+        assert!(!outlook.is_equivalent(Tz::Europe__London, since));
+        // Before 1996 daylight saving time ended in September
+        let since = NaiveDate::from_ymd_opt(1990, 1, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        assert!(!outlook.is_equivalent(Tz::Europe__Berlin, since));
+    }
+
+    #[test]
+    fn test_remove_iana_timezones() {
+        // Not recognised as an IANA timezone
+        assert_eq!(remove_iana_timezones(BERLIN_OUTLOOK), None);
+
+        let ics = remove_iana_timezones(BERLIN_THUNDERBIRD).unwrap();
+        assert!(!ics.contains("VTIMEZONE"));
+        assert!(ics.contains("DTSTART;TZID=Europe/Berlin:20240101T100000\r\n"));
+        assert!(ics.contains("DTEND;TZID=\"Europe/Berlin\":20240101T110000\r\n"));
+        let original =
+            CalendarObject::from_ics("thunderbird".to_owned(), BERLIN_THUNDERBIRD.to_owned())
+                .unwrap();
+        let normalized = original.clone().with_timezones_by_reference().unwrap();
+        assert_eq!(normalized.get_ics(), ics);
+        assert_ne!(normalized.get_etag(), original.get_etag());
+        assert_eq!(
+            normalized.get_first_occurence().unwrap().unwrap().utc(),
+            original.get_first_occurence().unwrap().unwrap().utc()
+        );
+
+        // The VTIMEZONE is inserted again for clients that need it
+        let with_timezones = add_iana_timezones(&ics).unwrap();
+        assert!(with_timezones.contains("BEGIN:VTIMEZONE\r\nTZID:Europe/Berlin\r\n"));
+        assert_eq!(add_iana_timezones(&with_timezones), None);
+        assert_eq!(add_iana_timezones(BERLIN_OUTLOOK), None);
+        let restored = CalendarObject::from_ics("thunderbird".to_owned(), with_timezones).unwrap();
+        assert_eq!(
+            restored.get_first_occurence().unwrap().unwrap().utc(),
+            original.get_first_occurence().unwrap().unwrap().utc()
+        );
+    }
+}
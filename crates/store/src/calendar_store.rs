@@ -1,14 +1,33 @@
 use crate::calendar::{Calendar, CalendarObject};
 use crate::error::Error;
+use crate::quota::Quota;
+use crate::revision::ObjectRevision;
 use async_trait::async_trait;
-use chrono::NaiveDate;
+use chrono::{NaiveDate, NaiveDateTime, TimeDelta};
+use futures_util::StreamExt;
+use futures_util::stream::{self, BoxStream};
 
+pub type CalendarObjectStream = BoxStream<'static, Result<CalendarObject, Error>>;
+
+/// Time range of a calendar query as the UTC dates of its start and end
 #[derive(Default, Debug, Clone)]
 pub struct CalendarQuery {
     pub time_start: Option<NaiveDate>,
     pub time_end: Option<NaiveDate>,
 }
 
+impl CalendarQuery {
+    /// The dates to compare the dates of the first and last occurences with when prefiltering.
+    /// Floating times only have a local date, which can be a day off from the UTC date,
+    /// so the range is extended by a day in each direction.
+    pub fn occurence_dates(&self) -> (Option<NaiveDate>, Option<NaiveDate>) {
+        (
+            self.time_start.map(|start| start - TimeDelta::days(1)),
+            self.time_end.map(|end| end + TimeDelta::days(1)),
+        )
+    }
+}
+
 #[async_trait]
 pub trait CalendarStore: Send + Sync + 'static {
     async fn get_calendar(&self, principal: &str, id: &str) -> Result<Calendar, Error>;
@@ -30,12 +49,18 @@ pub trait CalendarStore: Send + Sync + 'static {
     ) -> Result<(), Error>;
     async fn restore_calendar(&self, principal: &str, name: &str) -> Result<(), Error>;
 
+    /// Returns the objects changed and deleted since `synctoken` and the new synctoken.
+    /// With a `limit` the changes might be truncated, which is indicated by the last value.
+    /// The sync then continues from the returned synctoken.
+    /// A synctoken from before the changelog was compacted returns [`Error::InvalidSyncToken`],
+    /// `0` requests an initial sync and is always valid, as are the negative synctokens continuing it.
     async fn sync_changes(
         &self,
         principal: &str,
         cal_id: &str,
         synctoken: i64,
-    ) -> Result<(Vec<CalendarObject>, Vec<String>, i64), Error>;
+        limit: Option<usize>,
+    ) -> Result<(Vec<CalendarObject>, Vec<String>, i64, bool), Error>;
 
     /// Since the <calendar-query> rules are rather complex this function
     /// is only meant to do some prefiltering
@@ -47,25 +72,101 @@ pub trait CalendarStore: Send + Sync + 'static {
     ) -> Result<Vec<CalendarObject>, Error> {
         self.get_objects(principal, cal_id).await
     }
+    /// Like [`CalendarStore::calendar_query`] but yields the objects as they are loaded
+    /// so large calendars don't have to be held in memory at once
+    async fn calendar_query_stream(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        query: CalendarQuery,
+    ) -> Result<CalendarObjectStream, Error> {
+        let objects = self.calendar_query(principal, cal_id, query).await?;
+        Ok(stream::iter(objects.into_iter().map(Ok)).boxed())
+    }
 
     async fn get_objects(
         &self,
         principal: &str,
         cal_id: &str,
     ) -> Result<Vec<CalendarObject>, Error>;
+    /// Like [`CalendarStore::get_objects`] but yields the objects as they are loaded
+    async fn get_objects_stream(
+        &self,
+        principal: &str,
+        cal_id: &str,
+    ) -> Result<CalendarObjectStream, Error> {
+        let objects = self.get_objects(principal, cal_id).await?;
+        Ok(stream::iter(objects.into_iter().map(Ok)).boxed())
+    }
+    /// Like [`CalendarStore::get_objects`] but the objects may only carry their metadata
+    /// (see [`CalendarObject::from_metadata`]), for listings that don't need the calendar data
+    async fn get_objects_metadata(
+        &self,
+        principal: &str,
+        cal_id: &str,
+    ) -> Result<Vec<CalendarObject>, Error> {
+        self.get_objects(principal, cal_id).await
+    }
     async fn get_object(
         &self,
         principal: &str,
         cal_id: &str,
         object_id: &str,
     ) -> Result<CalendarObject, Error>;
+    /// Fetches multiple objects at once, objects that don't exist are left out
+    async fn get_objects_by_ids(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        object_ids: &[String],
+    ) -> Result<Vec<CalendarObject>, Error> {
+        let mut objects = vec![];
+        for object_id in object_ids {
+            match self.get_object(principal, cal_id, object_id).await {
+                Ok(object) => objects.push(object),
+                Err(Error::NotFound) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(objects)
+    }
+    /// Also records the object as a new revision, `changed_by` is the principal making the change
     async fn put_object(
         &self,
         principal: String,
         cal_id: String,
         object: CalendarObject,
         overwrite: bool,
+        changed_by: Option<&str>,
     ) -> Result<(), Error>;
+    /// Like [`CalendarStore::put_object`] but fails with [`Error::QuotaExceeded`] if the object
+    /// grows beyond the principal's quota.
+    /// Stores with transactions check the quota atomically with the write,
+    /// the default implementation checks it before writing.
+    async fn put_object_with_quota(
+        &self,
+        principal: String,
+        cal_id: String,
+        object: CalendarObject,
+        overwrite: bool,
+        changed_by: Option<&str>,
+        quota: &Quota,
+    ) -> Result<(), Error> {
+        let old_size = match self.get_object(&principal, &cal_id, object.get_id()).await {
+            Ok(existing) => existing.get_ics().len() as u64,
+            Err(Error::NotFound) => 0,
+            Err(err) => return Err(err),
+        };
+        if !quota
+            .allows_put(&principal, old_size, object.get_ics().len() as u64)
+            .await?
+        {
+            return Err(Error::QuotaExceeded);
+        }
+        self.put_object(principal, cal_id, object, overwrite, changed_by)
+            .await
+    }
+    /// Without the trashbin the object is deleted permanently, also if it's already in the trashbin
     async fn delete_object(
         &self,
         principal: &str,
@@ -79,6 +180,66 @@ pub trait CalendarStore: Send + Sync + 'static {
         cal_id: &str,
         object_id: &str,
     ) -> Result<(), Error>;
+    /// Returns the objects of a calendar that are in the trashbin
+    async fn get_deleted_objects(
+        &self,
+        principal: &str,
+        cal_id: &str,
+    ) -> Result<Vec<CalendarObject>, Error>;
+
+    /// Returns the total size in bytes of a principal's objects, counted against its quota.
+    /// Objects in the trashbin don't count.
+    async fn get_used_bytes(&self, principal: &str) -> Result<u64, Error>;
+
+    /// Permanently deletes calendars and objects moved to the trashbin before `deleted_before`
+    /// and returns how many were deleted.
+    /// The changelog of deleted objects is pruned to the deletion itself.
+    async fn purge_trashbin(&self, deleted_before: NaiveDateTime) -> Result<usize, Error>;
+
+    /// Collapses the changelog of every object to its latest change and drops changes made
+    /// before `horizon`, except for the latest change of objects that still exist.
+    /// Synctokens from before `horizon` become invalid. Returns how many changes were dropped.
+    async fn compact_changelog(&self, horizon: NaiveDateTime) -> Result<usize, Error>;
+
+    /// Returns the most recent revisions of an object, newest first.
+    /// Permanently deleting an object also deletes its revisions.
+    async fn get_object_revisions(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        object_id: &str,
+    ) -> Result<Vec<ObjectRevision<CalendarObject>>, Error>;
+
+    /// Writes an older revision of an object as its new version,
+    /// like any other write it has to fit into the principal's quota
+    async fn rollback_object(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        object_id: &str,
+        revision: i64,
+        changed_by: Option<&str>,
+        quota: &Quota,
+    ) -> Result<(), Error> {
+        let revision = self
+            .get_object_revisions(principal, cal_id, object_id)
+            .await?
+            .into_iter()
+            .find(|rev| rev.revision == revision)
+            .ok_or(Error::NotFound)?;
+        if revision.object.get_ics().len() as u64 > quota.max_resource_size() {
+            return Err(Error::MaxResourceSizeExceeded);
+        }
+        self.put_object_with_quota(
+            principal.to_owned(),
+            cal_id.to_owned(),
+            revision.object,
+            true,
+            changed_by,
+            quota,
+        )
+        .await
+    }
 
     fn is_read_only(&self) -> bool;
 }
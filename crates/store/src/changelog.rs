@@ -0,0 +1,35 @@
+use crate::{AddressbookStore, CalendarStore, Error};
+use chrono::{TimeDelta, Utc};
+use std::{sync::Arc, time::Duration};
+use tracing::{error, info};
+
+const COMPACTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Compacts the changelogs of all calendars and addressbooks and drops changes made more than
+/// `retention_days` ago, returns how many changes were dropped.
+/// Clients with an older synctoken have to do an initial sync again.
+pub async fn compact_changelog(
+    addr_store: &impl AddressbookStore,
+    cal_store: &impl CalendarStore,
+    retention_days: u64,
+) -> Result<usize, Error> {
+    let horizon = Utc::now().naive_utc() - TimeDelta::days(retention_days as i64);
+    Ok(cal_store.compact_changelog(horizon).await? + addr_store.compact_changelog(horizon).await?)
+}
+
+/// Compacts the changelog every hour
+pub async fn changelog_compactor(
+    addr_store: Arc<impl AddressbookStore>,
+    cal_store: Arc<impl CalendarStore>,
+    retention_days: u64,
+) {
+    let mut interval = tokio::time::interval(COMPACTION_INTERVAL);
+    loop {
+        interval.tick().await;
+        match compact_changelog(addr_store.as_ref(), cal_store.as_ref(), retention_days).await {
+            Ok(0) => {}
+            Ok(count) => info!("Dropped {count} entries from the changelog"),
+            Err(err) => error!("Compacting the changelog failed: {err}"),
+        }
+    }
+}
@@ -1,28 +1,35 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 
 use crate::{
-    calendar::CalendarObjectType, AddressObject, Addressbook, AddressbookStore, Calendar,
-    CalendarObject, CalendarStore, Error,
+    AddressObject, Addressbook, AddressbookStore, BirthdayCalendarSettings, Calendar,
+    CalendarObject, CalendarStore, Error, ObjectRevision, addressbook::VcardDateRange,
+    calendar::CalendarObjectType, calendar_store::CalendarQuery, revision::MAX_REVISIONS,
 };
 use async_trait::async_trait;
+use chrono::{NaiveDateTime, TimeDelta};
 use derive_more::derive::Constructor;
 use sha2::{Digest, Sha256};
 
 #[derive(Constructor, Clone)]
 pub struct ContactBirthdayStore<AS: AddressbookStore>(Arc<AS>);
 
-fn birthday_calendar(addressbook: Addressbook) -> Calendar {
+fn birthday_calendar(addressbook: Addressbook, settings: &BirthdayCalendarSettings) -> Calendar {
     Calendar {
         principal: addressbook.principal,
         id: addressbook.id,
         displayname: addressbook
             .displayname
-            .map(|name| format!("{} birthdays", name)),
+            .map(|name| settings.format_displayname(&name)),
         order: 0,
         description: None,
-        color: None,
+        color: settings.color.to_owned(),
         timezone: None,
         timezone_id: None,
+        created_at: addressbook.created_at,
+        updated_at: addressbook.updated_at,
         deleted_at: addressbook.deleted_at,
         synctoken: addressbook.synctoken,
         subscription_url: None,
@@ -36,20 +43,62 @@ fn birthday_calendar(addressbook: Addressbook) -> Calendar {
     }
 }
 
+/// The date types of a contact that a client synced at `synctoken` might know.
+/// They are taken from the revision current at `synctoken` and all later ones.
+/// If the history doesn't reach back far enough (e.g. because the contact was deleted
+/// permanently) every possible date type is returned.
+fn previous_date_types(
+    revisions: &[ObjectRevision<AddressObject>],
+    synctoken: i64,
+    settings: &BirthdayCalendarSettings,
+) -> Result<HashSet<String>, Error> {
+    // The client doesn't know anything yet
+    if synctoken == 0 {
+        return Ok(HashSet::new());
+    }
+    let known_revisions = match revisions
+        .iter()
+        .position(|revision| revision.revision <= synctoken)
+    {
+        Some(position) => &revisions[..=position],
+        // The contact was created after the synctoken
+        None if !revisions.is_empty() && revisions.len() < MAX_REVISIONS => revisions,
+        None => {
+            return Ok(AddressObject::significant_date_types(settings)
+                .into_iter()
+                .collect());
+        }
+    };
+    let mut date_types = HashSet::new();
+    for revision in known_revisions {
+        date_types.extend(revision.object.get_significant_dates(settings)?.into_keys());
+    }
+    Ok(date_types)
+}
+
 #[async_trait]
 impl<AS: AddressbookStore> CalendarStore for ContactBirthdayStore<AS> {
     async fn get_calendar(&self, principal: &str, id: &str) -> Result<Calendar, Error> {
         let addressbook = self.0.get_addressbook(principal, id).await?;
-        Ok(birthday_calendar(addressbook))
+        let settings = self.0.get_birthday_settings(principal).await?;
+        Ok(birthday_calendar(addressbook, &settings))
     }
     async fn get_calendars(&self, principal: &str) -> Result<Vec<Calendar>, Error> {
         let addressbooks = self.0.get_addressbooks(principal).await?;
-        Ok(addressbooks.into_iter().map(birthday_calendar).collect())
+        let settings = self.0.get_birthday_settings(principal).await?;
+        Ok(addressbooks
+            .into_iter()
+            .map(|addressbook| birthday_calendar(addressbook, &settings))
+            .collect())
     }
 
     async fn get_deleted_calendars(&self, principal: &str) -> Result<Vec<Calendar>, Error> {
         let addressbooks = self.0.get_deleted_addressbooks(principal).await?;
-        Ok(addressbooks.into_iter().map(birthday_calendar).collect())
+        let settings = self.0.get_birthday_settings(principal).await?;
+        Ok(addressbooks
+            .into_iter()
+            .map(|addressbook| birthday_calendar(addressbook, &settings))
+            .collect())
     }
 
     async fn update_calendar(
@@ -82,16 +131,102 @@ impl<AS: AddressbookStore> CalendarStore for ContactBirthdayStore<AS> {
         principal: &str,
         cal_id: &str,
         synctoken: i64,
-    ) -> Result<(Vec<CalendarObject>, Vec<String>, i64), Error> {
-        let (objects, deleted_objects, new_synctoken) =
-            self.0.sync_changes(principal, cal_id, synctoken).await?;
-        let objects: Result<Vec<Option<CalendarObject>>, Error> = objects
+        limit: Option<usize>,
+    ) -> Result<(Vec<CalendarObject>, Vec<String>, i64, bool), Error> {
+        let settings = self.0.get_birthday_settings(principal).await?;
+        // Date types that were disabled since have to be reported as deleted
+        let settings_before = BirthdayCalendarSettings {
+            include_anniversaries: true,
+            include_abdates: true,
+            ..settings.clone()
+        };
+        let date_types = AddressObject::significant_date_types(&settings_before);
+        // Every contact results in up to one object for each date type
+        let contact_limit = limit.map(|limit| (limit / date_types.len().max(1)).max(1));
+        let (objects, deleted_objects, new_synctoken, truncated) = self
+            .0
+            .sync_changes(principal, cal_id, synctoken, contact_limit)
+            .await?;
+
+        let mut revisions = if synctoken == 0 {
+            HashMap::new()
+        } else {
+            let object_ids: Vec<String> = objects
+                .iter()
+                .map(|object| object.get_id().to_owned())
+                .chain(deleted_objects.iter().cloned())
+                .collect();
+            self.0
+                .get_objects_revisions(principal, cal_id, &object_ids)
+                .await?
+        };
+
+        let mut changed_objects = vec![];
+        // A contact might have lost some of its dates, they are reported as deleted
+        let mut deleted_ids = vec![];
+        for object in objects {
+            let mut dates = object.get_significant_dates(&settings)?;
+            let previous_date_types = previous_date_types(
+                &revisions.remove(object.get_id()).unwrap_or_default(),
+                synctoken,
+                &settings_before,
+            )?;
+            for date_type in &date_types {
+                match dates.remove(date_type) {
+                    Some(date) => changed_objects.push(date),
+                    None if previous_date_types.contains(date_type) => {
+                        deleted_ids.push(format!("{}-{date_type}", object.get_id()))
+                    }
+                    None => {}
+                }
+            }
+        }
+        for object_id in deleted_objects {
+            for date_type in previous_date_types(
+                &revisions.remove(&object_id).unwrap_or_default(),
+                synctoken,
+                &settings_before,
+            )? {
+                deleted_ids.push(format!("{object_id}-{date_type}"));
+            }
+        }
+
+        // A limit below the number of date types can't hold all dates of a single contact,
+        // the dates that don't fit are left out
+        if let Some(limit) = limit
+            && changed_objects.len() + deleted_ids.len() > limit
+        {
+            changed_objects.truncate(limit);
+            deleted_ids.truncate(limit - changed_objects.len());
+        }
+
+        Ok((changed_objects, deleted_ids, new_synctoken, truncated))
+    }
+
+    async fn calendar_query(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        query: CalendarQuery,
+    ) -> Result<Vec<CalendarObject>, Error> {
+        // Some extra tolerance for timezones, the results get filtered exactly afterwards
+        let start = query.time_start.map(|start| start - TimeDelta::days(1));
+        let end = query.time_end.map(|end| end + TimeDelta::days(1));
+
+        let settings = self.0.get_birthday_settings(principal).await?;
+        let objects: Result<Vec<HashMap<String, CalendarObject>>, Error> = self
+            .0
+            .get_objects_with_dates(principal, cal_id, &VcardDateRange::new(start, end))
+            .await?
             .iter()
-            .map(AddressObject::get_birthday_object)
+            .map(|object| object.get_significant_dates_between(&settings, start, end))
+            .collect();
+        let objects = objects?
+            .into_iter()
+            .flat_map(HashMap::into_values)
             .collect();
-        let objects = objects?.into_iter().flatten().collect();
 
-        Ok((objects, deleted_objects, new_synctoken))
+        Ok(objects)
     }
 
     async fn get_objects(
@@ -99,12 +234,13 @@ impl<AS: AddressbookStore> CalendarStore for ContactBirthdayStore<AS> {
         principal: &str,
         cal_id: &str,
     ) -> Result<Vec<CalendarObject>, Error> {
-        let objects: Result<Vec<HashMap<&'static str, CalendarObject>>, Error> = self
+        let settings = self.0.get_birthday_settings(principal).await?;
+        let objects: Result<Vec<HashMap<String, CalendarObject>>, Error> = self
             .0
             .get_objects(principal, cal_id)
             .await?
             .iter()
-            .map(AddressObject::get_significant_dates)
+            .map(|object| object.get_significant_dates(&settings))
             .collect();
         let objects = objects?
             .into_iter()
@@ -121,10 +257,11 @@ impl<AS: AddressbookStore> CalendarStore for ContactBirthdayStore<AS> {
         object_id: &str,
     ) -> Result<CalendarObject, Error> {
         let (addressobject_id, date_type) = object_id.rsplit_once("-").ok_or(Error::NotFound)?;
+        let settings = self.0.get_birthday_settings(principal).await?;
         self.0
             .get_object(principal, cal_id, addressobject_id)
             .await?
-            .get_significant_dates()?
+            .get_significant_dates(&settings)?
             .remove(date_type)
             .ok_or(Error::NotFound)
     }
@@ -135,6 +272,7 @@ impl<AS: AddressbookStore> CalendarStore for ContactBirthdayStore<AS> {
         _cal_id: String,
         _object: CalendarObject,
         _overwrite: bool,
+        _changed_by: Option<&str>,
     ) -> Result<(), Error> {
         Err(Error::ReadOnly)
     }
@@ -158,7 +296,259 @@ impl<AS: AddressbookStore> CalendarStore for ContactBirthdayStore<AS> {
         Err(Error::ReadOnly)
     }
 
+    async fn get_deleted_objects(
+        &self,
+        _principal: &str,
+        _cal_id: &str,
+    ) -> Result<Vec<CalendarObject>, Error> {
+        Ok(vec![])
+    }
+
+    // The contacts are counted by the addressbook store
+    async fn get_used_bytes(&self, _principal: &str) -> Result<u64, Error> {
+        Ok(0)
+    }
+
+    // The contacts are purged by the addressbook store
+    async fn purge_trashbin(&self, _deleted_before: NaiveDateTime) -> Result<usize, Error> {
+        Ok(0)
+    }
+
+    // Uses the changelog of the addressbook store
+    async fn compact_changelog(&self, _horizon: NaiveDateTime) -> Result<usize, Error> {
+        Ok(0)
+    }
+
+    async fn get_object_revisions(
+        &self,
+        _principal: &str,
+        _cal_id: &str,
+        _object_id: &str,
+    ) -> Result<Vec<ObjectRevision<CalendarObject>>, Error> {
+        Ok(vec![])
+    }
+
     fn is_read_only(&self) -> bool {
         true
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::ContactBirthdayStore;
+    use crate::{
+        AddressObject, Addressbook, AddressbookStore, BirthdayCalendarSettings, CalendarObject,
+        CalendarStore, calendar_store::CalendarQuery, memory::MemoryAddressbookStore,
+    };
+    use chrono::NaiveDate;
+    use std::sync::Arc;
+
+    async fn create_store() -> (
+        Arc<MemoryAddressbookStore>,
+        ContactBirthdayStore<MemoryAddressbookStore>,
+    ) {
+        let (send, _recv) = tokio::sync::mpsc::channel(100);
+        let addr_store = Arc::new(MemoryAddressbookStore::new(send));
+        addr_store
+            .insert_addressbook(Addressbook {
+                principal: "user".to_owned(),
+                id: "contacts".to_owned(),
+                displayname: None,
+                description: None,
+                created_at: None,
+                updated_at: None,
+                deleted_at: None,
+                synctoken: 0,
+                push_topic: "contacts".to_owned(),
+            })
+            .await
+            .unwrap();
+        (addr_store.clone(), ContactBirthdayStore::new(addr_store))
+    }
+
+    async fn put(store: &MemoryAddressbookStore, id: &str, dates: &str) {
+        let vcf = format!("BEGIN:VCARD\r\nVERSION:4.0\r\nFN:{id}\r\n{dates}END:VCARD\r\n");
+        store
+            .put_object(
+                "user".to_owned(),
+                "contacts".to_owned(),
+                AddressObject::from_vcf(id.to_owned(), vcf).unwrap(),
+                true,
+                None,
+            )
+            .await
+            .unwrap();
+    }
+
+    fn ids(objects: Vec<CalendarObject>) -> Vec<String> {
+        let mut ids: Vec<_> = objects
+            .iter()
+            .map(|object| object.get_id().to_owned())
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    #[tokio::test]
+    async fn test_birthday_sync_changes() {
+        let (addr_store, store) = create_store().await;
+        addr_store
+            .update_birthday_settings(
+                "user",
+                BirthdayCalendarSettings {
+                    include_abdates: true,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        put(
+            &addr_store,
+            "a",
+            "BDAY:19850412\r\nANNIVERSARY:20100612\r\n",
+        )
+        .await;
+        put(&addr_store, "b", "BDAY:19900101\r\n").await;
+
+        let (objects, deleted, synctoken, _) = store
+            .sync_changes("user", "contacts", 0, None)
+            .await
+            .unwrap();
+        assert_eq!(
+            ids(objects),
+            vec!["a-anniversary", "a-birthday", "b-birthday"]
+        );
+        assert!(deleted.is_empty());
+
+        // Only dates that existed before are reported as deleted
+        put(&addr_store, "a", "BDAY:19850412\r\n").await;
+        put(&addr_store, "c", "BDAY:--1231\r\n").await;
+        let (objects, deleted, synctoken, _) = store
+            .sync_changes("user", "contacts", synctoken, None)
+            .await
+            .unwrap();
+        assert_eq!(ids(objects), vec!["a-birthday", "c-birthday"]);
+        assert_eq!(deleted, vec!["a-anniversary"]);
+
+        addr_store
+            .delete_object("user", "contacts", "b", true)
+            .await
+            .unwrap();
+        let (objects, deleted, _, _) = store
+            .sync_changes("user", "contacts", synctoken, None)
+            .await
+            .unwrap();
+        assert!(objects.is_empty());
+        assert_eq!(deleted, vec!["b-birthday"]);
+    }
+
+    #[tokio::test]
+    async fn test_birthday_sync_settings() {
+        let (addr_store, store) = create_store().await;
+        put(
+            &addr_store,
+            "a",
+            "BDAY:19850412\r\nANNIVERSARY:20100612\r\n",
+        )
+        .await;
+        let (_, _, synctoken, _) = store
+            .sync_changes("user", "contacts", 0, None)
+            .await
+            .unwrap();
+
+        // Changed settings are synced and disabled date types are reported as deleted
+        addr_store
+            .update_birthday_settings(
+                "user",
+                BirthdayCalendarSettings {
+                    birthday_summary_template: "{name}".to_owned(),
+                    include_anniversaries: false,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert!(
+            store
+                .get_calendar("user", "contacts")
+                .await
+                .unwrap()
+                .synctoken
+                > synctoken
+        );
+        let (objects, deleted, _, _) = store
+            .sync_changes("user", "contacts", synctoken, None)
+            .await
+            .unwrap();
+        assert_eq!(ids(objects), vec!["a-birthday"]);
+        assert_eq!(deleted, vec!["a-anniversary"]);
+    }
+
+    #[tokio::test]
+    async fn test_birthday_sync_limit() {
+        let (addr_store, store) = create_store().await;
+        for id in ["a", "b", "c", "d"] {
+            put(&addr_store, id, "BDAY:19850412\r\nANNIVERSARY:20100612\r\n").await;
+        }
+
+        let mut synctoken = 0;
+        let mut objects = vec![];
+        loop {
+            let (page, _, new_synctoken, truncated) = store
+                .sync_changes("user", "contacts", synctoken, Some(3))
+                .await
+                .unwrap();
+            assert!(page.len() <= 3);
+            objects.extend(page);
+            synctoken = new_synctoken;
+            if !truncated {
+                break;
+            }
+        }
+        assert_eq!(objects.len(), 8);
+
+        // Even a limit below the dates of a single contact is respected
+        let (objects, _, _, _) = store
+            .sync_changes("user", "contacts", 0, Some(1))
+            .await
+            .unwrap();
+        assert_eq!(objects.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_birthday_calendar_query() {
+        let (addr_store, store) = create_store().await;
+        put(&addr_store, "a", "BDAY:19850412\r\n").await;
+        put(
+            &addr_store,
+            "b",
+            "BDAY:19900101\r\nANNIVERSARY:20100612\r\n",
+        )
+        .await;
+        put(&addr_store, "c", "BDAY:--1231\r\n").await;
+        put(&addr_store, "d", "BDAY:20300412\r\n").await;
+
+        let query = |start: &str, end: &str| CalendarQuery {
+            time_start: NaiveDate::parse_from_str(start, "%Y-%m-%d").ok(),
+            time_end: NaiveDate::parse_from_str(end, "%Y-%m-%d").ok(),
+        };
+        let objects = store
+            .calendar_query("user", "contacts", query("2024-04-01", "2024-04-30"))
+            .await
+            .unwrap();
+        assert_eq!(ids(objects), vec!["a-birthday"]);
+        let objects = store
+            .calendar_query("user", "contacts", query("2024-12-31", "2025-01-01"))
+            .await
+            .unwrap();
+        assert_eq!(ids(objects), vec!["b-birthday", "c-birthday"]);
+        let objects = store
+            .calendar_query("user", "contacts", query("2024-01-01", "2024-12-31"))
+            .await
+            .unwrap();
+        assert_eq!(
+            ids(objects),
+            vec!["a-birthday", "b-anniversary", "b-birthday", "c-birthday"]
+        );
+    }
+}
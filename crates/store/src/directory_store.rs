@@ -0,0 +1,313 @@
+use std::sync::Arc;
+
+use crate::{
+    AddressObject, Addressbook, AddressbookStore, BirthdayCalendarSettings, Error, ObjectRevision,
+    addressbook::escape_text,
+    auth::{AuthenticationProvider, User, user::PrincipalType},
+};
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use derive_more::derive::Constructor;
+use sha2::{Digest, Sha256};
+
+/// The id of the directory addressbook
+pub const DIRECTORY_ID: &str = "principals";
+
+/// Read-only addressbook listing all principals of an `AuthenticationProvider`
+/// Served as a directory gateway (https://datatracker.ietf.org/doc/html/rfc6352#section-6.2.3)
+#[derive(Constructor, Clone)]
+pub struct DirectoryStore<AP: AuthenticationProvider>(Arc<AP>);
+
+fn principal_vcard(principal: &User) -> Result<AddressObject, Error> {
+    let name = principal.displayname.as_deref().unwrap_or(&principal.id);
+    // https://datatracker.ietf.org/doc/html/rfc6350#section-6.1.4
+    let kind = match principal.principal_type {
+        PrincipalType::Individual => Some("individual"),
+        PrincipalType::Group => Some("group"),
+        PrincipalType::Room => Some("location"),
+        PrincipalType::Resource | PrincipalType::Unknown => None,
+    }
+    .map(|kind| format!("KIND:{kind}\r\n"))
+    .unwrap_or_default();
+    let emails: String = principal
+        .emails
+        .iter()
+        .enumerate()
+        .map(|(i, email)| {
+            // The first address is the primary one
+            let pref = if i == 0 { ";PREF=1" } else { "" };
+            format!("EMAIL{pref}:{}\r\n", escape_text(email))
+        })
+        .collect();
+
+    AddressObject::from_vcf(
+        principal.id.to_owned(),
+        format!(
+            "BEGIN:VCARD\r\nVERSION:4.0\r\nUID:{uid}\r\n{kind}FN:{name}\r\n{emails}END:VCARD\r\n",
+            uid = escape_text(&principal.id),
+            name = escape_text(name),
+        ),
+    )
+}
+
+impl<AP: AuthenticationProvider> DirectoryStore<AP> {
+    async fn get_directory(&self, principal: &str) -> Result<Addressbook, Error> {
+        let objects = self.get_principal_objects().await?;
+        Ok(Addressbook {
+            id: DIRECTORY_ID.to_owned(),
+            principal: principal.to_owned(),
+            displayname: Some("Company Directory".to_owned()),
+            description: None,
+            created_at: None,
+            updated_at: None,
+            deleted_at: None,
+            synctoken: Self::synctoken(&objects),
+            push_topic: {
+                let mut hasher = Sha256::new();
+                hasher.update("directory");
+                hasher.update(principal);
+                format!("{:x}", hasher.finalize())
+            },
+        })
+    }
+
+    async fn get_principal_objects(&self) -> Result<Vec<AddressObject>, Error> {
+        let mut principals = self.0.get_principals().await?;
+        // The synctoken must not depend on the order of the authentication provider
+        principals.sort_by(|a, b| a.id.cmp(&b.id));
+        principals.iter().map(principal_vcard).collect()
+    }
+
+    /// Since the principals don't keep a changelog we derive the synctoken from the content
+    fn synctoken(objects: &[AddressObject]) -> i64 {
+        let mut hasher = Sha256::new();
+        for object in objects {
+            hasher.update(object.get_id());
+            hasher.update(object.get_vcf());
+        }
+        let hash: [u8; 8] = hasher.finalize()[..8].try_into().unwrap();
+        // Keep the synctoken positive
+        (u64::from_be_bytes(hash) >> 1) as i64
+    }
+}
+
+#[async_trait]
+impl<AP: AuthenticationProvider> AddressbookStore for DirectoryStore<AP> {
+    async fn get_addressbook(&self, principal: &str, id: &str) -> Result<Addressbook, Error> {
+        if id != DIRECTORY_ID {
+            return Err(Error::NotFound);
+        }
+        self.get_directory(principal).await
+    }
+    async fn get_addressbooks(&self, principal: &str) -> Result<Vec<Addressbook>, Error> {
+        Ok(vec![self.get_directory(principal).await?])
+    }
+    async fn get_deleted_addressbooks(&self, _principal: &str) -> Result<Vec<Addressbook>, Error> {
+        Ok(vec![])
+    }
+
+    async fn update_addressbook(
+        &self,
+        _principal: String,
+        _id: String,
+        _addressbook: Addressbook,
+    ) -> Result<(), Error> {
+        Err(Error::ReadOnly)
+    }
+    async fn insert_addressbook(&self, _addressbook: Addressbook) -> Result<(), Error> {
+        Err(Error::ReadOnly)
+    }
+    async fn delete_addressbook(
+        &self,
+        _principal: &str,
+        _name: &str,
+        _use_trashbin: bool,
+    ) -> Result<(), Error> {
+        Err(Error::ReadOnly)
+    }
+    async fn restore_addressbook(&self, _principal: &str, _name: &str) -> Result<(), Error> {
+        Err(Error::ReadOnly)
+    }
+
+    /// Without a changelog we cannot tell which principals have been removed,
+    /// so a stale synctoken is rejected and the client has to sync from scratch
+    async fn sync_changes(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+        synctoken: i64,
+        // The synctoken is derived from all principals so the directory can't be synced in pages
+        _limit: Option<usize>,
+    ) -> Result<(Vec<AddressObject>, Vec<String>, i64, bool), Error> {
+        let objects = self.get_objects(principal, addressbook_id).await?;
+        let new_synctoken = Self::synctoken(&objects);
+        match synctoken {
+            0 => Ok((objects, vec![], new_synctoken, false)),
+            _ if synctoken == new_synctoken => Ok((vec![], vec![], new_synctoken, false)),
+            _ => Err(Error::InvalidSyncToken),
+        }
+    }
+
+    async fn get_objects(
+        &self,
+        _principal: &str,
+        addressbook_id: &str,
+    ) -> Result<Vec<AddressObject>, Error> {
+        if addressbook_id != DIRECTORY_ID {
+            return Err(Error::NotFound);
+        }
+        self.get_principal_objects().await
+    }
+    async fn get_object(
+        &self,
+        _principal: &str,
+        addressbook_id: &str,
+        object_id: &str,
+    ) -> Result<AddressObject, Error> {
+        if addressbook_id != DIRECTORY_ID {
+            return Err(Error::NotFound);
+        }
+        let principal = self
+            .0
+            .get_principal(object_id)
+            .await?
+            .ok_or(Error::NotFound)?;
+        principal_vcard(&principal)
+    }
+    async fn put_object(
+        &self,
+        _principal: String,
+        _addressbook_id: String,
+        _object: AddressObject,
+        _overwrite: bool,
+        _changed_by: Option<&str>,
+    ) -> Result<(), Error> {
+        Err(Error::ReadOnly)
+    }
+    async fn delete_object(
+        &self,
+        _principal: &str,
+        _addressbook_id: &str,
+        _object_id: &str,
+        _use_trashbin: bool,
+    ) -> Result<(), Error> {
+        Err(Error::ReadOnly)
+    }
+    async fn restore_object(
+        &self,
+        _principal: &str,
+        _addressbook_id: &str,
+        _object_id: &str,
+    ) -> Result<(), Error> {
+        Err(Error::ReadOnly)
+    }
+    async fn get_deleted_objects(
+        &self,
+        _principal: &str,
+        _addressbook_id: &str,
+    ) -> Result<Vec<AddressObject>, Error> {
+        Ok(vec![])
+    }
+    async fn get_used_bytes(&self, _principal: &str) -> Result<u64, Error> {
+        Ok(0)
+    }
+    async fn purge_trashbin(&self, _deleted_before: NaiveDateTime) -> Result<usize, Error> {
+        Ok(0)
+    }
+    async fn compact_changelog(&self, _horizon: NaiveDateTime) -> Result<usize, Error> {
+        Ok(0)
+    }
+
+    async fn get_object_revisions(
+        &self,
+        _principal: &str,
+        _addressbook_id: &str,
+        _object_id: &str,
+    ) -> Result<Vec<ObjectRevision<AddressObject>>, Error> {
+        Ok(vec![])
+    }
+
+    async fn get_birthday_settings(
+        &self,
+        _principal: &str,
+    ) -> Result<BirthdayCalendarSettings, Error> {
+        Ok(BirthdayCalendarSettings::default())
+    }
+    async fn update_birthday_settings(
+        &self,
+        _principal: &str,
+        _settings: BirthdayCalendarSettings,
+    ) -> Result<(), Error> {
+        Err(Error::ReadOnly)
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+}
+
+#[test]
+fn test_principal_vcard() {
+    let principal = User {
+        id: "alice".to_owned(),
+        displayname: Some("Alice, from Accounting".to_owned()),
+        principal_type: PrincipalType::Individual,
+        password: None,
+        app_tokens: vec![],
+        memberships: vec![],
+        emails: vec!["alice@example.com".to_owned(), "a@example.org".to_owned()],
+    };
+    let object = principal_vcard(&principal).unwrap();
+    assert_eq!(object.get_id(), "alice");
+    assert_eq!(
+        object.get_full_name().map(String::as_str),
+        Some("Alice\\, from Accounting")
+    );
+    assert!(object.get_vcf().contains("KIND:individual\r\n"));
+    assert!(
+        object
+            .get_vcf()
+            .contains("EMAIL;PREF=1:alice@example.com\r\n")
+    );
+    assert!(object.get_vcf().contains("EMAIL:a@example.org\r\n"));
+}
+
+#[tokio::test]
+async fn test_directory_sync_changes() {
+    use crate::memory::MemoryPrincipalStore;
+
+    let user = |id: &str| User {
+        id: id.to_owned(),
+        displayname: None,
+        principal_type: PrincipalType::Individual,
+        password: None,
+        app_tokens: vec![],
+        memberships: vec![],
+        emails: vec![],
+    };
+    let auth = Arc::new(MemoryPrincipalStore::new(vec![user("alice"), user("bob")]));
+    let store = DirectoryStore::new(auth.clone());
+
+    let (objects, deleted, synctoken, _) = store
+        .sync_changes("alice", DIRECTORY_ID, 0, None)
+        .await
+        .unwrap();
+    assert_eq!(objects.len(), 2);
+    assert!(deleted.is_empty());
+
+    let (objects, _, unchanged, _) = store
+        .sync_changes("alice", DIRECTORY_ID, synctoken, None)
+        .await
+        .unwrap();
+    assert!(objects.is_empty());
+    assert_eq!(unchanged, synctoken);
+
+    // Removed principals can't be reported, so the client has to start over
+    auth.remove_principal("bob").await.unwrap();
+    assert!(matches!(
+        store
+            .sync_changes("alice", DIRECTORY_ID, synctoken, None)
+            .await,
+        Err(Error::InvalidSyncToken)
+    ));
+}
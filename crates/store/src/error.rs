@@ -14,6 +14,15 @@ pub enum Error {
     #[error("Read-only")]
     ReadOnly,
 
+    #[error("Sync token is outside the retained changelog")]
+    InvalidSyncToken,
+
+    #[error("Quota exceeded")]
+    QuotaExceeded,
+
+    #[error("Resource exceeds max-resource-size")]
+    MaxResourceSizeExceeded,
+
     #[error("Error generating password hash")]
     PasswordHash,
 
@@ -34,6 +43,9 @@ impl ResponseError for Error {
             Self::AlreadyExists => StatusCode::CONFLICT,
             Self::InvalidData(_) => StatusCode::BAD_REQUEST,
             Self::ReadOnly => StatusCode::FORBIDDEN,
+            Self::InvalidSyncToken => StatusCode::FORBIDDEN,
+            Self::QuotaExceeded => StatusCode::INSUFFICIENT_STORAGE,
+            Self::MaxResourceSizeExceeded => StatusCode::FORBIDDEN,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
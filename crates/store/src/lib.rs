@@ -5,18 +5,28 @@ pub mod error;
 pub use error::Error;
 pub mod auth;
 pub mod calendar;
+pub mod changelog;
 mod contact_birthday_store;
+mod directory_store;
+pub mod memory;
+pub mod quota;
+pub mod revision;
 mod secret;
 mod subscription_store;
 pub mod synctoken;
+pub mod trashbin;
+#[cfg(feature = "testing")]
+pub mod testing;
 
 pub use addressbook_store::AddressbookStore;
 pub use calendar_store::CalendarStore;
 pub use contact_birthday_store::ContactBirthdayStore;
+pub use directory_store::{DIRECTORY_ID, DirectoryStore};
+pub use revision::ObjectRevision;
 pub use secret::Secret;
 pub use subscription_store::*;
 
-pub use addressbook::{AddressObject, Addressbook};
+pub use addressbook::{AddressObject, Addressbook, BirthdayCalendarSettings};
 pub use calendar::{Calendar, CalendarObject};
 
 #[derive(Debug, Clone)]
@@ -0,0 +1,395 @@
+use super::MemoryCollection;
+use crate::synctoken::format_synctoken;
+use crate::{
+    AddressObject, Addressbook, AddressbookStore, BirthdayCalendarSettings, CollectionOperation,
+    CollectionOperationDomain, CollectionOperationType, Error, ObjectRevision,
+};
+use async_trait::async_trait;
+use chrono::{NaiveDateTime, Utc};
+use std::collections::HashMap;
+use tokio::sync::RwLock;
+use tokio::sync::mpsc::Sender;
+use tracing::error;
+
+type AddressbookCollection = MemoryCollection<Addressbook, AddressObject>;
+
+#[derive(Debug)]
+pub struct MemoryAddressbookStore {
+    /// Addressbooks by (principal, id)
+    addressbooks: RwLock<HashMap<(String, String), AddressbookCollection>>,
+    birthday_settings: RwLock<HashMap<String, BirthdayCalendarSettings>>,
+    sender: Sender<CollectionOperation>,
+}
+
+impl MemoryAddressbookStore {
+    pub fn new(sender: Sender<CollectionOperation>) -> Self {
+        Self {
+            addressbooks: RwLock::default(),
+            birthday_settings: RwLock::default(),
+            sender,
+        }
+    }
+
+    fn key(principal: &str, id: &str) -> (String, String) {
+        (principal.to_owned(), id.to_owned())
+    }
+
+    fn to_addressbook(collection: &AddressbookCollection) -> Addressbook {
+        Addressbook {
+            synctoken: collection.synctoken,
+            updated_at: Some(collection.updated_at),
+            ..collection.collection.clone()
+        }
+    }
+
+    fn send_push_notification(
+        &self,
+        r#type: CollectionOperationType,
+        topic: String,
+        synctoken: Option<i64>,
+    ) {
+        if let Err(err) = self.sender.try_send(CollectionOperation {
+            r#type,
+            domain: CollectionOperationDomain::Addressbook,
+            topic,
+            sync_token: synctoken.map(format_synctoken),
+        }) {
+            error!("Push notification about addressbook failed: {err}");
+        }
+    }
+
+    /// Applies an object operation and sends a push notification about it
+    async fn modify_objects(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+        operation: impl FnOnce(&mut AddressbookCollection) -> Result<i64, Error>,
+    ) -> Result<(), Error> {
+        let (topic, synctoken) = {
+            let mut addressbooks = self.addressbooks.write().await;
+            let addressbook = addressbooks
+                .get_mut(&Self::key(principal, addressbook_id))
+                .ok_or(Error::NotFound)?;
+            let synctoken = operation(addressbook)?;
+            (addressbook.collection.push_topic.to_owned(), synctoken)
+        };
+        self.send_push_notification(CollectionOperationType::Object, topic, Some(synctoken));
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl AddressbookStore for MemoryAddressbookStore {
+    async fn get_addressbook(&self, principal: &str, id: &str) -> Result<Addressbook, Error> {
+        self.addressbooks
+            .read()
+            .await
+            .get(&Self::key(principal, id))
+            .map(Self::to_addressbook)
+            .ok_or(Error::NotFound)
+    }
+
+    async fn get_addressbooks(&self, principal: &str) -> Result<Vec<Addressbook>, Error> {
+        Ok(self
+            .addressbooks
+            .read()
+            .await
+            .values()
+            .filter(|addressbook| addressbook.collection.principal == principal)
+            .filter(|addressbook| addressbook.collection.deleted_at.is_none())
+            .map(Self::to_addressbook)
+            .collect())
+    }
+
+    async fn get_deleted_addressbooks(&self, principal: &str) -> Result<Vec<Addressbook>, Error> {
+        Ok(self
+            .addressbooks
+            .read()
+            .await
+            .values()
+            .filter(|addressbook| addressbook.collection.principal == principal)
+            .filter(|addressbook| addressbook.collection.deleted_at.is_some())
+            .map(Self::to_addressbook)
+            .collect())
+    }
+
+    async fn update_addressbook(
+        &self,
+        principal: String,
+        id: String,
+        addressbook: Addressbook,
+    ) -> Result<(), Error> {
+        let mut addressbooks = self.addressbooks.write().await;
+        let new_key = Self::key(&addressbook.principal, &addressbook.id);
+        let old_key = Self::key(&principal, &id);
+        if new_key != old_key && addressbooks.contains_key(&new_key) {
+            return Err(Error::AlreadyExists);
+        }
+        let mut collection = addressbooks.remove(&old_key).ok_or(Error::NotFound)?;
+        collection.updated_at = Utc::now().naive_utc();
+        collection.collection = Addressbook {
+            // Not updated through this method
+            created_at: collection.collection.created_at,
+            deleted_at: collection.collection.deleted_at,
+            ..addressbook
+        };
+        addressbooks.insert(new_key, collection);
+        Ok(())
+    }
+
+    async fn insert_addressbook(&self, addressbook: Addressbook) -> Result<(), Error> {
+        let mut addressbooks = self.addressbooks.write().await;
+        let key = Self::key(&addressbook.principal, &addressbook.id);
+        if addressbooks.contains_key(&key) {
+            return Err(Error::AlreadyExists);
+        }
+        addressbooks.insert(
+            key,
+            MemoryCollection::new(Addressbook {
+                created_at: Some(Utc::now().naive_utc()),
+                deleted_at: None,
+                synctoken: 0,
+                ..addressbook
+            }),
+        );
+        Ok(())
+    }
+
+    async fn delete_addressbook(
+        &self,
+        principal: &str,
+        name: &str,
+        use_trashbin: bool,
+    ) -> Result<(), Error> {
+        let topic = {
+            let mut addressbooks = self.addressbooks.write().await;
+            let key = Self::key(principal, name);
+            match addressbooks.get_mut(&key) {
+                Some(addressbook) if use_trashbin => {
+                    addressbook.collection.deleted_at = Some(Utc::now().naive_utc());
+                    addressbook.collection.push_topic.to_owned()
+                }
+                Some(_) => addressbooks.remove(&key).unwrap().collection.push_topic,
+                None => return Ok(()),
+            }
+        };
+        self.send_push_notification(CollectionOperationType::Delete, topic, None);
+        Ok(())
+    }
+
+    async fn restore_addressbook(&self, principal: &str, name: &str) -> Result<(), Error> {
+        self.addressbooks
+            .write()
+            .await
+            .get_mut(&Self::key(principal, name))
+            .ok_or(Error::NotFound)?
+            .collection
+            .deleted_at = None;
+        Ok(())
+    }
+
+    async fn sync_changes(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+        synctoken: i64,
+        limit: Option<usize>,
+    ) -> Result<(Vec<AddressObject>, Vec<String>, i64, bool), Error> {
+        self.addressbooks
+            .read()
+            .await
+            .get(&Self::key(principal, addressbook_id))
+            .ok_or(Error::NotFound)?
+            .sync_changes(synctoken, limit)
+    }
+
+    async fn get_objects(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+    ) -> Result<Vec<AddressObject>, Error> {
+        Ok(self
+            .addressbooks
+            .read()
+            .await
+            .get(&Self::key(principal, addressbook_id))
+            .ok_or(Error::NotFound)?
+            .objects
+            .values()
+            .cloned()
+            .collect())
+    }
+
+    async fn get_object(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+        object_id: &str,
+    ) -> Result<AddressObject, Error> {
+        self.addressbooks
+            .read()
+            .await
+            .get(&Self::key(principal, addressbook_id))
+            .ok_or(Error::NotFound)?
+            .objects
+            .get(object_id)
+            .cloned()
+            .ok_or(Error::NotFound)
+    }
+
+    async fn put_object(
+        &self,
+        principal: String,
+        addressbook_id: String,
+        object: AddressObject,
+        overwrite: bool,
+        changed_by: Option<&str>,
+    ) -> Result<(), Error> {
+        let object_id = object.get_id().to_owned();
+        self.modify_objects(&principal, &addressbook_id, |addressbook| {
+            addressbook.put_object(&object_id, object, overwrite, changed_by)
+        })
+        .await
+    }
+
+    async fn delete_object(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+        object_id: &str,
+        use_trashbin: bool,
+    ) -> Result<(), Error> {
+        self.modify_objects(principal, addressbook_id, |addressbook| {
+            addressbook.delete_object(object_id, use_trashbin)
+        })
+        .await
+    }
+
+    async fn restore_object(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+        object_id: &str,
+    ) -> Result<(), Error> {
+        self.modify_objects(principal, addressbook_id, |addressbook| {
+            addressbook.restore_object(object_id)
+        })
+        .await
+    }
+
+    async fn get_deleted_objects(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+    ) -> Result<Vec<AddressObject>, Error> {
+        Ok(self
+            .addressbooks
+            .read()
+            .await
+            .get(&Self::key(principal, addressbook_id))
+            .ok_or(Error::NotFound)?
+            .deleted_objects())
+    }
+
+    async fn get_used_bytes(&self, principal: &str) -> Result<u64, Error> {
+        Ok(self
+            .addressbooks
+            .read()
+            .await
+            .values()
+            .filter(|addressbook| addressbook.collection.principal == principal)
+            .flat_map(|addressbook| addressbook.objects.values())
+            .map(|object| object.get_vcf().len() as u64)
+            .sum())
+    }
+
+    async fn compact_changelog(&self, horizon: NaiveDateTime) -> Result<usize, Error> {
+        Ok(self
+            .addressbooks
+            .write()
+            .await
+            .values_mut()
+            .map(|collection| collection.compact_changelog(horizon))
+            .sum())
+    }
+
+    async fn purge_trashbin(&self, deleted_before: NaiveDateTime) -> Result<usize, Error> {
+        let mut addressbooks = self.addressbooks.write().await;
+        let count = addressbooks.len();
+        addressbooks.retain(|_, collection| {
+            collection
+                .collection
+                .deleted_at
+                .is_none_or(|deleted_at| deleted_at >= deleted_before)
+        });
+        let mut count = count - addressbooks.len();
+        for collection in addressbooks.values_mut() {
+            count += collection.purge_trash(deleted_before);
+        }
+        Ok(count)
+    }
+
+    async fn get_object_revisions(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+        object_id: &str,
+    ) -> Result<Vec<ObjectRevision<AddressObject>>, Error> {
+        Ok(self
+            .addressbooks
+            .read()
+            .await
+            .get(&Self::key(principal, addressbook_id))
+            .ok_or(Error::NotFound)?
+            .object_revisions(object_id))
+    }
+
+    async fn get_birthday_settings(
+        &self,
+        principal: &str,
+    ) -> Result<BirthdayCalendarSettings, Error> {
+        Ok(self
+            .birthday_settings
+            .read()
+            .await
+            .get(principal)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn update_birthday_settings(
+        &self,
+        principal: &str,
+        settings: BirthdayCalendarSettings,
+    ) -> Result<(), Error> {
+        settings.validate()?;
+        let previous = self
+            .birthday_settings
+            .write()
+            .await
+            .insert(principal.to_owned(), settings.clone());
+        if previous.unwrap_or_default() == settings {
+            return Ok(());
+        }
+        // Every contact is rendered differently in the birthday calendars now
+        let notifications: Vec<_> = self
+            .addressbooks
+            .write()
+            .await
+            .iter_mut()
+            .filter(|((addressbook_principal, _), _)| addressbook_principal == principal)
+            .map(|(_, addressbook)| {
+                let synctoken = addressbook.log_all_objects();
+                (addressbook.collection.push_topic.to_owned(), synctoken)
+            })
+            .collect();
+        for (topic, synctoken) in notifications {
+            self.send_push_notification(CollectionOperationType::Object, topic, Some(synctoken));
+        }
+        Ok(())
+    }
+
+    fn is_read_only(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,91 @@
+use crate::{
+    Error,
+    auth::{AuthenticationProvider, User, user::AppToken},
+};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use tokio::sync::RwLock;
+
+#[derive(Debug, Default)]
+pub struct MemoryPrincipalStore {
+    principals: RwLock<HashMap<String, User>>,
+}
+
+impl MemoryPrincipalStore {
+    pub fn new(principals: Vec<User>) -> Self {
+        Self {
+            principals: RwLock::new(
+                principals
+                    .into_iter()
+                    .map(|user| (user.id.clone(), user))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+#[async_trait]
+impl AuthenticationProvider for MemoryPrincipalStore {
+    async fn get_principals(&self) -> Result<Vec<User>, Error> {
+        Ok(self.principals.read().await.values().cloned().collect())
+    }
+
+    async fn get_principal(&self, id: &str) -> Result<Option<User>, Error> {
+        Ok(self.principals.read().await.get(id).cloned())
+    }
+
+    async fn remove_principal(&self, id: &str) -> Result<(), Error> {
+        self.principals.write().await.remove(id);
+        Ok(())
+    }
+
+    async fn insert_principal(&self, user: User, overwrite: bool) -> Result<(), Error> {
+        let mut principals = self.principals.write().await;
+        if !overwrite && principals.contains_key(&user.id) {
+            return Err(Error::AlreadyExists);
+        }
+        principals.insert(user.id.clone(), user);
+        Ok(())
+    }
+
+    async fn validate_password(
+        &self,
+        user_id: &str,
+        password: &str,
+    ) -> Result<Option<User>, Error> {
+        Ok(self
+            .get_principal(user_id)
+            .await?
+            .filter(|user| user.verify_password(password)))
+    }
+
+    async fn validate_app_token(&self, user_id: &str, token: &str) -> Result<Option<User>, Error> {
+        Ok(self
+            .get_principal(user_id)
+            .await?
+            .filter(|user| user.verify_app_token(token)))
+    }
+
+    async fn add_app_token(
+        &self,
+        user_id: &str,
+        name: String,
+        token: String,
+    ) -> Result<String, Error> {
+        let mut principals = self.principals.write().await;
+        let principal = principals.get_mut(user_id).ok_or(Error::NotFound)?;
+        let app_token = AppToken::new(name, &token)?;
+        let id = app_token.id.clone();
+        principal.app_tokens.push(app_token);
+        Ok(id)
+    }
+
+    async fn remove_app_token(&self, user_id: &str, token_id: &str) -> Result<(), Error> {
+        if let Some(principal) = self.principals.write().await.get_mut(user_id) {
+            principal
+                .app_tokens
+                .retain(|app_token| app_token.id != token_id);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,378 @@
+use super::MemoryCollection;
+use crate::calendar_store::CalendarQuery;
+use crate::synctoken::format_synctoken;
+use crate::{
+    Calendar, CalendarObject, CalendarStore, CollectionOperation, CollectionOperationDomain,
+    CollectionOperationType, Error, ObjectRevision,
+};
+use async_trait::async_trait;
+use chrono::{NaiveDateTime, Utc};
+use std::collections::HashMap;
+use tokio::sync::RwLock;
+use tokio::sync::mpsc::Sender;
+use tracing::error;
+
+type CalendarCollection = MemoryCollection<Calendar, CalendarObject>;
+
+#[derive(Debug)]
+pub struct MemoryCalendarStore {
+    /// Calendars by (principal, id)
+    calendars: RwLock<HashMap<(String, String), CalendarCollection>>,
+    sender: Sender<CollectionOperation>,
+}
+
+impl MemoryCalendarStore {
+    pub fn new(sender: Sender<CollectionOperation>) -> Self {
+        Self {
+            calendars: RwLock::default(),
+            sender,
+        }
+    }
+
+    fn key(principal: &str, id: &str) -> (String, String) {
+        (principal.to_owned(), id.to_owned())
+    }
+
+    fn to_calendar(collection: &CalendarCollection) -> Calendar {
+        Calendar {
+            synctoken: collection.synctoken,
+            updated_at: Some(collection.updated_at),
+            ..collection.collection.clone()
+        }
+    }
+
+    fn send_push_notification(
+        &self,
+        r#type: CollectionOperationType,
+        topic: String,
+        synctoken: Option<i64>,
+    ) {
+        if let Err(err) = self.sender.try_send(CollectionOperation {
+            r#type,
+            domain: CollectionOperationDomain::Calendar,
+            topic,
+            sync_token: synctoken.map(format_synctoken),
+        }) {
+            error!("Push notification about calendar failed: {err}");
+        }
+    }
+
+    /// Applies an object operation and sends a push notification about it
+    async fn modify_objects(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        operation: impl FnOnce(&mut CalendarCollection) -> Result<i64, Error>,
+    ) -> Result<(), Error> {
+        let (topic, synctoken) = {
+            let mut calendars = self.calendars.write().await;
+            let calendar = calendars
+                .get_mut(&Self::key(principal, cal_id))
+                .ok_or(Error::NotFound)?;
+            let synctoken = operation(calendar)?;
+            (calendar.collection.push_topic.to_owned(), synctoken)
+        };
+        self.send_push_notification(CollectionOperationType::Object, topic, Some(synctoken));
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl CalendarStore for MemoryCalendarStore {
+    async fn get_calendar(&self, principal: &str, id: &str) -> Result<Calendar, Error> {
+        self.calendars
+            .read()
+            .await
+            .get(&Self::key(principal, id))
+            .map(Self::to_calendar)
+            .ok_or(Error::NotFound)
+    }
+
+    async fn get_calendars(&self, principal: &str) -> Result<Vec<Calendar>, Error> {
+        Ok(self
+            .calendars
+            .read()
+            .await
+            .values()
+            .filter(|cal| cal.collection.principal == principal)
+            .filter(|cal| cal.collection.deleted_at.is_none())
+            .map(Self::to_calendar)
+            .collect())
+    }
+
+    async fn get_deleted_calendars(&self, principal: &str) -> Result<Vec<Calendar>, Error> {
+        Ok(self
+            .calendars
+            .read()
+            .await
+            .values()
+            .filter(|cal| cal.collection.principal == principal)
+            .filter(|cal| cal.collection.deleted_at.is_some())
+            .map(Self::to_calendar)
+            .collect())
+    }
+
+    async fn update_calendar(
+        &self,
+        principal: String,
+        id: String,
+        calendar: Calendar,
+    ) -> Result<(), Error> {
+        let mut calendars = self.calendars.write().await;
+        let new_key = Self::key(&calendar.principal, &calendar.id);
+        let old_key = Self::key(&principal, &id);
+        if new_key != old_key && calendars.contains_key(&new_key) {
+            return Err(Error::AlreadyExists);
+        }
+        let mut collection = calendars.remove(&old_key).ok_or(Error::NotFound)?;
+        collection.updated_at = Utc::now().naive_utc();
+        collection.collection = Calendar {
+            // Not updated through this method
+            created_at: collection.collection.created_at,
+            deleted_at: collection.collection.deleted_at,
+            subscription_url: collection.collection.subscription_url,
+            ..calendar
+        };
+        calendars.insert(new_key, collection);
+        Ok(())
+    }
+
+    async fn insert_calendar(&self, calendar: Calendar) -> Result<(), Error> {
+        let mut calendars = self.calendars.write().await;
+        let key = Self::key(&calendar.principal, &calendar.id);
+        if calendars.contains_key(&key) {
+            return Err(Error::AlreadyExists);
+        }
+        calendars.insert(
+            key,
+            MemoryCollection::new(Calendar {
+                created_at: Some(Utc::now().naive_utc()),
+                deleted_at: None,
+                synctoken: 0,
+                ..calendar
+            }),
+        );
+        Ok(())
+    }
+
+    async fn delete_calendar(
+        &self,
+        principal: &str,
+        name: &str,
+        use_trashbin: bool,
+    ) -> Result<(), Error> {
+        let topic = {
+            let mut calendars = self.calendars.write().await;
+            let key = Self::key(principal, name);
+            match calendars.get_mut(&key) {
+                Some(cal) if use_trashbin => {
+                    cal.collection.deleted_at = Some(Utc::now().naive_utc());
+                    cal.collection.push_topic.to_owned()
+                }
+                Some(_) => calendars.remove(&key).unwrap().collection.push_topic,
+                None => return Ok(()),
+            }
+        };
+        self.send_push_notification(CollectionOperationType::Delete, topic, None);
+        Ok(())
+    }
+
+    async fn restore_calendar(&self, principal: &str, name: &str) -> Result<(), Error> {
+        self.calendars
+            .write()
+            .await
+            .get_mut(&Self::key(principal, name))
+            .ok_or(Error::NotFound)?
+            .collection
+            .deleted_at = None;
+        Ok(())
+    }
+
+    async fn sync_changes(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        synctoken: i64,
+        limit: Option<usize>,
+    ) -> Result<(Vec<CalendarObject>, Vec<String>, i64, bool), Error> {
+        self.calendars
+            .read()
+            .await
+            .get(&Self::key(principal, cal_id))
+            .ok_or(Error::NotFound)?
+            .sync_changes(synctoken, limit)
+    }
+
+    async fn calendar_query(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        query: CalendarQuery,
+    ) -> Result<Vec<CalendarObject>, Error> {
+        let (start, end) = query.occurence_dates();
+
+        Ok(self
+            .get_objects(principal, cal_id)
+            .await?
+            .into_iter()
+            .filter(|object| {
+                let first_occurence = object.get_first_occurence().ok().flatten();
+                let last_occurence = object.get_last_occurence().ok().flatten();
+                let after_start = match (start, last_occurence) {
+                    (Some(start), Some(last_occurence)) => last_occurence.date() >= start,
+                    _ => true,
+                };
+                let before_end = match (end, first_occurence) {
+                    (Some(end), Some(first_occurence)) => first_occurence.date() <= end,
+                    _ => true,
+                };
+                after_start && before_end
+            })
+            .collect())
+    }
+
+    async fn get_objects(
+        &self,
+        principal: &str,
+        cal_id: &str,
+    ) -> Result<Vec<CalendarObject>, Error> {
+        Ok(self
+            .calendars
+            .read()
+            .await
+            .get(&Self::key(principal, cal_id))
+            .ok_or(Error::NotFound)?
+            .objects
+            .values()
+            .cloned()
+            .collect())
+    }
+
+    async fn get_object(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        object_id: &str,
+    ) -> Result<CalendarObject, Error> {
+        self.calendars
+            .read()
+            .await
+            .get(&Self::key(principal, cal_id))
+            .ok_or(Error::NotFound)?
+            .objects
+            .get(object_id)
+            .cloned()
+            .ok_or(Error::NotFound)
+    }
+
+    async fn put_object(
+        &self,
+        principal: String,
+        cal_id: String,
+        object: CalendarObject,
+        overwrite: bool,
+        changed_by: Option<&str>,
+    ) -> Result<(), Error> {
+        let object_id = object.get_id().to_owned();
+        self.modify_objects(&principal, &cal_id, |calendar| {
+            calendar.put_object(&object_id, object, overwrite, changed_by)
+        })
+        .await
+    }
+
+    async fn delete_object(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        object_id: &str,
+        use_trashbin: bool,
+    ) -> Result<(), Error> {
+        self.modify_objects(principal, cal_id, |calendar| {
+            calendar.delete_object(object_id, use_trashbin)
+        })
+        .await
+    }
+
+    async fn restore_object(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        object_id: &str,
+    ) -> Result<(), Error> {
+        self.modify_objects(principal, cal_id, |calendar| {
+            calendar.restore_object(object_id)
+        })
+        .await
+    }
+
+    async fn get_deleted_objects(
+        &self,
+        principal: &str,
+        cal_id: &str,
+    ) -> Result<Vec<CalendarObject>, Error> {
+        Ok(self
+            .calendars
+            .read()
+            .await
+            .get(&Self::key(principal, cal_id))
+            .ok_or(Error::NotFound)?
+            .deleted_objects())
+    }
+
+    async fn get_used_bytes(&self, principal: &str) -> Result<u64, Error> {
+        Ok(self
+            .calendars
+            .read()
+            .await
+            .values()
+            .filter(|cal| cal.collection.principal == principal)
+            .flat_map(|cal| cal.objects.values())
+            .map(|object| object.get_ics().len() as u64)
+            .sum())
+    }
+
+    async fn compact_changelog(&self, horizon: NaiveDateTime) -> Result<usize, Error> {
+        Ok(self
+            .calendars
+            .write()
+            .await
+            .values_mut()
+            .map(|collection| collection.compact_changelog(horizon))
+            .sum())
+    }
+
+    async fn purge_trashbin(&self, deleted_before: NaiveDateTime) -> Result<usize, Error> {
+        let mut calendars = self.calendars.write().await;
+        let count = calendars.len();
+        calendars.retain(|_, collection| {
+            collection
+                .collection
+                .deleted_at
+                .is_none_or(|deleted_at| deleted_at >= deleted_before)
+        });
+        let mut count = count - calendars.len();
+        for collection in calendars.values_mut() {
+            count += collection.purge_trash(deleted_before);
+        }
+        Ok(count)
+    }
+
+    async fn get_object_revisions(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        object_id: &str,
+    ) -> Result<Vec<ObjectRevision<CalendarObject>>, Error> {
+        Ok(self
+            .calendars
+            .read()
+            .await
+            .get(&Self::key(principal, cal_id))
+            .ok_or(Error::NotFound)?
+            .object_revisions(object_id))
+    }
+
+    fn is_read_only(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,425 @@
+//! Store implementations that keep everything in memory
+//!
+//! Useful for tests and ephemeral deployments since they don't need a database.
+//! They behave like the persistent stores regarding synctokens, the trashbin and push notifications.
+mod addressbook_store;
+mod auth;
+mod calendar_store;
+mod subscription_store;
+
+pub use addressbook_store::MemoryAddressbookStore;
+pub use auth::MemoryPrincipalStore;
+pub use calendar_store::MemoryCalendarStore;
+pub use subscription_store::MemorySubscriptionStore;
+
+use crate::revision::{MAX_REVISIONS, ObjectRevision};
+use crate::synctoken::{resolve_synctoken, truncate_changes};
+use crate::{AddressObject, CalendarObject, Error};
+use chrono::{NaiveDateTime, Utc};
+use std::collections::{HashMap, HashSet};
+
+/// Objects that carry the timestamps tracked by the store
+trait Timestamped {
+    fn get_created_at(&self) -> Option<NaiveDateTime>;
+    fn with_timestamps(self, created_at: NaiveDateTime, updated_at: NaiveDateTime) -> Self;
+}
+
+impl Timestamped for CalendarObject {
+    fn get_created_at(&self) -> Option<NaiveDateTime> {
+        CalendarObject::get_created_at(self)
+    }
+
+    fn with_timestamps(self, created_at: NaiveDateTime, updated_at: NaiveDateTime) -> Self {
+        CalendarObject::with_timestamps(self, Some(created_at), Some(updated_at))
+    }
+}
+
+impl Timestamped for AddressObject {
+    fn get_created_at(&self) -> Option<NaiveDateTime> {
+        AddressObject::get_created_at(self)
+    }
+
+    fn with_timestamps(self, created_at: NaiveDateTime, updated_at: NaiveDateTime) -> Self {
+        AddressObject::with_timestamps(self, Some(created_at), Some(updated_at))
+    }
+}
+
+#[cfg(test)]
+impl Timestamped for String {
+    fn get_created_at(&self) -> Option<NaiveDateTime> {
+        None
+    }
+
+    fn with_timestamps(self, _created_at: NaiveDateTime, _updated_at: NaiveDateTime) -> Self {
+        self
+    }
+}
+
+/// A collection with its objects, trashbin and changelog
+#[derive(Debug, Clone)]
+struct MemoryCollection<C, O> {
+    collection: C,
+    synctoken: i64,
+    /// Time of the last change to the collection or its objects
+    updated_at: NaiveDateTime,
+    objects: HashMap<String, O>,
+    /// Objects with the time they were deleted
+    trash: HashMap<String, (O, NaiveDateTime)>,
+    /// (synctoken, object_id, created_at)
+    changelog: Vec<(i64, String, NaiveDateTime)>,
+    /// Oldest synctoken that's still valid after compacting the changelog
+    min_synctoken: i64,
+    /// Most recent revisions of every object, oldest first
+    revisions: HashMap<String, Vec<ObjectRevision<O>>>,
+}
+
+impl<C, O: Clone + Timestamped> MemoryCollection<C, O> {
+    fn new(collection: C) -> Self {
+        Self {
+            collection,
+            synctoken: 0,
+            updated_at: Utc::now().naive_utc(),
+            objects: HashMap::new(),
+            trash: HashMap::new(),
+            changelog: vec![],
+            min_synctoken: 0,
+            revisions: HashMap::new(),
+        }
+    }
+
+    fn log_change(&mut self, object_id: &str) -> i64 {
+        self.synctoken += 1;
+        self.updated_at = Utc::now().naive_utc();
+        self.changelog
+            .push((self.synctoken, object_id.to_owned(), self.updated_at));
+        self.synctoken
+    }
+
+    /// Logs a change of every object, e.g. because their rendering as birthdays changed
+    fn log_all_objects(&mut self) -> i64 {
+        let mut object_ids: Vec<String> = self.objects.keys().cloned().collect();
+        object_ids.sort();
+        for object_id in object_ids {
+            self.log_change(&object_id);
+        }
+        self.synctoken
+    }
+
+    fn put_object(
+        &mut self,
+        object_id: &str,
+        object: O,
+        overwrite: bool,
+        changed_by: Option<&str>,
+    ) -> Result<i64, Error> {
+        if !overwrite && self.objects.contains_key(object_id) {
+            return Err(Error::AlreadyExists);
+        }
+        let now = Utc::now().naive_utc();
+        // Like in the database stores a trashed object keeps its creation date
+        let created_at = self
+            .objects
+            .get(object_id)
+            .or(self.trash.get(object_id).map(|(object, _)| object))
+            .and_then(Timestamped::get_created_at)
+            .unwrap_or(now);
+        let object = object.with_timestamps(created_at, now);
+        self.trash.remove(object_id);
+        self.objects.insert(object_id.to_owned(), object.clone());
+        let synctoken = self.log_change(object_id);
+
+        let revisions = self.revisions.entry(object_id.to_owned()).or_default();
+        revisions.push(ObjectRevision {
+            revision: synctoken,
+            created_at: Utc::now().naive_utc(),
+            changed_by: changed_by.map(str::to_owned),
+            object,
+        });
+        if revisions.len() > MAX_REVISIONS {
+            revisions.drain(..revisions.len() - MAX_REVISIONS);
+        }
+        Ok(synctoken)
+    }
+
+    fn delete_object(&mut self, object_id: &str, use_trashbin: bool) -> Result<i64, Error> {
+        match self.objects.remove(object_id) {
+            Some(object) if use_trashbin => {
+                self.trash
+                    .insert(object_id.to_owned(), (object, Utc::now().naive_utc()));
+            }
+            Some(_) => {
+                self.revisions.remove(object_id);
+            }
+            None if !use_trashbin && self.trash.remove(object_id).is_some() => {
+                self.revisions.remove(object_id);
+            }
+            None => return Err(Error::NotFound),
+        }
+        Ok(self.log_change(object_id))
+    }
+
+    fn restore_object(&mut self, object_id: &str) -> Result<i64, Error> {
+        if self.objects.contains_key(object_id) {
+            return Err(Error::AlreadyExists);
+        }
+        let (object, _) = self.trash.remove(object_id).ok_or(Error::NotFound)?;
+        let created_at = object
+            .get_created_at()
+            .unwrap_or_else(|| Utc::now().naive_utc());
+        let object = object.with_timestamps(created_at, Utc::now().naive_utc());
+        self.objects.insert(object_id.to_owned(), object);
+        Ok(self.log_change(object_id))
+    }
+
+    /// Newest first
+    fn object_revisions(&self, object_id: &str) -> Vec<ObjectRevision<O>> {
+        self.revisions
+            .get(object_id)
+            .map(|revisions| revisions.iter().rev().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    fn deleted_objects(&self) -> Vec<O> {
+        self.trash
+            .values()
+            .map(|(object, _)| object.clone())
+            .collect()
+    }
+
+    /// Returns the number of purged objects
+    fn purge_trash(&mut self, deleted_before: NaiveDateTime) -> usize {
+        let count = self.trash.len();
+        self.trash
+            .retain(|_, (_, deleted_at)| *deleted_at >= deleted_before);
+        let count = count - self.trash.len();
+        self.revisions.retain(|object_id, _| {
+            self.objects.contains_key(object_id) || self.trash.contains_key(object_id)
+        });
+
+        // Only the latest change of a deleted object is needed to report its deletion
+        let mut seen = HashSet::new();
+        let mut changelog = std::mem::take(&mut self.changelog);
+        changelog.reverse();
+        changelog.retain(|(_, object_id, _)| {
+            self.objects.contains_key(object_id)
+                || self.trash.contains_key(object_id)
+                || seen.insert(object_id.to_owned())
+        });
+        changelog.reverse();
+        self.changelog = changelog;
+        count
+    }
+
+    /// Returns the number of dropped changes
+    fn compact_changelog(&mut self, horizon: NaiveDateTime) -> usize {
+        let count = self.changelog.len();
+        for (synctoken, _, created_at) in &self.changelog {
+            if *created_at < horizon {
+                self.min_synctoken = self.min_synctoken.max(*synctoken);
+            }
+        }
+
+        // Keep the latest change of every object, if it's older than the horizon only
+        // for objects that still exist so that an initial sync still lists them
+        let mut seen = HashSet::new();
+        let mut changelog = std::mem::take(&mut self.changelog);
+        changelog.reverse();
+        changelog.retain(|(_, object_id, created_at)| {
+            seen.insert(object_id.to_owned())
+                && (*created_at >= horizon || self.objects.contains_key(object_id))
+        });
+        changelog.reverse();
+        self.changelog = changelog;
+        count - self.changelog.len()
+    }
+
+    fn sync_changes(
+        &self,
+        synctoken: i64,
+        limit: Option<usize>,
+    ) -> Result<(Vec<O>, Vec<String>, i64, bool), Error> {
+        let (synctoken, initial) = resolve_synctoken(synctoken, self.min_synctoken)?;
+        let mut latest_changes: HashMap<&str, i64> = HashMap::new();
+        for (change_synctoken, object_id, _) in &self.changelog {
+            if *change_synctoken > synctoken {
+                latest_changes.insert(object_id, *change_synctoken);
+            }
+        }
+        let mut changes: Vec<_> = latest_changes.into_iter().collect();
+        changes.sort_by_key(|(_, synctoken)| *synctoken);
+        let continuation =
+            truncate_changes(&mut changes, |(_, synctoken)| *synctoken, limit, initial);
+
+        let mut objects = vec![];
+        let mut deleted_objects = vec![];
+        for (object_id, _) in changes {
+            match self.objects.get(object_id) {
+                Some(object) => objects.push(object.to_owned()),
+                None => deleted_objects.push(object_id.to_owned()),
+            }
+        }
+        Ok(match continuation {
+            Some(continuation) => (objects, deleted_objects, continuation, true),
+            None => (
+                objects,
+                deleted_objects,
+                self.synctoken.max(synctoken),
+                false,
+            ),
+        })
+    }
+}
+
+#[test]
+fn test_memory_collection_sync_changes() {
+    let mut collection = MemoryCollection::<(), String>::new(());
+    assert_eq!(
+        collection
+            .put_object("a", "a".to_owned(), false, None)
+            .unwrap(),
+        1
+    );
+    assert_eq!(
+        collection
+            .put_object("b", "b".to_owned(), false, None)
+            .unwrap(),
+        2
+    );
+    assert!(matches!(
+        collection.put_object("a", "a".to_owned(), false, None),
+        Err(Error::AlreadyExists)
+    ));
+    assert_eq!(collection.delete_object("a", true).unwrap(), 3);
+
+    assert_eq!(
+        collection.sync_changes(0, None).unwrap(),
+        (vec!["b".to_owned()], vec!["a".to_owned()], 3, false)
+    );
+    assert_eq!(
+        collection.sync_changes(3, None).unwrap(),
+        (vec![], vec![], 3, false)
+    );
+
+    assert_eq!(collection.restore_object("a").unwrap(), 4);
+    assert_eq!(
+        collection.sync_changes(3, None).unwrap(),
+        (vec!["a".to_owned()], vec![], 4, false)
+    );
+    assert!(matches!(
+        collection.restore_object("a"),
+        Err(Error::AlreadyExists)
+    ));
+}
+
+#[test]
+fn test_memory_collection_purge_trash() {
+    let mut collection = MemoryCollection::<(), String>::new(());
+    collection
+        .put_object("a", "a".to_owned(), false, None)
+        .unwrap();
+    collection
+        .put_object("a", "a".to_owned(), true, None)
+        .unwrap();
+    collection
+        .put_object("b", "b".to_owned(), false, None)
+        .unwrap();
+    collection.delete_object("a", true).unwrap();
+    assert_eq!(collection.deleted_objects(), vec!["a".to_owned()]);
+
+    let before_deletion = Utc::now().naive_utc() - chrono::TimeDelta::hours(1);
+    assert_eq!(collection.purge_trash(before_deletion), 0);
+    assert_eq!(collection.purge_trash(Utc::now().naive_utc()), 1);
+    assert!(collection.deleted_objects().is_empty());
+    // The deletion is still reported
+    assert_eq!(
+        collection
+            .changelog
+            .iter()
+            .map(|(synctoken, object_id, _)| (*synctoken, object_id.as_str()))
+            .collect::<Vec<_>>(),
+        vec![(3, "b"), (4, "a")]
+    );
+    assert_eq!(
+        collection.sync_changes(3, None).unwrap(),
+        (vec![], vec!["a".to_owned()], 4, false)
+    );
+}
+
+#[test]
+fn test_memory_collection_revisions() {
+    let mut collection = MemoryCollection::<(), String>::new(());
+    for i in 0..MAX_REVISIONS + 2 {
+        collection
+            .put_object("a", i.to_string(), true, Some("user"))
+            .unwrap();
+    }
+    let revisions = collection.object_revisions("a");
+    assert_eq!(revisions.len(), MAX_REVISIONS);
+    assert_eq!(revisions[0].object, (MAX_REVISIONS + 1).to_string());
+    assert_eq!(revisions[0].revision, collection.synctoken);
+    assert_eq!(revisions[0].changed_by.as_deref(), Some("user"));
+
+    collection.delete_object("a", true).unwrap();
+    assert_eq!(collection.object_revisions("a").len(), MAX_REVISIONS);
+    collection.delete_object("a", false).unwrap();
+    assert!(collection.object_revisions("a").is_empty());
+}
+
+#[test]
+fn test_memory_collection_compact_changelog() {
+    let mut collection = MemoryCollection::<(), String>::new(());
+    collection
+        .put_object("a", "a".to_owned(), false, None)
+        .unwrap();
+    collection
+        .put_object("a", "a".to_owned(), true, None)
+        .unwrap();
+    collection
+        .put_object("b", "b".to_owned(), false, None)
+        .unwrap();
+    collection.delete_object("b", false).unwrap();
+
+    // Nothing is older than the horizon so only the history of each object is collapsed
+    let before = Utc::now().naive_utc() - chrono::TimeDelta::hours(1);
+    assert_eq!(collection.compact_changelog(before), 2);
+    assert_eq!(
+        collection.sync_changes(1, None).unwrap(),
+        (vec!["a".to_owned()], vec!["b".to_owned()], 4, false)
+    );
+
+    // The deletion is dropped but a is still listed on an initial sync
+    assert_eq!(
+        collection.compact_changelog(Utc::now().naive_utc() + chrono::TimeDelta::hours(1)),
+        1
+    );
+    assert!(matches!(
+        collection.sync_changes(3, None),
+        Err(Error::InvalidSyncToken)
+    ));
+    assert_eq!(
+        collection.sync_changes(4, None).unwrap(),
+        (vec![], vec![], 4, false)
+    );
+    assert_eq!(
+        collection.sync_changes(0, None).unwrap(),
+        (vec!["a".to_owned()], vec![], 4, false)
+    );
+}
+
+#[test]
+fn test_memory_collection_sync_changes_limit() {
+    let mut collection = MemoryCollection::<(), String>::new(());
+    for id in ["a", "b", "c"] {
+        collection
+            .put_object(id, id.to_owned(), false, None)
+            .unwrap();
+    }
+    assert_eq!(
+        collection.sync_changes(0, Some(2)).unwrap(),
+        (vec!["a".to_owned(), "b".to_owned()], vec![], -2, true)
+    );
+    assert_eq!(
+        collection.sync_changes(-2, Some(2)).unwrap(),
+        (vec!["c".to_owned()], vec![], 3, false)
+    );
+}
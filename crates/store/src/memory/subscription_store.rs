@@ -0,0 +1,46 @@
+use crate::{Error, Subscription, SubscriptionStore};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use tokio::sync::RwLock;
+
+#[derive(Debug, Default)]
+pub struct MemorySubscriptionStore {
+    subscriptions: RwLock<HashMap<String, Subscription>>,
+}
+
+#[async_trait]
+impl SubscriptionStore for MemorySubscriptionStore {
+    async fn get_subscriptions(&self, topic: &str) -> Result<Vec<Subscription>, Error> {
+        Ok(self
+            .subscriptions
+            .read()
+            .await
+            .values()
+            .filter(|sub| sub.topic == topic)
+            .cloned()
+            .collect())
+    }
+
+    async fn get_subscription(&self, id: &str) -> Result<Subscription, Error> {
+        self.subscriptions
+            .read()
+            .await
+            .get(id)
+            .cloned()
+            .ok_or(Error::NotFound)
+    }
+
+    async fn upsert_subscription(&self, sub: Subscription) -> Result<bool, Error> {
+        Ok(self
+            .subscriptions
+            .write()
+            .await
+            .insert(sub.id.to_owned(), sub)
+            .is_some())
+    }
+
+    async fn delete_subscription(&self, id: &str) -> Result<(), Error> {
+        self.subscriptions.write().await.remove(id);
+        Ok(())
+    }
+}
@@ -0,0 +1,155 @@
+use crate::{AddressbookStore, CalendarStore, Error};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct QuotaConfig {
+    // Maximum size of a single calendar object or vCard in bytes
+    pub max_resource_size: u64,
+    // Maximum total size of a principal's objects in bytes, unlimited if unset
+    pub default_quota: Option<u64>,
+    // Quotas of individual principals, overriding the default
+    pub principals: HashMap<String, u64>,
+}
+
+impl Default for QuotaConfig {
+    fn default() -> Self {
+        Self {
+            max_resource_size: 10_000_000,
+            default_quota: None,
+            principals: HashMap::new(),
+        }
+    }
+}
+
+impl QuotaConfig {
+    pub fn get_quota(&self, principal: &str) -> Option<u64> {
+        self.principals
+            .get(principal)
+            .copied()
+            .or(self.default_quota)
+    }
+}
+
+/// Storage used by a principal and the quota that applies to it (RFC 4331)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuotaUsage {
+    pub used_bytes: u64,
+    pub quota: Option<u64>,
+}
+
+impl QuotaUsage {
+    pub fn available_bytes(&self) -> Option<u64> {
+        self.quota
+            .map(|quota| quota.saturating_sub(self.used_bytes))
+    }
+
+    /// Whether replacing an object of `old_size` bytes with one of `new_size` bytes stays within
+    /// the quota. Objects that don't grow are always accepted, even if the quota is exceeded.
+    pub fn allows(&self, old_size: u64, new_size: u64) -> bool {
+        match self.quota {
+            Some(quota) if new_size > old_size => {
+                self.used_bytes.saturating_sub(old_size) + new_size <= quota
+            }
+            _ => true,
+        }
+    }
+}
+
+/// Computes the storage used by principals across their calendars and addressbooks
+pub struct Quota {
+    config: QuotaConfig,
+    cal_store: Arc<dyn CalendarStore>,
+    addr_store: Arc<dyn AddressbookStore>,
+}
+
+impl Quota {
+    pub fn new(
+        config: QuotaConfig,
+        cal_store: Arc<dyn CalendarStore>,
+        addr_store: Arc<dyn AddressbookStore>,
+    ) -> Self {
+        Self {
+            config,
+            cal_store,
+            addr_store,
+        }
+    }
+
+    pub fn max_resource_size(&self) -> u64 {
+        self.config.max_resource_size
+    }
+
+    pub fn get_quota(&self, principal: &str) -> Option<u64> {
+        self.config.get_quota(principal)
+    }
+
+    pub async fn get_usage(&self, principal: &str) -> Result<QuotaUsage, Error> {
+        Ok(QuotaUsage {
+            used_bytes: self.cal_store.get_used_bytes(principal).await?
+                + self.addr_store.get_used_bytes(principal).await?,
+            quota: self.config.get_quota(principal),
+        })
+    }
+
+    /// Whether a principal may replace an object of `old_size` bytes with one of `new_size` bytes
+    pub async fn allows_put(
+        &self,
+        principal: &str,
+        old_size: u64,
+        new_size: u64,
+    ) -> Result<bool, Error> {
+        if self.config.get_quota(principal).is_none() || new_size <= old_size {
+            return Ok(true);
+        }
+        Ok(self.get_usage(principal).await?.allows(old_size, new_size))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{QuotaConfig, QuotaUsage};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_quota_config() {
+        let config = QuotaConfig {
+            default_quota: Some(1000),
+            principals: HashMap::from([("admin".to_owned(), 5000)]),
+            ..Default::default()
+        };
+        assert_eq!(config.get_quota("user"), Some(1000));
+        assert_eq!(config.get_quota("admin"), Some(5000));
+        assert_eq!(QuotaConfig::default().get_quota("user"), None);
+    }
+
+    #[test]
+    fn test_quota_usage() {
+        let usage = QuotaUsage {
+            used_bytes: 900,
+            quota: Some(1000),
+        };
+        assert_eq!(usage.available_bytes(), Some(100));
+        assert!(usage.allows(0, 100));
+        assert!(!usage.allows(0, 101));
+        assert!(usage.allows(50, 150));
+        assert!(!usage.allows(50, 151));
+
+        let exceeded = QuotaUsage {
+            used_bytes: 1200,
+            quota: Some(1000),
+        };
+        assert_eq!(exceeded.available_bytes(), Some(0));
+        assert!(exceeded.allows(100, 100));
+        assert!(!exceeded.allows(0, 1));
+
+        let unlimited = QuotaUsage {
+            used_bytes: 1200,
+            quota: None,
+        };
+        assert_eq!(unlimited.available_bytes(), None);
+        assert!(unlimited.allows(0, u32::MAX as u64));
+    }
+}
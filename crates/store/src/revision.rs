@@ -0,0 +1,96 @@
+use chrono::NaiveDateTime;
+
+/// Number of revisions kept per object, older ones are discarded
+pub const MAX_REVISIONS: usize = 10;
+
+/// A version of an object as it was written to the store
+#[derive(Debug, Clone)]
+pub struct ObjectRevision<O> {
+    /// Synctoken of the change that wrote this version
+    pub revision: i64,
+    pub created_at: NaiveDateTime,
+    /// Principal that made the change, unknown for changes made outside of RustiCal
+    pub changed_by: Option<String>,
+    pub object: O,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiffLine<'a> {
+    Unchanged(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+impl DiffLine<'_> {
+    /// Formats the line like a unified diff
+    pub fn format(&self) -> String {
+        match self {
+            Self::Unchanged(line) => format!(" {line}"),
+            Self::Removed(line) => format!("-{line}"),
+            Self::Added(line) => format!("+{line}"),
+        }
+    }
+}
+
+/// Line-based diff using the longest common subsequence.
+/// Objects are small enough for the quadratic table.
+pub fn diff_lines<'a>(old: &'a str, new: &'a str) -> Vec<DiffLine<'a>> {
+    let old: Vec<_> = old.lines().collect();
+    let new: Vec<_> = new.lines().collect();
+
+    // lcs[i][j] is the length of the common subsequence of old[i..] and new[j..]
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut diff = vec![];
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            diff.push(DiffLine::Unchanged(old[i]));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            diff.push(DiffLine::Removed(old[i]));
+            i += 1;
+        } else {
+            diff.push(DiffLine::Added(new[j]));
+            j += 1;
+        }
+    }
+    diff.extend(old[i..].iter().map(|line| DiffLine::Removed(line)));
+    diff.extend(new[j..].iter().map(|line| DiffLine::Added(line)));
+    diff
+}
+
+#[test]
+fn test_diff_lines() {
+    let old = "BEGIN:VEVENT\r\nSUMMARY:Old\r\nDTSTART:20240101\r\nEND:VEVENT\r\n";
+    let new = "BEGIN:VEVENT\r\nDTSTART:20240101\r\nSUMMARY:New\r\nEND:VEVENT\r\n";
+    assert_eq!(
+        diff_lines(old, new),
+        vec![
+            DiffLine::Unchanged("BEGIN:VEVENT"),
+            DiffLine::Removed("SUMMARY:Old"),
+            DiffLine::Unchanged("DTSTART:20240101"),
+            DiffLine::Added("SUMMARY:New"),
+            DiffLine::Unchanged("END:VEVENT"),
+        ]
+    );
+    assert_eq!(
+        diff_lines("", "A\nB"),
+        vec![DiffLine::Added("A"), DiffLine::Added("B")]
+    );
+    assert!(
+        diff_lines("A", "A")
+            .iter()
+            .all(|line| matches!(line, DiffLine::Unchanged(_)))
+    );
+}
@@ -2,6 +2,7 @@ use crate::Error;
 use async_trait::async_trait;
 use chrono::NaiveDateTime;
 
+#[derive(Debug, Clone)]
 pub struct Subscription {
     pub id: String,
     pub topic: String,
@@ -1,7 +1,15 @@
+use crate::Error;
+
 const SYNC_NAMESPACE: &str = "github.com/lennart-k/rustical/ns/";
+const INITIAL_SYNC_PREFIX: &str = "initial/";
 
+/// Formats a synctoken, negative synctokens continue an initial sync (see [`truncate_changes`])
 pub fn format_synctoken(synctoken: i64) -> String {
-    format!("{}{}", SYNC_NAMESPACE, synctoken)
+    if synctoken < 0 {
+        format!("{}{}{}", SYNC_NAMESPACE, INITIAL_SYNC_PREFIX, -synctoken)
+    } else {
+        format!("{}{}", SYNC_NAMESPACE, synctoken)
+    }
 }
 
 pub fn parse_synctoken(synctoken: &str) -> Option<i64> {
@@ -9,5 +17,137 @@ pub fn parse_synctoken(synctoken: &str) -> Option<i64> {
         return None;
     }
     let (_, synctoken) = synctoken.split_at(SYNC_NAMESPACE.len());
-    synctoken.parse::<i64>().ok()
+    match synctoken.strip_prefix(INITIAL_SYNC_PREFIX) {
+        Some(synctoken) => Some(-synctoken.parse::<i64>().ok().filter(|&s| s > 0)?),
+        None => synctoken.parse::<i64>().ok().filter(|&s| s >= 0),
+    }
+}
+
+const HOME_SYNC_NAMESPACE: &str = "github.com/lennart-k/rustical/ns/home/";
+
+fn escape_collection_id(id: &str) -> String {
+    id.replace('%', "%25")
+        .replace(',', "%2C")
+        .replace('=', "%3D")
+}
+
+fn unescape_collection_id(id: &str) -> String {
+    id.replace("%3D", "=")
+        .replace("%2C", ",")
+        .replace("%25", "%")
+}
+
+/// Formats the sync-token of a home collection from the synctokens of its member collections.
+pub fn format_home_synctoken(synctokens: &[(String, i64)]) -> String {
+    let entries: Vec<String> = synctokens
+        .iter()
+        .map(|(id, synctoken)| format!("{}={}", escape_collection_id(id), synctoken))
+        .collect();
+    format!("{}{}", HOME_SYNC_NAMESPACE, entries.join(","))
+}
+
+pub fn parse_home_synctoken(synctoken: &str) -> Option<Vec<(String, i64)>> {
+    let entries = synctoken.strip_prefix(HOME_SYNC_NAMESPACE)?;
+    if entries.is_empty() {
+        return Some(vec![]);
+    }
+    entries
+        .split(',')
+        .map(|entry| {
+            let (id, synctoken) = entry.rsplit_once('=')?;
+            Some((unescape_collection_id(id), synctoken.parse::<i64>().ok()?))
+        })
+        .collect()
+}
+
+/// Resolves a requested synctoken to the synctoken after which changes are listed
+/// and whether the sync is an initial one.
+/// A synctoken from before `min_synctoken` is invalid since the changelog was compacted,
+/// except for continuations of an initial sync which lists every existing object anyway.
+pub fn resolve_synctoken(synctoken: i64, min_synctoken: i64) -> Result<(i64, bool), Error> {
+    if synctoken <= 0 {
+        Ok((-synctoken, true))
+    } else if synctoken < min_synctoken {
+        Err(Error::InvalidSyncToken)
+    } else {
+        Ok((synctoken, false))
+    }
+}
+
+/// Truncates changes ordered by their synctoken to `limit` for a paged sync.
+/// Returns the continuation synctoken if changes were left out,
+/// which is negative for an initial sync so that it stays valid across the compacted changelog.
+pub fn truncate_changes<T>(
+    changes: &mut Vec<T>,
+    synctoken_of: impl Fn(&T) -> i64,
+    limit: Option<usize>,
+    initial: bool,
+) -> Option<i64> {
+    // At least one change is needed to make progress
+    let limit = limit?.max(1);
+    if changes.len() <= limit {
+        return None;
+    }
+    changes.truncate(limit);
+    let synctoken = changes.last().map(synctoken_of)?;
+    Some(if initial { -synctoken } else { synctoken })
+}
+
+#[test]
+fn test_truncate_changes() {
+    let mut changes = vec![1, 2, 5, 6];
+    assert_eq!(truncate_changes(&mut changes, |c| *c, None, false), None);
+    assert_eq!(truncate_changes(&mut changes, |c| *c, Some(4), false), None);
+    assert_eq!(
+        truncate_changes(&mut changes, |c| *c, Some(3), false),
+        Some(5)
+    );
+    assert_eq!(changes, vec![1, 2, 5]);
+    assert_eq!(
+        truncate_changes(&mut changes, |c| *c, Some(0), false),
+        Some(1)
+    );
+
+    let mut changes = vec![1, 2, 5, 6];
+    assert_eq!(
+        truncate_changes(&mut changes, |c| *c, Some(1), true),
+        Some(-1)
+    );
+    assert_eq!(changes, vec![1]);
+}
+
+#[test]
+fn test_resolve_synctoken() {
+    assert_eq!(resolve_synctoken(0, 4).unwrap(), (0, true));
+    assert_eq!(resolve_synctoken(-2, 4).unwrap(), (2, true));
+    assert_eq!(resolve_synctoken(4, 4).unwrap(), (4, false));
+    assert!(matches!(
+        resolve_synctoken(2, 4),
+        Err(Error::InvalidSyncToken)
+    ));
+
+    assert_eq!(
+        format_synctoken(-2),
+        "github.com/lennart-k/rustical/ns/initial/2"
+    );
+    assert_eq!(parse_synctoken(&format_synctoken(-2)), Some(-2));
+    assert_eq!(parse_synctoken(&format_synctoken(2)), Some(2));
+    assert_eq!(parse_synctoken("github.com/lennart-k/rustical/ns/-2"), None);
+    assert_eq!(
+        parse_synctoken("github.com/lennart-k/rustical/ns/initial/0"),
+        None
+    );
+}
+
+#[test]
+fn test_home_synctoken() {
+    let synctokens = vec![("work".to_owned(), 4), ("a,b=c%d".to_owned(), 0)];
+    let token = format_home_synctoken(&synctokens);
+    assert_eq!(parse_home_synctoken(&token), Some(synctokens));
+    assert_eq!(
+        parse_home_synctoken(&format_home_synctoken(&[])),
+        Some(vec![])
+    );
+    assert_eq!(parse_home_synctoken(&format_synctoken(4)), None);
+    assert_eq!(parse_synctoken(&token), None);
 }
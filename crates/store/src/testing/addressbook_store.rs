@@ -0,0 +1,916 @@
+use super::{
+    PRINCIPAL, assert_delete_notification, assert_object_notification, drain_operations, sorted,
+    unlimited_quota,
+};
+use crate::revision::MAX_REVISIONS;
+use crate::{
+    AddressObject, Addressbook, AddressbookStore, BirthdayCalendarSettings, CollectionOperation,
+    CollectionOperationDomain, Error, addressbook::VcardDateRange,
+};
+use chrono::{NaiveDate, TimeDelta, Utc};
+use tokio::sync::mpsc::Receiver;
+
+pub(super) fn contact(uid: &str, name: &str) -> AddressObject {
+    let vcf = format!(
+        "BEGIN:VCARD\r
+VERSION:4.0\r
+UID:{uid}\r
+FN:{name}\r
+BDAY:19900101\r
+END:VCARD\r
+"
+    );
+    AddressObject::from_vcf(uid.to_owned(), vcf).unwrap()
+}
+
+pub(super) async fn create_addressbook<AS: AddressbookStore>(store: &AS, id: &str) -> Addressbook {
+    store
+        .insert_addressbook(Addressbook {
+            id: id.to_owned(),
+            principal: PRINCIPAL.to_owned(),
+            displayname: Some(id.to_owned()),
+            description: None,
+            created_at: None,
+            updated_at: None,
+            deleted_at: None,
+            synctoken: 0,
+            push_topic: format!("{id}-topic"),
+        })
+        .await
+        .unwrap();
+    store.get_addressbook(PRINCIPAL, id).await.unwrap()
+}
+
+async fn put<AS: AddressbookStore>(
+    store: &AS,
+    addressbook_id: &str,
+    object: AddressObject,
+    overwrite: bool,
+) {
+    store
+        .put_object(
+            PRINCIPAL.to_owned(),
+            addressbook_id.to_owned(),
+            object,
+            overwrite,
+            Some(PRINCIPAL),
+        )
+        .await
+        .unwrap();
+}
+
+fn object_ids(objects: Vec<AddressObject>) -> Vec<String> {
+    sorted(objects.iter().map(|obj| obj.get_id().to_owned()).collect())
+}
+
+/// Runs all addressbook store checks
+pub async fn test_addressbook_store<AS: AddressbookStore>(
+    store: &AS,
+    recv: &mut Receiver<CollectionOperation>,
+) {
+    test_addressbook_overwrite(store).await;
+    test_addressbook_etags(store).await;
+    test_addressbook_timestamps(store).await;
+    test_addressbook_used_bytes(store).await;
+    test_addressbook_trashbin(store).await;
+    test_addressbook_purge_trashbin(store).await;
+    test_addressbook_revisions(store).await;
+    test_addressbook_sync_changes(store).await;
+    test_addressbook_sync_changes_limit(store).await;
+    test_addressbook_notifications(store, recv).await;
+    test_addressbook_compact_changelog(store).await;
+    test_addressbook_compact_changelog_limit(store).await;
+    test_birthday_settings(store).await;
+    test_addressbook_objects_with_dates(store).await;
+}
+
+/// Objects must only be replaced with `overwrite`
+pub async fn test_addressbook_overwrite<AS: AddressbookStore>(store: &AS) {
+    let addressbook_id = "overwrite";
+    create_addressbook(store, addressbook_id).await;
+
+    let first = contact("a", "First");
+    let second = contact("a", "Second");
+    put(store, addressbook_id, first.clone(), false).await;
+    assert!(matches!(
+        store
+            .put_object(
+                PRINCIPAL.to_owned(),
+                addressbook_id.to_owned(),
+                second.clone(),
+                false,
+                None,
+            )
+            .await,
+        Err(Error::AlreadyExists)
+    ));
+    assert_eq!(
+        store
+            .get_object(PRINCIPAL, addressbook_id, "a")
+            .await
+            .unwrap()
+            .get_vcf(),
+        first.get_vcf()
+    );
+
+    put(store, addressbook_id, second.clone(), true).await;
+    assert_eq!(
+        store
+            .get_object(PRINCIPAL, addressbook_id, "a")
+            .await
+            .unwrap()
+            .get_vcf(),
+        second.get_vcf()
+    );
+    assert_eq!(
+        object_ids(store.get_objects(PRINCIPAL, addressbook_id).await.unwrap()),
+        vec!["a"]
+    );
+}
+
+/// Etags computed on write must be returned unchanged by the store
+pub async fn test_addressbook_etags<AS: AddressbookStore>(store: &AS) {
+    let addressbook_id = "etags";
+    create_addressbook(store, addressbook_id).await;
+
+    let first = contact("a", "First");
+    put(store, addressbook_id, first.clone(), false).await;
+    let stored = store
+        .get_object(PRINCIPAL, addressbook_id, "a")
+        .await
+        .unwrap();
+    assert_eq!(stored.get_etag(), first.get_etag());
+    let objects = store.get_objects(PRINCIPAL, addressbook_id).await.unwrap();
+    assert_eq!(objects[0].get_etag(), first.get_etag());
+
+    let second = contact("a", "Second");
+    assert_ne!(second.get_etag(), first.get_etag());
+    put(store, addressbook_id, second.clone(), true).await;
+    let stored = store
+        .get_object(PRINCIPAL, addressbook_id, "a")
+        .await
+        .unwrap();
+    assert_eq!(stored.get_etag(), second.get_etag());
+
+    // Listings without content must carry the same metadata
+    put(store, addressbook_id, contact("b", "Trashed"), false).await;
+    store
+        .delete_object(PRINCIPAL, addressbook_id, "b", true)
+        .await
+        .unwrap();
+    let metadata = store
+        .get_objects_metadata(PRINCIPAL, addressbook_id)
+        .await
+        .unwrap();
+    assert_eq!(metadata.len(), 1);
+    assert_eq!(metadata[0].get_id(), "a");
+    assert_eq!(metadata[0].get_etag(), second.get_etag());
+    assert_eq!(metadata[0].get_updated_at(), stored.get_updated_at());
+}
+
+/// Collections and objects must track when they were created and last modified
+pub async fn test_addressbook_timestamps<AS: AddressbookStore>(store: &AS) {
+    let addressbook_id = "timestamps";
+    let addressbook = create_addressbook(store, addressbook_id).await;
+    let addressbook_updated_at = addressbook.updated_at.unwrap();
+
+    put(store, addressbook_id, contact("a", "First"), false).await;
+    let object = store
+        .get_object(PRINCIPAL, addressbook_id, "a")
+        .await
+        .unwrap();
+    let object_updated_at = object.get_updated_at().unwrap();
+    let addressbook = store
+        .get_addressbook(PRINCIPAL, addressbook_id)
+        .await
+        .unwrap();
+    assert!(addressbook.updated_at.unwrap() >= addressbook_updated_at);
+
+    // Overwriting keeps the creation date
+    put(store, addressbook_id, contact("a", "Second"), true).await;
+    let overwritten = store
+        .get_object(PRINCIPAL, addressbook_id, "a")
+        .await
+        .unwrap();
+    assert_eq!(overwritten.get_created_at(), object.get_created_at());
+    assert!(overwritten.get_updated_at().unwrap() >= object_updated_at);
+    assert_eq!(
+        store.get_objects(PRINCIPAL, addressbook_id).await.unwrap()[0].get_updated_at(),
+        overwritten.get_updated_at()
+    );
+}
+
+/// Used bytes must follow the size of the objects outside the trashbin
+pub async fn test_addressbook_used_bytes<AS: AddressbookStore>(store: &AS) {
+    let addressbook_id = "used_bytes";
+    create_addressbook(store, addressbook_id).await;
+    let used_bytes = store.get_used_bytes(PRINCIPAL).await.unwrap();
+
+    let small = contact("a", "Small");
+    put(store, addressbook_id, small.clone(), false).await;
+    assert_eq!(
+        store.get_used_bytes(PRINCIPAL).await.unwrap(),
+        used_bytes + small.get_vcf().len() as u64
+    );
+
+    let large = contact("a", "Replaced by a larger contact");
+    put(store, addressbook_id, large.clone(), true).await;
+    assert_eq!(
+        store.get_used_bytes(PRINCIPAL).await.unwrap(),
+        used_bytes + large.get_vcf().len() as u64
+    );
+
+    store
+        .delete_object(PRINCIPAL, addressbook_id, "a", true)
+        .await
+        .unwrap();
+    assert_eq!(store.get_used_bytes(PRINCIPAL).await.unwrap(), used_bytes);
+    assert_eq!(store.get_used_bytes("someone-else").await.unwrap(), 0);
+}
+
+/// Trashed objects and addressbooks must be hidden until restored
+pub async fn test_addressbook_trashbin<AS: AddressbookStore>(store: &AS) {
+    let addressbook_id = "trashbin";
+    create_addressbook(store, addressbook_id).await;
+    let object = contact("a", "Trashed");
+    put(store, addressbook_id, object.clone(), false).await;
+    put(store, addressbook_id, contact("b", "Deleted"), false).await;
+
+    store
+        .delete_object(PRINCIPAL, addressbook_id, "a", true)
+        .await
+        .unwrap();
+    assert!(matches!(
+        store.get_object(PRINCIPAL, addressbook_id, "a").await,
+        Err(Error::NotFound)
+    ));
+    assert_eq!(
+        object_ids(store.get_objects(PRINCIPAL, addressbook_id).await.unwrap()),
+        vec!["b"]
+    );
+    store
+        .restore_object(PRINCIPAL, addressbook_id, "a")
+        .await
+        .unwrap();
+    assert_eq!(
+        store
+            .get_object(PRINCIPAL, addressbook_id, "a")
+            .await
+            .unwrap()
+            .get_vcf(),
+        object.get_vcf()
+    );
+
+    store
+        .delete_object(PRINCIPAL, addressbook_id, "b", false)
+        .await
+        .unwrap();
+    assert!(matches!(
+        store.get_object(PRINCIPAL, addressbook_id, "b").await,
+        Err(Error::NotFound)
+    ));
+
+    store
+        .delete_addressbook(PRINCIPAL, addressbook_id, true)
+        .await
+        .unwrap();
+    let addressbook_ids = |addressbooks: Vec<Addressbook>| -> Vec<String> {
+        addressbooks
+            .into_iter()
+            .map(|addressbook| addressbook.id)
+            .filter(|id| id == addressbook_id)
+            .collect()
+    };
+    assert!(addressbook_ids(store.get_addressbooks(PRINCIPAL).await.unwrap()).is_empty());
+    assert_eq!(
+        addressbook_ids(store.get_deleted_addressbooks(PRINCIPAL).await.unwrap()),
+        vec![addressbook_id]
+    );
+
+    store
+        .restore_addressbook(PRINCIPAL, addressbook_id)
+        .await
+        .unwrap();
+    assert_eq!(
+        addressbook_ids(store.get_addressbooks(PRINCIPAL).await.unwrap()),
+        vec![addressbook_id]
+    );
+    assert_eq!(
+        object_ids(store.get_objects(PRINCIPAL, addressbook_id).await.unwrap()),
+        vec!["a"]
+    );
+
+    store
+        .delete_addressbook(PRINCIPAL, addressbook_id, false)
+        .await
+        .unwrap();
+    assert!(matches!(
+        store.get_addressbook(PRINCIPAL, addressbook_id).await,
+        Err(Error::NotFound)
+    ));
+}
+
+/// Purging only deletes what was moved to the trashbin before the cutoff,
+/// the deletion of purged objects must still be reported by `sync_changes`
+pub async fn test_addressbook_purge_trashbin<AS: AddressbookStore>(store: &AS) {
+    let addressbook_id = "purge";
+    create_addressbook(store, addressbook_id).await;
+    put(store, addressbook_id, contact("a", "A"), false).await;
+    put(store, addressbook_id, contact("b", "B"), false).await;
+    let synctoken = store
+        .get_addressbook(PRINCIPAL, addressbook_id)
+        .await
+        .unwrap()
+        .synctoken;
+    store
+        .delete_object(PRINCIPAL, addressbook_id, "a", true)
+        .await
+        .unwrap();
+    assert_eq!(
+        object_ids(
+            store
+                .get_deleted_objects(PRINCIPAL, addressbook_id)
+                .await
+                .unwrap()
+        ),
+        vec!["a"]
+    );
+
+    let trashed_addressbook_id = "purge-trashed";
+    create_addressbook(store, trashed_addressbook_id).await;
+    store
+        .delete_addressbook(PRINCIPAL, trashed_addressbook_id, true)
+        .await
+        .unwrap();
+
+    store
+        .purge_trashbin(Utc::now().naive_utc() - TimeDelta::hours(1))
+        .await
+        .unwrap();
+    assert_eq!(
+        object_ids(
+            store
+                .get_deleted_objects(PRINCIPAL, addressbook_id)
+                .await
+                .unwrap()
+        ),
+        vec!["a"]
+    );
+    store
+        .get_addressbook(PRINCIPAL, trashed_addressbook_id)
+        .await
+        .unwrap();
+
+    let purged = store
+        .purge_trashbin(Utc::now().naive_utc() + TimeDelta::minutes(1))
+        .await
+        .unwrap();
+    assert!(purged >= 2, "expected at least two purged items: {purged}");
+    assert!(
+        store
+            .get_deleted_objects(PRINCIPAL, addressbook_id)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    assert!(matches!(
+        store
+            .get_addressbook(PRINCIPAL, trashed_addressbook_id)
+            .await,
+        Err(Error::NotFound)
+    ));
+    assert_eq!(
+        object_ids(store.get_objects(PRINCIPAL, addressbook_id).await.unwrap()),
+        vec!["b"]
+    );
+
+    let (objects, deleted, _, _) = store
+        .sync_changes(PRINCIPAL, addressbook_id, synctoken, None)
+        .await
+        .unwrap();
+    assert!(objects.is_empty());
+    assert_eq!(deleted, vec!["a"]);
+}
+
+/// Every write is recorded as a revision, rolling back writes an old revision as a new change
+pub async fn test_addressbook_revisions<S: AddressbookStore>(store: &S) {
+    let addressbook_id = "revisions";
+    create_addressbook(store, addressbook_id).await;
+    let first = contact("a", "First");
+    put(store, addressbook_id, first.clone(), false).await;
+    store
+        .put_object(
+            PRINCIPAL.to_owned(),
+            addressbook_id.to_owned(),
+            contact("a", "Second"),
+            true,
+            Some("editor"),
+        )
+        .await
+        .unwrap();
+
+    let revisions = store
+        .get_object_revisions(PRINCIPAL, addressbook_id, "a")
+        .await
+        .unwrap();
+    assert_eq!(revisions.len(), 2);
+    assert!(revisions[0].revision > revisions[1].revision);
+    assert_eq!(revisions[0].changed_by.as_deref(), Some("editor"));
+    assert_eq!(revisions[1].changed_by.as_deref(), Some(PRINCIPAL));
+    assert_eq!(revisions[1].object.get_vcf(), first.get_vcf());
+    let synctoken = store
+        .get_addressbook(PRINCIPAL, addressbook_id)
+        .await
+        .unwrap()
+        .synctoken;
+    assert_eq!(revisions[0].revision, synctoken);
+
+    store
+        .rollback_object(
+            PRINCIPAL,
+            addressbook_id,
+            "a",
+            revisions[1].revision,
+            Some(PRINCIPAL),
+            &unlimited_quota(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        store
+            .get_object(PRINCIPAL, addressbook_id, "a")
+            .await
+            .unwrap()
+            .get_vcf(),
+        first.get_vcf()
+    );
+    let (objects, _, new_synctoken, _) = store
+        .sync_changes(PRINCIPAL, addressbook_id, synctoken, None)
+        .await
+        .unwrap();
+    assert_eq!(object_ids(objects), vec!["a"]);
+    assert!(new_synctoken > synctoken);
+    assert!(matches!(
+        store
+            .rollback_object(
+                PRINCIPAL,
+                addressbook_id,
+                "a",
+                -1,
+                Some(PRINCIPAL),
+                &unlimited_quota()
+            )
+            .await,
+        Err(Error::NotFound)
+    ));
+
+    // Fetching the revisions of multiple objects at once returns the same revisions
+    put(store, addressbook_id, contact("b", "B"), false).await;
+    let revisions = store
+        .get_objects_revisions(
+            PRINCIPAL,
+            addressbook_id,
+            &["a".to_owned(), "b".to_owned(), "missing".to_owned()],
+        )
+        .await
+        .unwrap();
+    assert_eq!(sorted(revisions.keys().cloned().collect()), ["a", "b"]);
+    for (object_id, object_revisions) in revisions {
+        let expected = store
+            .get_object_revisions(PRINCIPAL, addressbook_id, &object_id)
+            .await
+            .unwrap();
+        assert_eq!(
+            object_revisions
+                .iter()
+                .map(|revision| (revision.revision, revision.object.get_vcf().to_owned()))
+                .collect::<Vec<_>>(),
+            expected
+                .iter()
+                .map(|revision| (revision.revision, revision.object.get_vcf().to_owned()))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    for _ in 0..MAX_REVISIONS {
+        put(store, addressbook_id, first.clone(), true).await;
+    }
+    assert_eq!(
+        store
+            .get_object_revisions(PRINCIPAL, addressbook_id, "a")
+            .await
+            .unwrap()
+            .len(),
+        MAX_REVISIONS
+    );
+
+    store
+        .delete_object(PRINCIPAL, addressbook_id, "a", false)
+        .await
+        .unwrap();
+    assert!(
+        store
+            .get_object_revisions(PRINCIPAL, addressbook_id, "a")
+            .await
+            .unwrap()
+            .is_empty()
+    );
+}
+
+/// `sync_changes` must report the latest state of every object changed after the synctoken
+pub async fn test_addressbook_sync_changes<AS: AddressbookStore>(store: &AS) {
+    let addressbook_id = "sync-changes";
+    let initial_synctoken = create_addressbook(store, addressbook_id).await.synctoken;
+    let sync = async |synctoken| {
+        let (objects, deleted, new_synctoken, _) = store
+            .sync_changes(PRINCIPAL, addressbook_id, synctoken, None)
+            .await
+            .unwrap();
+        (object_ids(objects), sorted(deleted), new_synctoken)
+    };
+
+    put(store, addressbook_id, contact("a", "A"), false).await;
+    put(store, addressbook_id, contact("b", "B"), false).await;
+    let (objects, deleted, synctoken_added) = sync(initial_synctoken).await;
+    assert_eq!(
+        (objects, deleted),
+        (vec!["a".to_owned(), "b".to_owned()], vec![])
+    );
+    assert!(synctoken_added > initial_synctoken);
+    assert_eq!(
+        synctoken_added,
+        store
+            .get_addressbook(PRINCIPAL, addressbook_id)
+            .await
+            .unwrap()
+            .synctoken
+    );
+    assert_eq!(
+        sync(synctoken_added).await,
+        (vec![], vec![], synctoken_added)
+    );
+
+    store
+        .delete_object(PRINCIPAL, addressbook_id, "a", true)
+        .await
+        .unwrap();
+    store
+        .delete_object(PRINCIPAL, addressbook_id, "b", false)
+        .await
+        .unwrap();
+    let (objects, deleted, synctoken_deleted) = sync(synctoken_added).await;
+    assert_eq!(
+        (objects, deleted),
+        (vec![], vec!["a".to_owned(), "b".to_owned()])
+    );
+    assert!(synctoken_deleted > synctoken_added);
+
+    store
+        .restore_object(PRINCIPAL, addressbook_id, "a")
+        .await
+        .unwrap();
+    let (objects, deleted, synctoken_restored) = sync(synctoken_deleted).await;
+    assert_eq!((objects, deleted), (vec!["a".to_owned()], vec![]));
+    assert_eq!(
+        sync(initial_synctoken).await,
+        (
+            vec!["a".to_owned()],
+            vec!["b".to_owned()],
+            synctoken_restored
+        )
+    );
+}
+
+/// Every object change and addressbook deletion must be announced on the operation channel
+pub async fn test_addressbook_notifications<AS: AddressbookStore>(
+    store: &AS,
+    recv: &mut Receiver<CollectionOperation>,
+) {
+    let addressbook_id = "notifications";
+    let topic = create_addressbook(store, addressbook_id).await.push_topic;
+    drain_operations(recv);
+    let assert_notification = async |recv: &mut Receiver<CollectionOperation>| {
+        let synctoken = store
+            .get_addressbook(PRINCIPAL, addressbook_id)
+            .await
+            .unwrap()
+            .format_synctoken();
+        assert_object_notification(
+            recv,
+            CollectionOperationDomain::Addressbook,
+            &topic,
+            &synctoken,
+        );
+    };
+
+    put(store, addressbook_id, contact("a", "A"), false).await;
+    assert_notification(recv).await;
+    store
+        .delete_object(PRINCIPAL, addressbook_id, "a", true)
+        .await
+        .unwrap();
+    assert_notification(recv).await;
+    store
+        .restore_object(PRINCIPAL, addressbook_id, "a")
+        .await
+        .unwrap();
+    assert_notification(recv).await;
+    store
+        .delete_object(PRINCIPAL, addressbook_id, "a", false)
+        .await
+        .unwrap();
+    assert_notification(recv).await;
+
+    store
+        .delete_addressbook(PRINCIPAL, addressbook_id, true)
+        .await
+        .unwrap();
+    assert_delete_notification(recv, CollectionOperationDomain::Addressbook, &topic);
+}
+
+/// Birthday settings default until they are updated,
+/// changing them logs a change of every contact since all birthdays are rendered anew
+pub async fn test_birthday_settings<AS: AddressbookStore>(store: &AS) {
+    assert_eq!(
+        store.get_birthday_settings(PRINCIPAL).await.unwrap(),
+        BirthdayCalendarSettings::default()
+    );
+    let addressbook_id = "birthday-settings";
+    create_addressbook(store, addressbook_id).await;
+    put(store, addressbook_id, contact("a", "A"), false).await;
+    let synctoken = store
+        .get_addressbook(PRINCIPAL, addressbook_id)
+        .await
+        .unwrap()
+        .synctoken;
+
+    let settings = BirthdayCalendarSettings {
+        include_abdates: true,
+        ..Default::default()
+    };
+    store
+        .update_birthday_settings(PRINCIPAL, settings.clone())
+        .await
+        .unwrap();
+    assert_eq!(
+        store.get_birthday_settings(PRINCIPAL).await.unwrap(),
+        settings
+    );
+    let (objects, deleted, new_synctoken, _) = store
+        .sync_changes(PRINCIPAL, addressbook_id, synctoken, None)
+        .await
+        .unwrap();
+    assert_eq!(object_ids(objects), vec!["a"]);
+    assert!(deleted.is_empty());
+    assert!(new_synctoken > synctoken);
+
+    // Unchanged settings don't change anything
+    store
+        .update_birthday_settings(PRINCIPAL, settings)
+        .await
+        .unwrap();
+    assert_eq!(
+        store
+            .get_addressbook(PRINCIPAL, addressbook_id)
+            .await
+            .unwrap()
+            .synctoken,
+        new_synctoken
+    );
+}
+
+/// `get_objects_with_dates` returns the contacts with a date recurring in the range and
+/// follows changes and deletions of objects
+pub async fn test_addressbook_objects_with_dates<AS: AddressbookStore>(store: &AS) {
+    let addressbook_id = "dates";
+    create_addressbook(store, addressbook_id).await;
+    let with_dates = |uid: &str, dates: &str| {
+        let vcf =
+            format!("BEGIN:VCARD\r\nVERSION:4.0\r\nUID:{uid}\r\nFN:{uid}\r\n{dates}END:VCARD\r\n");
+        AddressObject::from_vcf(uid.to_owned(), vcf).unwrap()
+    };
+    put(
+        store,
+        addressbook_id,
+        with_dates("april", "BDAY:19850412\r\n"),
+        false,
+    )
+    .await;
+    put(
+        store,
+        addressbook_id,
+        with_dates(
+            "new-year",
+            "ANNIVERSARY:--0101\r\nitem1.X-ABDATE:2015-12-31\r\n",
+        ),
+        false,
+    )
+    .await;
+    put(
+        store,
+        addressbook_id,
+        with_dates("future", "BDAY:20300412\r\n"),
+        false,
+    )
+    .await;
+    put(store, addressbook_id, with_dates("none", ""), false).await;
+
+    let query = async |start: &str, end: &str| {
+        let date = |value: &str| NaiveDate::parse_from_str(value, "%Y-%m-%d").ok();
+        object_ids(
+            store
+                .get_objects_with_dates(
+                    PRINCIPAL,
+                    addressbook_id,
+                    &VcardDateRange::new(date(start), date(end)),
+                )
+                .await
+                .unwrap(),
+        )
+    };
+    assert_eq!(query("2024-04-01", "2024-04-30").await, vec!["april"]);
+    assert_eq!(query("2024-12-30", "2025-01-02").await, vec!["new-year"]);
+    assert_eq!(
+        query("2030-04-12", "2030-04-12").await,
+        vec!["april", "future"]
+    );
+    assert_eq!(
+        query("2024-01-01", "2024-12-31").await,
+        vec!["april", "new-year"]
+    );
+
+    // The index follows changes
+    put(
+        store,
+        addressbook_id,
+        with_dates("april", "BDAY:19850512\r\n"),
+        true,
+    )
+    .await;
+    assert!(query("2024-04-01", "2024-04-30").await.is_empty());
+    store
+        .delete_object(PRINCIPAL, addressbook_id, "new-year", true)
+        .await
+        .unwrap();
+    assert!(query("2024-12-30", "2025-01-02").await.is_empty());
+    store
+        .restore_object(PRINCIPAL, addressbook_id, "new-year")
+        .await
+        .unwrap();
+    assert_eq!(query("2024-12-30", "2025-01-02").await, vec!["new-year"]);
+    store
+        .delete_object(PRINCIPAL, addressbook_id, "new-year", false)
+        .await
+        .unwrap();
+    assert!(query("2024-12-30", "2025-01-02").await.is_empty());
+}
+
+/// With a limit `sync_changes` returns the changes in pages that continue from the returned synctoken
+pub async fn test_addressbook_sync_changes_limit<AS: AddressbookStore>(store: &AS) {
+    let addressbook_id = "sync-limit";
+    create_addressbook(store, addressbook_id).await;
+    for uid in ["a", "b", "c"] {
+        put(store, addressbook_id, contact(uid, "X"), false).await;
+    }
+    store
+        .delete_object(PRINCIPAL, addressbook_id, "a", false)
+        .await
+        .unwrap();
+    let synctoken = store
+        .get_addressbook(PRINCIPAL, addressbook_id)
+        .await
+        .unwrap()
+        .synctoken;
+
+    let (objects, deleted, continuation, truncated) = store
+        .sync_changes(PRINCIPAL, addressbook_id, 0, Some(1))
+        .await
+        .unwrap();
+    assert!(truncated);
+    assert_eq!(object_ids(objects), vec!["b"]);
+    assert!(deleted.is_empty());
+
+    let (objects, deleted, continuation, truncated) = store
+        .sync_changes(PRINCIPAL, addressbook_id, continuation, Some(1))
+        .await
+        .unwrap();
+    assert!(truncated);
+    assert_eq!(object_ids(objects), vec!["c"]);
+    assert!(deleted.is_empty());
+
+    let (objects, deleted, new_synctoken, truncated) = store
+        .sync_changes(PRINCIPAL, addressbook_id, continuation, Some(1))
+        .await
+        .unwrap();
+    assert!(!truncated);
+    assert!(objects.is_empty());
+    assert_eq!(deleted, vec!["a"]);
+    assert_eq!(new_synctoken, synctoken);
+
+    let (objects, deleted, new_synctoken, truncated) = store
+        .sync_changes(PRINCIPAL, addressbook_id, 0, Some(3))
+        .await
+        .unwrap();
+    assert!(!truncated);
+    assert_eq!(object_ids(objects), vec!["b", "c"]);
+    assert_eq!(deleted, vec!["a"]);
+    assert_eq!(new_synctoken, synctoken);
+}
+
+/// Compacting collapses the changelog and drops changes before the horizon,
+/// synctokens from before the horizon become invalid
+pub async fn test_addressbook_compact_changelog<AS: AddressbookStore>(store: &AS) {
+    let addressbook_id = "compact";
+    create_addressbook(store, addressbook_id).await;
+    put(store, addressbook_id, contact("a", "A"), false).await;
+    put(store, addressbook_id, contact("a", "A2"), true).await;
+    put(store, addressbook_id, contact("b", "B"), false).await;
+    store
+        .delete_object(PRINCIPAL, addressbook_id, "b", false)
+        .await
+        .unwrap();
+    let synctoken = store
+        .get_addressbook(PRINCIPAL, addressbook_id)
+        .await
+        .unwrap()
+        .synctoken;
+
+    // Nothing is older than the horizon, only the history of every object is collapsed
+    store
+        .compact_changelog(Utc::now().naive_utc() - TimeDelta::hours(1))
+        .await
+        .unwrap();
+    let (objects, deleted, new_synctoken, _) = store
+        .sync_changes(PRINCIPAL, addressbook_id, 1, None)
+        .await
+        .unwrap();
+    assert_eq!(object_ids(objects), vec!["a"]);
+    assert_eq!(deleted, vec!["b"]);
+    assert_eq!(new_synctoken, synctoken);
+
+    store
+        .compact_changelog(Utc::now().naive_utc() + TimeDelta::minutes(1))
+        .await
+        .unwrap();
+    assert!(matches!(
+        store.sync_changes(PRINCIPAL, addressbook_id, 1, None).await,
+        Err(Error::InvalidSyncToken)
+    ));
+    let (objects, deleted, new_synctoken, _) = store
+        .sync_changes(PRINCIPAL, addressbook_id, synctoken, None)
+        .await
+        .unwrap();
+    assert!(objects.is_empty() && deleted.is_empty());
+    assert_eq!(new_synctoken, synctoken);
+    // An initial sync still lists all objects
+    let (objects, deleted, new_synctoken, _) = store
+        .sync_changes(PRINCIPAL, addressbook_id, 0, None)
+        .await
+        .unwrap();
+    assert_eq!(object_ids(objects), vec!["a"]);
+    assert!(deleted.is_empty());
+    assert_eq!(new_synctoken, synctoken);
+
+    put(store, addressbook_id, contact("c", "C"), false).await;
+    let (objects, _, new_synctoken, _) = store
+        .sync_changes(PRINCIPAL, addressbook_id, synctoken, None)
+        .await
+        .unwrap();
+    assert_eq!(object_ids(objects), vec!["c"]);
+    assert_eq!(new_synctoken, synctoken + 1);
+}
+
+/// An initial sync is paged according to `limit` even if all changes were compacted
+pub async fn test_addressbook_compact_changelog_limit<AS: AddressbookStore>(store: &AS) {
+    let addressbook_id = "compact-limit";
+    create_addressbook(store, addressbook_id).await;
+    for uid in ["a", "b", "c"] {
+        put(store, addressbook_id, contact(uid, "X"), false).await;
+    }
+    let synctoken = store
+        .get_addressbook(PRINCIPAL, addressbook_id)
+        .await
+        .unwrap()
+        .synctoken;
+    store
+        .compact_changelog(Utc::now().naive_utc() + TimeDelta::minutes(1))
+        .await
+        .unwrap();
+
+    let mut ids = vec![];
+    let mut continuation = 0;
+    loop {
+        let (objects, deleted, new_synctoken, truncated) = store
+            .sync_changes(PRINCIPAL, addressbook_id, continuation, Some(1))
+            .await
+            .unwrap();
+        assert!(objects.len() + deleted.len() <= 1);
+        ids.extend(object_ids(objects));
+        continuation = new_synctoken;
+        if !truncated {
+            break;
+        }
+    }
+    assert_eq!(sorted(ids), vec!["a", "b", "c"]);
+    assert_eq!(continuation, synctoken);
+}
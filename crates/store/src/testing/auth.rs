@@ -0,0 +1,129 @@
+use crate::Error;
+use crate::auth::user::PrincipalType;
+use crate::auth::{AuthenticationProvider, User};
+
+fn principal(id: &str, displayname: &str, memberships: &[&str]) -> User {
+    User {
+        id: id.to_owned(),
+        displayname: Some(displayname.to_owned()),
+        principal_type: PrincipalType::Group,
+        password: Some(password_auth::generate_hash("password").into()),
+        app_tokens: vec![],
+        memberships: memberships.iter().map(|id| (*id).to_owned()).collect(),
+        emails: vec!["b@example.com".to_owned(), "a@example.com".to_owned()],
+    }
+}
+
+/// Runs all authentication provider checks
+pub async fn test_authentication_provider<AP: AuthenticationProvider>(store: &AP) {
+    let id = "conformance-principal";
+    assert!(store.get_principal(id).await.unwrap().is_none());
+    store
+        .insert_principal(principal(id, "First", &["a", "b"]), false)
+        .await
+        .unwrap();
+    assert!(matches!(
+        store
+            .insert_principal(principal(id, "Second", &[]), false)
+            .await,
+        Err(Error::AlreadyExists)
+    ));
+
+    let user = store.get_principal(id).await.unwrap().unwrap();
+    assert_eq!(user.displayname.as_deref(), Some("First"));
+    assert_eq!(user.principal_type, PrincipalType::Group);
+    // The order of email addresses matters since the first one is the primary address
+    assert_eq!(user.emails, vec!["b@example.com", "a@example.com"]);
+    let mut memberships = user.memberships.clone();
+    memberships.sort();
+    assert_eq!(memberships, vec!["a", "b"]);
+    assert!(
+        store
+            .get_principals()
+            .await
+            .unwrap()
+            .iter()
+            .any(|principal| principal.id == id)
+    );
+
+    store
+        .insert_principal(principal(id, "Second", &["c"]), true)
+        .await
+        .unwrap();
+    let user = store.get_principal(id).await.unwrap().unwrap();
+    assert_eq!(user.displayname.as_deref(), Some("Second"));
+    assert_eq!(user.memberships, vec!["c"]);
+
+    assert!(
+        store
+            .validate_password(id, "password")
+            .await
+            .unwrap()
+            .is_some()
+    );
+    assert!(
+        store
+            .validate_password(id, "wrong")
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert!(
+        store
+            .validate_password("nonexistent", "password")
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    let token_id = store
+        .add_app_token(id, "Phone".to_owned(), "token".to_owned())
+        .await
+        .unwrap();
+    assert!(
+        store
+            .validate_app_token(id, "token")
+            .await
+            .unwrap()
+            .is_some()
+    );
+    assert!(
+        store
+            .validate_app_token(id, "wrong")
+            .await
+            .unwrap()
+            .is_none()
+    );
+    let user = store.get_principal(id).await.unwrap().unwrap();
+    assert_eq!(user.app_tokens.len(), 1);
+    assert_eq!(user.app_tokens[0].id, token_id);
+    assert_eq!(user.app_tokens[0].name, "Phone");
+    assert!(matches!(
+        store
+            .add_app_token("nonexistent", "Phone".to_owned(), "token".to_owned())
+            .await,
+        Err(Error::NotFound)
+    ));
+
+    // Overwriting a principal keeps the app tokens it contains
+    store.insert_principal(user, true).await.unwrap();
+    assert!(
+        store
+            .validate_app_token(id, "token")
+            .await
+            .unwrap()
+            .is_some()
+    );
+
+    store.remove_app_token(id, &token_id).await.unwrap();
+    assert!(
+        store
+            .validate_app_token(id, "token")
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    store.remove_principal(id).await.unwrap();
+    assert!(store.get_principal(id).await.unwrap().is_none());
+}
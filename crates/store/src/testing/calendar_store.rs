@@ -0,0 +1,1113 @@
+use super::{
+    PRINCIPAL, assert_delete_notification, assert_object_notification, drain_operations, sorted,
+    unlimited_quota,
+};
+use crate::calendar::CalTimezone;
+use crate::calendar_store::CalendarQuery;
+use crate::revision::MAX_REVISIONS;
+use crate::{
+    Calendar, CalendarObject, CalendarStore, CollectionOperation, CollectionOperationDomain, Error,
+};
+use chrono::{NaiveDate, TimeDelta, Utc};
+use futures_util::TryStreamExt;
+use tokio::sync::mpsc::Receiver;
+
+pub(super) fn event(uid: &str, start: &str, end: &str, summary: &str) -> CalendarObject {
+    let ics = format!(
+        "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:-//rustical//conformance//EN\r
+BEGIN:VEVENT\r
+UID:{uid}\r
+DTSTAMP:20240101T000000Z\r
+DTSTART:{start}T100000Z\r
+DTEND:{end}T110000Z\r
+SUMMARY:{summary}\r
+END:VEVENT\r
+END:VCALENDAR\r
+"
+    );
+    CalendarObject::from_ics(uid.to_owned(), ics).unwrap()
+}
+
+fn todo(uid: &str) -> CalendarObject {
+    let ics = format!(
+        "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:-//rustical//conformance//EN\r
+BEGIN:VTODO\r
+UID:{uid}\r
+DTSTAMP:20240101T000000Z\r
+SUMMARY:Undated\r
+END:VTODO\r
+END:VCALENDAR\r
+"
+    );
+    CalendarObject::from_ics(uid.to_owned(), ics).unwrap()
+}
+
+pub(super) async fn create_calendar<CS: CalendarStore>(store: &CS, id: &str) -> Calendar {
+    store
+        .insert_calendar(Calendar {
+            principal: PRINCIPAL.to_owned(),
+            id: id.to_owned(),
+            displayname: Some(id.to_owned()),
+            push_topic: format!("{id}-topic"),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    store.get_calendar(PRINCIPAL, id).await.unwrap()
+}
+
+async fn put<CS: CalendarStore>(store: &CS, cal_id: &str, object: CalendarObject, overwrite: bool) {
+    store
+        .put_object(
+            PRINCIPAL.to_owned(),
+            cal_id.to_owned(),
+            object,
+            overwrite,
+            Some(PRINCIPAL),
+        )
+        .await
+        .unwrap();
+}
+
+fn object_ids(objects: Vec<CalendarObject>) -> Vec<String> {
+    sorted(objects.iter().map(|obj| obj.get_id().to_owned()).collect())
+}
+
+/// Runs all calendar store checks
+pub async fn test_calendar_store<CS: CalendarStore>(
+    store: &CS,
+    recv: &mut Receiver<CollectionOperation>,
+) {
+    test_calendar_overwrite(store).await;
+    test_calendar_timestamps(store).await;
+    test_calendar_objects_metadata(store).await;
+    test_calendar_used_bytes(store).await;
+    test_calendar_trashbin(store).await;
+    test_calendar_purge_trashbin(store).await;
+    test_calendar_revisions(store).await;
+    test_calendar_sync_changes(store).await;
+    test_calendar_sync_changes_limit(store).await;
+    test_calendar_query(store).await;
+    test_calendar_occurences(store).await;
+    test_calendar_object_streams(store).await;
+    test_calendar_notifications(store, recv).await;
+    test_calendar_compact_changelog(store).await;
+    test_calendar_compact_changelog_limit(store).await;
+}
+
+/// Objects must only be replaced with `overwrite`
+pub async fn test_calendar_overwrite<CS: CalendarStore>(store: &CS) {
+    let cal_id = "overwrite";
+    create_calendar(store, cal_id).await;
+    assert!(matches!(
+        store
+            .insert_calendar(Calendar {
+                principal: PRINCIPAL.to_owned(),
+                id: cal_id.to_owned(),
+                ..Default::default()
+            })
+            .await,
+        Err(Error::AlreadyExists)
+    ));
+
+    let first = event("a", "20240101", "20240101", "First");
+    let second = event("a", "20240101", "20240101", "Second");
+    put(store, cal_id, first.clone(), false).await;
+    assert!(matches!(
+        store
+            .put_object(
+                PRINCIPAL.to_owned(),
+                cal_id.to_owned(),
+                second.clone(),
+                false,
+                None,
+            )
+            .await,
+        Err(Error::AlreadyExists)
+    ));
+    assert_eq!(
+        store
+            .get_object(PRINCIPAL, cal_id, "a")
+            .await
+            .unwrap()
+            .get_ics(),
+        first.get_ics()
+    );
+
+    put(store, cal_id, second.clone(), true).await;
+    assert_eq!(
+        store
+            .get_object(PRINCIPAL, cal_id, "a")
+            .await
+            .unwrap()
+            .get_ics(),
+        second.get_ics()
+    );
+    assert_eq!(
+        object_ids(store.get_objects(PRINCIPAL, cal_id).await.unwrap()),
+        vec!["a"]
+    );
+}
+
+/// Collections and objects must track when they were created and last modified
+pub async fn test_calendar_timestamps<CS: CalendarStore>(store: &CS) {
+    let cal_id = "timestamps";
+    let calendar = create_calendar(store, cal_id).await;
+    let calendar_updated_at = calendar.updated_at.unwrap();
+
+    put(store, cal_id, todo("a"), false).await;
+    let object = store.get_object(PRINCIPAL, cal_id, "a").await.unwrap();
+    let object_updated_at = object.get_updated_at().unwrap();
+    let calendar = store.get_calendar(PRINCIPAL, cal_id).await.unwrap();
+    assert!(calendar.updated_at.unwrap() >= calendar_updated_at);
+
+    // Overwriting keeps the creation date
+    put(
+        store,
+        cal_id,
+        event("a", "20240101", "20240101", "Changed"),
+        true,
+    )
+    .await;
+    let overwritten = store.get_object(PRINCIPAL, cal_id, "a").await.unwrap();
+    assert_eq!(overwritten.get_created_at(), object.get_created_at());
+    assert!(overwritten.get_updated_at().unwrap() >= object_updated_at);
+    assert_eq!(
+        store.get_objects(PRINCIPAL, cal_id).await.unwrap()[0].get_updated_at(),
+        overwritten.get_updated_at()
+    );
+}
+
+/// Listings without content must carry the same metadata as the full objects
+pub async fn test_calendar_objects_metadata<CS: CalendarStore>(store: &CS) {
+    let cal_id = "objects-metadata";
+    create_calendar(store, cal_id).await;
+    put(
+        store,
+        cal_id,
+        event("event", "20240101", "20240101", "Event"),
+        false,
+    )
+    .await;
+    put(store, cal_id, todo("todo"), false).await;
+    put(store, cal_id, todo("trashed"), false).await;
+    let zoned = event("zoned", "20240101", "20240101", "Zoned");
+    let zoned = zoned.get_ics().replace(
+        "DTSTART:20240101T100000Z",
+        "DTSTART;TZID=Europe/Berlin:20240101T100000",
+    );
+    let zoned = CalendarObject::from_ics("zoned".to_owned(), zoned).unwrap();
+    assert!(zoned.references_timezones());
+    put(store, cal_id, zoned, false).await;
+    store
+        .delete_object(PRINCIPAL, cal_id, "trashed", true)
+        .await
+        .unwrap();
+
+    let mut objects = store.get_objects(PRINCIPAL, cal_id).await.unwrap();
+    let mut metadata = store.get_objects_metadata(PRINCIPAL, cal_id).await.unwrap();
+    objects.sort_by(|a, b| a.get_id().cmp(b.get_id()));
+    metadata.sort_by(|a, b| a.get_id().cmp(b.get_id()));
+    assert_eq!(metadata.len(), objects.len());
+    for (metadata, object) in metadata.iter().zip(&objects) {
+        assert_eq!(metadata.get_id(), object.get_id());
+        assert_eq!(metadata.get_etag(), object.get_etag());
+        assert_eq!(
+            metadata.get_etag_with_timezones(),
+            object.get_etag_with_timezones()
+        );
+        assert_eq!(metadata.get_object_type(), object.get_object_type());
+        assert_eq!(metadata.get_updated_at(), object.get_updated_at());
+    }
+}
+
+/// Used bytes must follow the size of the objects outside the trashbin
+pub async fn test_calendar_used_bytes<CS: CalendarStore>(store: &CS) {
+    let cal_id = "used_bytes";
+    create_calendar(store, cal_id).await;
+    let used_bytes = store.get_used_bytes(PRINCIPAL).await.unwrap();
+
+    let small = todo("a");
+    put(store, cal_id, small.clone(), false).await;
+    assert_eq!(
+        store.get_used_bytes(PRINCIPAL).await.unwrap(),
+        used_bytes + small.get_ics().len() as u64
+    );
+
+    let large = event("a", "20240101", "20240101", "Replaced by a larger event");
+    put(store, cal_id, large.clone(), true).await;
+    assert_eq!(
+        store.get_used_bytes(PRINCIPAL).await.unwrap(),
+        used_bytes + large.get_ics().len() as u64
+    );
+
+    store
+        .delete_object(PRINCIPAL, cal_id, "a", true)
+        .await
+        .unwrap();
+    assert_eq!(store.get_used_bytes(PRINCIPAL).await.unwrap(), used_bytes);
+    assert_eq!(store.get_used_bytes("someone-else").await.unwrap(), 0);
+}
+
+/// Trashed objects and calendars must be hidden until restored
+pub async fn test_calendar_trashbin<CS: CalendarStore>(store: &CS) {
+    let cal_id = "trashbin";
+    create_calendar(store, cal_id).await;
+    let object = event("a", "20240101", "20240101", "Trashed");
+    put(store, cal_id, object.clone(), false).await;
+    put(
+        store,
+        cal_id,
+        event("b", "20240101", "20240101", "Deleted"),
+        false,
+    )
+    .await;
+
+    store
+        .delete_object(PRINCIPAL, cal_id, "a", true)
+        .await
+        .unwrap();
+    assert!(matches!(
+        store.get_object(PRINCIPAL, cal_id, "a").await,
+        Err(Error::NotFound)
+    ));
+    assert_eq!(
+        object_ids(store.get_objects(PRINCIPAL, cal_id).await.unwrap()),
+        vec!["b"]
+    );
+    store.restore_object(PRINCIPAL, cal_id, "a").await.unwrap();
+    assert_eq!(
+        store
+            .get_object(PRINCIPAL, cal_id, "a")
+            .await
+            .unwrap()
+            .get_ics(),
+        object.get_ics()
+    );
+
+    store
+        .delete_object(PRINCIPAL, cal_id, "b", false)
+        .await
+        .unwrap();
+    assert!(matches!(
+        store.get_object(PRINCIPAL, cal_id, "b").await,
+        Err(Error::NotFound)
+    ));
+
+    store
+        .delete_calendar(PRINCIPAL, cal_id, true)
+        .await
+        .unwrap();
+    let calendar_ids = |calendars: Vec<Calendar>| -> Vec<String> {
+        calendars
+            .into_iter()
+            .map(|cal| cal.id)
+            .filter(|id| id == cal_id)
+            .collect()
+    };
+    assert!(calendar_ids(store.get_calendars(PRINCIPAL).await.unwrap()).is_empty());
+    assert_eq!(
+        calendar_ids(store.get_deleted_calendars(PRINCIPAL).await.unwrap()),
+        vec![cal_id]
+    );
+    assert!(
+        store
+            .get_calendar(PRINCIPAL, cal_id)
+            .await
+            .unwrap()
+            .deleted_at
+            .is_some()
+    );
+
+    store.restore_calendar(PRINCIPAL, cal_id).await.unwrap();
+    assert_eq!(
+        calendar_ids(store.get_calendars(PRINCIPAL).await.unwrap()),
+        vec![cal_id]
+    );
+    assert!(calendar_ids(store.get_deleted_calendars(PRINCIPAL).await.unwrap()).is_empty());
+    assert_eq!(
+        object_ids(store.get_objects(PRINCIPAL, cal_id).await.unwrap()),
+        vec!["a"]
+    );
+
+    store
+        .delete_calendar(PRINCIPAL, cal_id, false)
+        .await
+        .unwrap();
+    assert!(matches!(
+        store.get_calendar(PRINCIPAL, cal_id).await,
+        Err(Error::NotFound)
+    ));
+    assert!(calendar_ids(store.get_deleted_calendars(PRINCIPAL).await.unwrap()).is_empty());
+}
+
+/// Purging only deletes what was moved to the trashbin before the cutoff,
+/// the deletion of purged objects must still be reported by `sync_changes`
+pub async fn test_calendar_purge_trashbin<CS: CalendarStore>(store: &CS) {
+    let cal_id = "purge";
+    create_calendar(store, cal_id).await;
+    put(
+        store,
+        cal_id,
+        event("a", "20240101", "20240101", "A"),
+        false,
+    )
+    .await;
+    put(
+        store,
+        cal_id,
+        event("b", "20240101", "20240101", "B"),
+        false,
+    )
+    .await;
+    let synctoken = store
+        .get_calendar(PRINCIPAL, cal_id)
+        .await
+        .unwrap()
+        .synctoken;
+    store
+        .delete_object(PRINCIPAL, cal_id, "a", true)
+        .await
+        .unwrap();
+    assert_eq!(
+        object_ids(store.get_deleted_objects(PRINCIPAL, cal_id).await.unwrap()),
+        vec!["a"]
+    );
+
+    let trashed_cal_id = "purge-trashed";
+    create_calendar(store, trashed_cal_id).await;
+    store
+        .delete_calendar(PRINCIPAL, trashed_cal_id, true)
+        .await
+        .unwrap();
+
+    store
+        .purge_trashbin(Utc::now().naive_utc() - TimeDelta::hours(1))
+        .await
+        .unwrap();
+    assert_eq!(
+        object_ids(store.get_deleted_objects(PRINCIPAL, cal_id).await.unwrap()),
+        vec!["a"]
+    );
+    store.get_calendar(PRINCIPAL, trashed_cal_id).await.unwrap();
+
+    let purged = store
+        .purge_trashbin(Utc::now().naive_utc() + TimeDelta::minutes(1))
+        .await
+        .unwrap();
+    assert!(purged >= 2, "expected at least two purged items: {purged}");
+    assert!(
+        store
+            .get_deleted_objects(PRINCIPAL, cal_id)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    assert!(matches!(
+        store.get_calendar(PRINCIPAL, trashed_cal_id).await,
+        Err(Error::NotFound)
+    ));
+    assert_eq!(
+        object_ids(store.get_objects(PRINCIPAL, cal_id).await.unwrap()),
+        vec!["b"]
+    );
+
+    let (objects, deleted, _, _) = store
+        .sync_changes(PRINCIPAL, cal_id, synctoken, None)
+        .await
+        .unwrap();
+    assert!(objects.is_empty());
+    assert_eq!(deleted, vec!["a"]);
+}
+
+/// Every write is recorded as a revision, rolling back writes an old revision as a new change
+pub async fn test_calendar_revisions<S: CalendarStore>(store: &S) {
+    let cal_id = "revisions";
+    create_calendar(store, cal_id).await;
+    let first = event("a", "20240101", "20240101", "First");
+    put(store, cal_id, first.clone(), false).await;
+    store
+        .put_object(
+            PRINCIPAL.to_owned(),
+            cal_id.to_owned(),
+            event("a", "20240101", "20240101", "Second"),
+            true,
+            Some("editor"),
+        )
+        .await
+        .unwrap();
+
+    let revisions = store
+        .get_object_revisions(PRINCIPAL, cal_id, "a")
+        .await
+        .unwrap();
+    assert_eq!(revisions.len(), 2);
+    assert!(revisions[0].revision > revisions[1].revision);
+    assert_eq!(revisions[0].changed_by.as_deref(), Some("editor"));
+    assert_eq!(revisions[1].changed_by.as_deref(), Some(PRINCIPAL));
+    assert_eq!(revisions[1].object.get_ics(), first.get_ics());
+    let synctoken = store
+        .get_calendar(PRINCIPAL, cal_id)
+        .await
+        .unwrap()
+        .synctoken;
+    assert_eq!(revisions[0].revision, synctoken);
+
+    store
+        .rollback_object(
+            PRINCIPAL,
+            cal_id,
+            "a",
+            revisions[1].revision,
+            Some(PRINCIPAL),
+            &unlimited_quota(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        store
+            .get_object(PRINCIPAL, cal_id, "a")
+            .await
+            .unwrap()
+            .get_ics(),
+        first.get_ics()
+    );
+    let (objects, _, new_synctoken, _) = store
+        .sync_changes(PRINCIPAL, cal_id, synctoken, None)
+        .await
+        .unwrap();
+    assert_eq!(object_ids(objects), vec!["a"]);
+    assert!(new_synctoken > synctoken);
+    assert!(matches!(
+        store
+            .rollback_object(
+                PRINCIPAL,
+                cal_id,
+                "a",
+                -1,
+                Some(PRINCIPAL),
+                &unlimited_quota()
+            )
+            .await,
+        Err(Error::NotFound)
+    ));
+
+    for _ in 0..MAX_REVISIONS {
+        put(store, cal_id, first.clone(), true).await;
+    }
+    assert_eq!(
+        store
+            .get_object_revisions(PRINCIPAL, cal_id, "a")
+            .await
+            .unwrap()
+            .len(),
+        MAX_REVISIONS
+    );
+
+    store
+        .delete_object(PRINCIPAL, cal_id, "a", false)
+        .await
+        .unwrap();
+    assert!(
+        store
+            .get_object_revisions(PRINCIPAL, cal_id, "a")
+            .await
+            .unwrap()
+            .is_empty()
+    );
+}
+
+/// `sync_changes` must report the latest state of every object changed after the synctoken
+pub async fn test_calendar_sync_changes<CS: CalendarStore>(store: &CS) {
+    let cal_id = "sync-changes";
+    let initial_synctoken = create_calendar(store, cal_id).await.synctoken;
+    let sync = async |synctoken| {
+        let (objects, deleted, new_synctoken, _) = store
+            .sync_changes(PRINCIPAL, cal_id, synctoken, None)
+            .await
+            .unwrap();
+        (object_ids(objects), sorted(deleted), new_synctoken)
+    };
+    let current_synctoken = async || {
+        store
+            .get_calendar(PRINCIPAL, cal_id)
+            .await
+            .unwrap()
+            .synctoken
+    };
+
+    assert_eq!(
+        sync(initial_synctoken).await,
+        (vec![], vec![], initial_synctoken)
+    );
+
+    put(
+        store,
+        cal_id,
+        event("a", "20240101", "20240101", "A"),
+        false,
+    )
+    .await;
+    put(
+        store,
+        cal_id,
+        event("b", "20240101", "20240101", "B"),
+        false,
+    )
+    .await;
+    let (objects, deleted, synctoken_added) = sync(initial_synctoken).await;
+    assert_eq!(
+        (objects, deleted),
+        (vec!["a".to_owned(), "b".to_owned()], vec![])
+    );
+    assert!(synctoken_added > initial_synctoken);
+    assert_eq!(synctoken_added, current_synctoken().await);
+    assert_eq!(
+        sync(synctoken_added).await,
+        (vec![], vec![], synctoken_added)
+    );
+
+    store
+        .delete_object(PRINCIPAL, cal_id, "a", true)
+        .await
+        .unwrap();
+    let (objects, deleted, synctoken_trashed) = sync(synctoken_added).await;
+    assert_eq!((objects, deleted), (vec![], vec!["a".to_owned()]));
+    assert!(synctoken_trashed > synctoken_added);
+
+    store.restore_object(PRINCIPAL, cal_id, "a").await.unwrap();
+    let (objects, deleted, synctoken_restored) = sync(synctoken_trashed).await;
+    assert_eq!((objects, deleted), (vec!["a".to_owned()], vec![]));
+    assert!(synctoken_restored > synctoken_trashed);
+    // Only the latest state of an object counts
+    assert_eq!(
+        sync(synctoken_added).await,
+        (vec!["a".to_owned()], vec![], synctoken_restored)
+    );
+
+    store
+        .delete_object(PRINCIPAL, cal_id, "b", false)
+        .await
+        .unwrap();
+    let (objects, deleted, synctoken_deleted) = sync(synctoken_restored).await;
+    assert_eq!((objects, deleted), (vec![], vec!["b".to_owned()]));
+    assert_eq!(synctoken_deleted, current_synctoken().await);
+
+    assert_eq!(
+        sync(initial_synctoken).await,
+        (
+            vec!["a".to_owned()],
+            vec!["b".to_owned()],
+            synctoken_deleted
+        )
+    );
+}
+
+/// `calendar_query` may return more objects than requested but must never miss a matching one
+pub async fn test_calendar_query<CS: CalendarStore>(store: &CS) {
+    let cal_id = "calendar-query";
+    create_calendar(store, cal_id).await;
+    put(
+        store,
+        cal_id,
+        event("past", "20240110", "20240110", "Past"),
+        false,
+    )
+    .await;
+    put(
+        store,
+        cal_id,
+        event("spanning", "20240520", "20240605", "Spanning"),
+        false,
+    )
+    .await;
+    put(
+        store,
+        cal_id,
+        event("current", "20240615", "20240615", "Current"),
+        false,
+    )
+    .await;
+    put(
+        store,
+        cal_id,
+        event("future", "20241220", "20241220", "Future"),
+        false,
+    )
+    .await;
+    put(store, cal_id, todo("undated"), false).await;
+    // Floating times only have a local date, which can be a day off from the UTC date
+    let floating = |uid: &str, start: &str, end: &str| {
+        let ics = event(uid, "20240101", "20240101", "Floating")
+            .get_ics()
+            .replace("DTSTART:20240101T100000Z", &format!("DTSTART:{start}"))
+            .replace("DTEND:20240101T110000Z", &format!("DTEND:{end}"));
+        CalendarObject::from_ics(uid.to_owned(), ics).unwrap()
+    };
+    // 2024-01-01T18:00Z in Australia/Sydney
+    put(
+        store,
+        cal_id,
+        floating("floating-sydney", "20240102T050000", "20240102T060000"),
+        false,
+    )
+    .await;
+    // 2024-01-01T03:00Z in America/New_York
+    put(
+        store,
+        cal_id,
+        floating("floating-new-york", "20231231T220000", "20231231T230000"),
+        false,
+    )
+    .await;
+    put(
+        store,
+        cal_id,
+        event("trashed", "20240615", "20240615", "Trashed"),
+        false,
+    )
+    .await;
+    store
+        .delete_object(PRINCIPAL, cal_id, "trashed", true)
+        .await
+        .unwrap();
+
+    let query = async |time_start, time_end| {
+        object_ids(
+            store
+                .calendar_query(
+                    PRINCIPAL,
+                    cal_id,
+                    CalendarQuery {
+                        time_start,
+                        time_end,
+                    },
+                )
+                .await
+                .unwrap(),
+        )
+    };
+
+    assert_eq!(
+        query(None, None).await,
+        vec![
+            "current",
+            "floating-new-york",
+            "floating-sydney",
+            "future",
+            "past",
+            "spanning",
+            "undated"
+        ]
+    );
+
+    // A range ending on 2024-01-01T20:00Z
+    let until_new_year = query(None, NaiveDate::from_ymd_opt(2024, 1, 1)).await;
+    assert!(until_new_year.iter().any(|id| id == "floating-sydney"));
+    // A range starting on 2024-01-01T02:00Z
+    let since_new_year = query(NaiveDate::from_ymd_opt(2024, 1, 1), None).await;
+    assert!(since_new_year.iter().any(|id| id == "floating-new-york"));
+
+    let june = query(
+        NaiveDate::from_ymd_opt(2024, 6, 1),
+        NaiveDate::from_ymd_opt(2024, 6, 30),
+    )
+    .await;
+    for expected in ["spanning", "current", "undated"] {
+        assert!(june.iter().any(|id| id == expected), "missing {expected}");
+    }
+    assert!(!june.iter().any(|id| id == "trashed"));
+
+    let since_july = query(NaiveDate::from_ymd_opt(2024, 7, 1), None).await;
+    for expected in ["future", "undated"] {
+        assert!(
+            since_july.iter().any(|id| id == expected),
+            "missing {expected}"
+        );
+    }
+}
+
+/// Objects read from the store must report the same occurences as when they were put
+pub async fn test_calendar_occurences<CS: CalendarStore>(store: &CS) {
+    let cal_id = "calendar-occurences";
+    create_calendar(store, cal_id).await;
+    let event = |uid: &str, dtstart: &str, rrule: &str| {
+        let ics = format!(
+            "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:-//rustical//conformance//EN\r
+BEGIN:VEVENT\r
+UID:{uid}\r
+DTSTAMP:20240101T000000Z\r
+DTSTART{dtstart}\r
+DURATION:PT1H\r
+{rrule}END:VEVENT\r
+END:VCALENDAR\r
+"
+        );
+        CalendarObject::from_ics(uid.to_owned(), ics).unwrap()
+    };
+    let objects = [
+        event("utc", ":20240102T050000Z", ""),
+        event("zoned", ";TZID=Australia/Sydney:20240102T050000", ""),
+        event(
+            "floating",
+            ":20240102T050000",
+            "RRULE:FREQ=DAILY;COUNT=3\r\n",
+        ),
+        event("date", ";VALUE=DATE:20240102", ""),
+        event("infinite", ":20240102T050000Z", "RRULE:FREQ=WEEKLY\r\n"),
+    ];
+    for object in &objects {
+        put(store, cal_id, object.clone(), false).await;
+    }
+
+    let timezone = CalTimezone::Olson(chrono_tz::America::New_York);
+    for object in &objects {
+        let stored = store
+            .get_object(PRINCIPAL, cal_id, object.get_id())
+            .await
+            .unwrap();
+        for (stored, expected) in [
+            (
+                stored.get_first_occurence().unwrap(),
+                object.get_first_occurence().unwrap(),
+            ),
+            (
+                stored.get_last_occurence().unwrap(),
+                object.get_last_occurence().unwrap(),
+            ),
+        ] {
+            // Zoned times may come back in UTC, floating times must stay floating
+            assert_eq!(
+                stored
+                    .as_ref()
+                    .map(|datetime| datetime.utc_in(Some(&timezone))),
+                expected
+                    .as_ref()
+                    .map(|datetime| datetime.utc_in(Some(&timezone))),
+                "{}",
+                object.get_id()
+            );
+        }
+    }
+}
+
+/// The streaming variants must yield the same objects, also if they are fetched in several pages
+pub async fn test_calendar_object_streams<CS: CalendarStore>(store: &CS) {
+    let cal_id = "object-streams";
+    create_calendar(store, cal_id).await;
+    for i in 0..150 {
+        put(store, cal_id, todo(&format!("todo-{i:03}")), false).await;
+    }
+    put(
+        store,
+        cal_id,
+        event("june", "20240615", "20240615", "June"),
+        false,
+    )
+    .await;
+    put(
+        store,
+        cal_id,
+        event("trashed", "20240615", "20240615", "Trashed"),
+        false,
+    )
+    .await;
+    store
+        .delete_object(PRINCIPAL, cal_id, "trashed", true)
+        .await
+        .unwrap();
+
+    let objects: Vec<CalendarObject> = store
+        .get_objects_stream(PRINCIPAL, cal_id)
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(objects.len(), 151);
+    assert_eq!(
+        object_ids(objects),
+        object_ids(store.get_objects(PRINCIPAL, cal_id).await.unwrap())
+    );
+
+    let query = CalendarQuery {
+        time_start: NaiveDate::from_ymd_opt(2024, 6, 1),
+        time_end: NaiveDate::from_ymd_opt(2024, 6, 30),
+    };
+    let objects: Vec<CalendarObject> = store
+        .calendar_query_stream(PRINCIPAL, cal_id, query.clone())
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+    assert!(objects.iter().any(|object| object.get_id() == "june"));
+    assert_eq!(
+        object_ids(objects),
+        object_ids(
+            store
+                .calendar_query(PRINCIPAL, cal_id, query)
+                .await
+                .unwrap()
+        )
+    );
+
+    // Missing and trashed objects are left out
+    let requested = ["todo-000", "june", "trashed", "missing", "todo-149"].map(str::to_owned);
+    let objects = store
+        .get_objects_by_ids(PRINCIPAL, cal_id, &requested)
+        .await
+        .unwrap();
+    assert_eq!(object_ids(objects), ["june", "todo-000", "todo-149"]);
+    assert!(
+        store
+            .get_objects_by_ids(PRINCIPAL, cal_id, &[])
+            .await
+            .unwrap()
+            .is_empty()
+    );
+}
+
+/// Every object change and calendar deletion must be announced on the operation channel
+pub async fn test_calendar_notifications<CS: CalendarStore>(
+    store: &CS,
+    recv: &mut Receiver<CollectionOperation>,
+) {
+    let cal_id = "notifications";
+    let topic = create_calendar(store, cal_id).await.push_topic;
+    drain_operations(recv);
+    let assert_notification = async |recv: &mut Receiver<CollectionOperation>| {
+        let synctoken = store
+            .get_calendar(PRINCIPAL, cal_id)
+            .await
+            .unwrap()
+            .format_synctoken();
+        assert_object_notification(
+            recv,
+            CollectionOperationDomain::Calendar,
+            &topic,
+            &synctoken,
+        );
+    };
+
+    put(
+        store,
+        cal_id,
+        event("a", "20240101", "20240101", "A"),
+        false,
+    )
+    .await;
+    assert_notification(recv).await;
+    put(store, cal_id, event("a", "20240101", "20240101", "B"), true).await;
+    assert_notification(recv).await;
+    store
+        .delete_object(PRINCIPAL, cal_id, "a", true)
+        .await
+        .unwrap();
+    assert_notification(recv).await;
+    store.restore_object(PRINCIPAL, cal_id, "a").await.unwrap();
+    assert_notification(recv).await;
+    store
+        .delete_object(PRINCIPAL, cal_id, "a", false)
+        .await
+        .unwrap();
+    assert_notification(recv).await;
+
+    store
+        .delete_calendar(PRINCIPAL, cal_id, true)
+        .await
+        .unwrap();
+    assert_delete_notification(recv, CollectionOperationDomain::Calendar, &topic);
+}
+
+/// With a limit `sync_changes` returns the changes in pages that continue from the returned synctoken
+pub async fn test_calendar_sync_changes_limit<CS: CalendarStore>(store: &CS) {
+    let cal_id = "sync-limit";
+    create_calendar(store, cal_id).await;
+    for uid in ["a", "b", "c"] {
+        put(
+            store,
+            cal_id,
+            event(uid, "20240101", "20240101", "X"),
+            false,
+        )
+        .await;
+    }
+    store
+        .delete_object(PRINCIPAL, cal_id, "a", false)
+        .await
+        .unwrap();
+    let synctoken = store
+        .get_calendar(PRINCIPAL, cal_id)
+        .await
+        .unwrap()
+        .synctoken;
+
+    let (objects, deleted, continuation, truncated) = store
+        .sync_changes(PRINCIPAL, cal_id, 0, Some(1))
+        .await
+        .unwrap();
+    assert!(truncated);
+    assert_eq!(object_ids(objects), vec!["b"]);
+    assert!(deleted.is_empty());
+
+    let (objects, deleted, continuation, truncated) = store
+        .sync_changes(PRINCIPAL, cal_id, continuation, Some(1))
+        .await
+        .unwrap();
+    assert!(truncated);
+    assert_eq!(object_ids(objects), vec!["c"]);
+    assert!(deleted.is_empty());
+
+    let (objects, deleted, new_synctoken, truncated) = store
+        .sync_changes(PRINCIPAL, cal_id, continuation, Some(1))
+        .await
+        .unwrap();
+    assert!(!truncated);
+    assert!(objects.is_empty());
+    assert_eq!(deleted, vec!["a"]);
+    assert_eq!(new_synctoken, synctoken);
+
+    let (objects, deleted, new_synctoken, truncated) = store
+        .sync_changes(PRINCIPAL, cal_id, 0, Some(3))
+        .await
+        .unwrap();
+    assert!(!truncated);
+    assert_eq!(object_ids(objects), vec!["b", "c"]);
+    assert_eq!(deleted, vec!["a"]);
+    assert_eq!(new_synctoken, synctoken);
+}
+
+/// Compacting collapses the changelog and drops changes before the horizon,
+/// synctokens from before the horizon become invalid
+pub async fn test_calendar_compact_changelog<CS: CalendarStore>(store: &CS) {
+    let cal_id = "compact";
+    create_calendar(store, cal_id).await;
+    put(
+        store,
+        cal_id,
+        event("a", "20240101", "20240101", "A"),
+        false,
+    )
+    .await;
+    put(
+        store,
+        cal_id,
+        event("a", "20240101", "20240101", "A2"),
+        true,
+    )
+    .await;
+    put(
+        store,
+        cal_id,
+        event("b", "20240101", "20240101", "B"),
+        false,
+    )
+    .await;
+    store
+        .delete_object(PRINCIPAL, cal_id, "b", false)
+        .await
+        .unwrap();
+    let synctoken = store
+        .get_calendar(PRINCIPAL, cal_id)
+        .await
+        .unwrap()
+        .synctoken;
+
+    // Nothing is older than the horizon, only the history of every object is collapsed
+    store
+        .compact_changelog(Utc::now().naive_utc() - TimeDelta::hours(1))
+        .await
+        .unwrap();
+    let (objects, deleted, new_synctoken, _) = store
+        .sync_changes(PRINCIPAL, cal_id, 1, None)
+        .await
+        .unwrap();
+    assert_eq!(object_ids(objects), vec!["a"]);
+    assert_eq!(deleted, vec!["b"]);
+    assert_eq!(new_synctoken, synctoken);
+
+    store
+        .compact_changelog(Utc::now().naive_utc() + TimeDelta::minutes(1))
+        .await
+        .unwrap();
+    assert!(matches!(
+        store.sync_changes(PRINCIPAL, cal_id, 1, None).await,
+        Err(Error::InvalidSyncToken)
+    ));
+    let (objects, deleted, new_synctoken, _) = store
+        .sync_changes(PRINCIPAL, cal_id, synctoken, None)
+        .await
+        .unwrap();
+    assert!(objects.is_empty() && deleted.is_empty());
+    assert_eq!(new_synctoken, synctoken);
+    // An initial sync still lists all objects
+    let (objects, deleted, new_synctoken, _) = store
+        .sync_changes(PRINCIPAL, cal_id, 0, None)
+        .await
+        .unwrap();
+    assert_eq!(object_ids(objects), vec!["a"]);
+    assert!(deleted.is_empty());
+    assert_eq!(new_synctoken, synctoken);
+
+    put(
+        store,
+        cal_id,
+        event("c", "20240101", "20240101", "C"),
+        false,
+    )
+    .await;
+    let (objects, _, new_synctoken, _) = store
+        .sync_changes(PRINCIPAL, cal_id, synctoken, None)
+        .await
+        .unwrap();
+    assert_eq!(object_ids(objects), vec!["c"]);
+    assert_eq!(new_synctoken, synctoken + 1);
+}
+
+/// An initial sync is paged according to `limit` even if all changes were compacted
+pub async fn test_calendar_compact_changelog_limit<CS: CalendarStore>(store: &CS) {
+    let cal_id = "compact-limit";
+    create_calendar(store, cal_id).await;
+    for uid in ["a", "b", "c"] {
+        put(
+            store,
+            cal_id,
+            event(uid, "20240101", "20240101", "X"),
+            false,
+        )
+        .await;
+    }
+    let synctoken = store
+        .get_calendar(PRINCIPAL, cal_id)
+        .await
+        .unwrap()
+        .synctoken;
+    store
+        .compact_changelog(Utc::now().naive_utc() + TimeDelta::minutes(1))
+        .await
+        .unwrap();
+
+    let mut ids = vec![];
+    let mut continuation = 0;
+    loop {
+        let (objects, deleted, new_synctoken, truncated) = store
+            .sync_changes(PRINCIPAL, cal_id, continuation, Some(1))
+            .await
+            .unwrap();
+        assert!(objects.len() + deleted.len() <= 1);
+        ids.extend(object_ids(objects));
+        continuation = new_synctoken;
+        if !truncated {
+            break;
+        }
+    }
+    assert_eq!(sorted(ids), vec!["a", "b", "c"]);
+    assert_eq!(continuation, synctoken);
+}
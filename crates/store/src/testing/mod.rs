@@ -0,0 +1,103 @@
+//! Conformance tests for store implementations
+//!
+//! The SQLite store is the reference implementation, these checks pin down the behaviour
+//! that every implementation of [`CalendarStore`](crate::CalendarStore),
+//! [`AddressbookStore`](crate::AddressbookStore), [`SubscriptionStore`](crate::SubscriptionStore)
+//! and [`AuthenticationProvider`](crate::auth::AuthenticationProvider) has to share.
+//! They panic on failure and are meant to be called from an integration test:
+//! ```ignore
+//! #[tokio::test]
+//! async fn test_conformance() {
+//!     let (send, mut recv) = tokio::sync::mpsc::channel(100);
+//!     let store = MyCalendarStore::new(send);
+//!     rustical_store::testing::test_calendar_store(&store, &mut recv).await;
+//! }
+//! ```
+//! Every check works on its own collections, so all of them can run against the same store.
+mod addressbook_store;
+mod auth;
+mod calendar_store;
+mod quota;
+mod subscription_store;
+
+pub use addressbook_store::*;
+pub use auth::*;
+pub use calendar_store::*;
+pub use quota::*;
+pub use subscription_store::*;
+
+use crate::memory::{MemoryAddressbookStore, MemoryCalendarStore};
+use crate::quota::{Quota, QuotaConfig};
+use crate::{CollectionOperation, CollectionOperationDomain, CollectionOperationType};
+use std::sync::Arc;
+use tokio::sync::mpsc::Receiver;
+
+/// Principal owning all collections created by the conformance tests
+pub const PRINCIPAL: &str = "conformance";
+
+fn drain_operations(recv: &mut Receiver<CollectionOperation>) -> Vec<CollectionOperation> {
+    let mut operations = vec![];
+    while let Ok(operation) = recv.try_recv() {
+        operations.push(operation);
+    }
+    operations
+}
+
+/// Asserts that exactly one notification about an object change has been emitted
+fn assert_object_notification(
+    recv: &mut Receiver<CollectionOperation>,
+    domain: CollectionOperationDomain,
+    topic: &str,
+    synctoken: &str,
+) {
+    let operations = drain_operations(recv);
+    assert_eq!(
+        operations.len(),
+        1,
+        "expected one notification: {operations:?}"
+    );
+    let operation = &operations[0];
+    assert!(matches!(operation.r#type, CollectionOperationType::Object));
+    assert_eq!(
+        std::mem::discriminant(&operation.domain),
+        std::mem::discriminant(&domain)
+    );
+    assert_eq!(operation.topic, topic);
+    assert_eq!(operation.sync_token.as_deref(), Some(synctoken));
+}
+
+/// Asserts that exactly one notification about a deleted collection has been emitted
+fn assert_delete_notification(
+    recv: &mut Receiver<CollectionOperation>,
+    domain: CollectionOperationDomain,
+    topic: &str,
+) {
+    let operations = drain_operations(recv);
+    assert_eq!(
+        operations.len(),
+        1,
+        "expected one notification: {operations:?}"
+    );
+    let operation = &operations[0];
+    assert!(matches!(operation.r#type, CollectionOperationType::Delete));
+    assert_eq!(
+        std::mem::discriminant(&operation.domain),
+        std::mem::discriminant(&domain)
+    );
+    assert_eq!(operation.topic, topic);
+}
+
+/// A quota without limits, it never has to look at the stores it is created with
+fn unlimited_quota() -> Quota {
+    let (send, _recv) = tokio::sync::mpsc::channel(1);
+    Quota::new(
+        QuotaConfig::default(),
+        Arc::new(MemoryCalendarStore::new(send.clone())),
+        Arc::new(MemoryAddressbookStore::new(send)),
+    )
+}
+
+fn sorted(mut ids: Vec<String>) -> Vec<String> {
+    ids.sort();
+    ids
+}
@@ -0,0 +1,165 @@
+use super::PRINCIPAL;
+use super::addressbook_store::{contact, create_addressbook};
+use super::calendar_store::{create_calendar, event};
+use crate::quota::{Quota, QuotaConfig};
+use crate::{AddressbookStore, CalendarStore, Error};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Writes with a quota must count calendars and addressbooks together
+/// and leave nothing behind when they're rejected
+pub async fn test_quota<CS: CalendarStore, AS: AddressbookStore>(
+    cal_store: Arc<CS>,
+    addr_store: Arc<AS>,
+) {
+    let collection_id = "quota";
+    create_calendar(cal_store.as_ref(), collection_id).await;
+    create_addressbook(addr_store.as_ref(), collection_id).await;
+
+    let first_event = event("a", "20240101", "20240101", "First");
+    let first_contact = contact("a", "First");
+    let unlimited = Quota::new(
+        QuotaConfig::default(),
+        cal_store.clone(),
+        addr_store.clone(),
+    );
+    let used_bytes = unlimited.get_usage(PRINCIPAL).await.unwrap().used_bytes;
+    let limit =
+        used_bytes + first_event.get_ics().len() as u64 + first_contact.get_vcf().len() as u64;
+    let quota = Quota::new(
+        QuotaConfig {
+            principals: HashMap::from([(PRINCIPAL.to_owned(), limit)]),
+            ..Default::default()
+        },
+        cal_store.clone(),
+        addr_store.clone(),
+    );
+
+    let put_event = |object| {
+        cal_store.put_object_with_quota(
+            PRINCIPAL.to_owned(),
+            collection_id.to_owned(),
+            object,
+            true,
+            Some(PRINCIPAL),
+            &quota,
+        )
+    };
+    let put_contact = |object| {
+        addr_store.put_object_with_quota(
+            PRINCIPAL.to_owned(),
+            collection_id.to_owned(),
+            object,
+            true,
+            Some(PRINCIPAL),
+            &quota,
+        )
+    };
+
+    put_event(first_event.clone()).await.unwrap();
+    put_contact(first_contact.clone()).await.unwrap();
+    assert_eq!(quota.get_usage(PRINCIPAL).await.unwrap().used_bytes, limit);
+
+    // Growing beyond the quota is rejected in both stores
+    assert!(matches!(
+        put_event(event("b", "20240101", "20240101", "Second")).await,
+        Err(Error::QuotaExceeded)
+    ));
+    assert!(matches!(
+        cal_store.get_object(PRINCIPAL, collection_id, "b").await,
+        Err(Error::NotFound)
+    ));
+    assert!(matches!(
+        put_contact(contact("a", "First and grown")).await,
+        Err(Error::QuotaExceeded)
+    ));
+    assert_eq!(
+        addr_store
+            .get_object(PRINCIPAL, collection_id, "a")
+            .await
+            .unwrap()
+            .get_vcf(),
+        first_contact.get_vcf()
+    );
+    assert_eq!(quota.get_usage(PRINCIPAL).await.unwrap().used_bytes, limit);
+
+    // Replacing an object with one of the same size is always allowed
+    put_event(event("a", "20240102", "20240102", "Other"))
+        .await
+        .unwrap();
+
+    // Rolling back to a larger revision is a write like any other
+    let grown_contact = contact("a", "First and grown");
+    addr_store
+        .put_object_with_quota(
+            PRINCIPAL.to_owned(),
+            collection_id.to_owned(),
+            grown_contact.clone(),
+            true,
+            Some(PRINCIPAL),
+            &unlimited,
+        )
+        .await
+        .unwrap();
+    put_contact(first_contact.clone()).await.unwrap();
+    let grown_revision = addr_store
+        .get_object_revisions(PRINCIPAL, collection_id, "a")
+        .await
+        .unwrap()[1]
+        .revision;
+    let rollback = |quota| {
+        addr_store.rollback_object(
+            PRINCIPAL,
+            collection_id,
+            "a",
+            grown_revision,
+            Some(PRINCIPAL),
+            quota,
+        )
+    };
+    assert!(matches!(rollback(&quota).await, Err(Error::QuotaExceeded)));
+    let small_resources = Quota::new(
+        QuotaConfig {
+            max_resource_size: first_contact.get_vcf().len() as u64,
+            ..Default::default()
+        },
+        cal_store.clone(),
+        addr_store.clone(),
+    );
+    assert!(matches!(
+        rollback(&small_resources).await,
+        Err(Error::MaxResourceSizeExceeded)
+    ));
+    assert_eq!(
+        addr_store
+            .get_object(PRINCIPAL, collection_id, "a")
+            .await
+            .unwrap()
+            .get_vcf(),
+        first_contact.get_vcf()
+    );
+    assert_eq!(quota.get_usage(PRINCIPAL).await.unwrap().used_bytes, limit);
+
+    // Objects in the trashbin free their space
+    cal_store
+        .delete_object(PRINCIPAL, collection_id, "a", true)
+        .await
+        .unwrap();
+    put_event(event("b", "20240101", "20240101", "Other"))
+        .await
+        .unwrap();
+    assert_eq!(quota.get_usage(PRINCIPAL).await.unwrap().used_bytes, limit);
+
+    cal_store
+        .delete_calendar(PRINCIPAL, collection_id, false)
+        .await
+        .unwrap();
+    addr_store
+        .delete_addressbook(PRINCIPAL, collection_id, false)
+        .await
+        .unwrap();
+    assert_eq!(
+        quota.get_usage(PRINCIPAL).await.unwrap().used_bytes,
+        used_bytes
+    );
+}
@@ -0,0 +1,63 @@
+use crate::{Error, Subscription, SubscriptionStore};
+use chrono::NaiveDate;
+
+fn subscription(id: &str, topic: &str, push_resource: &str) -> Subscription {
+    Subscription {
+        id: id.to_owned(),
+        topic: topic.to_owned(),
+        expiration: NaiveDate::from_ymd_opt(2100, 1, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap(),
+        push_resource: push_resource.to_owned(),
+    }
+}
+
+/// Runs all subscription store checks
+pub async fn test_subscription_store<SS: SubscriptionStore>(store: &SS) {
+    store
+        .upsert_subscription(subscription("a", "topic", "https://push.example.com/a"))
+        .await
+        .unwrap();
+    store
+        .upsert_subscription(subscription("b", "topic", "https://push.example.com/b"))
+        .await
+        .unwrap();
+    store
+        .upsert_subscription(subscription("c", "other", "https://push.example.com/c"))
+        .await
+        .unwrap();
+
+    let mut ids: Vec<_> = store
+        .get_subscriptions("topic")
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|sub| sub.id)
+        .collect();
+    ids.sort();
+    assert_eq!(ids, vec!["a", "b"]);
+
+    let sub = store.get_subscription("a").await.unwrap();
+    assert_eq!(sub.topic, "topic");
+    assert_eq!(sub.push_resource, "https://push.example.com/a");
+    assert_eq!(sub.expiration, subscription("a", "", "").expiration);
+
+    // Upserting replaces the subscription
+    store
+        .upsert_subscription(subscription("a", "other", "https://push.example.com/new"))
+        .await
+        .unwrap();
+    let sub = store.get_subscription("a").await.unwrap();
+    assert_eq!(sub.topic, "other");
+    assert_eq!(sub.push_resource, "https://push.example.com/new");
+    assert_eq!(store.get_subscriptions("topic").await.unwrap().len(), 1);
+    assert_eq!(store.get_subscriptions("other").await.unwrap().len(), 2);
+
+    store.delete_subscription("a").await.unwrap();
+    assert!(matches!(
+        store.get_subscription("a").await,
+        Err(Error::NotFound)
+    ));
+    assert_eq!(store.get_subscriptions("other").await.unwrap().len(), 1);
+}
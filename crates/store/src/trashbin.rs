@@ -0,0 +1,35 @@
+use crate::{AddressbookStore, CalendarStore, Error};
+use chrono::{TimeDelta, Utc};
+use std::{sync::Arc, time::Duration};
+use tracing::{error, info};
+
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Permanently deletes everything that was moved to the trashbin more than `retention_days` ago
+/// and returns how many calendars, addressbooks and objects were deleted
+pub async fn purge_trashbin(
+    addr_store: &impl AddressbookStore,
+    cal_store: &impl CalendarStore,
+    retention_days: u64,
+) -> Result<usize, Error> {
+    let deleted_before = Utc::now().naive_utc() - TimeDelta::days(retention_days as i64);
+    Ok(cal_store.purge_trashbin(deleted_before).await?
+        + addr_store.purge_trashbin(deleted_before).await?)
+}
+
+/// Purges the trashbin every hour
+pub async fn trashbin_purger(
+    addr_store: Arc<impl AddressbookStore>,
+    cal_store: Arc<impl CalendarStore>,
+    retention_days: u64,
+) {
+    let mut interval = tokio::time::interval(PURGE_INTERVAL);
+    loop {
+        interval.tick().await;
+        match purge_trashbin(addr_store.as_ref(), cal_store.as_ref(), retention_days).await {
+            Ok(0) => {}
+            Ok(count) => info!("Purged {count} items from the trashbin"),
+            Err(err) => error!("Purging the trashbin failed: {err}"),
+        }
+    }
+}
@@ -35,7 +35,7 @@ async fn test_create_event<CS: CalendarStore>(store: CS) {
 
     let object = CalendarObject::from_ics("asd".to_owned(), EVENT.to_owned()).unwrap();
     store
-        .put_object("testuser".to_owned(), "test".to_owned(), object, true)
+        .put_object("testuser".to_owned(), "test".to_owned(), object, true, None)
         .await
         .unwrap();
 
@@ -0,0 +1,49 @@
+use rustical_store::memory::{
+    MemoryAddressbookStore, MemoryCalendarStore, MemoryPrincipalStore, MemorySubscriptionStore,
+};
+use rustical_store::testing::{
+    test_addressbook_store, test_authentication_provider, test_calendar_store, test_quota,
+    test_subscription_store,
+};
+use rustical_store_sqlite::{
+    SqliteStore, addressbook_store::SqliteAddressbookStore, calendar_store::SqliteCalendarStore,
+    create_test_db, principal_store::SqlitePrincipalStore,
+};
+use std::sync::Arc;
+
+#[tokio::test]
+async fn test_memory_conformance() {
+    let (send, mut recv) = tokio::sync::mpsc::channel(100);
+    test_calendar_store(&MemoryCalendarStore::new(send.clone()), &mut recv).await;
+    test_addressbook_store(&MemoryAddressbookStore::new(send.clone()), &mut recv).await;
+    test_quota(
+        Arc::new(MemoryCalendarStore::new(send.clone())),
+        Arc::new(MemoryAddressbookStore::new(send)),
+    )
+    .await;
+    test_subscription_store(&MemorySubscriptionStore::default()).await;
+    test_authentication_provider(&MemoryPrincipalStore::default()).await;
+}
+
+#[tokio::test]
+async fn test_sqlite_conformance() {
+    let db = create_test_db().await.unwrap();
+    let (send, mut recv) = tokio::sync::mpsc::channel(100);
+    test_calendar_store(
+        &SqliteCalendarStore::new(db.clone(), send.clone()),
+        &mut recv,
+    )
+    .await;
+    test_addressbook_store(
+        &SqliteAddressbookStore::new(db.clone(), send.clone()),
+        &mut recv,
+    )
+    .await;
+    test_quota(
+        Arc::new(SqliteCalendarStore::new(db.clone(), send.clone())),
+        Arc::new(SqliteAddressbookStore::new(db.clone(), send)),
+    )
+    .await;
+    test_subscription_store(&SqliteStore::new(db.clone())).await;
+    test_authentication_provider(&SqlitePrincipalStore::new(db)).await;
+}
@@ -0,0 +1,16 @@
+-- Dates of contacts (birthdays, anniversaries and X-ABDATEs) to prefilter birthday calendars.
+-- Written together with the object, objects stored before are filled in on startup.
+CREATE TABLE addressobjectdates (
+    principal TEXT NOT NULL,
+    addressbook_id TEXT NOT NULL,
+    object_id TEXT NOT NULL,
+    -- Month and day as MMDD
+    month_day INTEGER NOT NULL,
+    year INTEGER,
+    FOREIGN KEY (principal, addressbook_id, object_id)
+    REFERENCES addressobjects (principal, addressbook_id, id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+CREATE INDEX idx_addrobj_dates ON addressobjectdates (principal, addressbook_id, month_day);
+
+ALTER TABLE addressobjects ADD COLUMN dates_indexed BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,62 @@
+-- Bytes used by the objects of a principal outside the trashbin, kept up to date by triggers
+-- so that quotas can be enforced in the transaction of a write
+CREATE TABLE principal_usage (
+    principal TEXT NOT NULL PRIMARY KEY,
+    calendar_bytes BIGINT NOT NULL DEFAULT 0,
+    addressbook_bytes BIGINT NOT NULL DEFAULT 0
+);
+
+INSERT INTO principal_usage (principal, calendar_bytes)
+SELECT principal, SUM(octet_length(ics)) FROM calendarobjects
+WHERE deleted_at IS NULL GROUP BY principal;
+
+INSERT INTO principal_usage (principal, addressbook_bytes)
+SELECT principal, SUM(octet_length(vcf)) FROM addressobjects
+WHERE deleted_at IS NULL GROUP BY principal
+ON CONFLICT (principal) DO UPDATE SET addressbook_bytes = excluded.addressbook_bytes;
+
+CREATE FUNCTION calendarobjects_usage() RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP IN ('UPDATE', 'DELETE') THEN
+        IF OLD.deleted_at IS NULL THEN
+            UPDATE principal_usage SET calendar_bytes = calendar_bytes - octet_length(OLD.ics)
+            WHERE principal = OLD.principal;
+        END IF;
+    END IF;
+    IF TG_OP IN ('INSERT', 'UPDATE') THEN
+        IF NEW.deleted_at IS NULL THEN
+            INSERT INTO principal_usage (principal, calendar_bytes)
+            VALUES (NEW.principal, octet_length(NEW.ics))
+            ON CONFLICT (principal) DO UPDATE
+            SET calendar_bytes = principal_usage.calendar_bytes + excluded.calendar_bytes;
+        END IF;
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER calendarobjects_usage AFTER INSERT OR UPDATE OF principal, ics, deleted_at OR DELETE
+ON calendarobjects FOR EACH ROW EXECUTE FUNCTION calendarobjects_usage();
+
+CREATE FUNCTION addressobjects_usage() RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP IN ('UPDATE', 'DELETE') THEN
+        IF OLD.deleted_at IS NULL THEN
+            UPDATE principal_usage SET addressbook_bytes = addressbook_bytes - octet_length(OLD.vcf)
+            WHERE principal = OLD.principal;
+        END IF;
+    END IF;
+    IF TG_OP IN ('INSERT', 'UPDATE') THEN
+        IF NEW.deleted_at IS NULL THEN
+            INSERT INTO principal_usage (principal, addressbook_bytes)
+            VALUES (NEW.principal, octet_length(NEW.vcf))
+            ON CONFLICT (principal) DO UPDATE
+            SET addressbook_bytes = principal_usage.addressbook_bytes + excluded.addressbook_bytes;
+        END IF;
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER addressobjects_usage AFTER INSERT OR UPDATE OF principal, vcf, deleted_at OR DELETE
+ON addressobjects FOR EACH ROW EXECUTE FUNCTION addressobjects_usage();
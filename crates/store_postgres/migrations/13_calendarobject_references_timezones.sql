@@ -0,0 +1,4 @@
+-- Whether an object references IANA timezones without their VTIMEZONE, so that its ETag
+-- for clients that don't accept timezones by reference (RFC 7809) is known without its content.
+-- NULL if not known yet, objects stored before are filled in on startup.
+ALTER TABLE calendarobjects ADD COLUMN references_timezones BOOLEAN;
@@ -0,0 +1,7 @@
+CREATE TABLE davpush_subscriptions (
+    id TEXT NOT NULL,
+    topic TEXT NOT NULL,
+    expiration TIMESTAMP NOT NULL,
+    push_resource TEXT NOT NULL,
+    PRIMARY KEY (id)
+);
@@ -0,0 +1,12 @@
+CREATE TABLE birthday_settings (
+    principal TEXT NOT NULL,
+    birthday_summary_template TEXT NOT NULL,
+    anniversary_summary_template TEXT NOT NULL,
+    abdate_summary_template TEXT NOT NULL,
+    alarm_trigger TEXT,
+    color TEXT,
+    displayname_template TEXT NOT NULL,
+    include_anniversaries BOOLEAN NOT NULL,
+    include_abdates BOOLEAN NOT NULL,
+    PRIMARY KEY (principal)
+);
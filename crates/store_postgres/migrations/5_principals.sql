@@ -0,0 +1,38 @@
+CREATE TABLE principals (
+    id TEXT NOT NULL,
+    displayname TEXT,
+    principal_type TEXT NOT NULL,
+    password TEXT,
+    PRIMARY KEY (id)
+);
+
+CREATE TABLE principal_emails (
+    principal TEXT NOT NULL,
+    -- The first address is the primary one
+    position INTEGER NOT NULL,
+    email TEXT NOT NULL,
+    PRIMARY KEY (principal, position),
+    FOREIGN KEY (principal)
+    REFERENCES principals (id) ON DELETE CASCADE
+);
+
+CREATE TABLE memberships (
+    principal TEXT NOT NULL,
+    member_of TEXT NOT NULL,
+    PRIMARY KEY (principal, member_of),
+    FOREIGN KEY (principal)
+    REFERENCES principals (id) ON DELETE CASCADE
+);
+
+CREATE TABLE app_tokens (
+    id TEXT NOT NULL,
+    principal TEXT NOT NULL,
+    name TEXT NOT NULL,
+    token TEXT NOT NULL,
+    created_at TIMESTAMPTZ,
+    PRIMARY KEY (id),
+    FOREIGN KEY (principal)
+    REFERENCES principals (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_app_tokens_principal ON app_tokens (principal);
@@ -0,0 +1,27 @@
+CREATE TABLE calendarobjectrevisions (
+    principal TEXT NOT NULL,
+    cal_id TEXT NOT NULL,
+    object_id TEXT NOT NULL,
+    -- Synctoken of the change that wrote the revision
+    revision BIGINT NOT NULL,
+    ics TEXT NOT NULL,
+    changed_by TEXT,
+    created_at TIMESTAMP DEFAULT (now() AT TIME ZONE 'utc') NOT NULL,
+    PRIMARY KEY (principal, cal_id, object_id, revision),
+    FOREIGN KEY (principal, cal_id)
+    REFERENCES calendars (principal, id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+CREATE TABLE addressobjectrevisions (
+    principal TEXT NOT NULL,
+    addressbook_id TEXT NOT NULL,
+    object_id TEXT NOT NULL,
+    -- Synctoken of the change that wrote the revision
+    revision BIGINT NOT NULL,
+    vcf TEXT NOT NULL,
+    changed_by TEXT,
+    created_at TIMESTAMP DEFAULT (now() AT TIME ZONE 'utc') NOT NULL,
+    PRIMARY KEY (principal, addressbook_id, object_id, revision),
+    FOREIGN KEY (principal, addressbook_id)
+    REFERENCES addressbooks (principal, id) ON DELETE CASCADE ON UPDATE CASCADE
+);
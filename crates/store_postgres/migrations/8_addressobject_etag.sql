@@ -0,0 +1,3 @@
+-- Computed on write, the same way as AddressObject::get_etag
+ALTER TABLE addressobjects ADD COLUMN etag TEXT;
+UPDATE addressobjects SET etag = encode(sha256(convert_to(id || vcf, 'UTF8')), 'hex');
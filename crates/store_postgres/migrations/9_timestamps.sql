@@ -0,0 +1,15 @@
+-- The creation date of existing collections and objects is unknown and stays NULL,
+-- so the default is only set after adding the column.
+ALTER TABLE calendars ADD COLUMN created_at TIMESTAMP;
+ALTER TABLE calendars ALTER COLUMN created_at SET DEFAULT (now() AT TIME ZONE 'utc');
+ALTER TABLE calendars ADD COLUMN updated_at TIMESTAMP DEFAULT (now() AT TIME ZONE 'utc');
+
+ALTER TABLE addressbooks ADD COLUMN created_at TIMESTAMP;
+ALTER TABLE addressbooks ALTER COLUMN created_at SET DEFAULT (now() AT TIME ZONE 'utc');
+ALTER TABLE addressbooks ADD COLUMN updated_at TIMESTAMP DEFAULT (now() AT TIME ZONE 'utc');
+
+ALTER TABLE calendarobjects ADD COLUMN created_at TIMESTAMP;
+ALTER TABLE calendarobjects ALTER COLUMN created_at SET DEFAULT (now() AT TIME ZONE 'utc');
+
+ALTER TABLE addressobjects ADD COLUMN created_at TIMESTAMP;
+ALTER TABLE addressobjects ALTER COLUMN created_at SET DEFAULT (now() AT TIME ZONE 'utc');
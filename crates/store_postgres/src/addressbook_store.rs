@@ -0,0 +1,1317 @@
+use super::ChangeOperation;
+use async_trait::async_trait;
+use derive_more::derive::Constructor;
+use rustical_store::{
+    AddressObject, Addressbook, AddressbookStore, BirthdayCalendarSettings, CollectionOperation,
+    CollectionOperationDomain, CollectionOperationType, Error, ObjectRevision,
+    addressbook::{VcardDate, VcardDateRange},
+    quota::Quota,
+    revision::MAX_REVISIONS,
+    synctoken::{format_synctoken, resolve_synctoken, truncate_changes},
+};
+use sqlx::types::chrono::NaiveDateTime;
+use sqlx::{Acquire, Executor, PgConnection, PgPool, Postgres, Transaction};
+use std::collections::HashMap;
+use tokio::sync::mpsc::Sender;
+use tracing::{error, instrument};
+
+#[derive(Debug, Clone)]
+struct AddressObjectRow {
+    id: String,
+    vcf: String,
+    etag: Option<String>,
+    created_at: Option<NaiveDateTime>,
+    updated_at: Option<NaiveDateTime>,
+}
+
+impl From<AddressObjectRow> for AddressObject {
+    fn from(value: AddressObjectRow) -> Self {
+        // The object was validated when it was stored, it's only parsed once needed
+        Self::from_stored(value.id, value.vcf, value.etag)
+            .with_timestamps(value.created_at, value.updated_at)
+    }
+}
+
+/// Objects without a persisted etag need their content to compute it
+#[derive(Debug, Clone)]
+struct AddressObjectMetadataRow {
+    id: String,
+    etag: Option<String>,
+    vcf: Option<String>,
+    created_at: Option<NaiveDateTime>,
+    updated_at: Option<NaiveDateTime>,
+}
+
+impl From<AddressObjectMetadataRow> for AddressObject {
+    fn from(value: AddressObjectMetadataRow) -> Self {
+        match value.etag {
+            Some(etag) => Self::from_metadata(value.id, etag),
+            None => Self::from_stored(value.id, value.vcf.unwrap_or_default(), None),
+        }
+        .with_timestamps(value.created_at, value.updated_at)
+    }
+}
+
+#[derive(Debug, Constructor)]
+pub struct PostgresAddressbookStore {
+    db: PgPool,
+    sender: Sender<CollectionOperation>,
+}
+
+impl PostgresAddressbookStore {
+    async fn _get_addressbook<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+        id: &str,
+    ) -> Result<Addressbook, rustical_store::Error> {
+        let addressbook = sqlx::query_as!(
+            Addressbook,
+            r#"SELECT principal, id, synctoken, displayname, description, created_at, updated_at, deleted_at, push_topic
+                FROM addressbooks
+                WHERE (principal, id) = ($1, $2)"#,
+            principal,
+            id
+        )
+        .fetch_one(executor)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(addressbook)
+    }
+
+    async fn _get_addressbooks<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+    ) -> Result<Vec<Addressbook>, rustical_store::Error> {
+        let addressbooks = sqlx::query_as!(
+            Addressbook,
+            r#"SELECT principal, id, synctoken, displayname, description, created_at, updated_at, deleted_at, push_topic
+                FROM addressbooks
+                WHERE principal = $1 AND deleted_at IS NULL"#,
+            principal
+        )
+        .fetch_all(executor)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(addressbooks)
+    }
+
+    async fn _get_deleted_addressbooks<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+    ) -> Result<Vec<Addressbook>, rustical_store::Error> {
+        let addressbooks = sqlx::query_as!(
+            Addressbook,
+            r#"SELECT principal, id, synctoken, displayname, description, created_at, updated_at, deleted_at, push_topic
+                FROM addressbooks
+                WHERE principal = $1 AND deleted_at IS NOT NULL"#,
+            principal
+        )
+        .fetch_all(executor)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(addressbooks)
+    }
+
+    async fn _update_addressbook<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: String,
+        id: String,
+        addressbook: Addressbook,
+    ) -> Result<(), rustical_store::Error> {
+        let result = sqlx::query!(
+            r#"UPDATE addressbooks SET principal = $1, id = $2, displayname = $3, description = $4, push_topic = $5, updated_at = (now() AT TIME ZONE 'utc')
+                WHERE (principal, id) = ($6, $7)"#,
+            addressbook.principal,
+            addressbook.id,
+            addressbook.displayname,
+            addressbook.description,
+            addressbook.push_topic,
+            principal,
+            id
+        )
+        .execute(executor)
+        .await
+        .map_err(crate::Error::from)?;
+        if result.rows_affected() == 0 {
+            return Err(rustical_store::Error::NotFound);
+        }
+        Ok(())
+    }
+
+    async fn _insert_addressbook<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        addressbook: Addressbook,
+    ) -> Result<(), rustical_store::Error> {
+        sqlx::query!(
+            r#"INSERT INTO addressbooks (principal, id, displayname, description, push_topic)
+                VALUES ($1, $2, $3, $4, $5)"#,
+            addressbook.principal,
+            addressbook.id,
+            addressbook.displayname,
+            addressbook.description,
+            addressbook.push_topic,
+        )
+        .execute(executor)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(())
+    }
+
+    async fn _delete_addressbook<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+        addressbook_id: &str,
+        use_trashbin: bool,
+    ) -> Result<(), rustical_store::Error> {
+        match use_trashbin {
+            true => {
+                sqlx::query!(
+                    r#"UPDATE addressbooks SET deleted_at = (now() AT TIME ZONE 'utc') WHERE (principal, id) = ($1, $2)"#,
+                    principal, addressbook_id
+                )
+                .execute(executor)
+                .await.map_err(crate::Error::from)?;
+            }
+            false => {
+                sqlx::query!(
+                    r#"DELETE FROM addressbooks WHERE (principal, id) = ($1, $2)"#,
+                    principal,
+                    addressbook_id
+                )
+                .execute(executor)
+                .await
+                .map_err(crate::Error::from)?;
+            }
+        };
+
+        Ok(())
+    }
+
+    async fn _restore_addressbook<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+        addressbook_id: &str,
+    ) -> Result<(), rustical_store::Error> {
+        sqlx::query!(
+            r"UPDATE addressbooks SET deleted_at = NULL WHERE (principal, id) = ($1, $2)",
+            principal,
+            addressbook_id
+        )
+        .execute(executor)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(())
+    }
+
+    async fn _compact_changelog(
+        tx: &mut Transaction<'_, Postgres>,
+        horizon: NaiveDateTime,
+    ) -> Result<usize, rustical_store::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE addressbooks SET min_synctoken = greatest(min_synctoken, coalesce((
+                SELECT max(log.synctoken) FROM addressobjectchangelog AS log
+                WHERE (log.principal, log.addressbook_id) = (addressbooks.principal, addressbooks.id) AND log.created_at < $1
+            ), 0))"#,
+            horizon
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(crate::Error::from)?;
+
+        // The latest change of objects that still exist is kept so that an initial sync lists them
+        let count = sqlx::query!(
+            r#"
+            DELETE FROM addressobjectchangelog AS log
+            WHERE synctoken < (
+                SELECT max(latest.synctoken) FROM addressobjectchangelog AS latest
+                WHERE (latest.principal, latest.addressbook_id, latest.object_id) = (log.principal, log.addressbook_id, log.object_id)
+            ) OR (created_at < $1 AND NOT EXISTS (
+                SELECT 1 FROM addressobjects AS obj
+                WHERE (obj.principal, obj.addressbook_id, obj.id) = (log.principal, log.addressbook_id, log.object_id)
+                    AND obj.deleted_at IS NULL
+            ))"#,
+            horizon
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(crate::Error::from)?
+        .rows_affected();
+        Ok(count as usize)
+    }
+
+    async fn _sync_changes<'a, A: Acquire<'a, Database = Postgres>>(
+        acquire: A,
+        principal: &str,
+        addressbook_id: &str,
+        synctoken: i64,
+        limit: Option<usize>,
+    ) -> Result<(Vec<AddressObject>, Vec<String>, i64, bool), rustical_store::Error> {
+        struct Row {
+            object_id: String,
+            synctoken: i64,
+        }
+
+        let mut conn = acquire.acquire().await.map_err(crate::Error::from)?;
+
+        struct MinSynctoken {
+            min_synctoken: i64,
+        }
+        let min_synctoken = sqlx::query_as!(
+            MinSynctoken,
+            "SELECT min_synctoken FROM addressbooks WHERE (principal, id) = ($1, $2)",
+            principal,
+            addressbook_id
+        )
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(crate::Error::from)?
+        .map(|MinSynctoken { min_synctoken }| min_synctoken)
+        .unwrap_or_default();
+        let (synctoken, initial) = resolve_synctoken(synctoken, min_synctoken)?;
+
+        let mut changes = sqlx::query_as!(
+            Row,
+            r#"
+                SELECT object_id, max(synctoken) as "synctoken!" from addressobjectchangelog
+                WHERE (principal, addressbook_id) = ($1, $2) AND synctoken > $3
+                GROUP BY object_id
+                ORDER BY 2 ASC
+            "#,
+            principal,
+            addressbook_id,
+            synctoken
+        )
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(crate::Error::from)?;
+
+        let mut objects = vec![];
+        let mut deleted_objects = vec![];
+
+        let continuation = truncate_changes(
+            &mut changes,
+            |&Row { synctoken, .. }| synctoken,
+            limit,
+            initial,
+        );
+        let new_synctoken = changes
+            .last()
+            .map(|&Row { synctoken, .. }| synctoken)
+            .unwrap_or(synctoken)
+            // The latest changes might have been compacted away
+            .max(min_synctoken);
+
+        for Row { object_id, .. } in changes {
+            match Self::_get_object(&mut *conn, principal, addressbook_id, &object_id).await {
+                Ok(object) => objects.push(object),
+                Err(rustical_store::Error::NotFound) => deleted_objects.push(object_id),
+                Err(err) => return Err(err),
+            }
+        }
+
+        match continuation {
+            Some(continuation) => Ok((objects, deleted_objects, continuation, true)),
+            None => Ok((objects, deleted_objects, new_synctoken, false)),
+        }
+    }
+
+    async fn _get_objects<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+        addressbook_id: &str,
+    ) -> Result<Vec<AddressObject>, rustical_store::Error> {
+        sqlx::query_as!(
+            AddressObjectRow,
+            "SELECT id, vcf, etag, created_at, updated_at FROM addressobjects WHERE principal = $1 AND addressbook_id = $2 AND deleted_at IS NULL",
+            principal,
+            addressbook_id
+        )
+        .fetch_all(executor)
+        .await.map_err(crate::Error::from)?
+        .into_iter()
+        .map(|row| Ok(row.into()))
+        .collect()
+    }
+
+    async fn _get_objects_metadata<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+        addressbook_id: &str,
+    ) -> Result<Vec<AddressObject>, rustical_store::Error> {
+        Ok(sqlx::query_as!(
+            AddressObjectMetadataRow,
+            r#"SELECT id, etag, CASE WHEN etag IS NULL THEN vcf END AS "vcf: String", created_at, updated_at FROM addressobjects WHERE principal = $1 AND addressbook_id = $2 AND deleted_at IS NULL"#,
+            principal,
+            addressbook_id
+        )
+        .fetch_all(executor)
+        .await
+        .map_err(crate::Error::from)?
+        .into_iter()
+        .map(AddressObject::from)
+        .collect())
+    }
+
+    async fn _get_objects_with_dates<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+        addressbook_id: &str,
+        range: &VcardDateRange,
+    ) -> Result<Vec<AddressObject>, rustical_store::Error> {
+        let [(start1, end1), (start2, end2)] = range
+            .month_days
+            .map(|(start, end)| (start as i32, end as i32));
+        sqlx::query_as!(
+            AddressObjectRow,
+            r#"SELECT id, vcf, etag, created_at, updated_at FROM addressobjects AS obj
+                WHERE (principal, addressbook_id) = ($1, $2) AND deleted_at IS NULL AND (
+                    NOT dates_indexed OR EXISTS (
+                        SELECT 1 FROM addressobjectdates AS dates
+                        WHERE (dates.principal, dates.addressbook_id, dates.object_id) = (obj.principal, obj.addressbook_id, obj.id)
+                            AND (dates.year IS NULL OR dates.year <= $3)
+                            AND (dates.month_day BETWEEN $4 AND $5 OR dates.month_day BETWEEN $6 AND $7)
+                    )
+                )"#,
+            principal,
+            addressbook_id,
+            range.max_year,
+            start1,
+            end1,
+            start2,
+            end2
+        )
+        .fetch_all(executor)
+        .await.map_err(crate::Error::from)?
+        .into_iter()
+        .map(|row| Ok(row.into()))
+        .collect()
+    }
+
+    /// Replaces the indexed dates of an object, see [`AddressbookStore::get_objects_with_dates`]
+    pub(crate) async fn _index_dates(
+        conn: &mut PgConnection,
+        principal: &str,
+        addressbook_id: &str,
+        object_id: &str,
+        dates: &[VcardDate],
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "DELETE FROM addressobjectdates WHERE (principal, addressbook_id, object_id) = ($1, $2, $3)",
+            principal,
+            addressbook_id,
+            object_id
+        )
+        .execute(&mut *conn)
+        .await?;
+        for date in dates {
+            sqlx::query!(
+                "INSERT INTO addressobjectdates (principal, addressbook_id, object_id, month_day, year) VALUES ($1, $2, $3, $4, $5)",
+                principal,
+                addressbook_id,
+                object_id,
+                date.month_day() as i32,
+                date.year
+            )
+            .execute(&mut *conn)
+            .await?;
+        }
+        sqlx::query!(
+            "UPDATE addressobjects SET dates_indexed = TRUE WHERE (principal, addressbook_id, id) = ($1, $2, $3)",
+            principal,
+            addressbook_id,
+            object_id
+        )
+        .execute(&mut *conn)
+        .await?;
+        Ok(())
+    }
+
+    async fn _get_deleted_objects<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+        addressbook_id: &str,
+    ) -> Result<Vec<AddressObject>, rustical_store::Error> {
+        sqlx::query_as!(
+            AddressObjectRow,
+            "SELECT id, vcf, etag, created_at, updated_at FROM addressobjects WHERE principal = $1 AND addressbook_id = $2 AND deleted_at IS NOT NULL",
+            principal,
+            addressbook_id
+        )
+        .fetch_all(executor)
+        .await.map_err(crate::Error::from)?
+        .into_iter()
+        .map(|row| Ok(row.into()))
+        .collect()
+    }
+
+    async fn _get_used_bytes<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+    ) -> Result<u64, rustical_store::Error> {
+        let used_bytes = sqlx::query_scalar!(
+            r#"SELECT addressbook_bytes AS "used_bytes!" FROM principal_usage WHERE principal = $1"#,
+            principal
+        )
+        .fetch_optional(executor)
+        .await
+        .map_err(crate::Error::from)?
+        .unwrap_or_default();
+        Ok(used_bytes as u64)
+    }
+
+    /// Size in bytes of an object outside the trashbin as counted against the quota
+    async fn _get_object_size<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+        addressbook_id: &str,
+        object_id: &str,
+    ) -> Result<u64, rustical_store::Error> {
+        let size = sqlx::query_scalar!(
+            r#"SELECT octet_length(vcf) AS "size!" FROM addressobjects WHERE (principal, addressbook_id, id) = ($1, $2, $3) AND deleted_at IS NULL"#,
+            principal,
+            addressbook_id,
+            object_id
+        )
+        .fetch_optional(executor)
+        .await
+        .map_err(crate::Error::from)?
+        .unwrap_or_default();
+        Ok(size as u64)
+    }
+
+    /// Records a new revision with the synctoken of the current change and discards the oldest ones
+    async fn _log_revision(
+        tx: &mut Transaction<'_, Postgres>,
+        principal: &str,
+        addressbook_id: &str,
+        object_id: &str,
+        vcf: &str,
+        changed_by: Option<&str>,
+    ) -> Result<(), rustical_store::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO addressobjectrevisions (principal, addressbook_id, object_id, revision, vcf, changed_by)
+            VALUES ($1, $2, $3, (
+                SELECT synctoken FROM addressbooks WHERE (principal, id) = ($1, $2)
+            ), $4, $5)"#,
+            principal,
+            addressbook_id,
+            object_id,
+            vcf,
+            changed_by
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(crate::Error::from)?;
+
+        let max_revisions = MAX_REVISIONS as i64;
+        sqlx::query!(
+            r#"
+            DELETE FROM addressobjectrevisions
+            WHERE (principal, addressbook_id, object_id) = ($1, $2, $3) AND revision NOT IN (
+                SELECT revision FROM addressobjectrevisions
+                WHERE (principal, addressbook_id, object_id) = ($1, $2, $3)
+                ORDER BY revision DESC
+                LIMIT $4
+            )"#,
+            principal,
+            addressbook_id,
+            object_id,
+            max_revisions
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(())
+    }
+
+    async fn _delete_revisions<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+        addressbook_id: &str,
+        object_id: &str,
+    ) -> Result<(), rustical_store::Error> {
+        sqlx::query!(
+            "DELETE FROM addressobjectrevisions WHERE (principal, addressbook_id, object_id) = ($1, $2, $3)",
+            principal,
+            addressbook_id,
+            object_id
+        )
+        .execute(executor)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(())
+    }
+
+    async fn _get_object_revisions<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+        addressbook_id: &str,
+        object_id: &str,
+    ) -> Result<Vec<ObjectRevision<AddressObject>>, rustical_store::Error> {
+        struct RevisionRow {
+            revision: i64,
+            vcf: String,
+            changed_by: Option<String>,
+            created_at: NaiveDateTime,
+        }
+        sqlx::query_as!(
+            RevisionRow,
+            "SELECT revision, vcf, changed_by, created_at FROM addressobjectrevisions WHERE (principal, addressbook_id, object_id) = ($1, $2, $3) ORDER BY revision DESC",
+            principal,
+            addressbook_id,
+            object_id
+        )
+        .fetch_all(executor)
+        .await
+        .map_err(crate::Error::from)?
+        .into_iter()
+        .map(|row| {
+            Ok(ObjectRevision {
+                revision: row.revision,
+                created_at: row.created_at,
+                changed_by: row.changed_by,
+                object: AddressObject::from_vcf(object_id.to_owned(), row.vcf)?,
+            })
+        })
+        .collect()
+    }
+
+    async fn _purge_trashbin(
+        tx: &mut Transaction<'_, Postgres>,
+        deleted_before: NaiveDateTime,
+    ) -> Result<usize, rustical_store::Error> {
+        // The objects and changelogs of addressbooks are deleted through the foreign key
+        let addressbooks = sqlx::query!(
+            "DELETE FROM addressbooks WHERE deleted_at < $1",
+            deleted_before
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(crate::Error::from)?
+        .rows_affected();
+        let objects = sqlx::query!(
+            "DELETE FROM addressobjects WHERE deleted_at < $1",
+            deleted_before
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(crate::Error::from)?
+        .rows_affected();
+
+        sqlx::query!(
+            r#"
+            DELETE FROM addressobjectrevisions AS rev
+            WHERE NOT EXISTS (
+                SELECT 1 FROM addressobjects AS obj
+                WHERE (obj.principal, obj.addressbook_id, obj.id) = (rev.principal, rev.addressbook_id, rev.object_id)
+            )"#
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(crate::Error::from)?;
+
+        // Only the latest change of a deleted object is needed to report its deletion
+        sqlx::query!(
+            r#"
+            DELETE FROM addressobjectchangelog AS log
+            WHERE NOT EXISTS (
+                SELECT 1 FROM addressobjects AS obj
+                WHERE (obj.principal, obj.addressbook_id, obj.id) = (log.principal, log.addressbook_id, log.object_id)
+            ) AND synctoken < (
+                SELECT max(latest.synctoken) FROM addressobjectchangelog AS latest
+                WHERE (latest.principal, latest.addressbook_id, latest.object_id) = (log.principal, log.addressbook_id, log.object_id)
+            )"#
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(crate::Error::from)?;
+
+        Ok((addressbooks + objects) as usize)
+    }
+
+    async fn _get_object<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+        addressbook_id: &str,
+        object_id: &str,
+    ) -> Result<AddressObject, rustical_store::Error> {
+        Ok(sqlx::query_as!(
+            AddressObjectRow,
+            "SELECT id, vcf, etag, created_at, updated_at FROM addressobjects WHERE (principal, addressbook_id, id) = ($1, $2, $3) AND deleted_at IS NULL",
+            principal,
+            addressbook_id,
+            object_id
+        )
+        .fetch_one(executor)
+        .await
+        .map_err(crate::Error::from)?
+        .into())
+    }
+
+    async fn _put_object<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: String,
+        addressbook_id: String,
+        object: AddressObject,
+        overwrite: bool,
+    ) -> Result<(), rustical_store::Error> {
+        let (object_id, vcf) = (object.get_id(), object.get_vcf());
+        let etag = object.get_etag();
+
+        (if overwrite {
+            sqlx::query!(
+            r#"INSERT INTO addressobjects (principal, addressbook_id, id, vcf, etag) VALUES ($1, $2, $3, $4, $5)
+                ON CONFLICT (principal, addressbook_id, id) DO UPDATE SET
+                    vcf = excluded.vcf, etag = excluded.etag, updated_at = (now() AT TIME ZONE 'utc'), deleted_at = NULL"#,
+            principal,
+            addressbook_id,
+            object_id,
+            vcf,
+            etag
+        )
+        } else {
+            // If the object already exists a database error is thrown and handled in error.rs
+            sqlx::query!(
+            "INSERT INTO addressobjects (principal, addressbook_id, id, vcf, etag) VALUES ($1, $2, $3, $4, $5)",
+            principal,
+            addressbook_id,
+            object_id,
+            vcf,
+            etag
+        )
+        })
+        .execute(executor)
+        .await
+        .map_err(crate::Error::from)?;
+
+        Ok(())
+    }
+
+    async fn _delete_object<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+        addressbook_id: &str,
+        object_id: &str,
+        use_trashbin: bool,
+    ) -> Result<(), rustical_store::Error> {
+        match use_trashbin {
+            true => {
+                sqlx::query!(
+                    r#"UPDATE addressobjects SET deleted_at = (now() AT TIME ZONE 'utc'), updated_at = (now() AT TIME ZONE 'utc') WHERE (principal, addressbook_id, id) = ($1, $2, $3)"#,
+                    principal,
+                    addressbook_id,
+                    object_id
+                )
+                .execute(executor)
+                .await.map_err(crate::Error::from)?;
+            }
+            false => {
+                sqlx::query!(
+                    "DELETE FROM addressobjects WHERE (principal, addressbook_id, id) = ($1, $2, $3)",
+                    principal,
+                    addressbook_id,
+                    object_id
+                )
+                .execute(executor)
+                .await
+                .map_err(crate::Error::from)?;
+            }
+        };
+        Ok(())
+    }
+
+    async fn _restore_object<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+        addressbook_id: &str,
+        object_id: &str,
+    ) -> Result<(), rustical_store::Error> {
+        sqlx::query!(
+            r#"UPDATE addressobjects SET deleted_at = NULL, updated_at = (now() AT TIME ZONE 'utc') WHERE (principal, addressbook_id, id) = ($1, $2, $3)"#,
+            principal,
+            addressbook_id,
+            object_id
+        )
+        .execute(executor)
+        .await.map_err(crate::Error::from)?;
+        Ok(())
+    }
+
+    async fn _get_birthday_settings<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+    ) -> Result<BirthdayCalendarSettings, rustical_store::Error> {
+        let settings = sqlx::query_as!(
+            BirthdayCalendarSettings,
+            r#"SELECT birthday_summary_template, anniversary_summary_template, abdate_summary_template, alarm_trigger, color, displayname_template, include_anniversaries, include_abdates
+                FROM birthday_settings
+                WHERE principal = $1"#,
+            principal
+        )
+        .fetch_optional(executor)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(settings.unwrap_or_default())
+    }
+
+    async fn _update_birthday_settings<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+        settings: BirthdayCalendarSettings,
+    ) -> Result<(), rustical_store::Error> {
+        sqlx::query!(
+            r#"INSERT INTO birthday_settings (principal, birthday_summary_template, anniversary_summary_template, abdate_summary_template, alarm_trigger, color, displayname_template, include_anniversaries, include_abdates)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+                ON CONFLICT (principal) DO UPDATE SET
+                    birthday_summary_template = excluded.birthday_summary_template,
+                    anniversary_summary_template = excluded.anniversary_summary_template,
+                    abdate_summary_template = excluded.abdate_summary_template,
+                    alarm_trigger = excluded.alarm_trigger,
+                    color = excluded.color,
+                    displayname_template = excluded.displayname_template,
+                    include_anniversaries = excluded.include_anniversaries,
+                    include_abdates = excluded.include_abdates"#,
+            principal,
+            settings.birthday_summary_template,
+            settings.anniversary_summary_template,
+            settings.abdate_summary_template,
+            settings.alarm_trigger,
+            settings.color,
+            settings.displayname_template,
+            settings.include_anniversaries,
+            settings.include_abdates
+        )
+        .execute(executor)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(())
+    }
+
+    /// Checks the quota in the transaction of the write if there is one
+    async fn put_object_within_quota(
+        &self,
+        principal: String,
+        addressbook_id: String,
+        object: AddressObject,
+        overwrite: bool,
+        changed_by: Option<&str>,
+        quota: Option<u64>,
+    ) -> Result<(), rustical_store::Error> {
+        let mut tx = self.db.begin().await.map_err(crate::Error::from)?;
+
+        let object_id = object.get_id().to_owned();
+        let vcf = object.get_vcf().to_owned();
+        let dates = object.get_dates();
+
+        let old_size = if quota.is_some() {
+            crate::lock_principal_usage(&mut tx, &principal)
+                .await
+                .map_err(crate::Error::from)?;
+            Self::_get_object_size(&mut *tx, &principal, &addressbook_id, &object_id).await?
+        } else {
+            0
+        };
+
+        Self::_put_object(
+            &mut *tx,
+            principal.to_owned(),
+            addressbook_id.to_owned(),
+            object,
+            overwrite,
+        )
+        .await?;
+        Self::_index_dates(&mut tx, &principal, &addressbook_id, &object_id, &dates)
+            .await
+            .map_err(crate::Error::from)?;
+
+        let synctoken = log_object_operation(
+            &mut tx,
+            &principal,
+            &addressbook_id,
+            &object_id,
+            ChangeOperation::Add,
+        )
+        .await
+        .map_err(crate::Error::from)?;
+
+        Self::_log_revision(
+            &mut tx,
+            &principal,
+            &addressbook_id,
+            &object_id,
+            &vcf,
+            changed_by,
+        )
+        .await?;
+
+        if let Some(quota) = quota {
+            crate::check_quota(&mut tx, &principal, quota, old_size, vcf.len() as u64).await?;
+        }
+
+        tx.commit().await.map_err(crate::Error::from)?;
+
+        if let Err(err) = self.sender.try_send(CollectionOperation {
+            r#type: CollectionOperationType::Object,
+            domain: CollectionOperationDomain::Addressbook,
+            topic: self
+                .get_addressbook(&principal, &addressbook_id)
+                .await?
+                .push_topic,
+            sync_token: Some(synctoken),
+        }) {
+            error!("Push notification about deleted addressbook failed: {err}");
+        };
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl AddressbookStore for PostgresAddressbookStore {
+    #[instrument]
+    async fn get_addressbook(
+        &self,
+        principal: &str,
+        id: &str,
+    ) -> Result<Addressbook, rustical_store::Error> {
+        Self::_get_addressbook(&self.db, principal, id).await
+    }
+
+    #[instrument]
+    async fn get_addressbooks(
+        &self,
+        principal: &str,
+    ) -> Result<Vec<Addressbook>, rustical_store::Error> {
+        Self::_get_addressbooks(&self.db, principal).await
+    }
+
+    #[instrument]
+    async fn get_deleted_addressbooks(
+        &self,
+        principal: &str,
+    ) -> Result<Vec<Addressbook>, rustical_store::Error> {
+        Self::_get_deleted_addressbooks(&self.db, principal).await
+    }
+
+    #[instrument]
+    async fn update_addressbook(
+        &self,
+        principal: String,
+        id: String,
+        addressbook: Addressbook,
+    ) -> Result<(), rustical_store::Error> {
+        Self::_update_addressbook(&self.db, principal, id, addressbook).await
+    }
+
+    #[instrument]
+    async fn insert_addressbook(
+        &self,
+        addressbook: Addressbook,
+    ) -> Result<(), rustical_store::Error> {
+        Self::_insert_addressbook(&self.db, addressbook).await
+    }
+
+    #[instrument]
+    async fn delete_addressbook(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+        use_trashbin: bool,
+    ) -> Result<(), rustical_store::Error> {
+        let mut tx = self.db.begin().await.map_err(crate::Error::from)?;
+
+        let addressbook = match Self::_get_addressbook(&mut *tx, principal, addressbook_id).await {
+            Ok(addressbook) => Some(addressbook),
+            Err(Error::NotFound) => None,
+            Err(err) => return Err(err),
+        };
+
+        Self::_delete_addressbook(&mut *tx, principal, addressbook_id, use_trashbin).await?;
+        tx.commit().await.map_err(crate::Error::from)?;
+
+        if let Some(addressbook) = addressbook
+            && let Err(err) = self.sender.try_send(CollectionOperation {
+                r#type: CollectionOperationType::Delete,
+                domain: CollectionOperationDomain::Addressbook,
+                topic: addressbook.push_topic,
+                sync_token: None,
+            })
+        {
+            error!("Push notification about deleted addressbook failed: {err}");
+        };
+
+        Ok(())
+    }
+
+    #[instrument]
+    async fn restore_addressbook(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+    ) -> Result<(), rustical_store::Error> {
+        Self::_restore_addressbook(&self.db, principal, addressbook_id).await
+    }
+
+    #[instrument]
+    async fn sync_changes(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+        synctoken: i64,
+        limit: Option<usize>,
+    ) -> Result<(Vec<AddressObject>, Vec<String>, i64, bool), rustical_store::Error> {
+        Self::_sync_changes(&self.db, principal, addressbook_id, synctoken, limit).await
+    }
+
+    #[instrument]
+    async fn get_objects(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+    ) -> Result<Vec<AddressObject>, rustical_store::Error> {
+        Self::_get_objects(&self.db, principal, addressbook_id).await
+    }
+
+    #[instrument]
+    async fn get_objects_metadata(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+    ) -> Result<Vec<AddressObject>, rustical_store::Error> {
+        Self::_get_objects_metadata(&self.db, principal, addressbook_id).await
+    }
+
+    #[instrument]
+    async fn get_objects_with_dates(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+        range: &VcardDateRange,
+    ) -> Result<Vec<AddressObject>, rustical_store::Error> {
+        Self::_get_objects_with_dates(&self.db, principal, addressbook_id, range).await
+    }
+
+    #[instrument]
+    async fn get_deleted_objects(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+    ) -> Result<Vec<AddressObject>, rustical_store::Error> {
+        Self::_get_deleted_objects(&self.db, principal, addressbook_id).await
+    }
+
+    #[instrument]
+    async fn get_used_bytes(&self, principal: &str) -> Result<u64, rustical_store::Error> {
+        Self::_get_used_bytes(&self.db, principal).await
+    }
+
+    #[instrument]
+    async fn compact_changelog(
+        &self,
+        horizon: NaiveDateTime,
+    ) -> Result<usize, rustical_store::Error> {
+        let mut tx = self.db.begin().await.map_err(crate::Error::from)?;
+        let count = Self::_compact_changelog(&mut tx, horizon).await?;
+        tx.commit().await.map_err(crate::Error::from)?;
+        Ok(count)
+    }
+
+    #[instrument]
+    async fn purge_trashbin(
+        &self,
+        deleted_before: NaiveDateTime,
+    ) -> Result<usize, rustical_store::Error> {
+        let mut tx = self.db.begin().await.map_err(crate::Error::from)?;
+        let count = Self::_purge_trashbin(&mut tx, deleted_before).await?;
+        tx.commit().await.map_err(crate::Error::from)?;
+        Ok(count)
+    }
+
+    #[instrument]
+    async fn get_object(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+        object_id: &str,
+    ) -> Result<AddressObject, rustical_store::Error> {
+        Self::_get_object(&self.db, principal, addressbook_id, object_id).await
+    }
+
+    #[instrument]
+    async fn put_object(
+        &self,
+        principal: String,
+        addressbook_id: String,
+        object: AddressObject,
+        overwrite: bool,
+        changed_by: Option<&str>,
+    ) -> Result<(), rustical_store::Error> {
+        self.put_object_within_quota(
+            principal,
+            addressbook_id,
+            object,
+            overwrite,
+            changed_by,
+            None,
+        )
+        .await
+    }
+
+    #[instrument(skip(quota))]
+    async fn put_object_with_quota(
+        &self,
+        principal: String,
+        addressbook_id: String,
+        object: AddressObject,
+        overwrite: bool,
+        changed_by: Option<&str>,
+        quota: &Quota,
+    ) -> Result<(), rustical_store::Error> {
+        let quota = quota.get_quota(&principal);
+        self.put_object_within_quota(
+            principal,
+            addressbook_id,
+            object,
+            overwrite,
+            changed_by,
+            quota,
+        )
+        .await
+    }
+
+    #[instrument]
+    async fn delete_object(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+        object_id: &str,
+        use_trashbin: bool,
+    ) -> Result<(), rustical_store::Error> {
+        let mut tx = self.db.begin().await.map_err(crate::Error::from)?;
+
+        // Locks the usage before the object like quota-checked writes do, to avoid deadlocks
+        crate::lock_principal_usage(&mut tx, principal)
+            .await
+            .map_err(crate::Error::from)?;
+        Self::_delete_object(&mut *tx, principal, addressbook_id, object_id, use_trashbin).await?;
+        if !use_trashbin {
+            Self::_delete_revisions(&mut *tx, principal, addressbook_id, object_id).await?;
+        }
+
+        let synctoken = log_object_operation(
+            &mut tx,
+            principal,
+            addressbook_id,
+            object_id,
+            ChangeOperation::Delete,
+        )
+        .await
+        .map_err(crate::Error::from)?;
+
+        tx.commit().await.map_err(crate::Error::from)?;
+
+        // TODO: Watch for errors here?
+        let _ = self.sender.try_send(CollectionOperation {
+            r#type: CollectionOperationType::Object,
+            domain: CollectionOperationDomain::Addressbook,
+            topic: self
+                .get_addressbook(principal, addressbook_id)
+                .await?
+                .push_topic,
+            sync_token: Some(synctoken),
+        });
+        Ok(())
+    }
+
+    #[instrument]
+    async fn restore_object(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+        object_id: &str,
+    ) -> Result<(), rustical_store::Error> {
+        let mut tx = self.db.begin().await.map_err(crate::Error::from)?;
+
+        Self::_restore_object(&mut *tx, principal, addressbook_id, object_id).await?;
+
+        let synctoken = log_object_operation(
+            &mut tx,
+            principal,
+            addressbook_id,
+            object_id,
+            ChangeOperation::Add,
+        )
+        .await
+        .map_err(crate::Error::from)?;
+        tx.commit().await.map_err(crate::Error::from)?;
+
+        // TODO: Watch for errors here?
+        let _ = self.sender.try_send(CollectionOperation {
+            r#type: CollectionOperationType::Object,
+            domain: CollectionOperationDomain::Addressbook,
+            topic: self
+                .get_addressbook(principal, addressbook_id)
+                .await?
+                .push_topic,
+            sync_token: Some(synctoken),
+        });
+
+        Ok(())
+    }
+
+    #[instrument]
+    async fn get_object_revisions(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+        object_id: &str,
+    ) -> Result<Vec<ObjectRevision<AddressObject>>, rustical_store::Error> {
+        Self::_get_object_revisions(&self.db, principal, addressbook_id, object_id).await
+    }
+
+    #[instrument]
+    async fn get_objects_revisions(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+        object_ids: &[String],
+    ) -> Result<HashMap<String, Vec<ObjectRevision<AddressObject>>>, rustical_store::Error> {
+        struct RevisionRow {
+            object_id: String,
+            revision: i64,
+            vcf: String,
+            changed_by: Option<String>,
+            created_at: NaiveDateTime,
+        }
+        let rows = sqlx::query_as!(
+            RevisionRow,
+            "SELECT object_id, revision, vcf, changed_by, created_at FROM addressobjectrevisions WHERE principal = $1 AND addressbook_id = $2 AND object_id = ANY($3) ORDER BY revision DESC",
+            principal,
+            addressbook_id,
+            object_ids
+        )
+        .fetch_all(&self.db)
+        .await
+        .map_err(crate::Error::from)?;
+
+        let mut revisions: HashMap<String, Vec<ObjectRevision<AddressObject>>> = HashMap::new();
+        for row in rows {
+            let revision = ObjectRevision {
+                revision: row.revision,
+                created_at: row.created_at,
+                changed_by: row.changed_by,
+                object: AddressObject::from_vcf(row.object_id.to_owned(), row.vcf)?,
+            };
+            revisions.entry(row.object_id).or_default().push(revision);
+        }
+        Ok(revisions)
+    }
+
+    #[instrument]
+    async fn get_birthday_settings(
+        &self,
+        principal: &str,
+    ) -> Result<BirthdayCalendarSettings, rustical_store::Error> {
+        Self::_get_birthday_settings(&self.db, principal).await
+    }
+
+    #[instrument]
+    async fn update_birthday_settings(
+        &self,
+        principal: &str,
+        settings: BirthdayCalendarSettings,
+    ) -> Result<(), rustical_store::Error> {
+        settings.validate()?;
+        let mut tx = self.db.begin().await.map_err(crate::Error::from)?;
+        let previous = Self::_get_birthday_settings(&mut *tx, principal).await?;
+        if previous == settings {
+            return Ok(());
+        }
+        Self::_update_birthday_settings(&mut *tx, principal, settings).await?;
+
+        // Every contact is rendered differently in the birthday calendars now
+        let objects = sqlx::query!(
+            r#"SELECT addressbook_id, id FROM addressobjects
+                WHERE principal = $1 AND deleted_at IS NULL
+                ORDER BY addressbook_id, id"#,
+            principal
+        )
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(crate::Error::from)?;
+        let mut synctokens = HashMap::new();
+        for object in objects {
+            let synctoken = log_object_operation(
+                &mut tx,
+                principal,
+                &object.addressbook_id,
+                &object.id,
+                ChangeOperation::Add,
+            )
+            .await
+            .map_err(crate::Error::from)?;
+            synctokens.insert(object.addressbook_id, synctoken);
+        }
+        tx.commit().await.map_err(crate::Error::from)?;
+
+        for (addressbook_id, synctoken) in synctokens {
+            if let Err(err) = self.sender.try_send(CollectionOperation {
+                r#type: CollectionOperationType::Object,
+                domain: CollectionOperationDomain::Addressbook,
+                topic: self
+                    .get_addressbook(principal, &addressbook_id)
+                    .await?
+                    .push_topic,
+                sync_token: Some(synctoken),
+            }) {
+                error!("Push notification about addressbook failed: {err}");
+            }
+        }
+        Ok(())
+    }
+
+    fn is_read_only(&self) -> bool {
+        false
+    }
+}
+
+// Logs an operation to an address object
+async fn log_object_operation(
+    tx: &mut Transaction<'_, Postgres>,
+    principal: &str,
+    addressbook_id: &str,
+    object_id: &str,
+    operation: ChangeOperation,
+) -> Result<String, sqlx::Error> {
+    struct Synctoken {
+        synctoken: i64,
+    }
+    let Synctoken { synctoken } = sqlx::query_as!(
+        Synctoken,
+        r#"
+        UPDATE addressbooks
+        SET synctoken = synctoken + 1, updated_at = (now() AT TIME ZONE 'utc')
+        WHERE (principal, id) = ($1, $2)
+        RETURNING synctoken"#,
+        principal,
+        addressbook_id
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO addressobjectchangelog (principal, addressbook_id, object_id, "operation", synctoken)
+        VALUES ($1, $2, $3, $4, $5)"#,
+        principal,
+        addressbook_id,
+        object_id,
+        operation as _,
+        synctoken
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(format_synctoken(synctoken))
+}
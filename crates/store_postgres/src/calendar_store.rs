@@ -0,0 +1,1300 @@
+use super::ChangeOperation;
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use derive_more::derive::Constructor;
+use futures_util::stream::{self, StreamExt, TryStreamExt};
+use rustical_store::calendar::{CalDateTime, CalendarObjectType};
+use rustical_store::calendar_store::{CalendarObjectStream, CalendarQuery};
+use rustical_store::quota::Quota;
+use rustical_store::revision::MAX_REVISIONS;
+use rustical_store::synctoken::{format_synctoken, resolve_synctoken, truncate_changes};
+use rustical_store::{Calendar, CalendarObject, CalendarStore, Error, ObjectRevision};
+use rustical_store::{CollectionOperation, CollectionOperationType};
+use sqlx::types::chrono::NaiveDateTime;
+use sqlx::{Acquire, Executor, PgPool, Postgres, Transaction};
+use tokio::sync::mpsc::Sender;
+use tracing::{error, instrument};
+
+/// Number of objects fetched at once when streaming objects
+const OBJECT_STREAM_PAGE_SIZE: i64 = 100;
+
+#[derive(Debug, Clone)]
+struct CalendarObjectRow {
+    id: String,
+    ics: String,
+    etag: Option<String>,
+    object_type: i16,
+    occurences: Option<String>,
+    references_timezones: Option<bool>,
+    created_at: Option<NaiveDateTime>,
+    updated_at: Option<NaiveDateTime>,
+}
+
+impl TryFrom<CalendarObjectRow> for CalendarObject {
+    type Error = rustical_store::Error;
+
+    fn try_from(value: CalendarObjectRow) -> Result<Self, Self::Error> {
+        // The object was validated when it was stored, it's only parsed once needed
+        Ok(CalendarObject::from_stored(
+            value.id,
+            value.ics,
+            value.etag,
+            i64::from(value.object_type).try_into()?,
+        )
+        .with_occurences(value.occurences.as_deref())
+        .with_references_timezones(value.references_timezones)
+        .with_timestamps(value.created_at, value.updated_at))
+    }
+}
+
+/// Objects without a persisted etag or timezone references need their content to compute them
+#[derive(Debug, Clone)]
+struct CalendarObjectMetadataRow {
+    id: String,
+    etag: Option<String>,
+    ics: Option<String>,
+    references_timezones: Option<bool>,
+    object_type: i16,
+    created_at: Option<NaiveDateTime>,
+    updated_at: Option<NaiveDateTime>,
+}
+
+impl TryFrom<CalendarObjectMetadataRow> for CalendarObject {
+    type Error = rustical_store::Error;
+
+    fn try_from(value: CalendarObjectMetadataRow) -> Result<Self, Self::Error> {
+        let object_type = i64::from(value.object_type).try_into()?;
+        Ok(match (value.etag, value.ics) {
+            (Some(etag), None) => CalendarObject::from_metadata(value.id, etag, object_type),
+            (etag, ics) => {
+                CalendarObject::from_stored(value.id, ics.unwrap_or_default(), etag, object_type)
+            }
+        }
+        .with_references_timezones(value.references_timezones)
+        .with_timestamps(value.created_at, value.updated_at))
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+struct CalendarRow {
+    principal: String,
+    id: String,
+    displayname: Option<String>,
+    order: i64,
+    description: Option<String>,
+    color: Option<String>,
+    timezone: Option<String>,
+    timezone_id: Option<String>,
+    created_at: Option<NaiveDateTime>,
+    updated_at: Option<NaiveDateTime>,
+    deleted_at: Option<NaiveDateTime>,
+    synctoken: i64,
+    subscription_url: Option<String>,
+    push_topic: String,
+    comp_event: bool,
+    comp_todo: bool,
+    comp_journal: bool,
+}
+
+impl From<CalendarRow> for Calendar {
+    fn from(value: CalendarRow) -> Self {
+        let mut components = vec![];
+        if value.comp_event {
+            components.push(CalendarObjectType::Event);
+        }
+        if value.comp_todo {
+            components.push(CalendarObjectType::Todo);
+        }
+        if value.comp_journal {
+            components.push(CalendarObjectType::Journal);
+        }
+        Self {
+            principal: value.principal,
+            id: value.id,
+            displayname: value.displayname,
+            order: value.order,
+            description: value.description,
+            color: value.color,
+            timezone: value.timezone,
+            timezone_id: value.timezone_id,
+            created_at: value.created_at,
+            updated_at: value.updated_at,
+            deleted_at: value.deleted_at,
+            synctoken: value.synctoken,
+            subscription_url: value.subscription_url,
+            push_topic: value.push_topic,
+            components,
+        }
+    }
+}
+
+#[derive(Debug, Constructor)]
+pub struct PostgresCalendarStore {
+    db: PgPool,
+    sender: Sender<CollectionOperation>,
+}
+
+impl PostgresCalendarStore {
+    async fn _get_calendar<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+        id: &str,
+    ) -> Result<Calendar, Error> {
+        let cal = sqlx::query_as!(
+            CalendarRow,
+            r#"SELECT principal, id, displayname, "order", description, color, timezone, timezone_id,
+                    created_at, updated_at, deleted_at, synctoken, subscription_url, push_topic, comp_event, comp_todo, comp_journal
+                FROM calendars
+                WHERE (principal, id) = ($1, $2)"#,
+            principal,
+            id
+        )
+        .fetch_one(executor)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(cal.into())
+    }
+
+    async fn _get_calendars<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+    ) -> Result<Vec<Calendar>, Error> {
+        let cals = sqlx::query_as!(
+            CalendarRow,
+            r#"SELECT principal, id, displayname, "order", description, color, timezone, timezone_id,
+                    created_at, updated_at, deleted_at, synctoken, subscription_url, push_topic, comp_event, comp_todo, comp_journal
+                FROM calendars
+                WHERE principal = $1 AND deleted_at IS NULL"#,
+            principal
+        )
+        .fetch_all(executor)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(cals.into_iter().map(Calendar::from).collect())
+    }
+
+    async fn _get_deleted_calendars<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+    ) -> Result<Vec<Calendar>, Error> {
+        let cals = sqlx::query_as!(
+            CalendarRow,
+            r#"SELECT principal, id, displayname, "order", description, color, timezone, timezone_id,
+                    created_at, updated_at, deleted_at, synctoken, subscription_url, push_topic, comp_event, comp_todo, comp_journal
+                FROM calendars
+                WHERE principal = $1 AND deleted_at IS NOT NULL"#,
+            principal
+        )
+        .fetch_all(executor)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(cals.into_iter().map(Calendar::from).collect())
+    }
+
+    async fn _insert_calendar<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        calendar: Calendar,
+    ) -> Result<(), Error> {
+        let comp_event = calendar.components.contains(&CalendarObjectType::Event);
+        let comp_todo = calendar.components.contains(&CalendarObjectType::Todo);
+        let comp_journal = calendar.components.contains(&CalendarObjectType::Journal);
+
+        sqlx::query!(
+            r#"INSERT INTO calendars (principal, id, displayname, description, "order", color, timezone, timezone_id, push_topic, comp_event, comp_todo, comp_journal)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)"#,
+            calendar.principal,
+            calendar.id,
+            calendar.displayname,
+            calendar.description,
+            calendar.order,
+            calendar.color,
+            calendar.timezone,
+            calendar.timezone_id,
+            calendar.push_topic,
+            comp_event, comp_todo, comp_journal
+        )
+        .execute(executor)
+        .await.map_err(crate::Error::from)?;
+
+        Ok(())
+    }
+
+    async fn _update_calendar<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: String,
+        id: String,
+        calendar: Calendar,
+    ) -> Result<(), Error> {
+        let comp_event = calendar.components.contains(&CalendarObjectType::Event);
+        let comp_todo = calendar.components.contains(&CalendarObjectType::Todo);
+        let comp_journal = calendar.components.contains(&CalendarObjectType::Journal);
+
+        let result = sqlx::query!(
+            r#"UPDATE calendars SET principal = $1, id = $2, displayname = $3, description = $4, "order" = $5, color = $6, timezone = $7, timezone_id = $8, push_topic = $9, comp_event = $10, comp_todo = $11, comp_journal = $12, updated_at = (now() AT TIME ZONE 'utc')
+                WHERE (principal, id) = ($13, $14)"#,
+            calendar.principal,
+            calendar.id,
+            calendar.displayname,
+            calendar.description,
+            calendar.order,
+            calendar.color,
+            calendar.timezone,
+            calendar.timezone_id,
+            calendar.push_topic,
+            comp_event, comp_todo, comp_journal,
+            principal,
+            id
+        ).execute(executor).await.map_err(crate::Error::from)?;
+        if result.rows_affected() == 0 {
+            return Err(rustical_store::Error::NotFound);
+        }
+        Ok(())
+    }
+
+    async fn _delete_calendar<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+        id: &str,
+        use_trashbin: bool,
+    ) -> Result<(), Error> {
+        match use_trashbin {
+            true => sqlx::query!(
+                r#"UPDATE calendars SET deleted_at = (now() AT TIME ZONE 'utc') WHERE (principal, id) = ($1, $2)"#,
+                principal,
+                id
+            )
+            .execute(executor)
+            .await
+            .map_err(crate::Error::from)?,
+            false => sqlx::query!(
+                r#"DELETE FROM calendars WHERE (principal, id) = ($1, $2)"#,
+                principal,
+                id
+            )
+            .execute(executor)
+            .await
+            .map_err(crate::Error::from)?,
+        };
+        Ok(())
+    }
+
+    async fn _restore_calendar<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+        id: &str,
+    ) -> Result<(), Error> {
+        sqlx::query!(
+            r"UPDATE calendars SET deleted_at = NULL WHERE (principal, id) = ($1, $2)",
+            principal,
+            id
+        )
+        .execute(executor)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(())
+    }
+
+    async fn _get_objects<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+        cal_id: &str,
+    ) -> Result<Vec<CalendarObject>, Error> {
+        sqlx::query_as!(
+            CalendarObjectRow,
+            "SELECT id, ics, etag, object_type, occurences, references_timezones, created_at, updated_at FROM calendarobjects WHERE principal = $1 AND cal_id = $2 AND deleted_at IS NULL",
+            principal,
+            cal_id
+        )
+        .fetch_all(executor)
+        .await.map_err(crate::Error::from)?
+        .into_iter()
+        .map(|row| row.try_into())
+        .collect()
+    }
+
+    async fn _get_objects_metadata<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+        cal_id: &str,
+    ) -> Result<Vec<CalendarObject>, Error> {
+        sqlx::query_as!(
+            CalendarObjectMetadataRow,
+            r#"SELECT id, etag, CASE WHEN etag IS NULL OR references_timezones IS NULL THEN ics END AS "ics: String", references_timezones, object_type, created_at, updated_at FROM calendarobjects WHERE principal = $1 AND cal_id = $2 AND deleted_at IS NULL"#,
+            principal,
+            cal_id
+        )
+        .fetch_all(executor)
+        .await
+        .map_err(crate::Error::from)?
+        .into_iter()
+        .map(|row| row.try_into())
+        .collect()
+    }
+
+    async fn _get_deleted_objects<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+        cal_id: &str,
+    ) -> Result<Vec<CalendarObject>, Error> {
+        sqlx::query_as!(
+            CalendarObjectRow,
+            "SELECT id, ics, etag, object_type, occurences, references_timezones, created_at, updated_at FROM calendarobjects WHERE principal = $1 AND cal_id = $2 AND deleted_at IS NOT NULL",
+            principal,
+            cal_id
+        )
+        .fetch_all(executor)
+        .await.map_err(crate::Error::from)?
+        .into_iter()
+        .map(|row| row.try_into())
+        .collect()
+    }
+
+    async fn _get_used_bytes<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+    ) -> Result<u64, Error> {
+        let used_bytes = sqlx::query_scalar!(
+            r#"SELECT calendar_bytes AS "used_bytes!" FROM principal_usage WHERE principal = $1"#,
+            principal
+        )
+        .fetch_optional(executor)
+        .await
+        .map_err(crate::Error::from)?
+        .unwrap_or_default();
+        Ok(used_bytes as u64)
+    }
+
+    /// Size in bytes of an object outside the trashbin as counted against the quota
+    async fn _get_object_size<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+        cal_id: &str,
+        object_id: &str,
+    ) -> Result<u64, Error> {
+        let size = sqlx::query_scalar!(
+            r#"SELECT octet_length(ics) AS "size!" FROM calendarobjects WHERE (principal, cal_id, id) = ($1, $2, $3) AND deleted_at IS NULL"#,
+            principal,
+            cal_id,
+            object_id
+        )
+        .fetch_optional(executor)
+        .await
+        .map_err(crate::Error::from)?
+        .unwrap_or_default();
+        Ok(size as u64)
+    }
+
+    async fn _calendar_query<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+        cal_id: &str,
+        query: CalendarQuery,
+    ) -> Result<Vec<CalendarObject>, Error> {
+        let (start, end) = query.occurence_dates();
+
+        sqlx::query_as!(
+            CalendarObjectRow,
+            r"SELECT id, ics, etag, object_type, occurences, references_timezones, created_at, updated_at FROM calendarobjects
+                WHERE principal = $1 AND cal_id = $2 AND deleted_at IS NULL
+                    AND (last_occurence IS NULL OR $3::DATE IS NULL OR last_occurence >= $3)
+                    AND (first_occurence IS NULL OR $4::DATE IS NULL OR first_occurence <= $4)
+            ",
+            principal,
+            cal_id,
+            start,
+            end,
+        )
+        .fetch_all(executor)
+        .await
+        .map_err(crate::Error::from)?
+        .into_iter()
+        .map(|row| row.try_into())
+        .collect()
+    }
+
+    /// Fetches the objects of a calendar query ordered by their id, starting after `after`
+    #[allow(clippy::too_many_arguments)]
+    async fn _calendar_query_page<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+        cal_id: &str,
+        start: Option<NaiveDate>,
+        end: Option<NaiveDate>,
+        after: &str,
+        limit: i64,
+    ) -> Result<Vec<CalendarObjectRow>, Error> {
+        Ok(sqlx::query_as!(
+            CalendarObjectRow,
+            r"SELECT id, ics, etag, object_type, occurences, references_timezones, created_at, updated_at FROM calendarobjects
+                WHERE principal = $1 AND cal_id = $2 AND deleted_at IS NULL
+                    AND (last_occurence IS NULL OR $3::DATE IS NULL OR last_occurence >= $3)
+                    AND (first_occurence IS NULL OR $4::DATE IS NULL OR first_occurence <= $4)
+                    AND id > $5
+                ORDER BY id LIMIT $6
+            ",
+            principal,
+            cal_id,
+            start,
+            end,
+            after,
+            limit,
+        )
+        .fetch_all(executor)
+        .await
+        .map_err(crate::Error::from)?)
+    }
+
+    /// Streams the objects in pages so that no connection is held while the consumer is busy
+    fn _calendar_query_stream(
+        db: PgPool,
+        principal: String,
+        cal_id: String,
+        query: CalendarQuery,
+    ) -> CalendarObjectStream {
+        let (start, end) = query.occurence_dates();
+
+        stream::try_unfold(Some(String::new()), move |after| {
+            let db = db.clone();
+            let principal = principal.clone();
+            let cal_id = cal_id.clone();
+            async move {
+                let Some(after) = after else {
+                    return Ok::<_, Error>(None);
+                };
+                let rows = Self::_calendar_query_page(
+                    &db,
+                    &principal,
+                    &cal_id,
+                    start,
+                    end,
+                    &after,
+                    OBJECT_STREAM_PAGE_SIZE,
+                )
+                .await?;
+                let next = if rows.len() as i64 == OBJECT_STREAM_PAGE_SIZE {
+                    rows.last().map(|row| row.id.to_owned())
+                } else {
+                    None
+                };
+                let objects = rows
+                    .into_iter()
+                    .map(CalendarObject::try_from)
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Some((stream::iter(objects.into_iter().map(Ok)), next)))
+            }
+        })
+        .try_flatten()
+        .boxed()
+    }
+
+    async fn _get_object<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+        cal_id: &str,
+        object_id: &str,
+    ) -> Result<CalendarObject, Error> {
+        sqlx::query_as!(
+            CalendarObjectRow,
+            "SELECT id, ics, etag, object_type, occurences, references_timezones, created_at, updated_at FROM calendarobjects WHERE (principal, cal_id, id) = ($1, $2, $3) AND deleted_at IS NULL",
+            principal,
+            cal_id,
+            object_id
+        )
+        .fetch_one(executor)
+        .await
+        .map_err(crate::Error::from)?
+        .try_into()
+    }
+
+    #[instrument]
+    async fn _put_object<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: String,
+        cal_id: String,
+        object: CalendarObject,
+        overwrite: bool,
+    ) -> Result<(), Error> {
+        // TODO: Prevent objects from being commited to a subscription calendar
+        let (object_id, ics) = (object.get_id(), object.get_ics());
+
+        let first_occurence = object
+            .get_first_occurence()
+            .ok()
+            .flatten()
+            .as_ref()
+            .map(CalDateTime::date);
+        let last_occurence = object
+            .get_last_occurence()
+            .ok()
+            .flatten()
+            .as_ref()
+            .map(CalDateTime::date);
+        let occurences = object.format_occurences().ok();
+        let references_timezones = object.references_timezones();
+        let etag = object.get_etag();
+        let object_type = object.get_object_type() as i16;
+
+        (if overwrite {
+            sqlx::query!(
+                r#"INSERT INTO calendarobjects (principal, cal_id, id, ics, first_occurence, last_occurence, occurences, references_timezones, etag, object_type) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+                    ON CONFLICT (principal, cal_id, id) DO UPDATE SET
+                        ics = excluded.ics, first_occurence = excluded.first_occurence, last_occurence = excluded.last_occurence,
+                        occurences = excluded.occurences, references_timezones = excluded.references_timezones,
+                        etag = excluded.etag, object_type = excluded.object_type,
+                        updated_at = (now() AT TIME ZONE 'utc'), deleted_at = NULL"#,
+                principal,
+                cal_id,
+                object_id,
+                ics,
+                first_occurence,
+                last_occurence,
+                occurences,
+                references_timezones,
+                etag,
+                object_type,
+            )
+        } else {
+            // If the object already exists a database error is thrown and handled in error.rs
+            sqlx::query!(
+                "INSERT INTO calendarobjects (principal, cal_id, id, ics, first_occurence, last_occurence, occurences, references_timezones, etag, object_type) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+                principal,
+                cal_id,
+                object_id,
+                ics,
+                first_occurence,
+                last_occurence,
+                occurences,
+                references_timezones,
+                etag,
+                object_type,
+            )
+        })
+        .execute(executor)
+        .await
+        .map_err(crate::Error::from)?;
+
+        Ok(())
+    }
+
+    #[instrument]
+    async fn _delete_object<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+        cal_id: &str,
+        id: &str,
+        use_trashbin: bool,
+    ) -> Result<(), Error> {
+        match use_trashbin {
+            true => {
+                sqlx::query!(
+                    r#"UPDATE calendarobjects SET deleted_at = (now() AT TIME ZONE 'utc'), updated_at = (now() AT TIME ZONE 'utc') WHERE (principal, cal_id, id) = ($1, $2, $3)"#,
+                    principal,
+                    cal_id,
+                    id
+                )
+                .execute(executor)
+                .await.map_err(crate::Error::from)?;
+            }
+            false => {
+                sqlx::query!(
+                    "DELETE FROM calendarobjects WHERE (principal, cal_id, id) = ($1, $2, $3)",
+                    principal,
+                    cal_id,
+                    id
+                )
+                .execute(executor)
+                .await
+                .map_err(crate::Error::from)?;
+            }
+        };
+        Ok(())
+    }
+
+    #[instrument]
+    async fn _restore_object<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+        cal_id: &str,
+        object_id: &str,
+    ) -> Result<(), Error> {
+        sqlx::query!(
+            r#"UPDATE calendarobjects SET deleted_at = NULL, updated_at = (now() AT TIME ZONE 'utc') WHERE (principal, cal_id, id) = ($1, $2, $3)"#,
+            principal,
+            cal_id,
+            object_id
+        )
+        .execute(executor)
+        .await.map_err(crate::Error::from)?;
+        Ok(())
+    }
+
+    /// Records a new revision with the synctoken of the current change and discards the oldest ones
+    async fn _log_revision(
+        tx: &mut Transaction<'_, Postgres>,
+        principal: &str,
+        cal_id: &str,
+        object_id: &str,
+        ics: &str,
+        changed_by: Option<&str>,
+    ) -> Result<(), rustical_store::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO calendarobjectrevisions (principal, cal_id, object_id, revision, ics, changed_by)
+            VALUES ($1, $2, $3, (
+                SELECT synctoken FROM calendars WHERE (principal, id) = ($1, $2)
+            ), $4, $5)"#,
+            principal,
+            cal_id,
+            object_id,
+            ics,
+            changed_by
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(crate::Error::from)?;
+
+        let max_revisions = MAX_REVISIONS as i64;
+        sqlx::query!(
+            r#"
+            DELETE FROM calendarobjectrevisions
+            WHERE (principal, cal_id, object_id) = ($1, $2, $3) AND revision NOT IN (
+                SELECT revision FROM calendarobjectrevisions
+                WHERE (principal, cal_id, object_id) = ($1, $2, $3)
+                ORDER BY revision DESC
+                LIMIT $4
+            )"#,
+            principal,
+            cal_id,
+            object_id,
+            max_revisions
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(())
+    }
+
+    async fn _delete_revisions<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+        cal_id: &str,
+        object_id: &str,
+    ) -> Result<(), rustical_store::Error> {
+        sqlx::query!(
+            "DELETE FROM calendarobjectrevisions WHERE (principal, cal_id, object_id) = ($1, $2, $3)",
+            principal,
+            cal_id,
+            object_id
+        )
+        .execute(executor)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(())
+    }
+
+    async fn _get_object_revisions<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+        cal_id: &str,
+        object_id: &str,
+    ) -> Result<Vec<ObjectRevision<CalendarObject>>, rustical_store::Error> {
+        struct RevisionRow {
+            revision: i64,
+            ics: String,
+            changed_by: Option<String>,
+            created_at: NaiveDateTime,
+        }
+        sqlx::query_as!(
+            RevisionRow,
+            "SELECT revision, ics, changed_by, created_at FROM calendarobjectrevisions WHERE (principal, cal_id, object_id) = ($1, $2, $3) ORDER BY revision DESC",
+            principal,
+            cal_id,
+            object_id
+        )
+        .fetch_all(executor)
+        .await
+        .map_err(crate::Error::from)?
+        .into_iter()
+        .map(|row| {
+            Ok(ObjectRevision {
+                revision: row.revision,
+                created_at: row.created_at,
+                changed_by: row.changed_by,
+                object: CalendarObject::from_ics(object_id.to_owned(), row.ics)?,
+            })
+        })
+        .collect()
+    }
+
+    async fn _purge_trashbin(
+        tx: &mut Transaction<'_, Postgres>,
+        deleted_before: NaiveDateTime,
+    ) -> Result<usize, Error> {
+        // The objects and changelogs of calendars are deleted through the foreign key
+        let calendars = sqlx::query!(
+            "DELETE FROM calendars WHERE deleted_at < $1",
+            deleted_before
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(crate::Error::from)?
+        .rows_affected();
+        let objects = sqlx::query!(
+            "DELETE FROM calendarobjects WHERE deleted_at < $1",
+            deleted_before
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(crate::Error::from)?
+        .rows_affected();
+
+        sqlx::query!(
+            r#"
+            DELETE FROM calendarobjectrevisions AS rev
+            WHERE NOT EXISTS (
+                SELECT 1 FROM calendarobjects AS obj
+                WHERE (obj.principal, obj.cal_id, obj.id) = (rev.principal, rev.cal_id, rev.object_id)
+            )"#
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(crate::Error::from)?;
+
+        // Only the latest change of a deleted object is needed to report its deletion
+        sqlx::query!(
+            r#"
+            DELETE FROM calendarobjectchangelog AS log
+            WHERE NOT EXISTS (
+                SELECT 1 FROM calendarobjects AS obj
+                WHERE (obj.principal, obj.cal_id, obj.id) = (log.principal, log.cal_id, log.object_id)
+            ) AND synctoken < (
+                SELECT max(latest.synctoken) FROM calendarobjectchangelog AS latest
+                WHERE (latest.principal, latest.cal_id, latest.object_id) = (log.principal, log.cal_id, log.object_id)
+            )"#
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(crate::Error::from)?;
+
+        Ok((calendars + objects) as usize)
+    }
+
+    async fn _compact_changelog(
+        tx: &mut Transaction<'_, Postgres>,
+        horizon: NaiveDateTime,
+    ) -> Result<usize, Error> {
+        sqlx::query!(
+            r#"
+            UPDATE calendars SET min_synctoken = greatest(min_synctoken, coalesce((
+                SELECT max(log.synctoken) FROM calendarobjectchangelog AS log
+                WHERE (log.principal, log.cal_id) = (calendars.principal, calendars.id) AND log.created_at < $1
+            ), 0))"#,
+            horizon
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(crate::Error::from)?;
+
+        // The latest change of objects that still exist is kept so that an initial sync lists them
+        let count = sqlx::query!(
+            r#"
+            DELETE FROM calendarobjectchangelog AS log
+            WHERE synctoken < (
+                SELECT max(latest.synctoken) FROM calendarobjectchangelog AS latest
+                WHERE (latest.principal, latest.cal_id, latest.object_id) = (log.principal, log.cal_id, log.object_id)
+            ) OR (created_at < $1 AND NOT EXISTS (
+                SELECT 1 FROM calendarobjects AS obj
+                WHERE (obj.principal, obj.cal_id, obj.id) = (log.principal, log.cal_id, log.object_id)
+                    AND obj.deleted_at IS NULL
+            ))"#,
+            horizon
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(crate::Error::from)?
+        .rows_affected();
+        Ok(count as usize)
+    }
+
+    async fn _sync_changes<'a, A: Acquire<'a, Database = Postgres>>(
+        acquire: A,
+        principal: &str,
+        cal_id: &str,
+        synctoken: i64,
+        limit: Option<usize>,
+    ) -> Result<(Vec<CalendarObject>, Vec<String>, i64, bool), Error> {
+        struct Row {
+            object_id: String,
+            synctoken: i64,
+        }
+
+        let mut conn = acquire.acquire().await.map_err(crate::Error::from)?;
+
+        struct MinSynctoken {
+            min_synctoken: i64,
+        }
+        let min_synctoken = sqlx::query_as!(
+            MinSynctoken,
+            "SELECT min_synctoken FROM calendars WHERE (principal, id) = ($1, $2)",
+            principal,
+            cal_id
+        )
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(crate::Error::from)?
+        .map(|MinSynctoken { min_synctoken }| min_synctoken)
+        .unwrap_or_default();
+        let (synctoken, initial) = resolve_synctoken(synctoken, min_synctoken)?;
+
+        let mut changes = sqlx::query_as!(
+            Row,
+            r#"
+                SELECT object_id, max(synctoken) as "synctoken!" from calendarobjectchangelog
+                WHERE (principal, cal_id) = ($1, $2) AND synctoken > $3
+                GROUP BY object_id
+                ORDER BY 2 ASC
+            "#,
+            principal,
+            cal_id,
+            synctoken
+        )
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(crate::Error::from)?;
+
+        let mut objects = vec![];
+        let mut deleted_objects = vec![];
+
+        let continuation = truncate_changes(
+            &mut changes,
+            |&Row { synctoken, .. }| synctoken,
+            limit,
+            initial,
+        );
+        let new_synctoken = changes
+            .last()
+            .map(|&Row { synctoken, .. }| synctoken)
+            .unwrap_or(synctoken)
+            // The latest changes might have been compacted away
+            .max(min_synctoken);
+
+        for Row { object_id, .. } in changes {
+            match Self::_get_object(&mut *conn, principal, cal_id, &object_id).await {
+                Ok(object) => objects.push(object),
+                Err(rustical_store::Error::NotFound) => deleted_objects.push(object_id),
+                Err(err) => return Err(err),
+            }
+        }
+
+        match continuation {
+            Some(continuation) => Ok((objects, deleted_objects, continuation, true)),
+            None => Ok((objects, deleted_objects, new_synctoken, false)),
+        }
+    }
+
+    /// Checks the quota in the transaction of the write if there is one
+    async fn put_object_within_quota(
+        &self,
+        principal: String,
+        cal_id: String,
+        object: CalendarObject,
+        overwrite: bool,
+        changed_by: Option<&str>,
+        quota: Option<u64>,
+    ) -> Result<(), Error> {
+        // TODO: Prevent objects from being commited to a subscription calendar
+        let mut tx = self.db.begin().await.map_err(crate::Error::from)?;
+
+        let object_id = object.get_id().to_owned();
+        let ics = object.get_ics().to_owned();
+
+        let old_size = if quota.is_some() {
+            crate::lock_principal_usage(&mut tx, &principal)
+                .await
+                .map_err(crate::Error::from)?;
+            Self::_get_object_size(&mut *tx, &principal, &cal_id, &object_id).await?
+        } else {
+            0
+        };
+
+        Self::_put_object(
+            &mut *tx,
+            principal.to_owned(),
+            cal_id.to_owned(),
+            object,
+            overwrite,
+        )
+        .await?;
+
+        let synctoken = log_object_operation(
+            &mut tx,
+            &principal,
+            &cal_id,
+            &object_id,
+            ChangeOperation::Add,
+        )
+        .await?;
+
+        Self::_log_revision(&mut tx, &principal, &cal_id, &object_id, &ics, changed_by).await?;
+
+        if let Some(quota) = quota {
+            crate::check_quota(&mut tx, &principal, quota, old_size, ics.len() as u64).await?;
+        }
+
+        tx.commit().await.map_err(crate::Error::from)?;
+
+        if let Err(err) = self.sender.try_send(CollectionOperation {
+            r#type: CollectionOperationType::Object,
+            domain: rustical_store::CollectionOperationDomain::Calendar,
+            topic: self.get_calendar(&principal, &cal_id).await?.push_topic,
+            sync_token: Some(synctoken),
+        }) {
+            error!("Push notification about deleted calendar failed: {err}");
+        };
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl CalendarStore for PostgresCalendarStore {
+    #[instrument]
+    async fn get_calendar(&self, principal: &str, id: &str) -> Result<Calendar, Error> {
+        Self::_get_calendar(&self.db, principal, id).await
+    }
+
+    #[instrument]
+    async fn get_calendars(&self, principal: &str) -> Result<Vec<Calendar>, Error> {
+        Self::_get_calendars(&self.db, principal).await
+    }
+
+    #[instrument]
+    async fn get_deleted_calendars(&self, principal: &str) -> Result<Vec<Calendar>, Error> {
+        Self::_get_deleted_calendars(&self.db, principal).await
+    }
+
+    #[instrument]
+    async fn insert_calendar(&self, calendar: Calendar) -> Result<(), Error> {
+        Self::_insert_calendar(&self.db, calendar).await
+    }
+
+    #[instrument]
+    async fn update_calendar(
+        &self,
+        principal: String,
+        id: String,
+        calendar: Calendar,
+    ) -> Result<(), Error> {
+        Self::_update_calendar(&self.db, principal, id, calendar).await
+    }
+
+    // Does not actually delete the calendar but just disables it
+    #[instrument]
+    async fn delete_calendar(
+        &self,
+        principal: &str,
+        id: &str,
+        use_trashbin: bool,
+    ) -> Result<(), Error> {
+        let mut tx = self.db.begin().await.map_err(crate::Error::from)?;
+
+        let cal = match Self::_get_calendar(&mut *tx, principal, id).await {
+            Ok(cal) => Some(cal),
+            Err(Error::NotFound) => None,
+            Err(err) => return Err(err),
+        };
+
+        Self::_delete_calendar(&mut *tx, principal, id, use_trashbin).await?;
+        tx.commit().await.map_err(crate::Error::from)?;
+
+        if let Some(cal) = cal
+            && let Err(err) = self.sender.try_send(CollectionOperation {
+                r#type: CollectionOperationType::Delete,
+                domain: rustical_store::CollectionOperationDomain::Calendar,
+                topic: cal.push_topic,
+                sync_token: None,
+            })
+        {
+            error!("Push notification about deleted calendar failed: {err}");
+        };
+        Ok(())
+    }
+
+    #[instrument]
+    async fn restore_calendar(&self, principal: &str, id: &str) -> Result<(), Error> {
+        Self::_restore_calendar(&self.db, principal, id).await
+    }
+
+    #[instrument]
+    async fn calendar_query(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        query: CalendarQuery,
+    ) -> Result<Vec<CalendarObject>, Error> {
+        Self::_calendar_query(&self.db, principal, cal_id, query).await
+    }
+
+    #[instrument]
+    async fn calendar_query_stream(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        query: CalendarQuery,
+    ) -> Result<CalendarObjectStream, Error> {
+        Ok(Self::_calendar_query_stream(
+            self.db.clone(),
+            principal.to_owned(),
+            cal_id.to_owned(),
+            query,
+        ))
+    }
+
+    #[instrument]
+    async fn get_objects(
+        &self,
+        principal: &str,
+        cal_id: &str,
+    ) -> Result<Vec<CalendarObject>, Error> {
+        Self::_get_objects(&self.db, principal, cal_id).await
+    }
+
+    #[instrument]
+    async fn get_objects_metadata(
+        &self,
+        principal: &str,
+        cal_id: &str,
+    ) -> Result<Vec<CalendarObject>, Error> {
+        Self::_get_objects_metadata(&self.db, principal, cal_id).await
+    }
+
+    #[instrument]
+    async fn get_objects_stream(
+        &self,
+        principal: &str,
+        cal_id: &str,
+    ) -> Result<CalendarObjectStream, Error> {
+        Ok(Self::_calendar_query_stream(
+            self.db.clone(),
+            principal.to_owned(),
+            cal_id.to_owned(),
+            CalendarQuery::default(),
+        ))
+    }
+
+    #[instrument]
+    async fn get_object(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        object_id: &str,
+    ) -> Result<CalendarObject, Error> {
+        Self::_get_object(&self.db, principal, cal_id, object_id).await
+    }
+
+    #[instrument]
+    async fn get_objects_by_ids(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        object_ids: &[String],
+    ) -> Result<Vec<CalendarObject>, Error> {
+        sqlx::query_as!(
+            CalendarObjectRow,
+            "SELECT id, ics, etag, object_type, occurences, references_timezones, created_at, updated_at FROM calendarobjects WHERE principal = $1 AND cal_id = $2 AND id = ANY($3) AND deleted_at IS NULL",
+            principal,
+            cal_id,
+            object_ids
+        )
+        .fetch_all(&self.db)
+        .await
+        .map_err(crate::Error::from)?
+        .into_iter()
+        .map(CalendarObject::try_from)
+        .collect()
+    }
+
+    #[instrument]
+    async fn put_object(
+        &self,
+        principal: String,
+        cal_id: String,
+        object: CalendarObject,
+        overwrite: bool,
+        changed_by: Option<&str>,
+    ) -> Result<(), Error> {
+        self.put_object_within_quota(principal, cal_id, object, overwrite, changed_by, None)
+            .await
+    }
+
+    #[instrument(skip(quota))]
+    async fn put_object_with_quota(
+        &self,
+        principal: String,
+        cal_id: String,
+        object: CalendarObject,
+        overwrite: bool,
+        changed_by: Option<&str>,
+        quota: &Quota,
+    ) -> Result<(), Error> {
+        let quota = quota.get_quota(&principal);
+        self.put_object_within_quota(principal, cal_id, object, overwrite, changed_by, quota)
+            .await
+    }
+
+    #[instrument]
+    async fn delete_object(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        id: &str,
+        use_trashbin: bool,
+    ) -> Result<(), Error> {
+        let mut tx = self.db.begin().await.map_err(crate::Error::from)?;
+
+        // Locks the usage before the object like quota-checked writes do, to avoid deadlocks
+        crate::lock_principal_usage(&mut tx, principal)
+            .await
+            .map_err(crate::Error::from)?;
+        Self::_delete_object(&mut *tx, principal, cal_id, id, use_trashbin).await?;
+        if !use_trashbin {
+            Self::_delete_revisions(&mut *tx, principal, cal_id, id).await?;
+        }
+
+        let synctoken =
+            log_object_operation(&mut tx, principal, cal_id, id, ChangeOperation::Delete).await?;
+        tx.commit().await.map_err(crate::Error::from)?;
+
+        if let Err(err) = self.sender.try_send(CollectionOperation {
+            r#type: CollectionOperationType::Object,
+            domain: rustical_store::CollectionOperationDomain::Calendar,
+            topic: self.get_calendar(principal, cal_id).await?.push_topic,
+            sync_token: Some(synctoken),
+        }) {
+            error!("Push notification about deleted calendar failed: {err}");
+        };
+        Ok(())
+    }
+
+    #[instrument]
+    async fn restore_object(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        object_id: &str,
+    ) -> Result<(), Error> {
+        let mut tx = self.db.begin().await.map_err(crate::Error::from)?;
+
+        Self::_restore_object(&mut *tx, principal, cal_id, object_id).await?;
+
+        let synctoken =
+            log_object_operation(&mut tx, principal, cal_id, object_id, ChangeOperation::Add)
+                .await?;
+        tx.commit().await.map_err(crate::Error::from)?;
+
+        if let Err(err) = self.sender.try_send(CollectionOperation {
+            r#type: CollectionOperationType::Object,
+            domain: rustical_store::CollectionOperationDomain::Calendar,
+            topic: self.get_calendar(principal, cal_id).await?.push_topic,
+            sync_token: Some(synctoken),
+        }) {
+            error!("Push notification about deleted calendar failed: {err}");
+        };
+        Ok(())
+    }
+
+    #[instrument]
+    async fn get_deleted_objects(
+        &self,
+        principal: &str,
+        cal_id: &str,
+    ) -> Result<Vec<CalendarObject>, Error> {
+        Self::_get_deleted_objects(&self.db, principal, cal_id).await
+    }
+
+    #[instrument]
+    async fn get_used_bytes(&self, principal: &str) -> Result<u64, Error> {
+        Self::_get_used_bytes(&self.db, principal).await
+    }
+
+    #[instrument]
+    async fn compact_changelog(&self, horizon: NaiveDateTime) -> Result<usize, Error> {
+        let mut tx = self.db.begin().await.map_err(crate::Error::from)?;
+        let count = Self::_compact_changelog(&mut tx, horizon).await?;
+        tx.commit().await.map_err(crate::Error::from)?;
+        Ok(count)
+    }
+
+    #[instrument]
+    async fn purge_trashbin(&self, deleted_before: NaiveDateTime) -> Result<usize, Error> {
+        let mut tx = self.db.begin().await.map_err(crate::Error::from)?;
+        let count = Self::_purge_trashbin(&mut tx, deleted_before).await?;
+        tx.commit().await.map_err(crate::Error::from)?;
+        Ok(count)
+    }
+
+    #[instrument]
+    async fn sync_changes(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        synctoken: i64,
+        limit: Option<usize>,
+    ) -> Result<(Vec<CalendarObject>, Vec<String>, i64, bool), Error> {
+        Self::_sync_changes(&self.db, principal, cal_id, synctoken, limit).await
+    }
+
+    #[instrument]
+    async fn get_object_revisions(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        object_id: &str,
+    ) -> Result<Vec<ObjectRevision<CalendarObject>>, rustical_store::Error> {
+        Self::_get_object_revisions(&self.db, principal, cal_id, object_id).await
+    }
+
+    fn is_read_only(&self) -> bool {
+        false
+    }
+}
+
+// Logs an operation to the events
+async fn log_object_operation(
+    tx: &mut Transaction<'_, Postgres>,
+    principal: &str,
+    cal_id: &str,
+    object_id: &str,
+    operation: ChangeOperation,
+) -> Result<String, Error> {
+    struct Synctoken {
+        synctoken: i64,
+    }
+    let Synctoken { synctoken } = sqlx::query_as!(
+        Synctoken,
+        r#"
+        UPDATE calendars
+        SET synctoken = synctoken + 1, updated_at = (now() AT TIME ZONE 'utc')
+        WHERE (principal, id) = ($1, $2)
+        RETURNING synctoken"#,
+        principal,
+        cal_id
+    )
+    .fetch_one(&mut **tx)
+    .await
+    .map_err(crate::Error::from)?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO calendarobjectchangelog (principal, cal_id, object_id, "operation", synctoken)
+        VALUES ($1, $2, $3, $4, $5)"#,
+        principal,
+        cal_id,
+        object_id,
+        operation as _,
+        synctoken
+    )
+    .execute(&mut **tx)
+    .await
+    .map_err(crate::Error::from)?;
+    Ok(format_synctoken(synctoken))
+}
@@ -0,0 +1,188 @@
+use addressbook_store::PostgresAddressbookStore;
+use rustical_store::calendar::references_iana_timezones;
+use rustical_store::{AddressObject, CalendarObject};
+use serde::Serialize;
+use sqlx::{
+    Executor, PgConnection, PgPool, Pool, Postgres,
+    postgres::{PgConnectOptions, PgPoolOptions},
+};
+
+pub mod addressbook_store;
+pub mod calendar_store;
+pub mod error;
+pub mod principal_store;
+pub mod subscription_store;
+
+pub use error::Error;
+
+#[derive(Debug, Clone, Serialize, sqlx::Type)]
+#[serde(rename_all = "kebab-case")]
+#[repr(i32)]
+pub(crate) enum ChangeOperation {
+    // There's no distinction between Add and Modify
+    Add,
+    Delete,
+}
+
+#[derive(Debug)]
+pub struct PostgresStore {
+    db: PgPool,
+}
+
+impl PostgresStore {
+    pub fn new(db: PgPool) -> Self {
+        Self { db }
+    }
+}
+
+pub async fn create_db_pool(db_url: &str, migrate: bool) -> Result<Pool<Postgres>, sqlx::Error> {
+    let db = PgPool::connect(db_url).await?;
+    if migrate {
+        println!("Running database migrations");
+        sqlx::migrate!("./migrations").run(&db).await?;
+        backfill_addressobject_dates(&mut *db.acquire().await?).await?;
+        backfill_calendarobject_occurences(&mut *db.acquire().await?).await?;
+        backfill_calendarobject_references_timezones(&mut *db.acquire().await?).await?;
+    }
+    Ok(db)
+}
+
+/// Indexes the dates of address objects stored before dates were indexed
+async fn backfill_addressobject_dates(conn: &mut PgConnection) -> Result<(), sqlx::Error> {
+    let rows = sqlx::query!(
+        "SELECT principal, addressbook_id, id, vcf FROM addressobjects WHERE NOT dates_indexed"
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    for row in rows {
+        // Invalid objects have no dates
+        let dates = AddressObject::from_vcf(row.id.to_owned(), row.vcf)
+            .map(|object| object.get_dates())
+            .unwrap_or_default();
+        PostgresAddressbookStore::_index_dates(
+            conn,
+            &row.principal,
+            &row.addressbook_id,
+            &row.id,
+            &dates,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Fills in the occurences of calendar objects stored before they were persisted
+async fn backfill_calendarobject_occurences(conn: &mut PgConnection) -> Result<(), sqlx::Error> {
+    let rows = sqlx::query!(
+        "SELECT principal, cal_id, id, ics FROM calendarobjects WHERE occurences IS NULL"
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    for row in rows {
+        // Invalid objects keep computing their occurences on read
+        let Ok(occurences) = CalendarObject::from_ics(row.id.to_owned(), row.ics)
+            .and_then(|object| object.format_occurences())
+        else {
+            continue;
+        };
+        sqlx::query!(
+            "UPDATE calendarobjects SET occurences = $1 WHERE (principal, cal_id, id) = ($2, $3, $4)",
+            occurences,
+            row.principal,
+            row.cal_id,
+            row.id
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Fills in whether calendar objects stored before reference timezones
+async fn backfill_calendarobject_references_timezones(
+    conn: &mut PgConnection,
+) -> Result<(), sqlx::Error> {
+    let rows = sqlx::query!(
+        "SELECT principal, cal_id, id, ics FROM calendarobjects WHERE references_timezones IS NULL"
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    for row in rows {
+        let references_timezones = references_iana_timezones(&row.ics);
+        sqlx::query!(
+            "UPDATE calendarobjects SET references_timezones = $1 WHERE (principal, cal_id, id) = ($2, $3, $4)",
+            references_timezones,
+            row.principal,
+            row.cal_id,
+            row.id
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Locks the usage of a principal until the end of the transaction,
+/// so that concurrent writes can't exceed the quota together
+pub(crate) async fn lock_principal_usage(
+    conn: &mut PgConnection,
+    principal: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO principal_usage (principal) VALUES ($1) ON CONFLICT (principal) DO UPDATE SET principal = excluded.principal",
+        principal
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Fails if a principal exceeds its quota after one of its objects grew from `old_size` to
+/// `new_size` bytes, which rolls back the write. Objects that don't grow are always accepted.
+pub(crate) async fn check_quota(
+    conn: &mut PgConnection,
+    principal: &str,
+    quota: u64,
+    old_size: u64,
+    new_size: u64,
+) -> Result<(), Error> {
+    if new_size <= old_size {
+        return Ok(());
+    }
+    let used_bytes = sqlx::query_scalar!(
+        r#"SELECT calendar_bytes + addressbook_bytes AS "used_bytes!" FROM principal_usage WHERE principal = $1"#,
+        principal
+    )
+    .fetch_optional(conn)
+    .await?
+    .unwrap_or_default();
+    if used_bytes as u64 > quota {
+        return Err(rustical_store::Error::QuotaExceeded.into());
+    }
+    Ok(())
+}
+
+/// Creates a database pool in a new schema of the database at `RUSTICAL_TEST_POSTGRES_URL`
+pub async fn create_test_db() -> Result<PgPool, sqlx::Error> {
+    let db_url = std::env::var("RUSTICAL_TEST_POSTGRES_URL").map_err(|_| {
+        sqlx::Error::Configuration(
+            "RUSTICAL_TEST_POSTGRES_URL must be set to run postgres tests".into(),
+        )
+    })?;
+    let schema = format!("test_{}", uuid::Uuid::new_v4().simple());
+    PgPool::connect(&db_url)
+        .await?
+        .execute(format!("CREATE SCHEMA {schema}").as_str())
+        .await?;
+
+    let options: PgConnectOptions = db_url.parse()?;
+    let db = PgPoolOptions::new()
+        .connect_with(options.options([("search_path", &schema)]))
+        .await?;
+    sqlx::migrate!("./migrations").run(&db).await?;
+    Ok(db)
+}
+
+pub async fn create_test_store() -> Result<PostgresStore, sqlx::Error> {
+    Ok(PostgresStore::new(create_test_db().await?))
+}
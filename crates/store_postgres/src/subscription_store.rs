@@ -0,0 +1,53 @@
+use crate::PostgresStore;
+use async_trait::async_trait;
+use rustical_store::{Error, Subscription, SubscriptionStore};
+
+#[async_trait]
+impl SubscriptionStore for PostgresStore {
+    async fn get_subscriptions(&self, topic: &str) -> Result<Vec<Subscription>, Error> {
+        Ok(sqlx::query_as!(
+            Subscription,
+            r#"SELECT id, topic, expiration, push_resource
+                FROM davpush_subscriptions
+                WHERE (topic) = ($1)"#,
+            topic
+        )
+        .fetch_all(&self.db)
+        .await
+        .map_err(crate::Error::from)?)
+    }
+
+    async fn get_subscription(&self, id: &str) -> Result<Subscription, Error> {
+        Ok(sqlx::query_as!(
+            Subscription,
+            r#"SELECT id, topic, expiration, push_resource
+                FROM davpush_subscriptions
+                WHERE (id) = ($1)"#,
+            id
+        )
+        .fetch_one(&self.db)
+        .await
+        .map_err(crate::Error::from)?)
+    }
+
+    async fn upsert_subscription(&self, sub: Subscription) -> Result<bool, Error> {
+        sqlx::query!(
+            r#"INSERT INTO davpush_subscriptions (id, topic, expiration, push_resource) VALUES ($1, $2, $3, $4)
+                ON CONFLICT (id) DO UPDATE SET
+                    topic = excluded.topic, expiration = excluded.expiration, push_resource = excluded.push_resource"#,
+            sub.id,
+            sub.topic,
+            sub.expiration,
+            sub.push_resource
+        ).execute(&self.db).await.map_err(crate::Error::from)?;
+        // TODO: Correctly return whether a subscription already existed
+        Ok(false)
+    }
+    async fn delete_subscription(&self, id: &str) -> Result<(), Error> {
+        sqlx::query!(r#"DELETE FROM davpush_subscriptions WHERE id = $1"#, id)
+            .execute(&self.db)
+            .await
+            .map_err(crate::Error::from)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,173 @@
+use chrono::NaiveDateTime;
+use rustical_store::{
+    Calendar, CalendarObject, CalendarStore, Error, Subscription, SubscriptionStore,
+    testing::{
+        test_addressbook_store, test_authentication_provider, test_calendar_store, test_quota,
+        test_subscription_store,
+    },
+};
+use rustical_store_postgres::{
+    PostgresStore, addressbook_store::PostgresAddressbookStore,
+    calendar_store::PostgresCalendarStore, create_test_db, principal_store::PostgresPrincipalStore,
+};
+use std::sync::Arc;
+
+// These tests need a postgres server, run them with
+// RUSTICAL_TEST_POSTGRES_URL=postgres://... cargo test -- --ignored
+const EVENT: &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:-//rustical//test//EN\r
+BEGIN:VEVENT\r
+UID:asd\r
+DTSTAMP:20230831T102923Z\r
+DTSTART:20230829T043000Z\r
+DTEND:20230829T045500Z\r
+SUMMARY:Test\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+
+async fn cal_store() -> PostgresCalendarStore {
+    let db = create_test_db().await.unwrap();
+    let (send, _recv) = tokio::sync::mpsc::channel(100);
+    let store = PostgresCalendarStore::new(db, send);
+    store
+        .insert_calendar(Calendar {
+            id: "test".to_owned(),
+            principal: "testuser".to_owned(),
+            displayname: Some("Test Calendar".to_owned()),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    store
+}
+
+#[tokio::test]
+#[ignore = "requires a postgres server at RUSTICAL_TEST_POSTGRES_URL"]
+async fn test_put_get_object() {
+    let store = cal_store().await;
+    let object = CalendarObject::from_ics("asd".to_owned(), EVENT.to_owned()).unwrap();
+    store
+        .put_object(
+            "testuser".to_owned(),
+            "test".to_owned(),
+            object.clone(),
+            false,
+            None,
+        )
+        .await
+        .unwrap();
+    assert!(matches!(
+        store
+            .put_object(
+                "testuser".to_owned(),
+                "test".to_owned(),
+                object,
+                false,
+                None
+            )
+            .await,
+        Err(Error::AlreadyExists)
+    ));
+
+    let event = store.get_object("testuser", "test", "asd").await.unwrap();
+    assert_eq!(event.get_ics(), EVENT);
+    assert_eq!(
+        store.get_objects("testuser", "test").await.unwrap().len(),
+        1
+    );
+}
+
+#[tokio::test]
+#[ignore = "requires a postgres server at RUSTICAL_TEST_POSTGRES_URL"]
+async fn test_sync_changes_and_trashbin() {
+    let store = cal_store().await;
+    let object = CalendarObject::from_ics("asd".to_owned(), EVENT.to_owned()).unwrap();
+    store
+        .put_object(
+            "testuser".to_owned(),
+            "test".to_owned(),
+            object,
+            false,
+            None,
+        )
+        .await
+        .unwrap();
+
+    let (objects, deleted, synctoken, _) = store
+        .sync_changes("testuser", "test", 0, None)
+        .await
+        .unwrap();
+    assert_eq!(objects.len(), 1);
+    assert!(deleted.is_empty());
+
+    store
+        .delete_object("testuser", "test", "asd", true)
+        .await
+        .unwrap();
+    assert!(matches!(
+        store.get_object("testuser", "test", "asd").await,
+        Err(Error::NotFound)
+    ));
+    let (objects, deleted, synctoken, _) = store
+        .sync_changes("testuser", "test", synctoken, None)
+        .await
+        .unwrap();
+    assert!(objects.is_empty());
+    assert_eq!(deleted, vec!["asd".to_owned()]);
+
+    store
+        .restore_object("testuser", "test", "asd")
+        .await
+        .unwrap();
+    let (objects, deleted, _, _) = store
+        .sync_changes("testuser", "test", synctoken, None)
+        .await
+        .unwrap();
+    assert_eq!(objects.len(), 1);
+    assert!(deleted.is_empty());
+}
+
+#[tokio::test]
+#[ignore = "requires a postgres server at RUSTICAL_TEST_POSTGRES_URL"]
+async fn test_subscriptions() {
+    let db = create_test_db().await.unwrap();
+    let store = rustical_store_postgres::PostgresStore::new(db);
+    let subscription = || Subscription {
+        id: "sub".to_owned(),
+        topic: "topic".to_owned(),
+        expiration: NaiveDateTime::parse_from_str("2030-01-01 00:00:00", "%Y-%m-%d %H:%M:%S")
+            .unwrap(),
+        push_resource: "https://push.example.com/sub".to_owned(),
+    };
+    store.upsert_subscription(subscription()).await.unwrap();
+    store.upsert_subscription(subscription()).await.unwrap();
+    assert_eq!(store.get_subscriptions("topic").await.unwrap().len(), 1);
+    store.delete_subscription("sub").await.unwrap();
+    assert!(store.get_subscriptions("topic").await.unwrap().is_empty());
+}
+
+#[tokio::test]
+#[ignore = "requires a postgres server at RUSTICAL_TEST_POSTGRES_URL"]
+async fn test_conformance() {
+    let db = create_test_db().await.unwrap();
+    let (send, mut recv) = tokio::sync::mpsc::channel(100);
+    test_calendar_store(
+        &PostgresCalendarStore::new(db.clone(), send.clone()),
+        &mut recv,
+    )
+    .await;
+    test_addressbook_store(
+        &PostgresAddressbookStore::new(db.clone(), send.clone()),
+        &mut recv,
+    )
+    .await;
+    test_quota(
+        Arc::new(PostgresCalendarStore::new(db.clone(), send.clone())),
+        Arc::new(PostgresAddressbookStore::new(db.clone(), send)),
+    )
+    .await;
+    test_subscription_store(&PostgresStore::new(db.clone())).await;
+    test_authentication_provider(&PostgresPrincipalStore::new(db)).await;
+}
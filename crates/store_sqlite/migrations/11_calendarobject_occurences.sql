@@ -0,0 +1,4 @@
+-- First and last occurence with their time as first/last so that time-range queries don't
+-- need to parse objects. Zoned times are stored in UTC, floating times stay floating.
+-- NULL if not known yet, objects stored before are filled in on startup.
+ALTER TABLE calendarobjects ADD COLUMN occurences TEXT;
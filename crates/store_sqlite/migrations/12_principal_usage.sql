@@ -0,0 +1,64 @@
+-- Bytes used by the objects of a principal outside the trashbin, kept up to date by triggers
+-- so that quotas can be enforced in the transaction of a write
+CREATE TABLE principal_usage (
+    principal TEXT NOT NULL PRIMARY KEY,
+    calendar_bytes INTEGER NOT NULL DEFAULT 0,
+    addressbook_bytes INTEGER NOT NULL DEFAULT 0
+);
+
+INSERT INTO principal_usage (principal, calendar_bytes)
+SELECT principal, SUM(length(CAST(ics AS BLOB))) FROM calendarobjects
+WHERE deleted_at IS NULL GROUP BY principal;
+
+INSERT INTO principal_usage (principal, addressbook_bytes)
+SELECT principal, SUM(length(CAST(vcf AS BLOB))) FROM addressobjects
+WHERE deleted_at IS NULL GROUP BY principal
+ON CONFLICT (principal) DO UPDATE SET addressbook_bytes = excluded.addressbook_bytes;
+
+CREATE TRIGGER calendarobjects_usage_insert AFTER INSERT ON calendarobjects
+WHEN NEW.deleted_at IS NULL
+BEGIN
+    INSERT INTO principal_usage (principal, calendar_bytes)
+    VALUES (NEW.principal, length(CAST(NEW.ics AS BLOB)))
+    ON CONFLICT (principal) DO UPDATE SET calendar_bytes = calendar_bytes + excluded.calendar_bytes;
+END;
+
+CREATE TRIGGER calendarobjects_usage_update AFTER UPDATE OF principal, ics, deleted_at ON calendarobjects
+BEGIN
+    UPDATE principal_usage SET calendar_bytes = calendar_bytes - length(CAST(OLD.ics AS BLOB))
+    WHERE principal = OLD.principal AND OLD.deleted_at IS NULL;
+    INSERT INTO principal_usage (principal, calendar_bytes)
+    SELECT NEW.principal, length(CAST(NEW.ics AS BLOB)) WHERE NEW.deleted_at IS NULL
+    ON CONFLICT (principal) DO UPDATE SET calendar_bytes = calendar_bytes + excluded.calendar_bytes;
+END;
+
+CREATE TRIGGER calendarobjects_usage_delete AFTER DELETE ON calendarobjects
+WHEN OLD.deleted_at IS NULL
+BEGIN
+    UPDATE principal_usage SET calendar_bytes = calendar_bytes - length(CAST(OLD.ics AS BLOB))
+    WHERE principal = OLD.principal;
+END;
+
+CREATE TRIGGER addressobjects_usage_insert AFTER INSERT ON addressobjects
+WHEN NEW.deleted_at IS NULL
+BEGIN
+    INSERT INTO principal_usage (principal, addressbook_bytes)
+    VALUES (NEW.principal, length(CAST(NEW.vcf AS BLOB)))
+    ON CONFLICT (principal) DO UPDATE SET addressbook_bytes = addressbook_bytes + excluded.addressbook_bytes;
+END;
+
+CREATE TRIGGER addressobjects_usage_update AFTER UPDATE OF principal, vcf, deleted_at ON addressobjects
+BEGIN
+    UPDATE principal_usage SET addressbook_bytes = addressbook_bytes - length(CAST(OLD.vcf AS BLOB))
+    WHERE principal = OLD.principal AND OLD.deleted_at IS NULL;
+    INSERT INTO principal_usage (principal, addressbook_bytes)
+    SELECT NEW.principal, length(CAST(NEW.vcf AS BLOB)) WHERE NEW.deleted_at IS NULL
+    ON CONFLICT (principal) DO UPDATE SET addressbook_bytes = addressbook_bytes + excluded.addressbook_bytes;
+END;
+
+CREATE TRIGGER addressobjects_usage_delete AFTER DELETE ON addressobjects
+WHEN OLD.deleted_at IS NULL
+BEGIN
+    UPDATE principal_usage SET addressbook_bytes = addressbook_bytes - length(CAST(OLD.vcf AS BLOB))
+    WHERE principal = OLD.principal;
+END;
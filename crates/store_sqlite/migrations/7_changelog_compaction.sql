@@ -0,0 +1,6 @@
+-- Synctokens before this have been compacted out of the changelog
+ALTER TABLE calendars ADD COLUMN min_synctoken INTEGER DEFAULT 0 NOT NULL;
+ALTER TABLE addressbooks ADD COLUMN min_synctoken INTEGER DEFAULT 0 NOT NULL;
+
+CREATE INDEX idx_calobj_log_created_at ON calendarobjectchangelog (created_at);
+CREATE INDEX idx_addrobj_log_created_at ON addressobjectchangelog (created_at);
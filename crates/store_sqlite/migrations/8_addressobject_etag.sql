@@ -0,0 +1,2 @@
+-- Computed on write, objects stored before are filled in on startup
+ALTER TABLE addressobjects ADD COLUMN etag TEXT;
@@ -0,0 +1,13 @@
+-- SQLite doesn't allow adding columns with a non-constant default, so they are set on write.
+-- The creation date of existing collections and objects is unknown and stays NULL.
+ALTER TABLE calendars ADD COLUMN created_at DATETIME;
+ALTER TABLE calendars ADD COLUMN updated_at DATETIME;
+UPDATE calendars SET updated_at = datetime();
+
+ALTER TABLE addressbooks ADD COLUMN created_at DATETIME;
+ALTER TABLE addressbooks ADD COLUMN updated_at DATETIME;
+UPDATE addressbooks SET updated_at = datetime();
+
+ALTER TABLE calendarobjects ADD COLUMN created_at DATETIME;
+
+ALTER TABLE addressobjects ADD COLUMN created_at DATETIME;
@@ -2,24 +2,56 @@ use super::ChangeOperation;
 use async_trait::async_trait;
 use derive_more::derive::Constructor;
 use rustical_store::{
-    synctoken::format_synctoken, AddressObject, Addressbook, AddressbookStore, CollectionOperation,
-    CollectionOperationDomain, CollectionOperationType, Error,
+    AddressObject, Addressbook, AddressbookStore, BirthdayCalendarSettings, CollectionOperation,
+    CollectionOperationDomain, CollectionOperationType, Error, ObjectRevision,
+    addressbook::{VcardDate, VcardDateRange},
+    quota::Quota,
+    revision::MAX_REVISIONS,
+    synctoken::{format_synctoken, resolve_synctoken, truncate_changes},
 };
-use sqlx::{Acquire, Executor, Sqlite, SqlitePool, Transaction};
+use sqlx::types::chrono::NaiveDateTime;
+use sqlx::{Acquire, Executor, QueryBuilder, Sqlite, SqliteConnection, SqlitePool, Transaction};
+use std::collections::HashMap;
 use tokio::sync::mpsc::Sender;
 use tracing::{error, instrument};
 
+/// Number of objects whose revisions are fetched in a single query
+const REVISIONS_BATCH_SIZE: usize = 500;
+
 #[derive(Debug, Clone)]
 struct AddressObjectRow {
     id: String,
     vcf: String,
+    etag: Option<String>,
+    created_at: Option<NaiveDateTime>,
+    updated_at: Option<NaiveDateTime>,
+}
+
+impl From<AddressObjectRow> for AddressObject {
+    fn from(value: AddressObjectRow) -> Self {
+        // The object was validated when it was stored, it's only parsed once needed
+        Self::from_stored(value.id, value.vcf, value.etag)
+            .with_timestamps(value.created_at, value.updated_at)
+    }
 }
 
-impl TryFrom<AddressObjectRow> for AddressObject {
-    type Error = crate::Error;
+/// Objects without a persisted etag need their content to compute it
+#[derive(Debug, Clone)]
+struct AddressObjectMetadataRow {
+    id: String,
+    etag: Option<String>,
+    vcf: Option<String>,
+    created_at: Option<NaiveDateTime>,
+    updated_at: Option<NaiveDateTime>,
+}
 
-    fn try_from(value: AddressObjectRow) -> Result<Self, Self::Error> {
-        Ok(Self::from_vcf(value.id, value.vcf)?)
+impl From<AddressObjectMetadataRow> for AddressObject {
+    fn from(value: AddressObjectMetadataRow) -> Self {
+        match value.etag {
+            Some(etag) => Self::from_metadata(value.id, etag),
+            None => Self::from_stored(value.id, value.vcf.unwrap_or_default(), None),
+        }
+        .with_timestamps(value.created_at, value.updated_at)
     }
 }
 
@@ -37,7 +69,7 @@ impl SqliteAddressbookStore {
     ) -> Result<Addressbook, rustical_store::Error> {
         let addressbook = sqlx::query_as!(
             Addressbook,
-            r#"SELECT principal, id, synctoken, displayname, description, deleted_at, push_topic
+            r#"SELECT principal, id, synctoken, displayname, description, created_at, updated_at, deleted_at, push_topic
                 FROM addressbooks
                 WHERE (principal, id) = (?, ?)"#,
             principal,
@@ -55,7 +87,7 @@ impl SqliteAddressbookStore {
     ) -> Result<Vec<Addressbook>, rustical_store::Error> {
         let addressbooks = sqlx::query_as!(
             Addressbook,
-            r#"SELECT principal, id, synctoken, displayname, description, deleted_at, push_topic
+            r#"SELECT principal, id, synctoken, displayname, description, created_at, updated_at, deleted_at, push_topic
                 FROM addressbooks
                 WHERE principal = ? AND deleted_at IS NULL"#,
             principal
@@ -72,7 +104,7 @@ impl SqliteAddressbookStore {
     ) -> Result<Vec<Addressbook>, rustical_store::Error> {
         let addressbooks = sqlx::query_as!(
             Addressbook,
-            r#"SELECT principal, id, synctoken, displayname, description, deleted_at, push_topic
+            r#"SELECT principal, id, synctoken, displayname, description, created_at, updated_at, deleted_at, push_topic
                 FROM addressbooks
                 WHERE principal = ? AND deleted_at IS NOT NULL"#,
             principal
@@ -90,7 +122,7 @@ impl SqliteAddressbookStore {
         addressbook: Addressbook,
     ) -> Result<(), rustical_store::Error> {
         let result = sqlx::query!(
-            r#"UPDATE addressbooks SET principal = ?, id = ?, displayname = ?, description = ?, push_topic = ?
+            r#"UPDATE addressbooks SET principal = ?, id = ?, displayname = ?, description = ?, push_topic = ?, updated_at = datetime()
                 WHERE (principal, id) = (?, ?)"#,
             addressbook.principal,
             addressbook.id,
@@ -114,8 +146,8 @@ impl SqliteAddressbookStore {
         addressbook: Addressbook,
     ) -> Result<(), rustical_store::Error> {
         sqlx::query!(
-            r#"INSERT INTO addressbooks (principal, id, displayname, description, push_topic)
-                VALUES (?, ?, ?, ?, ?)"#,
+            r#"INSERT INTO addressbooks (principal, id, displayname, description, push_topic, created_at, updated_at)
+                VALUES (?, ?, ?, ?, ?, datetime(), datetime())"#,
             addressbook.principal,
             addressbook.id,
             addressbook.displayname,
@@ -174,12 +206,50 @@ impl SqliteAddressbookStore {
         Ok(())
     }
 
+    async fn _compact_changelog(
+        tx: &mut Transaction<'_, Sqlite>,
+        horizon: NaiveDateTime,
+    ) -> Result<usize, rustical_store::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE addressbooks SET min_synctoken = max(min_synctoken, coalesce((
+                SELECT max(log.synctoken) FROM addressobjectchangelog AS log
+                WHERE (log.principal, log.addressbook_id) = (addressbooks.principal, addressbooks.id) AND log.created_at < ?1
+            ), 0))"#,
+            horizon
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(crate::Error::from)?;
+
+        // The latest change of objects that still exist is kept so that an initial sync lists them
+        let count = sqlx::query!(
+            r#"
+            DELETE FROM addressobjectchangelog AS log
+            WHERE synctoken < (
+                SELECT max(latest.synctoken) FROM addressobjectchangelog AS latest
+                WHERE (latest.principal, latest.addressbook_id, latest.object_id) = (log.principal, log.addressbook_id, log.object_id)
+            ) OR (created_at < ?1 AND NOT EXISTS (
+                SELECT 1 FROM addressobjects AS obj
+                WHERE (obj.principal, obj.addressbook_id, obj.id) = (log.principal, log.addressbook_id, log.object_id)
+                    AND obj.deleted_at IS NULL
+            ))"#,
+            horizon
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(crate::Error::from)?
+        .rows_affected();
+        Ok(count as usize)
+    }
+
     async fn _sync_changes<'a, A: Acquire<'a, Database = Sqlite>>(
         acquire: A,
         principal: &str,
         addressbook_id: &str,
         synctoken: i64,
-    ) -> Result<(Vec<AddressObject>, Vec<String>, i64), rustical_store::Error> {
+        limit: Option<usize>,
+    ) -> Result<(Vec<AddressObject>, Vec<String>, i64, bool), rustical_store::Error> {
         struct Row {
             object_id: String,
             synctoken: i64,
@@ -187,13 +257,32 @@ impl SqliteAddressbookStore {
 
         let mut conn = acquire.acquire().await.map_err(crate::Error::from)?;
 
-        let changes = sqlx::query_as!(
+        struct MinSynctoken {
+            min_synctoken: i64,
+        }
+        let min_synctoken = sqlx::query_as!(
+            MinSynctoken,
+            "SELECT min_synctoken FROM addressbooks WHERE (principal, id) = (?, ?)",
+            principal,
+            addressbook_id
+        )
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(crate::Error::from)?
+        .map(|MinSynctoken { min_synctoken }| min_synctoken)
+        .unwrap_or_default();
+        let (synctoken, initial) = resolve_synctoken(synctoken, min_synctoken)?;
+
+        let mut changes = sqlx::query_as!(
             Row,
             r#"
-                SELECT DISTINCT object_id, max(0, synctoken) as "synctoken!: i64" from addressobjectchangelog
-                WHERE synctoken > ?
-                ORDER BY synctoken ASC
+                SELECT object_id, max(0, max(synctoken)) as "synctoken!: i64" from addressobjectchangelog
+                WHERE (principal, addressbook_id) = (?, ?) AND synctoken > ?
+                GROUP BY object_id
+                ORDER BY "synctoken!: i64" ASC
             "#,
+            principal,
+            addressbook_id,
             synctoken
         )
         .fetch_all(&mut *conn)
@@ -202,10 +291,18 @@ impl SqliteAddressbookStore {
         let mut objects = vec![];
         let mut deleted_objects = vec![];
 
+        let continuation = truncate_changes(
+            &mut changes,
+            |&Row { synctoken, .. }| synctoken,
+            limit,
+            initial,
+        );
         let new_synctoken = changes
             .last()
             .map(|&Row { synctoken, .. }| synctoken)
-            .unwrap_or(0);
+            .unwrap_or(synctoken)
+            // The latest changes might have been compacted away
+            .max(min_synctoken);
 
         for Row { object_id, .. } in changes {
             match Self::_get_object(&mut *conn, principal, addressbook_id, &object_id).await {
@@ -215,7 +312,10 @@ impl SqliteAddressbookStore {
             }
         }
 
-        Ok((objects, deleted_objects, new_synctoken))
+        match continuation {
+            Some(continuation) => Ok((objects, deleted_objects, continuation, true)),
+            None => Ok((objects, deleted_objects, new_synctoken, false)),
+        }
     }
 
     async fn _get_objects<'e, E: Executor<'e, Database = Sqlite>>(
@@ -225,17 +325,313 @@ impl SqliteAddressbookStore {
     ) -> Result<Vec<AddressObject>, rustical_store::Error> {
         sqlx::query_as!(
             AddressObjectRow,
-            "SELECT id, vcf FROM addressobjects WHERE principal = ? AND addressbook_id = ? AND deleted_at IS NULL",
+            "SELECT id, vcf, etag, created_at, updated_at FROM addressobjects WHERE principal = ? AND addressbook_id = ? AND deleted_at IS NULL",
             principal,
             addressbook_id
         )
         .fetch_all(executor)
         .await.map_err(crate::Error::from)?
         .into_iter()
-        .map(|row| row.try_into().map_err(rustical_store::Error::from))
+        .map(|row| Ok(row.into()))
+        .collect()
+    }
+
+    async fn _get_objects_metadata<'e, E: Executor<'e, Database = Sqlite>>(
+        executor: E,
+        principal: &str,
+        addressbook_id: &str,
+    ) -> Result<Vec<AddressObject>, rustical_store::Error> {
+        Ok(sqlx::query_as!(
+            AddressObjectMetadataRow,
+            r#"SELECT id, etag, IIF(etag IS NULL, vcf, NULL) AS "vcf: String", created_at, updated_at FROM addressobjects WHERE principal = ? AND addressbook_id = ? AND deleted_at IS NULL"#,
+            principal,
+            addressbook_id
+        )
+        .fetch_all(executor)
+        .await
+        .map_err(crate::Error::from)?
+        .into_iter()
+        .map(AddressObject::from)
+        .collect())
+    }
+
+    async fn _get_objects_with_dates<'e, E: Executor<'e, Database = Sqlite>>(
+        executor: E,
+        principal: &str,
+        addressbook_id: &str,
+        range: &VcardDateRange,
+    ) -> Result<Vec<AddressObject>, rustical_store::Error> {
+        let [(start1, end1), (start2, end2)] = range.month_days;
+        sqlx::query_as!(
+            AddressObjectRow,
+            r#"SELECT id, vcf, etag, created_at, updated_at FROM addressobjects AS obj
+                WHERE (principal, addressbook_id) = (?1, ?2) AND deleted_at IS NULL AND (
+                    NOT dates_indexed OR EXISTS (
+                        SELECT 1 FROM addressobjectdates AS dates
+                        WHERE (dates.principal, dates.addressbook_id, dates.object_id) = (obj.principal, obj.addressbook_id, obj.id)
+                            AND (dates.year IS NULL OR dates.year <= ?3)
+                            AND (dates.month_day BETWEEN ?4 AND ?5 OR dates.month_day BETWEEN ?6 AND ?7)
+                    )
+                )"#,
+            principal,
+            addressbook_id,
+            range.max_year,
+            start1,
+            end1,
+            start2,
+            end2
+        )
+        .fetch_all(executor)
+        .await.map_err(crate::Error::from)?
+        .into_iter()
+        .map(|row| Ok(row.into()))
+        .collect()
+    }
+
+    /// Replaces the indexed dates of an object, see [`AddressbookStore::get_objects_with_dates`]
+    pub(crate) async fn _index_dates(
+        conn: &mut SqliteConnection,
+        principal: &str,
+        addressbook_id: &str,
+        object_id: &str,
+        dates: &[VcardDate],
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "DELETE FROM addressobjectdates WHERE (principal, addressbook_id, object_id) = (?, ?, ?)",
+            principal,
+            addressbook_id,
+            object_id
+        )
+        .execute(&mut *conn)
+        .await?;
+        for date in dates {
+            let month_day = date.month_day();
+            sqlx::query!(
+                "INSERT INTO addressobjectdates (principal, addressbook_id, object_id, month_day, year) VALUES (?, ?, ?, ?, ?)",
+                principal,
+                addressbook_id,
+                object_id,
+                month_day,
+                date.year
+            )
+            .execute(&mut *conn)
+            .await?;
+        }
+        sqlx::query!(
+            "UPDATE addressobjects SET dates_indexed = TRUE WHERE (principal, addressbook_id, id) = (?, ?, ?)",
+            principal,
+            addressbook_id,
+            object_id
+        )
+        .execute(&mut *conn)
+        .await?;
+        Ok(())
+    }
+
+    async fn _get_deleted_objects<'e, E: Executor<'e, Database = Sqlite>>(
+        executor: E,
+        principal: &str,
+        addressbook_id: &str,
+    ) -> Result<Vec<AddressObject>, rustical_store::Error> {
+        sqlx::query_as!(
+            AddressObjectRow,
+            "SELECT id, vcf, etag, created_at, updated_at FROM addressobjects WHERE principal = ? AND addressbook_id = ? AND deleted_at IS NOT NULL",
+            principal,
+            addressbook_id
+        )
+        .fetch_all(executor)
+        .await.map_err(crate::Error::from)?
+        .into_iter()
+        .map(|row| Ok(row.into()))
+        .collect()
+    }
+
+    async fn _get_used_bytes<'e, E: Executor<'e, Database = Sqlite>>(
+        executor: E,
+        principal: &str,
+    ) -> Result<u64, rustical_store::Error> {
+        let used_bytes = sqlx::query_scalar!(
+            r#"SELECT addressbook_bytes AS "used_bytes!: i64" FROM principal_usage WHERE principal = ?"#,
+            principal
+        )
+        .fetch_optional(executor)
+        .await
+        .map_err(crate::Error::from)?
+        .unwrap_or_default();
+        Ok(used_bytes as u64)
+    }
+
+    /// Size in bytes of an object outside the trashbin as counted against the quota
+    async fn _get_object_size<'e, E: Executor<'e, Database = Sqlite>>(
+        executor: E,
+        principal: &str,
+        addressbook_id: &str,
+        object_id: &str,
+    ) -> Result<u64, rustical_store::Error> {
+        let size = sqlx::query_scalar!(
+            r#"SELECT length(CAST(vcf AS BLOB)) AS "size!: i64" FROM addressobjects WHERE (principal, addressbook_id, id) = (?, ?, ?) AND deleted_at IS NULL"#,
+            principal,
+            addressbook_id,
+            object_id
+        )
+        .fetch_optional(executor)
+        .await
+        .map_err(crate::Error::from)?
+        .unwrap_or_default();
+        Ok(size as u64)
+    }
+
+    /// Records a new revision with the synctoken of the current change and discards the oldest ones
+    async fn _log_revision(
+        tx: &mut Transaction<'_, Sqlite>,
+        principal: &str,
+        addressbook_id: &str,
+        object_id: &str,
+        vcf: &str,
+        changed_by: Option<&str>,
+    ) -> Result<(), rustical_store::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO addressobjectrevisions (principal, addressbook_id, object_id, revision, vcf, changed_by)
+            VALUES (?1, ?2, ?3, (
+                SELECT synctoken FROM addressbooks WHERE (principal, id) = (?1, ?2)
+            ), ?4, ?5)"#,
+            principal,
+            addressbook_id,
+            object_id,
+            vcf,
+            changed_by
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(crate::Error::from)?;
+
+        let max_revisions = MAX_REVISIONS as i64;
+        sqlx::query!(
+            r#"
+            DELETE FROM addressobjectrevisions
+            WHERE (principal, addressbook_id, object_id) = (?1, ?2, ?3) AND revision NOT IN (
+                SELECT revision FROM addressobjectrevisions
+                WHERE (principal, addressbook_id, object_id) = (?1, ?2, ?3)
+                ORDER BY revision DESC
+                LIMIT ?4
+            )"#,
+            principal,
+            addressbook_id,
+            object_id,
+            max_revisions
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(())
+    }
+
+    async fn _delete_revisions<'e, E: Executor<'e, Database = Sqlite>>(
+        executor: E,
+        principal: &str,
+        addressbook_id: &str,
+        object_id: &str,
+    ) -> Result<(), rustical_store::Error> {
+        sqlx::query!(
+            "DELETE FROM addressobjectrevisions WHERE (principal, addressbook_id, object_id) = (?1, ?2, ?3)",
+            principal,
+            addressbook_id,
+            object_id
+        )
+        .execute(executor)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(())
+    }
+
+    async fn _get_object_revisions<'e, E: Executor<'e, Database = Sqlite>>(
+        executor: E,
+        principal: &str,
+        addressbook_id: &str,
+        object_id: &str,
+    ) -> Result<Vec<ObjectRevision<AddressObject>>, rustical_store::Error> {
+        struct RevisionRow {
+            revision: i64,
+            vcf: String,
+            changed_by: Option<String>,
+            created_at: NaiveDateTime,
+        }
+        sqlx::query_as!(
+            RevisionRow,
+            "SELECT revision, vcf, changed_by, created_at FROM addressobjectrevisions WHERE (principal, addressbook_id, object_id) = (?1, ?2, ?3) ORDER BY revision DESC",
+            principal,
+            addressbook_id,
+            object_id
+        )
+        .fetch_all(executor)
+        .await
+        .map_err(crate::Error::from)?
+        .into_iter()
+        .map(|row| {
+            Ok(ObjectRevision {
+                revision: row.revision,
+                created_at: row.created_at,
+                changed_by: row.changed_by,
+                object: AddressObject::from_vcf(object_id.to_owned(), row.vcf)?,
+            })
+        })
         .collect()
     }
 
+    async fn _purge_trashbin(
+        tx: &mut Transaction<'_, Sqlite>,
+        deleted_before: NaiveDateTime,
+    ) -> Result<usize, rustical_store::Error> {
+        // The objects and changelogs of addressbooks are deleted through the foreign key
+        let addressbooks = sqlx::query!(
+            "DELETE FROM addressbooks WHERE deleted_at < ?",
+            deleted_before
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(crate::Error::from)?
+        .rows_affected();
+        let objects = sqlx::query!(
+            "DELETE FROM addressobjects WHERE deleted_at < ?",
+            deleted_before
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(crate::Error::from)?
+        .rows_affected();
+
+        sqlx::query!(
+            r#"
+            DELETE FROM addressobjectrevisions AS rev
+            WHERE NOT EXISTS (
+                SELECT 1 FROM addressobjects AS obj
+                WHERE (obj.principal, obj.addressbook_id, obj.id) = (rev.principal, rev.addressbook_id, rev.object_id)
+            )"#
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(crate::Error::from)?;
+
+        // Only the latest change of a deleted object is needed to report its deletion
+        sqlx::query!(
+            r#"
+            DELETE FROM addressobjectchangelog AS log
+            WHERE NOT EXISTS (
+                SELECT 1 FROM addressobjects AS obj
+                WHERE (obj.principal, obj.addressbook_id, obj.id) = (log.principal, log.addressbook_id, log.object_id)
+            ) AND synctoken < (
+                SELECT max(latest.synctoken) FROM addressobjectchangelog AS latest
+                WHERE (latest.principal, latest.addressbook_id, latest.object_id) = (log.principal, log.addressbook_id, log.object_id)
+            )"#
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(crate::Error::from)?;
+
+        Ok((addressbooks + objects) as usize)
+    }
+
     async fn _get_object<'e, E: Executor<'e, Database = Sqlite>>(
         executor: E,
         principal: &str,
@@ -244,7 +640,7 @@ impl SqliteAddressbookStore {
     ) -> Result<AddressObject, rustical_store::Error> {
         Ok(sqlx::query_as!(
             AddressObjectRow,
-            "SELECT id, vcf FROM addressobjects WHERE (principal, addressbook_id, id) = (?, ?, ?)",
+            "SELECT id, vcf, etag, created_at, updated_at FROM addressobjects WHERE (principal, addressbook_id, id) = (?, ?, ?) AND deleted_at IS NULL",
             principal,
             addressbook_id,
             object_id
@@ -252,7 +648,7 @@ impl SqliteAddressbookStore {
         .fetch_one(executor)
         .await
         .map_err(crate::Error::from)?
-        .try_into()?)
+        .into())
     }
 
     async fn _put_object<'e, E: Executor<'e, Database = Sqlite>>(
@@ -263,23 +659,28 @@ impl SqliteAddressbookStore {
         overwrite: bool,
     ) -> Result<(), rustical_store::Error> {
         let (object_id, vcf) = (object.get_id(), object.get_vcf());
+        let etag = object.get_etag();
 
         (if overwrite {
             sqlx::query!(
-            "REPLACE INTO addressobjects (principal, addressbook_id, id, vcf) VALUES (?, ?, ?, ?)",
+            r#"INSERT INTO addressobjects (principal, addressbook_id, id, vcf, etag, created_at) VALUES (?, ?, ?, ?, ?, datetime())
+                ON CONFLICT (principal, addressbook_id, id) DO UPDATE SET
+                    vcf = excluded.vcf, etag = excluded.etag, updated_at = datetime(), deleted_at = NULL"#,
             principal,
             addressbook_id,
             object_id,
-            vcf
+            vcf,
+            etag
         )
         } else {
             // If the object already exists a database error is thrown and handled in error.rs
             sqlx::query!(
-            "INSERT INTO addressobjects (principal, addressbook_id, id, vcf) VALUES (?, ?, ?, ?)",
+            "INSERT INTO addressobjects (principal, addressbook_id, id, vcf, etag, created_at) VALUES (?, ?, ?, ?, ?, datetime())",
             principal,
             addressbook_id,
             object_id,
-            vcf
+            vcf,
+            etag
         )
         })
         .execute(executor)
@@ -309,7 +710,8 @@ impl SqliteAddressbookStore {
             }
             false => {
                 sqlx::query!(
-                    "DELETE FROM addressobjects WHERE addressbook_id = ? AND id = ?",
+                    "DELETE FROM addressobjects WHERE (principal, addressbook_id, id) = (?, ?, ?)",
+                    principal,
                     addressbook_id,
                     object_id
                 )
@@ -337,6 +739,125 @@ impl SqliteAddressbookStore {
         .await.map_err(crate::Error::from)?;
         Ok(())
     }
+
+    async fn _get_birthday_settings<'e, E: Executor<'e, Database = Sqlite>>(
+        executor: E,
+        principal: &str,
+    ) -> Result<BirthdayCalendarSettings, rustical_store::Error> {
+        let settings = sqlx::query_as!(
+            BirthdayCalendarSettings,
+            r#"SELECT birthday_summary_template, anniversary_summary_template, abdate_summary_template, alarm_trigger, color, displayname_template, include_anniversaries, include_abdates
+                FROM birthday_settings
+                WHERE principal = ?"#,
+            principal
+        )
+        .fetch_optional(executor)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(settings.unwrap_or_default())
+    }
+
+    async fn _update_birthday_settings<'e, E: Executor<'e, Database = Sqlite>>(
+        executor: E,
+        principal: &str,
+        settings: BirthdayCalendarSettings,
+    ) -> Result<(), rustical_store::Error> {
+        sqlx::query!(
+            r#"REPLACE INTO birthday_settings (principal, birthday_summary_template, anniversary_summary_template, abdate_summary_template, alarm_trigger, color, displayname_template, include_anniversaries, include_abdates)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)"#,
+            principal,
+            settings.birthday_summary_template,
+            settings.anniversary_summary_template,
+            settings.abdate_summary_template,
+            settings.alarm_trigger,
+            settings.color,
+            settings.displayname_template,
+            settings.include_anniversaries,
+            settings.include_abdates
+        )
+        .execute(executor)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(())
+    }
+
+    /// Checks the quota in the transaction of the write if there is one
+    async fn put_object_within_quota(
+        &self,
+        principal: String,
+        addressbook_id: String,
+        object: AddressObject,
+        overwrite: bool,
+        changed_by: Option<&str>,
+        quota: Option<u64>,
+    ) -> Result<(), rustical_store::Error> {
+        let mut tx = self.db.begin().await.map_err(crate::Error::from)?;
+
+        let object_id = object.get_id().to_owned();
+        let vcf = object.get_vcf().to_owned();
+        let dates = object.get_dates();
+
+        let old_size = if quota.is_some() {
+            crate::lock_principal_usage(&mut tx, &principal)
+                .await
+                .map_err(crate::Error::from)?;
+            Self::_get_object_size(&mut *tx, &principal, &addressbook_id, &object_id).await?
+        } else {
+            0
+        };
+
+        Self::_put_object(
+            &mut *tx,
+            principal.to_owned(),
+            addressbook_id.to_owned(),
+            object,
+            overwrite,
+        )
+        .await?;
+        Self::_index_dates(&mut tx, &principal, &addressbook_id, &object_id, &dates)
+            .await
+            .map_err(crate::Error::from)?;
+
+        let synctoken = log_object_operation(
+            &mut tx,
+            &principal,
+            &addressbook_id,
+            &object_id,
+            ChangeOperation::Add,
+        )
+        .await
+        .map_err(crate::Error::from)?;
+
+        Self::_log_revision(
+            &mut tx,
+            &principal,
+            &addressbook_id,
+            &object_id,
+            &vcf,
+            changed_by,
+        )
+        .await?;
+
+        if let Some(quota) = quota {
+            crate::check_quota(&mut tx, &principal, quota, old_size, vcf.len() as u64).await?;
+        }
+
+        tx.commit().await.map_err(crate::Error::from)?;
+
+        if let Err(err) = self.sender.try_send(CollectionOperation {
+            r#type: CollectionOperationType::Object,
+            domain: CollectionOperationDomain::Addressbook,
+            topic: self
+                .get_addressbook(&principal, &addressbook_id)
+                .await?
+                .push_topic,
+            sync_token: Some(synctoken),
+        }) {
+            error!("Push notification about deleted addressbook failed: {err}");
+        };
+
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -402,16 +923,16 @@ impl AddressbookStore for SqliteAddressbookStore {
         Self::_delete_addressbook(&mut *tx, principal, addressbook_id, use_trashbin).await?;
         tx.commit().await.map_err(crate::Error::from)?;
 
-        if let Some(addressbook) = addressbook {
-            if let Err(err) = self.sender.try_send(CollectionOperation {
+        if let Some(addressbook) = addressbook
+            && let Err(err) = self.sender.try_send(CollectionOperation {
                 r#type: CollectionOperationType::Delete,
                 domain: CollectionOperationDomain::Addressbook,
                 topic: addressbook.push_topic,
                 sync_token: None,
-            }) {
-                error!("Push notification about deleted addressbook failed: {err}");
-            };
-        }
+            })
+        {
+            error!("Push notification about deleted addressbook failed: {err}");
+        };
 
         Ok(())
     }
@@ -431,8 +952,9 @@ impl AddressbookStore for SqliteAddressbookStore {
         principal: &str,
         addressbook_id: &str,
         synctoken: i64,
-    ) -> Result<(Vec<AddressObject>, Vec<String>, i64), rustical_store::Error> {
-        Self::_sync_changes(&self.db, principal, addressbook_id, synctoken).await
+        limit: Option<usize>,
+    ) -> Result<(Vec<AddressObject>, Vec<String>, i64, bool), rustical_store::Error> {
+        Self::_sync_changes(&self.db, principal, addressbook_id, synctoken, limit).await
     }
 
     #[instrument]
@@ -444,6 +966,61 @@ impl AddressbookStore for SqliteAddressbookStore {
         Self::_get_objects(&self.db, principal, addressbook_id).await
     }
 
+    #[instrument]
+    async fn get_objects_metadata(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+    ) -> Result<Vec<AddressObject>, rustical_store::Error> {
+        Self::_get_objects_metadata(&self.db, principal, addressbook_id).await
+    }
+
+    #[instrument]
+    async fn get_objects_with_dates(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+        range: &VcardDateRange,
+    ) -> Result<Vec<AddressObject>, rustical_store::Error> {
+        Self::_get_objects_with_dates(&self.db, principal, addressbook_id, range).await
+    }
+
+    #[instrument]
+    async fn get_deleted_objects(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+    ) -> Result<Vec<AddressObject>, rustical_store::Error> {
+        Self::_get_deleted_objects(&self.db, principal, addressbook_id).await
+    }
+
+    #[instrument]
+    async fn get_used_bytes(&self, principal: &str) -> Result<u64, rustical_store::Error> {
+        Self::_get_used_bytes(&self.db, principal).await
+    }
+
+    #[instrument]
+    async fn compact_changelog(
+        &self,
+        horizon: NaiveDateTime,
+    ) -> Result<usize, rustical_store::Error> {
+        let mut tx = self.db.begin().await.map_err(crate::Error::from)?;
+        let count = Self::_compact_changelog(&mut tx, horizon).await?;
+        tx.commit().await.map_err(crate::Error::from)?;
+        Ok(count)
+    }
+
+    #[instrument]
+    async fn purge_trashbin(
+        &self,
+        deleted_before: NaiveDateTime,
+    ) -> Result<usize, rustical_store::Error> {
+        let mut tx = self.db.begin().await.map_err(crate::Error::from)?;
+        let count = Self::_purge_trashbin(&mut tx, deleted_before).await?;
+        tx.commit().await.map_err(crate::Error::from)?;
+        Ok(count)
+    }
+
     #[instrument]
     async fn get_object(
         &self,
@@ -461,45 +1038,39 @@ impl AddressbookStore for SqliteAddressbookStore {
         addressbook_id: String,
         object: AddressObject,
         overwrite: bool,
+        changed_by: Option<&str>,
     ) -> Result<(), rustical_store::Error> {
-        let mut tx = self.db.begin().await.map_err(crate::Error::from)?;
-
-        let object_id = object.get_id().to_owned();
-
-        Self::_put_object(
-            &mut *tx,
-            principal.to_owned(),
-            addressbook_id.to_owned(),
+        self.put_object_within_quota(
+            principal,
+            addressbook_id,
             object,
             overwrite,
+            changed_by,
+            None,
         )
-        .await?;
+        .await
+    }
 
-        let synctoken = log_object_operation(
-            &mut tx,
-            &principal,
-            &addressbook_id,
-            &object_id,
-            ChangeOperation::Add,
+    #[instrument(skip(quota))]
+    async fn put_object_with_quota(
+        &self,
+        principal: String,
+        addressbook_id: String,
+        object: AddressObject,
+        overwrite: bool,
+        changed_by: Option<&str>,
+        quota: &Quota,
+    ) -> Result<(), rustical_store::Error> {
+        let quota = quota.get_quota(&principal);
+        self.put_object_within_quota(
+            principal,
+            addressbook_id,
+            object,
+            overwrite,
+            changed_by,
+            quota,
         )
         .await
-        .map_err(crate::Error::from)?;
-
-        tx.commit().await.map_err(crate::Error::from)?;
-
-        if let Err(err) = self.sender.try_send(CollectionOperation {
-            r#type: CollectionOperationType::Object,
-            domain: CollectionOperationDomain::Addressbook,
-            topic: self
-                .get_addressbook(&principal, &addressbook_id)
-                .await?
-                .push_topic,
-            sync_token: Some(synctoken),
-        }) {
-            error!("Push notification about deleted addressbook failed: {err}");
-        };
-
-        Ok(())
     }
 
     #[instrument]
@@ -513,6 +1084,9 @@ impl AddressbookStore for SqliteAddressbookStore {
         let mut tx = self.db.begin().await.map_err(crate::Error::from)?;
 
         Self::_delete_object(&mut *tx, principal, addressbook_id, object_id, use_trashbin).await?;
+        if !use_trashbin {
+            Self::_delete_revisions(&mut *tx, principal, addressbook_id, object_id).await?;
+        }
 
         let synctoken = log_object_operation(
             &mut tx,
@@ -574,6 +1148,133 @@ impl AddressbookStore for SqliteAddressbookStore {
 
         Ok(())
     }
+
+    #[instrument]
+    async fn get_object_revisions(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+        object_id: &str,
+    ) -> Result<Vec<ObjectRevision<AddressObject>>, rustical_store::Error> {
+        Self::_get_object_revisions(&self.db, principal, addressbook_id, object_id).await
+    }
+
+    #[instrument]
+    async fn get_objects_revisions(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+        object_ids: &[String],
+    ) -> Result<HashMap<String, Vec<ObjectRevision<AddressObject>>>, rustical_store::Error> {
+        #[derive(sqlx::FromRow)]
+        struct RevisionRow {
+            object_id: String,
+            revision: i64,
+            vcf: String,
+            changed_by: Option<String>,
+            created_at: NaiveDateTime,
+        }
+        let mut revisions: HashMap<String, Vec<ObjectRevision<AddressObject>>> = HashMap::new();
+        // Keeps the number of bound parameters well below SQLite's limit
+        for object_ids in object_ids.chunks(REVISIONS_BATCH_SIZE) {
+            // The number of ids varies so the query can't be checked at compile time
+            let mut query = QueryBuilder::<Sqlite>::new(
+                "SELECT object_id, revision, vcf, changed_by, created_at FROM addressobjectrevisions WHERE principal = ",
+            );
+            query.push_bind(principal);
+            query.push(" AND addressbook_id = ");
+            query.push_bind(addressbook_id);
+            query.push(" AND object_id IN (");
+            let mut ids = query.separated(", ");
+            for object_id in object_ids {
+                ids.push_bind(object_id);
+            }
+            query.push(") ORDER BY revision DESC");
+            let rows = query
+                .build_query_as::<RevisionRow>()
+                .fetch_all(&self.db)
+                .await
+                .map_err(crate::Error::from)?;
+
+            for row in rows {
+                let revision = ObjectRevision {
+                    revision: row.revision,
+                    created_at: row.created_at,
+                    changed_by: row.changed_by,
+                    object: AddressObject::from_vcf(row.object_id.to_owned(), row.vcf)?,
+                };
+                revisions.entry(row.object_id).or_default().push(revision);
+            }
+        }
+        Ok(revisions)
+    }
+
+    #[instrument]
+    async fn get_birthday_settings(
+        &self,
+        principal: &str,
+    ) -> Result<BirthdayCalendarSettings, rustical_store::Error> {
+        Self::_get_birthday_settings(&self.db, principal).await
+    }
+
+    #[instrument]
+    async fn update_birthday_settings(
+        &self,
+        principal: &str,
+        settings: BirthdayCalendarSettings,
+    ) -> Result<(), rustical_store::Error> {
+        settings.validate()?;
+        let mut tx = self.db.begin().await.map_err(crate::Error::from)?;
+        let previous = Self::_get_birthday_settings(&mut *tx, principal).await?;
+        if previous == settings {
+            return Ok(());
+        }
+        Self::_update_birthday_settings(&mut *tx, principal, settings).await?;
+
+        // Every contact is rendered differently in the birthday calendars now
+        let objects = sqlx::query!(
+            r#"SELECT addressbook_id, id FROM addressobjects
+                WHERE principal = ? AND deleted_at IS NULL
+                ORDER BY addressbook_id, id"#,
+            principal
+        )
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(crate::Error::from)?;
+        let mut synctokens = HashMap::new();
+        for object in objects {
+            let synctoken = log_object_operation(
+                &mut tx,
+                principal,
+                &object.addressbook_id,
+                &object.id,
+                ChangeOperation::Add,
+            )
+            .await
+            .map_err(crate::Error::from)?;
+            synctokens.insert(object.addressbook_id, synctoken);
+        }
+        tx.commit().await.map_err(crate::Error::from)?;
+
+        for (addressbook_id, synctoken) in synctokens {
+            if let Err(err) = self.sender.try_send(CollectionOperation {
+                r#type: CollectionOperationType::Object,
+                domain: CollectionOperationDomain::Addressbook,
+                topic: self
+                    .get_addressbook(principal, &addressbook_id)
+                    .await?
+                    .push_topic,
+                sync_token: Some(synctoken),
+            }) {
+                error!("Push notification about addressbook failed: {err}");
+            }
+        }
+        Ok(())
+    }
+
+    fn is_read_only(&self) -> bool {
+        false
+    }
 }
 
 // Logs an operation to an address object
@@ -591,7 +1292,7 @@ async fn log_object_operation(
         Synctoken,
         r#"
         UPDATE addressbooks
-        SET synctoken = synctoken + 1
+        SET synctoken = synctoken + 1, updated_at = datetime()
         WHERE (principal, id) = (?1, ?2)
         RETURNING synctoken"#,
         principal,
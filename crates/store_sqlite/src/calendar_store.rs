@@ -1,28 +1,77 @@
 use super::ChangeOperation;
 use async_trait::async_trait;
-use chrono::TimeDelta;
+use chrono::NaiveDate;
 use derive_more::derive::Constructor;
+use futures_util::stream::{self, StreamExt, TryStreamExt};
 use rustical_store::calendar::{CalDateTime, CalendarObjectType};
-use rustical_store::calendar_store::CalendarQuery;
-use rustical_store::synctoken::format_synctoken;
-use rustical_store::{Calendar, CalendarObject, CalendarStore, Error};
+use rustical_store::calendar_store::{CalendarObjectStream, CalendarQuery};
+use rustical_store::quota::Quota;
+use rustical_store::revision::MAX_REVISIONS;
+use rustical_store::synctoken::{format_synctoken, resolve_synctoken, truncate_changes};
+use rustical_store::{Calendar, CalendarObject, CalendarStore, Error, ObjectRevision};
 use rustical_store::{CollectionOperation, CollectionOperationType};
 use sqlx::types::chrono::NaiveDateTime;
-use sqlx::{Acquire, Executor, Sqlite, SqlitePool, Transaction};
+use sqlx::{Acquire, Executor, QueryBuilder, Sqlite, SqlitePool, Transaction};
 use tokio::sync::mpsc::Sender;
 use tracing::{error, instrument};
 
-#[derive(Debug, Clone)]
+/// Number of objects fetched at once when streaming objects
+const OBJECT_STREAM_PAGE_SIZE: i64 = 100;
+
+#[derive(Debug, Clone, sqlx::FromRow)]
 struct CalendarObjectRow {
     id: String,
     ics: String,
+    etag: Option<String>,
+    object_type: i64,
+    occurences: Option<String>,
+    references_timezones: Option<bool>,
+    created_at: Option<NaiveDateTime>,
+    updated_at: Option<NaiveDateTime>,
 }
 
 impl TryFrom<CalendarObjectRow> for CalendarObject {
     type Error = rustical_store::Error;
 
     fn try_from(value: CalendarObjectRow) -> Result<Self, Self::Error> {
-        CalendarObject::from_ics(value.id, value.ics)
+        // The object was validated when it was stored, it's only parsed once needed
+        Ok(CalendarObject::from_stored(
+            value.id,
+            value.ics,
+            value.etag,
+            value.object_type.try_into()?,
+        )
+        .with_occurences(value.occurences.as_deref())
+        .with_references_timezones(value.references_timezones)
+        .with_timestamps(value.created_at, value.updated_at))
+    }
+}
+
+/// Objects without a persisted etag or timezone references need their content to compute them
+#[derive(Debug, Clone)]
+struct CalendarObjectMetadataRow {
+    id: String,
+    etag: Option<String>,
+    ics: Option<String>,
+    references_timezones: Option<bool>,
+    object_type: i64,
+    created_at: Option<NaiveDateTime>,
+    updated_at: Option<NaiveDateTime>,
+}
+
+impl TryFrom<CalendarObjectMetadataRow> for CalendarObject {
+    type Error = rustical_store::Error;
+
+    fn try_from(value: CalendarObjectMetadataRow) -> Result<Self, Self::Error> {
+        let object_type = value.object_type.try_into()?;
+        Ok(match (value.etag, value.ics) {
+            (Some(etag), None) => CalendarObject::from_metadata(value.id, etag, object_type),
+            (etag, ics) => {
+                CalendarObject::from_stored(value.id, ics.unwrap_or_default(), etag, object_type)
+            }
+        }
+        .with_references_timezones(value.references_timezones)
+        .with_timestamps(value.created_at, value.updated_at))
     }
 }
 
@@ -36,6 +85,8 @@ struct CalendarRow {
     color: Option<String>,
     timezone: Option<String>,
     timezone_id: Option<String>,
+    created_at: Option<NaiveDateTime>,
+    updated_at: Option<NaiveDateTime>,
     deleted_at: Option<NaiveDateTime>,
     synctoken: i64,
     subscription_url: Option<String>,
@@ -66,6 +117,8 @@ impl From<CalendarRow> for Calendar {
             color: value.color,
             timezone: value.timezone,
             timezone_id: value.timezone_id,
+            created_at: value.created_at,
+            updated_at: value.updated_at,
             deleted_at: value.deleted_at,
             synctoken: value.synctoken,
             subscription_url: value.subscription_url,
@@ -89,7 +142,8 @@ impl SqliteCalendarStore {
     ) -> Result<Calendar, Error> {
         let cal = sqlx::query_as!(
             CalendarRow,
-            r#"SELECT *
+            r#"SELECT principal, id, displayname, "order", description, color, timezone, timezone_id,
+                    created_at, updated_at, deleted_at, synctoken, subscription_url, push_topic, comp_event, comp_todo, comp_journal
                 FROM calendars
                 WHERE (principal, id) = (?, ?)"#,
             principal,
@@ -107,7 +161,8 @@ impl SqliteCalendarStore {
     ) -> Result<Vec<Calendar>, Error> {
         let cals = sqlx::query_as!(
             CalendarRow,
-            r#"SELECT *
+            r#"SELECT principal, id, displayname, "order", description, color, timezone, timezone_id,
+                    created_at, updated_at, deleted_at, synctoken, subscription_url, push_topic, comp_event, comp_todo, comp_journal
                 FROM calendars
                 WHERE principal = ? AND deleted_at IS NULL"#,
             principal
@@ -124,7 +179,8 @@ impl SqliteCalendarStore {
     ) -> Result<Vec<Calendar>, Error> {
         let cals = sqlx::query_as!(
             CalendarRow,
-            r#"SELECT *
+            r#"SELECT principal, id, displayname, "order", description, color, timezone, timezone_id,
+                    created_at, updated_at, deleted_at, synctoken, subscription_url, push_topic, comp_event, comp_todo, comp_journal
                 FROM calendars
                 WHERE principal = ? AND deleted_at IS NOT NULL"#,
             principal
@@ -144,8 +200,8 @@ impl SqliteCalendarStore {
         let comp_journal = calendar.components.contains(&CalendarObjectType::Journal);
 
         sqlx::query!(
-            r#"INSERT INTO calendars (principal, id, displayname, description, "order", color, timezone, timezone_id, push_topic, comp_event, comp_todo, comp_journal)
-                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"#,
+            r#"INSERT INTO calendars (principal, id, displayname, description, "order", color, timezone, timezone_id, push_topic, comp_event, comp_todo, comp_journal, created_at, updated_at)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, datetime(), datetime())"#,
             calendar.principal,
             calendar.id,
             calendar.displayname,
@@ -174,7 +230,7 @@ impl SqliteCalendarStore {
         let comp_journal = calendar.components.contains(&CalendarObjectType::Journal);
 
         let result = sqlx::query!(
-            r#"UPDATE calendars SET principal = ?, id = ?, displayname = ?, description = ?, "order" = ?, color = ?, timezone = ?, timezone_id = ?, push_topic = ?, comp_event = ?, comp_todo = ?, comp_journal = ?
+            r#"UPDATE calendars SET principal = ?, id = ?, displayname = ?, description = ?, "order" = ?, color = ?, timezone = ?, timezone_id = ?, push_topic = ?, comp_event = ?, comp_todo = ?, comp_journal = ?, updated_at = datetime()
                 WHERE (principal, id) = (?, ?)"#,
             calendar.principal,
             calendar.id,
@@ -245,32 +301,101 @@ impl SqliteCalendarStore {
     ) -> Result<Vec<CalendarObject>, Error> {
         sqlx::query_as!(
             CalendarObjectRow,
-            "SELECT id, ics FROM calendarobjects WHERE principal = ? AND cal_id = ? AND deleted_at IS NULL",
+            "SELECT id, ics, etag, object_type, occurences, references_timezones, created_at, updated_at FROM calendarobjects WHERE principal = ? AND cal_id = ? AND deleted_at IS NULL",
+            principal,
+            cal_id
+        )
+        .fetch_all(executor)
+        .await.map_err(crate::Error::from)?
+        .into_iter()
+        .map(|row| row.try_into())
+        .collect()
+    }
+
+    async fn _get_objects_metadata<'e, E: Executor<'e, Database = Sqlite>>(
+        executor: E,
+        principal: &str,
+        cal_id: &str,
+    ) -> Result<Vec<CalendarObject>, Error> {
+        sqlx::query_as!(
+            CalendarObjectMetadataRow,
+            r#"SELECT id, etag, IIF(etag IS NULL OR references_timezones IS NULL, ics, NULL) AS "ics: String", references_timezones, object_type, created_at, updated_at FROM calendarobjects WHERE principal = ? AND cal_id = ? AND deleted_at IS NULL"#,
+            principal,
+            cal_id
+        )
+        .fetch_all(executor)
+        .await
+        .map_err(crate::Error::from)?
+        .into_iter()
+        .map(|row| row.try_into())
+        .collect()
+    }
+
+    async fn _get_deleted_objects<'e, E: Executor<'e, Database = Sqlite>>(
+        executor: E,
+        principal: &str,
+        cal_id: &str,
+    ) -> Result<Vec<CalendarObject>, Error> {
+        sqlx::query_as!(
+            CalendarObjectRow,
+            "SELECT id, ics, etag, object_type, occurences, references_timezones, created_at, updated_at FROM calendarobjects WHERE principal = ? AND cal_id = ? AND deleted_at IS NOT NULL",
             principal,
             cal_id
         )
         .fetch_all(executor)
         .await.map_err(crate::Error::from)?
         .into_iter()
-        .map(|row| row.try_into().map_err(rustical_store::Error::from))
+        .map(|row| row.try_into())
         .collect()
     }
 
+    async fn _get_used_bytes<'e, E: Executor<'e, Database = Sqlite>>(
+        executor: E,
+        principal: &str,
+    ) -> Result<u64, Error> {
+        let used_bytes = sqlx::query_scalar!(
+            r#"SELECT calendar_bytes AS "used_bytes!: i64" FROM principal_usage WHERE principal = ?"#,
+            principal
+        )
+        .fetch_optional(executor)
+        .await
+        .map_err(crate::Error::from)?
+        .unwrap_or_default();
+        Ok(used_bytes as u64)
+    }
+
+    /// Size in bytes of an object outside the trashbin as counted against the quota
+    async fn _get_object_size<'e, E: Executor<'e, Database = Sqlite>>(
+        executor: E,
+        principal: &str,
+        cal_id: &str,
+        object_id: &str,
+    ) -> Result<u64, Error> {
+        let size = sqlx::query_scalar!(
+            r#"SELECT length(CAST(ics AS BLOB)) AS "size!: i64" FROM calendarobjects WHERE (principal, cal_id, id) = (?, ?, ?) AND deleted_at IS NULL"#,
+            principal,
+            cal_id,
+            object_id
+        )
+        .fetch_optional(executor)
+        .await
+        .map_err(crate::Error::from)?
+        .unwrap_or_default();
+        Ok(size as u64)
+    }
+
     async fn _calendar_query<'e, E: Executor<'e, Database = Sqlite>>(
         executor: E,
         principal: &str,
         cal_id: &str,
         query: CalendarQuery,
     ) -> Result<Vec<CalendarObject>, Error> {
-        // We extend our query interval by one day in each direction since we really don't want to
-        // miss any objects because of timezone differences
         // I've previously tried NaiveDate::MIN,MAX, but it seems like sqlite cannot handle these
-        let start = query.time_start.map(|start| start - TimeDelta::days(1));
-        let end = query.time_end.map(|end| end + TimeDelta::days(1));
+        let (start, end) = query.occurence_dates();
 
         sqlx::query_as!(
             CalendarObjectRow,
-            r"SELECT id, ics FROM calendarobjects
+            r"SELECT id, ics, etag, object_type, occurences, references_timezones, created_at, updated_at FROM calendarobjects
                 WHERE principal = ? AND cal_id = ? AND deleted_at IS NULL
                     AND (last_occurence IS NULL OR ? IS NULL OR last_occurence >= date(?))
                     AND (first_occurence IS NULL OR ? IS NULL OR first_occurence <= date(?))
@@ -286,10 +411,87 @@ impl SqliteCalendarStore {
         .await
         .map_err(crate::Error::from)?
         .into_iter()
-        .map(|row| row.try_into().map_err(rustical_store::Error::from))
+        .map(|row| row.try_into())
         .collect()
     }
 
+    /// Fetches the objects of a calendar query ordered by their id, starting after `after`
+    #[allow(clippy::too_many_arguments)]
+    async fn _calendar_query_page<'e, E: Executor<'e, Database = Sqlite>>(
+        executor: E,
+        principal: &str,
+        cal_id: &str,
+        start: Option<NaiveDate>,
+        end: Option<NaiveDate>,
+        after: &str,
+        limit: i64,
+    ) -> Result<Vec<CalendarObjectRow>, Error> {
+        Ok(sqlx::query_as!(
+            CalendarObjectRow,
+            r"SELECT id, ics, etag, object_type, occurences, references_timezones, created_at, updated_at FROM calendarobjects
+                WHERE principal = ? AND cal_id = ? AND deleted_at IS NULL
+                    AND (last_occurence IS NULL OR ? IS NULL OR last_occurence >= date(?))
+                    AND (first_occurence IS NULL OR ? IS NULL OR first_occurence <= date(?))
+                    AND id > ?
+                ORDER BY id LIMIT ?
+            ",
+            principal,
+            cal_id,
+            start,
+            start,
+            end,
+            end,
+            after,
+            limit,
+        )
+        .fetch_all(executor)
+        .await
+        .map_err(crate::Error::from)?)
+    }
+
+    /// Streams the objects in pages so that no connection is held while the consumer is busy
+    fn _calendar_query_stream(
+        db: SqlitePool,
+        principal: String,
+        cal_id: String,
+        query: CalendarQuery,
+    ) -> CalendarObjectStream {
+        let (start, end) = query.occurence_dates();
+
+        stream::try_unfold(Some(String::new()), move |after| {
+            let db = db.clone();
+            let principal = principal.clone();
+            let cal_id = cal_id.clone();
+            async move {
+                let Some(after) = after else {
+                    return Ok::<_, Error>(None);
+                };
+                let rows = Self::_calendar_query_page(
+                    &db,
+                    &principal,
+                    &cal_id,
+                    start,
+                    end,
+                    &after,
+                    OBJECT_STREAM_PAGE_SIZE,
+                )
+                .await?;
+                let next = if rows.len() as i64 == OBJECT_STREAM_PAGE_SIZE {
+                    rows.last().map(|row| row.id.to_owned())
+                } else {
+                    None
+                };
+                let objects = rows
+                    .into_iter()
+                    .map(CalendarObject::try_from)
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Some((stream::iter(objects.into_iter().map(Ok)), next)))
+            }
+        })
+        .try_flatten()
+        .boxed()
+    }
+
     async fn _get_object<'e, E: Executor<'e, Database = Sqlite>>(
         executor: E,
         principal: &str,
@@ -298,7 +500,7 @@ impl SqliteCalendarStore {
     ) -> Result<CalendarObject, Error> {
         sqlx::query_as!(
             CalendarObjectRow,
-            "SELECT id, ics FROM calendarobjects WHERE (principal, cal_id, id) = (?, ?, ?)",
+            "SELECT id, ics, etag, object_type, occurences, references_timezones, created_at, updated_at FROM calendarobjects WHERE (principal, cal_id, id) = (?, ?, ?) AND deleted_at IS NULL",
             principal,
             cal_id,
             object_id
@@ -332,31 +534,42 @@ impl SqliteCalendarStore {
             .flatten()
             .as_ref()
             .map(CalDateTime::date);
+        let occurences = object.format_occurences().ok();
+        let references_timezones = object.references_timezones();
         let etag = object.get_etag();
         let object_type = object.get_object_type() as u8;
 
         (if overwrite {
             sqlx::query!(
-                "REPLACE INTO calendarobjects (principal, cal_id, id, ics, first_occurence, last_occurence, etag, object_type) VALUES (?, ?, ?, ?, date(?), date(?), ?, ?)",
+                r#"INSERT INTO calendarobjects (principal, cal_id, id, ics, first_occurence, last_occurence, occurences, references_timezones, etag, object_type, created_at) VALUES (?, ?, ?, ?, date(?), date(?), ?, ?, ?, ?, datetime())
+                    ON CONFLICT (principal, cal_id, id) DO UPDATE SET
+                        ics = excluded.ics, first_occurence = excluded.first_occurence, last_occurence = excluded.last_occurence,
+                        occurences = excluded.occurences, references_timezones = excluded.references_timezones,
+                        etag = excluded.etag, object_type = excluded.object_type,
+                        updated_at = datetime(), deleted_at = NULL"#,
                 principal,
                 cal_id,
                 object_id,
                 ics,
                 first_occurence,
                 last_occurence,
+                occurences,
+                references_timezones,
                 etag,
                 object_type,
             )
         } else {
             // If the object already exists a database error is thrown and handled in error.rs
             sqlx::query!(
-                "INSERT INTO calendarobjects (principal, cal_id, id, ics, first_occurence, last_occurence, etag, object_type) VALUES (?, ?, ?, ?, date(?), date(?), ?, ?)",
+                "INSERT INTO calendarobjects (principal, cal_id, id, ics, first_occurence, last_occurence, occurences, references_timezones, etag, object_type, created_at) VALUES (?, ?, ?, ?, date(?), date(?), ?, ?, ?, ?, datetime())",
                 principal,
                 cal_id,
                 object_id,
                 ics,
                 first_occurence,
                 last_occurence,
+                occurences,
+                references_timezones,
                 etag,
                 object_type,
             )
@@ -389,7 +602,8 @@ impl SqliteCalendarStore {
             }
             false => {
                 sqlx::query!(
-                    "DELETE FROM calendarobjects WHERE cal_id = ? AND id = ?",
+                    "DELETE FROM calendarobjects WHERE (principal, cal_id, id) = (?, ?, ?)",
+                    principal,
                     cal_id,
                     id
                 )
@@ -419,12 +633,198 @@ impl SqliteCalendarStore {
         Ok(())
     }
 
+    /// Records a new revision with the synctoken of the current change and discards the oldest ones
+    async fn _log_revision(
+        tx: &mut Transaction<'_, Sqlite>,
+        principal: &str,
+        cal_id: &str,
+        object_id: &str,
+        ics: &str,
+        changed_by: Option<&str>,
+    ) -> Result<(), rustical_store::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO calendarobjectrevisions (principal, cal_id, object_id, revision, ics, changed_by)
+            VALUES (?1, ?2, ?3, (
+                SELECT synctoken FROM calendars WHERE (principal, id) = (?1, ?2)
+            ), ?4, ?5)"#,
+            principal,
+            cal_id,
+            object_id,
+            ics,
+            changed_by
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(crate::Error::from)?;
+
+        let max_revisions = MAX_REVISIONS as i64;
+        sqlx::query!(
+            r#"
+            DELETE FROM calendarobjectrevisions
+            WHERE (principal, cal_id, object_id) = (?1, ?2, ?3) AND revision NOT IN (
+                SELECT revision FROM calendarobjectrevisions
+                WHERE (principal, cal_id, object_id) = (?1, ?2, ?3)
+                ORDER BY revision DESC
+                LIMIT ?4
+            )"#,
+            principal,
+            cal_id,
+            object_id,
+            max_revisions
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(())
+    }
+
+    async fn _delete_revisions<'e, E: Executor<'e, Database = Sqlite>>(
+        executor: E,
+        principal: &str,
+        cal_id: &str,
+        object_id: &str,
+    ) -> Result<(), rustical_store::Error> {
+        sqlx::query!(
+            "DELETE FROM calendarobjectrevisions WHERE (principal, cal_id, object_id) = (?1, ?2, ?3)",
+            principal,
+            cal_id,
+            object_id
+        )
+        .execute(executor)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(())
+    }
+
+    async fn _get_object_revisions<'e, E: Executor<'e, Database = Sqlite>>(
+        executor: E,
+        principal: &str,
+        cal_id: &str,
+        object_id: &str,
+    ) -> Result<Vec<ObjectRevision<CalendarObject>>, rustical_store::Error> {
+        struct RevisionRow {
+            revision: i64,
+            ics: String,
+            changed_by: Option<String>,
+            created_at: NaiveDateTime,
+        }
+        sqlx::query_as!(
+            RevisionRow,
+            "SELECT revision, ics, changed_by, created_at FROM calendarobjectrevisions WHERE (principal, cal_id, object_id) = (?1, ?2, ?3) ORDER BY revision DESC",
+            principal,
+            cal_id,
+            object_id
+        )
+        .fetch_all(executor)
+        .await
+        .map_err(crate::Error::from)?
+        .into_iter()
+        .map(|row| {
+            Ok(ObjectRevision {
+                revision: row.revision,
+                created_at: row.created_at,
+                changed_by: row.changed_by,
+                object: CalendarObject::from_ics(object_id.to_owned(), row.ics)?,
+            })
+        })
+        .collect()
+    }
+
+    async fn _purge_trashbin(
+        tx: &mut Transaction<'_, Sqlite>,
+        deleted_before: NaiveDateTime,
+    ) -> Result<usize, Error> {
+        // The objects and changelogs of calendars are deleted through the foreign key
+        let calendars = sqlx::query!("DELETE FROM calendars WHERE deleted_at < ?", deleted_before)
+            .execute(&mut **tx)
+            .await
+            .map_err(crate::Error::from)?
+            .rows_affected();
+        let objects = sqlx::query!(
+            "DELETE FROM calendarobjects WHERE deleted_at < ?",
+            deleted_before
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(crate::Error::from)?
+        .rows_affected();
+
+        sqlx::query!(
+            r#"
+            DELETE FROM calendarobjectrevisions AS rev
+            WHERE NOT EXISTS (
+                SELECT 1 FROM calendarobjects AS obj
+                WHERE (obj.principal, obj.cal_id, obj.id) = (rev.principal, rev.cal_id, rev.object_id)
+            )"#
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(crate::Error::from)?;
+
+        // Only the latest change of a deleted object is needed to report its deletion
+        sqlx::query!(
+            r#"
+            DELETE FROM calendarobjectchangelog AS log
+            WHERE NOT EXISTS (
+                SELECT 1 FROM calendarobjects AS obj
+                WHERE (obj.principal, obj.cal_id, obj.id) = (log.principal, log.cal_id, log.object_id)
+            ) AND synctoken < (
+                SELECT max(latest.synctoken) FROM calendarobjectchangelog AS latest
+                WHERE (latest.principal, latest.cal_id, latest.object_id) = (log.principal, log.cal_id, log.object_id)
+            )"#
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(crate::Error::from)?;
+
+        Ok((calendars + objects) as usize)
+    }
+
+    async fn _compact_changelog(
+        tx: &mut Transaction<'_, Sqlite>,
+        horizon: NaiveDateTime,
+    ) -> Result<usize, Error> {
+        sqlx::query!(
+            r#"
+            UPDATE calendars SET min_synctoken = max(min_synctoken, coalesce((
+                SELECT max(log.synctoken) FROM calendarobjectchangelog AS log
+                WHERE (log.principal, log.cal_id) = (calendars.principal, calendars.id) AND log.created_at < ?1
+            ), 0))"#,
+            horizon
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(crate::Error::from)?;
+
+        // The latest change of objects that still exist is kept so that an initial sync lists them
+        let count = sqlx::query!(
+            r#"
+            DELETE FROM calendarobjectchangelog AS log
+            WHERE synctoken < (
+                SELECT max(latest.synctoken) FROM calendarobjectchangelog AS latest
+                WHERE (latest.principal, latest.cal_id, latest.object_id) = (log.principal, log.cal_id, log.object_id)
+            ) OR (created_at < ?1 AND NOT EXISTS (
+                SELECT 1 FROM calendarobjects AS obj
+                WHERE (obj.principal, obj.cal_id, obj.id) = (log.principal, log.cal_id, log.object_id)
+                    AND obj.deleted_at IS NULL
+            ))"#,
+            horizon
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(crate::Error::from)?
+        .rows_affected();
+        Ok(count as usize)
+    }
+
     async fn _sync_changes<'a, A: Acquire<'a, Database = Sqlite>>(
         acquire: A,
         principal: &str,
         cal_id: &str,
         synctoken: i64,
-    ) -> Result<(Vec<CalendarObject>, Vec<String>, i64), Error> {
+        limit: Option<usize>,
+    ) -> Result<(Vec<CalendarObject>, Vec<String>, i64, bool), Error> {
         struct Row {
             object_id: String,
             synctoken: i64,
@@ -432,13 +832,32 @@ impl SqliteCalendarStore {
 
         let mut conn = acquire.acquire().await.map_err(crate::Error::from)?;
 
-        let changes = sqlx::query_as!(
+        struct MinSynctoken {
+            min_synctoken: i64,
+        }
+        let min_synctoken = sqlx::query_as!(
+            MinSynctoken,
+            "SELECT min_synctoken FROM calendars WHERE (principal, id) = (?, ?)",
+            principal,
+            cal_id
+        )
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(crate::Error::from)?
+        .map(|MinSynctoken { min_synctoken }| min_synctoken)
+        .unwrap_or_default();
+        let (synctoken, initial) = resolve_synctoken(synctoken, min_synctoken)?;
+
+        let mut changes = sqlx::query_as!(
             Row,
             r#"
-                SELECT DISTINCT object_id, max(0, synctoken) as "synctoken!: i64" from calendarobjectchangelog
-                WHERE synctoken > ?
-                ORDER BY synctoken ASC
+                SELECT object_id, max(0, max(synctoken)) as "synctoken!: i64" from calendarobjectchangelog
+                WHERE (principal, cal_id) = (?, ?) AND synctoken > ?
+                GROUP BY object_id
+                ORDER BY "synctoken!: i64" ASC
             "#,
+            principal,
+            cal_id,
             synctoken
         )
         .fetch_all(&mut *conn)
@@ -447,10 +866,18 @@ impl SqliteCalendarStore {
         let mut objects = vec![];
         let mut deleted_objects = vec![];
 
+        let continuation = truncate_changes(
+            &mut changes,
+            |&Row { synctoken, .. }| synctoken,
+            limit,
+            initial,
+        );
         let new_synctoken = changes
             .last()
             .map(|&Row { synctoken, .. }| synctoken)
-            .unwrap_or(0);
+            .unwrap_or(synctoken)
+            // The latest changes might have been compacted away
+            .max(min_synctoken);
 
         for Row { object_id, .. } in changes {
             match Self::_get_object(&mut *conn, principal, cal_id, &object_id).await {
@@ -460,7 +887,72 @@ impl SqliteCalendarStore {
             }
         }
 
-        Ok((objects, deleted_objects, new_synctoken))
+        match continuation {
+            Some(continuation) => Ok((objects, deleted_objects, continuation, true)),
+            None => Ok((objects, deleted_objects, new_synctoken, false)),
+        }
+    }
+
+    /// Checks the quota in the transaction of the write if there is one
+    async fn put_object_within_quota(
+        &self,
+        principal: String,
+        cal_id: String,
+        object: CalendarObject,
+        overwrite: bool,
+        changed_by: Option<&str>,
+        quota: Option<u64>,
+    ) -> Result<(), Error> {
+        // TODO: Prevent objects from being commited to a subscription calendar
+        let mut tx = self.db.begin().await.map_err(crate::Error::from)?;
+
+        let object_id = object.get_id().to_owned();
+        let ics = object.get_ics().to_owned();
+
+        let old_size = if quota.is_some() {
+            crate::lock_principal_usage(&mut tx, &principal)
+                .await
+                .map_err(crate::Error::from)?;
+            Self::_get_object_size(&mut *tx, &principal, &cal_id, &object_id).await?
+        } else {
+            0
+        };
+
+        Self::_put_object(
+            &mut *tx,
+            principal.to_owned(),
+            cal_id.to_owned(),
+            object,
+            overwrite,
+        )
+        .await?;
+
+        let synctoken = log_object_operation(
+            &mut tx,
+            &principal,
+            &cal_id,
+            &object_id,
+            ChangeOperation::Add,
+        )
+        .await?;
+
+        Self::_log_revision(&mut tx, &principal, &cal_id, &object_id, &ics, changed_by).await?;
+
+        if let Some(quota) = quota {
+            crate::check_quota(&mut tx, &principal, quota, old_size, ics.len() as u64).await?;
+        }
+
+        tx.commit().await.map_err(crate::Error::from)?;
+
+        if let Err(err) = self.sender.try_send(CollectionOperation {
+            r#type: CollectionOperationType::Object,
+            domain: rustical_store::CollectionOperationDomain::Calendar,
+            topic: self.get_calendar(&principal, &cal_id).await?.push_topic,
+            sync_token: Some(synctoken),
+        }) {
+            error!("Push notification about deleted calendar failed: {err}");
+        };
+        Ok(())
     }
 }
 
@@ -515,16 +1007,16 @@ impl CalendarStore for SqliteCalendarStore {
         Self::_delete_calendar(&mut *tx, principal, id, use_trashbin).await?;
         tx.commit().await.map_err(crate::Error::from)?;
 
-        if let Some(cal) = cal {
-            if let Err(err) = self.sender.try_send(CollectionOperation {
+        if let Some(cal) = cal
+            && let Err(err) = self.sender.try_send(CollectionOperation {
                 r#type: CollectionOperationType::Delete,
                 domain: rustical_store::CollectionOperationDomain::Calendar,
                 topic: cal.push_topic,
                 sync_token: None,
-            }) {
-                error!("Push notification about deleted calendar failed: {err}");
-            };
-        }
+            })
+        {
+            error!("Push notification about deleted calendar failed: {err}");
+        };
         Ok(())
     }
 
@@ -543,6 +1035,21 @@ impl CalendarStore for SqliteCalendarStore {
         Self::_calendar_query(&self.db, principal, cal_id, query).await
     }
 
+    #[instrument]
+    async fn calendar_query_stream(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        query: CalendarQuery,
+    ) -> Result<CalendarObjectStream, Error> {
+        Ok(Self::_calendar_query_stream(
+            self.db.clone(),
+            principal.to_owned(),
+            cal_id.to_owned(),
+            query,
+        ))
+    }
+
     #[instrument]
     async fn get_objects(
         &self,
@@ -552,6 +1059,29 @@ impl CalendarStore for SqliteCalendarStore {
         Self::_get_objects(&self.db, principal, cal_id).await
     }
 
+    #[instrument]
+    async fn get_objects_metadata(
+        &self,
+        principal: &str,
+        cal_id: &str,
+    ) -> Result<Vec<CalendarObject>, Error> {
+        Self::_get_objects_metadata(&self.db, principal, cal_id).await
+    }
+
+    #[instrument]
+    async fn get_objects_stream(
+        &self,
+        principal: &str,
+        cal_id: &str,
+    ) -> Result<CalendarObjectStream, Error> {
+        Ok(Self::_calendar_query_stream(
+            self.db.clone(),
+            principal.to_owned(),
+            cal_id.to_owned(),
+            CalendarQuery::default(),
+        ))
+    }
+
     #[instrument]
     async fn get_object(
         &self,
@@ -562,6 +1092,39 @@ impl CalendarStore for SqliteCalendarStore {
         Self::_get_object(&self.db, principal, cal_id, object_id).await
     }
 
+    #[instrument]
+    async fn get_objects_by_ids(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        object_ids: &[String],
+    ) -> Result<Vec<CalendarObject>, Error> {
+        if object_ids.is_empty() {
+            return Ok(vec![]);
+        }
+        // The number of ids varies so the query can't be checked at compile time
+        let mut query = QueryBuilder::<Sqlite>::new(
+            "SELECT id, ics, etag, object_type, occurences, references_timezones, created_at, updated_at FROM calendarobjects WHERE principal = ",
+        );
+        query.push_bind(principal);
+        query.push(" AND cal_id = ");
+        query.push_bind(cal_id);
+        query.push(" AND deleted_at IS NULL AND id IN (");
+        let mut ids = query.separated(", ");
+        for object_id in object_ids {
+            ids.push_bind(object_id);
+        }
+        query.push(")");
+        query
+            .build_query_as::<CalendarObjectRow>()
+            .fetch_all(&self.db)
+            .await
+            .map_err(crate::Error::from)?
+            .into_iter()
+            .map(CalendarObject::try_from)
+            .collect()
+    }
+
     #[instrument]
     async fn put_object(
         &self,
@@ -569,41 +1132,25 @@ impl CalendarStore for SqliteCalendarStore {
         cal_id: String,
         object: CalendarObject,
         overwrite: bool,
+        changed_by: Option<&str>,
     ) -> Result<(), Error> {
-        // TODO: Prevent objects from being commited to a subscription calendar
-        let mut tx = self.db.begin().await.map_err(crate::Error::from)?;
-
-        let object_id = object.get_id().to_owned();
-
-        Self::_put_object(
-            &mut *tx,
-            principal.to_owned(),
-            cal_id.to_owned(),
-            object,
-            overwrite,
-        )
-        .await?;
-
-        let synctoken = log_object_operation(
-            &mut tx,
-            &principal,
-            &cal_id,
-            &object_id,
-            ChangeOperation::Add,
-        )
-        .await?;
-
-        tx.commit().await.map_err(crate::Error::from)?;
+        self.put_object_within_quota(principal, cal_id, object, overwrite, changed_by, None)
+            .await
+    }
 
-        if let Err(err) = self.sender.try_send(CollectionOperation {
-            r#type: CollectionOperationType::Object,
-            domain: rustical_store::CollectionOperationDomain::Calendar,
-            topic: self.get_calendar(&principal, &cal_id).await?.push_topic,
-            sync_token: Some(synctoken),
-        }) {
-            error!("Push notification about deleted calendar failed: {err}");
-        };
-        Ok(())
+    #[instrument(skip(quota))]
+    async fn put_object_with_quota(
+        &self,
+        principal: String,
+        cal_id: String,
+        object: CalendarObject,
+        overwrite: bool,
+        changed_by: Option<&str>,
+        quota: &Quota,
+    ) -> Result<(), Error> {
+        let quota = quota.get_quota(&principal);
+        self.put_object_within_quota(principal, cal_id, object, overwrite, changed_by, quota)
+            .await
     }
 
     #[instrument]
@@ -617,6 +1164,9 @@ impl CalendarStore for SqliteCalendarStore {
         let mut tx = self.db.begin().await.map_err(crate::Error::from)?;
 
         Self::_delete_object(&mut *tx, principal, cal_id, id, use_trashbin).await?;
+        if !use_trashbin {
+            Self::_delete_revisions(&mut *tx, principal, cal_id, id).await?;
+        }
 
         let synctoken =
             log_object_operation(&mut tx, principal, cal_id, id, ChangeOperation::Delete).await?;
@@ -660,14 +1210,55 @@ impl CalendarStore for SqliteCalendarStore {
         Ok(())
     }
 
+    #[instrument]
+    async fn get_deleted_objects(
+        &self,
+        principal: &str,
+        cal_id: &str,
+    ) -> Result<Vec<CalendarObject>, Error> {
+        Self::_get_deleted_objects(&self.db, principal, cal_id).await
+    }
+
+    #[instrument]
+    async fn get_used_bytes(&self, principal: &str) -> Result<u64, Error> {
+        Self::_get_used_bytes(&self.db, principal).await
+    }
+
+    #[instrument]
+    async fn compact_changelog(&self, horizon: NaiveDateTime) -> Result<usize, Error> {
+        let mut tx = self.db.begin().await.map_err(crate::Error::from)?;
+        let count = Self::_compact_changelog(&mut tx, horizon).await?;
+        tx.commit().await.map_err(crate::Error::from)?;
+        Ok(count)
+    }
+
+    #[instrument]
+    async fn purge_trashbin(&self, deleted_before: NaiveDateTime) -> Result<usize, Error> {
+        let mut tx = self.db.begin().await.map_err(crate::Error::from)?;
+        let count = Self::_purge_trashbin(&mut tx, deleted_before).await?;
+        tx.commit().await.map_err(crate::Error::from)?;
+        Ok(count)
+    }
+
     #[instrument]
     async fn sync_changes(
         &self,
         principal: &str,
         cal_id: &str,
         synctoken: i64,
-    ) -> Result<(Vec<CalendarObject>, Vec<String>, i64), Error> {
-        Self::_sync_changes(&self.db, principal, cal_id, synctoken).await
+        limit: Option<usize>,
+    ) -> Result<(Vec<CalendarObject>, Vec<String>, i64, bool), Error> {
+        Self::_sync_changes(&self.db, principal, cal_id, synctoken, limit).await
+    }
+
+    #[instrument]
+    async fn get_object_revisions(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        object_id: &str,
+    ) -> Result<Vec<ObjectRevision<CalendarObject>>, rustical_store::Error> {
+        Self::_get_object_revisions(&self.db, principal, cal_id, object_id).await
     }
 
     fn is_read_only(&self) -> bool {
@@ -690,7 +1281,7 @@ async fn log_object_operation(
         Synctoken,
         r#"
         UPDATE calendars
-        SET synctoken = synctoken + 1
+        SET synctoken = synctoken + 1, updated_at = datetime()
         WHERE (principal, id) = (?1, ?2)
         RETURNING synctoken"#,
         principal,
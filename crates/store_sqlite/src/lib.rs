@@ -1,9 +1,13 @@
+use addressbook_store::SqliteAddressbookStore;
+use rustical_store::calendar::references_iana_timezones;
+use rustical_store::{AddressObject, CalendarObject};
 use serde::Serialize;
-use sqlx::{sqlite::SqliteConnectOptions, Pool, Sqlite, SqlitePool};
+use sqlx::{sqlite::SqliteConnectOptions, Pool, Sqlite, SqliteConnection, SqlitePool};
 
 pub mod addressbook_store;
 pub mod calendar_store;
 pub mod error;
+pub mod principal_store;
 pub mod subscription_store;
 
 pub use error::Error;
@@ -38,11 +42,167 @@ pub async fn create_db_pool(db_url: &str, migrate: bool) -> Result<Pool<Sqlite>,
     .await?;
     if migrate {
         println!("Running database migrations");
-        sqlx::migrate!("./migrations").run(&db).await?;
+        // Use a single connection since every connection to a temporary database is separate
+        let mut conn = db.acquire().await?;
+        sqlx::migrate!("./migrations").run(&mut *conn).await?;
+        backfill_etags(&mut conn).await?;
+        backfill_addressobject_dates(&mut conn).await?;
+        backfill_calendarobject_occurences(&mut conn).await?;
+        backfill_calendarobject_references_timezones(&mut conn).await?;
     }
     Ok(db)
 }
 
+/// Fills in the etags of address objects stored before etags were persisted
+async fn backfill_etags(conn: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+    let rows = sqlx::query!(
+        "SELECT principal, addressbook_id, id, vcf FROM addressobjects WHERE etag IS NULL"
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    for row in rows {
+        // Invalid objects keep computing their etag on read
+        let Ok(object) = AddressObject::from_vcf(row.id.to_owned(), row.vcf) else {
+            continue;
+        };
+        let etag = object.get_etag();
+        sqlx::query!(
+            "UPDATE addressobjects SET etag = ? WHERE (principal, addressbook_id, id) = (?, ?, ?)",
+            etag,
+            row.principal,
+            row.addressbook_id,
+            row.id
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Indexes the dates of address objects stored before dates were indexed
+async fn backfill_addressobject_dates(conn: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+    let rows = sqlx::query!(
+        "SELECT principal, addressbook_id, id, vcf FROM addressobjects WHERE NOT dates_indexed"
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    for row in rows {
+        // Invalid objects have no dates
+        let dates = AddressObject::from_vcf(row.id.to_owned(), row.vcf)
+            .map(|object| object.get_dates())
+            .unwrap_or_default();
+        SqliteAddressbookStore::_index_dates(
+            conn,
+            &row.principal,
+            &row.addressbook_id,
+            &row.id,
+            &dates,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Fills in the occurences of calendar objects stored before they were persisted
+async fn backfill_calendarobject_occurences(
+    conn: &mut SqliteConnection,
+) -> Result<(), sqlx::Error> {
+    let rows = sqlx::query!(
+        "SELECT principal, cal_id, id, ics FROM calendarobjects WHERE occurences IS NULL"
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    for row in rows {
+        // Invalid objects keep computing their occurences on read
+        let Ok(occurences) = CalendarObject::from_ics(row.id.to_owned(), row.ics)
+            .and_then(|object| object.format_occurences())
+        else {
+            continue;
+        };
+        sqlx::query!(
+            "UPDATE calendarobjects SET occurences = ? WHERE (principal, cal_id, id) = (?, ?, ?)",
+            occurences,
+            row.principal,
+            row.cal_id,
+            row.id
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Fills in whether calendar objects stored before reference timezones
+async fn backfill_calendarobject_references_timezones(
+    conn: &mut SqliteConnection,
+) -> Result<(), sqlx::Error> {
+    let rows = sqlx::query!(
+        "SELECT principal, cal_id, id, ics FROM calendarobjects WHERE references_timezones IS NULL"
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    for row in rows {
+        let references_timezones = references_iana_timezones(&row.ics);
+        sqlx::query!(
+            "UPDATE calendarobjects SET references_timezones = ? WHERE (principal, cal_id, id) = (?, ?, ?)",
+            references_timezones,
+            row.principal,
+            row.cal_id,
+            row.id
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Locks the usage of a principal until the end of the transaction,
+/// so that concurrent writes can't exceed the quota together
+pub(crate) async fn lock_principal_usage(
+    conn: &mut SqliteConnection,
+    principal: &str,
+) -> Result<(), sqlx::Error> {
+    // SQLite has no row locks, the first write of a transaction locks the whole database
+    sqlx::query!(
+        "INSERT INTO principal_usage (principal) VALUES (?) ON CONFLICT (principal) DO NOTHING",
+        principal
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Fails if a principal exceeds its quota after one of its objects grew from `old_size` to
+/// `new_size` bytes, which rolls back the write. Objects that don't grow are always accepted.
+pub(crate) async fn check_quota(
+    conn: &mut SqliteConnection,
+    principal: &str,
+    quota: u64,
+    old_size: u64,
+    new_size: u64,
+) -> Result<(), Error> {
+    if new_size <= old_size {
+        return Ok(());
+    }
+    let used_bytes = sqlx::query_scalar!(
+        r#"SELECT calendar_bytes + addressbook_bytes AS "used_bytes!: i64" FROM principal_usage WHERE principal = ?"#,
+        principal
+    )
+    .fetch_optional(conn)
+    .await?
+    .unwrap_or_default();
+    if used_bytes as u64 > quota {
+        return Err(rustical_store::Error::QuotaExceeded.into());
+    }
+    Ok(())
+}
+
+/// Writes a consistent snapshot of the database to a new file, also while it's in use
+pub async fn backup_db(db: &SqlitePool, path: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("VACUUM INTO ?").bind(path).execute(db).await?;
+    Ok(())
+}
+
 pub async fn create_test_db() -> Result<SqlitePool, sqlx::Error> {
     let db = SqlitePool::connect("sqlite::memory:").await?;
     sqlx::migrate!("./migrations").run(&db).await?;
@@ -0,0 +1,306 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use rustical_store::Error;
+use rustical_store::auth::user::{AppToken, PrincipalType};
+use rustical_store::auth::{AuthenticationProvider, User};
+use sqlx::{Acquire, Executor, Sqlite, SqlitePool, Transaction};
+use std::collections::HashMap;
+use tracing::instrument;
+
+struct PrincipalRow {
+    id: String,
+    displayname: Option<String>,
+    principal_type: String,
+    password: Option<String>,
+}
+
+impl From<PrincipalRow> for User {
+    fn from(value: PrincipalRow) -> Self {
+        Self {
+            id: value.id,
+            displayname: value.displayname,
+            principal_type: PrincipalType::from(value.principal_type.as_str()),
+            password: value.password.map(Into::into),
+            app_tokens: vec![],
+            memberships: vec![],
+            emails: vec![],
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SqlitePrincipalStore {
+    db: SqlitePool,
+}
+
+impl SqlitePrincipalStore {
+    pub fn new(db: SqlitePool) -> Self {
+        Self { db }
+    }
+
+    /// Returns all principals or only the one with the given id
+    async fn _get_principals<'a, A: Acquire<'a, Database = Sqlite>>(
+        acquire: A,
+        id: Option<&str>,
+    ) -> Result<Vec<User>, Error> {
+        struct ValueRow {
+            principal: String,
+            value: String,
+        }
+
+        let mut conn = acquire.acquire().await.map_err(crate::Error::from)?;
+
+        let mut principals: Vec<User> = sqlx::query_as!(
+            PrincipalRow,
+            "SELECT id, displayname, principal_type, password FROM principals
+                WHERE ? IS NULL OR id = ?
+                ORDER BY id",
+            id,
+            id
+        )
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(crate::Error::from)?
+        .into_iter()
+        .map(User::from)
+        .collect();
+        let positions: HashMap<String, usize> = principals
+            .iter()
+            .enumerate()
+            .map(|(position, principal)| (principal.id.to_owned(), position))
+            .collect();
+
+        let emails = sqlx::query_as!(
+            ValueRow,
+            "SELECT principal, email AS value FROM principal_emails
+                WHERE ? IS NULL OR principal = ?
+                ORDER BY principal, position",
+            id,
+            id
+        )
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(crate::Error::from)?;
+        // Principals inserted since they were queried are skipped
+        for ValueRow { principal, value } in emails {
+            if let Some(&position) = positions.get(&principal) {
+                principals[position].emails.push(value);
+            }
+        }
+
+        let memberships = sqlx::query_as!(
+            ValueRow,
+            "SELECT principal, member_of AS value FROM memberships
+                WHERE ? IS NULL OR principal = ?
+                ORDER BY principal, member_of",
+            id,
+            id
+        )
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(crate::Error::from)?;
+        for ValueRow { principal, value } in memberships {
+            if let Some(&position) = positions.get(&principal) {
+                principals[position].memberships.push(value);
+            }
+        }
+
+        struct AppTokenRow {
+            id: String,
+            principal: String,
+            name: String,
+            token: String,
+            created_at: Option<DateTime<Utc>>,
+        }
+        let app_tokens = sqlx::query_as!(
+            AppTokenRow,
+            r#"SELECT id, principal, name, token, created_at AS "created_at: DateTime<Utc>"
+                FROM app_tokens
+                WHERE ? IS NULL OR principal = ?
+                ORDER BY created_at"#,
+            id,
+            id
+        )
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(crate::Error::from)?;
+        for row in app_tokens {
+            if let Some(&position) = positions.get(&row.principal) {
+                principals[position].app_tokens.push(AppToken {
+                    id: row.id,
+                    name: row.name,
+                    token: row.token.into(),
+                    created_at: row.created_at,
+                });
+            }
+        }
+
+        Ok(principals)
+    }
+
+    async fn _insert_principal(
+        tx: &mut Transaction<'_, Sqlite>,
+        user: User,
+        overwrite: bool,
+    ) -> Result<(), Error> {
+        if overwrite {
+            Self::_remove_principal(&mut **tx, &user.id).await?;
+        }
+
+        let principal_type = user.principal_type.as_str();
+        let password = user.password.map(|password| password.0);
+        // If the principal already exists a database error is thrown and handled in error.rs
+        sqlx::query!(
+            "INSERT INTO principals (id, displayname, principal_type, password) VALUES (?, ?, ?, ?)",
+            user.id,
+            user.displayname,
+            principal_type,
+            password
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(crate::Error::from)?;
+
+        for (position, email) in user.emails.iter().enumerate() {
+            let position = position as i64;
+            sqlx::query!(
+                "INSERT INTO principal_emails (principal, position, email) VALUES (?, ?, ?)",
+                user.id,
+                position,
+                email
+            )
+            .execute(&mut **tx)
+            .await
+            .map_err(crate::Error::from)?;
+        }
+
+        for member_of in &user.memberships {
+            sqlx::query!(
+                "INSERT OR IGNORE INTO memberships (principal, member_of) VALUES (?, ?)",
+                user.id,
+                member_of
+            )
+            .execute(&mut **tx)
+            .await
+            .map_err(crate::Error::from)?;
+        }
+
+        for app_token in user.app_tokens {
+            Self::_insert_app_token(&mut **tx, &user.id, app_token).await?;
+        }
+        Ok(())
+    }
+
+    async fn _remove_principal<'e, E: Executor<'e, Database = Sqlite>>(
+        executor: E,
+        id: &str,
+    ) -> Result<(), Error> {
+        sqlx::query!("DELETE FROM principals WHERE id = ?", id)
+            .execute(executor)
+            .await
+            .map_err(crate::Error::from)?;
+        Ok(())
+    }
+
+    async fn _insert_app_token<'e, E: Executor<'e, Database = Sqlite>>(
+        executor: E,
+        principal: &str,
+        app_token: AppToken,
+    ) -> Result<(), Error> {
+        let token = app_token.token.0;
+        sqlx::query!(
+            "INSERT INTO app_tokens (id, principal, name, token, created_at) VALUES (?, ?, ?, ?, ?)",
+            app_token.id,
+            principal,
+            app_token.name,
+            token,
+            app_token.created_at
+        )
+        .execute(executor)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl AuthenticationProvider for SqlitePrincipalStore {
+    #[instrument]
+    async fn get_principals(&self) -> Result<Vec<User>, Error> {
+        Self::_get_principals(&self.db, None).await
+    }
+
+    #[instrument]
+    async fn get_principal(&self, id: &str) -> Result<Option<User>, Error> {
+        Ok(Self::_get_principals(&self.db, Some(id))
+            .await?
+            .into_iter()
+            .next())
+    }
+
+    #[instrument]
+    async fn remove_principal(&self, id: &str) -> Result<(), Error> {
+        Self::_remove_principal(&self.db, id).await
+    }
+
+    #[instrument(skip(user), fields(user.id = user.id))]
+    async fn insert_principal(&self, user: User, overwrite: bool) -> Result<(), Error> {
+        let mut tx = self.db.begin().await.map_err(crate::Error::from)?;
+        Self::_insert_principal(&mut tx, user, overwrite).await?;
+        tx.commit().await.map_err(crate::Error::from)?;
+        Ok(())
+    }
+
+    #[instrument(skip(password))]
+    async fn validate_password(
+        &self,
+        user_id: &str,
+        password: &str,
+    ) -> Result<Option<User>, Error> {
+        Ok(self
+            .get_principal(user_id)
+            .await?
+            .filter(|user| user.verify_password(password)))
+    }
+
+    #[instrument(skip(token))]
+    async fn validate_app_token(&self, user_id: &str, token: &str) -> Result<Option<User>, Error> {
+        Ok(self
+            .get_principal(user_id)
+            .await?
+            .filter(|user| user.verify_app_token(token)))
+    }
+
+    #[instrument(skip(token))]
+    async fn add_app_token(
+        &self,
+        user_id: &str,
+        name: String,
+        token: String,
+    ) -> Result<String, Error> {
+        let mut tx = self.db.begin().await.map_err(crate::Error::from)?;
+        sqlx::query!("SELECT id FROM principals WHERE id = ?", user_id)
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(crate::Error::from)?;
+
+        let app_token = AppToken::new(name, &token)?;
+        let id = app_token.id.to_owned();
+        Self::_insert_app_token(&mut *tx, user_id, app_token).await?;
+        tx.commit().await.map_err(crate::Error::from)?;
+        Ok(id)
+    }
+
+    #[instrument]
+    async fn remove_app_token(&self, user_id: &str, token_id: &str) -> Result<(), Error> {
+        sqlx::query!(
+            "DELETE FROM app_tokens WHERE (principal, id) = (?, ?)",
+            user_id,
+            token_id
+        )
+        .execute(&self.db)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,553 @@
+use crate::collection::{ChangeOperation, Collection};
+use crate::{CollectionKind, Vdir, default_push_topic};
+use async_trait::async_trait;
+use chrono::{NaiveDateTime, Utc};
+use derive_more::derive::Constructor;
+use rustical_store::synctoken::format_synctoken;
+use rustical_store::{
+    AddressObject, Addressbook, AddressbookStore, BirthdayCalendarSettings, Error, ObjectRevision,
+};
+use rustical_store::{CollectionOperation, CollectionOperationDomain, CollectionOperationType};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::mpsc::Sender;
+use tracing::{error, instrument, warn};
+
+const BIRTHDAY_SETTINGS_FILE: &str = "birthday_settings.toml";
+
+/// Addressbook properties without a vdir sidecar file
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct AddressbookMeta {
+    description: Option<String>,
+    deleted_at: Option<NaiveDateTime>,
+    push_topic: Option<String>,
+}
+
+impl From<&Addressbook> for AddressbookMeta {
+    fn from(value: &Addressbook) -> Self {
+        Self {
+            description: value.description.to_owned(),
+            deleted_at: value.deleted_at,
+            push_topic: Some(value.push_topic.to_owned()),
+        }
+    }
+}
+
+#[derive(Debug, Constructor)]
+pub struct VdirAddressbookStore {
+    vdir: Arc<Vdir>,
+    sender: Sender<CollectionOperation>,
+}
+
+impl VdirAddressbookStore {
+    fn collection(&self, principal: &str, id: &str) -> Result<Collection, crate::Error> {
+        self.vdir
+            .collection(CollectionKind::Addressbook, principal, id)
+    }
+
+    async fn _get_addressbook(
+        &self,
+        principal: &str,
+        id: &str,
+    ) -> Result<Addressbook, crate::Error> {
+        let collection = self.collection(principal, id)?;
+        let meta: AddressbookMeta = collection.read_meta().await?.unwrap_or_default();
+        let (created_at, updated_at) = collection.timestamps().await?;
+        Ok(Addressbook {
+            id: id.to_owned(),
+            principal: principal.to_owned(),
+            displayname: collection.read_sidecar("displayname").await?,
+            description: meta.description,
+            created_at,
+            updated_at,
+            deleted_at: meta.deleted_at,
+            synctoken: collection.synctoken().await?,
+            push_topic: meta
+                .push_topic
+                .unwrap_or_else(|| default_push_topic(CollectionKind::Addressbook, principal, id)),
+        })
+    }
+
+    async fn _get_all_addressbooks(
+        &self,
+        principal: &str,
+    ) -> Result<Vec<Addressbook>, crate::Error> {
+        let mut addressbooks = vec![];
+        for id in self
+            .vdir
+            .list_collections(CollectionKind::Addressbook, principal)
+            .await?
+        {
+            addressbooks.push(self._get_addressbook(principal, &id).await?);
+        }
+        Ok(addressbooks)
+    }
+
+    async fn write_addressbook(
+        collection: &Collection,
+        addressbook: &Addressbook,
+    ) -> Result<(), crate::Error> {
+        collection
+            .write_sidecar("displayname", addressbook.displayname.as_deref())
+            .await?;
+        collection
+            .write_meta(&AddressbookMeta::from(addressbook))
+            .await
+    }
+
+    fn send_push_notification(
+        &self,
+        r#type: CollectionOperationType,
+        topic: String,
+        synctoken: Option<i64>,
+    ) {
+        if let Err(err) = self.sender.try_send(CollectionOperation {
+            r#type,
+            domain: CollectionOperationDomain::Addressbook,
+            topic,
+            sync_token: synctoken.map(format_synctoken),
+        }) {
+            error!("Push notification about addressbook failed: {err}");
+        };
+    }
+}
+
+#[async_trait]
+impl AddressbookStore for VdirAddressbookStore {
+    #[instrument]
+    async fn get_addressbook(&self, principal: &str, id: &str) -> Result<Addressbook, Error> {
+        Ok(self._get_addressbook(principal, id).await?)
+    }
+
+    #[instrument]
+    async fn get_addressbooks(&self, principal: &str) -> Result<Vec<Addressbook>, Error> {
+        Ok(self
+            ._get_all_addressbooks(principal)
+            .await?
+            .into_iter()
+            .filter(|addressbook| addressbook.deleted_at.is_none())
+            .collect())
+    }
+
+    #[instrument]
+    async fn get_deleted_addressbooks(&self, principal: &str) -> Result<Vec<Addressbook>, Error> {
+        Ok(self
+            ._get_all_addressbooks(principal)
+            .await?
+            .into_iter()
+            .filter(|addressbook| addressbook.deleted_at.is_some())
+            .collect())
+    }
+
+    #[instrument]
+    async fn update_addressbook(
+        &self,
+        principal: String,
+        id: String,
+        addressbook: Addressbook,
+    ) -> Result<(), Error> {
+        let collection = self.collection(&principal, &id)?;
+        let _guard = self.vdir.lock.lock().await;
+        let old_meta: AddressbookMeta = collection.read_meta().await?.unwrap_or_default();
+
+        let target = self.collection(&addressbook.principal, &addressbook.id)?;
+        if target.path() != collection.path() {
+            collection.rename(&target).await?;
+        }
+        Self::write_addressbook(
+            &target,
+            &Addressbook {
+                // Not updated through this method
+                deleted_at: old_meta.deleted_at,
+                ..addressbook
+            },
+        )
+        .await?;
+        Ok(())
+    }
+
+    #[instrument]
+    async fn insert_addressbook(&self, addressbook: Addressbook) -> Result<(), Error> {
+        let collection = self.collection(&addressbook.principal, &addressbook.id)?;
+        let _guard = self.vdir.lock.lock().await;
+        collection.create().await?;
+        Self::write_addressbook(
+            &collection,
+            &Addressbook {
+                deleted_at: None,
+                ..addressbook
+            },
+        )
+        .await?;
+        Ok(())
+    }
+
+    #[instrument]
+    async fn delete_addressbook(
+        &self,
+        principal: &str,
+        id: &str,
+        use_trashbin: bool,
+    ) -> Result<(), Error> {
+        let collection = self.collection(principal, id)?;
+        let addressbook = {
+            let _guard = self.vdir.lock.lock().await;
+            let addressbook = match self._get_addressbook(principal, id).await {
+                Ok(addressbook) => addressbook,
+                Err(crate::Error::StoreError(Error::NotFound)) => return Ok(()),
+                Err(err) => return Err(err.into()),
+            };
+            if use_trashbin {
+                let mut meta: AddressbookMeta = collection.read_meta().await?.unwrap_or_default();
+                meta.deleted_at = Some(Utc::now().naive_utc());
+                collection.write_meta(&meta).await?;
+            } else {
+                collection.remove().await?;
+            }
+            addressbook
+        };
+
+        self.send_push_notification(
+            CollectionOperationType::Delete,
+            addressbook.push_topic,
+            None,
+        );
+        Ok(())
+    }
+
+    #[instrument]
+    async fn restore_addressbook(&self, principal: &str, id: &str) -> Result<(), Error> {
+        let collection = self.collection(principal, id)?;
+        let _guard = self.vdir.lock.lock().await;
+        let mut meta: AddressbookMeta = collection.read_meta().await?.unwrap_or_default();
+        meta.deleted_at = None;
+        collection.write_meta(&meta).await?;
+        Ok(())
+    }
+
+    #[instrument]
+    async fn sync_changes(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+        synctoken: i64,
+        limit: Option<usize>,
+    ) -> Result<(Vec<AddressObject>, Vec<String>, i64, bool), Error> {
+        let (changes, new_synctoken, truncated) = self
+            .collection(principal, addressbook_id)?
+            .changes_since(synctoken, limit)
+            .await?;
+
+        let mut objects = vec![];
+        let mut deleted_objects = vec![];
+        for object_id in changes {
+            match self.get_object(principal, addressbook_id, &object_id).await {
+                Ok(object) => objects.push(object),
+                Err(Error::NotFound) => deleted_objects.push(object_id),
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok((objects, deleted_objects, new_synctoken, truncated))
+    }
+
+    #[instrument]
+    async fn get_objects(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+    ) -> Result<Vec<AddressObject>, Error> {
+        let collection = self.collection(principal, addressbook_id)?;
+        let mut objects = vec![];
+        for object_id in collection.list_objects().await? {
+            let vcf = collection.read_object(&object_id).await?;
+            let updated_at = collection.object_modified_at(&object_id).await?;
+            match AddressObject::from_vcf(object_id, vcf) {
+                Ok(object) => objects.push(object.with_timestamps(None, Some(updated_at))),
+                // Objects edited on disk might be invalid, don't let them break the whole addressbook
+                Err(err) => warn!("Skipping invalid object in {addressbook_id}: {err}"),
+            }
+        }
+        Ok(objects)
+    }
+
+    #[instrument]
+    async fn get_object(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+        object_id: &str,
+    ) -> Result<AddressObject, Error> {
+        let collection = self.collection(principal, addressbook_id)?;
+        let vcf = collection.read_object(object_id).await?;
+        let updated_at = collection.object_modified_at(object_id).await?;
+        Ok(AddressObject::from_vcf(object_id.to_owned(), vcf)?
+            .with_timestamps(None, Some(updated_at)))
+    }
+
+    #[instrument]
+    async fn put_object(
+        &self,
+        principal: String,
+        addressbook_id: String,
+        object: AddressObject,
+        overwrite: bool,
+        changed_by: Option<&str>,
+    ) -> Result<(), Error> {
+        let collection = self.collection(&principal, &addressbook_id)?;
+        let synctoken = {
+            let _guard = self.vdir.lock.lock().await;
+            // Make sure that the addressbook exists
+            collection.read_meta::<AddressbookMeta>().await?;
+            collection
+                .write_object(object.get_id(), object.get_vcf(), overwrite)
+                .await?;
+            let synctoken = collection
+                .log_change(
+                    ChangeOperation::Add,
+                    object.get_id(),
+                    Some(object.get_etag()),
+                )
+                .await?;
+            collection
+                .add_revision(object.get_id(), synctoken, changed_by, object.get_vcf())
+                .await?;
+            synctoken
+        };
+
+        self.send_push_notification(
+            CollectionOperationType::Object,
+            self.get_addressbook(&principal, &addressbook_id)
+                .await?
+                .push_topic,
+            Some(synctoken),
+        );
+        Ok(())
+    }
+
+    #[instrument]
+    async fn delete_object(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+        object_id: &str,
+        use_trashbin: bool,
+    ) -> Result<(), Error> {
+        let collection = self.collection(principal, addressbook_id)?;
+        let synctoken = {
+            let _guard = self.vdir.lock.lock().await;
+            collection.delete_object(object_id, use_trashbin).await?;
+            collection
+                .log_change(ChangeOperation::Delete, object_id, None)
+                .await?
+        };
+
+        self.send_push_notification(
+            CollectionOperationType::Object,
+            self.get_addressbook(principal, addressbook_id)
+                .await?
+                .push_topic,
+            Some(synctoken),
+        );
+        Ok(())
+    }
+
+    #[instrument]
+    async fn restore_object(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+        object_id: &str,
+    ) -> Result<(), Error> {
+        let collection = self.collection(principal, addressbook_id)?;
+        let synctoken = {
+            let _guard = self.vdir.lock.lock().await;
+            collection.restore_object(object_id).await?;
+            let vcf = collection.read_object(object_id).await?;
+            let etag = AddressObject::from_vcf(object_id.to_owned(), vcf)?.get_etag();
+            collection
+                .log_change(ChangeOperation::Add, object_id, Some(etag))
+                .await?
+        };
+
+        self.send_push_notification(
+            CollectionOperationType::Object,
+            self.get_addressbook(principal, addressbook_id)
+                .await?
+                .push_topic,
+            Some(synctoken),
+        );
+        Ok(())
+    }
+
+    #[instrument]
+    async fn get_deleted_objects(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+    ) -> Result<Vec<AddressObject>, Error> {
+        let collection = self.collection(principal, addressbook_id)?;
+        let mut objects = vec![];
+        for (object_id, _) in collection.list_deleted_objects().await? {
+            let vcf = collection.read_deleted_object(&object_id).await?;
+            match AddressObject::from_vcf(object_id, vcf) {
+                Ok(object) => objects.push(object),
+                Err(err) => warn!("Skipping invalid deleted object in {addressbook_id}: {err}"),
+            }
+        }
+        Ok(objects)
+    }
+
+    #[instrument]
+    async fn get_used_bytes(&self, principal: &str) -> Result<u64, Error> {
+        let mut used_bytes = 0;
+        for id in self
+            .vdir
+            .list_collections(CollectionKind::Addressbook, principal)
+            .await?
+        {
+            used_bytes += self.collection(principal, &id)?.used_bytes().await?;
+        }
+        Ok(used_bytes)
+    }
+
+    #[instrument]
+    async fn purge_trashbin(&self, deleted_before: NaiveDateTime) -> Result<usize, Error> {
+        let _guard = self.vdir.lock.lock().await;
+        let mut count = 0;
+        for principal in self.vdir.list_principals().await? {
+            for id in self
+                .vdir
+                .list_collections(CollectionKind::Addressbook, &principal)
+                .await?
+            {
+                let collection = self.collection(&principal, &id)?;
+                let meta: AddressbookMeta = collection.read_meta().await?.unwrap_or_default();
+                if meta
+                    .deleted_at
+                    .is_some_and(|deleted_at| deleted_at < deleted_before)
+                {
+                    collection.remove().await?;
+                    count += 1;
+                } else {
+                    count += collection.purge_trash(deleted_before).await?;
+                }
+            }
+        }
+        Ok(count)
+    }
+
+    #[instrument]
+    async fn compact_changelog(&self, horizon: NaiveDateTime) -> Result<usize, Error> {
+        let _guard = self.vdir.lock.lock().await;
+        let mut count = 0;
+        for principal in self.vdir.list_principals().await? {
+            for id in self
+                .vdir
+                .list_collections(CollectionKind::Addressbook, &principal)
+                .await?
+            {
+                count += self
+                    .collection(&principal, &id)?
+                    .compact_changelog(horizon)
+                    .await?;
+            }
+        }
+        Ok(count)
+    }
+
+    #[instrument]
+    async fn get_object_revisions(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+        object_id: &str,
+    ) -> Result<Vec<ObjectRevision<AddressObject>>, Error> {
+        let revisions = self
+            .collection(principal, addressbook_id)?
+            .read_revisions(object_id)
+            .await?;
+        revisions
+            .into_iter()
+            .rev()
+            .map(|entry| {
+                Ok(ObjectRevision {
+                    revision: entry.revision,
+                    created_at: entry.created_at,
+                    changed_by: entry.changed_by,
+                    object: AddressObject::from_vcf(object_id.to_owned(), entry.content)?,
+                })
+            })
+            .collect()
+    }
+
+    #[instrument]
+    async fn get_birthday_settings(
+        &self,
+        principal: &str,
+    ) -> Result<BirthdayCalendarSettings, Error> {
+        let path = self
+            .vdir
+            .principal_path(principal)?
+            .join(BIRTHDAY_SETTINGS_FILE);
+        match tokio::fs::read_to_string(path).await {
+            Ok(settings) => Ok(toml::from_str(&settings).map_err(crate::Error::from)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                Ok(BirthdayCalendarSettings::default())
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    #[instrument]
+    async fn update_birthday_settings(
+        &self,
+        principal: &str,
+        settings: BirthdayCalendarSettings,
+    ) -> Result<(), Error> {
+        settings.validate()?;
+        if self.get_birthday_settings(principal).await? == settings {
+            return Ok(());
+        }
+        let addressbooks = self.get_addressbooks(principal).await?;
+        let path = self.vdir.principal_path(principal)?;
+        let mut notifications = vec![];
+        {
+            let _guard = self.vdir.lock.lock().await;
+            tokio::fs::create_dir_all(&path).await?;
+            tokio::fs::write(
+                path.join(BIRTHDAY_SETTINGS_FILE),
+                toml::to_string(&settings).map_err(crate::Error::from)?,
+            )
+            .await?;
+
+            // Every contact is rendered differently in the birthday calendars now
+            for addressbook in addressbooks {
+                let collection = self.collection(principal, &addressbook.id)?;
+                let mut synctoken = None;
+                for object_id in collection.list_objects().await? {
+                    let vcf = collection.read_object(&object_id).await?;
+                    let etag = AddressObject::from_vcf(object_id.to_owned(), vcf)?.get_etag();
+                    synctoken = Some(
+                        collection
+                            .log_change(ChangeOperation::Add, &object_id, Some(etag))
+                            .await?,
+                    );
+                }
+                if let Some(synctoken) = synctoken {
+                    notifications.push((addressbook.push_topic, synctoken));
+                }
+            }
+        }
+
+        for (topic, synctoken) in notifications {
+            self.send_push_notification(CollectionOperationType::Object, topic, Some(synctoken));
+        }
+        Ok(())
+    }
+
+    fn is_read_only(&self) -> bool {
+        false
+    }
+}
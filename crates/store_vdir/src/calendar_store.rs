@@ -0,0 +1,544 @@
+use crate::collection::{ChangeOperation, Collection};
+use crate::{CollectionKind, Vdir, default_push_topic};
+use async_trait::async_trait;
+use chrono::{NaiveDateTime, Utc};
+use derive_more::derive::Constructor;
+use futures_util::stream::{self, StreamExt, TryStreamExt};
+use rustical_store::calendar::CalendarObjectType;
+use rustical_store::calendar_store::{CalendarObjectStream, CalendarQuery};
+use rustical_store::synctoken::format_synctoken;
+use rustical_store::{Calendar, CalendarObject, CalendarStore, Error, ObjectRevision};
+use rustical_store::{CollectionOperation, CollectionOperationDomain, CollectionOperationType};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::mpsc::Sender;
+use tracing::{error, instrument, warn};
+
+/// Calendar properties without a vdir sidecar file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct CalendarMeta {
+    order: i64,
+    description: Option<String>,
+    timezone: Option<String>,
+    timezone_id: Option<String>,
+    deleted_at: Option<NaiveDateTime>,
+    subscription_url: Option<String>,
+    push_topic: Option<String>,
+    components: Vec<CalendarObjectType>,
+}
+
+impl Default for CalendarMeta {
+    fn default() -> Self {
+        Self {
+            order: 0,
+            description: None,
+            timezone: None,
+            timezone_id: None,
+            deleted_at: None,
+            subscription_url: None,
+            push_topic: None,
+            components: vec![
+                CalendarObjectType::Event,
+                CalendarObjectType::Todo,
+                CalendarObjectType::Journal,
+            ],
+        }
+    }
+}
+
+impl From<&Calendar> for CalendarMeta {
+    fn from(value: &Calendar) -> Self {
+        Self {
+            order: value.order,
+            description: value.description.to_owned(),
+            timezone: value.timezone.to_owned(),
+            timezone_id: value.timezone_id.to_owned(),
+            deleted_at: value.deleted_at,
+            subscription_url: value.subscription_url.to_owned(),
+            push_topic: Some(value.push_topic.to_owned()),
+            components: value.components.to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Constructor)]
+pub struct VdirCalendarStore {
+    vdir: Arc<Vdir>,
+    sender: Sender<CollectionOperation>,
+}
+
+impl VdirCalendarStore {
+    fn collection(&self, principal: &str, id: &str) -> Result<Collection, crate::Error> {
+        self.vdir
+            .collection(CollectionKind::Calendar, principal, id)
+    }
+
+    async fn _get_calendar(&self, principal: &str, id: &str) -> Result<Calendar, crate::Error> {
+        let collection = self.collection(principal, id)?;
+        let meta: CalendarMeta = collection.read_meta().await?.unwrap_or_default();
+        let (created_at, updated_at) = collection.timestamps().await?;
+        Ok(Calendar {
+            principal: principal.to_owned(),
+            id: id.to_owned(),
+            displayname: collection.read_sidecar("displayname").await?,
+            order: meta.order,
+            description: meta.description,
+            color: collection.read_sidecar("color").await?,
+            timezone: meta.timezone,
+            timezone_id: meta.timezone_id,
+            created_at,
+            updated_at,
+            deleted_at: meta.deleted_at,
+            synctoken: collection.synctoken().await?,
+            subscription_url: meta.subscription_url,
+            push_topic: meta
+                .push_topic
+                .unwrap_or_else(|| default_push_topic(CollectionKind::Calendar, principal, id)),
+            components: meta.components,
+        })
+    }
+
+    async fn _get_all_calendars(&self, principal: &str) -> Result<Vec<Calendar>, crate::Error> {
+        let mut calendars = vec![];
+        for id in self
+            .vdir
+            .list_collections(CollectionKind::Calendar, principal)
+            .await?
+        {
+            calendars.push(self._get_calendar(principal, &id).await?);
+        }
+        Ok(calendars)
+    }
+
+    async fn write_calendar(
+        collection: &Collection,
+        calendar: &Calendar,
+    ) -> Result<(), crate::Error> {
+        collection
+            .write_sidecar("displayname", calendar.displayname.as_deref())
+            .await?;
+        collection
+            .write_sidecar("color", calendar.color.as_deref())
+            .await?;
+        collection.write_meta(&CalendarMeta::from(calendar)).await
+    }
+
+    fn send_push_notification(
+        &self,
+        r#type: CollectionOperationType,
+        topic: String,
+        synctoken: Option<i64>,
+    ) {
+        if let Err(err) = self.sender.try_send(CollectionOperation {
+            r#type,
+            domain: CollectionOperationDomain::Calendar,
+            topic,
+            sync_token: synctoken.map(format_synctoken),
+        }) {
+            error!("Push notification about calendar failed: {err}");
+        };
+    }
+}
+
+#[async_trait]
+impl CalendarStore for VdirCalendarStore {
+    #[instrument]
+    async fn get_calendar(&self, principal: &str, id: &str) -> Result<Calendar, Error> {
+        Ok(self._get_calendar(principal, id).await?)
+    }
+
+    #[instrument]
+    async fn get_calendars(&self, principal: &str) -> Result<Vec<Calendar>, Error> {
+        Ok(self
+            ._get_all_calendars(principal)
+            .await?
+            .into_iter()
+            .filter(|cal| cal.deleted_at.is_none())
+            .collect())
+    }
+
+    #[instrument]
+    async fn get_deleted_calendars(&self, principal: &str) -> Result<Vec<Calendar>, Error> {
+        Ok(self
+            ._get_all_calendars(principal)
+            .await?
+            .into_iter()
+            .filter(|cal| cal.deleted_at.is_some())
+            .collect())
+    }
+
+    #[instrument]
+    async fn insert_calendar(&self, calendar: Calendar) -> Result<(), Error> {
+        let collection = self.collection(&calendar.principal, &calendar.id)?;
+        let _guard = self.vdir.lock.lock().await;
+        collection.create().await?;
+        Self::write_calendar(
+            &collection,
+            &Calendar {
+                deleted_at: None,
+                ..calendar
+            },
+        )
+        .await?;
+        Ok(())
+    }
+
+    #[instrument]
+    async fn update_calendar(
+        &self,
+        principal: String,
+        id: String,
+        calendar: Calendar,
+    ) -> Result<(), Error> {
+        let collection = self.collection(&principal, &id)?;
+        let _guard = self.vdir.lock.lock().await;
+        let old_meta: CalendarMeta = collection.read_meta().await?.unwrap_or_default();
+
+        let target = self.collection(&calendar.principal, &calendar.id)?;
+        if target.path() != collection.path() {
+            collection.rename(&target).await?;
+        }
+        Self::write_calendar(
+            &target,
+            &Calendar {
+                // Not updated through this method
+                deleted_at: old_meta.deleted_at,
+                subscription_url: old_meta.subscription_url,
+                ..calendar
+            },
+        )
+        .await?;
+        Ok(())
+    }
+
+    #[instrument]
+    async fn delete_calendar(
+        &self,
+        principal: &str,
+        id: &str,
+        use_trashbin: bool,
+    ) -> Result<(), Error> {
+        let collection = self.collection(principal, id)?;
+        let cal = {
+            let _guard = self.vdir.lock.lock().await;
+            let cal = match self._get_calendar(principal, id).await {
+                Ok(cal) => cal,
+                Err(crate::Error::StoreError(Error::NotFound)) => return Ok(()),
+                Err(err) => return Err(err.into()),
+            };
+            if use_trashbin {
+                let mut meta: CalendarMeta = collection.read_meta().await?.unwrap_or_default();
+                meta.deleted_at = Some(Utc::now().naive_utc());
+                collection.write_meta(&meta).await?;
+            } else {
+                collection.remove().await?;
+            }
+            cal
+        };
+
+        self.send_push_notification(CollectionOperationType::Delete, cal.push_topic, None);
+        Ok(())
+    }
+
+    #[instrument]
+    async fn restore_calendar(&self, principal: &str, id: &str) -> Result<(), Error> {
+        let collection = self.collection(principal, id)?;
+        let _guard = self.vdir.lock.lock().await;
+        let mut meta: CalendarMeta = collection.read_meta().await?.unwrap_or_default();
+        meta.deleted_at = None;
+        collection.write_meta(&meta).await?;
+        Ok(())
+    }
+
+    #[instrument]
+    async fn get_objects(
+        &self,
+        principal: &str,
+        cal_id: &str,
+    ) -> Result<Vec<CalendarObject>, Error> {
+        self.get_objects_stream(principal, cal_id)
+            .await?
+            .try_collect()
+            .await
+    }
+
+    #[instrument]
+    async fn get_objects_stream(
+        &self,
+        principal: &str,
+        cal_id: &str,
+    ) -> Result<CalendarObjectStream, Error> {
+        let collection = self.collection(principal, cal_id)?;
+        let object_ids = collection.list_objects().await?;
+        let cal_id = cal_id.to_owned();
+        Ok(stream::iter(object_ids)
+            .filter_map(move |object_id| {
+                let collection = collection.clone();
+                let cal_id = cal_id.clone();
+                async move {
+                    let (ics, updated_at) = match collection.read_object(&object_id).await {
+                        Ok(ics) => match collection.object_modified_at(&object_id).await {
+                            Ok(updated_at) => (ics, updated_at),
+                            Err(err) => return Some(Err(err.into())),
+                        },
+                        Err(err) => return Some(Err(err.into())),
+                    };
+                    match CalendarObject::from_ics(object_id, ics) {
+                        Ok(object) => Some(Ok(object.with_timestamps(None, Some(updated_at)))),
+                        // Objects edited on disk might be invalid, don't let them break the whole calendar
+                        Err(err) => {
+                            warn!("Skipping invalid object in {cal_id}: {err}");
+                            None
+                        }
+                    }
+                }
+            })
+            .boxed())
+    }
+
+    #[instrument]
+    async fn calendar_query_stream(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        _query: CalendarQuery,
+    ) -> Result<CalendarObjectStream, Error> {
+        // There's no index to prefilter with
+        self.get_objects_stream(principal, cal_id).await
+    }
+
+    #[instrument]
+    async fn get_object(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        object_id: &str,
+    ) -> Result<CalendarObject, Error> {
+        let collection = self.collection(principal, cal_id)?;
+        let ics = collection.read_object(object_id).await?;
+        let updated_at = collection.object_modified_at(object_id).await?;
+        Ok(CalendarObject::from_ics(object_id.to_owned(), ics)?
+            .with_timestamps(None, Some(updated_at)))
+    }
+
+    #[instrument]
+    async fn put_object(
+        &self,
+        principal: String,
+        cal_id: String,
+        object: CalendarObject,
+        overwrite: bool,
+        changed_by: Option<&str>,
+    ) -> Result<(), Error> {
+        let collection = self.collection(&principal, &cal_id)?;
+        let synctoken = {
+            let _guard = self.vdir.lock.lock().await;
+            // Make sure that the calendar exists
+            collection.read_meta::<CalendarMeta>().await?;
+            collection
+                .write_object(object.get_id(), object.get_ics(), overwrite)
+                .await?;
+            let synctoken = collection
+                .log_change(
+                    ChangeOperation::Add,
+                    object.get_id(),
+                    Some(object.get_etag()),
+                )
+                .await?;
+            collection
+                .add_revision(object.get_id(), synctoken, changed_by, object.get_ics())
+                .await?;
+            synctoken
+        };
+
+        self.send_push_notification(
+            CollectionOperationType::Object,
+            self.get_calendar(&principal, &cal_id).await?.push_topic,
+            Some(synctoken),
+        );
+        Ok(())
+    }
+
+    #[instrument]
+    async fn delete_object(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        id: &str,
+        use_trashbin: bool,
+    ) -> Result<(), Error> {
+        let collection = self.collection(principal, cal_id)?;
+        let synctoken = {
+            let _guard = self.vdir.lock.lock().await;
+            collection.delete_object(id, use_trashbin).await?;
+            collection
+                .log_change(ChangeOperation::Delete, id, None)
+                .await?
+        };
+
+        self.send_push_notification(
+            CollectionOperationType::Object,
+            self.get_calendar(principal, cal_id).await?.push_topic,
+            Some(synctoken),
+        );
+        Ok(())
+    }
+
+    #[instrument]
+    async fn restore_object(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        object_id: &str,
+    ) -> Result<(), Error> {
+        let collection = self.collection(principal, cal_id)?;
+        let synctoken = {
+            let _guard = self.vdir.lock.lock().await;
+            collection.restore_object(object_id).await?;
+            let ics = collection.read_object(object_id).await?;
+            let etag = CalendarObject::from_ics(object_id.to_owned(), ics)?.get_etag();
+            collection
+                .log_change(ChangeOperation::Add, object_id, Some(etag))
+                .await?
+        };
+
+        self.send_push_notification(
+            CollectionOperationType::Object,
+            self.get_calendar(principal, cal_id).await?.push_topic,
+            Some(synctoken),
+        );
+        Ok(())
+    }
+
+    #[instrument]
+    async fn get_deleted_objects(
+        &self,
+        principal: &str,
+        cal_id: &str,
+    ) -> Result<Vec<CalendarObject>, Error> {
+        let collection = self.collection(principal, cal_id)?;
+        let mut objects = vec![];
+        for (object_id, _) in collection.list_deleted_objects().await? {
+            let ics = collection.read_deleted_object(&object_id).await?;
+            match CalendarObject::from_ics(object_id, ics) {
+                Ok(object) => objects.push(object),
+                Err(err) => warn!("Skipping invalid deleted object in {cal_id}: {err}"),
+            }
+        }
+        Ok(objects)
+    }
+
+    #[instrument]
+    async fn get_used_bytes(&self, principal: &str) -> Result<u64, Error> {
+        let mut used_bytes = 0;
+        for id in self
+            .vdir
+            .list_collections(CollectionKind::Calendar, principal)
+            .await?
+        {
+            used_bytes += self.collection(principal, &id)?.used_bytes().await?;
+        }
+        Ok(used_bytes)
+    }
+
+    #[instrument]
+    async fn purge_trashbin(&self, deleted_before: NaiveDateTime) -> Result<usize, Error> {
+        let _guard = self.vdir.lock.lock().await;
+        let mut count = 0;
+        for principal in self.vdir.list_principals().await? {
+            for id in self
+                .vdir
+                .list_collections(CollectionKind::Calendar, &principal)
+                .await?
+            {
+                let collection = self.collection(&principal, &id)?;
+                let meta: CalendarMeta = collection.read_meta().await?.unwrap_or_default();
+                if meta
+                    .deleted_at
+                    .is_some_and(|deleted_at| deleted_at < deleted_before)
+                {
+                    collection.remove().await?;
+                    count += 1;
+                } else {
+                    count += collection.purge_trash(deleted_before).await?;
+                }
+            }
+        }
+        Ok(count)
+    }
+
+    #[instrument]
+    async fn compact_changelog(&self, horizon: NaiveDateTime) -> Result<usize, Error> {
+        let _guard = self.vdir.lock.lock().await;
+        let mut count = 0;
+        for principal in self.vdir.list_principals().await? {
+            for id in self
+                .vdir
+                .list_collections(CollectionKind::Calendar, &principal)
+                .await?
+            {
+                count += self
+                    .collection(&principal, &id)?
+                    .compact_changelog(horizon)
+                    .await?;
+            }
+        }
+        Ok(count)
+    }
+
+    #[instrument]
+    async fn get_object_revisions(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        object_id: &str,
+    ) -> Result<Vec<ObjectRevision<CalendarObject>>, Error> {
+        let revisions = self
+            .collection(principal, cal_id)?
+            .read_revisions(object_id)
+            .await?;
+        revisions
+            .into_iter()
+            .rev()
+            .map(|entry| {
+                Ok(ObjectRevision {
+                    revision: entry.revision,
+                    created_at: entry.created_at,
+                    changed_by: entry.changed_by,
+                    object: CalendarObject::from_ics(object_id.to_owned(), entry.content)?,
+                })
+            })
+            .collect()
+    }
+
+    #[instrument]
+    async fn sync_changes(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        synctoken: i64,
+        limit: Option<usize>,
+    ) -> Result<(Vec<CalendarObject>, Vec<String>, i64, bool), Error> {
+        let (changes, new_synctoken, truncated) = self
+            .collection(principal, cal_id)?
+            .changes_since(synctoken, limit)
+            .await?;
+
+        let mut objects = vec![];
+        let mut deleted_objects = vec![];
+        for object_id in changes {
+            match self.get_object(principal, cal_id, &object_id).await {
+                Ok(object) => objects.push(object),
+                Err(Error::NotFound) => deleted_objects.push(object_id),
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok((objects, deleted_objects, new_synctoken, truncated))
+    }
+
+    fn is_read_only(&self) -> bool {
+        false
+    }
+}
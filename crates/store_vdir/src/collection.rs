@@ -0,0 +1,647 @@
+use crate::Error;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use rustical_store::revision::MAX_REVISIONS;
+use rustical_store::synctoken::{resolve_synctoken, truncate_changes};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+use tokio::fs;
+
+/// Rustical-specific collection properties that have no vdir sidecar file
+const META_FILE: &str = ".rustical.toml";
+const CHANGELOG_FILE: &str = ".changelog";
+/// Contains the synctoken of the latest change so that writes don't have to read the changelog
+const SYNCTOKEN_FILE: &str = ".synctoken";
+/// Contains the oldest synctoken that's still valid after compacting the changelog
+const MIN_SYNCTOKEN_FILE: &str = ".min_synctoken";
+const TRASH_DIR: &str = ".trash";
+/// Contains a `{object_id}.toml` file with the most recent revisions of every object
+const REVISIONS_DIR: &str = ".revisions";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ChangeOperation {
+    // There's no distinction between Add and Modify
+    Add,
+    Delete,
+}
+
+/// A line of the changelog: `{synctoken}\t{operation}\t{object_id}\t{etag}\t{created_at}`
+/// with `created_at` as a unix timestamp
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ChangelogEntry {
+    pub synctoken: i64,
+    pub operation: ChangeOperation,
+    pub object_id: String,
+    /// Only set for additions, used to detect changes made on disk
+    pub etag: Option<String>,
+    /// Missing in changelogs written by older versions
+    pub created_at: Option<NaiveDateTime>,
+}
+
+impl ChangelogEntry {
+    fn parse(line: &str) -> Option<Self> {
+        let mut parts = line.split('\t');
+        let synctoken = parts.next()?.parse().ok()?;
+        let operation = match parts.next()? {
+            "add" => ChangeOperation::Add,
+            "delete" => ChangeOperation::Delete,
+            _ => return None,
+        };
+        let object_id = parts.next()?.to_owned();
+        let etag = parts
+            .next()
+            .filter(|etag| !etag.is_empty())
+            .map(str::to_owned);
+        let created_at = parts
+            .next()
+            .and_then(|timestamp| timestamp.parse().ok())
+            .and_then(|timestamp| DateTime::from_timestamp(timestamp, 0))
+            .map(|created_at| created_at.naive_utc());
+        Some(Self {
+            synctoken,
+            operation,
+            object_id,
+            etag,
+            created_at,
+        })
+    }
+
+    fn format(&self) -> String {
+        let operation = match self.operation {
+            ChangeOperation::Add => "add",
+            ChangeOperation::Delete => "delete",
+        };
+        let created_at = self
+            .created_at
+            .map(|created_at| created_at.and_utc().timestamp().to_string())
+            .unwrap_or_default();
+        format!(
+            "{}\t{operation}\t{}\t{}\t{created_at}\n",
+            self.synctoken,
+            self.object_id,
+            self.etag.as_deref().unwrap_or_default()
+        )
+    }
+}
+
+/// Object and collection ids are used as file names so they must not escape the collection
+pub(crate) fn check_id(id: &str) -> Result<(), Error> {
+    if id.is_empty() || id.starts_with('.') || id.contains(['/', '\\', '\0', '\t', '\n']) {
+        return Err(rustical_store::Error::InvalidData(format!("Invalid id: {id}")).into());
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct RevisionEntry {
+    pub revision: i64,
+    pub created_at: NaiveDateTime,
+    pub changed_by: Option<String>,
+    pub content: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct RevisionFile {
+    /// Oldest first
+    revisions: Vec<RevisionEntry>,
+}
+
+fn naive_utc(time: SystemTime) -> NaiveDateTime {
+    DateTime::<Utc>::from(time).naive_utc()
+}
+
+/// Writes to a temporary file first so that readers never see partially written files
+async fn write_atomic(path: &Path, content: &str) -> Result<(), Error> {
+    let file_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    let tmp_path = path.with_file_name(format!(".{file_name}.tmp"));
+    fs::write(&tmp_path, content).await?;
+    fs::rename(&tmp_path, path).await?;
+    Ok(())
+}
+
+/// A collection directory following the vdir layout
+/// (https://vdirsyncer.pimutils.org/en/stable/vdir.html):
+/// One file per object named `{object_id}.{extension}`, metadata in sidecar files
+#[derive(Debug, Clone)]
+pub(crate) struct Collection {
+    path: PathBuf,
+    extension: &'static str,
+}
+
+impl Collection {
+    pub fn new(path: PathBuf, extension: &'static str) -> Self {
+        Self { path, extension }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub async fn create(&self) -> Result<(), Error> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        // Fails with AlreadyExists if the collection exists
+        fs::create_dir(&self.path).await?;
+        Ok(())
+    }
+
+    pub async fn remove(&self) -> Result<(), Error> {
+        fs::remove_dir_all(&self.path).await?;
+        Ok(())
+    }
+
+    pub async fn rename(&self, target: &Collection) -> Result<(), Error> {
+        if fs::try_exists(&target.path).await? {
+            return Err(rustical_store::Error::AlreadyExists.into());
+        }
+        if let Some(parent) = target.path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::rename(&self.path, &target.path).await?;
+        Ok(())
+    }
+
+    /// Reads a sidecar file like `displayname` or `color`
+    pub async fn read_sidecar(&self, name: &str) -> Result<Option<String>, Error> {
+        match fs::read_to_string(self.path.join(name)).await {
+            Ok(value) => Ok(Some(value.trim_end_matches(['\r', '\n']).to_owned())),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Writes a sidecar file, removing it if the value is None
+    pub async fn write_sidecar(&self, name: &str, value: Option<&str>) -> Result<(), Error> {
+        let path = self.path.join(name);
+        match value {
+            Some(value) => write_atomic(&path, value).await,
+            None => match fs::remove_file(&path).await {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+                _ => Ok(()),
+            },
+        }
+    }
+
+    /// Returns None if the collection has no metadata yet, e.g. because it was created by another tool
+    pub async fn read_meta<M: DeserializeOwned>(&self) -> Result<Option<M>, Error> {
+        if !fs::try_exists(&self.path).await? {
+            return Err(rustical_store::Error::NotFound.into());
+        }
+        match fs::read_to_string(self.path.join(META_FILE)).await {
+            Ok(meta) => Ok(Some(toml::from_str(&meta)?)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub async fn write_meta<M: Serialize>(&self, meta: &M) -> Result<(), Error> {
+        write_atomic(&self.path.join(META_FILE), &toml::to_string(meta)?).await
+    }
+
+    /// Creation and modification time of the collection directory.
+    /// Files are only written by renaming them into the directory, so every change updates it.
+    pub async fn timestamps(
+        &self,
+    ) -> Result<(Option<NaiveDateTime>, Option<NaiveDateTime>), Error> {
+        let metadata = fs::metadata(&self.path).await?;
+        Ok((
+            metadata.created().ok().map(naive_utc),
+            metadata.modified().ok().map(naive_utc),
+        ))
+    }
+
+    fn object_path(&self, object_id: &str) -> PathBuf {
+        self.path.join(format!("{object_id}.{}", self.extension))
+    }
+
+    fn trash_path(&self, object_id: &str) -> PathBuf {
+        self.path
+            .join(TRASH_DIR)
+            .join(format!("{object_id}.{}", self.extension))
+    }
+
+    /// Returns the object id if the file name belongs to an object of this collection
+    pub fn object_id_from_file_name<'a>(&self, file_name: &'a str) -> Option<&'a str> {
+        let object_id = file_name.strip_suffix(self.extension)?.strip_suffix('.')?;
+        check_id(object_id).ok()?;
+        Some(object_id)
+    }
+
+    pub async fn read_object(&self, object_id: &str) -> Result<String, Error> {
+        check_id(object_id)?;
+        Ok(fs::read_to_string(self.object_path(object_id)).await?)
+    }
+
+    /// Objects are replaced on write, so only their modification time is known
+    pub async fn object_modified_at(&self, object_id: &str) -> Result<NaiveDateTime, Error> {
+        check_id(object_id)?;
+        let metadata = fs::metadata(self.object_path(object_id)).await?;
+        Ok(naive_utc(metadata.modified()?))
+    }
+
+    /// Returns the ids of all objects that are not in the trashbin
+    pub async fn list_objects(&self) -> Result<Vec<String>, Error> {
+        let mut entries = fs::read_dir(&self.path).await?;
+        let mut object_ids = vec![];
+        while let Some(entry) = entries.next_entry().await? {
+            if !entry.file_type().await?.is_file() {
+                continue;
+            }
+            if let Some(object_id) = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| self.object_id_from_file_name(name))
+            {
+                object_ids.push(object_id.to_owned());
+            }
+        }
+        object_ids.sort();
+        Ok(object_ids)
+    }
+
+    /// Total size of the objects that are not in the trashbin
+    pub async fn used_bytes(&self) -> Result<u64, Error> {
+        let mut used_bytes = 0;
+        for object_id in self.list_objects().await? {
+            used_bytes += fs::metadata(self.object_path(&object_id)).await?.len();
+        }
+        Ok(used_bytes)
+    }
+
+    pub async fn write_object(
+        &self,
+        object_id: &str,
+        content: &str,
+        overwrite: bool,
+    ) -> Result<(), Error> {
+        check_id(object_id)?;
+        let path = self.object_path(object_id);
+        if !overwrite && fs::try_exists(&path).await? {
+            return Err(rustical_store::Error::AlreadyExists.into());
+        }
+        write_atomic(&path, content).await?;
+        // A new version supersedes a trashed one
+        match fs::remove_file(self.trash_path(object_id)).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
+    pub async fn delete_object(&self, object_id: &str, use_trashbin: bool) -> Result<(), Error> {
+        check_id(object_id)?;
+        let path = self.object_path(object_id);
+        if use_trashbin {
+            let trash_path = self.trash_path(object_id);
+            fs::create_dir_all(self.path.join(TRASH_DIR)).await?;
+            fs::rename(path, &trash_path).await?;
+            // The modification time of a trashed file is the time it was deleted
+            fs::OpenOptions::new()
+                .write(true)
+                .open(&trash_path)
+                .await?
+                .into_std()
+                .await
+                .set_modified(SystemTime::now())?;
+        } else {
+            match fs::remove_file(path).await {
+                // Delete the object from the trashbin instead
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                    fs::remove_file(self.trash_path(object_id)).await?;
+                }
+                result => result?,
+            }
+            self.delete_revisions(object_id).await?;
+        }
+        Ok(())
+    }
+
+    /// Returns the ids of all objects in the trashbin with the time they were deleted
+    pub async fn list_deleted_objects(&self) -> Result<Vec<(String, NaiveDateTime)>, Error> {
+        let mut entries = match fs::read_dir(self.path.join(TRASH_DIR)).await {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(err.into()),
+        };
+        let mut objects = vec![];
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            if !metadata.is_file() {
+                continue;
+            }
+            if let Some(object_id) = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| self.object_id_from_file_name(name))
+            {
+                let deleted_at = naive_utc(metadata.modified()?);
+                objects.push((object_id.to_owned(), deleted_at));
+            }
+        }
+        objects.sort();
+        Ok(objects)
+    }
+
+    pub async fn read_deleted_object(&self, object_id: &str) -> Result<String, Error> {
+        check_id(object_id)?;
+        Ok(fs::read_to_string(self.trash_path(object_id)).await?)
+    }
+
+    fn revisions_path(&self, object_id: &str) -> PathBuf {
+        self.path
+            .join(REVISIONS_DIR)
+            .join(format!("{object_id}.toml"))
+    }
+
+    /// Returns the recorded revisions of an object, oldest first
+    pub async fn read_revisions(&self, object_id: &str) -> Result<Vec<RevisionEntry>, Error> {
+        check_id(object_id)?;
+        match fs::read_to_string(self.revisions_path(object_id)).await {
+            Ok(content) => Ok(toml::from_str::<RevisionFile>(&content)?.revisions),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Records a revision and discards the oldest ones.
+    /// The caller must hold the store's write lock
+    pub async fn add_revision(
+        &self,
+        object_id: &str,
+        revision: i64,
+        changed_by: Option<&str>,
+        content: &str,
+    ) -> Result<(), Error> {
+        let mut revisions = self.read_revisions(object_id).await?;
+        revisions.push(RevisionEntry {
+            revision,
+            created_at: Utc::now().naive_utc(),
+            changed_by: changed_by.map(str::to_owned),
+            content: content.to_owned(),
+        });
+        if revisions.len() > MAX_REVISIONS {
+            revisions.drain(..revisions.len() - MAX_REVISIONS);
+        }
+        fs::create_dir_all(self.path.join(REVISIONS_DIR)).await?;
+        write_atomic(
+            &self.revisions_path(object_id),
+            &toml::to_string(&RevisionFile { revisions })?,
+        )
+        .await
+    }
+
+    async fn delete_revisions(&self, object_id: &str) -> Result<(), Error> {
+        match fs::remove_file(self.revisions_path(object_id)).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Permanently deletes objects that were moved to the trashbin before `deleted_before`
+    /// and returns how many were deleted.
+    /// The caller must hold the store's write lock
+    pub async fn purge_trash(&self, deleted_before: NaiveDateTime) -> Result<usize, Error> {
+        let mut count = 0;
+        for (object_id, deleted_at) in self.list_deleted_objects().await? {
+            if deleted_at < deleted_before {
+                fs::remove_file(self.trash_path(&object_id)).await?;
+                self.delete_revisions(&object_id).await?;
+                count += 1;
+            }
+        }
+        self.prune_changelog().await?;
+        Ok(count)
+    }
+
+    /// Removes all but the latest changelog entry of objects that no longer exist,
+    /// which is sufficient to report their deletion.
+    /// The caller must hold the store's write lock
+    async fn prune_changelog(&self) -> Result<(), Error> {
+        let mut existing: HashSet<String> = self.list_objects().await?.into_iter().collect();
+        existing.extend(
+            self.list_deleted_objects()
+                .await?
+                .into_iter()
+                .map(|(object_id, _)| object_id),
+        );
+        let changelog = self.read_changelog().await?;
+        let mut seen = HashSet::new();
+        let mut pruned: Vec<_> = changelog
+            .iter()
+            .rev()
+            .filter(|entry| {
+                existing.contains(&entry.object_id) || seen.insert(entry.object_id.to_owned())
+            })
+            .collect();
+        if pruned.len() == changelog.len() {
+            return Ok(());
+        }
+        pruned.reverse();
+        let content: String = pruned.into_iter().map(ChangelogEntry::format).collect();
+        write_atomic(&self.path.join(CHANGELOG_FILE), &content).await
+    }
+
+    pub async fn restore_object(&self, object_id: &str) -> Result<(), Error> {
+        check_id(object_id)?;
+        let path = self.object_path(object_id);
+        if fs::try_exists(&path).await? {
+            return Err(rustical_store::Error::AlreadyExists.into());
+        }
+        fs::rename(self.trash_path(object_id), path).await?;
+        Ok(())
+    }
+
+    pub async fn read_changelog(&self) -> Result<Vec<ChangelogEntry>, Error> {
+        match fs::read_to_string(self.path.join(CHANGELOG_FILE)).await {
+            Ok(changelog) => Ok(changelog
+                .lines()
+                .filter_map(ChangelogEntry::parse)
+                .collect()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                if !fs::try_exists(&self.path).await? {
+                    return Err(rustical_store::Error::NotFound.into());
+                }
+                Ok(vec![])
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// The synctoken is the one of the latest change,
+    /// which might have been compacted out of the changelog
+    pub async fn synctoken(&self) -> Result<i64, Error> {
+        if let Some(synctoken) = self
+            .read_sidecar(SYNCTOKEN_FILE)
+            .await?
+            .and_then(|synctoken| synctoken.parse().ok())
+        {
+            return Ok(synctoken);
+        }
+        // Collections written by older versions only have the changelog
+        let last_change = self
+            .read_changelog()
+            .await?
+            .last()
+            .map(|entry| entry.synctoken)
+            .unwrap_or_default();
+        Ok(last_change.max(self.min_synctoken().await?))
+    }
+
+    pub async fn min_synctoken(&self) -> Result<i64, Error> {
+        Ok(self
+            .read_sidecar(MIN_SYNCTOKEN_FILE)
+            .await?
+            .and_then(|min_synctoken| min_synctoken.parse().ok())
+            .unwrap_or_default())
+    }
+
+    /// Collapses the changelog of every object to its latest change and drops changes
+    /// before `horizon`, except for the latest change of objects that still exist.
+    /// Changes without a timestamp are treated as older than the horizon.
+    /// Returns the number of dropped changes.
+    /// The caller must hold the store's write lock
+    pub async fn compact_changelog(&self, horizon: NaiveDateTime) -> Result<usize, Error> {
+        let is_old = |entry: &ChangelogEntry| entry.created_at.is_none_or(|at| at < horizon);
+        let existing: HashSet<String> = self.list_objects().await?.into_iter().collect();
+        let changelog = self.read_changelog().await?;
+
+        let min_synctoken = self.min_synctoken().await?;
+        let new_min_synctoken = changelog
+            .iter()
+            .filter(|entry| is_old(entry))
+            .map(|entry| entry.synctoken)
+            .fold(min_synctoken, i64::max);
+        if new_min_synctoken != min_synctoken {
+            self.write_sidecar(MIN_SYNCTOKEN_FILE, Some(&new_min_synctoken.to_string()))
+                .await?;
+        }
+
+        let mut seen = HashSet::new();
+        let mut compacted: Vec<_> = changelog
+            .iter()
+            .rev()
+            .filter(|entry| {
+                seen.insert(entry.object_id.to_owned())
+                    && (!is_old(entry) || existing.contains(&entry.object_id))
+            })
+            .collect();
+        let count = changelog.len() - compacted.len();
+        if count == 0 {
+            return Ok(0);
+        }
+        compacted.reverse();
+        let content: String = compacted.into_iter().map(ChangelogEntry::format).collect();
+        write_atomic(&self.path.join(CHANGELOG_FILE), &content).await?;
+        Ok(count)
+    }
+
+    /// Appends an entry to the changelog and returns the new synctoken
+    /// The caller must hold the store's write lock
+    pub async fn log_change(
+        &self,
+        operation: ChangeOperation,
+        object_id: &str,
+        etag: Option<String>,
+    ) -> Result<i64, Error> {
+        use tokio::io::AsyncWriteExt;
+
+        let entry = ChangelogEntry {
+            synctoken: self.synctoken().await? + 1,
+            operation,
+            object_id: object_id.to_owned(),
+            etag,
+            created_at: Some(Utc::now().naive_utc()),
+        };
+        // Written first so that a failed append leaves a gap instead of reusing the synctoken
+        self.write_sidecar(SYNCTOKEN_FILE, Some(&entry.synctoken.to_string()))
+            .await?;
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path.join(CHANGELOG_FILE))
+            .await?;
+        file.write_all(entry.format().as_bytes()).await?;
+        file.flush().await?;
+        Ok(entry.synctoken)
+    }
+
+    /// Returns the ids of changed objects since the synctoken ordered by their latest change,
+    /// the new synctoken and whether the changes were truncated to `limit`
+    pub async fn changes_since(
+        &self,
+        synctoken: i64,
+        limit: Option<usize>,
+    ) -> Result<(Vec<String>, i64, bool), Error> {
+        let min_synctoken = self.min_synctoken().await?;
+        let (synctoken, initial) = resolve_synctoken(synctoken, min_synctoken)?;
+        let changelog = self.read_changelog().await?;
+        // Taken from the changelog as read, the synctoken sidecar might already be ahead of it
+        let new_synctoken = changelog
+            .last()
+            .map(|entry| entry.synctoken)
+            .unwrap_or_default()
+            .max(min_synctoken)
+            .max(synctoken);
+
+        let mut latest_changes = HashMap::new();
+        for entry in changelog
+            .into_iter()
+            .filter(|entry| entry.synctoken > synctoken)
+        {
+            latest_changes.insert(entry.object_id, entry.synctoken);
+        }
+        let mut changes: Vec<_> = latest_changes.into_iter().collect();
+        changes.sort_by_key(|(_, synctoken)| *synctoken);
+        let continuation =
+            truncate_changes(&mut changes, |(_, synctoken)| *synctoken, limit, initial);
+        Ok((
+            changes
+                .into_iter()
+                .map(|(object_id, _)| object_id)
+                .collect(),
+            continuation.unwrap_or(new_synctoken),
+            continuation.is_some(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChangeOperation, ChangelogEntry, check_id};
+    use chrono::DateTime;
+
+    #[test]
+    fn test_changelog_entry() {
+        let entry = ChangelogEntry {
+            synctoken: 3,
+            operation: ChangeOperation::Add,
+            object_id: "event".to_owned(),
+            etag: Some("abc".to_owned()),
+            created_at: DateTime::from_timestamp(1700000000, 0).map(|at| at.naive_utc()),
+        };
+        assert_eq!(entry.format(), "3\tadd\tevent\tabc\t1700000000\n");
+        assert_eq!(
+            ChangelogEntry::parse(entry.format().trim_end()),
+            Some(entry)
+        );
+
+        let entry = ChangelogEntry::parse("4\tdelete\tevent\t").unwrap();
+        assert_eq!(entry.operation, ChangeOperation::Delete);
+        assert_eq!(entry.etag, None);
+        assert_eq!(entry.created_at, None);
+    }
+
+    #[test]
+    fn test_check_id() {
+        assert!(check_id("event").is_ok());
+        assert!(check_id("").is_err());
+        assert!(check_id(".changelog").is_err());
+        assert!(check_id("../calendar").is_err());
+    }
+}
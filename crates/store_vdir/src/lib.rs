@@ -0,0 +1,239 @@
+use collection::{ChangeOperation, ChangelogEntry, Collection, check_id};
+use rustical_store::{AddressObject, CalendarObject, CollectionOperationDomain};
+use sha2::{Digest, Sha256};
+use std::{collections::BTreeMap, path::PathBuf};
+use tokio::sync::Mutex;
+use tracing::warn;
+
+pub mod addressbook_store;
+pub mod calendar_store;
+mod collection;
+pub mod error;
+pub mod subscription_store;
+pub mod watcher;
+
+pub use error::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CollectionKind {
+    Calendar,
+    Addressbook,
+}
+
+impl CollectionKind {
+    fn dir_name(&self) -> &'static str {
+        match self {
+            Self::Calendar => "calendars",
+            Self::Addressbook => "addressbooks",
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            Self::Calendar => "ics",
+            Self::Addressbook => "vcf",
+        }
+    }
+
+    pub(crate) fn domain(&self) -> CollectionOperationDomain {
+        match self {
+            Self::Calendar => CollectionOperationDomain::Calendar,
+            Self::Addressbook => CollectionOperationDomain::Addressbook,
+        }
+    }
+
+    /// Parses an object to make sure it's valid and returns its etag
+    fn etag(&self, object_id: &str, content: String) -> Result<String, rustical_store::Error> {
+        Ok(match self {
+            Self::Calendar => CalendarObject::from_ics(object_id.to_owned(), content)?.get_etag(),
+            Self::Addressbook => AddressObject::from_vcf(object_id.to_owned(), content)?.get_etag(),
+        })
+    }
+}
+
+/// Collections created by other programs have no stored push topic so we derive a stable one
+pub(crate) fn default_push_topic(kind: CollectionKind, principal: &str, id: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(kind.dir_name());
+    hasher.update(principal);
+    hasher.update(id);
+    format!("{:x}", hasher.finalize())
+}
+
+/// A directory tree of vdir collections:
+/// `{root}/{principal}/calendars/{cal_id}/{object_id}.ics` and
+/// `{root}/{principal}/addressbooks/{addressbook_id}/{object_id}.vcf`
+#[derive(Debug)]
+pub struct Vdir {
+    root: PathBuf,
+    // Serializes writes so that synctokens stay consistent
+    lock: Mutex<()>,
+}
+
+impl Vdir {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn principal_path(&self, principal: &str) -> Result<PathBuf, Error> {
+        check_id(principal)?;
+        Ok(self.root.join(principal))
+    }
+
+    fn collections_path(&self, kind: CollectionKind, principal: &str) -> Result<PathBuf, Error> {
+        Ok(self.principal_path(principal)?.join(kind.dir_name()))
+    }
+
+    pub(crate) fn collection(
+        &self,
+        kind: CollectionKind,
+        principal: &str,
+        id: &str,
+    ) -> Result<Collection, Error> {
+        check_id(id)?;
+        Ok(Collection::new(
+            self.collections_path(kind, principal)?.join(id),
+            kind.extension(),
+        ))
+    }
+
+    /// Returns the ids of all principals with a directory
+    pub(crate) async fn list_principals(&self) -> Result<Vec<String>, Error> {
+        let mut entries = match tokio::fs::read_dir(&self.root).await {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(err.into()),
+        };
+        let mut principals = vec![];
+        while let Some(entry) = entries.next_entry().await? {
+            if !entry.file_type().await?.is_dir() {
+                continue;
+            }
+            if let Some(principal) = entry.file_name().to_str()
+                && check_id(principal).is_ok()
+            {
+                principals.push(principal.to_owned());
+            }
+        }
+        principals.sort();
+        Ok(principals)
+    }
+
+    /// Returns the ids of all collections of a principal
+    pub(crate) async fn list_collections(
+        &self,
+        kind: CollectionKind,
+        principal: &str,
+    ) -> Result<Vec<String>, Error> {
+        let mut entries = match tokio::fs::read_dir(self.collections_path(kind, principal)?).await {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(err.into()),
+        };
+        let mut ids = vec![];
+        while let Some(entry) = entries.next_entry().await? {
+            if !entry.file_type().await?.is_dir() {
+                continue;
+            }
+            if let Some(id) = entry.file_name().to_str()
+                && check_id(id).is_ok()
+            {
+                ids.push(id.to_owned());
+            }
+        }
+        ids.sort();
+        Ok(ids)
+    }
+
+    /// Compares an object on disk with its latest changelog entry and logs a change
+    /// if it was modified by another program.
+    /// The caller must hold the write lock.
+    async fn reconcile(
+        &self,
+        kind: CollectionKind,
+        collection: &Collection,
+        object_id: &str,
+        last_change: Option<&ChangelogEntry>,
+    ) -> Result<Option<i64>, Error> {
+        let etag = match collection.read_object(object_id).await {
+            Ok(content) => match kind.etag(object_id, content) {
+                Ok(etag) => Some(etag),
+                Err(err) => {
+                    warn!(
+                        "Ignoring invalid object {object_id} in {}: {err}",
+                        collection.path().display()
+                    );
+                    return Ok(None);
+                }
+            },
+            Err(Error::StoreError(rustical_store::Error::NotFound)) => None,
+            Err(err) => return Err(err),
+        };
+
+        let (operation, etag) = match (etag, last_change) {
+            (Some(etag), Some(last_change)) if last_change.etag.as_ref() == Some(&etag) => {
+                return Ok(None);
+            }
+            (Some(etag), _) => (ChangeOperation::Add, Some(etag)),
+            (None, Some(last_change)) if last_change.operation == ChangeOperation::Add => {
+                (ChangeOperation::Delete, None)
+            }
+            (None, _) => return Ok(None),
+        };
+        Ok(Some(
+            collection.log_change(operation, object_id, etag).await?,
+        ))
+    }
+
+    /// Logs a change if an object was modified on disk by another program.
+    /// Returns the new synctoken if a change was logged.
+    pub(crate) async fn reconcile_object(
+        &self,
+        kind: CollectionKind,
+        collection: &Collection,
+        object_id: &str,
+    ) -> Result<Option<i64>, Error> {
+        let _guard = self.lock.lock().await;
+        let last_change = collection
+            .read_changelog()
+            .await?
+            .into_iter()
+            .rev()
+            .find(|entry| entry.object_id == object_id);
+        self.reconcile(kind, collection, object_id, last_change.as_ref())
+            .await
+    }
+
+    /// Reconciles all objects of a collection, including objects that have been deleted on disk.
+    /// Returns the new synctoken if any changes were logged.
+    pub(crate) async fn reconcile_collection(
+        &self,
+        kind: CollectionKind,
+        collection: &Collection,
+    ) -> Result<Option<i64>, Error> {
+        let _guard = self.lock.lock().await;
+        let mut last_changes: BTreeMap<String, Option<ChangelogEntry>> = collection
+            .list_objects()
+            .await?
+            .into_iter()
+            .map(|object_id| (object_id, None))
+            .collect();
+        for entry in collection.read_changelog().await? {
+            last_changes.insert(entry.object_id.to_owned(), Some(entry));
+        }
+
+        let mut synctoken = None;
+        for (object_id, last_change) in last_changes {
+            if let Some(new_synctoken) = self
+                .reconcile(kind, collection, &object_id, last_change.as_ref())
+                .await?
+            {
+                synctoken = Some(new_synctoken);
+            }
+        }
+        Ok(synctoken)
+    }
+}
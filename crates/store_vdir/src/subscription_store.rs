@@ -0,0 +1,108 @@
+use crate::Vdir;
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use derive_more::derive::Constructor;
+use rustical_store::{Error, Subscription, SubscriptionStore};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+const SUBSCRIPTIONS_FILE: &str = ".subscriptions.toml";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SubscriptionRow {
+    id: String,
+    topic: String,
+    expiration: NaiveDateTime,
+    push_resource: String,
+}
+
+impl From<SubscriptionRow> for Subscription {
+    fn from(value: SubscriptionRow) -> Self {
+        Self {
+            id: value.id,
+            topic: value.topic,
+            expiration: value.expiration,
+            push_resource: value.push_resource,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Subscriptions {
+    #[serde(default)]
+    subscription: Vec<SubscriptionRow>,
+}
+
+/// Stores DAV Push subscriptions in a file at the root of the vdir
+#[derive(Debug, Constructor)]
+pub struct VdirStore {
+    vdir: Arc<Vdir>,
+}
+
+impl VdirStore {
+    async fn read_subscriptions(&self) -> Result<Subscriptions, crate::Error> {
+        match tokio::fs::read_to_string(self.vdir.root.join(SUBSCRIPTIONS_FILE)).await {
+            Ok(subscriptions) => Ok(toml::from_str(&subscriptions)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Subscriptions::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn write_subscriptions(&self, subscriptions: &Subscriptions) -> Result<(), crate::Error> {
+        tokio::fs::create_dir_all(&self.vdir.root).await?;
+        tokio::fs::write(
+            self.vdir.root.join(SUBSCRIPTIONS_FILE),
+            toml::to_string(subscriptions)?,
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SubscriptionStore for VdirStore {
+    async fn get_subscriptions(&self, topic: &str) -> Result<Vec<Subscription>, Error> {
+        Ok(self
+            .read_subscriptions()
+            .await?
+            .subscription
+            .into_iter()
+            .filter(|sub| sub.topic == topic)
+            .map(Subscription::from)
+            .collect())
+    }
+
+    async fn get_subscription(&self, id: &str) -> Result<Subscription, Error> {
+        self.read_subscriptions()
+            .await?
+            .subscription
+            .into_iter()
+            .find(|sub| sub.id == id)
+            .map(Subscription::from)
+            .ok_or(Error::NotFound)
+    }
+
+    async fn upsert_subscription(&self, sub: Subscription) -> Result<bool, Error> {
+        let _guard = self.vdir.lock.lock().await;
+        let mut subscriptions = self.read_subscriptions().await?;
+        let len = subscriptions.subscription.len();
+        subscriptions.subscription.retain(|row| row.id != sub.id);
+        let existed = subscriptions.subscription.len() != len;
+        subscriptions.subscription.push(SubscriptionRow {
+            id: sub.id,
+            topic: sub.topic,
+            expiration: sub.expiration,
+            push_resource: sub.push_resource,
+        });
+        self.write_subscriptions(&subscriptions).await?;
+        Ok(existed)
+    }
+
+    async fn delete_subscription(&self, id: &str) -> Result<(), Error> {
+        let _guard = self.vdir.lock.lock().await;
+        let mut subscriptions = self.read_subscriptions().await?;
+        subscriptions.subscription.retain(|row| row.id != id);
+        self.write_subscriptions(&subscriptions).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,158 @@
+use crate::{CollectionKind, Error, Vdir, default_push_topic};
+use notify::{EventKind, RecursiveMode, Watcher};
+use rustical_store::synctoken::format_synctoken;
+use rustical_store::{CollectionOperation, CollectionOperationType};
+use serde::Deserialize;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::mpsc::Sender;
+use tracing::{error, info};
+
+#[derive(Debug, Default, Deserialize)]
+struct TopicMeta {
+    push_topic: Option<String>,
+}
+
+/// What a changed path refers to
+enum ChangedPath {
+    Collection(CollectionKind, String, String),
+    Object(CollectionKind, String, String, String),
+}
+
+impl Vdir {
+    fn parse_changed_path(&self, path: &Path) -> Option<ChangedPath> {
+        let components: Vec<&str> = path
+            .strip_prefix(&self.root)
+            .ok()?
+            .iter()
+            .map(|component| component.to_str())
+            .collect::<Option<_>>()?;
+        let (principal, kind, collection_id) = match components[..] {
+            [principal, kind, collection_id, ..] => (principal, kind, collection_id),
+            _ => return None,
+        };
+        let kind = [CollectionKind::Calendar, CollectionKind::Addressbook]
+            .into_iter()
+            .find(|candidate| candidate.dir_name() == kind)?;
+        let collection = self.collection(kind, principal, collection_id).ok()?;
+        match components[3..] {
+            [] => Some(ChangedPath::Collection(
+                kind,
+                principal.to_owned(),
+                collection_id.to_owned(),
+            )),
+            [file_name] => Some(ChangedPath::Object(
+                kind,
+                principal.to_owned(),
+                collection_id.to_owned(),
+                collection.object_id_from_file_name(file_name)?.to_owned(),
+            )),
+            _ => None,
+        }
+    }
+
+    async fn push_topic(&self, kind: CollectionKind, principal: &str, id: &str) -> String {
+        let meta = match self.collection(kind, principal, id) {
+            Ok(collection) => collection.read_meta::<TopicMeta>().await.ok().flatten(),
+            Err(_) => None,
+        };
+        meta.and_then(|meta| meta.push_topic)
+            .unwrap_or_else(|| default_push_topic(kind, principal, id))
+    }
+
+    async fn handle_change(
+        &self,
+        changed_path: ChangedPath,
+        sender: &Sender<CollectionOperation>,
+    ) -> Result<(), Error> {
+        let (kind, principal, collection_id, synctoken) = match changed_path {
+            ChangedPath::Collection(kind, principal, collection_id) => {
+                let collection = self.collection(kind, &principal, &collection_id)?;
+                let synctoken = self.reconcile_collection(kind, &collection).await?;
+                (kind, principal, collection_id, synctoken)
+            }
+            ChangedPath::Object(kind, principal, collection_id, object_id) => {
+                let collection = self.collection(kind, &principal, &collection_id)?;
+                let synctoken = self.reconcile_object(kind, &collection, &object_id).await?;
+                (kind, principal, collection_id, synctoken)
+            }
+        };
+        if let Some(synctoken) = synctoken
+            && let Err(err) = sender.try_send(CollectionOperation {
+                r#type: CollectionOperationType::Object,
+                domain: kind.domain(),
+                topic: self.push_topic(kind, &principal, &collection_id).await,
+                sync_token: Some(format_synctoken(synctoken)),
+            })
+        {
+            error!("Push notification about changed collection failed: {err}");
+        }
+        Ok(())
+    }
+
+    /// Picks up changes that were made while rustical was not running
+    async fn reconcile_all(&self, sender: &Sender<CollectionOperation>) -> Result<(), Error> {
+        let mut principals = tokio::fs::read_dir(&self.root).await?;
+        while let Some(entry) = principals.next_entry().await? {
+            let Some(principal) = entry.file_name().to_str().map(str::to_owned) else {
+                continue;
+            };
+            if principal.starts_with('.') || !entry.file_type().await?.is_dir() {
+                continue;
+            }
+            for kind in [CollectionKind::Calendar, CollectionKind::Addressbook] {
+                for collection_id in self.list_collections(kind, &principal).await? {
+                    self.handle_change(
+                        ChangedPath::Collection(kind, principal.to_owned(), collection_id),
+                        sender,
+                    )
+                    .await?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Watches the vdir for changes made by other programs (e.g. vdirsyncer or git)
+/// and logs them so that sync-collection and DAV Push keep working.
+/// Must be called from within a tokio runtime.
+pub fn watch(vdir: Arc<Vdir>, sender: Sender<CollectionOperation>) -> Result<(), Error> {
+    let (event_send, mut event_recv) = tokio::sync::mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        match event {
+            Ok(event) => {
+                // Only fails if the receiving task has stopped
+                let _ = event_send.send(event);
+            }
+            Err(err) => error!("Error watching vdir: {err}"),
+        }
+    })?;
+    std::fs::create_dir_all(&vdir.root)?;
+    watcher.watch(&vdir.root, RecursiveMode::Recursive)?;
+    info!("Watching {} for changes", vdir.root.display());
+
+    tokio::spawn(async move {
+        // The watcher stops once it's dropped
+        let _watcher = watcher;
+        if let Err(err) = vdir.reconcile_all(&sender).await {
+            error!("Error checking vdir for changes: {err}");
+        }
+        while let Some(event) = event_recv.recv().await {
+            if matches!(event.kind, EventKind::Access(_)) {
+                continue;
+            }
+            for path in event.paths {
+                let Some(changed_path) = vdir.parse_changed_path(&path) else {
+                    continue;
+                };
+                match vdir.handle_change(changed_path, &sender).await {
+                    // The collection has been removed
+                    Ok(()) | Err(Error::StoreError(rustical_store::Error::NotFound)) => {}
+                    Err(err) => error!("Error handling change of {}: {err}", path.display()),
+                }
+            }
+        }
+    });
+    Ok(())
+}
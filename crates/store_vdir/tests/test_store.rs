@@ -0,0 +1,289 @@
+use rustical_store::{
+    AddressObject, Addressbook, AddressbookStore, BirthdayCalendarSettings, Calendar,
+    CalendarObject, CalendarStore, CollectionOperation, Error,
+    testing::{test_addressbook_store, test_calendar_store, test_quota, test_subscription_store},
+};
+use rustical_store_vdir::{
+    Vdir, addressbook_store::VdirAddressbookStore, calendar_store::VdirCalendarStore,
+    subscription_store::VdirStore, watcher,
+};
+use std::{sync::Arc, time::Duration};
+use tokio::sync::mpsc::Receiver;
+
+const EVENT: &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:-//rustical//test//EN\r
+BEGIN:VEVENT\r
+UID:asd\r
+DTSTAMP:20230831T102923Z\r
+DTSTART:20230829T043000Z\r
+DTEND:20230829T045500Z\r
+SUMMARY:Test\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+
+const CONTACT: &str = "BEGIN:VCARD\r
+VERSION:4.0\r
+UID:contact\r
+FN:Alice\r
+END:VCARD\r
+";
+
+async fn cal_store(
+    root: &std::path::Path,
+) -> (Arc<Vdir>, VdirCalendarStore, Receiver<CollectionOperation>) {
+    let vdir = Arc::new(Vdir::new(root));
+    let (send, recv) = tokio::sync::mpsc::channel(100);
+    let store = VdirCalendarStore::new(vdir.clone(), send);
+    store
+        .insert_calendar(Calendar {
+            id: "test".to_owned(),
+            principal: "testuser".to_owned(),
+            displayname: Some("Test Calendar".to_owned()),
+            color: Some("#ff0000".to_owned()),
+            push_topic: "topic".to_owned(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    (vdir, store, recv)
+}
+
+#[tokio::test]
+async fn test_calendar_layout() {
+    let dir = tempfile::tempdir().unwrap();
+    let (_, store, _recv) = cal_store(dir.path()).await;
+    let object = CalendarObject::from_ics("asd".to_owned(), EVENT.to_owned()).unwrap();
+    store
+        .put_object(
+            "testuser".to_owned(),
+            "test".to_owned(),
+            object,
+            false,
+            None,
+        )
+        .await
+        .unwrap();
+
+    let cal_path = dir.path().join("testuser/calendars/test");
+    assert_eq!(
+        std::fs::read_to_string(cal_path.join("displayname")).unwrap(),
+        "Test Calendar"
+    );
+    assert_eq!(
+        std::fs::read_to_string(cal_path.join("color")).unwrap(),
+        "#ff0000"
+    );
+    assert_eq!(
+        std::fs::read_to_string(cal_path.join("asd.ics")).unwrap(),
+        EVENT
+    );
+
+    let cal = store.get_calendar("testuser", "test").await.unwrap();
+    assert_eq!(cal.displayname.as_deref(), Some("Test Calendar"));
+    assert_eq!(cal.push_topic, "topic");
+    assert_eq!(cal.synctoken, 1);
+    assert_eq!(
+        std::fs::read_to_string(cal_path.join(".synctoken")).unwrap(),
+        "1"
+    );
+    // Collections without the sidecar take the synctoken from the changelog
+    std::fs::remove_file(cal_path.join(".synctoken")).unwrap();
+    let cal = store.get_calendar("testuser", "test").await.unwrap();
+    assert_eq!(cal.synctoken, 1);
+    assert!(matches!(
+        store.get_object("testuser", "test", "../test").await,
+        Err(Error::InvalidData(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_calendar_sync_and_trashbin() {
+    let dir = tempfile::tempdir().unwrap();
+    let (_, store, _recv) = cal_store(dir.path()).await;
+    let object = CalendarObject::from_ics("asd".to_owned(), EVENT.to_owned()).unwrap();
+    store
+        .put_object(
+            "testuser".to_owned(),
+            "test".to_owned(),
+            object.clone(),
+            false,
+            None,
+        )
+        .await
+        .unwrap();
+    assert!(matches!(
+        store
+            .put_object(
+                "testuser".to_owned(),
+                "test".to_owned(),
+                object,
+                false,
+                None
+            )
+            .await,
+        Err(Error::AlreadyExists)
+    ));
+
+    let (objects, deleted, synctoken, _) = store
+        .sync_changes("testuser", "test", 0, None)
+        .await
+        .unwrap();
+    assert_eq!(objects.len(), 1);
+    assert!(deleted.is_empty());
+
+    store
+        .delete_object("testuser", "test", "asd", true)
+        .await
+        .unwrap();
+    assert!(matches!(
+        store.get_object("testuser", "test", "asd").await,
+        Err(Error::NotFound)
+    ));
+    let (objects, deleted, synctoken, _) = store
+        .sync_changes("testuser", "test", synctoken, None)
+        .await
+        .unwrap();
+    assert!(objects.is_empty());
+    assert_eq!(deleted, vec!["asd".to_owned()]);
+
+    store
+        .restore_object("testuser", "test", "asd")
+        .await
+        .unwrap();
+    let (objects, _, _, _) = store
+        .sync_changes("testuser", "test", synctoken, None)
+        .await
+        .unwrap();
+    assert_eq!(objects.len(), 1);
+
+    store
+        .delete_calendar("testuser", "test", true)
+        .await
+        .unwrap();
+    assert!(store.get_calendars("testuser").await.unwrap().is_empty());
+    assert_eq!(
+        store.get_deleted_calendars("testuser").await.unwrap().len(),
+        1
+    );
+    store.restore_calendar("testuser", "test").await.unwrap();
+    assert_eq!(store.get_calendars("testuser").await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn test_watcher() {
+    let dir = tempfile::tempdir().unwrap();
+    let (vdir, store, _recv) = cal_store(dir.path()).await;
+    let cal_path = dir.path().join("testuser/calendars/test");
+    // Created while not watching
+    std::fs::write(cal_path.join("asd.ics"), EVENT).unwrap();
+
+    let (send, mut recv) = tokio::sync::mpsc::channel(100);
+    watcher::watch(vdir, send).unwrap();
+    let operation = tokio::time::timeout(Duration::from_secs(10), recv.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(operation.topic, "topic");
+    let (objects, _, synctoken, _) = store
+        .sync_changes("testuser", "test", 0, None)
+        .await
+        .unwrap();
+    assert_eq!(objects.len(), 1);
+
+    std::fs::remove_file(cal_path.join("asd.ics")).unwrap();
+    tokio::time::timeout(Duration::from_secs(10), recv.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    let (_, deleted, _, _) = store
+        .sync_changes("testuser", "test", synctoken, None)
+        .await
+        .unwrap();
+    assert_eq!(deleted, vec!["asd".to_owned()]);
+}
+
+#[tokio::test]
+async fn test_addressbook() {
+    let dir = tempfile::tempdir().unwrap();
+    let (send, _recv) = tokio::sync::mpsc::channel(100);
+    let store = VdirAddressbookStore::new(Arc::new(Vdir::new(dir.path())), send);
+    store
+        .insert_addressbook(Addressbook {
+            id: "contacts".to_owned(),
+            principal: "testuser".to_owned(),
+            displayname: Some("Contacts".to_owned()),
+            description: None,
+            created_at: None,
+            updated_at: None,
+            deleted_at: None,
+            synctoken: 0,
+            push_topic: "topic".to_owned(),
+        })
+        .await
+        .unwrap();
+    let object = AddressObject::from_vcf("contact".to_owned(), CONTACT.to_owned()).unwrap();
+    store
+        .put_object(
+            "testuser".to_owned(),
+            "contacts".to_owned(),
+            object,
+            false,
+            None,
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        std::fs::read_to_string(
+            dir.path()
+                .join("testuser/addressbooks/contacts/contact.vcf")
+        )
+        .unwrap(),
+        CONTACT
+    );
+    assert_eq!(
+        store
+            .get_objects("testuser", "contacts")
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+
+    let settings = BirthdayCalendarSettings {
+        include_abdates: true,
+        ..Default::default()
+    };
+    store
+        .update_birthday_settings("testuser", settings.clone())
+        .await
+        .unwrap();
+    assert_eq!(
+        store.get_birthday_settings("testuser").await.unwrap(),
+        settings
+    );
+}
+
+#[tokio::test]
+async fn test_conformance() {
+    let dir = tempfile::tempdir().unwrap();
+    let vdir = Arc::new(Vdir::new(dir.path()));
+    let (send, mut recv) = tokio::sync::mpsc::channel(100);
+    test_calendar_store(
+        &VdirCalendarStore::new(vdir.clone(), send.clone()),
+        &mut recv,
+    )
+    .await;
+    test_addressbook_store(
+        &VdirAddressbookStore::new(vdir.clone(), send.clone()),
+        &mut recv,
+    )
+    .await;
+    test_quota(
+        Arc::new(VdirCalendarStore::new(vdir.clone(), send.clone())),
+        Arc::new(VdirAddressbookStore::new(vdir.clone(), send)),
+    )
+    .await;
+    test_subscription_store(&VdirStore::new(vdir)).await;
+}
@@ -126,10 +126,8 @@ impl<T: ValueSerialize> XmlSerialize for T {
         let qname = tagname.as_ref().map(|tagname| QName(tagname));
         if let Some(qname) = &qname {
             let mut bytes_start = BytesStart::from(qname.to_owned());
-            if !has_prefix {
-                if let Some(ns) = &ns {
-                    bytes_start.push_attribute((b"xmlns".as_ref(), ns.as_ref()));
-                }
+            if !has_prefix && let Some(ns) = &ns {
+                bytes_start.push_attribute((b"xmlns".as_ref(), ns.as_ref()));
             }
             writer.write_event(Event::Start(bytes_start))?;
         }
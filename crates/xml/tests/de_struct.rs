@@ -1,3 +1,5 @@
+#![allow(dead_code)]
+
 use quick_xml::name::Namespace;
 use rustical_xml::de::XmlDocument;
 use rustical_xml::{Unparsed, XmlDeserialize, XmlRootTag};
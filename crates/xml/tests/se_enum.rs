@@ -1,3 +1,5 @@
+#![allow(dead_code)]
+
 use rustical_xml::{XmlRootTag, XmlSerialize, XmlSerializeRoot};
 
 #[test]
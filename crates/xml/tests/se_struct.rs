@@ -1,11 +1,8 @@
-use std::{
-    borrow::{Borrow, Cow},
-    collections::HashMap,
-};
+use std::collections::HashMap;
 
-use quick_xml::name::Namespace;
 use quick_xml::Writer;
-use rustical_xml::{XmlDocument, XmlRootTag, XmlSerialize, XmlSerializeRoot};
+use quick_xml::name::Namespace;
+use rustical_xml::{XmlRootTag, XmlSerialize, XmlSerializeRoot};
 use xml_derive::XmlDeserialize;
 
 #[test]
@@ -31,7 +28,7 @@ fn test_struct_document() {
     }
     .serialize_root(&mut writer)
     .unwrap();
-    let out = String::from_utf8(buf).unwrap();
+    let _out = String::from_utf8(buf).unwrap();
 }
 
 #[test]
@@ -61,7 +58,7 @@ fn test_struct_untagged_attr() {
     }
     .serialize_root(&mut writer)
     .unwrap();
-    let out = String::from_utf8(buf).unwrap();
+    let _out = String::from_utf8(buf).unwrap();
 }
 
 #[test]
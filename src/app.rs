@@ -3,11 +3,13 @@ use actix_web::dev::{ServiceFactory, ServiceRequest, ServiceResponse};
 use actix_web::middleware::NormalizePath;
 use actix_web::{App, web};
 use rustical_caldav::caldav_service;
+use rustical_caldav::tzdist::tzdist_service;
 use rustical_carddav::carddav_service;
 use rustical_frontend::nextcloud_login::{NextcloudFlows, configure_nextcloud_login};
 use rustical_frontend::{FrontendConfig, configure_frontend};
 use rustical_oidc::OidcConfig;
 use rustical_store::auth::AuthenticationProvider;
+use rustical_store::quota::{Quota, QuotaConfig};
 use rustical_store::{AddressbookStore, CalendarStore, SubscriptionStore};
 use std::sync::Arc;
 use tracing_actix_web::TracingLogger;
@@ -24,6 +26,7 @@ pub fn make_app<AS: AddressbookStore, CS: CalendarStore, S: SubscriptionStore>(
     oidc_config: Option<OidcConfig>,
     nextcloud_login_config: NextcloudLoginConfig,
     nextcloud_flows_state: Arc<NextcloudFlows>,
+    quota_config: QuotaConfig,
 ) -> App<
     impl ServiceFactory<
         ServiceRequest,
@@ -33,6 +36,11 @@ pub fn make_app<AS: AddressbookStore, CS: CalendarStore, S: SubscriptionStore>(
         Error = actix_web::Error,
     >,
 > {
+    let quota = Arc::new(Quota::new(
+        quota_config,
+        cal_store.clone(),
+        addr_store.clone(),
+    ));
     let mut app = App::new()
         // .wrap(Logger::new("[%s] %r"))
         .wrap(TracingLogger::default())
@@ -42,16 +50,19 @@ pub fn make_app<AS: AddressbookStore, CS: CalendarStore, S: SubscriptionStore>(
             cal_store.clone(),
             addr_store.clone(),
             subscription_store.clone(),
+            quota.clone(),
         )))
         .service(web::scope("/carddav").service(carddav_service(
             auth_provider.clone(),
             addr_store.clone(),
             subscription_store,
+            quota.clone(),
         )))
         .service(
             web::scope("/.well-known")
                 .service(web::redirect("/caldav", "/caldav"))
-                .service(web::redirect("/carddav", "/carddav")),
+                .service(web::redirect("/carddav", "/carddav"))
+                .service(tzdist_service()),
         );
 
     if nextcloud_login_config.enabled {
@@ -72,6 +83,7 @@ pub fn make_app<AS: AddressbookStore, CS: CalendarStore, S: SubscriptionStore>(
                     auth_provider.clone(),
                     cal_store.clone(),
                     addr_store.clone(),
+                    quota,
                     frontend_config,
                     oidc_config,
                 )
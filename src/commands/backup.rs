@@ -0,0 +1,507 @@
+use crate::config::{AuthConfig, Config, DataStoreConfig};
+use crate::{get_postgres_stores, get_sqlite_stores, get_vdir_stores};
+use anyhow::{anyhow, bail};
+use clap::Parser;
+use figment::{
+    Figment,
+    providers::{Env, Format, Toml},
+};
+use rustical_ldap::LdapAuthenticationProvider;
+use rustical_store::auth::{AuthenticationProvider, TomlPrincipalStore, TomlUserStoreConfig};
+use rustical_store::memory::MemoryPrincipalStore;
+use rustical_store::{AddressbookStore, CalendarStore, CollectionOperation, Error};
+use rustical_store_vdir::Vdir;
+use rustical_store_vdir::addressbook_store::VdirAddressbookStore;
+use rustical_store_vdir::calendar_store::VdirCalendarStore;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::mpsc::Receiver;
+
+const DB_FILE: &str = "db.sqlite3";
+const PRINCIPALS_FILE: &str = "principals.toml";
+
+#[derive(Parser, Debug)]
+pub struct BackupArgs {
+    #[arg(short, long, env, default_value = "/etc/rustical/config.toml")]
+    config_file: String,
+    #[arg(
+        long,
+        help = "Export a portable archive of ICS/VCF files instead of a database snapshot"
+    )]
+    portable: bool,
+    #[arg(help = "Directory to write the backup to, must not exist yet")]
+    path: String,
+}
+
+#[derive(Parser, Debug)]
+pub struct RestoreArgs {
+    #[arg(short, long, env, default_value = "/etc/rustical/config.toml")]
+    config_file: String,
+    #[arg(
+        long,
+        help = "Import a portable archive of ICS/VCF files into the configured data store"
+    )]
+    portable: bool,
+    #[arg(long, help = "Overwrite an existing database and principals file")]
+    force: bool,
+    #[arg(long, env, help = "Do no run database migrations (only for sql store)")]
+    no_migrations: bool,
+    path: String,
+}
+
+pub(crate) fn load_config(config_file: &str) -> anyhow::Result<Config> {
+    Ok(Figment::new()
+        .merge(Toml::file(config_file))
+        .merge(Env::prefixed("RUSTICAL_").split("__"))
+        .extract()?)
+}
+
+/// File containing principals (or only app tokens for LDAP) that is not part of the data store
+fn principals_file(auth: &AuthConfig) -> Option<&str> {
+    match auth {
+        AuthConfig::Toml(auth_config) => Some(&auth_config.path),
+        AuthConfig::Sql(_) => None,
+        AuthConfig::Ldap(ldap_config) => ldap_config.app_token_path.as_deref(),
+    }
+}
+
+pub async fn cmd_backup(args: BackupArgs) -> anyhow::Result<()> {
+    let config = load_config(&args.config_file)?;
+    let path = Path::new(&args.path);
+    if args.portable {
+        fs::create_dir(path)?;
+        return transfer(&config, path, true, true).await;
+    }
+
+    let DataStoreConfig::Sqlite(store_config) = &config.data_store else {
+        bail!(
+            "Database snapshots are only supported for SQLite, use --portable to export an archive instead (or pg_dump for PostgreSQL)"
+        );
+    };
+    if !Path::new(&store_config.db_url).exists() {
+        bail!("Database {} does not exist", store_config.db_url);
+    }
+    fs::create_dir(path)?;
+    let db = rustical_store_sqlite::create_db_pool(&store_config.db_url, false).await?;
+    rustical_store_sqlite::backup_db(&db, &path.join(DB_FILE).to_string_lossy()).await?;
+    if let Some(principals_path) = principals_file(&config.auth) {
+        fs::copy(principals_path, path.join(PRINCIPALS_FILE))?;
+    }
+    println!("Backup written to {}", path.display());
+    Ok(())
+}
+
+pub async fn cmd_restore(args: RestoreArgs) -> anyhow::Result<()> {
+    let config = load_config(&args.config_file)?;
+    let path = Path::new(&args.path);
+    if args.portable {
+        return transfer(&config, path, false, !args.no_migrations).await;
+    }
+
+    let DataStoreConfig::Sqlite(store_config) = &config.data_store else {
+        bail!(
+            "Database snapshots can only be restored to SQLite, use --portable to import an archive instead"
+        );
+    };
+    println!("Make sure RustiCal is not running while restoring");
+    restore_snapshot(
+        path,
+        &store_config.db_url,
+        principals_file(&config.auth),
+        args.force,
+    )
+}
+
+/// Replaces the database and principals file with the ones from a snapshot
+fn restore_snapshot(
+    path: &Path,
+    db_url: &str,
+    principals_path: Option<&str>,
+    force: bool,
+) -> anyhow::Result<()> {
+    let mut targets = vec![(path.join(DB_FILE), db_url)];
+    if let Some(principals_path) = principals_path
+        && path.join(PRINCIPALS_FILE).exists()
+    {
+        targets.push((path.join(PRINCIPALS_FILE), principals_path));
+    }
+    for (source, target) in &targets {
+        if !source.exists() {
+            bail!("{} does not exist", source.display());
+        }
+        if Path::new(target).exists() && !force {
+            bail!("{target} already exists, use --force to overwrite it");
+        }
+    }
+
+    // Nothing is touched until every file has been copied next to its target
+    let mut copies = vec![];
+    for (source, target) in &targets {
+        let copy = format!("{target}.restore");
+        if let Err(err) = fs::copy(source, &copy) {
+            let _ = fs::remove_file(&copy);
+            for (copy, _) in copies {
+                let _ = fs::remove_file(copy);
+            }
+            return Err(err.into());
+        }
+        copies.push((copy, *target));
+    }
+    for (copy, target) in copies {
+        fs::rename(copy, target)?;
+        println!("Restored {target}");
+    }
+    // A write-ahead log left over from the previous database must not be applied to the restored one
+    for suffix in ["-wal", "-shm"] {
+        let _ = fs::remove_file(format!("{db_url}{suffix}"));
+    }
+    Ok(())
+}
+
+// Changes are not pushed to clients since the server is not running
+pub(crate) fn drain(mut recv: Receiver<CollectionOperation>) {
+    tokio::spawn(async move { while recv.recv().await.is_some() {} });
+}
+
+/// Exports the configured data store to a portable archive or imports one into it
+async fn transfer(config: &Config, path: &Path, export: bool, migrate: bool) -> anyhow::Result<()> {
+    match &config.data_store {
+        DataStoreConfig::Sqlite(store_config) => {
+            let (addr_store, cal_store, _, principal_store, update_recv) =
+                get_sqlite_stores(migrate, store_config).await?;
+            drain(update_recv);
+            let user_store = principal_store_from_config(&config.auth, Some(principal_store))?;
+            transfer_archive(path, export, &*addr_store, &*cal_store, &*user_store).await
+        }
+        DataStoreConfig::Postgres(store_config) => {
+            let (addr_store, cal_store, _, principal_store, update_recv) =
+                get_postgres_stores(migrate, store_config).await?;
+            drain(update_recv);
+            let user_store = principal_store_from_config(&config.auth, Some(principal_store))?;
+            transfer_archive(path, export, &*addr_store, &*cal_store, &*user_store).await
+        }
+        DataStoreConfig::Memory(_) => {
+            bail!("The in-memory data store does not persist anything to back up or restore")
+        }
+        DataStoreConfig::Vdir(store_config) => {
+            let (addr_store, cal_store, _, update_recv) = get_vdir_stores(store_config)?;
+            drain(update_recv);
+            let user_store =
+                principal_store_from_config(&config.auth, None::<Arc<MemoryPrincipalStore>>)?;
+            transfer_archive(path, export, &*addr_store, &*cal_store, &*user_store).await
+        }
+    }
+}
+
+fn principal_store_from_config(
+    auth: &AuthConfig,
+    // Only available for SQL data stores
+    sql_principal_store: Option<Arc<impl AuthenticationProvider>>,
+) -> anyhow::Result<Arc<dyn AuthenticationProvider>> {
+    Ok(match auth {
+        AuthConfig::Toml(auth_config) => Arc::new(TomlPrincipalStore::new(auth_config.clone())?),
+        AuthConfig::Sql(_) => {
+            sql_principal_store.ok_or(anyhow!("SQL authentication requires an SQL data store"))?
+        }
+        AuthConfig::Ldap(ldap_config) => {
+            if let Some(path) = &ldap_config.app_token_path {
+                let local = TomlPrincipalStore::new(TomlUserStoreConfig {
+                    path: path.to_owned(),
+                })?;
+                Arc::new(LdapAuthenticationProvider::new(
+                    (**ldap_config).clone(),
+                    Arc::new(local),
+                ))
+            } else {
+                let local = sql_principal_store.ok_or(anyhow!(
+                    "LDAP authentication requires app_token_path or an SQL data store"
+                ))?;
+                Arc::new(LdapAuthenticationProvider::new(
+                    (**ldap_config).clone(),
+                    local,
+                ))
+            }
+        }
+    })
+}
+
+/// The archive uses the vdir layout with an additional principals file:
+///
+/// ```text
+/// {path}/
+/// ├── principals.toml
+/// └── {principal}/
+///     ├── calendars/{calendar}/{object}.ics
+///     └── addressbooks/{addressbook}/{object}.vcf
+/// ```
+///
+/// Collections and objects in the trashbin are not included.
+async fn transfer_archive(
+    path: &Path,
+    export: bool,
+    addr_store: &impl AddressbookStore,
+    cal_store: &impl CalendarStore,
+    user_store: &dyn AuthenticationProvider,
+) -> anyhow::Result<()> {
+    let vdir = Arc::new(Vdir::new(path));
+    let (send, update_recv) = tokio::sync::mpsc::channel(1000);
+    drain(update_recv);
+    let archive_addr_store = VdirAddressbookStore::new(vdir.clone(), send.clone());
+    let archive_cal_store = VdirCalendarStore::new(vdir, send);
+
+    let principals_path = path.join(PRINCIPALS_FILE).to_string_lossy().into_owned();
+    if export {
+        fs::write(&principals_path, "")?;
+    }
+    let archive_user_store = TomlPrincipalStore::new(TomlUserStoreConfig {
+        path: principals_path,
+    })?;
+
+    let mut principals = if export {
+        user_store.get_principals().await?
+    } else {
+        archive_user_store.get_principals().await?
+    };
+    principals.sort_by(|a, b| a.id.cmp(&b.id));
+    let principal_ids: Vec<_> = principals.iter().map(|p| p.id.to_owned()).collect();
+
+    if export {
+        for principal in principals {
+            archive_user_store.insert_principal(principal, true).await?;
+        }
+        copy_collections(
+            &principal_ids,
+            (addr_store, cal_store),
+            (&archive_addr_store, &archive_cal_store),
+        )
+        .await?;
+        println!("Archive written to {}", path.display());
+    } else {
+        for principal in principals {
+            let id = principal.id.to_owned();
+            match user_store.insert_principal(principal, false).await {
+                Ok(()) => println!("Principal {id} imported"),
+                Err(Error::AlreadyExists) => println!("Principal {id} already exists, skipped"),
+                Err(Error::ReadOnly) => {
+                    println!("Principal {id} skipped since principals are read-only")
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+        copy_collections(
+            &principal_ids,
+            (&archive_addr_store, &archive_cal_store),
+            (addr_store, cal_store),
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Copies the collections of the given principals between data stores.
+/// Existing collections are merged, objects with the same id overwritten.
+async fn copy_collections(
+    principals: &[String],
+    (from_addr_store, from_cal_store): (&impl AddressbookStore, &impl CalendarStore),
+    (to_addr_store, to_cal_store): (&impl AddressbookStore, &impl CalendarStore),
+) -> anyhow::Result<()> {
+    for principal in principals {
+        for calendar in from_cal_store.get_calendars(principal).await? {
+            let id = calendar.id.to_owned();
+            let objects = from_cal_store.get_objects(principal, &id).await?;
+            match to_cal_store.insert_calendar(calendar).await {
+                Ok(()) | Err(Error::AlreadyExists) => {}
+                Err(err) => return Err(err.into()),
+            }
+            let count = objects.len();
+            for object in objects {
+                to_cal_store
+                    .put_object(principal.to_owned(), id.to_owned(), object, true, None)
+                    .await?;
+            }
+            println!("Calendar {principal}/{id}: {count} objects");
+        }
+
+        for addressbook in from_addr_store.get_addressbooks(principal).await? {
+            let id = addressbook.id.to_owned();
+            let objects = from_addr_store.get_objects(principal, &id).await?;
+            match to_addr_store.insert_addressbook(addressbook).await {
+                Ok(()) | Err(Error::AlreadyExists) => {}
+                Err(err) => return Err(err.into()),
+            }
+            let count = objects.len();
+            for object in objects {
+                to_addr_store
+                    .put_object(principal.to_owned(), id.to_owned(), object, true, None)
+                    .await?;
+            }
+            println!("Addressbook {principal}/{id}: {count} objects");
+        }
+
+        let birthday_settings = from_addr_store.get_birthday_settings(principal).await?;
+        to_addr_store
+            .update_birthday_settings(principal, birthday_settings)
+            .await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DB_FILE, restore_snapshot, transfer_archive};
+    use rustical_store::auth::user::PrincipalType;
+    use rustical_store::auth::{AuthenticationProvider, User};
+    use rustical_store::memory::{
+        MemoryAddressbookStore, MemoryCalendarStore, MemoryPrincipalStore,
+    };
+    use rustical_store::{
+        AddressObject, Addressbook, AddressbookStore, Calendar, CalendarObject, CalendarStore,
+    };
+    use std::path::Path;
+
+    const EVENT: &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:-//rustical//test//EN\r
+BEGIN:VEVENT\r
+UID:event\r
+DTSTAMP:20230831T102923Z\r
+DTSTART:20230829T043000Z\r
+DTEND:20230829T045500Z\r
+SUMMARY:Test\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+
+    const CONTACT: &str = "BEGIN:VCARD\r
+VERSION:4.0\r
+UID:contact\r
+FN:Alice\r
+END:VCARD\r
+";
+
+    #[test]
+    fn test_restore_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_url = dir.path().join("db.sqlite3").to_string_lossy().into_owned();
+        let wal = format!("{db_url}-wal");
+        std::fs::write(&db_url, "live").unwrap();
+        std::fs::write(&wal, "uncheckpointed").unwrap();
+
+        // A missing snapshot leaves the live database alone
+        let backup = dir.path().join("backup");
+        assert!(restore_snapshot(&backup, &db_url, None, true).is_err());
+        assert_eq!(std::fs::read_to_string(&wal).unwrap(), "uncheckpointed");
+
+        std::fs::create_dir(&backup).unwrap();
+        std::fs::write(backup.join(DB_FILE), "snapshot").unwrap();
+        assert!(restore_snapshot(&backup, &db_url, None, false).is_err());
+        restore_snapshot(&backup, &db_url, None, true).unwrap();
+        assert_eq!(std::fs::read_to_string(&db_url).unwrap(), "snapshot");
+        assert!(!Path::new(&wal).exists());
+        assert!(!Path::new(&format!("{db_url}.restore")).exists());
+    }
+
+    #[tokio::test]
+    async fn test_portable_archive() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("archive");
+        std::fs::create_dir(&path).unwrap();
+
+        let (send, _recv) = tokio::sync::mpsc::channel(100);
+        let user_store = MemoryPrincipalStore::default();
+        user_store
+            .insert_principal(
+                User {
+                    id: "user".to_owned(),
+                    displayname: Some("User".to_owned()),
+                    principal_type: PrincipalType::Individual,
+                    password: None,
+                    app_tokens: vec![],
+                    memberships: vec![],
+                    emails: vec!["user@example.com".to_owned()],
+                },
+                false,
+            )
+            .await
+            .unwrap();
+        let cal_store = MemoryCalendarStore::new(send.clone());
+        cal_store
+            .insert_calendar(Calendar {
+                principal: "user".to_owned(),
+                id: "calendar".to_owned(),
+                displayname: Some("Calendar".to_owned()),
+                push_topic: "calendar-topic".to_owned(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        cal_store
+            .put_object(
+                "user".to_owned(),
+                "calendar".to_owned(),
+                CalendarObject::from_ics("event".to_owned(), EVENT.to_owned()).unwrap(),
+                false,
+                None,
+            )
+            .await
+            .unwrap();
+        let addr_store = MemoryAddressbookStore::new(send.clone());
+        addr_store
+            .insert_addressbook(Addressbook {
+                id: "contacts".to_owned(),
+                principal: "user".to_owned(),
+                displayname: Some("Contacts".to_owned()),
+                description: None,
+                created_at: None,
+                updated_at: None,
+                deleted_at: None,
+                synctoken: 0,
+                push_topic: "contacts-topic".to_owned(),
+            })
+            .await
+            .unwrap();
+        addr_store
+            .put_object(
+                "user".to_owned(),
+                "contacts".to_owned(),
+                AddressObject::from_vcf("contact".to_owned(), CONTACT.to_owned()).unwrap(),
+                false,
+                None,
+            )
+            .await
+            .unwrap();
+
+        transfer_archive(&path, true, &addr_store, &cal_store, &user_store)
+            .await
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(path.join("user/calendars/calendar/event.ics")).unwrap(),
+            EVENT
+        );
+
+        // Import into empty stores
+        let user_store = MemoryPrincipalStore::default();
+        let cal_store = MemoryCalendarStore::new(send.clone());
+        let addr_store = MemoryAddressbookStore::new(send);
+        transfer_archive(&path, false, &addr_store, &cal_store, &user_store)
+            .await
+            .unwrap();
+
+        let user = user_store.get_principal("user").await.unwrap().unwrap();
+        assert_eq!(user.emails, vec!["user@example.com"]);
+        let calendar = cal_store.get_calendar("user", "calendar").await.unwrap();
+        assert_eq!(calendar.displayname.as_deref(), Some("Calendar"));
+        assert_eq!(calendar.push_topic, "calendar-topic");
+        let event = cal_store
+            .get_object("user", "calendar", "event")
+            .await
+            .unwrap();
+        assert_eq!(event.get_ics(), EVENT);
+        let contact = addr_store
+            .get_object("user", "contacts", "contact")
+            .await
+            .unwrap();
+        assert_eq!(contact.get_vcf(), CONTACT);
+    }
+}
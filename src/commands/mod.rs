@@ -11,7 +11,9 @@ use crate::config::{
     TracingConfig,
 };
 
+pub mod backup;
 pub mod principals;
+pub mod purge;
 
 #[derive(Debug, Parser)]
 pub struct GenConfigArgs {}
@@ -42,6 +44,9 @@ pub fn cmd_gen_config(_args: GenConfigArgs) -> anyhow::Result<()> {
         oidc: None,
         dav_push: DavPushConfig::default(),
         nextcloud_login: Default::default(),
+        trashbin: Default::default(),
+        changelog: Default::default(),
+        quota: Default::default(),
     };
     let generated_config = toml::to_string(&config)?;
     println!("{generated_config}");
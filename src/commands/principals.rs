@@ -1,3 +1,4 @@
+use anyhow::anyhow;
 use clap::{Parser, Subcommand};
 use figment::{
     Figment,
@@ -6,9 +7,19 @@ use figment::{
 use password_hash::PasswordHasher;
 use password_hash::SaltString;
 use rand::rngs::OsRng;
-use rustical_store::auth::{AuthenticationProvider, TomlPrincipalStore, User, user::PrincipalType};
+use rustical_ldap::LdapAuthenticationProvider;
+use rustical_store::{
+    Error, Secret,
+    auth::{
+        AuthenticationProvider, TomlPrincipalStore, TomlUserStoreConfig, User,
+        user::{PrincipalType, normalize_emails},
+    },
+};
+use rustical_store_postgres::principal_store::PostgresPrincipalStore;
+use rustical_store_sqlite::principal_store::SqlitePrincipalStore;
+use std::sync::Arc;
 
-use crate::config::{self, Config};
+use crate::config::{AuthConfig, Config, DataStoreConfig};
 
 #[derive(Parser, Debug)]
 pub struct PrincipalsArgs {
@@ -28,6 +39,11 @@ struct CreateArgs {
     name: Option<String>,
     #[arg(long, help = "Ask for password input")]
     password: bool,
+    #[arg(
+        long = "email",
+        help = "Email address, the first one is the primary address"
+    )]
+    emails: Vec<String>,
 }
 
 #[derive(Parser, Debug)]
@@ -35,11 +51,50 @@ struct RemoveArgs {
     id: String,
 }
 
+#[derive(Parser, Debug)]
+struct EditArgs {
+    id: String,
+    #[arg(value_enum, short, long)]
+    principal_type: Option<PrincipalType>,
+    #[arg(short, long)]
+    name: Option<String>,
+    #[arg(long, help = "Ask for password input")]
+    password: bool,
+    #[arg(long, help = "Remove the password", conflicts_with = "password")]
+    remove_password: bool,
+    #[arg(
+        long = "email",
+        help = "Replaces the email addresses, the first one is the primary address"
+    )]
+    emails: Vec<String>,
+    #[arg(long, help = "Remove all email addresses", conflicts_with = "emails")]
+    remove_emails: bool,
+}
+
+#[derive(Parser, Debug)]
+struct ImportArgs {
+    #[arg(help = "Principals TOML file to import, principals that already exist are skipped")]
+    path: String,
+}
+
 #[derive(Debug, Subcommand)]
 enum Command {
     List,
     Create(CreateArgs),
     Remove(RemoveArgs),
+    Edit(EditArgs),
+    Import(ImportArgs),
+}
+
+fn read_password_hash() -> anyhow::Result<Secret<String>> {
+    let salt = SaltString::generate(OsRng);
+    println!("Enter your password:");
+    let password = rpassword::read_password()?;
+    Ok(argon2::Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .unwrap()
+        .to_string()
+        .into())
 }
 
 pub async fn cmd_principals(args: PrincipalsArgs) -> anyhow::Result<()> {
@@ -49,17 +104,60 @@ pub async fn cmd_principals(args: PrincipalsArgs) -> anyhow::Result<()> {
         .merge(Env::prefixed("RUSTICAL_").split("__"))
         .extract()?;
 
-    let user_store = match config.auth {
-        config::AuthConfig::Toml(config) => TomlPrincipalStore::new(config)?,
-    };
+    match (config.auth, config.data_store) {
+        (AuthConfig::Toml(auth_config), _) => {
+            run_command(TomlPrincipalStore::new(auth_config)?, args.command).await
+        }
+        (AuthConfig::Sql(_), DataStoreConfig::Sqlite(store_config)) => {
+            let db = rustical_store_sqlite::create_db_pool(&store_config.db_url, true).await?;
+            run_command(SqlitePrincipalStore::new(db), args.command).await
+        }
+        (AuthConfig::Sql(_), DataStoreConfig::Postgres(store_config)) => {
+            let db = rustical_store_postgres::create_db_pool(&store_config.db_url, true).await?;
+            run_command(PostgresPrincipalStore::new(db), args.command).await
+        }
+        (AuthConfig::Sql(_), _) => Err(anyhow!("SQL authentication requires an SQL data store")),
+        (AuthConfig::Ldap(ldap_config), data_store) => {
+            match (ldap_config.app_token_path.clone(), data_store) {
+                (Some(path), _) => {
+                    let local = TomlPrincipalStore::new(TomlUserStoreConfig { path })?;
+                    let user_store = LdapAuthenticationProvider::new(*ldap_config, Arc::new(local));
+                    run_command(user_store, args.command).await
+                }
+                (None, DataStoreConfig::Sqlite(store_config)) => {
+                    let db =
+                        rustical_store_sqlite::create_db_pool(&store_config.db_url, true).await?;
+                    let local = SqlitePrincipalStore::new(db);
+                    let user_store = LdapAuthenticationProvider::new(*ldap_config, Arc::new(local));
+                    run_command(user_store, args.command).await
+                }
+                (None, DataStoreConfig::Postgres(store_config)) => {
+                    let db =
+                        rustical_store_postgres::create_db_pool(&store_config.db_url, true).await?;
+                    let local = PostgresPrincipalStore::new(db);
+                    let user_store = LdapAuthenticationProvider::new(*ldap_config, Arc::new(local));
+                    run_command(user_store, args.command).await
+                }
+                (None, _) => Err(anyhow!(
+                    "LDAP authentication requires app_token_path or an SQL data store"
+                )),
+            }
+        }
+    }
+}
 
-    match args.command {
+async fn run_command(
+    user_store: impl AuthenticationProvider,
+    command: Command,
+) -> anyhow::Result<()> {
+    match command {
         Command::List => {
             for principal in user_store.get_principals().await? {
                 println!(
-                    "{} (displayname={}) [{}]",
+                    "{} (displayname={}, emails={}) [{}]",
                     principal.id,
                     principal.displayname.unwrap_or_default(),
+                    principal.emails.join(","),
                     principal.principal_type
                 );
             }
@@ -69,30 +167,27 @@ pub async fn cmd_principals(args: PrincipalsArgs) -> anyhow::Result<()> {
             principal_type,
             name,
             password,
+            emails,
         }) => {
-            let salt = SaltString::generate(OsRng);
+            let emails = normalize_emails(emails.iter().map(String::as_str))?;
             let password = if password {
-                println!("Enter your password:");
-                let password = rpassword::read_password()?;
-                Some(
-                    argon2::Argon2::default()
-                        .hash_password(password.as_bytes(), &salt)
-                        .unwrap()
-                        .to_string()
-                        .into(),
-                )
+                Some(read_password_hash()?)
             } else {
                 None
             };
             user_store
-                .insert_principal(User {
-                    id,
-                    displayname: name,
-                    principal_type: principal_type.unwrap_or_default(),
-                    app_tokens: vec![],
-                    password,
-                    memberships: vec![],
-                })
+                .insert_principal(
+                    User {
+                        id,
+                        displayname: name,
+                        principal_type: principal_type.unwrap_or_default(),
+                        app_tokens: vec![],
+                        password,
+                        memberships: vec![],
+                        emails,
+                    },
+                    false,
+                )
                 .await?;
             println!("Principal created");
         }
@@ -100,6 +195,49 @@ pub async fn cmd_principals(args: PrincipalsArgs) -> anyhow::Result<()> {
             user_store.remove_principal(&id).await?;
             println!("Principal {id} removed");
         }
+        Command::Edit(EditArgs {
+            id,
+            principal_type,
+            name,
+            password,
+            remove_password,
+            emails,
+            remove_emails,
+        }) => {
+            let mut principal = user_store
+                .get_principal(&id)
+                .await?
+                .ok_or(anyhow!("Principal {id} does not exist"))?;
+            if let Some(principal_type) = principal_type {
+                principal.principal_type = principal_type;
+            }
+            if name.is_some() {
+                principal.displayname = name;
+            }
+            if password {
+                principal.password = Some(read_password_hash()?);
+            }
+            if remove_password {
+                principal.password = None;
+            }
+            if !emails.is_empty() || remove_emails {
+                principal.emails = normalize_emails(emails.iter().map(String::as_str))?;
+            }
+            user_store.insert_principal(principal, true).await?;
+            println!("Principal {id} updated");
+        }
+        Command::Import(ImportArgs { path }) => {
+            let toml_store = TomlPrincipalStore::new(TomlUserStoreConfig { path })?;
+            for mut principal in toml_store.get_principals().await? {
+                let id = principal.id.to_owned();
+                principal.emails = normalize_emails(principal.emails.iter().map(String::as_str))?;
+                match user_store.insert_principal(principal, false).await {
+                    Ok(()) => println!("Principal {id} imported"),
+                    Err(Error::AlreadyExists) => println!("Principal {id} already exists, skipped"),
+                    Err(err) => return Err(err.into()),
+                }
+            }
+        }
     }
     Ok(())
 }
@@ -0,0 +1,50 @@
+use super::backup::{drain, load_config};
+use crate::config::DataStoreConfig;
+use crate::{get_postgres_stores, get_sqlite_stores, get_vdir_stores};
+use anyhow::bail;
+use clap::Parser;
+use rustical_store::trashbin::purge_trashbin;
+
+#[derive(Parser, Debug)]
+pub struct PurgeArgs {
+    #[arg(short, long, env, default_value = "/etc/rustical/config.toml")]
+    config_file: String,
+    #[arg(
+        long,
+        help = "Only purge items deleted more than this many days ago, defaults to trashbin.retention_days or 0"
+    )]
+    days: Option<u64>,
+    #[arg(long, env, help = "Do no run database migrations (only for sql store)")]
+    no_migrations: bool,
+}
+
+pub async fn cmd_purge(args: PurgeArgs) -> anyhow::Result<()> {
+    let config = load_config(&args.config_file)?;
+    let days = args.days.or(config.trashbin.retention_days).unwrap_or(0);
+    let migrate = !args.no_migrations;
+
+    let count = match &config.data_store {
+        DataStoreConfig::Sqlite(store_config) => {
+            let (addr_store, cal_store, _, _, update_recv) =
+                get_sqlite_stores(migrate, store_config).await?;
+            drain(update_recv);
+            purge_trashbin(&*addr_store, &*cal_store, days).await?
+        }
+        DataStoreConfig::Postgres(store_config) => {
+            let (addr_store, cal_store, _, _, update_recv) =
+                get_postgres_stores(migrate, store_config).await?;
+            drain(update_recv);
+            purge_trashbin(&*addr_store, &*cal_store, days).await?
+        }
+        DataStoreConfig::Vdir(store_config) => {
+            let (addr_store, cal_store, _, update_recv) = get_vdir_stores(store_config)?;
+            drain(update_recv);
+            purge_trashbin(&*addr_store, &*cal_store, days).await?
+        }
+        DataStoreConfig::Memory(_) => {
+            bail!("The in-memory data store does not persist anything to purge")
+        }
+    };
+    println!("Purged {count} items from the trashbin");
+    Ok(())
+}
@@ -1,6 +1,8 @@
 use rustical_frontend::FrontendConfig;
+use rustical_ldap::LdapConfig;
 use rustical_oidc::OidcConfig;
 use rustical_store::auth::TomlUserStoreConfig;
+use rustical_store::quota::QuotaConfig;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -25,18 +27,52 @@ pub struct SqliteDataStoreConfig {
     pub db_url: String,
 }
 
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct PostgresDataStoreConfig {
+    pub db_url: String,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct VdirDataStoreConfig {
+    pub path: String,
+    // Detect changes made by other programs
+    #[serde(default = "default_true")]
+    pub watch: bool,
+}
+
+/// Keeps all data in memory, everything is lost on restart
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MemoryDataStoreConfig {}
+
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
 #[serde(deny_unknown_fields)]
 pub enum DataStoreConfig {
     Sqlite(SqliteDataStoreConfig),
+    Postgres(PostgresDataStoreConfig),
+    Vdir(VdirDataStoreConfig),
+    Memory(MemoryDataStoreConfig),
 }
 
+/// Keeps principals in the database of the SQL data store
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SqlAuthConfig {}
+
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
 #[serde(deny_unknown_fields)]
 pub enum AuthConfig {
     Toml(TomlUserStoreConfig),
+    Sql(SqlAuthConfig),
+    Ldap(Box<LdapConfig>),
 }
 
 #[derive(Debug, Deserialize, Serialize, Default)]
@@ -76,6 +112,21 @@ impl Default for NextcloudLoginConfig {
     }
 }
 
+#[derive(Debug, Deserialize, Serialize, Default)]
+#[serde(deny_unknown_fields, default)]
+pub struct TrashbinConfig {
+    // Days after which deleted collections and objects are purged, keeps them forever if unset
+    pub retention_days: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Default)]
+#[serde(deny_unknown_fields, default)]
+pub struct ChangelogConfig {
+    // Days after which changes are dropped from the changelog, keeps them forever if unset.
+    // Clients that haven't synced since then have to do a full sync again.
+    pub retention_days: Option<u64>,
+}
+
 #[derive(Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct Config {
@@ -92,4 +143,10 @@ pub struct Config {
     pub dav_push: DavPushConfig,
     #[serde(default)]
     pub nextcloud_login: NextcloudLoginConfig,
+    #[serde(default)]
+    pub trashbin: TrashbinConfig,
+    #[serde(default)]
+    pub changelog: ChangelogConfig,
+    #[serde(default)]
+    pub quota: QuotaConfig,
 }
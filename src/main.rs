@@ -1,21 +1,41 @@
 use crate::config::Config;
 use actix_web::HttpServer;
 use actix_web::http::KeepAlive;
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 use app::make_app;
 use clap::{Parser, Subcommand};
+use commands::backup::{BackupArgs, RestoreArgs, cmd_backup, cmd_restore};
 use commands::principals::{PrincipalsArgs, cmd_principals};
+use commands::purge::{PurgeArgs, cmd_purge};
 use commands::{cmd_gen_config, cmd_pwhash};
-use config::{DataStoreConfig, SqliteDataStoreConfig};
+use config::{
+    AuthConfig, DataStoreConfig, PostgresDataStoreConfig, SqliteDataStoreConfig,
+    VdirDataStoreConfig,
+};
 use figment::Figment;
 use figment::providers::{Env, Format, Toml};
 use rustical_dav_push::notifier::push_notifier;
 use rustical_frontend::nextcloud_login::NextcloudFlows;
-use rustical_store::auth::TomlPrincipalStore;
+use rustical_ldap::LdapAuthenticationProvider;
+use rustical_store::auth::{AuthenticationProvider, TomlPrincipalStore, TomlUserStoreConfig};
+use rustical_store::changelog::changelog_compactor;
+use rustical_store::memory::{
+    MemoryAddressbookStore, MemoryCalendarStore, MemoryPrincipalStore, MemorySubscriptionStore,
+};
+use rustical_store::trashbin::trashbin_purger;
 use rustical_store::{AddressbookStore, CalendarStore, CollectionOperation, SubscriptionStore};
+use rustical_store_postgres::PostgresStore;
+use rustical_store_postgres::addressbook_store::PostgresAddressbookStore;
+use rustical_store_postgres::calendar_store::PostgresCalendarStore;
+use rustical_store_postgres::principal_store::PostgresPrincipalStore;
 use rustical_store_sqlite::addressbook_store::SqliteAddressbookStore;
 use rustical_store_sqlite::calendar_store::SqliteCalendarStore;
+use rustical_store_sqlite::principal_store::SqlitePrincipalStore;
 use rustical_store_sqlite::{SqliteStore, create_db_pool};
+use rustical_store_vdir::Vdir;
+use rustical_store_vdir::addressbook_store::VdirAddressbookStore;
+use rustical_store_vdir::calendar_store::VdirCalendarStore;
+use rustical_store_vdir::subscription_store::VdirStore;
 use setup_tracing::setup_tracing;
 use std::sync::Arc;
 use tokio::sync::mpsc::Receiver;
@@ -42,29 +62,226 @@ enum Command {
     GenConfig(commands::GenConfigArgs),
     Pwhash(commands::PwhashArgs),
     Principals(PrincipalsArgs),
+    Backup(BackupArgs),
+    Restore(RestoreArgs),
+    Purge(PurgeArgs),
 }
 
-async fn get_data_stores(
+async fn get_sqlite_stores(
     migrate: bool,
-    config: &DataStoreConfig,
+    config: &SqliteDataStoreConfig,
 ) -> Result<(
     Arc<impl AddressbookStore>,
     Arc<impl CalendarStore>,
     Arc<impl SubscriptionStore>,
+    Arc<SqlitePrincipalStore>,
     Receiver<CollectionOperation>,
 )> {
-    Ok(match &config {
-        DataStoreConfig::Sqlite(SqliteDataStoreConfig { db_url }) => {
-            let db = create_db_pool(db_url, migrate).await?;
-            // Channel to watch for changes (for DAV Push)
-            let (send, recv) = tokio::sync::mpsc::channel(1000);
-
-            let addressbook_store = Arc::new(SqliteAddressbookStore::new(db.clone(), send.clone()));
-            let cal_store = Arc::new(SqliteCalendarStore::new(db.clone(), send));
-            let subscription_store = Arc::new(SqliteStore::new(db.clone()));
-            (addressbook_store, cal_store, subscription_store, recv)
+    let db = create_db_pool(&config.db_url, migrate).await?;
+    // Channel to watch for changes (for DAV Push)
+    let (send, recv) = tokio::sync::mpsc::channel(1000);
+
+    let addressbook_store = Arc::new(SqliteAddressbookStore::new(db.clone(), send.clone()));
+    let cal_store = Arc::new(SqliteCalendarStore::new(db.clone(), send));
+    let subscription_store = Arc::new(SqliteStore::new(db.clone()));
+    let principal_store = Arc::new(SqlitePrincipalStore::new(db));
+    Ok((
+        addressbook_store,
+        cal_store,
+        subscription_store,
+        principal_store,
+        recv,
+    ))
+}
+
+async fn get_postgres_stores(
+    migrate: bool,
+    config: &PostgresDataStoreConfig,
+) -> Result<(
+    Arc<impl AddressbookStore>,
+    Arc<impl CalendarStore>,
+    Arc<impl SubscriptionStore>,
+    Arc<PostgresPrincipalStore>,
+    Receiver<CollectionOperation>,
+)> {
+    let db = rustical_store_postgres::create_db_pool(&config.db_url, migrate).await?;
+    // Channel to watch for changes (for DAV Push)
+    let (send, recv) = tokio::sync::mpsc::channel(1000);
+
+    let addressbook_store = Arc::new(PostgresAddressbookStore::new(db.clone(), send.clone()));
+    let cal_store = Arc::new(PostgresCalendarStore::new(db.clone(), send));
+    let subscription_store = Arc::new(PostgresStore::new(db.clone()));
+    let principal_store = Arc::new(PostgresPrincipalStore::new(db));
+    Ok((
+        addressbook_store,
+        cal_store,
+        subscription_store,
+        principal_store,
+        recv,
+    ))
+}
+
+fn get_memory_stores() -> (
+    Arc<impl AddressbookStore>,
+    Arc<impl CalendarStore>,
+    Arc<impl SubscriptionStore>,
+    Receiver<CollectionOperation>,
+) {
+    // Channel to watch for changes (for DAV Push)
+    let (send, recv) = tokio::sync::mpsc::channel(1000);
+
+    let addressbook_store = Arc::new(MemoryAddressbookStore::new(send.clone()));
+    let cal_store = Arc::new(MemoryCalendarStore::new(send));
+    let subscription_store = Arc::new(MemorySubscriptionStore::default());
+    (addressbook_store, cal_store, subscription_store, recv)
+}
+
+fn get_vdir_stores(
+    config: &VdirDataStoreConfig,
+) -> Result<(
+    Arc<impl AddressbookStore>,
+    Arc<impl CalendarStore>,
+    Arc<impl SubscriptionStore>,
+    Receiver<CollectionOperation>,
+)> {
+    let vdir = Arc::new(Vdir::new(&config.path));
+    // Channel to watch for changes (for DAV Push)
+    let (send, recv) = tokio::sync::mpsc::channel(1000);
+
+    if config.watch {
+        rustical_store_vdir::watcher::watch(vdir.clone(), send.clone())?;
+    }
+    let addressbook_store = Arc::new(VdirAddressbookStore::new(vdir.clone(), send.clone()));
+    let cal_store = Arc::new(VdirCalendarStore::new(vdir.clone(), send));
+    let subscription_store = Arc::new(VdirStore::new(vdir));
+    Ok((addressbook_store, cal_store, subscription_store, recv))
+}
+
+async fn serve(
+    config: Config,
+    addr_store: Arc<impl AddressbookStore>,
+    cal_store: Arc<impl CalendarStore>,
+    subscription_store: Arc<impl SubscriptionStore>,
+    update_recv: Receiver<CollectionOperation>,
+    // Only available for SQL data stores, also keeps the app tokens for LDAP
+    sql_principal_store: Option<Arc<impl AuthenticationProvider>>,
+) -> Result<()> {
+    if config.dav_push.enabled {
+        tokio::spawn(push_notifier(
+            config.dav_push.allowed_push_servers.clone(),
+            update_recv,
+            subscription_store.clone(),
+        ));
+    }
+
+    if let Some(retention_days) = config.trashbin.retention_days {
+        tokio::spawn(trashbin_purger(
+            addr_store.clone(),
+            cal_store.clone(),
+            retention_days,
+        ));
+    }
+
+    if let Some(retention_days) = config.changelog.retention_days {
+        tokio::spawn(changelog_compactor(
+            addr_store.clone(),
+            cal_store.clone(),
+            retention_days,
+        ));
+    }
+
+    match &config.auth {
+        AuthConfig::Toml(auth_config) => {
+            let user_store = Arc::new(TomlPrincipalStore::new(auth_config.clone())?);
+            run_server(
+                config,
+                addr_store,
+                cal_store,
+                subscription_store,
+                user_store,
+            )
+            .await
+        }
+        AuthConfig::Sql(_) => {
+            let user_store = sql_principal_store
+                .ok_or(anyhow!("SQL authentication requires an SQL data store"))?;
+            run_server(
+                config,
+                addr_store,
+                cal_store,
+                subscription_store,
+                user_store,
+            )
+            .await
+        }
+        AuthConfig::Ldap(ldap_config) => {
+            if let Some(path) = &ldap_config.app_token_path {
+                let local = Arc::new(TomlPrincipalStore::new(TomlUserStoreConfig {
+                    path: path.to_owned(),
+                })?);
+                let user_store = Arc::new(LdapAuthenticationProvider::new(
+                    (**ldap_config).clone(),
+                    local,
+                ));
+                run_server(
+                    config,
+                    addr_store,
+                    cal_store,
+                    subscription_store,
+                    user_store,
+                )
+                .await
+            } else {
+                let local = sql_principal_store.ok_or(anyhow!(
+                    "LDAP authentication requires app_token_path or an SQL data store"
+                ))?;
+                let user_store = Arc::new(LdapAuthenticationProvider::new(
+                    (**ldap_config).clone(),
+                    local,
+                ));
+                run_server(
+                    config,
+                    addr_store,
+                    cal_store,
+                    subscription_store,
+                    user_store,
+                )
+                .await
+            }
         }
+    }
+}
+
+async fn run_server(
+    config: Config,
+    addr_store: Arc<impl AddressbookStore>,
+    cal_store: Arc<impl CalendarStore>,
+    subscription_store: Arc<impl SubscriptionStore>,
+    user_store: Arc<impl AuthenticationProvider>,
+) -> Result<()> {
+    let nextcloud_flows = Arc::new(NextcloudFlows::default());
+
+    HttpServer::new(move || {
+        make_app(
+            addr_store.clone(),
+            cal_store.clone(),
+            subscription_store.clone(),
+            user_store.clone(),
+            config.frontend.clone(),
+            config.oidc.clone(),
+            config.nextcloud_login.clone(),
+            nextcloud_flows.clone(),
+            config.quota.clone(),
+        )
     })
+    .bind((config.http.host, config.http.port))?
+    // Workaround for a weird bug where
+    // new requests might timeout since they cannot properly reuse the connection
+    // https://github.com/lennart-k/rustical/issues/10
+    .keep_alive(KeepAlive::Disabled)
+    .run()
+    .await?;
+    Ok(())
 }
 
 #[tokio::main]
@@ -75,6 +292,9 @@ async fn main() -> Result<()> {
         Some(Command::GenConfig(gen_config_args)) => cmd_gen_config(gen_config_args)?,
         Some(Command::Pwhash(pwhash_args)) => cmd_pwhash(pwhash_args)?,
         Some(Command::Principals(principals_args)) => cmd_principals(principals_args).await?,
+        Some(Command::Backup(backup_args)) => cmd_backup(backup_args).await?,
+        Some(Command::Restore(restore_args)) => cmd_restore(restore_args).await?,
+        Some(Command::Purge(purge_args)) => cmd_purge(purge_args).await?,
         None => {
             let config: Config = Figment::new()
                 // TODO: What to do when config file does not exist?
@@ -84,42 +304,61 @@ async fn main() -> Result<()> {
 
             setup_tracing(&config.tracing);
 
-            let (addr_store, cal_store, subscription_store, update_recv) =
-                get_data_stores(!args.no_migrations, &config.data_store).await?;
-
-            if config.dav_push.enabled {
-                tokio::spawn(push_notifier(
-                    config.dav_push.allowed_push_servers,
-                    update_recv,
-                    subscription_store.clone(),
-                ));
+            let migrate = !args.no_migrations;
+            match &config.data_store {
+                DataStoreConfig::Sqlite(store_config) => {
+                    let (addr_store, cal_store, subscription_store, principal_store, update_recv) =
+                        get_sqlite_stores(migrate, store_config).await?;
+                    serve(
+                        config,
+                        addr_store,
+                        cal_store,
+                        subscription_store,
+                        update_recv,
+                        Some(principal_store),
+                    )
+                    .await?;
+                }
+                DataStoreConfig::Postgres(store_config) => {
+                    let (addr_store, cal_store, subscription_store, principal_store, update_recv) =
+                        get_postgres_stores(migrate, store_config).await?;
+                    serve(
+                        config,
+                        addr_store,
+                        cal_store,
+                        subscription_store,
+                        update_recv,
+                        Some(principal_store),
+                    )
+                    .await?;
+                }
+                DataStoreConfig::Memory(_) => {
+                    let (addr_store, cal_store, subscription_store, update_recv) =
+                        get_memory_stores();
+                    serve(
+                        config,
+                        addr_store,
+                        cal_store,
+                        subscription_store,
+                        update_recv,
+                        None::<Arc<MemoryPrincipalStore>>,
+                    )
+                    .await?;
+                }
+                DataStoreConfig::Vdir(store_config) => {
+                    let (addr_store, cal_store, subscription_store, update_recv) =
+                        get_vdir_stores(store_config)?;
+                    serve(
+                        config,
+                        addr_store,
+                        cal_store,
+                        subscription_store,
+                        update_recv,
+                        None::<Arc<MemoryPrincipalStore>>,
+                    )
+                    .await?;
+                }
             }
-
-            let user_store = match config.auth {
-                config::AuthConfig::Toml(config) => Arc::new(TomlPrincipalStore::new(config)?),
-            };
-
-            let nextcloud_flows = Arc::new(NextcloudFlows::default());
-
-            HttpServer::new(move || {
-                make_app(
-                    addr_store.clone(),
-                    cal_store.clone(),
-                    subscription_store.clone(),
-                    user_store.clone(),
-                    config.frontend.clone(),
-                    config.oidc.clone(),
-                    config.nextcloud_login.clone(),
-                    nextcloud_flows.clone(),
-                )
-            })
-            .bind((config.http.host, config.http.port))?
-            // Workaround for a weird bug where
-            // new requests might timeout since they cannot properly reuse the connection
-            // https://github.com/lennart-k/rustical/issues/10
-            .keep_alive(KeepAlive::Disabled)
-            .run()
-            .await?;
         }
     }
     Ok(())
@@ -129,90 +368,28 @@ async fn main() -> Result<()> {
 mod tests {
     use crate::{
         app::make_app, commands::generate_frontend_secret, config::NextcloudLoginConfig,
-        get_data_stores,
+        get_sqlite_stores,
     };
     use actix_web::{http::StatusCode, test::TestRequest};
-    use anyhow::anyhow;
-    use async_trait::async_trait;
     use rustical_frontend::FrontendConfig;
     use rustical_frontend::nextcloud_login::NextcloudFlows;
-    use rustical_store::auth::AuthenticationProvider;
+    use rustical_store::memory::MemoryPrincipalStore;
+    use rustical_store::quota::QuotaConfig;
     use std::sync::Arc;
 
-    #[derive(Debug, Clone)]
-    struct MockUserStore;
-
-    #[async_trait]
-    impl AuthenticationProvider for MockUserStore {
-        async fn get_principals(
-            &self,
-        ) -> Result<Vec<rustical_store::auth::User>, rustical_store::Error> {
-            Err(rustical_store::Error::Other(anyhow!("Not implemented")))
-        }
-        async fn get_principal(
-            &self,
-            _id: &str,
-        ) -> Result<Option<rustical_store::auth::User>, rustical_store::Error> {
-            Err(rustical_store::Error::Other(anyhow!("Not implemented")))
-        }
-
-        async fn remove_principal(&self, _id: &str) -> Result<(), rustical_store::Error> {
-            Err(rustical_store::Error::Other(anyhow!("Not implemented")))
-        }
-
-        async fn validate_password(
-            &self,
-            _user_id: &str,
-            _password: &str,
-        ) -> Result<Option<rustical_store::auth::User>, rustical_store::Error> {
-            Err(rustical_store::Error::Other(anyhow!("Not implemented")))
-        }
-
-        async fn validate_app_token(
-            &self,
-            _user_id: &str,
-            _token: &str,
-        ) -> Result<Option<rustical_store::auth::User>, rustical_store::Error> {
-            Err(rustical_store::Error::Other(anyhow!("Not implemented")))
-        }
-
-        async fn add_app_token(
-            &self,
-            _user_id: &str,
-            _name: String,
-            _token: String,
-        ) -> Result<String, rustical_store::Error> {
-            Err(rustical_store::Error::Other(anyhow!("Not implemented")))
-        }
-
-        async fn remove_app_token(
-            &self,
-            _user_id: &str,
-            _token_id: &str,
-        ) -> Result<(), rustical_store::Error> {
-            Err(rustical_store::Error::Other(anyhow!("Not implemented")))
-        }
-
-        async fn insert_principal(
-            &self,
-            _user: rustical_store::auth::User,
-        ) -> Result<(), rustical_store::Error> {
-            Err(rustical_store::Error::Other(anyhow!("Not implemented")))
-        }
-    }
-
     #[tokio::test]
     async fn test_main() {
-        let (addr_store, cal_store, subscription_store, _update_recv) = get_data_stores(
-            true,
-            &crate::config::DataStoreConfig::Sqlite(crate::config::SqliteDataStoreConfig {
-                db_url: "".to_owned(),
-            }),
-        )
-        .await
-        .unwrap();
+        let (addr_store, cal_store, subscription_store, _principal_store, _update_recv) =
+            get_sqlite_stores(
+                true,
+                &crate::config::SqliteDataStoreConfig {
+                    db_url: "".to_owned(),
+                },
+            )
+            .await
+            .unwrap();
 
-        let user_store = Arc::new(MockUserStore);
+        let user_store = Arc::new(MemoryPrincipalStore::default());
 
         let app = make_app(
             addr_store,
@@ -227,10 +404,32 @@ mod tests {
             None,
             NextcloudLoginConfig { enabled: false },
             Arc::new(NextcloudFlows::default()),
+            QuotaConfig::default(),
         );
         let app = actix_web::test::init_service(app).await;
         let req = TestRequest::get().uri("/").to_request();
         let resp = actix_web::test::call_service(&app, req).await;
         assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        // TZDIST
+        let req = TestRequest::get()
+            .uri("/.well-known/timezone/capabilities")
+            .to_request();
+        let resp = actix_web::test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        for uri in [
+            "/.well-known/timezone/zones/Europe%2FBerlin",
+            "/.well-known/timezone/zones/Europe/Berlin",
+        ] {
+            let req = TestRequest::get().uri(uri).to_request();
+            let body = actix_web::test::call_and_read_body(&app, req).await;
+            let body = String::from_utf8(body.to_vec()).unwrap();
+            assert!(body.contains("BEGIN:VTIMEZONE\r\nTZID:Europe/Berlin\r\n"));
+        }
+        let req = TestRequest::get()
+            .uri("/.well-known/timezone/zones/Middle%2FEarth")
+            .to_request();
+        let resp = actix_web::test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
     }
 }